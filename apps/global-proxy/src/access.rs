@@ -0,0 +1,317 @@
+//! Request filtering applied before anything is forwarded upstream.
+//!
+//! Covers source-IP allow/deny lists plus a few cheap request rules (denied path
+//! patterns, allowed methods, maximum URI length). Blocked requests are logged
+//! on the `global_proxy::audit` target.
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use http::{HeaderMap, Method, Request, StatusCode};
+use regex::Regex;
+use tracing::warn;
+
+/// An IPv4 or IPv6 network in CIDR notation. Bare addresses are single hosts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid IP address '{}'", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("invalid prefix length in '{}'", s))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AccessPolicy {
+    /// When non-empty, only these networks may connect.
+    pub allow_ips: Vec<IpNet>,
+    /// Networks that are always rejected, even if allowed above.
+    pub deny_ips: Vec<IpNet>,
+    /// Requests whose path matches any of these are rejected. Paths are
+    /// matched both as sent and percent-decoded with repeated slashes
+    /// collapsed.
+    pub deny_paths: Vec<Regex>,
+    /// When set, only these methods are forwarded.
+    pub allowed_methods: Option<Vec<Method>>,
+    /// Reject requests whose path and query exceed this many bytes.
+    pub max_uri_length: Option<usize>,
+    /// Number of trusted proxies in front of this one that append to
+    /// `X-Forwarded-For`. Zero means the socket peer address is the client.
+    pub trusted_proxy_hops: usize,
+}
+
+/// Why a request was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Denial {
+    pub status: StatusCode,
+    pub rule: &'static str,
+}
+
+impl AccessPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.allow_ips.is_empty()
+            && self.deny_ips.is_empty()
+            && self.deny_paths.is_empty()
+            && self.allowed_methods.is_none()
+            && self.max_uri_length.is_none()
+    }
+
+    /// Resolve the client address, honouring `trusted_proxy_hops`.
+    pub fn client_ip(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        if self.trusted_proxy_hops == 0 {
+            return peer.ip();
+        }
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .collect();
+        forwarded
+            .len()
+            .checked_sub(self.trusted_proxy_hops)
+            .and_then(|idx| forwarded.get(idx))
+            .and_then(|ip| ip.parse().ok())
+            .unwrap_or(peer.ip())
+    }
+
+    /// Evaluate the request, logging and returning the rule it violated.
+    pub fn check<B>(&self, peer: SocketAddr, req: &Request<B>) -> Result<(), Denial> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let client = self.client_ip(peer, req.headers());
+        let result = self.evaluate(client, req);
+        if let Err(denial) = &result {
+            let host = req
+                .headers()
+                .get(http::header::HOST)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("");
+            warn!(
+                target: "global_proxy::audit",
+                client = %client,
+                method = %req.method(),
+                host,
+                path = req.uri().path(),
+                rule = denial.rule,
+                status = denial.status.as_u16(),
+                "request blocked"
+            );
+        }
+        result
+    }
+
+    fn evaluate<B>(&self, client: IpAddr, req: &Request<B>) -> Result<(), Denial> {
+        let deny = |status, rule| Err(Denial { status, rule });
+
+        if self.deny_ips.iter().any(|net| net.contains(client)) {
+            return deny(StatusCode::FORBIDDEN, "ip_denylist");
+        }
+        if !self.allow_ips.is_empty() && !self.allow_ips.iter().any(|net| net.contains(client)) {
+            return deny(StatusCode::FORBIDDEN, "ip_allowlist");
+        }
+        if let Some(methods) = &self.allowed_methods
+            && !methods.contains(req.method())
+        {
+            return deny(StatusCode::METHOD_NOT_ALLOWED, "method");
+        }
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        if self
+            .max_uri_length
+            .is_some_and(|max| path_and_query.len() > max)
+        {
+            return deny(StatusCode::URI_TOO_LONG, "uri_length");
+        }
+        // Upstreams decode and collapse the path themselves, so `/%61dmin`
+        // and `//admin` have to hit a rule for `/admin`
+        let path = req.uri().path();
+        let normalized = normalize_path(path);
+        if self
+            .deny_paths
+            .iter()
+            .any(|re| re.is_match(path) || re.is_match(&normalized))
+        {
+            return deny(StatusCode::FORBIDDEN, "path_denylist");
+        }
+        Ok(())
+    }
+}
+
+/// `path` with percent-escapes decoded and runs of `/` collapsed to one.
+fn normalize_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let mut normalized = String::with_capacity(decoded.len());
+    for c in String::from_utf8_lossy(&decoded).chars() {
+        if !(c == '/' && normalized.ends_with('/')) {
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, uri: &str) -> Request<()> {
+        Request::builder().method(method).uri(uri).body(()).unwrap()
+    }
+
+    fn peer(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 1234)
+    }
+
+    #[test]
+    fn matches_cidrs() {
+        let net: IpNet = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains("10.1.200.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.0.9".parse().unwrap()));
+
+        let v6: IpNet = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(
+            "0.0.0.0/0"
+                .parse::<IpNet>()
+                .unwrap()
+                .contains("1.2.3.4".parse().unwrap())
+        );
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("nope".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn applies_rules_in_order() {
+        let policy = AccessPolicy {
+            allow_ips: vec!["10.0.0.0/8".parse().unwrap()],
+            deny_ips: vec!["10.9.0.0/16".parse().unwrap()],
+            deny_paths: vec![Regex::new(r"^/(\.env|\.git|wp-admin)").unwrap()],
+            allowed_methods: Some(vec![Method::GET, Method::POST]),
+            max_uri_length: Some(32),
+            trusted_proxy_hops: 0,
+        };
+        let get = |uri: &str| request(Method::GET, uri);
+        let rule = |ip: &str, req: &Request<()>| policy.check(peer(ip), req).err().map(|d| d.rule);
+
+        assert_eq!(rule("10.1.1.1", &get("/app")), None);
+        assert_eq!(rule("192.168.1.1", &get("/app")), Some("ip_allowlist"));
+        assert_eq!(rule("10.9.1.1", &get("/app")), Some("ip_denylist"));
+        assert_eq!(
+            rule("10.1.1.1", &get("/.git/config")),
+            Some("path_denylist")
+        );
+        assert_eq!(
+            rule("10.1.1.1", &request(Method::DELETE, "/app")),
+            Some("method")
+        );
+        assert_eq!(
+            rule(
+                "10.1.1.1",
+                &get("/app?q=aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")
+            ),
+            Some("uri_length")
+        );
+    }
+
+    #[test]
+    fn path_rules_see_through_encoding_and_extra_slashes() {
+        let policy = AccessPolicy {
+            deny_paths: vec![Regex::new(r"^/admin").unwrap()],
+            ..Default::default()
+        };
+        let denied = |uri: &str| {
+            policy
+                .check(peer("10.1.1.1"), &request(Method::GET, uri))
+                .is_err()
+        };
+        assert!(denied("/admin"));
+        assert!(denied("/%61dmin"));
+        assert!(denied("/%61%64%6D%69%6E/users"));
+        assert!(denied("//admin"));
+        assert!(denied("/%2Fadmin"));
+        assert!(!denied("/app/admin"));
+        assert!(!denied("/%zzadmin"));
+        assert_eq!(normalize_path("/a//b///c%2"), "/a/b/c%2");
+    }
+
+    #[test]
+    fn resolves_client_ip_through_trusted_proxies() {
+        let policy = AccessPolicy {
+            trusted_proxy_hops: 1,
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "6.6.6.6, 1.2.3.4".parse().unwrap());
+        assert_eq!(
+            policy.client_ip(peer("35.1.1.1"), &headers),
+            "1.2.3.4".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            policy.client_ip(peer("35.1.1.1"), &HeaderMap::new()),
+            "35.1.1.1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
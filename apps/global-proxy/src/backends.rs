@@ -0,0 +1,217 @@
+//! Failover across a prioritized list of backend hosts.
+//!
+//! Requests routed to a backend port go to the first host whose health checks
+//! are passing; when none are, the primary is used so errors come from the
+//! real backend rather than the proxy. Hosts are probed in the background: a
+//! host stops receiving new requests after `unhealthy_threshold` consecutive
+//! failed probes and is put back after one success.
+//!
+//! Switching hosts only affects new requests. Requests already waiting on a
+//! host and WebSocket tunnels open to it stay there until they finish, and
+//! `/health` reports how many are still draining from each host.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use futures_util::future::join_all;
+use http::{Request, Uri, uri::Scheme};
+use hyper::Body;
+use serde_json::{Value, json};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{info, warn};
+
+use crate::HttpClient;
+
+#[derive(Clone, Debug)]
+pub struct BackendFailoverConfig {
+    /// Hosts tried after `ProxyConfig::backend_host`, in priority order.
+    pub fallback_hosts: Vec<String>,
+    /// Port probed on every host.
+    pub health_check_port: u16,
+    /// Path probed on every host; any 2xx response counts as healthy.
+    pub health_check_path: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Consecutive failed probes before a host stops receiving new requests.
+    pub unhealthy_threshold: u32,
+}
+
+impl Default for BackendFailoverConfig {
+    fn default() -> Self {
+        Self {
+            fallback_hosts: Vec::new(),
+            health_check_port: 80,
+            health_check_path: "/health".to_string(),
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(2),
+            unhealthy_threshold: 2,
+        }
+    }
+}
+
+struct BackendHost {
+    host: String,
+    healthy: AtomicBool,
+    failures: AtomicU32,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl BackendHost {
+    fn new(host: String) -> Self {
+        Self {
+            host,
+            healthy: AtomicBool::new(true),
+            failures: AtomicU32::new(0),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// Backend hosts in priority order; the first is `ProxyConfig::backend_host`.
+pub(crate) struct BackendPool {
+    hosts: Vec<BackendHost>,
+    failover: Option<BackendFailoverConfig>,
+}
+
+/// A host chosen for one request, counted as in flight until dropped.
+pub(crate) struct BackendLease {
+    host: String,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl BackendLease {
+    pub(crate) fn host(&self) -> &str {
+        &self.host
+    }
+}
+
+impl Drop for BackendLease {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl BackendPool {
+    pub(crate) fn new(primary: String, failover: Option<BackendFailoverConfig>) -> Self {
+        let fallbacks = failover
+            .as_ref()
+            .map(|failover| failover.fallback_hosts.clone())
+            .unwrap_or_default();
+        let hosts = std::iter::once(primary)
+            .chain(fallbacks)
+            .map(BackendHost::new)
+            .collect();
+        Self { hosts, failover }
+    }
+
+    fn active(&self) -> &BackendHost {
+        self.hosts
+            .iter()
+            .find(|backend| backend.healthy.load(Ordering::Relaxed))
+            .unwrap_or(&self.hosts[0])
+    }
+
+    pub(crate) fn acquire(&self) -> BackendLease {
+        let backend = self.active();
+        backend.in_flight.fetch_add(1, Ordering::Relaxed);
+        BackendLease {
+            host: backend.host.clone(),
+            in_flight: backend.in_flight.clone(),
+        }
+    }
+
+    /// Per-host health for `/health`; `None` when failover is not configured.
+    pub(crate) fn status(&self) -> Option<Value> {
+        self.failover.as_ref()?;
+        let hosts: Vec<Value> = self
+            .hosts
+            .iter()
+            .map(|backend| {
+                json!({
+                    "host": backend.host,
+                    "healthy": backend.healthy.load(Ordering::Relaxed),
+                    "in_flight": backend.in_flight.load(Ordering::Relaxed),
+                })
+            })
+            .collect();
+        Some(json!({
+            "active": self.active().host,
+            "hosts": hosts,
+        }))
+    }
+
+    /// Probes every host once and updates its health.
+    pub(crate) async fn check(&self, client: &HttpClient, scheme: &Scheme) {
+        let Some(failover) = &self.failover else {
+            return;
+        };
+        let probes = self
+            .hosts
+            .iter()
+            .map(|backend| probe(client, scheme, &backend.host, failover));
+        let results = join_all(probes).await;
+        for (backend, passed) in self.hosts.iter().zip(results) {
+            if passed {
+                backend.failures.store(0, Ordering::Relaxed);
+                if !backend.healthy.swap(true, Ordering::Relaxed) {
+                    info!(host = %backend.host, "backend host is healthy again");
+                }
+            } else {
+                let failures = backend.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= failover.unhealthy_threshold
+                    && backend.healthy.swap(false, Ordering::Relaxed)
+                {
+                    warn!(host = %backend.host, failures, "backend host marked unhealthy");
+                }
+            }
+        }
+    }
+}
+
+async fn probe(
+    client: &HttpClient,
+    scheme: &Scheme,
+    host: &str,
+    failover: &BackendFailoverConfig,
+) -> bool {
+    let uri = format!(
+        "{}://{}:{}{}",
+        scheme.as_str(),
+        host,
+        failover.health_check_port,
+        failover.health_check_path
+    );
+    let Ok(uri) = uri.parse::<Uri>() else {
+        return false;
+    };
+    let Ok(request) = Request::get(uri).body(Body::empty()) else {
+        return false;
+    };
+    match tokio::time::timeout(failover.timeout, client.request(request)).await {
+        Ok(Ok(response)) => response.status().is_success(),
+        Ok(Err(_)) | Err(_) => false,
+    }
+}
+
+/// Re-probes the pool every `interval` until shutdown.
+pub(crate) fn spawn_health_checks(
+    pool: Arc<BackendPool>,
+    client: HttpClient,
+    scheme: Scheme,
+    mut shutdown: watch::Receiver<()>,
+) -> Option<JoinHandle<()>> {
+    let interval = pool.failover.as_ref()?.interval;
+    Some(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => pool.check(&client, &scheme).await,
+                _ = shutdown.changed() => break,
+            }
+        }
+    }))
+}
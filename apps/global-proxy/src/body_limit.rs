@@ -0,0 +1,156 @@
+//! Bounds on how much of an upstream response the proxy holds or relays.
+//!
+//! Response bodies stream straight through to the client. HTML is the one
+//! exception, since scripts are injected into it, and it is only buffered up
+//! to [`MAX_REWRITE_BYTES`]; bigger pages pass through unrewritten. With a
+//! maximum response size configured, a response declaring a larger
+//! `Content-Length` is refused before any of its body is read, and a body
+//! without a declared length is cut off once it crosses the limit.
+
+use std::{error::Error, fmt};
+
+use bytes::Bytes;
+use futures_util::{StreamExt, stream};
+use http::{HeaderMap, Response, header};
+use hyper::{Body, body::HttpBody};
+use tracing::warn;
+
+/// Largest HTML body buffered for script injection.
+pub(crate) const MAX_REWRITE_BYTES: usize = 16 * 1024 * 1024;
+
+/// A body stream failed because it crossed the configured maximum.
+#[derive(Debug)]
+pub(crate) struct ResponseTooLarge {
+    pub(crate) limit: u64,
+}
+
+impl fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "upstream response exceeded {} bytes", self.limit)
+    }
+}
+
+impl Error for ResponseTooLarge {}
+
+/// The limit `err` reports crossing, if it came from a body capped by
+/// [`enforce`].
+pub(crate) fn exceeded(err: &hyper::Error) -> Option<u64> {
+    let mut source = err.source();
+    while let Some(err) = source {
+        if let Some(too_large) = err.downcast_ref::<ResponseTooLarge>() {
+            return Some(too_large.limit);
+        }
+        source = err.source();
+    }
+    None
+}
+
+pub(crate) fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Cap `response`'s body at `limit` bytes. A declared length over the limit
+/// is returned as the error so the caller can refuse the response outright.
+pub(crate) fn enforce(response: Response<Body>, limit: u64) -> Result<Response<Body>, u64> {
+    if let Some(len) = content_length(response.headers())
+        && len > limit
+    {
+        return Err(len);
+    }
+    let (parts, body) = response.into_parts();
+    let mut seen = 0u64;
+    let body = body.map(move |chunk| {
+        let chunk = chunk?;
+        seen += chunk.len() as u64;
+        if seen > limit {
+            // Failing the stream aborts the client connection, so a cut-off
+            // download cannot be mistaken for a complete one.
+            warn!(limit, "upstream response exceeded size limit; aborting");
+            return Err(Box::new(ResponseTooLarge { limit }) as Box<dyn Error + Send + Sync>);
+        }
+        Ok(chunk)
+    });
+    Ok(Response::from_parts(parts, Body::wrap_stream(body)))
+}
+
+pub(crate) enum Buffered {
+    Complete(Bytes),
+    /// The body, with what was read so far put back in front of the rest.
+    TooLarge(Body),
+}
+
+/// Read all of `body` if it fits in `limit` bytes.
+pub(crate) async fn buffer_up_to(mut body: Body, limit: usize) -> Result<Buffered, hyper::Error> {
+    let mut buffered = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if buffered.len() + chunk.len() > limit {
+            let read = stream::iter([Ok(Bytes::from(buffered)), Ok(chunk)]);
+            return Ok(Buffered::TooLarge(Body::wrap_stream(read.chain(body))));
+        }
+        buffered.extend_from_slice(&chunk);
+    }
+    Ok(Buffered::Complete(Bytes::from(buffered)))
+}
+
+/// Total length of `body`, read without holding onto it.
+pub(crate) async fn drain(mut body: Body) -> Result<usize, hyper::Error> {
+    let mut len = 0;
+    while let Some(chunk) = body.data().await {
+        len += chunk?.len();
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunked(chunks: &[&'static str]) -> Body {
+        let chunks: Vec<Result<_, std::io::Error>> = chunks
+            .iter()
+            .map(|c| Ok(Bytes::from_static(c.as_bytes())))
+            .collect();
+        Body::wrap_stream(stream::iter(chunks))
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_refused_or_cut_off() {
+        let declared = Response::builder()
+            .header(header::CONTENT_LENGTH, "11")
+            .body(Body::from("hello world"))
+            .unwrap();
+        assert_eq!(enforce(declared, 10).err(), Some(11));
+
+        let response = Response::new(chunked(&["hello", " world"]));
+        let body = enforce(response, 10).unwrap().into_body();
+        let err = hyper::body::to_bytes(body).await.unwrap_err();
+        assert_eq!(exceeded(&err), Some(10));
+
+        let response = Response::new(chunked(&["hello", " world"]));
+        let body = enforce(response, 11).unwrap().into_body();
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn buffering_gives_back_bodies_over_the_limit_intact() {
+        let Buffered::Complete(bytes) = buffer_up_to(chunked(&["ab", "cd"]), 4).await.unwrap()
+        else {
+            panic!("fits in the limit");
+        };
+        assert_eq!(bytes, "abcd");
+
+        let Buffered::TooLarge(body) = buffer_up_to(chunked(&["ab", "cd", "ef"]), 3).await.unwrap()
+        else {
+            panic!("over the limit");
+        };
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "abcdef");
+        assert_eq!(drain(chunked(&["ab", "cde"])).await.unwrap(), 5);
+    }
+}
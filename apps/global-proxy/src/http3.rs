@@ -0,0 +1,271 @@
+//! Optional HTTP/3 (QUIC) listener.
+//!
+//! Requests are converted to the hyper types used by the TCP listener and go
+//! through the same `handle_request` path, so routing, access checks and the
+//! HTTP/1.1 backend connection are identical. TCP responses advertise the
+//! listener with `Alt-Svc` so browsers upgrade on their next request.
+
+use std::{fmt, net::SocketAddr, sync::Arc};
+
+use bytes::{Buf, Bytes};
+use h3::server::RequestStream;
+use http::{
+    HeaderMap, HeaderValue, Request, Response, Version,
+    header::{self, HeaderName},
+};
+use hyper::{Body, body::HttpBody};
+use quinn::crypto::rustls::QuicServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::{AppState, ProxyError, handle_request};
+
+const ALT_SVC_MAX_AGE_SECS: u32 = 86_400;
+
+/// Headers that are connection-specific in HTTP/1.1 and forbidden in HTTP/3.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "transfer-encoding",
+    "upgrade",
+    "keep-alive",
+    "proxy-connection",
+];
+
+#[derive(Clone)]
+pub struct Http3Config {
+    /// UDP address for the QUIC listener.
+    pub bind_addr: SocketAddr,
+    /// PEM certificate chain; QUIC always needs TLS, even behind a load balancer.
+    pub cert_chain_pem: Vec<u8>,
+    pub private_key_pem: Vec<u8>,
+    /// Send `Alt-Svc` on HTTP/1.1 and HTTP/2 responses.
+    pub advertise: bool,
+    /// Port announced in `Alt-Svc` when clients reach the listener through a
+    /// different public port. Defaults to the bound UDP port.
+    pub advertise_port: Option<u16>,
+}
+
+impl fmt::Debug for Http3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Http3Config")
+            .field("bind_addr", &self.bind_addr)
+            .field("advertise", &self.advertise)
+            .field("advertise_port", &self.advertise_port)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) fn bind(config: &Http3Config) -> Result<quinn::Endpoint, ProxyError> {
+    let setup = |msg: String| ProxyError::Http3(msg);
+    let certs = CertificateDer::pem_slice_iter(&config.cert_chain_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| setup(format!("invalid certificate chain: {err}")))?;
+    let key = PrivateKeyDer::from_pem_slice(&config.private_key_pem)
+        .map_err(|err| setup(format!("invalid private key: {err}")))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|err| setup(err.to_string()))?;
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).map_err(|err| setup(err.to_string()))?;
+
+    let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    Ok(quinn::Endpoint::server(server_config, config.bind_addr)?)
+}
+
+pub(crate) fn alt_svc_value(port: u16) -> HeaderValue {
+    HeaderValue::from_str(&format!("h3=\":{port}\"; ma={ALT_SVC_MAX_AGE_SECS}"))
+        .expect("alt-svc value is ascii")
+}
+
+pub(crate) async fn serve(
+    endpoint: quinn::Endpoint,
+    state: Arc<AppState>,
+    mut shutdown: watch::Receiver<()>,
+) {
+    loop {
+        tokio::select! {
+            incoming = endpoint.accept() => {
+                let Some(incoming) = incoming else { break };
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve_connection(incoming, state).await {
+                        debug!(%err, "http/3 connection closed");
+                    }
+                });
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+    endpoint.close(0u32.into(), b"shutdown");
+    endpoint.wait_idle().await;
+}
+
+async fn serve_connection(
+    incoming: quinn::Incoming,
+    state: Arc<AppState>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let connection = incoming.await?;
+    let peer = connection.remote_address();
+    let mut h3_conn: h3::server::Connection<_, Bytes> =
+        h3::server::Connection::new(h3_quinn::Connection::new(connection)).await?;
+
+    while let Some(resolver) = h3_conn.accept().await? {
+        let state = state.clone();
+        tokio::spawn(async move {
+            match resolver.resolve_request().await {
+                Ok((req, stream)) => {
+                    if let Err(err) = serve_request(state, peer, req, stream).await {
+                        warn!(%err, "http/3 request failed");
+                    }
+                }
+                Err(err) => debug!(%err, "http/3 request headers not received"),
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn serve_request<S>(
+    state: Arc<AppState>,
+    peer: SocketAddr,
+    req: http1::Request<()>,
+    stream: RequestStream<S, Bytes>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: h3::quic::BidiStream<Bytes> + Send + 'static,
+    S::SendStream: Send,
+    S::RecvStream: Send + 'static,
+{
+    let (mut send, mut recv) = stream.split();
+
+    // Only stream a body when the client sends one, so bodiless requests stay
+    // bodiless (not chunked) on the way to the backend.
+    let body = match recv.recv_data().await? {
+        None => Body::empty(),
+        Some(mut first) => {
+            let (mut tx, body) = Body::channel();
+            let first = first.copy_to_bytes(first.remaining());
+            tokio::spawn(async move {
+                if tx.send_data(first).await.is_err() {
+                    return;
+                }
+                while let Ok(Some(mut chunk)) = recv.recv_data().await {
+                    let chunk = chunk.copy_to_bytes(chunk.remaining());
+                    if tx.send_data(chunk).await.is_err() {
+                        return;
+                    }
+                }
+            });
+            body
+        }
+    };
+
+    let response = match to_hyper_request(req, body) {
+        Some(req) => handle_request(state, peer, req).await,
+        None => crate::text_response(http::StatusCode::BAD_REQUEST, "Malformed request"),
+    };
+
+    let (parts, mut body) = response.into_parts();
+    send.send_response(to_h3_response_head(parts.status, &parts.headers)?)
+        .await?;
+    while let Some(chunk) = body.data().await {
+        send.send_data(chunk?).await?;
+    }
+    if let Some(trailers) = body.trailers().await? {
+        send.send_trailers(to_h3_headers(&trailers)).await?;
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+fn to_hyper_request(req: http1::Request<()>, body: Body) -> Option<Request<Body>> {
+    let (parts, ()) = req.into_parts();
+    // The backend connection is HTTP/1.1 regardless of how the client arrived
+    let mut out = Request::builder()
+        .method(parts.method.as_str())
+        .uri(parts.uri.to_string())
+        .version(Version::HTTP_11)
+        .body(body)
+        .ok()?;
+    for (name, value) in &parts.headers {
+        let name = HeaderName::from_bytes(name.as_str().as_bytes()).ok()?;
+        let value = HeaderValue::from_bytes(value.as_bytes()).ok()?;
+        out.headers_mut().append(name, value);
+    }
+    // HTTP/3 carries the host in :authority; routing expects a Host header
+    if !out.headers().contains_key(header::HOST)
+        && let Some(authority) = parts.uri.authority()
+    {
+        let value = HeaderValue::from_str(authority.as_str()).ok()?;
+        out.headers_mut().insert(header::HOST, value);
+    }
+    Some(out)
+}
+
+fn to_h3_headers(headers: &HeaderMap) -> http1::HeaderMap {
+    let mut out = http1::HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        if CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (
+            http1::HeaderName::from_bytes(name.as_str().as_bytes()),
+            http1::HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            out.append(name, value);
+        }
+    }
+    out
+}
+
+fn to_h3_response_head(
+    status: http::StatusCode,
+    headers: &HeaderMap,
+) -> Result<http1::Response<()>, http1::Error> {
+    let mut response = http1::Response::builder()
+        .status(status.as_u16())
+        .body(())?;
+    *response.headers_mut() = to_h3_headers(headers);
+    Ok(response)
+}
+
+/// Insert the `Alt-Svc` advertisement into a TCP listener response.
+pub(crate) fn advertise(response: &mut Response<Body>, alt_svc: &HeaderValue) {
+    response
+        .headers_mut()
+        .insert(header::ALT_SVC, alt_svc.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_authority_to_host_and_drops_connection_headers() {
+        let req = http1::Request::builder()
+            .method("POST")
+            .uri("https://port-3000-abc.cmux.sh/api?x=1")
+            .header("x-test", "1")
+            .body(())
+            .unwrap();
+        let converted = to_hyper_request(req, Body::empty()).unwrap();
+        assert_eq!(converted.version(), Version::HTTP_11);
+        assert_eq!(converted.headers()[header::HOST], "port-3000-abc.cmux.sh");
+        assert_eq!(converted.uri().path_and_query().unwrap(), "/api?x=1");
+        assert_eq!(converted.headers()["x-test"], "1");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive"));
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        let head = to_h3_response_head(http::StatusCode::CREATED, &headers).unwrap();
+        assert_eq!(head.status(), 201);
+        assert!(!head.headers().contains_key("connection"));
+        assert_eq!(head.headers()["content-type"], "text/html");
+
+        assert_eq!(alt_svc_value(443), "h3=\":443\"; ma=86400");
+    }
+}
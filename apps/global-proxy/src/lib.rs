@@ -2,6 +2,7 @@ use std::{
     io::{self, Cursor, Read},
     net::SocketAddr,
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use brotli::Decompressor;
@@ -23,15 +24,34 @@ use hyper_rustls::HttpsConnectorBuilder;
 use lol_html::{HtmlRewriter, Settings, element, html_content::ContentType};
 use tokio::{
     io::{AsyncWriteExt, copy_bidirectional},
-    sync::oneshot,
+    sync::watch,
     task::JoinHandle,
 };
-use tracing::{error, warn};
+use tracing::{Instrument, debug, error, info_span, warn};
 use zstd::stream::read::Decoder as ZstdDecoder;
 
 use chrono::Utc;
 use serde_json::{Value, json};
 
+mod access;
+mod backends;
+mod body_limit;
+mod http3;
+mod otlp;
+mod request_id;
+mod signed_url;
+pub use access::{AccessPolicy, IpNet};
+pub use backends::BackendFailoverConfig;
+pub use http3::Http3Config;
+pub use otlp::OtlpConfig;
+pub use request_id::{REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+pub use signed_url::SignedUrlConfig;
+
+use backends::{BackendLease, BackendPool};
+use body_limit::Buffered;
+use otlp::{OtlpExporter, SpanRecord};
+use request_id::RequestContext;
+
 type HttpClient = Client<hyper_rustls::HttpsConnector<HttpConnector>, Body>;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -47,9 +67,20 @@ const FORWARD_ALL_WEBSOCKET_HEADERS: bool = true;
 pub struct ProxyConfig {
     pub bind_addr: SocketAddr,
     pub backend_host: String,
+    /// Health-checked fallback hosts used when `backend_host` is down.
+    pub backend_failover: Option<BackendFailoverConfig>,
     pub backend_scheme: Scheme,
     pub morph_domain_suffix: Option<String>,
     pub workspace_domain_suffix: Option<String>,
+    pub access: AccessPolicy,
+    /// Require signed, expiring links for matching preview hosts.
+    pub signed_urls: Option<SignedUrlConfig>,
+    /// Also serve HTTP/3 over QUIC; disabled when unset.
+    pub http3: Option<Http3Config>,
+    /// Export a span per request to an OpenTelemetry collector.
+    pub otlp: Option<OtlpConfig>,
+    /// Largest upstream response body relayed; unlimited when unset.
+    pub max_response_bytes: Option<u64>,
 }
 
 impl Default for ProxyConfig {
@@ -57,16 +88,24 @@ impl Default for ProxyConfig {
         Self {
             bind_addr: SocketAddr::from(([0, 0, 0, 0], 8080)),
             backend_host: "127.0.0.1".to_string(),
+            backend_failover: None,
             backend_scheme: Scheme::HTTP,
             morph_domain_suffix: None,
             workspace_domain_suffix: None,
+            access: AccessPolicy::default(),
+            signed_urls: None,
+            http3: None,
+            otlp: None,
+            max_response_bytes: None,
         }
     }
 }
 
 pub struct ProxyHandle {
     pub addr: SocketAddr,
-    shutdown: Option<oneshot::Sender<()>>,
+    /// Bound UDP address of the HTTP/3 listener, when enabled.
+    pub http3_addr: Option<SocketAddr>,
+    shutdown: Option<watch::Sender<()>>,
     task: JoinHandle<()>,
 }
 
@@ -85,14 +124,24 @@ pub enum ProxyError {
     Io(#[from] std::io::Error),
     #[error("hyper error: {0}")]
     Hyper(#[from] hyper::Error),
+    #[error("http/3 setup error: {0}")]
+    Http3(String),
+    #[error("otlp setup error: {0}")]
+    Otlp(String),
 }
 
 struct AppState {
     client: HttpClient,
-    backend_host: String,
+    backends: Arc<BackendPool>,
     backend_scheme: Scheme,
     morph_domain_suffix: Option<String>,
     workspace_domain_suffix: Option<String>,
+    access: AccessPolicy,
+    signed_urls: Option<SignedUrlConfig>,
+    /// `Alt-Svc` value advertising the HTTP/3 listener on TCP responses.
+    alt_svc: Option<HeaderValue>,
+    otlp: Option<OtlpExporter>,
+    max_response_bytes: Option<u64>,
 }
 
 pub async fn spawn_proxy(config: ProxyConfig) -> Result<ProxyHandle, ProxyError> {
@@ -107,50 +156,181 @@ pub async fn spawn_proxy(config: ProxyConfig) -> Result<ProxyHandle, ProxyError>
         .build();
     let client: HttpClient = Client::builder().build(https);
 
+    let endpoint = config.http3.as_ref().map(http3::bind).transpose()?;
+    let http3_addr = endpoint
+        .as_ref()
+        .map(quinn::Endpoint::local_addr)
+        .transpose()?;
+    let alt_svc = config
+        .http3
+        .as_ref()
+        .filter(|h3| h3.advertise)
+        .zip(http3_addr)
+        .map(|(h3, addr)| http3::alt_svc_value(h3.advertise_port.unwrap_or(addr.port())));
+
+    let (otlp, otlp_task) = match config.otlp {
+        Some(otlp) => {
+            let (exporter, task) = OtlpExporter::spawn(otlp, client.clone())?;
+            (Some(exporter), Some(task))
+        }
+        None => (None, None),
+    };
+
+    // Probe once up front so the first requests skip a host that is already down.
+    let backends = Arc::new(BackendPool::new(
+        config.backend_host,
+        config.backend_failover,
+    ));
+    backends.check(&client, &config.backend_scheme).await;
+
     let state = Arc::new(AppState {
         client,
-        backend_host: config.backend_host,
+        backends: backends.clone(),
         backend_scheme: config.backend_scheme,
         morph_domain_suffix: config.morph_domain_suffix,
         workspace_domain_suffix: config.workspace_domain_suffix,
+        access: config.access,
+        signed_urls: config.signed_urls.filter(SignedUrlConfig::is_enabled),
+        alt_svc,
+        otlp,
+        max_response_bytes: config.max_response_bytes,
     });
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
 
-    let make_svc = make_service_fn(move |_conn: &AddrStream| {
+    let health_check_task = backends::spawn_health_checks(
+        backends,
+        state.client.clone(),
+        state.backend_scheme.clone(),
+        shutdown_rx.clone(),
+    );
+
+    let http3_task = endpoint
+        .map(|endpoint| tokio::spawn(http3::serve(endpoint, state.clone(), shutdown_rx.clone())));
+
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
         let state = state.clone();
+        let peer = conn.remote_addr();
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
                 let state = state.clone();
-                async move { Ok::<_, hyper::Error>(handle_request(state, req).await) }
+                async move {
+                    let mut response = handle_request(state.clone(), peer, req).await;
+                    if let Some(alt_svc) = &state.alt_svc {
+                        http3::advertise(&mut response, alt_svc);
+                    }
+                    Ok::<_, hyper::Error>(response)
+                }
             }))
         }
     });
 
     let server = hyper::Server::from_tcp(listener)?.serve(make_svc);
-    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let mut shutdown_rx = shutdown_rx;
     let graceful = server.with_graceful_shutdown(async move {
-        let _ = shutdown_rx.await;
+        let _ = shutdown_rx.changed().await;
     });
     let task = tokio::spawn(async move {
         if let Err(err) = graceful.await {
             error!(%err, "proxy server error");
         }
+        if let Some(http3_task) = http3_task {
+            let _ = http3_task.await;
+        }
+        if let Some(health_check_task) = health_check_task {
+            let _ = health_check_task.await;
+        }
+        // The exporter flushes once the last request state is dropped.
+        if let Some(otlp_task) = otlp_task {
+            let _ = tokio::time::timeout(Duration::from_secs(15), otlp_task).await;
+        }
     });
 
     Ok(ProxyHandle {
         addr: local_addr,
+        http3_addr,
         shutdown: Some(shutdown_tx),
         task,
     })
 }
 
-async fn handle_request(state: Arc<AppState>, req: Request<Body>) -> Response<Body> {
+async fn handle_request(
+    state: Arc<AppState>,
+    peer: SocketAddr,
+    mut req: Request<Body>,
+) -> Response<Body> {
+    let start = SystemTime::now();
+    let context = RequestContext::from_headers(req.headers());
+    context.apply(req.headers_mut());
+    let method = req.method().clone();
+    let host = extract_host(&req).unwrap_or_default();
+    let path = req.uri().path().to_string();
+
+    let span = info_span!(
+        "request",
+        request_id = %context.request_id,
+        trace_id = %context.trace_id,
+        %method,
+        %host,
+        %path,
+    );
+    let mut response = dispatch_request(state.clone(), peer, req)
+        .instrument(span.clone())
+        .await;
+    span.in_scope(|| debug!(status = response.status().as_u16(), "request finished"));
+
+    if let Ok(value) = HeaderValue::from_str(&context.request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    // Gateway failures are what users report, so their pages carry the ID
+    // needed to find the matching proxy and backend logs.
+    if matches!(
+        response.status(),
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    ) && let Some(ErrorPage(message)) = response.extensions_mut().remove::<ErrorPage>()
+    {
+        *response.body_mut() = Body::from(format!(
+            "{}\n\nrequest id: {}\n",
+            message, context.request_id
+        ));
+    }
+
+    if let Some(otlp) = &state.otlp
+        && context.sampled
+        && path != "/health"
+    {
+        otlp.record(SpanRecord {
+            status: response.status().as_u16(),
+            context,
+            method,
+            host,
+            path,
+            start,
+            end: SystemTime::now(),
+        });
+    }
+    response
+}
+
+async fn dispatch_request(
+    state: Arc<AppState>,
+    peer: SocketAddr,
+    req: Request<Body>,
+) -> Response<Body> {
     if req.uri().path() == "/health" {
-        return json_response(
-            StatusCode::OK,
-            json!({
-                "status": "healthy",
-                "timestamp": Utc::now().to_rfc3339(),
-            }),
+        let mut body = json!({
+            "status": "healthy",
+            "timestamp": Utc::now().to_rfc3339(),
+        });
+        if let Some(backends) = state.backends.status() {
+            body["backends"] = backends;
+        }
+        return json_response(StatusCode::OK, body);
+    }
+
+    if let Err(denial) = state.access.check(peer, &req) {
+        return text_response(
+            denial.status,
+            denial.status.canonical_reason().unwrap_or("Forbidden"),
         );
     }
 
@@ -164,6 +344,25 @@ async fn handle_request(state: Arc<AppState>, req: Request<Body>) -> Response<Bo
         }
     };
 
+    let mut req = req;
+    let preview_cookie = match &state.signed_urls {
+        Some(signed_urls) => match signed_urls.authorize(&host, &mut req, Utc::now().timestamp()) {
+            Ok(cookie) => cookie,
+            Err(rejection) => return text_response(rejection.status, rejection.message),
+        },
+        None => None,
+    };
+
+    let mut response = route_request(state, host, req).await;
+    if let Some(cookie) = preview_cookie
+        && let Ok(value) = HeaderValue::from_str(&cookie)
+    {
+        response.headers_mut().append(header::SET_COOKIE, value);
+    }
+    response
+}
+
+async fn route_request(state: Arc<AppState>, host: String, req: Request<Body>) -> Response<Body> {
     if req.uri().path() == "/version" {
         match parse_cmux_host(&host) {
             Some((Some(_), _)) => {
@@ -326,6 +525,24 @@ enum Target {
     },
 }
 
+fn resolve_target(
+    state: &AppState,
+    target: Target,
+) -> (Scheme, String, Option<u16>, Option<BackendLease>) {
+    match target {
+        Target::BackendPort(port) => {
+            let lease = state.backends.acquire();
+            (
+                state.backend_scheme.clone(),
+                lease.host().to_string(),
+                Some(port),
+                Some(lease),
+            )
+        }
+        Target::Absolute { scheme, host, port } => (scheme, host, port, None),
+    }
+}
+
 #[derive(Clone)]
 struct ProxyBehavior {
     skip_service_worker: bool,
@@ -374,14 +591,8 @@ async fn forward_request(
         UpgradeCheck::NotUpgrade => {}
     }
 
-    let (scheme, host, port_opt) = match target {
-        Target::BackendPort(port) => (
-            state.backend_scheme.clone(),
-            state.backend_host.clone(),
-            Some(port),
-        ),
-        Target::Absolute { scheme, host, port } => (scheme, host, port),
-    };
+    // The lease keeps the request counted against its host while it drains.
+    let (scheme, host, port_opt, _lease) = resolve_target(&state, target);
 
     let authority = match port_opt {
         Some(port) => format!("{}:{}", host, port),
@@ -439,7 +650,10 @@ async fn forward_request(
 
     let response = match state.client.request(req).await {
         Ok(resp) => resp,
-        Err(_) => return text_response(StatusCode::BAD_GATEWAY, "Upstream fetch failed"),
+        Err(err) => {
+            warn!(%err, "upstream fetch failed");
+            return text_response(StatusCode::BAD_GATEWAY, "Upstream fetch failed");
+        }
     };
 
     if original_method == Method::HEAD
@@ -449,11 +663,21 @@ async fn forward_request(
         )
         && let Some(context) = head_fallback_context
         && let Some(fallback) =
-            handle_head_method_not_allowed(state, context, behavior.clone()).await
+            handle_head_method_not_allowed(state.clone(), context, behavior.clone()).await
     {
         return fallback;
     }
 
+    let response = match state.max_response_bytes {
+        // HEAD responses declare the length of a body they never send
+        Some(limit) if original_method != Method::HEAD => {
+            match body_limit::enforce(response, limit) {
+                Ok(response) => response,
+                Err(len) => return response_too_large(Some(len), limit),
+            }
+        }
+        _ => response,
+    };
     transform_response(response, behavior).await
 }
 
@@ -495,10 +719,12 @@ async fn transform_head_response_from_get(
     let version = transformed_response.version();
     let headers = transformed_response.headers().clone();
 
-    // Drain the transformed body so we can surface an accurate Content-Length
-    // header that matches the rewritten GET response.
-    let body_bytes = body::to_bytes(transformed_response.into_body()).await?;
-    let body_len = body_bytes.len();
+    // Surface a Content-Length matching the rewritten GET response, counting
+    // the body only when its length is not already known.
+    let body_len = match body_limit::content_length(&headers) {
+        Some(len) => len as usize,
+        None => body_limit::drain(transformed_response.into_body()).await?,
+    };
 
     Ok(build_head_response(
         status,
@@ -556,14 +782,7 @@ async fn handle_websocket(
     target: Target,
     behavior: ProxyBehavior,
 ) -> Response<Body> {
-    let (scheme, host, port_opt) = match target {
-        Target::BackendPort(port) => (
-            state.backend_scheme.clone(),
-            state.backend_host.clone(),
-            Some(port),
-        ),
-        Target::Absolute { scheme, host, port } => (scheme, host, port),
-    };
+    let (scheme, host, port_opt, lease) = resolve_target(&state, target);
 
     let authority = match port_opt {
         Some(port) => format!("{}:{}", host, port),
@@ -632,7 +851,9 @@ async fn handle_websocket(
     let client_upgrade = hyper::upgrade::on(req);
     let response = build_websocket_response(&backend_headers);
 
-    tokio::spawn(async move {
+    let tunnel = async move {
+        // Hold the backend lease until the tunnel closes.
+        let _lease = lease;
         match client_upgrade.await {
             Ok(client_stream) => {
                 if let Err(err) = tunnel_upgraded(client_stream, backend_stream).await {
@@ -645,7 +866,8 @@ async fn handle_websocket(
                 let _ = backend_stream.shutdown().await;
             }
         }
-    });
+    };
+    tokio::spawn(tunnel.in_current_span());
 
     response
 }
@@ -835,9 +1057,18 @@ async fn transform_response(response: Response<Body>, behavior: ProxyBehavior) -
         .and_then(|v| v.to_str().ok())
         .unwrap_or("");
 
-    if content_type.contains("text/html") {
-        match body::to_bytes(response.into_body()).await {
-            Ok(bytes) => {
+    let rewritable = body_limit::content_length(&headers)
+        .is_none_or(|len| len <= body_limit::MAX_REWRITE_BYTES as u64);
+    if content_type.contains("text/html") && rewritable {
+        match body_limit::buffer_up_to(response.into_body(), body_limit::MAX_REWRITE_BYTES).await {
+            Ok(Buffered::TooLarge(body)) => {
+                debug!("html body too large to rewrite; streaming it unchanged");
+                forward_response_with_body(
+                    status, version, &headers, &behavior, body,
+                    /* strip_payload_headers */ false,
+                )
+            }
+            Ok(Buffered::Complete(bytes)) => {
                 let decoded =
                     match decode_body_with_encoding(bytes.as_ref(), content_encoding.as_deref()) {
                         Ok(body) => Bytes::from(body),
@@ -884,7 +1115,10 @@ async fn transform_response(response: Response<Body>, behavior: ProxyBehavior) -
                     }
                 }
             }
-            Err(_) => text_response(StatusCode::BAD_GATEWAY, "Failed to read upstream body"),
+            Err(err) => match body_limit::exceeded(&err) {
+                Some(limit) => response_too_large(None, limit),
+                None => text_response(StatusCode::BAD_GATEWAY, "Failed to read upstream body"),
+            },
         }
     } else {
         forward_response_with_body(
@@ -1234,10 +1468,31 @@ fn cors_response(status: StatusCode) -> Response<Body> {
     builder.body(Body::empty()).unwrap()
 }
 
+/// Marks a response as generated by the proxy rather than the upstream, so
+/// `handle_request` can add the request ID to its body.
+#[derive(Clone)]
+struct ErrorPage(String);
+
+fn response_too_large(len: Option<u64>, limit: u64) -> Response<Body> {
+    warn!(len, limit, "refusing upstream response over the size limit");
+    let size = match len {
+        Some(len) => format!("{} bytes", len),
+        None => "more than that".to_string(),
+    };
+    text_response(
+        StatusCode::BAD_GATEWAY,
+        &format!(
+            "Upstream response too large: the proxy relays at most {} bytes and this response is {}.",
+            limit, size
+        ),
+    )
+}
+
 fn text_response(status: StatusCode, body: &str) -> Response<Body> {
     Response::builder()
         .status(status)
         .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .extension(ErrorPage(body.to_string()))
         .body(Body::from(body.to_string()))
         .unwrap()
 }
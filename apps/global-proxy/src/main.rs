@@ -1,6 +1,9 @@
-use std::{net::SocketAddr, str::FromStr};
+use std::{net::SocketAddr, str::FromStr, time::Duration};
 
-use global_proxy::{ProxyConfig, spawn_proxy};
+use global_proxy::{
+    AccessPolicy, BackendFailoverConfig, Http3Config, OtlpConfig, ProxyConfig, SignedUrlConfig,
+    spawn_proxy,
+};
 use http::uri::Scheme;
 use tracing::info;
 
@@ -24,8 +27,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             SocketAddr::from(([0, 0, 0, 0], port))
         }
     };
-    let backend_host =
-        std::env::var("GLOBAL_PROXY_BACKEND_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+    // A comma-separated list names fallback hosts in priority order.
+    let mut backend_hosts = env_list("GLOBAL_PROXY_BACKEND_HOST");
+    let backend_host = if backend_hosts.is_empty() {
+        "127.0.0.1".to_string()
+    } else {
+        backend_hosts.remove(0)
+    };
+    let backend_failover = backend_failover_from_env(backend_hosts)?;
 
     let backend_scheme = match std::env::var("GLOBAL_PROXY_BACKEND_SCHEME") {
         Ok(value) => Scheme::from_str(&value)
@@ -40,16 +49,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .ok()
         .and_then(normalize_suffix);
 
+    let access = access_policy_from_env()?;
+    let signed_urls = signed_urls_from_env()?;
+    let http3 = http3_from_env(bind_addr)?;
+    let otlp = otlp_from_env();
+    let max_response_bytes = match std::env::var("GLOBAL_PROXY_MAX_RESPONSE_BYTES") {
+        Ok(value) => Some(
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("GLOBAL_PROXY_MAX_RESPONSE_BYTES '{}' is invalid", value))?,
+        ),
+        Err(_) => None,
+    };
+
     let handle = spawn_proxy(ProxyConfig {
         bind_addr,
         backend_host,
+        backend_failover,
         backend_scheme,
         morph_domain_suffix,
         workspace_domain_suffix,
+        access,
+        signed_urls,
+        http3,
+        otlp,
+        max_response_bytes,
     })
     .await?;
 
     info!(addr = %handle.addr, "global proxy listening");
+    if let Some(addr) = handle.http3_addr {
+        info!(%addr, "global proxy listening for http/3");
+    }
 
     tokio::signal::ctrl_c().await?;
 
@@ -68,3 +100,194 @@ fn normalize_suffix(value: String) -> Option<String> {
         Some(format!(".{}", trimmed))
     }
 }
+
+fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn backend_failover_from_env(
+    fallback_hosts: Vec<String>,
+) -> Result<Option<BackendFailoverConfig>, Box<dyn std::error::Error>> {
+    if fallback_hosts.is_empty() {
+        return Ok(None);
+    }
+    let defaults = BackendFailoverConfig::default();
+    let health_check_port = match std::env::var("GLOBAL_PROXY_BACKEND_HEALTH_PORT") {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| format!("GLOBAL_PROXY_BACKEND_HEALTH_PORT '{}' is invalid", value))?,
+        Err(_) => {
+            return Err(
+                "GLOBAL_PROXY_BACKEND_HEALTH_PORT is required when GLOBAL_PROXY_BACKEND_HOST lists several hosts"
+                    .into(),
+            );
+        }
+    };
+    let interval = match std::env::var("GLOBAL_PROXY_BACKEND_HEALTH_INTERVAL_SECS") {
+        Ok(value) => Duration::from_secs(value.trim().parse().map_err(|_| {
+            format!(
+                "GLOBAL_PROXY_BACKEND_HEALTH_INTERVAL_SECS '{}' is invalid",
+                value
+            )
+        })?),
+        Err(_) => defaults.interval,
+    };
+    Ok(Some(BackendFailoverConfig {
+        fallback_hosts,
+        health_check_port,
+        health_check_path: std::env::var("GLOBAL_PROXY_BACKEND_HEALTH_PATH")
+            .unwrap_or(defaults.health_check_path),
+        interval,
+        ..defaults
+    }))
+}
+
+fn access_policy_from_env() -> Result<AccessPolicy, Box<dyn std::error::Error>> {
+    let parse_nets = |name: &str| {
+        env_list(name)
+            .iter()
+            .map(|item| item.parse())
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|err| format!("{}: {}", name, err))
+    };
+
+    // Path patterns are whitespace-separated so they can contain commas.
+    let deny_paths = std::env::var("GLOBAL_PROXY_DENY_PATHS")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(regex::Regex::new)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("GLOBAL_PROXY_DENY_PATHS: {}", err))?;
+
+    let methods = env_list("GLOBAL_PROXY_ALLOWED_METHODS");
+    let allowed_methods = if methods.is_empty() {
+        None
+    } else {
+        Some(
+            methods
+                .iter()
+                .map(|m| http::Method::from_str(&m.to_ascii_uppercase()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("GLOBAL_PROXY_ALLOWED_METHODS: {}", err))?,
+        )
+    };
+
+    let parse_usize = |name: &str| -> Result<Option<usize>, String> {
+        match std::env::var(name) {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{} '{}' is invalid", name, value)),
+            Err(_) => Ok(None),
+        }
+    };
+
+    Ok(AccessPolicy {
+        allow_ips: parse_nets("GLOBAL_PROXY_ALLOW_IPS")?,
+        deny_ips: parse_nets("GLOBAL_PROXY_DENY_IPS")?,
+        deny_paths,
+        allowed_methods,
+        max_uri_length: parse_usize("GLOBAL_PROXY_MAX_URI_LENGTH")?,
+        trusted_proxy_hops: parse_usize("GLOBAL_PROXY_TRUSTED_PROXY_HOPS")?.unwrap_or(0),
+    })
+}
+
+fn signed_urls_from_env() -> Result<Option<SignedUrlConfig>, Box<dyn std::error::Error>> {
+    let domain_suffixes: Vec<String> = env_list("GLOBAL_PROXY_SIGNED_DOMAIN_SUFFIXES")
+        .into_iter()
+        .filter_map(normalize_suffix)
+        .collect();
+    if domain_suffixes.is_empty() {
+        return Ok(None);
+    }
+    let secret = std::env::var("GLOBAL_PROXY_SIGNING_SECRET").unwrap_or_default();
+    if secret.is_empty() {
+        return Err(
+            "GLOBAL_PROXY_SIGNING_SECRET is required when GLOBAL_PROXY_SIGNED_DOMAIN_SUFFIXES is set"
+                .into(),
+        );
+    }
+    Ok(Some(SignedUrlConfig {
+        secret: secret.into_bytes(),
+        domain_suffixes,
+    }))
+}
+
+fn env_flag(name: &str, default: bool) -> Result<bool, String> {
+    match std::env::var(name) {
+        Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(format!("{} '{}' is invalid", name, value)),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+fn http3_from_env(
+    bind_addr: SocketAddr,
+) -> Result<Option<Http3Config>, Box<dyn std::error::Error>> {
+    let cert_path = std::env::var("GLOBAL_PROXY_H3_CERT").ok();
+    let key_path = std::env::var("GLOBAL_PROXY_H3_KEY").ok();
+    if !env_flag("GLOBAL_PROXY_H3", cert_path.is_some())? {
+        return Ok(None);
+    }
+    let (Some(cert_path), Some(key_path)) = (cert_path, key_path) else {
+        return Err("GLOBAL_PROXY_H3_CERT and GLOBAL_PROXY_H3_KEY are required for HTTP/3".into());
+    };
+
+    let bind_addr = match std::env::var("GLOBAL_PROXY_H3_BIND") {
+        Ok(addr) => addr
+            .parse()
+            .map_err(|_| format!("GLOBAL_PROXY_H3_BIND '{}' is invalid", addr))?,
+        Err(_) => bind_addr,
+    };
+    let advertise_port = match std::env::var("GLOBAL_PROXY_H3_ALT_SVC_PORT") {
+        Ok(value) => Some(
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("GLOBAL_PROXY_H3_ALT_SVC_PORT '{}' is invalid", value))?,
+        ),
+        Err(_) => None,
+    };
+
+    Ok(Some(Http3Config {
+        bind_addr,
+        cert_chain_pem: std::fs::read(&cert_path)
+            .map_err(|err| format!("GLOBAL_PROXY_H3_CERT {}: {}", cert_path, err))?,
+        private_key_pem: std::fs::read(&key_path)
+            .map_err(|err| format!("GLOBAL_PROXY_H3_KEY {}: {}", key_path, err))?,
+        advertise: env_flag("GLOBAL_PROXY_H3_ALT_SVC", true)?,
+        advertise_port,
+    }))
+}
+
+/// `GLOBAL_PROXY_OTLP_ENDPOINT` wins over the standard OpenTelemetry variable
+/// so the proxy can report somewhere other than a shared sidecar default.
+fn otlp_from_env() -> Option<OtlpConfig> {
+    let endpoint = ["GLOBAL_PROXY_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_ENDPOINT"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .map(|value| value.trim().to_string())
+        .find(|value| !value.is_empty())?;
+    let service_name = std::env::var("OTEL_SERVICE_NAME")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "global-proxy".to_string());
+    Some(OtlpConfig {
+        endpoint,
+        service_name,
+    })
+}
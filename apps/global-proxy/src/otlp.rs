@@ -0,0 +1,221 @@
+//! Minimal OTLP/HTTP span export.
+//!
+//! When a collector endpoint is configured, each proxied request is reported
+//! as one server span, batched and posted as OTLP JSON to
+//! `{endpoint}/v1/traces`. Export is best effort: if the collector is slow or
+//! down, spans are dropped instead of holding up requests.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::{Method, Request, Uri, header};
+use hyper::Body;
+use serde_json::{Value, json};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::warn;
+
+use crate::{HttpClient, ProxyError, VERSION, request_id::RequestContext};
+
+const QUEUE_CAPACITY: usize = 4096;
+const MAX_BATCH: usize = 512;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// OTLP span kind and status codes, from the protocol's enums.
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_CODE_ERROR: u8 = 2;
+
+#[derive(Clone, Debug)]
+pub struct OtlpConfig {
+    /// Collector base URL, e.g. `http://otel-collector:4318`.
+    pub endpoint: String,
+    /// Reported as the `service.name` resource attribute.
+    pub service_name: String,
+}
+
+/// One finished request, as exported.
+pub(crate) struct SpanRecord {
+    pub context: RequestContext,
+    pub method: Method,
+    pub host: String,
+    pub path: String,
+    pub status: u16,
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl SpanRecord {
+    fn to_json(&self) -> Value {
+        let mut span = json!({
+            "traceId": self.context.trace_id,
+            "spanId": self.context.span_id,
+            "name": self.method.as_str(),
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(self.end),
+            "attributes": [
+                string_attribute("http.request.method", self.method.as_str()),
+                string_attribute("server.address", &self.host),
+                string_attribute("url.path", &self.path),
+                json!({
+                    "key": "http.response.status_code",
+                    "value": { "intValue": self.status.to_string() },
+                }),
+                string_attribute("cmux.request_id", &self.context.request_id),
+            ],
+            "status": {},
+        });
+        if let Some(parent) = &self.context.parent_span_id {
+            span["parentSpanId"] = json!(parent);
+        }
+        if self.status >= 500 {
+            span["status"] = json!({ "code": STATUS_CODE_ERROR });
+        }
+        span
+    }
+}
+
+/// Handle for queueing spans; the export task ends once every handle is gone.
+#[derive(Clone)]
+pub(crate) struct OtlpExporter {
+    tx: mpsc::Sender<SpanRecord>,
+}
+
+impl OtlpExporter {
+    pub(crate) fn spawn(
+        config: OtlpConfig,
+        client: HttpClient,
+    ) -> Result<(Self, JoinHandle<()>), ProxyError> {
+        let uri = format!("{}/v1/traces", config.endpoint.trim_end_matches('/'))
+            .parse::<Uri>()
+            .map_err(|err| ProxyError::Otlp(format!("endpoint {}: {}", config.endpoint, err)))?;
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let task = tokio::spawn(export_loop(rx, client, uri, config.service_name));
+        Ok((Self { tx }, task))
+    }
+
+    pub(crate) fn record(&self, span: SpanRecord) {
+        // A full queue means the collector can't keep up; drop the span.
+        let _ = self.tx.try_send(span);
+    }
+}
+
+async fn export_loop(
+    mut rx: mpsc::Receiver<SpanRecord>,
+    client: HttpClient,
+    uri: Uri,
+    service_name: String,
+) {
+    let mut batch = Vec::new();
+    let mut ticker = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        let closed = tokio::select! {
+            span = rx.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < MAX_BATCH {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = ticker.tick() => false,
+        };
+        if !batch.is_empty() {
+            let body = export_request(&service_name, &batch).to_string();
+            post(&client, &uri, body, batch.len()).await;
+            batch.clear();
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+async fn post(client: &HttpClient, uri: &Uri, body: String, spans: usize) {
+    let request = match Request::post(uri.clone())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+    {
+        Ok(request) => request,
+        Err(err) => {
+            warn!(%err, "failed to build otlp export request");
+            return;
+        }
+    };
+    match tokio::time::timeout(EXPORT_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => {}
+        Ok(Ok(response)) => warn!(status = %response.status(), spans, "otlp export rejected"),
+        Ok(Err(err)) => warn!(%err, spans, "otlp export failed"),
+        Err(_) => warn!(spans, "otlp export timed out"),
+    }
+}
+
+fn export_request(service_name: &str, spans: &[SpanRecord]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [string_attribute("service.name", service_name)],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "global-proxy", "version": VERSION },
+                "spans": spans.iter().map(SpanRecord::to_json).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP JSON encodes 64-bit integers as strings.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_server_spans_as_otlp_json() {
+        let start = UNIX_EPOCH + Duration::from_millis(1_500);
+        let span = SpanRecord {
+            context: RequestContext {
+                request_id: "req-1".into(),
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".into(),
+                span_id: "b7ad6b7169203331".into(),
+                parent_span_id: Some("00f067aa0ba902b7".into()),
+                sampled: true,
+            },
+            method: Method::GET,
+            host: "port-3000-abc.cmux.sh".into(),
+            path: "/app".into(),
+            status: 502,
+            start,
+            end: start + Duration::from_millis(20),
+        };
+
+        let request = export_request("global-proxy", &[span]);
+        let resource = &request["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0],
+            json!({ "key": "service.name", "value": { "stringValue": "global-proxy" } })
+        );
+        let span = &resource["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span["spanId"], "b7ad6b7169203331");
+        assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(span["name"], "GET");
+        assert_eq!(span["kind"], 2);
+        assert_eq!(span["startTimeUnixNano"], "1500000000");
+        assert_eq!(span["endTimeUnixNano"], "1520000000");
+        assert_eq!(span["status"]["code"], 2);
+        assert!(span["attributes"].as_array().unwrap().contains(
+            &json!({ "key": "http.response.status_code", "value": { "intValue": "502" } })
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+//! Request IDs and W3C trace context for proxied requests.
+//!
+//! Every request is tagged with an `x-request-id` (the one the client or load
+//! balancer sent, when it looks sane) and a `traceparent` naming this hop as
+//! the parent span. Both are forwarded upstream and the ID is echoed back, so
+//! proxy logs, backend logs and error pages can be joined on one value.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use http::{HeaderMap, HeaderValue};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Longer incoming IDs are replaced rather than trusted into every log line.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RequestContext {
+    pub request_id: String,
+    /// 32 lowercase hex digits, shared by every hop of the trace.
+    pub trace_id: String,
+    /// 16 lowercase hex digits identifying this proxy's span.
+    pub span_id: String,
+    /// The caller's span, when the request arrived with a `traceparent`.
+    pub parent_span_id: Option<String>,
+    pub sampled: bool,
+}
+
+impl RequestContext {
+    /// Continue the caller's trace if it sent one, otherwise start a new one.
+    /// Requests without a usable ID reuse the trace ID so there is only one
+    /// value to search for.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let parent = headers
+            .get(TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_traceparent);
+        let (trace_id, parent_span_id, sampled) = match parent {
+            Some(parent) => (parent.trace_id, Some(parent.span_id), parent.sampled),
+            None => (random_hex(16), None, true),
+        };
+        let request_id = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| is_valid_request_id(id))
+            .map(str::to_string)
+            .unwrap_or_else(|| trace_id.clone());
+
+        Self {
+            request_id,
+            trace_id,
+            span_id: random_hex(8),
+            parent_span_id,
+            sampled,
+        }
+    }
+
+    pub(crate) fn traceparent(&self) -> String {
+        let flags = if self.sampled { "01" } else { "00" };
+        format!("00-{}-{}-{}", self.trace_id, self.span_id, flags)
+    }
+
+    /// Set the request ID and this hop's `traceparent` on outgoing headers.
+    pub(crate) fn apply(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.request_id) {
+            headers.insert(REQUEST_ID_HEADER, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&self.traceparent()) {
+            headers.insert(TRACEPARENT_HEADER, value);
+        }
+        if self.parent_span_id.is_none() {
+            // Vendor state belongs to the trace it arrived with, not ours.
+            headers.remove("tracestate");
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct TraceParent {
+    trace_id: String,
+    span_id: String,
+    sampled: bool,
+}
+
+/// Parse `VERSION-TRACEID-SPANID-FLAGS`. Unknown future versions are read by
+/// their first four fields, as the spec asks; all-zero IDs are invalid.
+fn parse_traceparent(value: &str) -> Option<TraceParent> {
+    let value = value.trim();
+    let mut parts = value.split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    let flags = parts.next()?;
+    if !is_lower_hex(version, 2) || version == "ff" || (version == "00" && value.len() != 55) {
+        return None;
+    }
+    if !is_lower_hex(trace_id, 32) || !is_lower_hex(span_id, 16) || !is_lower_hex(flags, 2) {
+        return None;
+    }
+    if trace_id.bytes().all(|b| b == b'0') || span_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some(TraceParent {
+        trace_id: trace_id.to_string(),
+        span_id: span_id.to_string(),
+        sampled: flags & 1 == 1,
+    })
+}
+
+fn is_lower_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// `bytes` random bytes as hex. IDs only need to be unique, not secret, so
+/// std's randomly keyed hasher over a counter and the clock is enough.
+fn random_hex(bytes: usize) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut out = String::with_capacity(bytes * 2 + 16);
+    while out.len() < bytes * 2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos()),
+        );
+        out.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    out.truncate(bytes * 2);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn parses_traceparent() {
+        assert_eq!(
+            parse_traceparent(PARENT),
+            Some(TraceParent {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".into(),
+                span_id: "00f067aa0ba902b7".into(),
+                sampled: true,
+            })
+        );
+        let future = "cc-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra";
+        assert!(!parse_traceparent(future).unwrap().sampled);
+
+        for bad in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(parse_traceparent(bad), None, "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn continues_incoming_trace_and_request_id() {
+        let context = RequestContext::from_headers(&headers(&[
+            (TRACEPARENT_HEADER, PARENT),
+            (REQUEST_ID_HEADER, "lb-7f3a"),
+        ]));
+        assert_eq!(context.request_id, "lb-7f3a");
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert!(is_lower_hex(&context.span_id, 16));
+        assert_ne!(context.span_id, "00f067aa0ba902b7");
+
+        let mut forwarded = headers(&[("tracestate", "vendor=1")]);
+        context.apply(&mut forwarded);
+        assert_eq!(forwarded[REQUEST_ID_HEADER], "lb-7f3a");
+        assert_eq!(
+            forwarded[TRACEPARENT_HEADER],
+            format!("00-4bf92f3577b34da6a3ce929d0e0e4736-{}-01", context.span_id)
+        );
+        assert_eq!(forwarded["tracestate"], "vendor=1");
+    }
+
+    #[test]
+    fn starts_new_trace_and_replaces_unusable_ids() {
+        let long = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        for id in ["", "has space", long.as_str()] {
+            let context = RequestContext::from_headers(&headers(&[(REQUEST_ID_HEADER, id)]));
+            assert!(is_lower_hex(&context.trace_id, 32));
+            assert_eq!(context.request_id, context.trace_id);
+            assert_eq!(context.parent_span_id, None);
+            assert!(context.sampled);
+        }
+
+        let first = RequestContext::from_headers(&HeaderMap::new());
+        let second = RequestContext::from_headers(&HeaderMap::new());
+        assert_ne!(first.trace_id, second.trace_id);
+
+        let mut forwarded = headers(&[("tracestate", "vendor=1")]);
+        first.apply(&mut forwarded);
+        assert!(forwarded.get("tracestate").is_none());
+        assert!(parse_traceparent(forwarded[TRACEPARENT_HEADER].to_str().unwrap()).is_some());
+    }
+}
@@ -0,0 +1,368 @@
+//! Time-limited signed preview URLs.
+//!
+//! For hosts under a configured domain suffix, requests must carry
+//! `cmux_expires=<unix seconds>&cmux_sig=<hex HMAC-SHA256>` where the MAC covers
+//! `host`, `path` and the expiry. A valid link is answered with a cookie signed
+//! over the same host and path (valid until the same expiry), which grants that
+//! path and everything below it, so the page's subresources and websockets load
+//! without their own signatures. The signing parameters and the cookie are
+//! removed before the request is forwarded, so workspace apps never see them.
+
+use hmac::{Hmac, Mac};
+use http::{Request, StatusCode, Uri, header};
+use sha2::Sha256;
+
+pub const EXPIRES_PARAM: &str = "cmux_expires";
+pub const SIGNATURE_PARAM: &str = "cmux_sig";
+pub const COOKIE_NAME: &str = "cmux_preview";
+
+#[derive(Clone, Debug, Default)]
+pub struct SignedUrlConfig {
+    /// HMAC key shared with whatever issues preview links.
+    pub secret: Vec<u8>,
+    /// Host suffixes (e.g. `.preview.cmux.sh`) that require a signature.
+    pub domain_suffixes: Vec<String>,
+}
+
+/// Why a request was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub status: StatusCode,
+    pub message: &'static str,
+}
+
+impl SignedUrlConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.secret.is_empty() && !self.domain_suffixes.is_empty()
+    }
+
+    pub fn applies_to(&self, host: &str) -> bool {
+        self.is_enabled()
+            && self
+                .domain_suffixes
+                .iter()
+                .any(|suffix| host.ends_with(suffix.as_str()))
+    }
+
+    /// Hex HMAC-SHA256 signature for `host` + `path` valid until `expires`.
+    pub fn sign(&self, host: &str, path: &str, expires: i64) -> String {
+        hex(&self.mac(host, path, expires).finalize().into_bytes())
+    }
+
+    /// Query string (without leading `?`) that makes `host` + `path` accessible until `expires`.
+    pub fn signed_query(&self, host: &str, path: &str, expires: i64) -> String {
+        format!(
+            "{}={}&{}={}",
+            EXPIRES_PARAM,
+            expires,
+            SIGNATURE_PARAM,
+            self.sign(host, path, expires)
+        )
+    }
+
+    fn mac(&self, host: &str, path: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}\n{}\n{}", host, path, expires).as_bytes());
+        mac
+    }
+
+    fn verify(&self, host: &str, path: &str, expires: i64, signature: &str) -> bool {
+        match unhex(signature) {
+            Some(bytes) => self.mac(host, path, expires).verify_slice(&bytes).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Check the request's signature or preview cookie. On success the signing
+    /// parameters are stripped from the URI, and a `Set-Cookie` value is
+    /// returned when a fresh link was presented.
+    pub fn authorize<B>(
+        &self,
+        host: &str,
+        req: &mut Request<B>,
+        now: i64,
+    ) -> Result<Option<String>, Rejection> {
+        if !self.applies_to(host) {
+            return Ok(None);
+        }
+        let cookies = preview_cookies(req);
+        strip_preview_cookie(req);
+
+        let query = req.uri().query().unwrap_or("");
+        let param = |name: &str| {
+            query.split('&').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                (key == name).then_some(value)
+            })
+        };
+
+        if let (Some(expires), Some(signature)) = (param(EXPIRES_PARAM), param(SIGNATURE_PARAM)) {
+            let expires: i64 = expires.parse().map_err(|_| INVALID)?;
+            if !self.verify(host, req.uri().path(), expires, signature) {
+                return Err(INVALID);
+            }
+            if expires <= now {
+                return Err(EXPIRED);
+            }
+            let signature = signature.to_string();
+            let path = req.uri().path().to_string();
+            // The browser only needs to send it below the path; `;` would end the attribute
+            let cookie_path = if path.contains(';') { "/" } else { &path };
+            *req.uri_mut() = strip_signing_params(req.uri());
+            let cookie = format!(
+                "{}={}.{}.{}; Path={}; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+                COOKIE_NAME,
+                expires,
+                hex(path.as_bytes()),
+                signature,
+                cookie_path,
+                expires - now
+            );
+            return Ok(Some(cookie));
+        }
+
+        // Browsers send one cookie per matching `Path`, most specific first
+        let path = req.uri().path();
+        let mut rejection = REQUIRED;
+        for cookie in cookies {
+            if !covers(&cookie.path, path)
+                || !self.verify(host, &cookie.path, cookie.expires, &cookie.signature)
+            {
+                rejection = INVALID;
+            } else if cookie.expires <= now {
+                rejection = EXPIRED;
+            } else {
+                return Ok(None);
+            }
+        }
+        Err(rejection)
+    }
+}
+
+/// Whether a link signed for `scope` grants `path`: the path itself and
+/// everything below it, on segment boundaries.
+fn covers(scope: &str, path: &str) -> bool {
+    path == scope
+        || path
+            .strip_prefix(scope.trim_end_matches('/'))
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+const REQUIRED: Rejection = Rejection {
+    status: StatusCode::UNAUTHORIZED,
+    message: "This preview requires a signed link",
+};
+const INVALID: Rejection = Rejection {
+    status: StatusCode::FORBIDDEN,
+    message: "Invalid preview link signature",
+};
+const EXPIRED: Rejection = Rejection {
+    status: StatusCode::FORBIDDEN,
+    message: "This preview link has expired",
+};
+
+/// A preview cookie: `<expires>.<hex path>.<signature>`.
+struct PreviewCookie {
+    expires: i64,
+    path: String,
+    signature: String,
+}
+
+fn is_preview_cookie(pair: &str) -> bool {
+    pair.trim()
+        .split_once('=')
+        .is_some_and(|(name, _)| name == COOKIE_NAME)
+}
+
+fn preview_cookies<B>(req: &Request<B>) -> Vec<PreviewCookie> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter(|pair| is_preview_cookie(pair))
+        .map(|pair| {
+            let parsed = pair.trim().split_once('=').and_then(|(_, value)| {
+                let mut parts = value.splitn(3, '.');
+                Some(PreviewCookie {
+                    expires: parts.next()?.parse().ok()?,
+                    path: String::from_utf8(unhex(parts.next()?)?).ok()?,
+                    signature: parts.next()?.to_string(),
+                })
+            });
+            // Unreadable cookies still count as presented, so they fail as invalid
+            parsed.unwrap_or(PreviewCookie {
+                expires: 0,
+                path: String::new(),
+                signature: String::new(),
+            })
+        })
+        .collect()
+}
+
+/// Drop the preview cookie from the request so upstream apps never see it.
+fn strip_preview_cookie<B>(req: &mut Request<B>) {
+    let kept: Vec<String> = req
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter(|pair| !pair.trim().is_empty() && !is_preview_cookie(pair))
+        .map(|pair| pair.trim().to_string())
+        .collect();
+    req.headers_mut().remove(header::COOKIE);
+    if !kept.is_empty()
+        && let Ok(value) = header::HeaderValue::from_str(&kept.join("; "))
+    {
+        req.headers_mut().insert(header::COOKIE, value);
+    }
+}
+
+fn strip_signing_params(uri: &Uri) -> Uri {
+    let query: Vec<&str> = uri
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split_once('=').map_or(*pair, |(key, _)| key);
+            !pair.is_empty() && key != EXPIRES_PARAM && key != SIGNATURE_PARAM
+        })
+        .collect();
+    let path_and_query = if query.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), query.join("&"))
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "port-3000-abc.preview.cmux.sh";
+
+    fn config() -> SignedUrlConfig {
+        SignedUrlConfig {
+            secret: b"test-secret".to_vec(),
+            domain_suffixes: vec![".preview.cmux.sh".to_string()],
+        }
+    }
+
+    fn request(uri: &str) -> Request<()> {
+        Request::builder().uri(uri).body(()).unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_link_and_strips_params() {
+        let config = config();
+        let query = config.signed_query(HOST, "/app", 2_000);
+        let mut req = request(&format!("/app?tab=1&{}", query));
+
+        let cookie = config.authorize(HOST, &mut req, 1_000).unwrap().unwrap();
+        assert_eq!(req.uri().to_string(), "/app?tab=1");
+        assert!(cookie.starts_with("cmux_preview=2000."));
+        assert!(cookie.contains("; Path=/app;"));
+        assert!(cookie.contains("Max-Age=1000"));
+
+        // The cookie then grants paths below the signed one
+        let token = cookie.split(';').next().unwrap().to_string();
+        let with_cookie = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header(header::COOKIE, format!("theme=dark; {}", token))
+                .body(())
+                .unwrap()
+        };
+        let mut asset = with_cookie("/app/assets/app.js");
+        assert_eq!(config.authorize(HOST, &mut asset, 1_500), Ok(None));
+        // ...without passing it on
+        assert_eq!(asset.headers()[header::COOKIE], "theme=dark");
+        assert_eq!(
+            config.authorize(HOST, &mut with_cookie("/app"), 2_500),
+            Err(EXPIRED)
+        );
+        assert_eq!(
+            config.authorize("other.preview.cmux.sh", &mut with_cookie("/app"), 1_500),
+            Err(INVALID)
+        );
+    }
+
+    #[test]
+    fn link_for_one_path_cannot_reach_another() {
+        let config = config();
+        let query = config.signed_query(HOST, "/a", 2_000);
+        let cookie = config
+            .authorize(HOST, &mut request(&format!("/a?{}", query)), 1_000)
+            .unwrap()
+            .unwrap();
+        let token = cookie.split(';').next().unwrap().to_string();
+        let with_cookie = |uri: &str| {
+            let mut req = Request::builder()
+                .uri(uri)
+                .header(header::COOKIE, token.as_str())
+                .body(())
+                .unwrap();
+            config.authorize(HOST, &mut req, 1_500)
+        };
+        assert_eq!(with_cookie("/a"), Ok(None));
+        assert_eq!(with_cookie("/a/b"), Ok(None));
+        assert_eq!(with_cookie("/b"), Err(INVALID));
+        assert_eq!(with_cookie("/ab"), Err(INVALID));
+        assert_eq!(with_cookie("/"), Err(INVALID));
+
+        // A link for the root covers the whole host
+        let query = config.signed_query(HOST, "/", 2_000);
+        let cookie = config
+            .authorize(HOST, &mut request(&format!("/?{}", query)), 1_000)
+            .unwrap()
+            .unwrap();
+        let mut anywhere = Request::builder()
+            .uri("/b/c")
+            .header(header::COOKIE, cookie.split(';').next().unwrap())
+            .body(())
+            .unwrap();
+        assert_eq!(config.authorize(HOST, &mut anywhere, 1_500), Ok(None));
+    }
+
+    #[test]
+    fn rejects_missing_tampered_and_expired_links() {
+        let config = config();
+        assert_eq!(
+            config.authorize(HOST, &mut request("/app"), 1_000),
+            Err(REQUIRED)
+        );
+
+        let query = config.signed_query(HOST, "/app", 2_000);
+        let mut other_path = request(&format!("/admin?{}", query));
+        assert_eq!(config.authorize(HOST, &mut other_path, 1_000), Err(INVALID));
+
+        let mut expired = request(&format!("/app?{}", query));
+        assert_eq!(config.authorize(HOST, &mut expired, 3_000), Err(EXPIRED));
+
+        // Hosts outside the configured suffixes are untouched
+        assert_eq!(
+            config.authorize("port-3000-abc.cmux.sh", &mut request("/app"), 1_000),
+            Ok(None)
+        );
+    }
+}
@@ -1,3 +1,6 @@
+// tungstenite handshake callbacks must return `Result<Response, ErrorResponse>`.
+#![allow(clippy::result_large_err)]
+
 use std::{
     net::{Ipv4Addr, SocketAddr},
     sync::{Arc, Mutex},
@@ -5,7 +8,10 @@ use std::{
 };
 
 use futures_util::{SinkExt, StreamExt};
-use global_proxy::{ProxyConfig, spawn_proxy};
+use global_proxy::{
+    AccessPolicy, BackendFailoverConfig, Http3Config, OtlpConfig, ProxyConfig, SignedUrlConfig,
+    spawn_proxy,
+};
 use hyper::{
     Body, Method as HyperMethod, Request, Response, Server, StatusCode,
     header::HeaderValue,
@@ -27,10 +33,14 @@ struct TestProxy {
 
 impl TestProxy {
     async fn spawn() -> Self {
+        Self::spawn_with(ProxyConfig::default()).await
+    }
+
+    async fn spawn_with(config: ProxyConfig) -> Self {
         let config = ProxyConfig {
             bind_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
             backend_host: "127.0.0.1".to_string(),
-            ..Default::default()
+            ..config
         };
 
         let handle = spawn_proxy(config).await.expect("failed to start proxy");
@@ -123,8 +133,14 @@ impl TestHttpBackend {
         port: u16,
         handler: Arc<dyn Fn(Request<Body>) -> Response<Body> + Send + Sync + 'static>,
     ) -> Self {
-        let listener = std::net::TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
-            .expect("bind backend on port");
+        Self::serve_at(SocketAddr::from((Ipv4Addr::LOCALHOST, port)), handler).await
+    }
+
+    async fn serve_at(
+        addr: SocketAddr,
+        handler: Arc<dyn Fn(Request<Body>) -> Response<Body> + Send + Sync + 'static>,
+    ) -> Self {
+        let listener = std::net::TcpListener::bind(addr).expect("bind backend on port");
         listener.set_nonblocking(true).expect("set nonblocking");
         let addr = listener.local_addr().expect("local addr");
 
@@ -438,6 +454,497 @@ async fn health_check() {
     proxy.shutdown().await;
 }
 
+fn named_backend(name: &'static str) -> Arc<dyn Fn(Request<Body>) -> Response<Body> + Send + Sync> {
+    Arc::new(move |_req: Request<Body>| Response::new(Body::from(name)))
+}
+
+#[tokio::test]
+async fn backend_failover_follows_health_checks() {
+    // Bind and release a port so the primary starts out down.
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .expect("free port")
+        .port();
+    let fallback = TestHttpBackend::serve_at(
+        SocketAddr::from((Ipv4Addr::new(127, 0, 0, 2), port)),
+        named_backend("fallback"),
+    )
+    .await;
+    let proxy = TestProxy::spawn_with(ProxyConfig {
+        backend_failover: Some(BackendFailoverConfig {
+            fallback_hosts: vec!["127.0.0.2".to_string()],
+            health_check_port: port,
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_millis(500),
+            unhealthy_threshold: 1,
+            ..BackendFailoverConfig::default()
+        }),
+        ..ProxyConfig::default()
+    })
+    .await;
+    let host = format!("port-{port}-test.cmux.sh");
+
+    let response = proxy.request(Method::GET, &host, "/", &[]).await;
+    assert_eq!(response.text().await.expect("text"), "fallback");
+
+    let health: serde_json::Value = proxy
+        .request(Method::GET, "localhost", "/health", &[])
+        .await
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(health["backends"]["active"], "127.0.0.2");
+    assert_eq!(health["backends"]["hosts"][0]["healthy"], false);
+    assert_eq!(health["backends"]["hosts"][1]["healthy"], true);
+
+    // Once the primary passes a health check, new requests move back to it.
+    let primary = TestHttpBackend::serve_on_port(port, named_backend("primary")).await;
+    let mut body = String::new();
+    for _ in 0..40 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let response = proxy.request(Method::GET, &host, "/", &[]).await;
+        body = response.text().await.expect("text");
+        if body == "primary" {
+            break;
+        }
+    }
+    assert_eq!(body, "primary");
+
+    proxy.shutdown().await;
+    primary.shutdown().await;
+    fallback.shutdown().await;
+}
+
+#[tokio::test]
+async fn access_policy_blocks_before_forwarding() {
+    let proxy = TestProxy::spawn_with(ProxyConfig {
+        access: AccessPolicy {
+            deny_paths: vec![regex::Regex::new(r"^/\.env").unwrap()],
+            allowed_methods: Some(vec![HyperMethod::GET]),
+            max_uri_length: Some(64),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .await;
+
+    let host = "cmux-test-base-8080.cmux.sh";
+    let response = proxy.request(Method::GET, host, "/.env", &[]).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = proxy.request(Method::POST, host, "/", &[]).await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    let long_path = format!("/{}", "a".repeat(80));
+    let response = proxy.request(Method::GET, host, &long_path, &[]).await;
+    assert_eq!(response.status(), StatusCode::URI_TOO_LONG);
+
+    // Health checks are never filtered
+    let response = proxy
+        .request(Method::POST, "localhost", "/health", &[])
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    proxy.shutdown().await;
+}
+
+#[tokio::test]
+async fn access_policy_denies_source_ips() {
+    let proxy = TestProxy::spawn_with(ProxyConfig {
+        access: AccessPolicy {
+            deny_ips: vec!["127.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .await;
+
+    let response = proxy
+        .request(Method::GET, "cmux-test-base-8080.cmux.sh", "/", &[])
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    proxy.shutdown().await;
+}
+
+#[tokio::test]
+async fn signed_preview_links_gate_forwarding() {
+    let backend = TestHttpBackend::serve(Arc::new(|req| {
+        let cookie = req
+            .headers()
+            .get("cookie")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| format!(" cookie={}", v))
+            .unwrap_or_default();
+        Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::from(format!("{}{}", req.uri(), cookie)))
+            .unwrap()
+    }))
+    .await;
+
+    let signing = SignedUrlConfig {
+        secret: b"preview-secret".to_vec(),
+        domain_suffixes: vec![".cmux.sh".to_string()],
+    };
+    let proxy = TestProxy::spawn_with(ProxyConfig {
+        signed_urls: Some(signing.clone()),
+        ..Default::default()
+    })
+    .await;
+    let host = format!("cmux-demo-{}.cmux.sh", backend.port());
+
+    let response = proxy.request(Method::GET, &host, "/app", &[]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let expired = signing.signed_query(&host, "/app", 1);
+    let response = proxy
+        .request(Method::GET, &host, &format!("/app?{}", expired), &[])
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let expires = chrono::Utc::now().timestamp() + 600;
+    let query = signing.signed_query(&host, "/app", expires);
+    let response = proxy
+        .request(Method::GET, &host, &format!("/app?x=1&{}", query), &[])
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = response
+        .headers()
+        .get("set-cookie")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .expect("preview cookie")
+        .to_string();
+    assert_eq!(response.text().await.expect("body"), "/app?x=1");
+
+    let cookies = format!("theme=dark; {}", cookie);
+    let response = proxy
+        .request(Method::GET, &host, "/app/app.js", &[("Cookie", &cookies)])
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // The preview cookie is not passed upstream
+    assert_eq!(
+        response.text().await.expect("body"),
+        "/app/app.js cookie=theme=dark"
+    );
+
+    // The link only grants the path it was signed for
+    let response = proxy
+        .request(Method::GET, &host, "/admin", &[("Cookie", &cookie)])
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    proxy.shutdown().await;
+    backend.shutdown().await;
+}
+
+async fn http3_get(
+    addr: SocketAddr,
+    cert: &rcgen::CertifiedKey,
+    host: &str,
+    path: &str,
+) -> (u16, String) {
+    use bytes::Buf;
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert.cert.der().clone()).expect("trust cert");
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .expect("tls13")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(tls).expect("quic tls");
+
+    let mut endpoint =
+        quinn::Endpoint::client(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("client");
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+    let conn = endpoint
+        .connect(addr, "localhost")
+        .expect("connect")
+        .await
+        .expect("handshake");
+
+    let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(conn))
+        .await
+        .expect("h3 client");
+    let drive = tokio::spawn(async move { driver.wait_idle().await });
+
+    let request = http1::Request::get(format!("https://{host}{path}"))
+        .body(())
+        .unwrap();
+    let mut stream = send_request.send_request(request).await.expect("send");
+    stream.finish().await.expect("finish");
+    let response = stream.recv_response().await.expect("response");
+    let mut body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await.expect("data") {
+        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+
+    drop(send_request);
+    drive.abort();
+    endpoint.close(0u32.into(), b"done");
+    (
+        response.status().as_u16(),
+        String::from_utf8(body).expect("utf8"),
+    )
+}
+
+#[tokio::test]
+async fn http3_listener_serves_requests_and_is_advertised() {
+    let backend = TestHttpBackend::serve(Arc::new(|req| {
+        Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::from(req.uri().to_string()))
+            .unwrap()
+    }))
+    .await;
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let proxy = TestProxy::spawn_with(ProxyConfig {
+        http3: Some(Http3Config {
+            bind_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            cert_chain_pem: cert.cert.pem().into_bytes(),
+            private_key_pem: cert.key_pair.serialize_pem().into_bytes(),
+            advertise: true,
+            advertise_port: Some(443),
+        }),
+        ..Default::default()
+    })
+    .await;
+    let h3_addr = proxy
+        .handle
+        .as_ref()
+        .and_then(|handle| handle.http3_addr)
+        .expect("http3 addr");
+
+    let response = proxy
+        .request(Method::GET, "localhost", "/health", &[])
+        .await;
+    assert_eq!(response.headers()["alt-svc"], "h3=\":443\"; ma=86400");
+
+    let (status, body) = http3_get(h3_addr, &cert, "localhost", "/health").await;
+    assert_eq!(status, 200);
+    assert!(body.contains("healthy"), "{body}");
+
+    let host = format!("cmux-demo-{}.cmux.sh", backend.port());
+    let (status, body) = http3_get(h3_addr, &cert, &host, "/app?x=1").await;
+    assert_eq!(status, 200);
+    assert_eq!(body, "/app?x=1");
+
+    proxy.shutdown().await;
+    backend.shutdown().await;
+}
+
+fn echo_trace_headers_backend() -> Arc<dyn Fn(Request<Body>) -> Response<Body> + Send + Sync> {
+    Arc::new(|req: Request<Body>| {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Response::new(Body::from(format!(
+            "{}|{}",
+            header("x-request-id"),
+            header("traceparent")
+        )))
+    })
+}
+
+#[tokio::test]
+async fn request_ids_and_trace_context_are_propagated() {
+    let backend = TestHttpBackend::serve(echo_trace_headers_backend()).await;
+    let proxy = TestProxy::spawn().await;
+    let host = format!("port-{}-test.cmux.sh", backend.port());
+
+    let response = proxy
+        .request(
+            Method::GET,
+            &host,
+            "/",
+            &[
+                ("x-request-id", "lb-7f3a"),
+                (
+                    "traceparent",
+                    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                ),
+            ],
+        )
+        .await;
+    assert_eq!(response.headers()["x-request-id"], "lb-7f3a");
+    let body = response.text().await.expect("body");
+    let (request_id, traceparent) = body.split_once('|').expect("echoed headers");
+    assert_eq!(request_id, "lb-7f3a");
+    let parts: Vec<&str> = traceparent.split('-').collect();
+    assert_eq!(parts.len(), 4);
+    assert_eq!(parts[1], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_ne!(parts[2], "00f067aa0ba902b7");
+    assert_eq!(parts[3], "01");
+
+    // Without incoming context the proxy starts a trace and uses its ID.
+    let response = proxy.request(Method::GET, &host, "/", &[]).await;
+    let echoed = response.headers()["x-request-id"]
+        .to_str()
+        .expect("ascii")
+        .to_string();
+    let body = response.text().await.expect("body");
+    let (request_id, traceparent) = body.split_once('|').expect("echoed headers");
+    assert_eq!(request_id, echoed);
+    assert_eq!(echoed.len(), 32);
+    assert!(traceparent.starts_with(&format!("00-{echoed}-")));
+
+    proxy.shutdown().await;
+    backend.shutdown().await;
+}
+
+#[tokio::test]
+async fn gateway_error_pages_include_request_id() {
+    // Bind and release a port so nothing is listening on it.
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .expect("free port")
+        .port();
+    let proxy = TestProxy::spawn().await;
+    let host = format!("port-{port}-test.cmux.sh");
+
+    let response = proxy
+        .request(Method::GET, &host, "/", &[("x-request-id", "req-502")])
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response.headers()["x-request-id"], "req-502");
+    assert_eq!(
+        response.text().await.expect("text"),
+        "Upstream fetch failed\n\nrequest id: req-502\n"
+    );
+
+    // Other proxy responses keep their body but still carry the header.
+    let response = proxy
+        .request(
+            Method::GET,
+            &host,
+            "/",
+            &[("x-request-id", "req-508"), ("X-Cmux-Proxied", "true")],
+        )
+        .await;
+    assert_eq!(response.headers()["x-request-id"], "req-508");
+    assert_eq!(
+        response.text().await.expect("text"),
+        "Loop detected in proxy"
+    );
+
+    proxy.shutdown().await;
+}
+
+#[tokio::test]
+async fn oversized_responses_are_refused_or_cut_off() {
+    let backend = TestHttpBackend::serve(Arc::new(|req: Request<Body>| {
+        let body = match req.uri().path() {
+            "/small" => Body::from("ok"),
+            "/sized" => Body::from(vec![b'x'; 64]),
+            // No Content-Length, so the size is only known while streaming
+            _ => Body::wrap_stream(futures_util::stream::iter(
+                (0..8).map(|_| Ok::<_, std::io::Error>(vec![b'x'; 16])),
+            )),
+        };
+        Response::new(body)
+    }))
+    .await;
+    let proxy = TestProxy::spawn_with(ProxyConfig {
+        max_response_bytes: Some(32),
+        ..Default::default()
+    })
+    .await;
+    let host = format!("port-{}-test.cmux.localhost", backend.port());
+
+    let response = proxy.request(Method::GET, &host, "/small", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.expect("text"), "ok");
+
+    let response = proxy
+        .request(Method::GET, &host, "/sized", &[("x-request-id", "req-big")])
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        response.text().await.expect("text"),
+        "Upstream response too large: the proxy relays at most 32 bytes and this response is 64 bytes.\n\nrequest id: req-big\n"
+    );
+
+    // HEAD only declares the length, so it is not refused
+    let response = proxy.request(Method::HEAD, &host, "/sized", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = proxy.request(Method::GET, &host, "/streamed", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.bytes().await.is_err());
+
+    proxy.shutdown().await;
+    backend.shutdown().await;
+}
+
+#[tokio::test]
+async fn request_spans_are_exported_to_otlp_collector() {
+    let (export_tx, mut export_rx) = tokio::sync::mpsc::unbounded_channel();
+    let collector = TestHttpBackend::serve(Arc::new(move |req: Request<Body>| {
+        let export_tx = export_tx.clone();
+        let path = req.uri().path().to_string();
+        tokio::spawn(async move {
+            let body = hyper::body::to_bytes(req.into_body())
+                .await
+                .expect("export body");
+            let _ = export_tx.send((path, body));
+        });
+        Response::new(Body::empty())
+    }))
+    .await;
+    let backend = TestHttpBackend::serve(echo_trace_headers_backend()).await;
+
+    let proxy = TestProxy::spawn_with(ProxyConfig {
+        otlp: Some(OtlpConfig {
+            endpoint: format!("http://{}/", collector.addr),
+            service_name: "preview-proxy".to_string(),
+        }),
+        ..Default::default()
+    })
+    .await;
+    let host = format!("port-{}-test.cmux.sh", backend.port());
+    let response = proxy
+        .request(Method::GET, &host, "/app", &[("x-request-id", "req-otel")])
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Shutting down flushes the pending batch.
+    proxy.shutdown().await;
+    let (path, body) = tokio::time::timeout(Duration::from_secs(5), export_rx.recv())
+        .await
+        .expect("export arrives")
+        .expect("export body");
+    assert_eq!(path, "/v1/traces");
+    let export: serde_json::Value = serde_json::from_slice(&body).expect("otlp json");
+    let resource = &export["resourceSpans"][0];
+    assert_eq!(
+        resource["resource"]["attributes"][0]["value"]["stringValue"],
+        "preview-proxy"
+    );
+    let span = &resource["scopeSpans"][0]["spans"][0];
+    assert_eq!(span["name"], "GET");
+    let attributes = span["attributes"].as_array().expect("attributes");
+    assert!(attributes.contains(&serde_json::json!({
+        "key": "cmux.request_id",
+        "value": { "stringValue": "req-otel" },
+    })));
+    assert!(attributes.contains(&serde_json::json!({
+        "key": "url.path",
+        "value": { "stringValue": "/app" },
+    })));
+
+    backend.shutdown().await;
+    collector.shutdown().await;
+}
+
 #[tokio::test]
 async fn version_endpoint_reports_package_version() {
     let proxy = TestProxy::spawn().await;
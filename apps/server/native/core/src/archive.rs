@@ -0,0 +1,199 @@
+use anyhow::{anyhow, bail, Result};
+use std::fs::File;
+use std::process::{Command, Stdio};
+
+use crate::diff::refs::oid_from_rev_parse;
+use crate::repo::cache::{ensure_repo, resolve_repo_url, swr_fetch_origin_all_path};
+use crate::repo::credentials::GitAuth;
+use crate::types::GitArchiveOptions;
+
+pub struct Archive {
+    pub commit_sha: String,
+    pub format: &'static str,
+    /// Archive bytes; `None` when written to `outputPath`.
+    pub data: Option<Vec<u8>>,
+    pub size: u64,
+}
+
+fn normalize_format(format: Option<&str>) -> Result<&'static str> {
+    match format.map(str::trim).unwrap_or("tar") {
+        "" | "tar" => Ok("tar"),
+        "tar.gz" | "tgz" => Ok("tar.gz"),
+        "zip" => Ok("zip"),
+        other => bail!("unsupported archive format '{}'", other),
+    }
+}
+
+/// Archive a commit's tree (optionally only some paths) straight from the
+/// object database via `git archive`, so no checkout is needed.
+pub fn git_archive(opts: GitArchiveOptions) -> Result<Archive> {
+    let format = normalize_format(opts.format.as_deref())?;
+    let rev = opts.refName.trim();
+    if rev.is_empty() {
+        bail!("refName is required");
+    }
+
+    let repo_path = if let Some(p) = &opts.originPathOverride {
+        std::path::PathBuf::from(p)
+    } else {
+        let url = resolve_repo_url(opts.repoFullName.as_deref(), opts.repoUrl.as_deref())?;
+        let auth = GitAuth::resolve(opts.teamSlugOrId.as_deref(), &url);
+        let path = ensure_repo(&url, auth.as_ref())?;
+        let _ =
+            swr_fetch_origin_all_path(&path, crate::repo::cache::fetch_window_ms(), auth.as_ref());
+        path
+    };
+
+    let repo = gix::open(&repo_path)?;
+    let oid = oid_from_rev_parse(&repo, rev)?;
+    let commit_sha = oid.to_string();
+
+    let mut cmd = Command::new("git");
+    cmd.current_dir(&repo_path)
+        .arg("archive")
+        .arg(format!("--format={format}"));
+    if let Some(prefix) = opts.prefix.as_deref().filter(|p| !p.is_empty()) {
+        cmd.arg(format!("--prefix={prefix}"));
+    }
+    cmd.arg(&commit_sha).arg("--");
+    for path in opts.paths.iter().flatten() {
+        let path = path.trim().trim_matches('/');
+        if !path.is_empty() {
+            cmd.arg(path);
+        }
+    }
+    cmd.stdin(Stdio::null()).stderr(Stdio::piped());
+
+    let (data, size) = match &opts.outputPath {
+        Some(out) => {
+            // Stream straight into the file so large trees never sit in memory.
+            let file = File::create(out)?;
+            cmd.stdout(file);
+            let output = cmd.output()?;
+            if let Err(err) = check_status(&output) {
+                let _ = std::fs::remove_file(out);
+                return Err(err);
+            }
+            (None, std::fs::metadata(out)?.len())
+        }
+        None => {
+            let output = cmd.output()?;
+            check_status(&output)?;
+            let size = output.stdout.len() as u64;
+            (Some(output.stdout), size)
+        }
+    };
+
+    Ok(Archive {
+        commit_sha,
+        format,
+        data,
+        size,
+    })
+}
+
+fn check_status(output: &std::process::Output) -> Result<()> {
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "git archive failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::run_git;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn list_tar(path: &std::path::Path) -> Vec<String> {
+        let out = Command::new("tar").arg("-tf").arg(path).output().unwrap();
+        assert!(out.status.success());
+        let mut names: Vec<String> = String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn archives_ref_and_subdirectory_without_checkout() {
+        let tmp = tempdir().expect("tempdir");
+        let repo = tmp.path().join("repo");
+        fs::create_dir_all(repo.join("app/src")).unwrap();
+        let cwd = repo.to_str().unwrap();
+        run_git(cwd, &["init"]).unwrap();
+        run_git(cwd, &["config", "user.name", "Test"]).unwrap();
+        run_git(cwd, &["config", "user.email", "test@example.com"]).unwrap();
+        run_git(cwd, &["checkout", "-b", "main"]).unwrap();
+        fs::write(repo.join("README.md"), "readme").unwrap();
+        fs::write(repo.join("app/src/main.rs"), "fn main() {}").unwrap();
+        run_git(cwd, &["add", "."]).unwrap();
+        run_git(cwd, &["commit", "-m", "initial"]).unwrap();
+        let main_sha = run_git(cwd, &["rev-parse", "HEAD"]).unwrap();
+        // Move the worktree on so the archive must come from the ref
+        run_git(cwd, &["checkout", "-b", "feature"]).unwrap();
+        fs::write(repo.join("extra.txt"), "extra").unwrap();
+        run_git(cwd, &["add", "."]).unwrap();
+        run_git(cwd, &["commit", "-m", "feature"]).unwrap();
+
+        let base = GitArchiveOptions {
+            originPathOverride: Some(cwd.to_string()),
+            refName: "main".to_string(),
+            ..Default::default()
+        };
+
+        let out = tmp.path().join("main.tar");
+        let res = git_archive(GitArchiveOptions {
+            outputPath: Some(out.to_string_lossy().into_owned()),
+            prefix: Some("repo-main/".to_string()),
+            ..base.clone()
+        })
+        .expect("archive");
+        assert_eq!(res.commit_sha, main_sha.trim());
+        assert_eq!(res.format, "tar");
+        assert!(res.data.is_none());
+        assert_eq!(res.size, fs::metadata(&out).unwrap().len());
+        let names = list_tar(&out);
+        assert!(names.contains(&"repo-main/README.md".to_string()));
+        assert!(names.contains(&"repo-main/app/src/main.rs".to_string()));
+        assert!(!names.iter().any(|n| n.ends_with("extra.txt")));
+
+        let res = git_archive(GitArchiveOptions {
+            paths: Some(vec!["app/".to_string()]),
+            ..base.clone()
+        })
+        .expect("subdir archive");
+        let data = res.data.expect("in-memory archive");
+        assert_eq!(res.size as usize, data.len());
+        let sub = tmp.path().join("sub.tar");
+        fs::write(&sub, &data).unwrap();
+        assert_eq!(list_tar(&sub), vec!["app/", "app/src/", "app/src/main.rs"]);
+
+        let res = git_archive(GitArchiveOptions {
+            format: Some("zip".to_string()),
+            ..base.clone()
+        })
+        .expect("zip archive");
+        assert!(res.data.expect("zip bytes").starts_with(b"PK"));
+
+        assert!(git_archive(GitArchiveOptions {
+            format: Some("rar".to_string()),
+            ..base.clone()
+        })
+        .is_err());
+        let missing = tmp.path().join("missing.tar");
+        assert!(git_archive(GitArchiveOptions {
+            paths: Some(vec!["nope".to_string()]),
+            outputPath: Some(missing.to_string_lossy().into_owned()),
+            ..base
+        })
+        .is_err());
+        assert!(!missing.exists());
+    }
+}
@@ -0,0 +1,409 @@
+use anyhow::{anyhow, bail, Result};
+use gix::bstr::ByteSlice;
+use gix::{hash::ObjectId, Repository};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::diff::refs::oid_from_rev_parse;
+use crate::repo::cache::{ensure_repo, resolve_repo_url, swr_fetch_origin_all_path};
+use crate::repo::credentials::GitAuth;
+use crate::types::{BisectMidpoint, GitBisectPlan, GitBisectPlanOptions};
+
+/// Reachability is tracked with one bitset per candidate, so cap the range.
+const MAX_CANDIDATES: usize = 20_000;
+/// Extra commits to walk after only good commits remain queued, to absorb
+/// committer clock skew (same idea as git's `SLOP`).
+const WALK_SLOP: usize = 5;
+
+struct CommitInfo {
+    parents: Vec<ObjectId>,
+    time: i64,
+    subject: String,
+}
+
+fn load_commit(repo: &Repository, id: ObjectId) -> Result<CommitInfo> {
+    let commit = repo.find_object(id)?.try_into_commit()?;
+    let time = commit
+        .committer()
+        .map(|sig| sig.time.seconds)
+        .unwrap_or_default();
+    let subject = commit
+        .message_raw_sloppy()
+        .lines()
+        .next()
+        .map(|line| line.to_str_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(CommitInfo {
+        parents: commit.parent_ids().map(|p| p.detach()).collect(),
+        time,
+        subject,
+    })
+}
+
+/// Commits reachable from `bad` but not from any of `goods`, walking newest
+/// first and stopping once only good ancestry is left in the queue.
+fn candidate_commits(
+    repo: &Repository,
+    bad: ObjectId,
+    goods: &[ObjectId],
+) -> Result<HashMap<ObjectId, CommitInfo>> {
+    fn enqueue(
+        repo: &Repository,
+        infos: &mut HashMap<ObjectId, CommitInfo>,
+        queue: &mut BinaryHeap<(i64, ObjectId)>,
+        id: ObjectId,
+    ) -> Result<()> {
+        let time = match infos.entry(id) {
+            std::collections::hash_map::Entry::Occupied(e) => e.get().time,
+            std::collections::hash_map::Entry::Vacant(e) => e.insert(load_commit(repo, id)?).time,
+        };
+        queue.push((time, id));
+        Ok(())
+    }
+
+    let mut infos: HashMap<ObjectId, CommitInfo> = HashMap::new();
+    let mut uninteresting: HashMap<ObjectId, bool> = HashMap::new();
+    let mut expanded: HashSet<(ObjectId, bool)> = HashSet::new();
+    let mut queue: BinaryHeap<(i64, ObjectId)> = BinaryHeap::new();
+    let mut interesting = 0usize;
+
+    uninteresting.insert(bad, false);
+    interesting += 1;
+    enqueue(repo, &mut infos, &mut queue, bad)?;
+    for &good in goods {
+        if uninteresting.insert(good, true) == Some(false) {
+            interesting -= 1;
+        }
+        enqueue(repo, &mut infos, &mut queue, good)?;
+    }
+
+    let mut slop = WALK_SLOP;
+    let mut oldest_interesting = i64::MAX;
+    while let Some((time, id)) = queue.pop() {
+        let bottom = uninteresting[&id];
+        if !expanded.insert((id, bottom)) {
+            continue;
+        }
+        if !bottom {
+            oldest_interesting = oldest_interesting.min(time);
+        }
+        for parent in infos[&id].parents.clone() {
+            match uninteresting.insert(parent, bottom) {
+                Some(true) => {
+                    uninteresting.insert(parent, true);
+                    continue;
+                }
+                Some(false) if !bottom => continue,
+                Some(false) => interesting -= 1,
+                None if !bottom => interesting += 1,
+                None => {}
+            }
+            enqueue(repo, &mut infos, &mut queue, parent)?;
+        }
+        if interesting > MAX_CANDIDATES {
+            bail!(
+                "more than {} commits between good and bad; pick a closer good ref",
+                MAX_CANDIDATES
+            );
+        }
+
+        // Good commits no newer than every candidate can only reach
+        // candidates through clock skew.
+        let settled = queue
+            .peek()
+            .is_none_or(|(time, _)| *time < oldest_interesting);
+        if settled && queue.iter().all(|(_, id)| uninteresting[id]) {
+            if slop == 0 {
+                break;
+            }
+            slop -= 1;
+        } else {
+            slop = WALK_SLOP;
+        }
+    }
+
+    infos.retain(|id, _| uninteresting.get(id) == Some(&false));
+    Ok(infos)
+}
+
+/// Number of candidates reachable from each candidate, itself included.
+fn reach_counts(candidates: &HashMap<ObjectId, CommitInfo>) -> HashMap<ObjectId, usize> {
+    let ids: Vec<ObjectId> = candidates.keys().copied().collect();
+    let index: HashMap<ObjectId, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    let words = ids.len().div_ceil(64);
+    let mut bits: Vec<Option<Vec<u64>>> = vec![None; ids.len()];
+
+    // Iterative post-order so parents are filled in before their children.
+    for start in 0..ids.len() {
+        let mut stack = vec![(start, false)];
+        while let Some((i, parents_done)) = stack.pop() {
+            if bits[i].is_some() {
+                continue;
+            }
+            let parents = candidates[&ids[i]]
+                .parents
+                .iter()
+                .filter_map(|p| index.get(p).copied());
+            if !parents_done {
+                stack.push((i, true));
+                stack.extend(parents.filter(|&p| bits[p].is_none()).map(|p| (p, false)));
+                continue;
+            }
+            let mut set = vec![0u64; words];
+            set[i / 64] |= 1 << (i % 64);
+            for p in parents {
+                if let Some(parent_set) = &bits[p] {
+                    for (word, parent_word) in set.iter_mut().zip(parent_set) {
+                        *word |= parent_word;
+                    }
+                }
+            }
+            bits[i] = Some(set);
+        }
+    }
+
+    ids.iter()
+        .zip(bits)
+        .map(|(id, set)| {
+            let count = set
+                .unwrap_or_default()
+                .iter()
+                .map(|w| w.count_ones() as usize)
+                .sum();
+            (*id, count)
+        })
+        .collect()
+}
+
+/// Worst-case number of tests to narrow `candidates` down to one.
+fn estimate_steps(candidates: usize) -> i32 {
+    (usize::BITS - candidates.saturating_sub(1).leading_zeros()) as i32
+}
+
+/// Plan the next bisection step without keeping any state between calls:
+/// the caller passes every good commit, the current bad one, and any skips.
+pub fn git_bisect_plan(opts: GitBisectPlanOptions) -> Result<GitBisectPlan> {
+    if opts.goodRefs.is_empty() {
+        bail!("at least one good ref is required");
+    }
+
+    let repo_path = if let Some(p) = &opts.originPathOverride {
+        std::path::PathBuf::from(p)
+    } else {
+        let url = resolve_repo_url(opts.repoFullName.as_deref(), opts.repoUrl.as_deref())?;
+        let auth = GitAuth::resolve(opts.teamSlugOrId.as_deref(), &url);
+        let path = ensure_repo(&url, auth.as_ref())?;
+        let _ =
+            swr_fetch_origin_all_path(&path, crate::repo::cache::fetch_window_ms(), auth.as_ref());
+        path
+    };
+    let repo = gix::open(&repo_path)?;
+
+    let resolve = |rev: &str| {
+        oid_from_rev_parse(&repo, rev.trim()).and_then(|oid| {
+            Ok(repo
+                .find_object(oid)?
+                .peel_to_kind(gix::object::Kind::Commit)?
+                .id)
+        })
+    };
+    let bad = resolve(&opts.badRef)?;
+    let goods = opts
+        .goodRefs
+        .iter()
+        .map(|r| resolve(r))
+        .collect::<Result<Vec<_>>>()?;
+    let skips = opts
+        .skipRefs
+        .iter()
+        .flatten()
+        .map(|r| resolve(r))
+        .collect::<Result<HashSet<_>>>()?;
+
+    let candidates = candidate_commits(&repo, bad, &goods)?;
+    if candidates.is_empty() {
+        return Err(anyhow!(
+            "bad ref {} is reachable from a good ref; nothing to bisect",
+            opts.badRef.trim()
+        ));
+    }
+
+    let total = candidates.len();
+    let reach = reach_counts(&candidates);
+    let mut ranked: Vec<(usize, i64, ObjectId)> = reach
+        .iter()
+        .filter(|(id, _)| **id != bad && !skips.contains(*id))
+        .map(|(id, &w)| (w.min(total - w), candidates[id].time, *id))
+        .collect();
+    // Best split first; newer commits first on ties, then by id for stability.
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+
+    let limit = opts.maxMidpoints.unwrap_or(3).max(1) as usize;
+    let midpoints = ranked
+        .into_iter()
+        .take(limit)
+        .map(|(_, _, id)| BisectMidpoint {
+            sha: id.to_string(),
+            subject: candidates[&id].subject.clone(),
+            remainingIfGood: (total - reach[&id]) as i32,
+            remainingIfBad: reach[&id] as i32,
+        })
+        .collect();
+
+    Ok(GitBisectPlan {
+        badSha: bad.to_string(),
+        goodShas: goods.iter().map(ObjectId::to_string).collect(),
+        candidateCount: total as i32,
+        estimatedSteps: estimate_steps(total),
+        midpoints,
+        firstBadSha: (total == 1).then(|| bad.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::run_git;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn commit(cwd: &str, name: &str, ts: i64) -> String {
+        std::fs::write(std::path::Path::new(cwd).join(name), name).unwrap();
+        run_git(cwd, &["add", "."]).unwrap();
+        let date = format!("{ts} +0000");
+        let out = Command::new("git")
+            .current_dir(cwd)
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .args(["commit", "-q", "-m", name])
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        run_git(cwd, &["rev-parse", "HEAD"])
+            .unwrap()
+            .trim()
+            .to_string()
+    }
+
+    fn init(root: &std::path::Path) -> String {
+        let cwd = root.to_str().unwrap().to_string();
+        run_git(&cwd, &["init", "-q", "-b", "main"]).unwrap();
+        run_git(&cwd, &["config", "user.name", "Test"]).unwrap();
+        run_git(&cwd, &["config", "user.email", "test@example.com"]).unwrap();
+        cwd
+    }
+
+    fn plan(cwd: &str, goods: &[&str], bad: &str, skips: &[&str]) -> Result<GitBisectPlan> {
+        git_bisect_plan(GitBisectPlanOptions {
+            originPathOverride: Some(cwd.to_string()),
+            goodRefs: goods.iter().map(|s| s.to_string()).collect(),
+            badRef: bad.to_string(),
+            skipRefs: Some(skips.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn estimate_steps_is_worst_case_log2() {
+        assert_eq!(estimate_steps(1), 0);
+        assert_eq!(estimate_steps(2), 1);
+        assert_eq!(estimate_steps(3), 2);
+        assert_eq!(estimate_steps(8), 3);
+        assert_eq!(estimate_steps(9), 4);
+    }
+
+    #[test]
+    fn drives_linear_bisection_to_first_bad_commit() {
+        let tmp = tempdir().unwrap();
+        let cwd = init(tmp.path());
+        let shas: Vec<String> = (0..16)
+            .map(|i| commit(&cwd, &format!("c{i}"), 1_700_000_000 + i * 60))
+            .collect();
+        let first_bad = 11;
+
+        let initial = plan(&cwd, &[&shas[0]], &shas[15], &[]).unwrap();
+        assert_eq!(initial.candidateCount, 15);
+        assert_eq!(initial.estimatedSteps, 4);
+        assert_eq!(initial.midpoints.len(), 3);
+        let mid = &initial.midpoints[0];
+        assert_eq!(mid.remainingIfGood + mid.remainingIfBad, 15);
+        assert!((mid.remainingIfGood - mid.remainingIfBad).abs() <= 1);
+        assert_eq!(
+            mid.subject,
+            format!("c{}", shas.iter().position(|s| *s == mid.sha).unwrap())
+        );
+
+        let mut goods = vec![shas[0].clone()];
+        let mut bad = shas[15].clone();
+        let mut steps = 0;
+        let found = loop {
+            let goods_ref: Vec<&str> = goods.iter().map(String::as_str).collect();
+            let p = plan(&cwd, &goods_ref, &bad, &[]).unwrap();
+            if let Some(sha) = p.firstBadSha {
+                break sha;
+            }
+            let next = p.midpoints[0].sha.clone();
+            let idx = shas.iter().position(|s| *s == next).unwrap();
+            if idx >= first_bad {
+                bad = next;
+            } else {
+                goods.push(next);
+            }
+            steps += 1;
+        };
+        assert_eq!(found, shas[first_bad]);
+        assert!(steps <= initial.estimatedSteps);
+
+        // Skipped commits are never suggested
+        let skipped = plan(&cwd, &[&shas[0]], &shas[15], &[&mid.sha]).unwrap();
+        assert!(skipped.midpoints.iter().all(|m| m.sha != mid.sha));
+
+        assert!(plan(&cwd, &[&shas[15]], &shas[0], &[]).is_err());
+    }
+
+    #[test]
+    fn merged_side_branch_is_part_of_the_range() {
+        let tmp = tempdir().unwrap();
+        let cwd = init(tmp.path());
+        let base = commit(&cwd, "base", 1_700_000_000);
+        run_git(&cwd, &["checkout", "-q", "-b", "side"]).unwrap();
+        let side1 = commit(&cwd, "side1", 1_700_000_060);
+        let side2 = commit(&cwd, "side2", 1_700_000_120);
+        run_git(&cwd, &["checkout", "-q", "main"]).unwrap();
+        let main1 = commit(&cwd, "main1", 1_700_000_180);
+        let out = Command::new("git")
+            .current_dir(&cwd)
+            .env("GIT_COMMITTER_DATE", "1700000240 +0000")
+            .args(["merge", "-q", "--no-ff", "-m", "merge", "side"])
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        let merge = run_git(&cwd, &["rev-parse", "HEAD"])
+            .unwrap()
+            .trim()
+            .to_string();
+
+        let p = plan(&cwd, &[&base], &merge, &[]).unwrap();
+        assert_eq!(p.candidateCount, 4);
+        let mids: HashSet<String> = p.midpoints.iter().map(|m| m.sha.clone()).collect();
+        assert_eq!(
+            mids,
+            HashSet::from([side1.clone(), side2.clone(), main1.clone()])
+        );
+        // side2 reaches side1 too, so it splits the range best
+        assert_eq!(p.midpoints[0].sha, side2);
+
+        // With side2 known good only main1 and the merge remain
+        let p = plan(&cwd, &[&base, &side2], &merge, &[]).unwrap();
+        assert_eq!(p.candidateCount, 2);
+        assert_eq!(p.midpoints.len(), 1);
+        assert_eq!(p.midpoints[0].sha, main1);
+
+        let p = plan(&cwd, &[&main1, &side2], &merge, &[]).unwrap();
+        assert_eq!(p.firstBadSha.as_deref(), Some(merge.as_str()));
+        assert!(p.midpoints.is_empty());
+    }
+}
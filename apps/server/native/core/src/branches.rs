@@ -3,6 +3,7 @@ use gix::bstr::ByteSlice;
 use gix::hash::ObjectId;
 
 use crate::repo::cache::{ensure_repo, resolve_repo_url, swr_fetch_origin_all_path};
+use crate::repo::credentials::GitAuth;
 use crate::types::{BranchInfo, GitListRemoteBranchesOptions};
 
 fn refname_to_branch(name: &str) -> Option<(String /*remote*/, String /*branch*/)> {
@@ -23,15 +24,20 @@ fn oid_to_hex(oid: ObjectId) -> String {
 
 pub fn list_remote_branches(opts: GitListRemoteBranchesOptions) -> Result<Vec<BranchInfo>> {
     // Resolve local repo path
-    let repo_path = if let Some(p) = &opts.originPathOverride {
-        std::path::PathBuf::from(p)
+    let (repo_path, auth) = if let Some(p) = &opts.originPathOverride {
+        (std::path::PathBuf::from(p), None)
     } else {
         let url = resolve_repo_url(opts.repoFullName.as_deref(), opts.repoUrl.as_deref())?;
-        ensure_repo(&url)?
+        let auth = GitAuth::resolve(opts.teamSlugOrId.as_deref(), &url);
+        (ensure_repo(&url, auth.as_ref())?, auth)
     };
 
     // Make sure remotes are fresh (this is cheap if within SWR window)
-    let _ = swr_fetch_origin_all_path(&repo_path, crate::repo::cache::fetch_window_ms());
+    let _ = swr_fetch_origin_all_path(
+        &repo_path,
+        crate::repo::cache::fetch_window_ms(),
+        auth.as_ref(),
+    );
 
     let repo = gix::open(&repo_path)?;
 
@@ -227,6 +233,7 @@ mod tests {
         let res = list_remote_branches(GitListRemoteBranchesOptions {
             repoFullName: None,
             repoUrl: None,
+            teamSlugOrId: None,
             originPathOverride: Some(clone.to_string_lossy().to_string()),
         })
         .expect("list branches");
@@ -0,0 +1,132 @@
+use anyhow::{anyhow, Result};
+use gix::{hash::ObjectId, Repository};
+use similar::{ChangeTag, TextDiff};
+
+use crate::{
+    repo::cache::{ensure_repo, resolve_repo_url},
+    repo::credentials::GitAuth,
+    types::{DiffContents, DiffEntry, GitDiffContentsOptions},
+};
+
+/// Handle for hydrating an entry's omitted contents later: the blob OIDs of
+/// both sides as `<old>..<new>`, with a missing side left empty.
+pub(crate) fn content_key(old: Option<&ObjectId>, new: Option<&ObjectId>) -> Option<String> {
+    if old.is_none() && new.is_none() {
+        return None;
+    }
+    let side = |id: Option<&ObjectId>| id.map(ObjectId::to_string).unwrap_or_default();
+    Some(format!("{}..{}", side(old), side(new)))
+}
+
+fn parse_content_key(key: &str) -> Result<(Option<ObjectId>, Option<ObjectId>)> {
+    let (old, new) = key
+        .split_once("..")
+        .ok_or_else(|| anyhow!("malformed content key '{}'", key))?;
+    let side = |hex: &str| -> Result<Option<ObjectId>> {
+        if hex.is_empty() {
+            return Ok(None);
+        }
+        ObjectId::from_hex(hex.as_bytes())
+            .map(Some)
+            .map_err(|_| anyhow!("malformed content key '{}'", key))
+    };
+    Ok((side(old)?, side(new)?))
+}
+
+/// Return contents in path order until `budget` bytes would be exceeded, then
+/// omit the rest, so the first response for a huge diff stays small and
+/// clients can hydrate the tail in chunks. Line counts are kept; omitted
+/// entries keep their `contentKey` for `git_diff_contents`.
+pub(crate) fn apply_total_budget(entries: &mut [DiffEntry], budget: usize) {
+    let mut spent = 0usize;
+    let mut exhausted = false;
+    for e in entries.iter_mut() {
+        if e.contentOmitted != Some(false) {
+            continue;
+        }
+        let size = e.oldContent.as_ref().map_or(0, String::len)
+            + e.newContent.as_ref().map_or(0, String::len);
+        exhausted = exhausted || spent + size > budget;
+        if exhausted {
+            e.oldContent = None;
+            e.newContent = None;
+            e.contentOmitted = Some(true);
+        } else {
+            spent += size;
+        }
+    }
+}
+
+/// Keys are only useful on entries still missing their contents.
+pub(crate) fn drop_unneeded_keys(entries: &mut [DiffEntry]) {
+    for e in entries.iter_mut() {
+        if e.contentOmitted != Some(true) {
+            e.contentKey = None;
+        }
+    }
+}
+
+fn blob_bytes(repo: &Repository, id: ObjectId) -> Result<Vec<u8>> {
+    let blob = repo.find_object(id)?.try_into_blob()?;
+    Ok(blob.data.to_vec())
+}
+
+fn line_changes(old: &str, new: &str) -> (i32, i32) {
+    let diff = TextDiff::from_lines(old, new);
+    let mut adds = 0i32;
+    let mut dels = 0i32;
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => adds += 1,
+            ChangeTag::Delete => dels += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    (adds, dels)
+}
+
+/// Load the contents `git_diff` omitted, by `contentKey`. Results come back in
+/// the order of `keys`; entries whose blobs are binary or over `maxBytes`
+/// stay omitted.
+pub fn diff_contents(opts: GitDiffContentsOptions) -> Result<Vec<DiffContents>> {
+    let repo_path = if let Some(p) = &opts.originPathOverride {
+        std::path::PathBuf::from(p)
+    } else {
+        let url = resolve_repo_url(opts.repoFullName.as_deref(), opts.repoUrl.as_deref())?;
+        let auth = GitAuth::resolve(opts.teamSlugOrId.as_deref(), &url);
+        ensure_repo(&url, auth.as_ref())?
+    };
+    let repo = gix::open(&repo_path)?;
+    let max_bytes = opts.maxBytes.map(|n| n.max(0) as usize);
+
+    let mut out = Vec::with_capacity(opts.keys.len());
+    for key in &opts.keys {
+        let (old_id, new_id) = parse_content_key(key)?;
+        let old = old_id.map(|id| blob_bytes(&repo, id)).transpose()?;
+        let new = new_id.map(|id| blob_bytes(&repo, id)).transpose()?;
+        let old = old.unwrap_or_default();
+        let new = new.unwrap_or_default();
+
+        let mut contents = DiffContents {
+            key: key.clone(),
+            contentOmitted: true,
+            ..Default::default()
+        };
+        let is_binary = |data: &[u8]| data.contains(&0) || std::str::from_utf8(data).is_err();
+        if is_binary(&old) || is_binary(&new) {
+            contents.isBinary = true;
+        } else if max_bytes.is_none_or(|max| old.len() + new.len() <= max) {
+            // Checked as UTF-8 just above
+            let old = String::from_utf8(old).unwrap_or_default();
+            let new = String::from_utf8(new).unwrap_or_default();
+            let (adds, dels) = line_changes(&old, &new);
+            contents.additions = adds;
+            contents.deletions = dels;
+            contents.oldContent = Some(old);
+            contents.newContent = Some(new);
+            contents.contentOmitted = false;
+        }
+        out.push(contents);
+    }
+    Ok(out)
+}
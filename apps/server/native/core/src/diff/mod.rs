@@ -1,3 +1,6 @@
+pub mod contents;
+pub mod owners;
+pub mod policies;
 pub mod refs;
 #[cfg(test)]
 pub mod workspace;
@@ -0,0 +1,227 @@
+use anyhow::Result;
+use gix::Repository;
+
+use crate::{
+    diff::refs::{diff_refs, oid_from_rev_parse, resolve_default_base},
+    repo::cache::{ensure_repo, resolve_repo_url},
+    repo::credentials::GitAuth,
+    types::{FileOwners, GitDiffOptions, GitDiffOwnersOptions},
+};
+
+/// Locations GitHub checks, in order; the first one present wins.
+const CODEOWNERS_PATHS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnerRule {
+    pub pattern: String,
+    pub owners: Vec<String>,
+    pub line: usize,
+}
+
+/// Parse CODEOWNERS content. A rule with no owners is kept: it explicitly
+/// un-assigns paths matched by earlier rules.
+pub fn parse_codeowners(content: &str) -> Vec<OwnerRule> {
+    let mut rules = Vec::new();
+    for (idx, raw) in content.lines().enumerate() {
+        let line = strip_comment(raw);
+        let mut fields = line.split_whitespace();
+        let Some(pattern) = fields.next() else {
+            continue;
+        };
+        rules.push(OwnerRule {
+            pattern: pattern.replace("\\#", "#"),
+            owners: fields.map(str::to_string).collect(),
+            line: idx + 1,
+        });
+    }
+    rules
+}
+
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    for (i, b) in bytes.iter().enumerate() {
+        if *b == b'#' && (i == 0 || bytes[i - 1] != b'\\') {
+            return &line[..i];
+        }
+    }
+    line
+}
+
+/// The rule that applies to `path`: as on GitHub, the last matching rule wins.
+pub fn owners_for_path<'a>(rules: &'a [OwnerRule], path: &str) -> Option<&'a OwnerRule> {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| pattern_matches(&rule.pattern, path))
+}
+
+/// Match a CODEOWNERS pattern (gitignore syntax without negation or
+/// character classes) against a repository-relative file path.
+pub fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let dir_only = pattern.ends_with('/');
+    let trimmed = pattern.trim_end_matches('/');
+    // A slash anywhere but the end anchors the pattern to the repository root
+    let anchored = trimmed.contains('/');
+    let trimmed = trimmed.trim_start_matches('/');
+    if trimmed.is_empty() {
+        return false;
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    if !anchored {
+        segments.push("**");
+    }
+    segments.extend(trimmed.split('/'));
+
+    // `docs/*` covers files directly in docs/ but not nested ones, so a
+    // wildcard final segment only matches files, never whole directories.
+    let last = segments.last().copied().unwrap_or_default();
+    let matches_dirs = last == "**" || !last.contains('*');
+
+    let parts: Vec<&str> = path.split('/').collect();
+    (1..=parts.len()).any(|n| {
+        let is_file = n == parts.len();
+        if is_file && dir_only {
+            return false;
+        }
+        if !is_file && !matches_dirs {
+            return false;
+        }
+        match_segments(&segments, &parts[..n])
+    })
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            match_segments(&pattern[1..], path)
+                || (!path.is_empty() && match_segments(pattern, &path[1..]))
+        }
+        (Some(p), Some(s)) => {
+            glob_segment(p.as_bytes(), s.as_bytes()) && match_segments(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+fn glob_segment(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') => {
+            glob_segment(&pattern[1..], text)
+                || (!text.is_empty() && glob_segment(pattern, &text[1..]))
+        }
+        Some(b'?') => !text.is_empty() && glob_segment(&pattern[1..], &text[1..]),
+        Some(c) => text.first() == Some(c) && glob_segment(&pattern[1..], &text[1..]),
+    }
+}
+
+fn read_codeowners(repo: &Repository, rev: &str) -> Result<Option<String>> {
+    let oid = oid_from_rev_parse(repo, rev)?;
+    let tree = repo.find_object(oid)?.peel_to_tree()?;
+    let mut buf = Vec::new();
+    for path in CODEOWNERS_PATHS {
+        if let Some(entry) = tree.lookup_entry_by_path(path, &mut buf)? {
+            if let Ok(blob) = entry.object()?.try_into_blob() {
+                return Ok(Some(String::from_utf8_lossy(&blob.data).into_owned()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Owners for each file changed between the diff's base and head. CODEOWNERS
+/// is read from `codeownersRef`, falling back to the base ref (as GitHub does).
+pub fn diff_owners(opts: GitDiffOwnersOptions) -> Result<Vec<FileOwners>> {
+    let entries = diff_refs(GitDiffOptions {
+        includeContents: Some(false),
+        ..opts.diff.clone()
+    })?;
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let repo_path = if let Some(p) = &opts.diff.originPathOverride {
+        std::path::PathBuf::from(p)
+    } else {
+        let url = resolve_repo_url(
+            opts.diff.repoFullName.as_deref(),
+            opts.diff.repoUrl.as_deref(),
+        )?;
+        let auth = GitAuth::resolve(opts.diff.teamSlugOrId.as_deref(), &url);
+        ensure_repo(&url, auth.as_ref())?
+    };
+    let repo = gix::open(&repo_path)?;
+
+    let rev = opts
+        .codeownersRef
+        .as_deref()
+        .or(opts.diff.baseRef.as_deref())
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+    let rev = match rev {
+        Some(rev) => rev,
+        None => {
+            let head = oid_from_rev_parse(&repo, opts.diff.headRef.trim())?;
+            resolve_default_base(&repo, head).to_string()
+        }
+    };
+    let rules = read_codeowners(&repo, &rev)?
+        .map(|content| parse_codeowners(&content))
+        .unwrap_or_default();
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let rule = owners_for_path(&rules, &entry.filePath);
+            FileOwners {
+                owners: rule.map(|r| r.owners.clone()).unwrap_or_default(),
+                pattern: rule.map(|r| r.pattern.clone()),
+                ruleLine: rule.map(|r| r.line as i32),
+                filePath: entry.filePath,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_follow_codeowners_semantics() {
+        assert!(pattern_matches("*", "src/a.rs"));
+        assert!(pattern_matches("*.js", "web/app/index.js"));
+        assert!(!pattern_matches("*.js", "web/app/index.ts"));
+        assert!(pattern_matches("/docs/", "docs/guide/intro.md"));
+        assert!(!pattern_matches("/docs/", "src/docs/x.md"));
+        assert!(pattern_matches("apps/", "packages/apps/x.ts"));
+        assert!(pattern_matches("docs/*", "docs/intro.md"));
+        assert!(!pattern_matches("docs/*", "docs/guide/intro.md"));
+        assert!(pattern_matches("**/logs", "deep/build/logs/out.txt"));
+        assert!(pattern_matches("/build/logs/", "build/logs/a/b.txt"));
+        assert!(pattern_matches("src/**/*.rs", "src/a/b/c.rs"));
+        assert!(pattern_matches("Makefile", "tools/Makefile"));
+        assert!(!pattern_matches("/Makefile", "tools/Makefile"));
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = parse_codeowners(
+            "# default\n* @org/everyone\n\n*.rs @rustaceans # inline\n/apps/www/ @web @design\n/apps/www/generated/\n",
+        );
+        assert_eq!(rules.len(), 4);
+        let owners = |path: &str| owners_for_path(&rules, path).map(|r| r.owners.clone());
+
+        assert_eq!(owners("README.md"), Some(vec!["@org/everyone".to_string()]));
+        assert_eq!(owners("src/lib.rs"), Some(vec!["@rustaceans".to_string()]));
+        assert_eq!(
+            owners("apps/www/main.rs"),
+            Some(vec!["@web".to_string(), "@design".to_string()])
+        );
+        assert_eq!(owners("apps/www/generated/api.ts"), Some(vec![]));
+        assert_eq!(owners_for_path(&rules, "src/lib.rs").unwrap().line, 4);
+    }
+}
@@ -6,13 +6,15 @@ use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
 use crate::{
-    repo::cache::{ensure_repo, resolve_repo_url},
-    types::{DiffEntry, GitDiffOptions},
+    diff::contents::{apply_total_budget, content_key, drop_unneeded_keys},
+    repo::cache::{cached_repo_path, ensure_repo, resolve_repo_url},
+    repo::credentials::GitAuth,
+    types::{DiffEntry, GitDiffOptions, GitDiffResult},
 };
 use gix::{hash::ObjectId, Repository};
 use similar::TextDiff;
 
-fn oid_from_rev_parse(repo: &Repository, rev: &str) -> anyhow::Result<ObjectId> {
+pub(crate) fn oid_from_rev_parse(repo: &Repository, rev: &str) -> anyhow::Result<ObjectId> {
     if let Ok(oid) = ObjectId::from_hex(rev.as_bytes()) {
         return Ok(oid);
     }
@@ -69,7 +71,7 @@ fn collect_tree_blobs(
     Ok(())
 }
 
-fn resolve_default_base(repo: &Repository, head_oid: ObjectId) -> ObjectId {
+pub(crate) fn resolve_default_base(repo: &Repository, head_oid: ObjectId) -> ObjectId {
     if let Ok(r) = repo.find_reference("refs/remotes/origin/HEAD") {
         if let Some(name) = r.target().try_name() {
             let s = name.as_bstr().to_str_lossy().into_owned();
@@ -165,9 +167,98 @@ fn parse_oid(hex: &str) -> Option<ObjectId> {
     ObjectId::from_hex(trimmed.as_bytes()).ok()
 }
 
+/// OIDs a diff was computed from, reported back so callers can pass them as
+/// `lastKnown*` hints next time.
+#[derive(Default)]
+struct ResolvedOids {
+    head: Option<ObjectId>,
+    base: Option<ObjectId>,
+    compare_base: Option<ObjectId>,
+}
+
 pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
+    diff_refs_resolved(opts, &mut ResolvedOids::default())
+}
+
+/// Like [`diff_refs`], but returns `notModified` without fetching or diffing
+/// when head still resolves to `lastKnownHeadSha` and `lastKnownBaseSha` is
+/// present locally, so the caller can keep its cached entries.
+pub fn diff_refs_incremental(opts: GitDiffOptions) -> Result<GitDiffResult> {
+    if let Some(result) = unchanged_since_last_diff(&opts)? {
+        return Ok(result);
+    }
+    let mut oids = ResolvedOids::default();
+    let entries = diff_refs_resolved(opts, &mut oids)?;
+    Ok(GitDiffResult {
+        notModified: false,
+        entries,
+        headSha: oids.head.map(|o| o.to_string()),
+        baseSha: oids.base.map(|o| o.to_string()),
+        mergeBaseSha: oids.compare_base.map(|o| o.to_string()),
+    })
+}
+
+fn unchanged_since_last_diff(opts: &GitDiffOptions) -> Result<Option<GitDiffResult>> {
+    let (Some(known_head), Some(known_base)) = (
+        opts.lastKnownHeadSha.as_deref().and_then(parse_oid),
+        opts.lastKnownBaseSha.as_deref().and_then(parse_oid),
+    ) else {
+        return Ok(None);
+    };
+    let head_ref = opts.headRef.trim();
+    if head_ref.is_empty() {
+        return Ok(None);
+    }
+    // Only a repo that is already on disk can answer without a clone
+    let (repo_path, auth) = match &opts.originPathOverride {
+        Some(p) => (std::path::PathBuf::from(p), None),
+        None => {
+            let url = resolve_repo_url(opts.repoFullName.as_deref(), opts.repoUrl.as_deref())?;
+            match cached_repo_path(&url) {
+                Some(path) => (path, GitAuth::resolve(opts.teamSlugOrId.as_deref(), &url)),
+                None => return Ok(None),
+            }
+        }
+    };
+    let Ok(repo) = gix::open(&repo_path) else {
+        return Ok(None);
+    };
+    let head_unchanged = oid_from_rev_parse(&repo, head_ref).is_ok_and(|oid| oid == known_head);
+    if !head_unchanged || repo.find_object(known_base).is_err() {
+        return Ok(None);
+    }
+    if opts.originPathOverride.is_none() {
+        // Refresh in the background so a moved remote branch is seen next time
+        std::thread::spawn(move || {
+            let _ = crate::repo::cache::swr_fetch_origin_all_path(
+                &repo_path,
+                crate::repo::cache::fetch_window_ms(),
+                auth.as_ref(),
+            );
+        });
+    }
+    let merge_base = opts
+        .lastKnownMergeCommitSha
+        .as_deref()
+        .and_then(parse_oid)
+        .and_then(|oid| repo.find_object(oid).ok()?.try_into_commit().ok())
+        .and_then(|commit| commit.parent_ids().next().map(|p| p.detach()))
+        .unwrap_or(known_base);
+    Ok(Some(GitDiffResult {
+        notModified: true,
+        entries: Vec::new(),
+        headSha: Some(known_head.to_string()),
+        baseSha: Some(known_base.to_string()),
+        mergeBaseSha: Some(merge_base.to_string()),
+    }))
+}
+
+fn diff_refs_resolved(opts: GitDiffOptions, oids: &mut ResolvedOids) -> Result<Vec<DiffEntry>> {
     let include = opts.includeContents.unwrap_or(true);
     let max_bytes = opts.maxBytes.unwrap_or(950 * 1024) as usize;
+    let max_total_bytes = opts.maxTotalBytes.map(|n| n.max(0) as usize);
+    let hashes = opts.includeHashes.unwrap_or(false);
+    let blob_hash = |id: Option<&ObjectId>| id.filter(|_| hashes).map(ObjectId::to_string);
     let t_total = Instant::now();
     #[cfg(test)]
     LAST_DIFF_DEBUG.with(|cell| {
@@ -194,11 +285,12 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
     );
 
     let t_repo_path = Instant::now();
-    let repo_path = if let Some(p) = &opts.originPathOverride {
-        std::path::PathBuf::from(p)
+    let (repo_path, auth) = if let Some(p) = &opts.originPathOverride {
+        (std::path::PathBuf::from(p), None)
     } else {
         let url = resolve_repo_url(opts.repoFullName.as_deref(), opts.repoUrl.as_deref())?;
-        ensure_repo(&url)?
+        let auth = GitAuth::resolve(opts.teamSlugOrId.as_deref(), &url);
+        (ensure_repo(&url, auth.as_ref())?, auth)
     };
     let _d_repo_path = t_repo_path.elapsed();
     let cwd = repo_path.to_string_lossy().to_string();
@@ -212,6 +304,7 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
         let _ = crate::repo::cache::swr_fetch_origin_all_path(
             std::path::Path::new(&cwd),
             crate::repo::cache::fetch_window_ms(),
+            auth.as_ref(),
         );
         t_fetch.elapsed()
     };
@@ -256,23 +349,30 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
         None => resolve_default_base(&repo, head_oid),
     };
     let _d_base = t_base.elapsed();
+    let mut known_base_is_ancestor = false;
     if let Some(ref known_base) = opts.lastKnownBaseSha {
         if let Some(candidate) = parse_oid(known_base) {
             if repo.find_object(candidate).is_ok() && is_ancestor(&repo, candidate, head_oid) {
                 resolved_base_oid = candidate;
+                known_base_is_ancestor = true;
             }
         }
     }
     let t_merge_base = Instant::now();
-    // Compute merge-base; prefer BFS (pure gix) to avoid shelling out
-    let mut compare_base_oid = crate::merge_base::merge_base(
-        &cwd,
-        &repo,
-        resolved_base_oid,
-        head_oid,
-        crate::merge_base::MergeBaseStrategy::Bfs,
-    )
-    .unwrap_or(resolved_base_oid);
+    // An ancestor of head is its own merge-base with head, so skip the walk
+    let mut compare_base_oid = if known_base_is_ancestor {
+        resolved_base_oid
+    } else {
+        // Compute merge-base; prefer BFS (pure gix) to avoid shelling out
+        crate::merge_base::merge_base(
+            &cwd,
+            &repo,
+            resolved_base_oid,
+            head_oid,
+            crate::merge_base::MergeBaseStrategy::Bfs,
+        )
+        .unwrap_or(resolved_base_oid)
+    };
     #[cfg(test)]
     let mut merge_commit_for_debug: Option<String> = None;
     if let Some(ref known_merge) = opts.lastKnownMergeCommitSha {
@@ -315,6 +415,11 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
         });
     });
     let _d_merge_base = t_merge_base.elapsed();
+    *oids = ResolvedOids {
+        head: Some(head_oid),
+        base: Some(resolved_base_oid),
+        compare_base: Some(compare_base_oid),
+    };
     #[cfg(debug_assertions)]
     println!(
         "[native.refs] MB({}, {})={}",
@@ -415,6 +520,9 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
             additions: 0,
             deletions: 0,
             isBinary: bin,
+            oldHash: blob_hash(Some(&oid)),
+            newHash: blob_hash(Some(&oid)),
+            contentKey: content_key(Some(&oid), Some(&oid)),
             ..Default::default()
         };
         if let Some(buf) = &new_data {
@@ -450,6 +558,9 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
                 additions: 0,
                 deletions: 0,
                 isBinary: bin,
+                oldHash: blob_hash(Some(old_id)),
+                newHash: blob_hash(Some(new_id)),
+                contentKey: content_key(Some(old_id), Some(new_id)),
                 ..Default::default()
             };
             if include && !bin {
@@ -518,6 +629,8 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
             additions: 0,
             deletions: 0,
             isBinary: bin,
+            newHash: blob_hash(Some(new_id)),
+            contentKey: content_key(None, Some(new_id)),
             ..Default::default()
         };
         if include && !bin {
@@ -559,6 +672,8 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
             additions: 0,
             deletions: 0,
             isBinary: bin,
+            oldHash: blob_hash(Some(old_id)),
+            contentKey: content_key(Some(old_id), None),
             ..Default::default()
         };
         if include && !bin {
@@ -644,155 +759,159 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
                 }
                 let status = parts[0].trim();
                 match status {
-                    "A" => {
-                        if parts.len() >= 2 {
-                            let path = parts[1].to_string();
-                            let mut e = DiffEntry {
-                                filePath: path.clone(),
-                                status: "added".into(),
-                                additions: 0,
-                                deletions: 0,
-                                isBinary: false,
-                                ..Default::default()
-                            };
-                            if include {
-                                // new content from head
-                                if let Ok(buf) = crate::util::run_git(
-                                    &cwd,
-                                    &["show", &format!("{}:{}", head_oid, path)],
-                                ) {
-                                    let new_sz = buf.len();
-                                    e.newSize = Some(new_sz as i32);
-                                    e.oldSize = Some(0);
-                                    if new_sz <= max_bytes {
-                                        e.newContent = Some(buf.clone());
-                                        e.oldContent = Some(String::new());
-                                        e.additions = buf.lines().count() as i32;
-                                        e.contentOmitted = Some(false);
-                                    } else {
-                                        e.contentOmitted = Some(true);
-                                    }
-                                }
-                            }
-                            fallback.push(e);
-                        }
-                    }
-                    "M" => {
-                        if parts.len() >= 2 {
-                            let path = parts[1].to_string();
-                            let mut e = DiffEntry {
-                                filePath: path.clone(),
-                                status: "modified".into(),
-                                additions: 0,
-                                deletions: 0,
-                                isBinary: false,
-                                ..Default::default()
-                            };
-                            if include {
-                                let old_s = crate::util::run_git(
-                                    &cwd,
-                                    &["show", &format!("{}:{}", compare_base_oid, path)],
-                                )
-                                .unwrap_or_default();
-                                let new_s = crate::util::run_git(
-                                    &cwd,
-                                    &["show", &format!("{}:{}", head_oid, path)],
-                                )
-                                .unwrap_or_default();
-                                let old_sz = old_s.len();
-                                let new_sz = new_s.len();
-                                e.oldSize = Some(old_sz as i32);
+                    "A" if parts.len() >= 2 => {
+                        let path = parts[1].to_string();
+                        let mut e = DiffEntry {
+                            filePath: path.clone(),
+                            status: "added".into(),
+                            additions: 0,
+                            deletions: 0,
+                            isBinary: false,
+                            newHash: blob_hash(head_map.get(&path)),
+                            contentKey: content_key(None, head_map.get(&path)),
+                            ..Default::default()
+                        };
+                        if include {
+                            // new content from head
+                            if let Ok(buf) = crate::util::run_git(
+                                &cwd,
+                                &["show", &format!("{}:{}", head_oid, path)],
+                            ) {
+                                let new_sz = buf.len();
                                 e.newSize = Some(new_sz as i32);
-                                if old_sz + new_sz <= max_bytes {
-                                    let diff = TextDiff::from_lines(&old_s, &new_s);
-                                    let mut adds = 0i32;
-                                    let mut dels = 0i32;
-                                    for op in diff.ops() {
-                                        let tag = op.tag();
-                                        for ch in diff.iter_changes(op) {
-                                            match (tag, ch.tag()) {
-                                                (similar::DiffTag::Insert, _) => adds += 1,
-                                                (similar::DiffTag::Delete, _) => dels += 1,
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                    e.additions = adds;
-                                    e.deletions = dels;
-                                    e.oldContent = Some(old_s);
-                                    e.newContent = Some(new_s);
+                                e.oldSize = Some(0);
+                                if new_sz <= max_bytes {
+                                    e.newContent = Some(buf.clone());
+                                    e.oldContent = Some(String::new());
+                                    e.additions = buf.lines().count() as i32;
                                     e.contentOmitted = Some(false);
                                 } else {
                                     e.contentOmitted = Some(true);
                                 }
                             }
-                            fallback.push(e);
                         }
+                        fallback.push(e);
                     }
-                    "D" => {
-                        if parts.len() >= 2 {
-                            let path = parts[1].to_string();
-                            let mut e = DiffEntry {
-                                filePath: path.clone(),
-                                status: "deleted".into(),
-                                additions: 0,
-                                deletions: 0,
-                                isBinary: false,
-                                ..Default::default()
-                            };
-                            if include {
-                                if let Ok(buf) = crate::util::run_git(
-                                    &cwd,
-                                    &["show", &format!("{}:{}", compare_base_oid, path)],
-                                ) {
-                                    let old_sz = buf.len();
-                                    e.oldSize = Some(old_sz as i32);
-                                    if old_sz <= max_bytes {
-                                        e.oldContent = Some(buf.clone());
-                                        e.newContent = Some(String::new());
-                                        e.deletions = buf.lines().count() as i32;
-                                        e.contentOmitted = Some(false);
-                                    } else {
-                                        e.contentOmitted = Some(true);
+                    "M" if parts.len() >= 2 => {
+                        let path = parts[1].to_string();
+                        let mut e = DiffEntry {
+                            filePath: path.clone(),
+                            status: "modified".into(),
+                            additions: 0,
+                            deletions: 0,
+                            isBinary: false,
+                            oldHash: blob_hash(base_map.get(&path)),
+                            newHash: blob_hash(head_map.get(&path)),
+                            contentKey: content_key(base_map.get(&path), head_map.get(&path)),
+                            ..Default::default()
+                        };
+                        if include {
+                            let old_s = crate::util::run_git(
+                                &cwd,
+                                &["show", &format!("{}:{}", compare_base_oid, path)],
+                            )
+                            .unwrap_or_default();
+                            let new_s = crate::util::run_git(
+                                &cwd,
+                                &["show", &format!("{}:{}", head_oid, path)],
+                            )
+                            .unwrap_or_default();
+                            let old_sz = old_s.len();
+                            let new_sz = new_s.len();
+                            e.oldSize = Some(old_sz as i32);
+                            e.newSize = Some(new_sz as i32);
+                            if old_sz + new_sz <= max_bytes {
+                                let diff = TextDiff::from_lines(&old_s, &new_s);
+                                let mut adds = 0i32;
+                                let mut dels = 0i32;
+                                for op in diff.ops() {
+                                    let tag = op.tag();
+                                    for ch in diff.iter_changes(op) {
+                                        match (tag, ch.tag()) {
+                                            (similar::DiffTag::Insert, _) => adds += 1,
+                                            (similar::DiffTag::Delete, _) => dels += 1,
+                                            _ => {}
+                                        }
                                     }
                                 }
+                                e.additions = adds;
+                                e.deletions = dels;
+                                e.oldContent = Some(old_s);
+                                e.newContent = Some(new_s);
+                                e.contentOmitted = Some(false);
+                            } else {
+                                e.contentOmitted = Some(true);
                             }
-                            fallback.push(e);
                         }
+                        fallback.push(e);
                     }
-                    "R" | "R100" | "R099" | "R098" | "R097" | "R096" | "R095" | "R094" | "R093"
-                    | "R092" | "R091" | "R090" => {
-                        if parts.len() >= 3 {
-                            let oldp = parts[1].to_string();
-                            let newp = parts[2].to_string();
-                            let mut e = DiffEntry {
-                                filePath: newp.clone(),
-                                oldPath: Some(oldp.clone()),
-                                status: "renamed".into(),
-                                additions: 0,
-                                deletions: 0,
-                                isBinary: false,
-                                ..Default::default()
-                            };
-                            if include {
-                                let new_s = crate::util::run_git(
-                                    &cwd,
-                                    &["show", &format!("{}:{}", head_oid, newp)],
-                                )
-                                .unwrap_or_default();
-                                let new_sz = new_s.len();
-                                e.newSize = Some(new_sz as i32);
-                                e.oldSize = Some(new_sz as i32);
-                                if new_sz <= max_bytes {
-                                    e.oldContent = Some(new_s.clone());
-                                    e.newContent = Some(new_s);
+                    "D" if parts.len() >= 2 => {
+                        let path = parts[1].to_string();
+                        let mut e = DiffEntry {
+                            filePath: path.clone(),
+                            status: "deleted".into(),
+                            additions: 0,
+                            deletions: 0,
+                            isBinary: false,
+                            oldHash: blob_hash(base_map.get(&path)),
+                            contentKey: content_key(base_map.get(&path), None),
+                            ..Default::default()
+                        };
+                        if include {
+                            if let Ok(buf) = crate::util::run_git(
+                                &cwd,
+                                &["show", &format!("{}:{}", compare_base_oid, path)],
+                            ) {
+                                let old_sz = buf.len();
+                                e.oldSize = Some(old_sz as i32);
+                                if old_sz <= max_bytes {
+                                    e.oldContent = Some(buf.clone());
+                                    e.newContent = Some(String::new());
+                                    e.deletions = buf.lines().count() as i32;
                                     e.contentOmitted = Some(false);
                                 } else {
                                     e.contentOmitted = Some(true);
                                 }
                             }
-                            fallback.push(e);
                         }
+                        fallback.push(e);
+                    }
+                    "R" | "R100" | "R099" | "R098" | "R097" | "R096" | "R095" | "R094" | "R093"
+                    | "R092" | "R091" | "R090"
+                        if parts.len() >= 3 =>
+                    {
+                        let oldp = parts[1].to_string();
+                        let newp = parts[2].to_string();
+                        let mut e = DiffEntry {
+                            filePath: newp.clone(),
+                            oldPath: Some(oldp.clone()),
+                            status: "renamed".into(),
+                            additions: 0,
+                            deletions: 0,
+                            isBinary: false,
+                            oldHash: blob_hash(base_map.get(&oldp)),
+                            newHash: blob_hash(head_map.get(&newp)),
+                            contentKey: content_key(base_map.get(&oldp), head_map.get(&newp)),
+                            ..Default::default()
+                        };
+                        if include {
+                            let new_s = crate::util::run_git(
+                                &cwd,
+                                &["show", &format!("{}:{}", head_oid, newp)],
+                            )
+                            .unwrap_or_default();
+                            let new_sz = new_s.len();
+                            e.newSize = Some(new_sz as i32);
+                            e.oldSize = Some(new_sz as i32);
+                            if new_sz <= max_bytes {
+                                e.oldContent = Some(new_s.clone());
+                                e.newContent = Some(new_s);
+                                e.contentOmitted = Some(false);
+                            } else {
+                                e.contentOmitted = Some(true);
+                            }
+                        }
+                        fallback.push(e);
                     }
                     _ => {}
                 }
@@ -803,25 +922,27 @@ pub fn diff_refs(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
                     "[native.refs] CLI fallback returning {} entries",
                     fallback.len()
                 );
-                // Stable sort by filePath (case-insensitive)
-                fallback.sort_by(|a, b| {
-                    a.filePath
-                        .to_lowercase()
-                        .cmp(&b.filePath.to_lowercase())
-                        .then_with(|| a.filePath.cmp(&b.filePath))
-                });
+                finish_entries(&mut fallback, max_total_bytes);
                 return Ok(fallback);
             }
         }
     }
 
-    // Stable sort by filePath (case-insensitive)
-    out.sort_by(|a, b| {
+    finish_entries(&mut out, max_total_bytes);
+    Ok(out)
+}
+
+/// Sort entries by path (case-insensitive, stable) and spend the response-wide
+/// content budget in that order.
+fn finish_entries(entries: &mut [DiffEntry], max_total_bytes: Option<usize>) {
+    entries.sort_by(|a, b| {
         a.filePath
             .to_lowercase()
             .cmp(&b.filePath.to_lowercase())
             .then_with(|| a.filePath.cmp(&b.filePath))
     });
-
-    Ok(out)
+    if let Some(budget) = max_total_bytes {
+        apply_total_budget(entries, budget);
+    }
+    drop_unneeded_keys(entries);
 }
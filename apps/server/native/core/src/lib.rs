@@ -1,5 +1,7 @@
 #![deny(clippy::all)]
 
+mod archive;
+mod bisect;
 mod branches;
 mod diff;
 mod merge_base;
@@ -9,7 +11,12 @@ mod util;
 
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
-use types::{BranchInfo, DiffEntry, GitDiffOptions, GitListRemoteBranchesOptions};
+use types::{
+    BranchInfo, DiffContents, DiffEntry, FileOwners, GitArchiveOptions, GitArchiveResult,
+    GitBisectPlan, GitBisectPlanOptions, GitCheckPoliciesOptions, GitDiffContentsOptions,
+    GitDiffOptions, GitDiffOwnersOptions, GitDiffResult, GitListRemoteBranchesOptions,
+    PolicyViolation,
+};
 
 #[napi]
 pub async fn get_time() -> String {
@@ -20,6 +27,14 @@ pub async fn get_time() -> String {
     now.as_millis().to_string()
 }
 
+/// Register the token used to fetch `teamSlugOrId`'s private repositories,
+/// or clear it with `null`. Takes precedence over `CMUX_GIT_TOKEN_<TEAM>` and
+/// `CMUX_GIT_TOKEN` from the environment.
+#[napi]
+pub fn git_set_team_token(team_slug_or_id: String, token: Option<String>) {
+    repo::credentials::set_team_token(&team_slug_or_id, token);
+}
+
 #[napi]
 pub async fn git_diff(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
     #[cfg(debug_assertions)]
@@ -28,7 +43,7 @@ pub async fn git_diff(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
     opts.headRef,
     opts.baseRef,
     opts.originPathOverride,
-    opts.repoUrl,
+    opts.repoUrl.as_deref().map(repo::credentials::redact),
     opts.repoFullName,
     opts.includeContents,
     opts.maxBytes
@@ -39,6 +54,66 @@ pub async fn git_diff(opts: GitDiffOptions) -> Result<Vec<DiffEntry>> {
         .map_err(|e| Error::from_reason(format!("{e:#}")))
 }
 
+#[napi]
+pub async fn git_diff_incremental(opts: GitDiffOptions) -> Result<GitDiffResult> {
+    #[cfg(debug_assertions)]
+    println!(
+        "[cmux_native_git] git_diff_incremental headRef={} lastKnownHeadSha={:?} lastKnownBaseSha={:?}",
+        opts.headRef, opts.lastKnownHeadSha, opts.lastKnownBaseSha
+    );
+    tokio::task::spawn_blocking(move || diff::refs::diff_refs_incremental(opts))
+        .await
+        .map_err(|e| Error::from_reason(format!("Join error: {e}")))?
+        .map_err(|e| Error::from_reason(format!("{e:#}")))
+}
+
+/// Load contents `git_diff` omitted for size, by the entries' `contentKey`.
+#[napi]
+pub async fn git_diff_contents(opts: GitDiffContentsOptions) -> Result<Vec<DiffContents>> {
+    #[cfg(debug_assertions)]
+    println!(
+        "[cmux_native_git] git_diff_contents keys={} maxBytes={:?}",
+        opts.keys.len(),
+        opts.maxBytes
+    );
+    tokio::task::spawn_blocking(move || diff::contents::diff_contents(opts))
+        .await
+        .map_err(|e| Error::from_reason(format!("Join error: {e}")))?
+        .map_err(|e| Error::from_reason(format!("{e:#}")))
+}
+
+#[napi]
+pub async fn git_diff_owners(opts: GitDiffOwnersOptions) -> Result<Vec<FileOwners>> {
+    #[cfg(debug_assertions)]
+    println!(
+        "[cmux_native_git] git_diff_owners headRef={} baseRef={:?} codeownersRef={:?}",
+        opts.diff.headRef, opts.diff.baseRef, opts.codeownersRef
+    );
+    tokio::task::spawn_blocking(move || diff::owners::diff_owners(opts))
+        .await
+        .map_err(|e| Error::from_reason(format!("Join error: {e}")))?
+        .map_err(|e| Error::from_reason(format!("{e:#}")))
+}
+
+/// Check a diff against push policies (file size, forbidden paths, secret
+/// patterns) and list every violation, so callers can block the push.
+#[napi]
+pub async fn git_check_policies(opts: GitCheckPoliciesOptions) -> Result<Vec<PolicyViolation>> {
+    #[cfg(debug_assertions)]
+    println!(
+        "[cmux_native_git] git_check_policies headRef={} baseRef={:?} maxFileBytes={:?} forbiddenPaths={:?} secretPatterns={}",
+        opts.diff.headRef,
+        opts.diff.baseRef,
+        opts.maxFileBytes,
+        opts.forbiddenPaths,
+        opts.secretPatterns.as_ref().map_or(0, Vec::len)
+    );
+    tokio::task::spawn_blocking(move || diff::policies::check_policies(opts))
+        .await
+        .map_err(|e| Error::from_reason(format!("Join error: {e}")))?
+        .map_err(|e| Error::from_reason(format!("{e:#}")))
+}
+
 #[napi]
 pub async fn git_list_remote_branches(
     opts: GitListRemoteBranchesOptions,
@@ -47,7 +122,7 @@ pub async fn git_list_remote_branches(
     println!(
     "[cmux_native_git] git_list_remote_branches repoFullName={:?} repoUrl={:?} originPathOverride={:?}",
     opts.repoFullName,
-    opts.repoUrl,
+    opts.repoUrl.as_deref().map(repo::credentials::redact),
     opts.originPathOverride
   );
     tokio::task::spawn_blocking(move || branches::list_remote_branches(opts))
@@ -56,5 +131,37 @@ pub async fn git_list_remote_branches(
         .map_err(|e| Error::from_reason(format!("{e:#}")))
 }
 
+#[napi]
+pub async fn git_archive(opts: GitArchiveOptions) -> Result<GitArchiveResult> {
+    #[cfg(debug_assertions)]
+    println!(
+        "[cmux_native_git] git_archive refName={} paths={:?} format={:?} outputPath={:?}",
+        opts.refName, opts.paths, opts.format, opts.outputPath
+    );
+    let archive = tokio::task::spawn_blocking(move || archive::git_archive(opts))
+        .await
+        .map_err(|e| Error::from_reason(format!("Join error: {e}")))?
+        .map_err(|e| Error::from_reason(format!("{e:#}")))?;
+    Ok(GitArchiveResult {
+        commitSha: archive.commit_sha,
+        format: archive.format.to_string(),
+        data: archive.data.map(Buffer::from),
+        size: archive.size as i64,
+    })
+}
+
+#[napi]
+pub async fn git_bisect_plan(opts: GitBisectPlanOptions) -> Result<GitBisectPlan> {
+    #[cfg(debug_assertions)]
+    println!(
+        "[cmux_native_git] git_bisect_plan goodRefs={:?} badRef={} skipRefs={:?}",
+        opts.goodRefs, opts.badRef, opts.skipRefs
+    );
+    tokio::task::spawn_blocking(move || bisect::git_bisect_plan(opts))
+        .await
+        .map_err(|e| Error::from_reason(format!("Join error: {e}")))?
+        .map_err(|e| Error::from_reason(format!("{e:#}")))
+}
+
 #[cfg(test)]
 mod tests;
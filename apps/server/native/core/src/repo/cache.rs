@@ -7,7 +7,9 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::util::run_git;
+use crate::repo::credentials::GitAuth;
+use crate::repo::lock::{is_git_lock_error, lock_timeout, recover_stale_git_locks, RepoLock};
+use crate::util::run_git_with_auth;
 
 const MAX_CACHE_REPOS: usize = 20;
 
@@ -58,36 +60,59 @@ fn slug_from_url(url: &str) -> String {
     }
 }
 
-pub fn ensure_repo(url: &str) -> Result<PathBuf> {
+/// Path of an already-cloned cache entry for `url`, without cloning or fetching.
+pub fn cached_repo_path(url: &str) -> Option<PathBuf> {
+    let path = default_cache_root().join(slug_from_url(url));
+    path.join(".git").join("HEAD").exists().then_some(path)
+}
+
+/// Clone `url` into the cache, or refresh the existing clone. `auth` is used
+/// for every fetch; see [`GitAuth::resolve`].
+pub fn ensure_repo(url: &str, auth: Option<&GitAuth>) -> Result<PathBuf> {
     let root = default_cache_root();
     fs::create_dir_all(&root)?;
     let path = root.join(slug_from_url(url));
-    let git_dir = path.join(".git");
-    let head = git_dir.join("HEAD");
-    if path.exists() && (!git_dir.exists() || !head.exists()) {
-        let _ = fs::remove_dir_all(&path);
-    }
-    if !path.exists() {
-        fs::create_dir_all(&path)?;
-        run_git(
-            root.to_string_lossy().as_ref(),
-            &[
-                "clone",
-                "--no-single-branch",
-                url,
-                path.file_name().unwrap().to_str().unwrap(),
-            ],
-        )?;
-        let _ = update_cache_index_with(&root, &path, Some(now_ms()));
-    } else {
-        let _ = swr_fetch_origin_all_path_bool(&path, fetch_window_ms());
-    }
-    let shallow = path.join(".git").join("shallow");
-    if shallow.exists() {
-        let _ = run_git(
-            path.to_string_lossy().as_ref(),
-            &["fetch", "--unshallow", "--tags"],
-        );
+    let cloned = {
+        // Other server processes share this cache; serialize clone/repair per repo.
+        let _lock = RepoLock::acquire(&path, lock_timeout())?;
+        let git_dir = path.join(".git");
+        let head = git_dir.join("HEAD");
+        if path.exists() && (!git_dir.exists() || !head.exists()) {
+            let _ = fs::remove_dir_all(&path);
+        }
+        let cloned = !path.exists();
+        if cloned {
+            fs::create_dir_all(&path)?;
+            if let Err(e) = run_git_with_auth(
+                root.to_string_lossy().as_ref(),
+                &[
+                    "clone",
+                    "--no-single-branch",
+                    url,
+                    path.file_name().unwrap().to_str().unwrap(),
+                ],
+                auth,
+            ) {
+                // Don't leave a half-cloned directory for the next caller
+                let _ = fs::remove_dir_all(&path);
+                return Err(e);
+            }
+            let _ = update_cache_index_with(&root, &path, Some(now_ms()));
+        } else {
+            recover_stale_git_locks(&path, None);
+        }
+        let shallow = path.join(".git").join("shallow");
+        if shallow.exists() {
+            let _ = run_git_with_auth(
+                path.to_string_lossy().as_ref(),
+                &["fetch", "--unshallow", "--tags"],
+                auth,
+            );
+        }
+        cloned
+    };
+    if !cloned {
+        let _ = swr_fetch_origin_all_path_bool(&path, fetch_window_ms(), auth);
     }
 
     update_cache_index(&root, &path)?;
@@ -95,6 +120,12 @@ pub fn ensure_repo(url: &str) -> Result<PathBuf> {
     Ok(path)
 }
 
+/// Hold the cache index lock while reading and rewriting `cache-index.json`.
+fn with_index_lock<T>(root: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let _lock = RepoLock::acquire(&root.join("cache-index"), lock_timeout())?;
+    f()
+}
+
 pub fn resolve_repo_url(repo_full_name: Option<&str>, repo_url: Option<&str>) -> Result<String> {
     if let Some(u) = repo_url {
         return Ok(u.to_string());
@@ -117,12 +148,19 @@ fn load_index(root: &Path) -> CacheIndex {
 
 fn save_index(root: &Path, idx: &CacheIndex) -> Result<()> {
     let idx_path = root.join("cache-index.json");
+    let tmp_path = root.join(format!("cache-index.json.{}.tmp", std::process::id()));
     let data = serde_json::to_vec_pretty(idx)?;
-    fs::write(idx_path, data)?;
+    // Write then rename so readers never see a truncated index
+    fs::write(&tmp_path, data)?;
+    fs::rename(tmp_path, idx_path)?;
     Ok(())
 }
 
 fn update_cache_index(root: &Path, repo_path: &Path) -> Result<()> {
+    with_index_lock(root, || update_cache_index_locked(root, repo_path))
+}
+
+fn update_cache_index_locked(root: &Path, repo_path: &Path) -> Result<()> {
     let mut idx = load_index(root);
     let slug = repo_path
         .file_name()
@@ -146,7 +184,7 @@ fn update_cache_index(root: &Path, repo_path: &Path) -> Result<()> {
         });
     }
     idx.entries
-        .sort_by(|a, b| b.last_access_ms.cmp(&a.last_access_ms));
+        .sort_by_key(|e| std::cmp::Reverse(e.last_access_ms));
     idx.entries.dedup_by(|a, b| a.slug == b.slug);
     save_index(root, &idx)?;
     Ok(())
@@ -163,6 +201,16 @@ fn update_cache_index_with(
     root: &Path,
     repo_path: &Path,
     last_fetch_ms: Option<u128>,
+) -> Result<()> {
+    with_index_lock(root, || {
+        update_cache_index_with_locked(root, repo_path, last_fetch_ms)
+    })
+}
+
+fn update_cache_index_with_locked(
+    root: &Path,
+    repo_path: &Path,
+    last_fetch_ms: Option<u128>,
 ) -> Result<()> {
     let mut idx = load_index(root);
     let pstr = repo_path.to_string_lossy().to_string();
@@ -186,7 +234,7 @@ fn update_cache_index_with(
         });
     }
     idx.entries
-        .sort_by(|a, b| b.last_access_ms.cmp(&a.last_access_ms));
+        .sort_by_key(|e| std::cmp::Reverse(e.last_access_ms));
     idx.entries.dedup_by(|a, b| a.slug == b.slug);
     save_index(root, &idx)?;
     Ok(())
@@ -219,7 +267,11 @@ fn set_map_last_fetch(repo_path: &Path, t: u128) {
     }
 }
 
-pub fn swr_fetch_origin_all_path_bool(path: &std::path::Path, window_ms: u128) -> Result<bool> {
+pub fn swr_fetch_origin_all_path_bool(
+    path: &std::path::Path,
+    window_ms: u128,
+    auth: Option<&GitAuth>,
+) -> Result<bool> {
     let cwd = path.to_string_lossy().to_string();
     let root = default_cache_root();
     let now = now_ms();
@@ -232,8 +284,13 @@ pub fn swr_fetch_origin_all_path_bool(path: &std::path::Path, window_ms: u128) -
         if now.saturating_sub(t) <= window_ms {
             let cwd_bg = cwd.clone();
             let root_bg = root.clone();
+            let auth_bg = auth.cloned();
             std::thread::spawn(move || {
-                let _ = run_git(&cwd_bg, &["fetch", "--all", "--tags", "--prune"]);
+                // Another process already fetching makes this refresh redundant
+                let Ok(Some(_lock)) = RepoLock::try_acquire(Path::new(&cwd_bg)) else {
+                    return;
+                };
+                let _ = fetch_all_locked(Path::new(&cwd_bg), auth_bg.as_ref());
                 let _ = update_cache_index_with(&root_bg, &PathBuf::from(&cwd_bg), Some(now_ms()));
                 set_map_last_fetch(&PathBuf::from(&cwd_bg), now_ms());
             });
@@ -241,40 +298,76 @@ pub fn swr_fetch_origin_all_path_bool(path: &std::path::Path, window_ms: u128) -
         }
     }
 
-    let _ = run_git(&cwd, &["fetch", "--all", "--tags", "--prune"]);
+    {
+        let _lock = RepoLock::acquire(path, lock_timeout())?;
+        let _ = fetch_all_locked(path, auth);
+    }
     let now2 = now_ms();
     let _ = update_cache_index_with(&root, &PathBuf::from(&cwd), Some(now2));
     set_map_last_fetch(&PathBuf::from(&cwd), now2);
     Ok(true)
 }
 
-pub fn swr_fetch_origin_all_path(path: &std::path::Path, window_ms: u128) -> Result<()> {
-    let _ = swr_fetch_origin_all_path_bool(path, window_ms)?;
+pub fn swr_fetch_origin_all_path(
+    path: &std::path::Path,
+    window_ms: u128,
+    auth: Option<&GitAuth>,
+) -> Result<()> {
+    let _ = swr_fetch_origin_all_path_bool(path, window_ms, auth)?;
     Ok(())
 }
 #[allow(dead_code)]
-pub fn fetch_origin_all_path(path: &std::path::Path) -> Result<()> {
-    let cwd = path.to_string_lossy().to_string();
-    let _ = run_git(&cwd, &["fetch", "--all", "--tags", "--prune"]);
+pub fn fetch_origin_all_path(path: &std::path::Path, auth: Option<&GitAuth>) -> Result<()> {
+    let _lock = RepoLock::acquire(path, lock_timeout())?;
+    let _ = fetch_all_locked(path, auth);
     Ok(())
 }
 
-fn enforce_cache_limit(root: &Path) -> Result<()> {
-    let mut idx = load_index(root);
-    if idx.entries.len() <= MAX_CACHE_REPOS {
-        return Ok(());
-    }
-    idx.entries
-        .sort_by(|a, b| b.last_access_ms.cmp(&a.last_access_ms));
-    let survivors = idx.entries[..MAX_CACHE_REPOS].to_vec();
-    let victims = idx.entries[MAX_CACHE_REPOS..].to_vec();
-    for v in &victims {
-        let p = PathBuf::from(&v.path);
-        let _ = fs::remove_dir_all(&p);
+const FETCH_ATTEMPTS: usize = 3;
+
+/// Fetch all remotes; caller must hold the repo's [`RepoLock`]. A fetch that
+/// trips over a lock file left by a crashed git is retried after clearing it.
+fn fetch_all_locked(path: &Path, auth: Option<&GitAuth>) -> Result<()> {
+    let cwd = path.to_string_lossy().to_string();
+    let mut attempt = 1;
+    loop {
+        match run_git_with_auth(&cwd, &["fetch", "--all", "--tags", "--prune"], auth) {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < FETCH_ATTEMPTS && is_git_lock_error(&e) => {
+                // We hold the repo lock, so nobody else is running git here
+                recover_stale_git_locks(path, Some(std::time::Duration::ZERO));
+                std::thread::sleep(std::time::Duration::from_millis(100 * attempt as u64));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
     }
-    idx.entries = survivors;
-    save_index(root, &idx)?;
-    Ok(())
+}
+
+fn enforce_cache_limit(root: &Path) -> Result<()> {
+    with_index_lock(root, || {
+        let mut idx = load_index(root);
+        if idx.entries.len() <= MAX_CACHE_REPOS {
+            return Ok(());
+        }
+        idx.entries
+            .sort_by_key(|e| std::cmp::Reverse(e.last_access_ms));
+        let mut survivors = idx.entries[..MAX_CACHE_REPOS].to_vec();
+        let victims = idx.entries[MAX_CACHE_REPOS..].to_vec();
+        for v in victims {
+            let p = PathBuf::from(&v.path);
+            // Never delete a repo another process is using; evict it next time
+            match RepoLock::try_acquire(&p) {
+                Ok(Some(_lock)) => {
+                    let _ = fs::remove_dir_all(&p);
+                }
+                _ => survivors.push(v),
+            }
+        }
+        idx.entries = survivors;
+        save_index(root, &idx)?;
+        Ok(())
+    })
 }
 
 #[cfg(test)]
@@ -303,12 +396,59 @@ mod tests {
         .expect("spawn");
         assert!(status.success());
 
-        let first = swr_fetch_origin_all_path_bool(&repo_dir, 5_000).expect("swr fetch 1");
-        let second = swr_fetch_origin_all_path_bool(&repo_dir, 5_000).expect("swr fetch 2");
+        let first = swr_fetch_origin_all_path_bool(&repo_dir, 5_000, None).expect("swr fetch 1");
+        let second = swr_fetch_origin_all_path_bool(&repo_dir, 5_000, None).expect("swr fetch 2");
         assert!(first, "first call should be synchronous fetch");
         assert!(
             !second,
             "second call within window should skip and background"
         );
     }
+
+    #[test]
+    fn repo_lock_excludes_other_holders_until_dropped() {
+        let tmp = tempdir().unwrap();
+        let repo_dir = tmp.path().join("repo");
+
+        let held = RepoLock::try_acquire(&repo_dir)
+            .unwrap()
+            .expect("first lock");
+        assert!(RepoLock::try_acquire(&repo_dir).unwrap().is_none());
+        let err = RepoLock::acquire(&repo_dir, std::time::Duration::from_millis(50))
+            .expect_err("second acquirer should time out");
+        assert!(
+            err.to_string()
+                .contains(&format!("pid={}", std::process::id())),
+            "timeout should name the holder: {err}"
+        );
+
+        drop(held);
+        assert!(RepoLock::acquire(&repo_dir, std::time::Duration::from_millis(50)).is_ok());
+    }
+
+    #[test]
+    fn stale_git_locks_are_recovered() {
+        let tmp = tempdir().unwrap();
+        let refs = tmp.path().join(".git").join("refs").join("heads");
+        std::fs::create_dir_all(&refs).unwrap();
+        let stale = refs.join("main.lock");
+        std::fs::write(&stale, "").unwrap();
+        let index_lock = tmp.path().join(".git").join("index.lock");
+        std::fs::write(&index_lock, "").unwrap();
+
+        // Fresh lock files may belong to a git that is still running
+        assert_eq!(recover_stale_git_locks(tmp.path(), None), 0);
+        assert!(stale.exists());
+
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(600);
+        std::fs::File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert_eq!(recover_stale_git_locks(tmp.path(), None), 1);
+        assert!(!stale.exists());
+        assert!(index_lock.exists());
+    }
 }
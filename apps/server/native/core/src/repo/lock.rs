@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+// Cloning a large repo can legitimately hold the lock for a while.
+pub const DEFAULT_LOCK_TIMEOUT_MS: u64 = 120_000;
+
+// git leaves `*.lock` files behind when killed mid-update; once we hold the
+// repo lock, any older than this cannot belong to a live cooperating process.
+const STALE_GIT_LOCK_AGE: Duration = Duration::from_secs(60);
+
+pub fn lock_timeout() -> Duration {
+    let ms = std::env::var("CMUX_GIT_LOCK_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_LOCK_TIMEOUT_MS);
+    Duration::from_millis(ms)
+}
+
+/// Cross-process advisory lock guarding one cached repo (or the cache index).
+/// Backed by an OS file lock on a sibling `<path>.lock` file, so it is released
+/// automatically if the holder dies. Unlocked on drop.
+#[derive(Debug)]
+pub struct RepoLock {
+    file: File,
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(".lock");
+    PathBuf::from(s)
+}
+
+fn open_lock_file(path: &Path) -> Result<File> {
+    let lock_file = lock_path(path);
+    if let Some(parent) = lock_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_file)?)
+}
+
+impl RepoLock {
+    /// Wait up to `timeout` for the lock, polling with backoff.
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<Self> {
+        let start = Instant::now();
+        let mut backoff = Duration::from_millis(10);
+        loop {
+            if let Some(lock) = Self::try_acquire(path)? {
+                return Ok(lock);
+            }
+            if start.elapsed() >= timeout {
+                return Err(anyhow!(
+                    "timed out after {}ms waiting for lock on {} (held by {})",
+                    timeout.as_millis(),
+                    path.display(),
+                    describe_holder(path)
+                ));
+            }
+            std::thread::sleep(backoff.min(timeout.saturating_sub(start.elapsed())));
+            backoff = (backoff * 2).min(Duration::from_millis(500));
+        }
+    }
+
+    /// Take the lock if nobody holds it.
+    pub fn try_acquire(path: &Path) -> Result<Option<Self>> {
+        let mut file = open_lock_file(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        // Record the holder for diagnostics; best effort.
+        let _ = file.set_len(0);
+        let _ = file.seek(SeekFrom::Start(0));
+        let _ = write!(file, "pid={} since_ms={}", std::process::id(), now_ms());
+        Ok(Some(Self { file }))
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+fn describe_holder(path: &Path) -> String {
+    let mut info = String::new();
+    if let Ok(mut f) = File::open(lock_path(path)) {
+        let _ = f.read_to_string(&mut info);
+    }
+    if info.trim().is_empty() {
+        "unknown".to_string()
+    } else {
+        info.trim().to_string()
+    }
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// Remove git's own `*.lock` files left in `.git` by processes that died
+/// mid-update. Must only be called while holding the repo's [`RepoLock`].
+/// Returns the number of files removed.
+pub fn recover_stale_git_locks(repo_path: &Path, min_age: Option<Duration>) -> usize {
+    let min_age = min_age.unwrap_or(STALE_GIT_LOCK_AGE);
+    let git_dir = repo_path.join(".git");
+    let mut removed = 0;
+    let mut stack = vec![git_dir.clone()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                // Object directories are large and never hold lock files
+                if dir != git_dir || entry.file_name() != "objects" {
+                    stack.push(path);
+                }
+                continue;
+            }
+            if path.extension().is_none_or(|ext| ext != "lock") {
+                continue;
+            }
+            let age = meta
+                .modified()
+                .ok()
+                .and_then(|m| m.elapsed().ok())
+                .unwrap_or_default();
+            if age >= min_age && fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+/// Whether a git error looks like contention on one of git's lock files.
+pub fn is_git_lock_error(err: &anyhow::Error) -> bool {
+    let msg = err.to_string();
+    msg.contains(".lock': File exists") || msg.contains("cannot lock ref")
+}
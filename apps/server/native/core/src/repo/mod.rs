@@ -1 +1,3 @@
 pub mod cache;
+pub mod credentials;
+pub mod lock;
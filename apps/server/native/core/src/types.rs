@@ -1,4 +1,5 @@
 #![allow(non_snake_case)]
+use napi::bindgen_prelude::Buffer;
 use napi_derive::napi;
 
 #[napi(object)]
@@ -17,6 +18,13 @@ pub struct DiffEntry {
     pub newSize: Option<i32>,
     pub patchSize: Option<i32>,
     pub patch: Option<String>,
+    /// Git blob OIDs of each side, set when `includeHashes` is requested.
+    /// Stable across polls, so clients can cache contents by hash and skip
+    /// re-rendering entries whose hashes haven't changed.
+    pub oldHash: Option<String>,
+    pub newHash: Option<String>,
+    /// Set when contents were omitted; pass to `gitDiffContents` to load them.
+    pub contentKey: Option<String>,
 }
 
 #[napi(object)]
@@ -35,6 +43,8 @@ pub struct BranchInfo {
 pub struct GitListRemoteBranchesOptions {
     pub repoFullName: Option<String>,
     pub repoUrl: Option<String>,
+    /// Team whose git token is used to fetch a private `repoUrl`.
+    pub teamSlugOrId: Option<String>,
     pub originPathOverride: Option<String>,
 }
 
@@ -56,7 +66,169 @@ pub struct GitDiffOptions {
     pub teamSlugOrId: Option<String>,
     pub originPathOverride: Option<String>,
     pub includeContents: Option<bool>,
+    /// Per-file limit: a file whose old + new contents exceed it is omitted.
     pub maxBytes: Option<i32>,
+    /// Limit on contents across the whole response, spent in path order;
+    /// files past it are omitted but keep their line counts (default: none).
+    pub maxTotalBytes: Option<i32>,
     pub lastKnownBaseSha: Option<String>,
     pub lastKnownMergeCommitSha: Option<String>,
+    /// Head commit of the caller's cached diff; see `gitDiffIncremental`.
+    pub lastKnownHeadSha: Option<String>,
+    /// Fill `oldHash`/`newHash` on each entry (default false).
+    pub includeHashes: Option<bool>,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct GitDiffContentsOptions {
+    pub repoFullName: Option<String>,
+    pub repoUrl: Option<String>,
+    /// Team whose git token is used to fetch a private `repoUrl`.
+    pub teamSlugOrId: Option<String>,
+    pub originPathOverride: Option<String>,
+    /// `contentKey`s of entries returned with `contentOmitted`.
+    pub keys: Vec<String>,
+    /// Keep files whose old + new contents exceed this omitted (default: no limit).
+    pub maxBytes: Option<i32>,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct DiffContents {
+    pub key: String,
+    pub isBinary: bool,
+    /// Still omitted: binary, or over `maxBytes`.
+    pub contentOmitted: bool,
+    pub oldContent: Option<String>,
+    pub newContent: Option<String>,
+    pub additions: i32,
+    pub deletions: i32,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct GitDiffResult {
+    /// Head and base are unchanged since `lastKnown*`; `entries` is empty and
+    /// the caller should reuse what it has.
+    pub notModified: bool,
+    pub entries: Vec<DiffEntry>,
+    pub headSha: Option<String>,
+    pub baseSha: Option<String>,
+    pub mergeBaseSha: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct GitDiffOwnersOptions {
+    pub diff: GitDiffOptions,
+    /// Ref to read CODEOWNERS from; defaults to the diff's base.
+    pub codeownersRef: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct FileOwners {
+    pub filePath: String,
+    pub owners: Vec<String>,
+    /// CODEOWNERS pattern that assigned the owners, if any matched.
+    pub pattern: Option<String>,
+    pub ruleLine: Option<i32>,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct GitCheckPoliciesOptions {
+    pub diff: GitDiffOptions,
+    /// Flag files whose new side is larger than this.
+    pub maxFileBytes: Option<i64>,
+    /// CODEOWNERS-style patterns no file may be added at or changed under.
+    pub forbiddenPaths: Option<Vec<String>>,
+    /// Regexes matched against each added line.
+    pub secretPatterns: Option<Vec<String>>,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct PolicyViolation {
+    pub filePath: String,
+    /// `forbiddenPath`, `maxFileSize` or `secret`.
+    pub rule: String,
+    pub message: String,
+    /// The forbidden path or secret pattern that matched.
+    pub pattern: Option<String>,
+    /// Line of the new file a secret was found on.
+    pub line: Option<i32>,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct GitArchiveOptions {
+    pub repoFullName: Option<String>,
+    pub repoUrl: Option<String>,
+    /// Team whose git token is used to fetch a private `repoUrl`.
+    pub teamSlugOrId: Option<String>,
+    pub originPathOverride: Option<String>,
+    /// Branch, tag or commit to archive.
+    pub refName: String,
+    /// Limit the archive to these paths (files or directories); whole tree if empty.
+    pub paths: Option<Vec<String>>,
+    /// `tar` (default), `tar.gz` or `zip`.
+    pub format: Option<String>,
+    /// Directory prefix for every entry, e.g. `repo-main/`.
+    pub prefix: Option<String>,
+    /// Write the archive to this file instead of returning it in memory.
+    pub outputPath: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct GitArchiveResult {
+    pub commitSha: String,
+    pub format: String,
+    /// Archive bytes; unset when written to `outputPath`.
+    pub data: Option<Buffer>,
+    pub size: i64,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct GitBisectPlanOptions {
+    pub repoFullName: Option<String>,
+    pub repoUrl: Option<String>,
+    /// Team whose git token is used to fetch a private `repoUrl`.
+    pub teamSlugOrId: Option<String>,
+    pub originPathOverride: Option<String>,
+    /// Every commit tested good so far (at least one).
+    pub goodRefs: Vec<String>,
+    /// Most recent commit tested bad.
+    pub badRef: String,
+    /// Commits that could not be tested; never suggested as midpoints.
+    pub skipRefs: Option<Vec<String>>,
+    /// Number of midpoints to return, best first (default 3).
+    pub maxMidpoints: Option<i32>,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct BisectMidpoint {
+    pub sha: String,
+    pub subject: String,
+    /// Candidates left if this commit tests good / bad.
+    pub remainingIfGood: i32,
+    pub remainingIfBad: i32,
+}
+
+#[napi(object)]
+#[derive(Default, Debug, Clone)]
+pub struct GitBisectPlan {
+    pub badSha: String,
+    pub goodShas: Vec<String>,
+    /// Commits that may still be the first bad one, including `badSha`.
+    pub candidateCount: i32,
+    /// Tests still needed in the worst case.
+    pub estimatedSteps: i32,
+    pub midpoints: Vec<BisectMidpoint>,
+    /// Set once bisection has converged.
+    pub firstBadSha: Option<String>,
 }
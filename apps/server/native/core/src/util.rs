@@ -1,14 +1,29 @@
 use anyhow::{anyhow, Result};
 use std::process::{Command, Stdio};
 
+use crate::repo::credentials::{redact, GitAuth};
+
 pub fn run_git(cwd: &str, args: &[&str]) -> Result<String> {
+    run_git_with_auth(cwd, args, None)
+}
+
+/// [`run_git`] with `auth`'s header configured for the remote. Errors never
+/// contain the token or URL userinfo.
+pub fn run_git_with_auth(cwd: &str, args: &[&str], auth: Option<&GitAuth>) -> Result<String> {
     let mut cmd = Command::new("git");
     cmd.current_dir(cwd).args(args).stdin(Stdio::null());
+    if let Some(auth) = auth {
+        auth.apply(&mut cmd);
+    }
     let output = cmd.output()?;
     if output.status.success() {
         Ok(String::from_utf8_lossy(&output.stdout).into_owned())
     } else {
         let err = String::from_utf8_lossy(&output.stderr);
-        Err(anyhow!("git {:?} failed: {}", args, err))
+        let message = format!("git {:?} failed: {}", args, err);
+        Err(anyhow!(match auth {
+            Some(auth) => auth.redact(&message),
+            None => redact(&message),
+        }))
     }
 }
@@ -13,17 +13,153 @@ export interface GitDiffOptions {
   teamSlugOrId?: string;
   originPathOverride?: string;
   includeContents?: boolean;
+  /** Per-file limit on old + new contents */
   maxBytes?: number;
+  /** Limit on contents across the response, spent in path order */
+  maxTotalBytes?: number;
   lastKnownBaseSha?: string;
   lastKnownMergeCommitSha?: string;
+  lastKnownHeadSha?: string;
+}
+
+export interface GitDiffResult {
+  /** Head and base are unchanged since the lastKnown* hints; entries is empty */
+  notModified: boolean;
+  entries: ReplaceDiffEntry[];
+  headSha?: string;
+  baseSha?: string;
+  mergeBaseSha?: string;
+}
+
+export interface GitDiffContentsOptions {
+  repoFullName?: string;
+  repoUrl?: string;
+  teamSlugOrId?: string;
+  originPathOverride?: string;
+  /** contentKey of each entry returned with contentOmitted */
+  keys: string[];
+  /** Keep files over this many bytes omitted */
+  maxBytes?: number;
+}
+
+export interface DiffContents {
+  key: string;
+  isBinary: boolean;
+  /** Still omitted: binary or over maxBytes */
+  contentOmitted: boolean;
+  oldContent?: string;
+  newContent?: string;
+  additions: number;
+  deletions: number;
+}
+
+export interface GitDiffOwnersOptions {
+  diff: GitDiffOptions;
+  /** Ref to read CODEOWNERS from; defaults to the diff's base */
+  codeownersRef?: string;
+}
+
+export interface FileOwners {
+  filePath: string;
+  owners: string[];
+  pattern?: string;
+  ruleLine?: number;
+}
+
+export interface GitCheckPoliciesOptions {
+  diff: GitDiffOptions;
+  /** Flag files whose new side is larger than this */
+  maxFileBytes?: number;
+  /** CODEOWNERS-style patterns no file may be added at or changed under */
+  forbiddenPaths?: string[];
+  /** Regexes matched against each added line */
+  secretPatterns?: string[];
+}
+
+export interface PolicyViolation {
+  filePath: string;
+  rule: "forbiddenPath" | "maxFileSize" | "secret";
+  message: string;
+  /** The forbidden path or secret pattern that matched */
+  pattern?: string;
+  /** Line of the new file a secret was found on */
+  line?: number;
+}
+
+export interface GitArchiveOptions {
+  /** Branch, tag or commit to archive */
+  refName: string;
+  repoFullName?: string;
+  repoUrl?: string;
+  teamSlugOrId?: string;
+  originPathOverride?: string;
+  /** Limit the archive to these files or directories */
+  paths?: string[];
+  format?: "tar" | "tar.gz" | "zip";
+  /** Directory prefix for every entry, e.g. "repo-main/" */
+  prefix?: string;
+  /** Write the archive to this file instead of returning it */
+  outputPath?: string;
+}
+
+export interface GitArchiveResult {
+  commitSha: string;
+  format: string;
+  /** Unset when written to outputPath */
+  data?: Buffer;
+  size: number;
+}
+
+export interface GitBisectPlanOptions {
+  /** Every commit tested good so far */
+  goodRefs: string[];
+  /** Most recent commit tested bad */
+  badRef: string;
+  repoFullName?: string;
+  repoUrl?: string;
+  teamSlugOrId?: string;
+  originPathOverride?: string;
+  /** Untestable commits; never suggested */
+  skipRefs?: string[];
+  /** Midpoints to return, best first (default 3) */
+  maxMidpoints?: number;
+}
+
+export interface BisectMidpoint {
+  sha: string;
+  subject: string;
+  remainingIfGood: number;
+  remainingIfBad: number;
+}
+
+export interface GitBisectPlan {
+  badSha: string;
+  goodShas: string[];
+  /** Commits that may still be the first bad one, including badSha */
+  candidateCount: number;
+  /** Tests still needed in the worst case */
+  estimatedSteps: number;
+  midpoints: BisectMidpoint[];
+  /** Set once bisection has converged */
+  firstBadSha?: string;
 }
 
 type NativeGitModule = {
   // napi-rs exports as camelCase
   gitDiff?: (opts: GitDiffOptions) => Promise<ReplaceDiffEntry[]>;
+  gitDiffIncremental?: (opts: GitDiffOptions) => Promise<GitDiffResult>;
+  gitDiffContents?: (opts: GitDiffContentsOptions) => Promise<DiffContents[]>;
+  gitDiffOwners?: (opts: GitDiffOwnersOptions) => Promise<FileOwners[]>;
+  gitCheckPolicies?: (
+    opts: GitCheckPoliciesOptions
+  ) => Promise<PolicyViolation[]>;
+  gitArchive?: (opts: GitArchiveOptions) => Promise<GitArchiveResult>;
+  gitBisectPlan?: (opts: GitBisectPlanOptions) => Promise<GitBisectPlan>;
+  gitSetTeamToken?: (teamSlugOrId: string, token: string | null) => void;
   gitListRemoteBranches?: (opts: {
     repoFullName?: string;
     repoUrl?: string;
+    teamSlugOrId?: string;
     originPathOverride?: string;
   }) => Promise<
     Array<{
@@ -94,6 +230,23 @@ export function loadNativeGit(): NativeGitModule | null {
   return cachedNative ?? null;
 }
 
+/**
+ * Token used when fetching a team's private repos (passed as teamSlugOrId).
+ * Pass null to clear it.
+ */
+export function setGitTeamToken(
+  teamSlugOrId: string,
+  token: string | null
+): void {
+  const mod = loadNativeGit();
+  if (!mod?.gitSetTeamToken) {
+    throw new Error(
+      "Native gitSetTeamToken not available; rebuild @cmux/native-core"
+    );
+  }
+  mod.gitSetTeamToken(teamSlugOrId, token);
+}
+
 export async function gitDiff(opts: GitDiffOptions): Promise<ReplaceDiffEntry[]> {
   const mod = loadNativeGit();
   if (!mod?.gitDiff) {
@@ -102,9 +255,80 @@ export async function gitDiff(opts: GitDiffOptions): Promise<ReplaceDiffEntry[]>
   return mod.gitDiff(opts);
 }
 
+export async function gitDiffIncremental(
+  opts: GitDiffOptions
+): Promise<GitDiffResult> {
+  const mod = loadNativeGit();
+  if (!mod?.gitDiffIncremental) {
+    throw new Error(
+      "Native gitDiffIncremental not available; rebuild @cmux/native-core"
+    );
+  }
+  return mod.gitDiffIncremental(opts);
+}
+
+export async function gitDiffContents(
+  opts: GitDiffContentsOptions
+): Promise<DiffContents[]> {
+  const mod = loadNativeGit();
+  if (!mod?.gitDiffContents) {
+    throw new Error(
+      "Native gitDiffContents not available; rebuild @cmux/native-core"
+    );
+  }
+  return mod.gitDiffContents(opts);
+}
+
+export async function gitDiffOwners(
+  opts: GitDiffOwnersOptions
+): Promise<FileOwners[]> {
+  const mod = loadNativeGit();
+  if (!mod?.gitDiffOwners) {
+    throw new Error(
+      "Native gitDiffOwners not available; rebuild @cmux/native-core"
+    );
+  }
+  return mod.gitDiffOwners(opts);
+}
+
+export async function gitCheckPolicies(
+  opts: GitCheckPoliciesOptions
+): Promise<PolicyViolation[]> {
+  const mod = loadNativeGit();
+  if (!mod?.gitCheckPolicies) {
+    throw new Error(
+      "Native gitCheckPolicies not available; rebuild @cmux/native-core"
+    );
+  }
+  return mod.gitCheckPolicies(opts);
+}
+
+export async function gitArchive(
+  opts: GitArchiveOptions
+): Promise<GitArchiveResult> {
+  const mod = loadNativeGit();
+  if (!mod?.gitArchive) {
+    throw new Error("Native gitArchive not available; rebuild @cmux/native-core");
+  }
+  return mod.gitArchive(opts);
+}
+
+export async function gitBisectPlan(
+  opts: GitBisectPlanOptions
+): Promise<GitBisectPlan> {
+  const mod = loadNativeGit();
+  if (!mod?.gitBisectPlan) {
+    throw new Error(
+      "Native gitBisectPlan not available; rebuild @cmux/native-core"
+    );
+  }
+  return mod.gitBisectPlan(opts);
+}
+
 export async function listRemoteBranches(opts: {
   repoFullName?: string;
   repoUrl?: string;
+  teamSlugOrId?: string;
   originPathOverride?: string;
 }): Promise<
   Array<{
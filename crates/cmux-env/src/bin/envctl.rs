@@ -1,17 +1,23 @@
-use std::fs::{self, File};
-use std::io::{self, Read};
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Context, Result};
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use cmux_env::{
-    client_send, client_send_autostart, parse_dotenv, parse_dotenv_base64, Request, Response,
-    Scope, ShellKind,
+    client_send, client_send_autostart, parse_dotenv, parse_dotenv_base64, Bundle, ChangeEvent,
+    Explanation, ImportRecord, KeyMatch, PromptStatus, Request, Response, Scope, ShellKind,
+    StepOutcome,
 };
+use serde_json::json;
 
 #[derive(Parser, Debug)]
 #[command(name = "envctl", version, about = "Client for cmux-envd")]
 struct Cli {
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -41,11 +47,26 @@ enum Commands {
         #[arg(long)]
         pwd: Option<PathBuf>,
     },
+    /// Show which scopes define KEY at PWD and which one wins
+    Explain {
+        key: String,
+        #[arg(long)]
+        pwd: Option<PathBuf>,
+    },
     /// List effective variables at PWD
     List {
         #[arg(long)]
         pwd: Option<PathBuf>,
     },
+    /// List effective keys starting with PREFIX (used by shell completion)
+    Keys {
+        #[arg(default_value = "")]
+        prefix: String,
+        #[arg(long)]
+        pwd: Option<PathBuf>,
+        #[arg(long, help = "Append a tab and the scope each key comes from")]
+        scopes: bool,
+    },
     /// Load .env from file or stdin (-). Optional --dir to scope to directory.
     Load {
         #[arg(value_name = "INPUT")]
@@ -60,11 +81,16 @@ enum Commands {
         shell: ShellType,
         #[arg(long, default_value_t = 0)]
         since: u64,
+        /// Export changes since GEN, applied only if ENVCTL_GEN is still GEN
+        #[arg(long, value_name = "GEN", conflicts_with = "since")]
+        expected_gen: Option<u64>,
         #[arg(long)]
         pwd: Option<PathBuf>,
     },
     /// Print hook for bash/zsh/fish
     Hook { shell: ShellType },
+    /// Print tab-completion script for bash/zsh/fish
+    Completions { shell: ShellType },
     /// Install hook into the user's shell rc file
     InstallHook {
         shell: ShellType,
@@ -73,10 +99,83 @@ enum Commands {
     },
     /// Show daemon status
     Status,
+    /// Print a one-line summary for shell prompts: the active directory
+    /// scope, "+N" for overridden keys and "*" when an export is pending
+    Prompt {
+        /// Generation the shell is at [default: $ENVCTL_GEN]
+        #[arg(long)]
+        since: Option<u64>,
+        #[arg(long)]
+        pwd: Option<PathBuf>,
+    },
+    /// Show recorded changes after generation SINCE
+    History {
+        #[arg(long, default_value_t = 0)]
+        since: u64,
+    },
+    /// Export or import scopes as a signed bundle
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
     /// Ping daemon
     Ping,
 }
 
+#[derive(Subcommand, Debug)]
+enum BundleCommands {
+    /// Write the variables of the selected scopes (all by default) as a
+    /// signed bundle
+    Export {
+        #[arg(long, help = "Include the global scope")]
+        global: bool,
+        #[arg(long, help = "Include this directory's scope (repeatable)")]
+        dir: Vec<PathBuf>,
+        #[command(flatten)]
+        key: KeyArgs,
+        #[arg(long, help = "Name the key in the bundle [default: its fingerprint]")]
+        key_id: Option<String>,
+        #[arg(short, long, help = "Write to FILE instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    /// Verify a bundle from file or stdin (-) and set its variables
+    Import {
+        #[arg(value_name = "INPUT")]
+        input: String,
+        #[command(flatten)]
+        key: KeyArgs,
+        #[arg(long, help = "Refuse bundles signed under another key id")]
+        key_id: Option<String>,
+    },
+    /// List imported bundles and the key ids they were signed with
+    Imports,
+}
+
+#[derive(clap::Args, Debug)]
+struct KeyArgs {
+    #[arg(
+        long,
+        help = "Read the signing key from FILE [default: $ENVCTL_BUNDLE_KEY]"
+    )]
+    key_file: Option<PathBuf>,
+}
+
+impl KeyArgs {
+    fn read(&self) -> Result<Vec<u8>> {
+        let key = match &self.key_file {
+            Some(path) => fs::read_to_string(path)
+                .with_context(|| format!("read key file {}", path.display()))?,
+            None => std::env::var("ENVCTL_BUNDLE_KEY")
+                .map_err(|_| anyhow!("no bundle key: pass --key-file or set ENVCTL_BUNDLE_KEY"))?,
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(anyhow!("bundle key is empty"));
+        }
+        Ok(key.as_bytes().to_vec())
+    }
+}
+
 #[derive(Copy, Clone, Debug, ValueEnum)]
 enum ShellType {
     Bash,
@@ -114,12 +213,203 @@ fn obfuscate_value(value: &str) -> String {
         .collect()
 }
 
+fn scope_label(scope: &Scope) -> String {
+    match scope {
+        Scope::Global => "global".to_string(),
+        Scope::Dir(dir) => dir.to_string_lossy().into_owned(),
+    }
+}
+
+fn prompt_line(status: &PromptStatus) -> String {
+    let mut parts = Vec::new();
+    if let Some(dir) = &status.active_scope {
+        let name = dir.file_name().unwrap_or(dir.as_os_str());
+        parts.push(name.to_string_lossy().into_owned());
+    }
+    if status.overridden > 0 {
+        parts.push(format!("+{}", status.overridden));
+    }
+    if status.dirty {
+        parts.push("*".to_string());
+    }
+    parts.join(" ")
+}
+
+fn outcome_label(outcome: StepOutcome) -> &'static str {
+    match outcome {
+        StepOutcome::Wins => "wins",
+        StepOutcome::Overridden => "overridden",
+        StepOutcome::Inactive => "inactive",
+        StepOutcome::NotDefined => "not set",
+    }
+}
+
+fn print_explanation(explanation: &Explanation) {
+    let winner = explanation
+        .steps
+        .iter()
+        .find(|step| step.outcome == StepOutcome::Wins);
+    match winner {
+        Some(step) => println!(
+            "{} at {}: set by {}",
+            explanation.key,
+            explanation.pwd.display(),
+            scope_label(&step.scope)
+        ),
+        None => println!(
+            "{} at {}: not set",
+            explanation.key,
+            explanation.pwd.display()
+        ),
+    }
+    for step in &explanation.steps {
+        let value = step
+            .value
+            .as_deref()
+            .map(obfuscate_value)
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "  {:<10}  {}  {}  ({})",
+            outcome_label(step.outcome),
+            scope_label(&step.scope),
+            value,
+            step.reason
+        );
+    }
+}
+
+// Bundles carry secrets, so they are only readable by their owner
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("open {}", path.display()))?;
+    f.write_all(contents)?;
+    Ok(())
+}
+
+fn bundle_command(command: BundleCommands, json: bool) -> Result<()> {
+    match command {
+        BundleCommands::Export {
+            global,
+            dir,
+            key,
+            key_id,
+            output,
+        } => {
+            let key = key.read()?;
+            let mut scopes: Vec<Scope> = dir.into_iter().map(Scope::Dir).collect();
+            if global {
+                scopes.insert(0, Scope::Global);
+            }
+            let scopes = (!scopes.is_empty()).then_some(scopes);
+            let resp = client_send_autostart(&Request::Scopes { scopes })?;
+            let Response::Scopes { scopes } = resp else {
+                return Err(anyhow!("unexpected response"));
+            };
+            let bundle = Bundle::sign(scopes, &key, key_id)?;
+            let mut out = serde_json::to_string_pretty(&bundle)?;
+            out.push('\n');
+            match output {
+                Some(path) => write_private(&path, out.as_bytes())?,
+                None => {
+                    print!("{}", out);
+                    return Ok(());
+                }
+            }
+            if json {
+                return print_json(json!({
+                    "ok": true,
+                    "key_id": bundle.key_id,
+                    "exported": bundle.len(),
+                }));
+            }
+            Ok(())
+        }
+        BundleCommands::Import { input, key, key_id } => {
+            let key = key.read()?;
+            let data = if input == "-" {
+                let mut buf = String::new();
+                io::stdin().read_to_string(&mut buf)?;
+                buf
+            } else {
+                fs::read_to_string(&input).with_context(|| format!("open {}", input))?
+            };
+            let bundle: Bundle = serde_json::from_str(&data).context("parse bundle")?;
+            if let Some(expected) = key_id.filter(|id| *id != bundle.key_id) {
+                return Err(anyhow!(
+                    "bundle is signed with key {}, expected {}",
+                    bundle.key_id,
+                    expected
+                ));
+            }
+            bundle.verify(&key)?;
+            let count = bundle.len();
+            let scopes = bundle.scopes.len();
+            let _ = client_send_autostart(&Request::Import {
+                key_id: bundle.key_id.clone(),
+                scopes: bundle.scopes,
+            })?;
+            if json {
+                return print_json(json!({
+                    "ok": true,
+                    "key_id": bundle.key_id,
+                    "imported": count,
+                }));
+            }
+            println!(
+                "imported {} variables in {} scopes (key {})",
+                count, scopes, bundle.key_id
+            );
+            Ok(())
+        }
+        BundleCommands::Imports => {
+            let resp = client_send_autostart(&Request::Imports)?;
+            let Response::Imports { imports } = resp else {
+                return Err(anyhow!("unexpected response"));
+            };
+            if json {
+                return print_json(json!({ "imports": imports }));
+            }
+            for ImportRecord {
+                generation,
+                key_id,
+                scopes,
+            } in imports
+            {
+                let scopes: Vec<String> = scopes.iter().map(scope_label).collect();
+                println!("{}\t{}\t{}", generation, key_id, scopes.join(","));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_json(value: serde_json::Value) -> Result<()> {
+    println!("{}", serde_json::to_string(&value)?);
+    Ok(())
+}
+
+/// Mutations print nothing in text mode; JSON callers still get a line to parse.
+fn print_done(json: bool) -> Result<()> {
+    if json {
+        print_json(json!({ "ok": true }))?;
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let json = cli.json;
     match cli.command {
+        Commands::Bundle { command } => bundle_command(command, json),
         Commands::Ping => {
             let resp = client_send(&Request::Ping)?;
             match resp {
+                Response::Pong if json => print_json(json!({ "pong": true })),
                 Response::Pong => {
                     println!("pong");
                     Ok(())
@@ -135,6 +425,13 @@ fn main() -> Result<()> {
                     globals,
                     scopes,
                 } => {
+                    if json {
+                        return print_json(json!({
+                            "generation": generation,
+                            "globals": globals,
+                            "scopes": scopes,
+                        }));
+                    }
                     println!("generation: {}", generation);
                     println!("globals: {}", globals);
                     println!("scopes: {}", scopes);
@@ -143,6 +440,59 @@ fn main() -> Result<()> {
                 _ => Err(anyhow!("unexpected response")),
             }
         }
+        Commands::Prompt { since, pwd } => {
+            let pwd = match pwd {
+                Some(pwd) => pwd,
+                None => std::env::current_dir()?,
+            };
+            let since = since
+                .or_else(|| std::env::var("ENVCTL_GEN").ok()?.parse().ok())
+                .unwrap_or(0);
+            // No autostart and no error: a prompt must render even when the
+            // daemon isn't running
+            let Ok(resp) = client_send(&Request::PromptStatus {
+                pwd: Some(pwd),
+                since,
+            }) else {
+                return Ok(());
+            };
+            match resp {
+                Response::PromptStatus { status } if json => {
+                    print_json(serde_json::to_value(&status)?)
+                }
+                Response::PromptStatus { status } => {
+                    let line = prompt_line(&status);
+                    if !line.is_empty() {
+                        println!("{}", line);
+                    }
+                    Ok(())
+                }
+                _ => Err(anyhow!("unexpected response")),
+            }
+        }
+        Commands::History { since } => {
+            let resp = client_send_autostart(&Request::History { since })?;
+            match resp {
+                Response::History { generation, events } => {
+                    if json {
+                        return print_json(json!({
+                            "generation": generation,
+                            "events": events,
+                        }));
+                    }
+                    for ChangeEvent {
+                        generation,
+                        key,
+                        scope,
+                    } in events
+                    {
+                        println!("{}\t{}\t{}", generation, key, scope_label(&scope));
+                    }
+                    Ok(())
+                }
+                _ => Err(anyhow!("unexpected response")),
+            }
+        }
         Commands::Set { kv, dir } => {
             let (key, val) = parse_kv(&kv)?;
             let scope = dir.map(Scope::Dir).unwrap_or(Scope::Global);
@@ -151,18 +501,36 @@ fn main() -> Result<()> {
                 value: val,
                 scope,
             })?;
-            Ok(())
+            print_done(json)
         }
         Commands::Unset { key, dir } => {
             let scope = dir.map(Scope::Dir).unwrap_or(Scope::Global);
             let _ = client_send_autostart(&Request::Unset { key, scope })?;
-            Ok(())
+            print_done(json)
         }
         Commands::Reset { dir } => {
             let scope = dir.map(Scope::Dir);
             let resp = client_send_autostart(&Request::Reset { scope })?;
             match resp {
-                Response::Ok => Ok(()),
+                Response::Ok => print_done(json),
+                _ => Err(anyhow!("unexpected response")),
+            }
+        }
+        Commands::Get { key, pwd } if json => {
+            let pwd = match pwd {
+                Some(pwd) => pwd,
+                None => std::env::current_dir()?,
+            };
+            let resp = client_send_autostart(&Request::Entries { pwd: Some(pwd) })?;
+            match resp {
+                Response::Entries { entries } => {
+                    let entry = entries.into_iter().find(|e| e.key == key);
+                    print_json(json!({
+                        "key": key,
+                        "value": entry.as_ref().map(|e| &e.value),
+                        "scope": entry.as_ref().map(|e| &e.scope),
+                    }))
+                }
                 _ => Err(anyhow!("unexpected response")),
             }
         }
@@ -185,6 +553,43 @@ fn main() -> Result<()> {
                 _ => Err(anyhow!("unexpected response")),
             }
         }
+        Commands::Explain { key, pwd } => {
+            let pwd = match pwd {
+                Some(pwd) => pwd,
+                None => std::env::current_dir()?,
+            };
+            let resp = client_send_autostart(&Request::Explain {
+                key,
+                pwd: Some(pwd),
+            })?;
+            match resp {
+                Response::Explain { explanation } if json => {
+                    print_json(serde_json::to_value(&explanation)?)
+                }
+                Response::Explain { explanation } => {
+                    print_explanation(&explanation);
+                    Ok(())
+                }
+                _ => Err(anyhow!("unexpected response")),
+            }
+        }
+        Commands::List { pwd } if json => {
+            let pwd = match pwd {
+                Some(pwd) => pwd,
+                None => std::env::current_dir()?,
+            };
+            let resp = client_send_autostart(&Request::Entries {
+                pwd: Some(pwd.clone()),
+            })?;
+            match resp {
+                // Values are not obfuscated: JSON output is for programs
+                Response::Entries { entries } => print_json(json!({
+                    "pwd": pwd,
+                    "entries": entries,
+                })),
+                _ => Err(anyhow!("unexpected response")),
+            }
+        }
         Commands::List { pwd } => {
             let pwd = match pwd {
                 Some(pwd) => pwd,
@@ -209,6 +614,39 @@ fn main() -> Result<()> {
                 _ => Err(anyhow!("unexpected response")),
             }
         }
+        Commands::Keys {
+            prefix,
+            pwd,
+            scopes,
+        } => {
+            let pwd = match pwd {
+                Some(pwd) => pwd,
+                None => std::env::current_dir()?,
+            };
+            // No autostart: completing against a daemon that isn't running
+            // should be instant and empty, not spawn one
+            let resp = client_send(&Request::Keys {
+                prefix,
+                pwd: Some(pwd),
+            })?;
+            match resp {
+                Response::Keys { keys } if json => print_json(json!({ "keys": keys })),
+                Response::Keys { keys } => {
+                    let mut out = String::new();
+                    for KeyMatch { key, scope } in keys {
+                        out.push_str(&key);
+                        if scopes {
+                            out.push('\t');
+                            out.push_str(&scope_label(&scope));
+                        }
+                        out.push('\n');
+                    }
+                    print!("{}", out);
+                    Ok(())
+                }
+                _ => Err(anyhow!("unexpected response")),
+            }
+        }
         Commands::Load { input, dir, base64 } => {
             let scope = dir.map(Scope::Dir).unwrap_or(Scope::Global);
             let entries = if base64 {
@@ -228,10 +666,19 @@ fn main() -> Result<()> {
                 let f = File::open(&input).with_context(|| format!("open {}", input))?;
                 parse_dotenv(f)?
             };
+            let count = entries.len();
             let _ = client_send_autostart(&Request::Load { entries, scope })?;
+            if json {
+                return print_json(json!({ "ok": true, "loaded": count }));
+            }
             Ok(())
         }
-        Commands::Export { shell, since, pwd } => {
+        Commands::Export {
+            shell,
+            since,
+            expected_gen,
+            pwd,
+        } => {
             let shell: ShellKind = shell.into();
             let pwd = pwd.unwrap_or(std::env::current_dir()?);
             // If --since not specified (0), try ENVCTL_GEN to provide a smoother UX
@@ -243,18 +690,32 @@ fn main() -> Result<()> {
             } else {
                 since
             };
-            let resp = client_send_autostart(&Request::Export { shell, since, pwd })?;
+            let resp = client_send_autostart(&Request::Export {
+                shell,
+                since,
+                pwd,
+                expected_gen,
+            })?;
             match resp {
                 Response::Export {
                     script,
-                    new_generation: _,
+                    new_generation,
                 } => {
+                    if json {
+                        return print_json(json!({
+                            "script": script,
+                            "generation": new_generation,
+                        }));
+                    }
                     print!("{}", script);
                     Ok(())
                 }
                 _ => Err(anyhow!("unexpected response")),
             }
         }
+        Commands::Hook { .. } | Commands::Completions { .. } if json => Err(anyhow!(
+            "--json is not supported for scripts; use the text output directly"
+        )),
         Commands::Hook { shell } => {
             match shell {
                 ShellType::Bash => print!("{}", hook_bash()),
@@ -263,14 +724,30 @@ fn main() -> Result<()> {
             }
             Ok(())
         }
+        Commands::Completions { shell } => {
+            match shell {
+                ShellType::Bash => print!("{}", completion_bash()),
+                ShellType::Zsh => print!("{}", completion_zsh()),
+                ShellType::Fish => print!("{}", completion_fish()),
+            }
+            Ok(())
+        }
         Commands::InstallHook { shell, rcfile } => {
-            install_hook(shell, rcfile)?;
+            let rc_path = install_hook(shell, rcfile)?;
+            if json {
+                return print_json(json!({ "shell": shell.as_str(), "rcfile": rc_path }));
+            }
+            println!(
+                "Installed envctl hook for {} at {}",
+                shell.as_str(),
+                rc_path.display()
+            );
             Ok(())
         }
     }
 }
 
-fn install_hook(shell: ShellType, rcfile: Option<PathBuf>) -> Result<()> {
+fn install_hook(shell: ShellType, rcfile: Option<PathBuf>) -> Result<PathBuf> {
     const START_MARKER: &str = "# >>> envctl hook >>>";
     const END_MARKER: &str = "# <<< envctl hook <<<";
 
@@ -330,13 +807,7 @@ fn install_hook(shell: ShellType, rcfile: Option<PathBuf>) -> Result<()> {
     fs::write(&rc_path, contents)
         .with_context(|| format!("writing rcfile {}", rc_path.display()))?;
 
-    println!(
-        "Installed envctl hook for {} at {}",
-        shell.as_str(),
-        rc_path.display()
-    );
-
-    Ok(())
+    Ok(rc_path)
 }
 
 fn default_rc_path(shell: ShellType) -> Result<PathBuf> {
@@ -367,7 +838,7 @@ fn hook_bash() -> String {
 # Apply env diffs safely (idempotent, uses ENVCTL_GEN)
 __envctl_apply() {
   local out
-  out="$(envctl export bash --since "${ENVCTL_GEN:-0}" --pwd "$PWD")" || return
+  out="$(envctl export bash --expected-gen "${ENVCTL_GEN:-0}" --pwd "$PWD")" || return
   eval "$out"
 }
 
@@ -425,7 +896,7 @@ fn hook_zsh() -> String {
 autoload -U add-zsh-hook
 envctl_preexec() {
   local out
-  out="$(envctl export zsh --since "${ENVCTL_GEN:-0}" --pwd "$PWD")" || return
+  out="$(envctl export zsh --expected-gen "${ENVCTL_GEN:-0}" --pwd "$PWD")" || return
   eval "$out"
 }
 add-zsh-hook preexec envctl_preexec
@@ -438,13 +909,94 @@ envctl_preexec
 fn hook_fish() -> String {
     r#"# envctl fish hook
 function __envctl_preexec --on-event fish_preexec
-  envctl export fish --since "$ENVCTL_GEN" --pwd "$PWD" | source
+  envctl export fish --expected-gen "$ENVCTL_GEN" --pwd "$PWD" | source
 end
 function __envctl_prompt --on-event fish_prompt
-  envctl export fish --since "$ENVCTL_GEN" --pwd "$PWD" | source
+  envctl export fish --expected-gen "$ENVCTL_GEN" --pwd "$PWD" | source
 end
 # Apply once at shell start
-envctl export fish --since "$ENVCTL_GEN" --pwd "$PWD" | source
+envctl export fish --expected-gen "$ENVCTL_GEN" --pwd "$PWD" | source
 "#
     .to_string()
 }
+
+fn subcommand_names() -> Vec<String> {
+    Cli::command()
+        .get_subcommands()
+        .map(|cmd| cmd.get_name().to_string())
+        .collect()
+}
+
+fn completion_bash() -> String {
+    format!(
+        r#"# envctl bash completion
+_envctl() {{
+  local cur="${{COMP_WORDS[COMP_CWORD]}}"
+  if (( COMP_CWORD == 1 )); then
+    COMPREPLY=( $(compgen -W "{subcommands}" -- "$cur") )
+    return
+  fi
+  case "${{COMP_WORDS[1]}}" in
+    get|unset|set|explain)
+      if (( COMP_CWORD == 2 )); then
+        local IFS=$'\n'
+        COMPREPLY=( $(envctl keys -- "$cur" 2>/dev/null) )
+      fi
+      ;;
+    export|hook|install-hook|completions)
+      if (( COMP_CWORD == 2 )); then
+        COMPREPLY=( $(compgen -W "bash zsh fish" -- "$cur") )
+      fi
+      ;;
+  esac
+}}
+complete -o default -F _envctl envctl
+"#,
+        subcommands = subcommand_names().join(" ")
+    )
+}
+
+fn completion_zsh() -> String {
+    format!(
+        r#"#compdef envctl
+# envctl zsh completion
+_envctl() {{
+  local -a keys
+  if (( CURRENT == 2 )); then
+    compadd -- {subcommands}
+    return
+  fi
+  case "$words[2]" in
+    get|unset|set|explain)
+      (( CURRENT == 3 )) || return
+      keys=(${{(f)"$(envctl keys --scopes -- "$PREFIX" 2>/dev/null)"}})
+      keys=("${{(@)keys//$'\t'/:}}")
+      _describe -t keys 'variable' keys
+      ;;
+    export|hook|install-hook|completions)
+      (( CURRENT == 3 )) && compadd -- bash zsh fish
+      ;;
+  esac
+}}
+if [ "$funcstack[1]" = "_envctl" ]; then
+  _envctl "$@"
+else
+  compdef _envctl envctl
+fi
+"#,
+        subcommands = subcommand_names().join(" ")
+    )
+}
+
+fn completion_fish() -> String {
+    let subcommands = subcommand_names().join(" ");
+    format!(
+        r#"# envctl fish completion
+complete -c envctl -f
+complete -c envctl -n "not __fish_seen_subcommand_from {subcommands}" -a "{subcommands}"
+complete -c envctl -n "__fish_seen_subcommand_from get unset set explain; and test (count (commandline -opc)) -eq 2" -a "(envctl keys --scopes -- (commandline -ct) 2>/dev/null)"
+complete -c envctl -n "__fish_seen_subcommand_from load" -F
+complete -c envctl -n "__fish_seen_subcommand_from export hook install-hook completions" -a "bash zsh fish"
+"#
+    )
+}
@@ -1,9 +1,11 @@
 use anyhow::{anyhow, Context, Result};
 use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
 use base64::Engine;
+use hmac::{Hmac, Mac};
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::io::{BufRead, BufReader, Cursor, Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
@@ -82,6 +84,24 @@ pub enum Request {
     List {
         pwd: Option<PathBuf>,
     },
+    /// Effective keys at `pwd` starting with `prefix`, for shell completion.
+    Keys {
+        prefix: String,
+        pwd: Option<PathBuf>,
+    },
+    /// Effective variables at `pwd` with the scope each value comes from.
+    Entries {
+        pwd: Option<PathBuf>,
+    },
+    /// Every scope that could supply `key` at `pwd` and which one wins.
+    Explain {
+        key: String,
+        pwd: Option<PathBuf>,
+    },
+    /// Recorded changes after generation `since`.
+    History {
+        since: u64,
+    },
     Load {
         entries: Vec<(String, String)>,
         scope: Scope,
@@ -93,6 +113,47 @@ pub enum Request {
         shell: ShellKind,
         since: u64,
         pwd: PathBuf,
+        /// Generation the shell is at; when set, it replaces `since` and the
+        /// script only applies if the shell's `ENVCTL_GEN` still matches.
+        #[serde(default)]
+        expected_gen: Option<u64>,
+    },
+    /// Apply all ops under a single generation bump.
+    Transaction {
+        ops: Vec<Op>,
+    },
+    /// Variables of the given scopes, or of every non-empty scope.
+    Scopes {
+        scopes: Option<Vec<Scope>>,
+    },
+    /// Set every variable of a verified [`Bundle`] under one generation and
+    /// record which key signed it.
+    Import {
+        key_id: String,
+        scopes: Vec<ScopeVars>,
+    },
+    /// Bundles imported so far, oldest first.
+    Imports,
+    /// A [`PromptStatus`] for a shell at generation `since` in `pwd`, cheap
+    /// enough to request on every prompt.
+    PromptStatus {
+        pwd: Option<PathBuf>,
+        since: u64,
+    },
+}
+
+/// A single mutation inside a `Request::Transaction`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Op {
+    Set {
+        key: String,
+        value: String,
+        scope: Scope,
+    },
+    Unset {
+        key: String,
+        scope: Scope,
     },
 }
 
@@ -112,15 +173,119 @@ pub enum Response {
     Map {
         entries: HashMap<String, String>,
     },
+    Keys {
+        keys: Vec<KeyMatch>,
+    },
+    Entries {
+        entries: Vec<EnvEntry>,
+    },
+    History {
+        generation: u64,
+        events: Vec<ChangeEvent>,
+    },
+    Explain {
+        explanation: Explanation,
+    },
     Export {
         script: String,
         new_generation: u64,
     },
+    Scopes {
+        scopes: Vec<ScopeVars>,
+    },
+    Imports {
+        imports: Vec<ImportRecord>,
+    },
+    PromptStatus {
+        status: PromptStatus,
+    },
     Error {
         message: String,
     },
 }
 
+/// A key offered for completion and the scope its effective value comes from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyMatch {
+    pub key: String,
+    pub scope: Scope,
+}
+
+/// An effective variable and the scope its value comes from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EnvEntry {
+    pub key: String,
+    pub value: String,
+    pub scope: Scope,
+}
+
+/// How `key` resolves at `pwd`: the effective value and every scope that
+/// took part, most specific first.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Explanation {
+    pub key: String,
+    pub pwd: PathBuf,
+    pub value: Option<String>,
+    /// The nearest directory scope enclosing `pwd`, the only one overlaid on
+    /// the globals.
+    pub active_scope: Option<PathBuf>,
+    pub steps: Vec<ExplainStep>,
+}
+
+/// What a shell prompt shows about the environment at a directory.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PromptStatus {
+    pub generation: u64,
+    /// A change after the shell's generation applies here, so its next
+    /// export will update variables
+    pub dirty: bool,
+    /// Global keys the active scope overrides
+    pub overridden: usize,
+    /// The nearest directory scope enclosing the directory, as in
+    /// [`Explanation::active_scope`]
+    pub active_scope: Option<PathBuf>,
+}
+
+/// One scope in an [`Explanation`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExplainStep {
+    pub scope: Scope,
+    /// Value this scope defines for the key, if any.
+    pub value: Option<String>,
+    pub outcome: StepOutcome,
+    pub reason: String,
+}
+
+/// The variables defined directly in one scope.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScopeVars {
+    pub scope: Scope,
+    pub vars: BTreeMap<String, String>,
+}
+
+/// A bundle applied by `Request::Import`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ImportRecord {
+    /// Generation after the import
+    pub generation: u64,
+    /// Key id the bundle was signed with
+    pub key_id: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepOutcome {
+    /// Supplies the effective value
+    Wins,
+    /// Defines the key, but the active scope overrides it
+    Overridden,
+    /// Defines the key, but a nearer directory scope is active instead
+    Inactive,
+    /// The active scope, which doesn't define the key
+    NotDefined,
+}
+
 fn read_json(stream: &mut UnixStream) -> Result<Request> {
     let mut reader = BufReader::new(stream);
     let mut line = String::new();
@@ -154,56 +319,94 @@ pub struct State {
     pub globals: HashMap<String, String>,
     pub scoped: HashMap<PathBuf, HashMap<String, String>>, // Dir -> (key -> value)
     pub history: Vec<ChangeEvent>,
+    pub imports: Vec<ImportRecord>,
 }
 
 impl State {
     pub fn set(&mut self, scope: Scope, key: String, value: String) -> bool {
+        match self.set_quiet(scope, key.clone(), value) {
+            Some(scope) => {
+                self.bump(key, scope);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn unset(&mut self, scope: Scope, key: String) -> bool {
+        match self.unset_quiet(scope, key.clone()) {
+            Some(scope) => {
+                self.bump(key, scope);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Apply a set without recording it; returns the canonical scope if it changed anything
+    fn set_quiet(&mut self, scope: Scope, key: String, value: String) -> Option<Scope> {
         match scope {
             Scope::Global => {
                 let changed = self.globals.get(&key) != Some(&value);
                 if changed {
-                    self.globals.insert(key.clone(), value);
-                    self.bump(key, Scope::Global);
+                    self.globals.insert(key, value);
                 }
-                changed
+                changed.then_some(Scope::Global)
             }
             Scope::Dir(path) => {
                 let path_c = canon(path);
                 let entry = self.scoped.entry(path_c.clone()).or_default();
                 let changed = entry.get(&key) != Some(&value);
                 if changed {
-                    entry.insert(key.clone(), value);
-                    self.bump(key, Scope::Dir(path_c));
+                    entry.insert(key, value);
                 }
-                changed
+                changed.then_some(Scope::Dir(path_c))
             }
         }
     }
 
-    pub fn unset(&mut self, scope: Scope, key: String) -> bool {
+    // Apply an unset without recording it; returns the canonical scope if the key existed
+    fn unset_quiet(&mut self, scope: Scope, key: String) -> Option<Scope> {
         match scope {
-            Scope::Global => {
-                let existed = self.globals.remove(&key).is_some();
-                if existed {
-                    self.bump(key, Scope::Global);
-                }
-                existed
-            }
+            Scope::Global => self.globals.remove(&key).map(|_| Scope::Global),
             Scope::Dir(path) => {
                 let path = canon(path);
-                if let Some(map) = self.scoped.get_mut(&path) {
-                    let existed = map.remove(&key).is_some();
-                    if existed {
-                        self.bump(key, Scope::Dir(path));
-                    }
-                    existed
-                } else {
-                    false
-                }
+                let existed = self
+                    .scoped
+                    .get_mut(&path)
+                    .is_some_and(|map| map.remove(&key).is_some());
+                existed.then_some(Scope::Dir(path))
             }
         }
     }
 
+    /// Apply every op and record all resulting changes under one generation,
+    /// so an Export never observes part of the batch.
+    pub fn transaction(&mut self, ops: Vec<Op>) -> bool {
+        let mut changes = Vec::new();
+        for op in ops {
+            let change = match op {
+                Op::Set { key, value, scope } => {
+                    self.set_quiet(scope, key.clone(), value).map(|s| (key, s))
+                }
+                Op::Unset { key, scope } => self.unset_quiet(scope, key.clone()).map(|s| (key, s)),
+            };
+            changes.extend(change);
+        }
+        if changes.is_empty() {
+            return false;
+        }
+        self.generation += 1;
+        for (key, scope) in changes {
+            self.history.push(ChangeEvent {
+                generation: self.generation,
+                key,
+                scope,
+            });
+        }
+        true
+    }
+
     fn bump(&mut self, key: String, scope: Scope) {
         self.generation += 1;
         // normalize dir scope to canonical form
@@ -219,9 +422,15 @@ impl State {
     }
 
     pub fn load(&mut self, scope: Scope, entries: Vec<(String, String)>) {
-        for (k, v) in entries {
-            self.set(scope.clone(), k, v);
-        }
+        let ops = entries
+            .into_iter()
+            .map(|(key, value)| Op::Set {
+                key,
+                value,
+                scope: scope.clone(),
+            })
+            .collect();
+        self.transaction(ops);
     }
 
     pub fn reset_globals(&mut self) -> bool {
@@ -285,6 +494,238 @@ impl State {
         self.globals.get(key).cloned()
     }
 
+    /// Effective keys at `pwd` that start with `prefix`, sorted by key.
+    /// Values are never cloned, so this stays cheap with thousands of vars.
+    pub fn keys_with_prefix(&self, prefix: &str, pwd: &Path) -> Vec<KeyMatch> {
+        let overlay = self.best_scope_for_pwd(pwd);
+        let mut keys: Vec<KeyMatch> = self
+            .globals
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .filter(|k| {
+                !overlay
+                    .as_ref()
+                    .is_some_and(|(_, vars)| vars.contains_key(*k))
+            })
+            .map(|k| KeyMatch {
+                key: k.clone(),
+                scope: Scope::Global,
+            })
+            .collect();
+        if let Some((dir, vars)) = &overlay {
+            keys.extend(
+                vars.keys()
+                    .filter(|k| k.starts_with(prefix))
+                    .map(|k| KeyMatch {
+                        key: k.clone(),
+                        scope: Scope::Dir(dir.clone()),
+                    }),
+            );
+        }
+        keys.sort_by(|a, b| a.key.cmp(&b.key));
+        keys
+    }
+
+    /// Effective variables at `pwd` with their provenance, sorted by key.
+    pub fn entries_for_pwd(&self, pwd: &Path) -> Vec<EnvEntry> {
+        let overlay = self.best_scope_for_pwd(pwd);
+        let mut entries: Vec<EnvEntry> = self
+            .globals
+            .iter()
+            .filter(|(k, _)| {
+                !overlay
+                    .as_ref()
+                    .is_some_and(|(_, vars)| vars.contains_key(*k))
+            })
+            .map(|(k, v)| EnvEntry {
+                key: k.clone(),
+                value: v.clone(),
+                scope: Scope::Global,
+            })
+            .collect();
+        if let Some((dir, vars)) = &overlay {
+            entries.extend(vars.iter().map(|(k, v)| EnvEntry {
+                key: k.clone(),
+                value: v.clone(),
+                scope: Scope::Dir(dir.clone()),
+            }));
+        }
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        entries
+    }
+
+    /// Explain how `key` resolves at `pwd`. Only the nearest enclosing
+    /// directory scope is overlaid on the globals, so a key set in an outer
+    /// directory is invisible below a nested scope that doesn't set it.
+    pub fn explain(&self, key: &str, pwd: &Path) -> Explanation {
+        let pwd = canon(pwd);
+        let active = self.best_scope_for_pwd(&pwd);
+        let active_dir = active.as_ref().map(|(dir, _)| dir.clone());
+        let active_value = active.as_ref().and_then(|(_, vars)| vars.get(key));
+
+        let mut enclosing: Vec<(&PathBuf, &HashMap<String, String>)> = self
+            .scoped
+            .iter()
+            .filter(|(dir, _)| is_ancestor(dir, &pwd))
+            .collect();
+        enclosing.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.components().count()));
+
+        let mut steps = Vec::new();
+        for (dir, vars) in enclosing {
+            let value = vars.get(key).cloned();
+            let is_active = active_dir.as_ref() == Some(dir);
+            let (outcome, reason) = match (is_active, &value) {
+                (true, Some(_)) => (
+                    StepOutcome::Wins,
+                    "nearest directory scope enclosing pwd".to_string(),
+                ),
+                (true, None) => (
+                    StepOutcome::NotDefined,
+                    "nearest directory scope enclosing pwd, but it doesn't set the key".to_string(),
+                ),
+                (false, Some(_)) => (
+                    StepOutcome::Inactive,
+                    format!(
+                        "only the nearest scope applies, and {} is nearer",
+                        active_dir
+                            .as_ref()
+                            .map(|d| d.display().to_string())
+                            .unwrap_or_default()
+                    ),
+                ),
+                (false, None) => continue,
+            };
+            steps.push(ExplainStep {
+                scope: Scope::Dir(dir.clone()),
+                value,
+                outcome,
+                reason,
+            });
+        }
+
+        if let Some(value) = self.globals.get(key) {
+            let (outcome, reason) = if active_value.is_some() {
+                (
+                    StepOutcome::Overridden,
+                    "overridden by the active directory scope".to_string(),
+                )
+            } else {
+                (
+                    StepOutcome::Wins,
+                    "no active directory scope sets the key".to_string(),
+                )
+            };
+            steps.push(ExplainStep {
+                scope: Scope::Global,
+                value: Some(value.clone()),
+                outcome,
+                reason,
+            });
+        }
+
+        Explanation {
+            key: key.to_string(),
+            value: self.get_effective(key, &pwd),
+            pwd,
+            active_scope: active_dir,
+            steps,
+        }
+    }
+
+    /// Variables defined directly in each of `scopes`, or in every scope
+    /// when `None`: globals first, then directories by path. Scopes without
+    /// variables are left out.
+    pub fn scope_vars(&self, scopes: Option<Vec<Scope>>) -> Vec<ScopeVars> {
+        let scopes = scopes.unwrap_or_else(|| {
+            let mut dirs: Vec<&PathBuf> = self.scoped.keys().collect();
+            dirs.sort();
+            std::iter::once(Scope::Global)
+                .chain(dirs.into_iter().map(|dir| Scope::Dir(dir.clone())))
+                .collect()
+        });
+        let mut out: Vec<ScopeVars> = Vec::new();
+        for scope in scopes {
+            let (scope, vars) = match scope {
+                Scope::Global => (Scope::Global, Some(&self.globals)),
+                Scope::Dir(dir) => {
+                    let dir = canon(dir);
+                    let vars = self.scoped.get(&dir);
+                    (Scope::Dir(dir), vars)
+                }
+            };
+            let Some(vars) = vars.filter(|vars| !vars.is_empty()) else {
+                continue;
+            };
+            if out.iter().any(|s| s.scope == scope) {
+                continue;
+            }
+            out.push(ScopeVars {
+                scope,
+                vars: vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            });
+        }
+        out
+    }
+
+    /// Set every variable in `scopes` under one generation, keeping the
+    /// other variables of those scopes, and record the import.
+    pub fn import(&mut self, key_id: String, scopes: Vec<ScopeVars>) {
+        let mut imported = Vec::new();
+        let mut ops = Vec::new();
+        for ScopeVars { scope, vars } in scopes {
+            imported.push(match &scope {
+                Scope::Global => Scope::Global,
+                Scope::Dir(dir) => Scope::Dir(canon(dir)),
+            });
+            ops.extend(vars.into_iter().map(|(key, value)| Op::Set {
+                key,
+                value,
+                scope: scope.clone(),
+            }));
+        }
+        self.transaction(ops);
+        self.imports.push(ImportRecord {
+            generation: self.generation,
+            key_id,
+            scopes: imported,
+        });
+    }
+
+    /// Changes recorded after generation `since`, oldest first.
+    pub fn history_since(&self, since: u64) -> Vec<ChangeEvent> {
+        self.history
+            .iter()
+            .filter(|e| e.generation > since)
+            .cloned()
+            .collect()
+    }
+
+    /// Summary of the environment at `pwd` for a shell at generation
+    /// `since`. Unlike an export, nothing is rendered and no value cloned.
+    pub fn prompt_status(&self, pwd: &Path, since: u64) -> PromptStatus {
+        let pwd = canon(pwd);
+        let dirty = self
+            .history
+            .iter()
+            .rev()
+            .take_while(|e| e.generation > since)
+            .any(|e| match &e.scope {
+                Scope::Global => true,
+                Scope::Dir(dir) => is_ancestor(dir, &pwd),
+            });
+        let active = self.best_scope_for_pwd(&pwd);
+        PromptStatus {
+            generation: self.generation,
+            dirty,
+            overridden: active.as_ref().map_or(0, |(_, vars)| {
+                vars.keys()
+                    .filter(|k| self.globals.contains_key(*k))
+                    .count()
+            }),
+            active_scope: active.map(|(dir, _)| dir),
+        }
+    }
+
     // Returns best matching directory scope (deepest ancestor) and its map
     fn best_scope_for_pwd(&self, pwd: &Path) -> Option<(PathBuf, &HashMap<String, String>)> {
         let pwd = canon(pwd);
@@ -331,6 +772,20 @@ impl State {
         let script = render_script(shell, &actions, new_gen);
         (script, new_gen)
     }
+
+    /// Compare-and-set export: the changes since `expected_gen`, in a script
+    /// that only applies while the shell's `ENVCTL_GEN` is still
+    /// `expected_gen`. A stale export evaluated after a newer one, or the same
+    /// script evaluated twice, is a no-op instead of reverting newer values.
+    pub fn export_if_current(
+        &self,
+        shell: ShellKind,
+        expected_gen: u64,
+        pwd: &Path,
+    ) -> (String, u64) {
+        let (script, new_gen) = self.export_since(shell.clone(), expected_gen, pwd);
+        (guard_script(shell, expected_gen, &script), new_gen)
+    }
 }
 
 fn is_ancestor(a: &Path, b: &Path) -> bool {
@@ -399,6 +854,21 @@ fn render_script(shell: ShellKind, actions: &[(String, Option<String>)], new_gen
     out
 }
 
+// Wrap `script` so it only runs while ENVCTL_GEN equals `expected_gen`
+fn guard_script(shell: ShellKind, expected_gen: u64, script: &str) -> String {
+    match shell {
+        ShellKind::Bash | ShellKind::Zsh => {
+            format!("if [ \"${{ENVCTL_GEN:-0}}\" = '{expected_gen}' ]; then\n{script}fi\n")
+        }
+        ShellKind::Fish if expected_gen == 0 => {
+            format!("if not set -q ENVCTL_GEN; or test \"$ENVCTL_GEN\" = 0\n{script}end\n")
+        }
+        ShellKind::Fish => {
+            format!("if test \"$ENVCTL_GEN\" = '{expected_gen}'\n{script}end\n")
+        }
+    }
+}
+
 fn is_valid_key(k: &str) -> bool {
     let first = k.chars().next();
     if !first
@@ -410,6 +880,100 @@ fn is_valid_key(k: &str) -> bool {
     k.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
 }
 
+// --------------- Bundles ---------------
+
+/// Format version written into new bundles.
+pub const BUNDLE_VERSION: u32 = 1;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Variables of several scopes, signed with HMAC-SHA256 so they can be
+/// moved between machines or kept in a secrets manager and checked before
+/// they are applied.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Bundle {
+    pub version: u32,
+    /// Names the signing key, so a bundle can be matched to the key that
+    /// verifies it without the key itself being stored
+    pub key_id: String,
+    pub scopes: Vec<ScopeVars>,
+    /// Base64 HMAC-SHA256 over everything else
+    pub signature: String,
+}
+
+// The signed fields, serialized in a fixed order
+#[derive(Serialize)]
+struct SignedPart<'a> {
+    version: u32,
+    key_id: &'a str,
+    scopes: &'a [ScopeVars],
+}
+
+impl Bundle {
+    /// Sign `scopes` with `key`. Without a `key_id` the key's fingerprint
+    /// is used.
+    pub fn sign(scopes: Vec<ScopeVars>, key: &[u8], key_id: Option<String>) -> Result<Self> {
+        let key_id = key_id.unwrap_or_else(|| key_fingerprint(key));
+        let mac = bundle_mac(BUNDLE_VERSION, &key_id, &scopes, key)?;
+        Ok(Self {
+            version: BUNDLE_VERSION,
+            key_id,
+            signature: BASE64_STANDARD.encode(mac.finalize().into_bytes()),
+            scopes,
+        })
+    }
+
+    /// Check the signature against `key` and that every key in the bundle
+    /// is a valid variable name.
+    pub fn verify(&self, key: &[u8]) -> Result<()> {
+        if self.version != BUNDLE_VERSION {
+            return Err(anyhow!("unsupported bundle version {}", self.version));
+        }
+        let signature = BASE64_STANDARD
+            .decode(self.signature.as_bytes())
+            .map_err(|e| anyhow!("invalid bundle signature: {}", e))?;
+        bundle_mac(self.version, &self.key_id, &self.scopes, key)?
+            .verify_slice(&signature)
+            .map_err(|_| anyhow!("bundle signature does not match key {}", self.key_id))?;
+        for ScopeVars { vars, .. } in &self.scopes {
+            if let Some(k) = vars.keys().find(|k| !is_valid_key(k)) {
+                return Err(anyhow!("invalid key in bundle: {}", k));
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of variables across all scopes.
+    pub fn len(&self) -> usize {
+        self.scopes.iter().map(|s| s.vars.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn bundle_mac(version: u32, key_id: &str, scopes: &[ScopeVars], key: &[u8]) -> Result<HmacSha256> {
+    if key.is_empty() {
+        return Err(anyhow!("empty bundle key"));
+    }
+    let payload = serde_json::to_vec(&SignedPart {
+        version,
+        key_id,
+        scopes,
+    })?;
+    let mut mac = HmacSha256::new_from_slice(key).map_err(|e| anyhow!("bundle key: {}", e))?;
+    mac.update(&payload);
+    Ok(mac)
+}
+
+/// Default key id: `sha256:` and the first 16 hex digits of the key's hash.
+pub fn key_fingerprint(key: &[u8]) -> String {
+    let digest = Sha256::digest(key);
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
 // --------------- Server plumbing ---------------
 
 pub fn run_server() -> Result<()> {
@@ -468,6 +1032,26 @@ fn handle_request(req: Request, state: &Arc<Mutex<State>>) -> Response {
             let entries = st.effective_for_pwd(&pwd);
             Response::Map { entries }
         }
+        Request::Keys { prefix, pwd } => {
+            let pwd = resolve_pwd(pwd);
+            let keys = st.keys_with_prefix(&prefix, &pwd);
+            Response::Keys { keys }
+        }
+        Request::Entries { pwd } => {
+            let pwd = resolve_pwd(pwd);
+            let entries = st.entries_for_pwd(&pwd);
+            Response::Entries { entries }
+        }
+        Request::Explain { key, pwd } => {
+            let pwd = resolve_pwd(pwd);
+            Response::Explain {
+                explanation: st.explain(&key, &pwd),
+            }
+        }
+        Request::History { since } => Response::History {
+            generation: st.generation,
+            events: st.history_since(since),
+        },
         Request::Load { entries, scope } => {
             st.load(scope, entries);
             Response::Ok
@@ -486,8 +1070,36 @@ fn handle_request(req: Request, state: &Arc<Mutex<State>>) -> Response {
             }
             Response::Ok
         }
-        Request::Export { shell, since, pwd } => {
-            let (script, new_generation) = st.export_since(shell, since, &pwd);
+        Request::Transaction { ops } => {
+            st.transaction(ops);
+            Response::Ok
+        }
+        Request::Scopes { scopes } => Response::Scopes {
+            scopes: st.scope_vars(scopes),
+        },
+        Request::Import { key_id, scopes } => {
+            st.import(key_id, scopes);
+            Response::Ok
+        }
+        Request::Imports => Response::Imports {
+            imports: st.imports.clone(),
+        },
+        Request::PromptStatus { pwd, since } => {
+            let pwd = resolve_pwd(pwd);
+            Response::PromptStatus {
+                status: st.prompt_status(&pwd, since),
+            }
+        }
+        Request::Export {
+            shell,
+            since,
+            pwd,
+            expected_gen,
+        } => {
+            let (script, new_generation) = match expected_gen {
+                Some(expected) => st.export_if_current(shell, expected, &pwd),
+                None => st.export_since(shell, since, &pwd),
+            };
             Response::Export {
                 script,
                 new_generation,
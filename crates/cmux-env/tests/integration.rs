@@ -326,6 +326,32 @@ fn export_then_eval_in_bash_updates_env() {
     let _ = child.wait();
 }
 
+#[test]
+fn stale_compare_and_set_export_is_a_no_op() {
+    use cmux_env::{Scope, ShellKind, State};
+
+    let mut state = State::default();
+    state.set(Scope::Global, "A".into(), "old".into());
+    let (stale, _) = state.export_if_current(ShellKind::Bash, 0, std::path::Path::new("/"));
+    state.set(Scope::Global, "A".into(), "new".into());
+    let (fresh, gen) = state.export_if_current(ShellKind::Bash, 0, std::path::Path::new("/"));
+
+    // The newer export wins even when the older one is evaluated last, and
+    // evaluating a script twice changes nothing
+    let out = Command::new("bash")
+        .arg("-c")
+        .arg(format!(
+            "unset ENVCTL_GEN\n{fresh}{fresh}{stale}echo \"$A $ENVCTL_GEN\""
+        ))
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout).trim(),
+        format!("new {gen}")
+    );
+}
+
 #[test]
 fn multi_line_value_round_trip_via_export() {
     let tmp = TempDir::new().unwrap();
@@ -815,3 +841,446 @@ fn load_from_base64_invalid_payload_fails() {
     let _ = child.kill();
     let _ = child.wait();
 }
+
+#[test]
+fn transaction_applies_under_one_generation() {
+    use cmux_env::{Op, Scope, ShellKind, State};
+
+    let mut state = State::default();
+    state.set(Scope::Global, "OLD".into(), "1".into());
+    let before = state.generation;
+
+    let changed = state.transaction(vec![
+        Op::Set {
+            key: "A".into(),
+            value: "1".into(),
+            scope: Scope::Global,
+        },
+        Op::Set {
+            key: "B".into(),
+            value: "2".into(),
+            scope: Scope::Global,
+        },
+        Op::Unset {
+            key: "OLD".into(),
+            scope: Scope::Global,
+        },
+        Op::Unset {
+            key: "MISSING".into(),
+            scope: Scope::Global,
+        },
+    ]);
+    assert!(changed);
+    assert_eq!(state.generation, before + 1);
+
+    let (script, gen) = state.export_since(ShellKind::Bash, before, std::path::Path::new("/"));
+    assert_eq!(gen, before + 1);
+    assert_eq!(
+        script,
+        format!("export A='1'\nexport B='2'\nunset -v OLD\nexport ENVCTL_GEN={gen}\n")
+    );
+
+    // A no-op transaction leaves the generation alone
+    assert!(!state.transaction(vec![Op::Set {
+        key: "A".into(),
+        value: "1".into(),
+        scope: Scope::Global,
+    }]));
+    assert_eq!(state.generation, before + 1);
+}
+
+#[test]
+fn keys_complete_by_prefix_with_scope() {
+    let tmp = TempDir::new().unwrap();
+    let mut child = start_envd_with_runtime(&tmp);
+
+    let proj = tmp.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    let proj_c = proj.canonicalize().unwrap();
+    let proj_s = proj.to_str().unwrap();
+
+    let dotenv: String = (0..2000).map(|i| format!("BULK_{i}=v\n")).collect();
+    let encoded = BASE64_STANDARD.encode(dotenv);
+    run_envctl(&tmp, &["load", "--base64", &encoded]).success();
+    run_envctl(&tmp, &["set", "DATABASE_URL=x"]).success();
+    run_envctl(&tmp, &["set", "DATA_DIR=global"]).success();
+    run_envctl(&tmp, &["set", "DATA_DIR=local", "--dir", proj_s]).success();
+
+    run_envctl(&tmp, &["keys", "DATA", "--scopes", "--pwd", proj_s])
+        .success()
+        .stdout(format!(
+            "DATABASE_URL\tglobal\nDATA_DIR\t{}\n",
+            proj_c.display()
+        ));
+    run_envctl(&tmp, &["keys", "BULK_199", "--pwd", "/"])
+        .success()
+        .stdout("BULK_199\nBULK_1990\nBULK_1991\nBULK_1992\nBULK_1993\nBULK_1994\nBULK_1995\nBULK_1996\nBULK_1997\nBULK_1998\nBULK_1999\n");
+
+    // The generated bash completion calls back into `envctl keys`
+    let bin_dir = cargo_bin("envctl").parent().unwrap().to_path_buf();
+    let script = r#"eval "$(envctl completions bash)"
+COMP_WORDS=(envctl get DATA); COMP_CWORD=2; _envctl; printf '%s\n' "${COMPREPLY[@]}"
+COMP_WORDS=(envctl install-h); COMP_CWORD=1; _envctl; printf '%s\n' "${COMPREPLY[@]}""#;
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg(script)
+        .env("XDG_RUNTIME_DIR", tmp.path())
+        .env(
+            "PATH",
+            format!("{}:{}", bin_dir.display(), std::env::var("PATH").unwrap()),
+        )
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "DATABASE_URL\nDATA_DIR\ninstall-hook\n"
+    );
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[test]
+fn json_output_reports_scope_provenance() {
+    let tmp = TempDir::new().unwrap();
+    let mut child = start_envd_with_runtime(&tmp);
+
+    let proj = tmp.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    let proj_c = proj.canonicalize().unwrap();
+    let proj_s = proj.to_str().unwrap();
+
+    run_envctl(&tmp, &["set", "FOO=global", "--json"])
+        .success()
+        .stdout("{\"ok\":true}\n");
+    run_envctl(&tmp, &["set", "BAR=one"]).success();
+    run_envctl(&tmp, &["set", "FOO=local", "--dir", proj_s]).success();
+
+    let parse = |args: &[&str]| -> serde_json::Value {
+        let out = run_envctl(&tmp, args).success().get_output().stdout.clone();
+        serde_json::from_slice(&out).unwrap()
+    };
+    let dir_scope = serde_json::json!({"type": "Dir", "path": proj_c});
+
+    assert_eq!(
+        parse(&["get", "FOO", "--pwd", proj_s, "--json"]),
+        serde_json::json!({"key": "FOO", "value": "local", "scope": dir_scope})
+    );
+    assert_eq!(
+        parse(&["--json", "get", "MISSING", "--pwd", proj_s]),
+        serde_json::json!({"key": "MISSING", "value": null, "scope": null})
+    );
+    assert_eq!(
+        parse(&["list", "--pwd", proj_s, "--json"]),
+        serde_json::json!({
+            "pwd": proj_s,
+            "entries": [
+                {"key": "BAR", "value": "one", "scope": {"type": "Global"}},
+                {"key": "FOO", "value": "local", "scope": dir_scope},
+            ],
+        })
+    );
+    assert_eq!(
+        parse(&["status", "--json"]),
+        serde_json::json!({"generation": 3, "globals": 2, "scopes": 1})
+    );
+    assert_eq!(
+        parse(&["history", "--since", "1", "--json"]),
+        serde_json::json!({
+            "generation": 3,
+            "events": [
+                {"generation": 2, "key": "BAR", "scope": {"type": "Global"}},
+                {"generation": 3, "key": "FOO", "scope": dir_scope},
+            ],
+        })
+    );
+    run_envctl(&tmp, &["history", "--since", "2"])
+        .success()
+        .stdout(format!("3\tFOO\t{}\n", proj_c.display()));
+    run_envctl(&tmp, &["hook", "bash", "--json"]).failure();
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[test]
+fn explain_shows_which_scope_wins() {
+    let tmp = TempDir::new().unwrap();
+    let mut child = start_envd_with_runtime(&tmp);
+
+    let proj = tmp.path().join("proj");
+    let sub = proj.join("sub");
+    std::fs::create_dir_all(&sub).unwrap();
+    let (proj_c, sub_c) = (proj.canonicalize().unwrap(), sub.canonicalize().unwrap());
+    let (proj_s, sub_s) = (proj.to_str().unwrap(), sub.to_str().unwrap());
+
+    run_envctl(&tmp, &["set", "DATABASE_URL=global"]).success();
+    run_envctl(&tmp, &["set", "DATABASE_URL=proj", "--dir", proj_s]).success();
+    run_envctl(&tmp, &["set", "OTHER=x", "--dir", sub_s]).success();
+
+    let parse = |args: &[&str]| -> serde_json::Value {
+        let out = run_envctl(&tmp, args).success().get_output().stdout.clone();
+        serde_json::from_slice(&out).unwrap()
+    };
+
+    // Inside proj the directory value wins over the global one
+    let explained = parse(&["explain", "DATABASE_URL", "--pwd", proj_s, "--json"]);
+    assert_eq!(explained["value"], "proj");
+    let outcomes: Vec<_> = explained["steps"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["outcome"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(outcomes, ["wins", "overridden"]);
+
+    // In the nested scope, proj's value is hidden: only the nearest scope applies
+    let explained = parse(&["explain", "DATABASE_URL", "--pwd", sub_s, "--json"]);
+    assert_eq!(explained["value"], "global");
+    assert_eq!(explained["active_scope"], serde_json::json!(sub_c));
+    let steps = explained["steps"].as_array().unwrap();
+    assert_eq!(
+        steps
+            .iter()
+            .map(|s| (s["scope"].clone(), s["outcome"].as_str().unwrap()))
+            .collect::<Vec<_>>(),
+        [
+            (
+                serde_json::json!({"type": "Dir", "path": sub_c}),
+                "not_defined"
+            ),
+            (
+                serde_json::json!({"type": "Dir", "path": proj_c}),
+                "inactive"
+            ),
+            (serde_json::json!({"type": "Global"}), "wins"),
+        ]
+    );
+
+    run_envctl(&tmp, &["explain", "DATABASE_URL", "--pwd", sub_s])
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "DATABASE_URL at {}: set by global\n",
+            sub_c.display()
+        )))
+        .stdout(predicate::str::contains("inactive"))
+        .stdout(predicate::str::contains("proj").and(predicate::str::contains("****")));
+    run_envctl(&tmp, &["explain", "MISSING", "--pwd", "/"])
+        .success()
+        .stdout("MISSING at /: not set\n");
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[test]
+fn bundle_round_trip_verifies_signature_and_records_key_id() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let mut src_envd = start_envd_with_runtime(&src);
+    let mut dst_envd = start_envd_with_runtime(&dst);
+
+    let proj = src.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    let proj_c = proj.canonicalize().unwrap();
+    let proj_s = proj.to_str().unwrap();
+    let key_file = src.path().join("bundle.key");
+    std::fs::write(&key_file, "s3cret\n").unwrap();
+    let key_s = key_file.to_str().unwrap();
+    let bundle_file = src.path().join("env.bundle");
+    let bundle_s = bundle_file.to_str().unwrap();
+
+    run_envctl(&src, &["set", "FOO=global"]).success();
+    run_envctl(&src, &["set", "LOCAL=it's here", "--dir", proj_s]).success();
+    run_envctl(
+        &src,
+        &["set", "OTHER=skip", "--dir", src.path().to_str().unwrap()],
+    )
+    .success();
+
+    let out = run_envctl(
+        &src,
+        &[
+            "bundle",
+            "export",
+            "--global",
+            "--dir",
+            proj_s,
+            "--key-file",
+            key_s,
+            "--key-id",
+            "ci",
+            "-o",
+            bundle_s,
+            "--json",
+        ],
+    )
+    .success()
+    .get_output()
+    .stdout
+    .clone();
+    let out: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(
+        out,
+        serde_json::json!({"ok": true, "key_id": "ci", "exported": 2})
+    );
+    let mode = std::fs::metadata(&bundle_file)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let bundle: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&bundle_file).unwrap()).unwrap();
+    assert_eq!(
+        bundle["scopes"],
+        serde_json::json!([
+            {"scope": {"type": "Global"}, "vars": {"FOO": "global"}},
+            {"scope": {"type": "Dir", "path": proj_c}, "vars": {"LOCAL": "it's here"}},
+        ])
+    );
+
+    // A wrong key, a different key id or a tampered value is refused
+    // before anything is applied
+    let wrong_key = dst.path().join("wrong.key");
+    std::fs::write(&wrong_key, "other").unwrap();
+    run_envctl(
+        &dst,
+        &[
+            "bundle",
+            "import",
+            bundle_s,
+            "--key-file",
+            wrong_key.to_str().unwrap(),
+        ],
+    )
+    .failure()
+    .stderr(predicate::str::contains("signature does not match key ci"));
+    run_envctl(
+        &dst,
+        &[
+            "bundle",
+            "import",
+            bundle_s,
+            "--key-file",
+            key_s,
+            "--key-id",
+            "prod",
+        ],
+    )
+    .failure()
+    .stderr(predicate::str::contains(
+        "signed with key ci, expected prod",
+    ));
+    let tampered = dst.path().join("tampered.bundle");
+    std::fs::write(
+        &tampered,
+        std::fs::read_to_string(&bundle_file)
+            .unwrap()
+            .replace("\"global\"", "\"evil\""),
+    )
+    .unwrap();
+    run_envctl(
+        &dst,
+        &[
+            "bundle",
+            "import",
+            tampered.to_str().unwrap(),
+            "--key-file",
+            key_s,
+        ],
+    )
+    .failure();
+    run_envctl(&dst, &["status", "--json"])
+        .success()
+        .stdout("{\"generation\":0,\"globals\":0,\"scopes\":0}\n");
+
+    let mut cmd = Command::cargo_bin("envctl").unwrap();
+    cmd.env("XDG_RUNTIME_DIR", dst.path())
+        .env("ENVCTL_BUNDLE_KEY", "s3cret")
+        .args(["bundle", "import", "-", "--key-id", "ci"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    let mut ch = cmd.spawn().unwrap();
+    use std::io::Write;
+    ch.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(&std::fs::read(&bundle_file).unwrap())
+        .unwrap();
+    let out = ch.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "imported 2 variables in 2 scopes (key ci)\n"
+    );
+
+    run_envctl(&dst, &["get", "FOO"])
+        .success()
+        .stdout("global\n");
+    run_envctl(&dst, &["get", "LOCAL", "--pwd", proj_s])
+        .success()
+        .stdout("it's here\n");
+    run_envctl(&dst, &["get", "OTHER", "--pwd", proj_s])
+        .success()
+        .stdout("");
+    run_envctl(&dst, &["bundle", "imports"])
+        .success()
+        .stdout(format!("1\tci\tglobal,{}\n", proj_c.display()));
+
+    let _ = src_envd.kill();
+    let _ = src_envd.wait();
+    let _ = dst_envd.kill();
+    let _ = dst_envd.wait();
+}
+
+#[test]
+fn prompt_summarizes_scope_overrides_and_pending_changes() {
+    let tmp = TempDir::new().unwrap();
+    let proj = tmp.path().join("proj");
+    let other = tmp.path().join("other");
+    std::fs::create_dir_all(&proj).unwrap();
+    std::fs::create_dir_all(&other).unwrap();
+    let (proj_s, other_s) = (proj.to_str().unwrap(), other.to_str().unwrap());
+
+    // Without a daemon the prompt stays empty rather than failing or starting one
+    run_envctl(&tmp, &["prompt", "--pwd", proj_s, "--since", "0"])
+        .success()
+        .stdout("");
+    assert!(!tmp.path().join("cmux-envd/envd.sock").exists());
+
+    let mut child = start_envd_with_runtime(&tmp);
+    run_envctl(&tmp, &["set", "A=1"]).success();
+    run_envctl(&tmp, &["set", "A=2", "--dir", proj_s]).success();
+    run_envctl(&tmp, &["set", "B=3", "--dir", proj_s]).success();
+    run_envctl(&tmp, &["set", "C=4", "--dir", other_s]).success();
+
+    run_envctl(&tmp, &["prompt", "--pwd", proj_s, "--since", "0"])
+        .success()
+        .stdout("proj +1 *\n");
+    // The last change was in another directory, so nothing is pending here
+    run_envctl(&tmp, &["prompt", "--pwd", proj_s, "--since", "3"])
+        .success()
+        .stdout("proj +1\n");
+    run_envctl(&tmp, &["prompt", "--pwd", other_s, "--since", "3"])
+        .success()
+        .stdout("other *\n");
+
+    let out = run_envctl(&tmp, &["prompt", "--pwd", proj_s, "--since", "4", "--json"])
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let status: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(
+        status,
+        serde_json::json!({
+            "generation": 4,
+            "dirty": false,
+            "overridden": 1,
+            "active_scope": proj.canonicalize().unwrap(),
+        })
+    );
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
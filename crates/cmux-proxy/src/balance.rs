@@ -0,0 +1,210 @@
+//! Replica selection for ports served by more than one upstream.
+//!
+//! A port can be mapped to several replica ports. Requests that carry an
+//! affinity key stick to one replica: the `cmux_affinity` cookie pins the
+//! replica port chosen earlier, and the `X-Cmux-Affinity` header is hashed onto
+//! the replica set. Requests without either are spread round-robin, and HTTP
+//! responses then set the cookie so the client (and its websockets) stay put.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use http::header::COOKIE;
+use http::HeaderMap;
+
+pub const AFFINITY_COOKIE: &str = "cmux_affinity";
+pub const AFFINITY_HEADER: &str = "X-Cmux-Affinity";
+
+#[derive(Debug)]
+struct ReplicaSet {
+    ports: Vec<u16>,
+    next: AtomicUsize,
+}
+
+/// Replica ports keyed by the port clients ask for. Cheap to clone; clones share
+/// round-robin state.
+#[derive(Clone, Debug, Default)]
+pub struct Replicas(Arc<HashMap<u16, ReplicaSet>>);
+
+/// Result of picking a replica for a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pick {
+    pub port: u16,
+    /// True when the replica was chosen fresh and the client should be told to
+    /// stick to it via the affinity cookie.
+    pub set_cookie: bool,
+}
+
+impl Replicas {
+    pub fn new(map: HashMap<u16, Vec<u16>>) -> Self {
+        let sets = map
+            .into_iter()
+            .filter(|(_, ports)| !ports.is_empty())
+            .map(|(port, ports)| {
+                let set = ReplicaSet {
+                    ports,
+                    next: AtomicUsize::new(0),
+                };
+                (port, set)
+            })
+            .collect();
+        Self(Arc::new(sets))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Choose the upstream port for a request addressed to `port`.
+    pub fn pick(&self, port: u16, headers: &HeaderMap) -> Pick {
+        let Some(set) = self.0.get(&port) else {
+            return Pick {
+                port,
+                set_cookie: false,
+            };
+        };
+
+        if let Some(pinned) = affinity_cookie(headers).filter(|p| set.ports.contains(p)) {
+            return Pick {
+                port: pinned,
+                set_cookie: false,
+            };
+        }
+
+        if let Some(key) = headers
+            .get(AFFINITY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+        {
+            let idx = (fnv1a(key.as_bytes()) as usize) % set.ports.len();
+            return Pick {
+                port: set.ports[idx],
+                set_cookie: false,
+            };
+        }
+
+        let idx = set.next.fetch_add(1, Ordering::Relaxed) % set.ports.len();
+        Pick {
+            port: set.ports[idx],
+            set_cookie: true,
+        }
+    }
+}
+
+impl FromStr for Replicas {
+    type Err = String;
+
+    /// Parse `PORT=R1,R2[;PORT=R1,R2...]`, e.g. `3000=3000,3001;8080=8081,8082`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = HashMap::new();
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (port, replicas) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected PORT=R1,R2 but got {entry:?}"))?;
+            let port: u16 = port
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid port in {entry:?}"))?;
+            let replicas = replicas
+                .split(',')
+                .map(|p| p.trim().parse::<u16>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("invalid replica port in {entry:?}"))?;
+            if replicas.is_empty() {
+                return Err(format!("no replicas listed for port {port}"));
+            }
+            map.insert(port, replicas);
+        }
+        Ok(Self::new(map))
+    }
+}
+
+/// `Set-Cookie` value pinning a client to `port`.
+pub fn affinity_set_cookie(port: u16) -> String {
+    format!("{AFFINITY_COOKIE}={port}; Path=/; HttpOnly; SameSite=Lax")
+}
+
+fn affinity_cookie(headers: &HeaderMap) -> Option<u16> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == AFFINITY_COOKIE).then(|| value.trim().parse().ok())?
+        })
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut h: u32 = 0x811C9DC5;
+    for b in bytes {
+        h ^= *b as u32;
+        h = h.wrapping_mul(0x01000193);
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn replicas() -> Replicas {
+        "3000=3001,3002,3003".parse().unwrap()
+    }
+
+    #[test]
+    fn round_robins_without_affinity() {
+        let r = replicas();
+        let headers = HeaderMap::new();
+        let ports: Vec<u16> = (0..4).map(|_| r.pick(3000, &headers).port).collect();
+        assert_eq!(ports, vec![3001, 3002, 3003, 3001]);
+        assert!(r.pick(3000, &headers).set_cookie);
+
+        // Ports without replicas pass straight through
+        assert_eq!(
+            r.pick(8080, &headers),
+            Pick {
+                port: 8080,
+                set_cookie: false
+            }
+        );
+    }
+
+    #[test]
+    fn sticks_to_cookie_and_header() {
+        let r = replicas();
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("a=b; cmux_affinity=3003"));
+        for _ in 0..3 {
+            assert_eq!(
+                r.pick(3000, &headers),
+                Pick {
+                    port: 3003,
+                    set_cookie: false
+                }
+            );
+        }
+
+        // A stale cookie for a port outside the set is ignored
+        headers.insert(COOKIE, HeaderValue::from_static("cmux_affinity=9999"));
+        assert!(r.pick(3000, &headers).set_cookie);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AFFINITY_HEADER, HeaderValue::from_static("session-42"));
+        let first = r.pick(3000, &headers).port;
+        assert!((0..5).all(|_| r.pick(3000, &headers).port == first));
+    }
+
+    #[test]
+    fn parses_replica_spec() {
+        assert!("3000=3001,3002;8080=8081".parse::<Replicas>().is_ok());
+        assert!("3000".parse::<Replicas>().is_err());
+        assert!("3000=abc".parse::<Replicas>().is_err());
+        assert!("".parse::<Replicas>().unwrap().is_empty());
+    }
+}
@@ -26,15 +26,38 @@ use tokio::sync::Notify;
 use tokio::task::{JoinHandle, JoinSet};
 use tracing::{error, info, warn};
 
-use http::header::{CONNECTION, HOST, UPGRADE};
+use http::header::{ACCEPT_ENCODING, CONNECTION, CONTENT_LENGTH, HOST, SET_COOKIE, UPGRADE};
+
+mod balance;
+mod port_fallback;
+mod rewrite;
+mod sniff;
+mod upstream_hosts;
+mod upstream_override;
+mod upstream_tls;
+pub use balance::Replicas;
+use balance::{affinity_set_cookie, Pick};
+pub use port_fallback::PortFallback;
+pub use rewrite::Rewrites;
+use rewrite::{OriginRewriter, PublicOrigin, RewriteBody};
+use sniff::Protocol;
+use upstream_hosts::CachedResolver;
+pub use upstream_hosts::UpstreamHosts;
+use upstream_override::UPSTREAM_OVERRIDE_HEADER;
+pub use upstream_override::{UpstreamOverrides, AUDIT_TARGET};
+pub use upstream_tls::UpstreamTls;
 
 type BoxBody =
     http_body_util::combinators::BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>;
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type HttpClient = Client<HttpConnector<CachedResolver>, BoxBody>;
 const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
 const HOST_OVERRIDE_HEADER: &str = "X-Cmux-Host-Override";
 const HTTP2_KEEP_ALIVE_INTERVAL_SECS: u64 = 30;
 const HTTP2_KEEP_ALIVE_TIMEOUT_SECS: u64 = 10;
+/// How long a silent client is given before it is assumed to be an SSH client
+/// waiting for the server banner (only when SSH passthrough is configured).
+const SNIFF_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
 
 trait ClientKeepAliveConfig {
     fn set_pool_max_idle_per_host(&mut self, max: usize);
@@ -298,6 +321,70 @@ pub struct ProxyConfig {
     pub listen: SocketAddr,
     pub upstream_host: String,
     pub allow_default_upstream: bool,
+    /// Where to forward connections detected as SSH (e.g. the workspace sshd).
+    /// SSH detection is disabled when unset.
+    pub ssh_upstream: Option<SocketAddr>,
+    /// Ports served by several upstream replicas, with sticky affinity.
+    pub replicas: Replicas,
+    /// Ports whose HTML/JS responses get absolute localhost URLs rewritten.
+    pub rewrites: Rewrites,
+    /// Ports whose upstreams only speak HTTPS.
+    pub upstream_tls: UpstreamTls,
+    /// Ports proxied to a named host instead of the default or workspace host.
+    pub upstream_hosts: UpstreamHosts,
+    /// Targets requests may pick with `X-Cmux-Upstream`; none when empty.
+    pub upstream_overrides: UpstreamOverrides,
+    /// Ports to look for a live server on when a request's port refuses
+    /// connections; disabled when empty.
+    pub port_fallback: PortFallback,
+}
+
+/// Everything in a [`ProxyConfig`] but the listen address, shared by all the
+/// listeners of [`spawn_proxy_multi`].
+#[derive(Clone, Debug)]
+pub struct ProxyOptions {
+    pub upstream_host: String,
+    pub allow_default_upstream: bool,
+    pub ssh_upstream: Option<SocketAddr>,
+    pub replicas: Replicas,
+    pub rewrites: Rewrites,
+    pub upstream_tls: UpstreamTls,
+    pub upstream_hosts: UpstreamHosts,
+    pub upstream_overrides: UpstreamOverrides,
+    pub port_fallback: PortFallback,
+}
+
+impl Default for ProxyOptions {
+    fn default() -> Self {
+        Self {
+            upstream_host: "127.0.0.1".to_string(),
+            allow_default_upstream: false,
+            ssh_upstream: None,
+            replicas: Replicas::default(),
+            rewrites: Rewrites::default(),
+            upstream_tls: UpstreamTls::default(),
+            upstream_hosts: UpstreamHosts::default(),
+            upstream_overrides: UpstreamOverrides::default(),
+            port_fallback: PortFallback::default(),
+        }
+    }
+}
+
+impl ProxyOptions {
+    fn config_for(&self, listen: SocketAddr) -> ProxyConfig {
+        ProxyConfig {
+            listen,
+            upstream_host: self.upstream_host.clone(),
+            allow_default_upstream: self.allow_default_upstream,
+            ssh_upstream: self.ssh_upstream,
+            replicas: self.replicas.clone(),
+            rewrites: self.rewrites.clone(),
+            upstream_tls: self.upstream_tls.clone(),
+            upstream_hosts: self.upstream_hosts.clone(),
+            upstream_overrides: self.upstream_overrides.clone(),
+            port_fallback: self.port_fallback.clone(),
+        }
+    }
 }
 
 pub fn spawn_proxy<S>(cfg: ProxyConfig, mut shutdown: S) -> (SocketAddr, JoinHandle<()>)
@@ -305,11 +392,11 @@ where
     S: Future<Output = ()> + Send + 'static + Unpin,
 {
     // Hyper client for proxying HTTP/1.1
-    let mut connector = HttpConnector::new();
+    let mut connector = HttpConnector::new_with_resolver(CachedResolver);
     connector.set_connect_timeout(Some(Duration::from_secs(5)));
     let mut client_builder = Client::builder(TokioExecutor::new());
     configure_http_client_builder(&mut client_builder);
-    let client: Client<HttpConnector, BoxBody> = client_builder.build(connector);
+    let client: HttpClient = client_builder.build(connector);
 
     let listen = cfg.listen;
     let std_listener = StdTcpListener::bind(listen).expect("bind");
@@ -353,19 +440,18 @@ where
 /// that completes when all servers exit (after shutdown is signaled).
 pub fn spawn_proxy_multi<S>(
     listens: Vec<SocketAddr>,
-    upstream_host: String,
-    allow_default_upstream: bool,
+    options: ProxyOptions,
     shutdown: S,
 ) -> (Vec<SocketAddr>, JoinHandle<()>)
 where
     S: Future<Output = ()> + Send + 'static,
 {
     // Prepare shared client and shutdown notifier
-    let mut connector = HttpConnector::new();
+    let mut connector = HttpConnector::new_with_resolver(CachedResolver);
     connector.set_connect_timeout(Some(Duration::from_secs(5)));
     let mut client_builder = Client::builder(TokioExecutor::new());
     configure_http_client_builder(&mut client_builder);
-    let client: Client<HttpConnector, BoxBody> = client_builder.build(connector);
+    let client: HttpClient = client_builder.build(connector);
 
     let notify = Arc::new(Notify::new());
     let notify_clone = notify.clone();
@@ -379,9 +465,7 @@ where
 
     for addr in listens {
         let client = client.clone();
-        let upstream = upstream_host.clone();
         let notify = notify.clone();
-        let allow_default = allow_default_upstream;
 
         let std_listener = match StdTcpListener::bind(addr) {
             Ok(listener) => listener,
@@ -410,6 +494,7 @@ where
         };
 
         bound_addrs.push(actual_addr);
+        let cfg = options.config_for(actual_addr);
 
         join_set.spawn(async move {
             info!("proxy listening on {}", actual_addr);
@@ -420,14 +505,9 @@ where
                         match result {
                             Ok((stream, remote_addr)) => {
                                 let client = client.clone();
-                                let upstream = upstream.clone();
+                                let cfg = cfg.clone();
 
                                 tokio::spawn(async move {
-                                    let cfg = ProxyConfig {
-                                        listen: actual_addr,
-                                        upstream_host: upstream.clone(),
-                                        allow_default_upstream: allow_default,
-                                    };
                                     if let Err(err) =
                                         serve_client_stream(stream, remote_addr, client, cfg).await
                                     {
@@ -455,12 +535,37 @@ where
 }
 
 async fn serve_client_stream(
-    stream: TcpStream,
+    mut stream: TcpStream,
     remote_addr: SocketAddr,
-    client: Client<HttpConnector, BoxBody>,
+    client: HttpClient,
     cfg: ProxyConfig,
 ) -> Result<(), BoxError> {
-    let (buffered_stream, client_prefers_http2) = sniff_http2_preface(stream).await?;
+    let (prefix, protocol) =
+        sniff::sniff_protocol(&mut stream, cfg.ssh_upstream.is_some(), SNIFF_IDLE_TIMEOUT).await?;
+    match protocol {
+        Protocol::Tls { sni } => {
+            let Some((host, port)) = sni
+                .as_deref()
+                .and_then(|sni| upstream_for_sni(sni, &cfg, remote_addr))
+            else {
+                warn!(client = %remote_addr, ?sni, "no route for TLS connection");
+                return Ok(());
+            };
+            info!(client = %remote_addr, ?sni, upstream = %host, port, "tls passthrough");
+            return tunnel_raw(stream, prefix, &host, port).await;
+        }
+        Protocol::Ssh => {
+            // sniff_protocol only reports SSH when an upstream is configured
+            let Some(target) = cfg.ssh_upstream else {
+                return Ok(());
+            };
+            info!(client = %remote_addr, %target, "ssh passthrough");
+            return tunnel_raw(stream, prefix, &target.ip().to_string(), target.port()).await;
+        }
+        Protocol::Http => {}
+    }
+
+    let (buffered_stream, client_prefers_http2) = sniff_http2_preface(stream, prefix).await?;
     let io = TokioIo::new(buffered_stream);
     let svc_client = client.clone();
     let svc_cfg = cfg.clone();
@@ -483,10 +588,55 @@ async fn serve_client_stream(
     Ok(())
 }
 
-async fn sniff_http2_preface(stream: TcpStream) -> io::Result<(BufferedStream, bool)> {
-    let mut buffer: Vec<u8> = Vec::new();
+/// Pipe raw bytes between the client (replaying the sniffed prefix first) and
+/// `host`:`port`.
+async fn tunnel_raw(
+    stream: TcpStream,
+    prefix: Vec<u8>,
+    host: &str,
+    port: u16,
+) -> Result<(), BoxError> {
+    let mut client_io = BufferedStream::new(stream, prefix);
+    let mut upstream = upstream_hosts::connect(host, port).await?;
+    if let Err(e) = copy_bidirectional(&mut client_io, &mut upstream).await {
+        warn!(%e, upstream = %host, port, "passthrough tunnel error");
+    }
+    let _ = upstream.shutdown().await;
+    Ok(())
+}
+
+/// Route a TLS connection by SNI of the form `<workspace>-<port>.<domain>`
+/// through [`upstream_target`], as a request for the same workspace and port
+/// would be, so host rules and replicas apply. There are no headers to carry
+/// an `X-Cmux-Upstream` override or an affinity cookie.
+fn upstream_for_sni(
+    sni: &str,
+    cfg: &ProxyConfig,
+    remote_addr: SocketAddr,
+) -> Option<(String, u16)> {
+    let label = sni.split('.').next()?;
+    let (_, port) = parse_workspace_port_label(label)?;
+    let (parts, ()) = Request::builder()
+        .header(HOST, format!("{label}.localhost"))
+        .body(())
+        .ok()?
+        .into_parts();
+    let mut pick = cfg.replicas.pick(port, &parts.headers);
+    let upstream = upstream_target(cfg, &parts, remote_addr, port, &mut pick).ok()?;
+    Some((upstream.host, upstream.port))
+}
+
+async fn sniff_http2_preface(
+    stream: TcpStream,
+    mut buffer: Vec<u8>,
+) -> io::Result<(BufferedStream, bool)> {
     let mut temp = [0u8; 24];
 
+    let seen = buffer.len().min(HTTP2_PREFACE.len());
+    if buffer[..seen] != HTTP2_PREFACE[..seen] {
+        return Ok((BufferedStream::new(stream, buffer), false));
+    }
+
     loop {
         if buffer.len() >= HTTP2_PREFACE.len() {
             break;
@@ -634,6 +784,101 @@ fn upstream_host_from_headers(
     Ok(default_host.to_string())
 }
 
+/// Where a request is sent.
+struct Upstream<'a> {
+    host: String,
+    port: u16,
+    /// Client for an upstream that only speaks HTTPS
+    tls: Option<&'a upstream_tls::HttpsClient>,
+}
+
+/// Upstream for a request addressed to `port`, with `pick` the replica
+/// chosen for it. A safelisted `X-Cmux-Upstream` header wins over everything
+/// else, then host rules, then the workspace and default hosts. TLS rules
+/// follow the port the request ends up addressed to: the override's when it
+/// has one, else the requested port's.
+#[allow(clippy::result_large_err)]
+fn upstream_target<'a>(
+    cfg: &'a ProxyConfig,
+    parts: &http::request::Parts,
+    remote_addr: SocketAddr,
+    port: u16,
+    pick: &mut Pick,
+) -> Result<Upstream<'a>, Response<BoxBody>> {
+    if let Some((host, target_port)) = upstream_override(cfg, parts, remote_addr, port)? {
+        // The replica wasn't used, so don't pin the client to it
+        pick.set_cookie = false;
+        return Ok(Upstream {
+            host,
+            port: target_port,
+            tls: cfg.upstream_tls.client_for(target_port),
+        });
+    }
+    let tls = cfg.upstream_tls.client_for(port);
+    if let Some(route) = cfg.upstream_hosts.route_for(port) {
+        return Ok(Upstream {
+            host: route.host.clone(),
+            port: route.port.unwrap_or(pick.port),
+            tls,
+        });
+    }
+    let host = upstream_host_from_headers(
+        &parts.headers,
+        &cfg.upstream_host,
+        cfg.allow_default_upstream,
+    )?;
+    Ok(Upstream {
+        host,
+        port: pick.port,
+        tls,
+    })
+}
+
+/// Target of the request's `X-Cmux-Upstream` header, if it has one the
+/// safelist allows. Allowed and refused overrides are both audited.
+#[allow(clippy::result_large_err)]
+fn upstream_override(
+    cfg: &ProxyConfig,
+    parts: &http::request::Parts,
+    remote_addr: SocketAddr,
+    port: u16,
+) -> Result<Option<(String, u16)>, Response<BoxBody>> {
+    let Some(value) = parts.headers.get(UPSTREAM_OVERRIDE_HEADER) else {
+        return Ok(None);
+    };
+    let value = String::from_utf8_lossy(value.as_bytes());
+    match cfg.upstream_overrides.check(&value) {
+        Ok(target) => {
+            info!(
+                target: AUDIT_TARGET,
+                client = %remote_addr,
+                method = %parts.method,
+                uri = %parts.uri,
+                port,
+                upstream = %value.trim(),
+                "upstream override"
+            );
+            Ok(Some(target))
+        }
+        Err(reason) => {
+            warn!(
+                target: AUDIT_TARGET,
+                client = %remote_addr,
+                method = %parts.method,
+                uri = %parts.uri,
+                port,
+                upstream = %value.trim(),
+                reason,
+                "upstream override refused"
+            );
+            Err(response_with(
+                StatusCode::FORBIDDEN,
+                format!("{}: {}", UPSTREAM_OVERRIDE_HEADER, reason),
+            ))
+        }
+    }
+}
+
 fn is_upgrade_request(req: &Request<Incoming>) -> bool {
     if req.method() == Method::CONNECT {
         return true;
@@ -664,6 +909,8 @@ fn strip_hop_by_hop_headers(h: &mut HeaderMap) {
         "x-cmux-port-internal",
         "x-cmux-workspace-internal",
         "x-cmux-host-override",
+        "x-cmux-affinity",
+        "x-cmux-upstream",
     ];
     for name in HOP_HEADERS {
         h.remove(*name);
@@ -686,16 +933,37 @@ fn strip_hop_by_hop_headers(h: &mut HeaderMap) {
 
 #[allow(clippy::result_large_err)]
 fn build_upstream_uri(
+    scheme: &str,
     upstream_host: &str,
     port: u16,
     orig: &Uri,
 ) -> Result<Uri, Response<BoxBody>> {
     let path_and_query = orig.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
-    let uri_str = format!("http://{}:{}{}", upstream_host, port, path_and_query);
+    let uri_str = format!("{}://{}:{}{}", scheme, upstream_host, port, path_and_query);
     Uri::from_str(&uri_str)
         .map_err(|_| response_with(StatusCode::BAD_GATEWAY, "invalid upstream uri".into()))
 }
 
+fn upstream_scheme(tls_client: Option<&upstream_tls::HttpsClient>) -> &'static str {
+    if tls_client.is_some() {
+        "https"
+    } else {
+        "http"
+    }
+}
+
+/// Send over the port's HTTPS client when it has a TLS rule, plain HTTP otherwise.
+async fn send_upstream(
+    client: &HttpClient,
+    tls_client: Option<&upstream_tls::HttpsClient>,
+    req: Request<BoxBody>,
+) -> Result<Response<Incoming>, hyper_util::client::legacy::Error> {
+    match tls_client {
+        Some(tls_client) => tls_client.request(req).await,
+        None => client.request(req).await,
+    }
+}
+
 // Attempt to parse a pattern like: <workspace>-<port>.localhost[:...]
 // Returns (workspace, port) if found and valid.
 fn parse_workspace_port_from_host(headers: &HeaderMap) -> Option<(String, u16)> {
@@ -716,8 +984,11 @@ fn parse_workspace_port_from_host(headers: &HeaderMap) -> Option<(String, u16)>
 
     // Take the label before .localhost
     let base_len = host_only.len() - SUFFIX.len();
-    let label = &host_only[..base_len];
+    parse_workspace_port_label(&host_only[..base_len])
+}
 
+// Split a `<workspace>-<port>` label into its parts.
+fn parse_workspace_port_label(label: &str) -> Option<(String, u16)> {
     // Expect last '-' separates workspace and port
     let dash_idx = label.rfind('-')?;
     let (ws_part, port_part) = label.split_at(dash_idx);
@@ -754,6 +1025,14 @@ fn enforce_local_host_header(
     Ok(())
 }
 
+fn append_affinity_cookie(headers: &mut HeaderMap, pick: Pick) {
+    if pick.set_cookie {
+        if let Ok(value) = HeaderValue::from_str(&affinity_set_cookie(pick.port)) {
+            headers.append(SET_COOKIE, value);
+        }
+    }
+}
+
 fn response_with(status: StatusCode, msg: String) -> Response<BoxBody> {
     Response::builder()
         .status(status)
@@ -763,7 +1042,7 @@ fn response_with(status: StatusCode, msg: String) -> Response<BoxBody> {
 }
 
 async fn handle(
-    client: Client<HttpConnector, BoxBody>,
+    client: HttpClient,
     cfg: ProxyConfig,
     remote_addr: SocketAddr,
     req: Request<Incoming>,
@@ -793,7 +1072,7 @@ async fn handle(
 }
 
 async fn handle_http(
-    client: Client<HttpConnector, BoxBody>,
+    client: HttpClient,
     cfg: &ProxyConfig,
     remote_addr: SocketAddr,
     req: Request<Incoming>,
@@ -801,11 +1080,31 @@ async fn handle_http(
     let (mut parts, incoming) = req.into_parts();
 
     let port = get_port_from_header(&parts.headers)?;
-    let upstream_host = upstream_host_from_headers(
-        &parts.headers,
-        &cfg.upstream_host,
-        cfg.allow_default_upstream,
-    )?;
+    let rewriter = cfg
+        .rewrites
+        .origins_for(port)
+        .and_then(|ports| {
+            PublicOrigin::from_headers(&parts.headers, port)
+                .map(|public| OriginRewriter::new(ports, &public))
+        })
+        .filter(|rewriter| !rewriter.is_noop());
+    // Where to send the client if nothing is listening on the port
+    let fallback_origin = (!cfg.port_fallback.is_empty()).then(|| {
+        let public = PublicOrigin::from_headers(&parts.headers, port);
+        let path_and_query = parts.uri.path_and_query().map_or("/", |pq| pq.as_str());
+        (
+            parts.method.clone(),
+            port,
+            path_and_query.to_string(),
+            public,
+        )
+    });
+    let mut pick = cfg.replicas.pick(port, &parts.headers);
+    let Upstream {
+        host: upstream_host,
+        port,
+        tls: tls_client,
+    } = upstream_target(cfg, &parts, remote_addr, port, &mut pick)?;
     let host_override = parts
         .headers
         .get(HOST_OVERRIDE_HEADER)
@@ -814,7 +1113,12 @@ async fn handle_http(
         .filter(|s| !s.is_empty());
     enforce_local_host_header(&parts.headers, host_override.as_deref())?;
 
-    parts.uri = build_upstream_uri(&upstream_host, port, &parts.uri)?;
+    parts.uri = build_upstream_uri(
+        upstream_scheme(tls_client),
+        &upstream_host,
+        port,
+        &parts.uri,
+    )?;
     parts.version = Version::HTTP_11;
 
     // Convert incoming body to BoxBody
@@ -825,6 +1129,7 @@ async fn handle_http(
     new_req.headers_mut().remove("x-cmux-port-internal");
     new_req.headers_mut().remove("x-cmux-workspace-internal");
     new_req.headers_mut().remove(HOST_OVERRIDE_HEADER);
+    new_req.headers_mut().remove(UPSTREAM_OVERRIDE_HEADER);
     if let Some(host) = host_override.as_ref() {
         if let Ok(value) = HeaderValue::from_str(host.as_str()) {
             new_req.headers_mut().insert(HOST, value);
@@ -833,6 +1138,10 @@ async fn handle_http(
 
     // Strip hop-by-hop headers on the proxied request
     strip_hop_by_hop_headers(new_req.headers_mut());
+    if rewriter.is_some() {
+        // The rewriter works on plain bytes, so ask for an uncompressed body
+        new_req.headers_mut().remove(ACCEPT_ENCODING);
+    }
 
     info!(
         client = %remote_addr,
@@ -843,12 +1152,29 @@ async fn handle_http(
         "proxy http"
     );
 
-    let upstream_resp = client.request(new_req).await.map_err(|e| {
-        response_with(
-            StatusCode::BAD_GATEWAY,
-            format!("upstream request error: {}", e),
-        )
-    })?;
+    let upstream_resp = match send_upstream(&client, tls_client, new_req).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            if let Some((method, requested_port, path_and_query, public)) =
+                fallback_origin.filter(|_| e.is_connect())
+            {
+                return Err(port_fallback_response(
+                    &cfg.port_fallback,
+                    &upstream_host,
+                    port,
+                    &method,
+                    requested_port,
+                    &path_and_query,
+                    public.as_ref(),
+                )
+                .await);
+            }
+            return Err(response_with(
+                StatusCode::BAD_GATEWAY,
+                format!("upstream request error: {}", e),
+            ));
+        }
+    };
 
     // Map upstream response back to client, stripping hop-by-hop headers
     let mut client_resp_builder = Response::builder().status(upstream_resp.status());
@@ -860,8 +1186,16 @@ async fn handle_http(
         headers.insert(name, value.clone());
     }
     strip_hop_by_hop_headers(headers);
+    append_affinity_cookie(headers, pick);
+    let rewriter = rewriter.filter(|_| rewrite::should_rewrite(headers));
+    if rewriter.is_some() {
+        headers.remove(CONTENT_LENGTH);
+    }
 
-    let body = incoming_to_box(upstream_resp.into_body());
+    let mut body = incoming_to_box(upstream_resp.into_body());
+    if let Some(rewriter) = rewriter {
+        body = RewriteBody::new(body, rewriter).boxed();
+    }
     let resp = client_resp_builder.body(body).map_err(|_| {
         response_with(
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -871,8 +1205,44 @@ async fn handle_http(
     Ok(resp)
 }
 
+/// Answer a request whose upstream `port` on `host` refused the connection:
+/// redirect to the first live fallback port when configured and possible,
+/// otherwise list the live ones.
+async fn port_fallback_response(
+    fallback: &PortFallback,
+    host: &str,
+    port: u16,
+    method: &Method,
+    requested_port: u16,
+    path_and_query: &str,
+    public: Option<&PublicOrigin>,
+) -> Response<BoxBody> {
+    let live = fallback.live_ports(host, port).await;
+    info!(port, upstream = %host, ?live, "upstream not listening, probed fallback ports");
+    let redirect = live
+        .first()
+        .filter(|_| fallback.redirects() && (method == Method::GET || method == Method::HEAD))
+        .and_then(|&live| public?.url_for(live, path_and_query))
+        .and_then(|url| HeaderValue::from_str(&url).ok());
+    if let Some(location) = redirect {
+        return Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header(http::header::LOCATION, location)
+            .body(empty_body())
+            .unwrap();
+    }
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header("content-type", "application/json")
+        .body(full_body(port_fallback::listing_json(
+            requested_port,
+            &live,
+        )))
+        .unwrap()
+}
+
 async fn handle_upgrade(
-    client: Client<HttpConnector, BoxBody>,
+    client: HttpClient,
     cfg: ProxyConfig,
     remote_addr: SocketAddr,
     req: Request<Incoming>,
@@ -880,40 +1250,47 @@ async fn handle_upgrade(
     // Treat as reverse-proxied upgrade (e.g., WebSocket). We forward the request to upstream,
     // then mirror the 101 response headers to the client and tunnel bytes between both upgrades.
 
-    let port = get_port_from_header(req.headers())?;
-    let upstream_host = upstream_host_from_headers(
-        req.headers(),
-        &cfg.upstream_host,
-        cfg.allow_default_upstream,
+    let (parts, incoming) = req.into_parts();
+    let port = get_port_from_header(&parts.headers)?;
+    let mut pick = cfg.replicas.pick(port, &parts.headers);
+    let Upstream {
+        host: upstream_host,
+        port,
+        tls: tls_client,
+    } = upstream_target(&cfg, &parts, remote_addr, port, &mut pick)?;
+    let upstream_uri = build_upstream_uri(
+        upstream_scheme(tls_client),
+        &upstream_host,
+        port,
+        &parts.uri,
     )?;
-    let upstream_uri = build_upstream_uri(&upstream_host, port, req.uri())?;
-    let host_override = req
-        .headers()
+    let host_override = parts
+        .headers
         .get(HOST_OVERRIDE_HEADER)
         .and_then(|v| v.to_str().ok())
         .map(|s| s.trim().to_string())
         .filter(|s| !s.is_empty());
-    enforce_local_host_header(req.headers(), host_override.as_deref())?;
+    enforce_local_host_header(&parts.headers, host_override.as_deref())?;
 
-    // Build proxied request for upstream - need to clone headers before consuming req
+    // Build proxied request for upstream
     let mut proxied_req_builder = Request::builder()
-        .method(req.method())
+        .method(&parts.method)
         .uri(upstream_uri)
-        .version(req.version());
+        .version(parts.version);
 
     // Copy headers
-    for (name, value) in req.headers().iter() {
+    for (name, value) in parts.headers.iter() {
         if !name.as_str().eq_ignore_ascii_case("x-cmux-port-internal")
             && !name
                 .as_str()
                 .eq_ignore_ascii_case("x-cmux-workspace-internal")
             && !name.as_str().eq_ignore_ascii_case(HOST_OVERRIDE_HEADER)
+            && !name.as_str().eq_ignore_ascii_case(UPSTREAM_OVERRIDE_HEADER)
         {
             proxied_req_builder = proxied_req_builder.header(name, value);
         }
     }
 
-    let (parts, incoming) = req.into_parts();
     let proxied_body: BoxBody = incoming_to_box(incoming);
     let mut proxied_req = proxied_req_builder.body(proxied_body).map_err(|_| {
         response_with(
@@ -937,12 +1314,14 @@ async fn handle_upgrade(
     info!(client = %remote_addr, port = port, upstream = %upstream_host, "proxy upgrade (e.g. websocket)");
 
     // Send to upstream and get its response (should be 101)
-    let upstream_resp = client.request(proxied_req).await.map_err(|e| {
-        response_with(
-            StatusCode::BAD_GATEWAY,
-            format!("upstream upgrade error: {}", e),
-        )
-    })?;
+    let upstream_resp = send_upstream(&client, tls_client, proxied_req)
+        .await
+        .map_err(|e| {
+            response_with(
+                StatusCode::BAD_GATEWAY,
+                format!("upstream upgrade error: {}", e),
+            )
+        })?;
 
     if upstream_resp.status() != StatusCode::SWITCHING_PROTOCOLS {
         // Return upstream status (probably 4xx/5xx) to client with body
@@ -971,6 +1350,7 @@ async fn handle_upgrade(
     }
     // Ensure Connection: upgrade and Upgrade headers are present
     out_headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    append_affinity_cookie(out_headers, pick);
 
     // Prepare response to client (empty body; the connection upgrades)
     let client_resp = client_resp_builder.body(empty_body()).map_err(|_| {
@@ -1015,17 +1395,17 @@ async fn handle_connect(
     cfg: &ProxyConfig,
     remote_addr: SocketAddr,
 ) -> Result<Response<BoxBody>, Response<BoxBody>> {
-    let port = get_port_from_header(req.headers())?;
-    let upstream_host = upstream_host_from_headers(
-        req.headers(),
-        &cfg.upstream_host,
-        cfg.allow_default_upstream,
-    )?;
-    let target = format!("{}:{}", upstream_host, port);
-    info!(client = %remote_addr, %target, "tcp tunnel via CONNECT");
-
     // Consume request to get parts for upgrade later
     let (parts, _incoming) = req.into_parts();
+    let port = get_port_from_header(&parts.headers)?;
+    let mut pick = cfg.replicas.pick(port, &parts.headers);
+    let Upstream {
+        host: upstream_host,
+        port,
+        ..
+    } = upstream_target(cfg, &parts, remote_addr, port, &mut pick)?;
+    let target = format!("{}:{}", upstream_host, port);
+    info!(client = %remote_addr, %target, "tcp tunnel via CONNECT");
 
     // Respond that the connection is established; then upgrade to a raw tunnel
     let resp = Response::builder()
@@ -1044,7 +1424,7 @@ async fn handle_connect(
         match hyper::upgrade::on(original_req).await {
             Ok(upgraded) => {
                 let mut client_io = TokioIo::new(upgraded);
-                match TcpStream::connect(&target).await {
+                match upstream_hosts::connect(&upstream_host, port).await {
                     Ok(mut upstream) => {
                         if let Err(e) = copy_bidirectional(&mut client_io, &mut upstream).await {
                             warn!(%e, "tcp tunnel error");
@@ -7,7 +7,7 @@ use tracing::info;
 #[command(
     author,
     version,
-    about = "Header-based proxy for HTTP, WS, and TCP (CONNECT)"
+    about = "Header-based proxy for HTTP, WS, and TCP (CONNECT), with TLS/SSH passthrough"
 )]
 struct Args {
     /// Listen address(es). Accepts multiple or comma-separated values.
@@ -23,6 +23,52 @@ struct Args {
     /// Allow requests without workspace headers to route to the default upstream host.
     #[arg(long, env = "CMUX_ALLOW_DEFAULT_UPSTREAM", default_value_t = true)]
     allow_default_upstream: bool,
+
+    /// Forward connections that speak SSH to this address (e.g. 127.0.0.1:22).
+    /// When unset, SSH clients are not detected on the shared port.
+    #[arg(long, env = "CMUX_SSH_UPSTREAM")]
+    ssh_upstream: Option<SocketAddr>,
+
+    /// Ports served by multiple upstream replicas, as `PORT=R1,R2` entries separated by `;`.
+    /// Example: --replicas "3000=3000,3001;8080=8081,8082"
+    #[arg(long, env = "CMUX_REPLICAS", default_value = "")]
+    replicas: cmux_proxy::Replicas,
+
+    /// Ports whose HTML/JS responses have absolute `http://localhost:PORT` (and ws://)
+    /// URLs rewritten to the public origin, as `PORT[=P1,P2]` entries separated by `;`.
+    /// A bare port rewrites only its own origin.
+    /// Example: --rewrite "3000;5173=5173,8080"
+    #[arg(long, env = "CMUX_REWRITE", default_value = "")]
+    rewrite: cmux_proxy::Rewrites,
+
+    /// Ports whose upstreams only listen with TLS, as `PORT[=OPT,...]` entries separated by `;`.
+    /// Options: `insecure` (skip verification), `ca=PATH`, `cert=PATH` + `key=PATH` (mTLS
+    /// identity) and `sni=NAME`. A bare port verifies against public roots.
+    /// Example: --upstream-tls "8443=insecure;9443=ca=/etc/ca.pem,cert=/etc/c.pem,key=/etc/k.pem"
+    #[arg(long, env = "CMUX_UPSTREAM_TLS", default_value = "")]
+    upstream_tls: cmux_proxy::UpstreamTls,
+
+    /// Ports proxied to a named host (e.g. a sibling container) instead of the upstream host,
+    /// as `PORT=HOST[:PORT]` entries separated by `;`. Without a port the requested one is
+    /// kept. Names are re-resolved every 30s.
+    /// Example: --upstream-hosts "5432=db;8080=api.internal:80"
+    #[arg(long, env = "CMUX_UPSTREAM_HOSTS", default_value = "")]
+    upstream_hosts: cmux_proxy::UpstreamHosts,
+
+    /// Targets a request may route itself to with an `X-Cmux-Upstream: HOST:PORT` header,
+    /// as `HOST:PORTS` patterns separated by `;`. `*` in a host matches anything; ports are a
+    /// port, a `LOW-HIGH` range or `*`. The header is refused when this is empty.
+    /// Example: --upstream-override-safelist "10.0.*:8000-8999;*.internal:*"
+    #[arg(long, env = "CMUX_UPSTREAM_OVERRIDE_SAFELIST", default_value = "")]
+    upstream_override_safelist: cmux_proxy::UpstreamOverrides,
+
+    /// Common dev ports to probe when a request's port refuses connections, as
+    /// `[MODE:]P1,P2,...`. Mode `list` (default) answers with a JSON listing of the live ports;
+    /// `redirect` sends GET/HEAD requests to the first live one when the public host encodes
+    /// the port. Disabled when empty.
+    /// Example: --port-fallback "redirect:3000,5173,5174,8000,8080"
+    #[arg(long, env = "CMUX_PORT_FALLBACK", default_value = "")]
+    port_fallback: cmux_proxy::PortFallback,
 }
 
 #[tokio::main]
@@ -42,6 +88,7 @@ async fn main() {
         "listen" = ?args.listen,
         "upstream_host" = %args.upstream_host,
         allow_default_upstream = args.allow_default_upstream,
+        "ssh_upstream" = ?args.ssh_upstream,
         "Starting cmux-proxy"
     );
 
@@ -55,13 +102,21 @@ async fn main() {
     listens.dedup();
     let listens = dedupe_wildcard_v4(listens);
 
-    let upstream_host = args.upstream_host;
-    let allow_default_upstream = args.allow_default_upstream;
+    let options = cmux_proxy::ProxyOptions {
+        upstream_host: args.upstream_host,
+        allow_default_upstream: args.allow_default_upstream,
+        ssh_upstream: args.ssh_upstream,
+        replicas: args.replicas,
+        rewrites: args.rewrite,
+        upstream_tls: args.upstream_tls,
+        upstream_hosts: args.upstream_hosts,
+        upstream_overrides: args.upstream_override_safelist,
+        port_fallback: args.port_fallback,
+    };
 
-    let (bound, handle) =
-        cmux_proxy::spawn_proxy_multi(listens, upstream_host, allow_default_upstream, async {
-            let _ = tokio::signal::ctrl_c().await;
-        });
+    let (bound, handle) = cmux_proxy::spawn_proxy_multi(listens, options, async {
+        let _ = tokio::signal::ctrl_c().await;
+    });
     info!("bound_addrs" = ?bound, "proxy started");
     let _ = handle.await;
 }
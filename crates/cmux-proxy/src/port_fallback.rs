@@ -0,0 +1,156 @@
+//! Fallback for requests to a workspace port nothing is listening on.
+//!
+//! Dev servers don't always come up where the preview link points: Vite moves
+//! on to 5174 when 5173 is taken, and a forgotten `PORT` leaves a server on
+//! its framework default. When an HTTP request's upstream can't be connected
+//! to and a fallback is configured, the proxy probes a short list of common
+//! dev ports on the same host. In `redirect` mode a GET or HEAD is redirected
+//! to the first live one through the public origin (as with rewrites);
+//! otherwise, or when the public host doesn't encode the port, the client
+//! gets a JSON listing of the live ports instead of a bare gateway error.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future;
+use tokio::time::timeout;
+
+use crate::upstream_hosts;
+
+/// How long a candidate port gets to accept a connection.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FallbackRule {
+    redirect: bool,
+    ports: Vec<u16>,
+}
+
+/// Ports probed when a request's upstream isn't listening; disabled when
+/// empty. Cheap to clone.
+#[derive(Clone, Debug, Default)]
+pub struct PortFallback(Option<Arc<FallbackRule>>);
+
+impl PortFallback {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Whether GET and HEAD requests are redirected to a live port.
+    pub(crate) fn redirects(&self) -> bool {
+        self.0.as_ref().is_some_and(|rule| rule.redirect)
+    }
+
+    /// Candidates other than `port` accepting connections on `host`, in
+    /// configured order. Probed concurrently, so this takes at most
+    /// [`PROBE_TIMEOUT`].
+    pub(crate) async fn live_ports(&self, host: &str, port: u16) -> Vec<u16> {
+        let Some(rule) = &self.0 else {
+            return Vec::new();
+        };
+        let candidates: Vec<u16> = rule.ports.iter().copied().filter(|p| *p != port).collect();
+        let probes = candidates.iter().map(|&candidate| async move {
+            matches!(
+                timeout(PROBE_TIMEOUT, upstream_hosts::connect(host, candidate)).await,
+                Ok(Ok(_))
+            )
+        });
+        let live = future::join_all(probes).await;
+        candidates
+            .into_iter()
+            .zip(live)
+            .filter_map(|(candidate, live)| live.then_some(candidate))
+            .collect()
+    }
+}
+
+impl FromStr for PortFallback {
+    type Err = String;
+
+    /// Parse `[MODE:]PORT[,PORT...]`, where `MODE` is `redirect` or `list`
+    /// (the default), e.g. `redirect:3000,5173,8080`. Empty disables it.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(Self::default());
+        }
+        let (redirect, ports) = match s.split_once(':') {
+            Some(("redirect", ports)) => (true, ports),
+            Some(("list", ports)) => (false, ports),
+            Some((mode, _)) => return Err(format!("unknown fallback mode {mode:?}")),
+            None => (false, s),
+        };
+        let mut parsed = Vec::new();
+        for port in ports.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let port = port
+                .parse::<u16>()
+                .ok()
+                .filter(|p| *p != 0)
+                .ok_or_else(|| format!("invalid fallback port {port:?}"))?;
+            if !parsed.contains(&port) {
+                parsed.push(port);
+            }
+        }
+        if parsed.is_empty() {
+            return Err(format!("no fallback ports in {s:?}"));
+        }
+        Ok(Self(Some(Arc::new(FallbackRule {
+            redirect,
+            ports: parsed,
+        }))))
+    }
+}
+
+/// Body listing the live ports when `port` refused the connection.
+pub(crate) fn listing_json(port: u16, live: &[u16]) -> String {
+    let live: Vec<String> = live.iter().map(u16::to_string).collect();
+    format!(
+        "{{\"error\":\"nothing is listening on port {port}\",\"port\":{port},\"livePorts\":[{}]}}",
+        live.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn parses_mode_and_ports() {
+        let fallback: PortFallback = "redirect: 3000, 5173,3000".parse().unwrap();
+        assert!(fallback.redirects());
+        assert_eq!(fallback.0.unwrap().ports, [3000, 5173]);
+        let fallback: PortFallback = "8080,8000".parse().unwrap();
+        assert!(!fallback.redirects());
+        assert!(!"list:8080".parse::<PortFallback>().unwrap().redirects());
+        assert!("".parse::<PortFallback>().unwrap().is_empty());
+        for bad in ["redirect:", "bounce:3000", "3000,x", "0", "70000"] {
+            assert!(bad.parse::<PortFallback>().is_err(), "{bad} should fail");
+        }
+    }
+
+    #[tokio::test]
+    async fn probes_candidates_in_order() {
+        let a = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let b = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (a, b) = (
+            a.local_addr().unwrap().port(),
+            b.local_addr().unwrap().port(),
+        );
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let fallback: PortFallback = format!("{b},{closed_port},{a}").parse().unwrap();
+        assert_eq!(fallback.live_ports("127.0.0.1", 1).await, [b, a]);
+        // The port that failed isn't offered again
+        assert_eq!(fallback.live_ports("127.0.0.1", b).await, [a]);
+        assert_eq!(
+            listing_json(3000, &[b, a]),
+            format!(
+                "{{\"error\":\"nothing is listening on port 3000\",\"port\":3000,\"livePorts\":[{b},{a}]}}"
+            )
+        );
+    }
+}
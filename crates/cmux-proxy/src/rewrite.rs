@@ -0,0 +1,404 @@
+//! Opt-in rewriting of absolute localhost URLs in HTML and JavaScript responses.
+//!
+//! Dev servers like to emit `http://localhost:5173/...` (or `ws://` for HMR),
+//! which breaks as soon as the page is opened through the workspace domain.
+//! For ports with a rewrite rule, such origins are replaced with the public
+//! origin the request came in on. The public host is derived from
+//! `X-Forwarded-Host` (falling back to `Host`): the requested port is swapped
+//! for the referenced one in its first label, so `port-3000-abc.example.com`
+//! maps `localhost:8080` to `port-8080-abc.example.com`.
+//!
+//! Bodies are rewritten as they stream, carrying a few bytes across chunk
+//! boundaries so origins split between chunks are still matched.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http::header::{CONTENT_ENCODING, CONTENT_TYPE, HOST};
+use http::HeaderMap;
+use hyper::body::{Body, Frame, SizeHint};
+
+use crate::{BoxBody, BoxError};
+
+const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "0.0.0.0"];
+
+/// Content types whose bodies may carry absolute URLs worth rewriting.
+const REWRITE_CONTENT_TYPES: &[&str] = &[
+    "text/html",
+    "text/javascript",
+    "application/javascript",
+    "application/x-javascript",
+];
+
+/// Ports whose responses are rewritten, each with the local ports whose
+/// origins are mapped. Cheap to clone.
+#[derive(Clone, Debug, Default)]
+pub struct Rewrites(Arc<HashMap<u16, Vec<u16>>>);
+
+impl Rewrites {
+    pub fn new(map: HashMap<u16, Vec<u16>>) -> Self {
+        Self(Arc::new(map))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Local ports whose origins are rewritten in responses for `port`.
+    pub fn origins_for(&self, port: u16) -> Option<&[u16]> {
+        self.0.get(&port).map(Vec::as_slice)
+    }
+}
+
+impl FromStr for Rewrites {
+    type Err = String;
+
+    /// Parse `PORT[=P1,P2][;PORT...]`, e.g. `3000;5173=5173,8080`. A bare port
+    /// only rewrites its own origin.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = HashMap::new();
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (port, origins) = entry.split_once('=').unwrap_or((entry, entry));
+            let port: u16 = port
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid port in {entry:?}"))?;
+            let origins = origins
+                .split(',')
+                .map(|p| p.trim().parse::<u16>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("invalid origin port in {entry:?}"))?;
+            map.insert(port, origins);
+        }
+        Ok(Self::new(map))
+    }
+}
+
+/// Origin a request was made on, as seen by the browser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PublicOrigin {
+    secure: bool,
+    host: String,
+    port: u16,
+}
+
+impl PublicOrigin {
+    /// `port` is the port the request was routed to (before replica selection).
+    pub(crate) fn from_headers(headers: &HeaderMap, port: u16) -> Option<Self> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+        let host = header("x-forwarded-host").or_else(|| header(HOST.as_str()))?;
+        let secure = header("x-forwarded-proto").is_some_and(|p| p.eq_ignore_ascii_case("https"));
+        Some(Self {
+            secure,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// Absolute public URL for `path_and_query` on local `port`, if the host
+    /// encodes ports.
+    pub(crate) fn url_for(&self, port: u16, path_and_query: &str) -> Option<String> {
+        let scheme = if self.secure { "https" } else { "http" };
+        Some(format!(
+            "{scheme}://{}{path_and_query}",
+            self.host_for(port)?
+        ))
+    }
+
+    /// Public `host[:port]` serving local `port`, if the host encodes ports.
+    fn host_for(&self, port: u16) -> Option<String> {
+        if port == self.port {
+            return Some(self.host.clone());
+        }
+        let (label, rest) = self.host.split_once('.').unwrap_or((&self.host, ""));
+        let own = self.port.to_string();
+        let segments: Vec<&str> = label.split('-').collect();
+        let idx = segments.iter().rposition(|s| *s == own)?;
+        let other = port.to_string();
+        let mut mapped = segments;
+        mapped[idx] = &other;
+        let label = mapped.join("-");
+        Some(if rest.is_empty() {
+            label
+        } else {
+            format!("{label}.{rest}")
+        })
+    }
+}
+
+/// Whether a response with these headers should go through the rewriter.
+pub(crate) fn should_rewrite(headers: &HeaderMap) -> bool {
+    let encoded = headers
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| !v.trim().eq_ignore_ascii_case("identity"));
+    let rewritable = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_ascii_lowercase())
+        .is_some_and(|v| REWRITE_CONTENT_TYPES.iter().any(|t| v.starts_with(t)));
+    rewritable && !encoded
+}
+
+/// Streaming byte replacer for a fixed set of origins.
+#[derive(Debug)]
+pub(crate) struct OriginRewriter {
+    patterns: Vec<(Vec<u8>, Vec<u8>)>,
+    longest: usize,
+    carry: Vec<u8>,
+}
+
+impl OriginRewriter {
+    pub(crate) fn new(local_ports: &[u16], public: &PublicOrigin) -> Self {
+        let (http, ws) = if public.secure {
+            ("https", "wss")
+        } else {
+            ("http", "ws")
+        };
+        let mut patterns = Vec::new();
+        for &port in local_ports {
+            let Some(host) = public.host_for(port) else {
+                continue;
+            };
+            for local in LOCAL_HOSTS {
+                for (from, to) in [("http", http), ("ws", ws)] {
+                    patterns.push((
+                        format!("{from}://{local}:{port}").into_bytes(),
+                        format!("{to}://{host}").into_bytes(),
+                    ));
+                }
+            }
+        }
+        let longest = patterns.iter().map(|(p, _)| p.len()).max().unwrap_or(0);
+        Self {
+            patterns,
+            longest,
+            carry: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_noop(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Rewrite the next chunk. Up to one pattern's worth of trailing bytes is
+    /// held back until more input (or `finish`) shows how it continues.
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.carry.extend_from_slice(chunk);
+        let input = std::mem::take(&mut self.carry);
+        let (out, consumed) = self.scan(&input, false);
+        self.carry = input[consumed..].to_vec();
+        out
+    }
+
+    /// Flush whatever was held back.
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        let input = std::mem::take(&mut self.carry);
+        self.scan(&input, true).0
+    }
+
+    fn scan(&self, input: &[u8], eof: bool) -> (Vec<u8>, usize) {
+        let mut out = Vec::with_capacity(input.len());
+        let mut i = 0;
+        while i < input.len() {
+            // A match needs one byte of lookahead to rule out a longer port
+            if !eof && input.len() - i <= self.longest {
+                break;
+            }
+            let byte = input[i];
+            if byte == b'h' || byte == b'w' {
+                let rest = &input[i..];
+                let hit = self.patterns.iter().find(|(from, _)| {
+                    rest.starts_with(from) && !rest.get(from.len()).is_some_and(u8::is_ascii_digit)
+                });
+                if let Some((from, to)) = hit {
+                    out.extend_from_slice(to);
+                    i += from.len();
+                    continue;
+                }
+            }
+            out.push(byte);
+            i += 1;
+        }
+        (out, i)
+    }
+}
+
+/// Response body passed through an [`OriginRewriter`].
+pub(crate) struct RewriteBody {
+    inner: BoxBody,
+    rewriter: OriginRewriter,
+    trailers: Option<HeaderMap>,
+    done: bool,
+}
+
+impl RewriteBody {
+    pub(crate) fn new(inner: BoxBody, rewriter: OriginRewriter) -> Self {
+        Self {
+            inner,
+            rewriter,
+            trailers: None,
+            done: false,
+        }
+    }
+}
+
+impl Body for RewriteBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let this = &mut *self;
+        loop {
+            if this.done {
+                return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+            }
+            match Pin::new(&mut this.inner).poll_frame(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+                    Ok(data) => {
+                        let out = this.rewriter.feed(&data);
+                        if !out.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(Bytes::from(out)))));
+                        }
+                    }
+                    Err(frame) => {
+                        // Trailers end the body; flush held-back bytes first
+                        this.trailers = frame.into_trailers().ok();
+                        this.done = true;
+                        let out = this.rewriter.finish();
+                        if !out.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(Bytes::from(out)))));
+                        }
+                    }
+                },
+                Poll::Ready(None) => {
+                    this.done = true;
+                    let out = this.rewriter.finish();
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::data(Bytes::from(out)))));
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        // Replacements change the length
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn public(host: &str, port: u16, secure: bool) -> PublicOrigin {
+        PublicOrigin {
+            secure,
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    fn rewrite_in_chunks(rewriter: &mut OriginRewriter, input: &str, size: usize) -> String {
+        let mut out = Vec::new();
+        for chunk in input.as_bytes().chunks(size) {
+            out.extend(rewriter.feed(chunk));
+        }
+        out.extend(rewriter.finish());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn rewrites_local_origins_across_chunk_boundaries() {
+        let origin = public("port-3000-abc.example.com", 3000, true);
+        let input = r#"<script src="http://localhost:3000/app.js"></script>
+<a href="http://127.0.0.1:8080/api">x</a> new WebSocket("ws://0.0.0.0:3000/hmr");
+http://localhost:30001/ stays, as does http://localhost:3000"#;
+        let expected = r#"<script src="https://port-3000-abc.example.com/app.js"></script>
+<a href="https://port-8080-abc.example.com/api">x</a> new WebSocket("wss://port-3000-abc.example.com/hmr");
+http://localhost:30001/ stays, as does https://port-3000-abc.example.com"#;
+        for size in [1, 3, 7, 64, 4096] {
+            let mut rewriter = OriginRewriter::new(&[3000, 8080], &origin);
+            assert_eq!(rewrite_in_chunks(&mut rewriter, input, size), expected);
+        }
+    }
+
+    #[test]
+    fn maps_ports_only_when_the_host_encodes_them() {
+        let origin = public("ws1-3000.localhost:39379", 3000, false);
+        assert_eq!(
+            origin.host_for(5173).as_deref(),
+            Some("ws1-5173.localhost:39379")
+        );
+        let plain = public("preview.example.com", 3000, true);
+        assert_eq!(plain.host_for(3000).as_deref(), Some("preview.example.com"));
+        assert_eq!(plain.host_for(8080), None);
+        assert!(OriginRewriter::new(&[8080], &plain).is_noop());
+    }
+
+    #[test]
+    fn public_origin_prefers_forwarded_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("10.0.0.5:39379"));
+        assert_eq!(
+            PublicOrigin::from_headers(&headers, 3000),
+            Some(public("10.0.0.5:39379", 3000, false))
+        );
+        headers.insert(
+            "x-forwarded-host",
+            HeaderValue::from_static("a.example.com"),
+        );
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+        assert_eq!(
+            PublicOrigin::from_headers(&headers, 3000),
+            Some(public("a.example.com", 3000, true))
+        );
+    }
+
+    #[test]
+    fn only_plain_html_and_js_are_rewritten() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        assert!(should_rewrite(&headers));
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        assert!(!should_rewrite(&headers));
+        headers.remove(CONTENT_ENCODING);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        assert!(!should_rewrite(&headers));
+    }
+
+    #[test]
+    fn parses_rewrite_spec() {
+        let rewrites: Rewrites = "3000; 5173=5173,8080".parse().unwrap();
+        assert_eq!(rewrites.origins_for(3000), Some(&[3000][..]));
+        assert_eq!(rewrites.origins_for(5173), Some(&[5173, 8080][..]));
+        assert_eq!(rewrites.origins_for(8080), None);
+        assert!("3000=abc".parse::<Rewrites>().is_err());
+        assert!("".parse::<Rewrites>().unwrap().is_empty());
+    }
+}
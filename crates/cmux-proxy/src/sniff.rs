@@ -0,0 +1,231 @@
+//! Protocol detection for connections arriving on the shared listener.
+//!
+//! The first bytes a client sends are enough to tell TLS (a handshake record),
+//! SSH (an `SSH-` identification string) and HTTP apart. The bytes read while
+//! deciding are returned so the caller can replay them to whichever handler
+//! takes the connection.
+
+use std::{io, time::Duration};
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// TLS record content type for handshake messages.
+const TLS_HANDSHAKE: u8 = 0x16;
+/// TLS handshake message type for ClientHello.
+const TLS_CLIENT_HELLO: u8 = 0x01;
+/// TLS extension carrying the server name.
+const TLS_EXT_SERVER_NAME: u16 = 0x0000;
+/// Upper bound on bytes buffered while waiting for a complete ClientHello record.
+const MAX_CLIENT_HELLO: usize = 16 * 1024 + 5;
+const SSH_PREFIX: &[u8] = b"SSH-";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    /// TLS handshake; `sni` is the requested server name if one was sent.
+    Tls {
+        sni: Option<String>,
+    },
+    Ssh,
+    Http,
+}
+
+/// Read just enough of the stream to classify it.
+///
+/// SSH clients may wait for the server banner before sending anything, so when
+/// `ssh_enabled` is set and the client stays silent for `idle_timeout`, the
+/// connection is treated as SSH.
+pub(crate) async fn sniff_protocol(
+    stream: &mut TcpStream,
+    ssh_enabled: bool,
+    idle_timeout: Duration,
+) -> io::Result<(Vec<u8>, Protocol)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let first = match tokio::time::timeout(idle_timeout, stream.read(&mut chunk)).await {
+        Ok(result) => result?,
+        Err(_) if ssh_enabled => return Ok((buffer, Protocol::Ssh)),
+        Err(_) => return Ok((buffer, Protocol::Http)),
+    };
+    buffer.extend_from_slice(&chunk[..first]);
+
+    loop {
+        let protocol = classify(&buffer, ssh_enabled);
+        let need_more = match &protocol {
+            None => true,
+            Some(Protocol::Tls { sni: None }) => tls_record_incomplete(&buffer),
+            Some(_) => false,
+        };
+        if !need_more || buffer.len() >= MAX_CLIENT_HELLO {
+            return Ok((buffer, protocol.unwrap_or(Protocol::Http)));
+        }
+
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok((buffer, protocol.unwrap_or(Protocol::Http)));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Classify buffered bytes, or `None` if more input is needed to decide.
+fn classify(buffer: &[u8], ssh_enabled: bool) -> Option<Protocol> {
+    if buffer.first() == Some(&TLS_HANDSHAKE) {
+        return Some(Protocol::Tls {
+            sni: parse_client_hello_sni(buffer),
+        });
+    }
+    if ssh_enabled {
+        let n = buffer.len().min(SSH_PREFIX.len());
+        if buffer[..n] == SSH_PREFIX[..n] {
+            return (n == SSH_PREFIX.len()).then_some(Protocol::Ssh);
+        }
+    }
+    Some(Protocol::Http)
+}
+
+fn tls_record_incomplete(buffer: &[u8]) -> bool {
+    match buffer.get(3..5) {
+        Some(len) => buffer.len() < 5 + u16::from_be_bytes([len[0], len[1]]) as usize,
+        None => true,
+    }
+}
+
+/// Extract the SNI host name from a TLS record holding a ClientHello.
+pub(crate) fn parse_client_hello_sni(record: &[u8]) -> Option<String> {
+    let mut r = Reader(record);
+    if r.u8()? != TLS_HANDSHAKE {
+        return None;
+    }
+    r.skip(2)?; // record version
+    let record_len = r.u16()? as usize;
+    let mut hs = Reader(r.take(record_len)?);
+
+    if hs.u8()? != TLS_CLIENT_HELLO {
+        return None;
+    }
+    hs.skip(3)?; // handshake length
+    hs.skip(2 + 32)?; // client version + random
+    let session_id_len = hs.u8()? as usize;
+    hs.skip(session_id_len)?;
+    let cipher_suites_len = hs.u16()? as usize;
+    hs.skip(cipher_suites_len)?;
+    let compression_len = hs.u8()? as usize;
+    hs.skip(compression_len)?;
+
+    let extensions_len = hs.u16()? as usize;
+    let mut exts = Reader(hs.take(extensions_len)?);
+    while !exts.0.is_empty() {
+        let ext_type = exts.u16()?;
+        let ext_len = exts.u16()? as usize;
+        let mut ext = Reader(exts.take(ext_len)?);
+        if ext_type != TLS_EXT_SERVER_NAME {
+            continue;
+        }
+
+        let list_len = ext.u16()? as usize;
+        let mut names = Reader(ext.take(list_len)?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let name_len = names.u16()? as usize;
+            let name = names.take(name_len)?;
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().map(str::to_ascii_lowercase);
+            }
+        }
+        return None;
+    }
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+/// Build a minimal ClientHello record carrying `sni`. Used by tests.
+#[cfg(test)]
+pub(crate) fn client_hello_with_sni(sni: &str) -> Vec<u8> {
+    let name = sni.as_bytes();
+    let mut sni_ext = Vec::new();
+    sni_ext.extend_from_slice(&((name.len() + 3) as u16).to_be_bytes());
+    sni_ext.push(0);
+    sni_ext.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    sni_ext.extend_from_slice(name);
+
+    let mut extensions = Vec::new();
+    // An unrelated extension first (supported_versions) to exercise skipping
+    extensions.extend_from_slice(&[0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04]);
+    extensions.extend_from_slice(&TLS_EXT_SERVER_NAME.to_be_bytes());
+    extensions.extend_from_slice(&(sni_ext.len() as u16).to_be_bytes());
+    extensions.extend_from_slice(&sni_ext);
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&[0u8; 32]);
+    body.push(0); // session id
+    body.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]); // one cipher suite
+    body.extend_from_slice(&[0x01, 0x00]); // null compression
+    body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    body.extend_from_slice(&extensions);
+
+    let mut handshake = vec![TLS_CLIENT_HELLO];
+    handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&body);
+
+    let mut record = vec![TLS_HANDSHAKE, 0x03, 0x01];
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sni_from_client_hello() {
+        let record = client_hello_with_sni("Workspace-1-3000.localhost");
+        assert_eq!(
+            parse_client_hello_sni(&record).as_deref(),
+            Some("workspace-1-3000.localhost")
+        );
+        assert_eq!(parse_client_hello_sni(&record[..record.len() - 4]), None);
+        assert!(!tls_record_incomplete(&record));
+        assert!(tls_record_incomplete(&record[..20]));
+    }
+
+    #[test]
+    fn classifies_first_bytes() {
+        assert_eq!(
+            classify(&client_hello_with_sni("a.b"), false),
+            Some(Protocol::Tls {
+                sni: Some("a.b".into())
+            })
+        );
+        assert_eq!(classify(b"SSH-2.0-OpenSSH_9.6", true), Some(Protocol::Ssh));
+        assert_eq!(classify(b"SS", true), None);
+        assert_eq!(classify(b"SSH-2.0", false), Some(Protocol::Http));
+        assert_eq!(classify(b"GET / HTTP/1.1\r\n", true), Some(Protocol::Http));
+    }
+}
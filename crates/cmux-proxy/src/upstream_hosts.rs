@@ -0,0 +1,258 @@
+//! Upstreams addressed by hostname rather than the default upstream host.
+//!
+//! Services in sibling containers are usually reached by name (`db`,
+//! `api.internal`), and their addresses change whenever a container is
+//! recreated. Ports with a host rule are proxied to that host instead of the
+//! workspace or default upstream host. Names are resolved through a shared
+//! cache that re-resolves entries older than [`DNS_REFRESH_INTERVAL`], and
+//! keeps serving the last good addresses when a refresh fails so a flaky
+//! resolver doesn't take the route down. Upstream URIs keep the hostname, so
+//! the HTTP clients pool connections per host and TLS rules verify the name.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use hyper_util::client::legacy::connect::dns::Name;
+use tokio::net::TcpStream;
+use tower_service::Service;
+use tracing::warn;
+
+/// How long resolved addresses are used before the name is looked up again.
+pub(crate) const DNS_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where requests for one port are sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct HostRoute {
+    pub(crate) host: String,
+    /// Port on `host`; the requested (or replica) port when unset.
+    pub(crate) port: Option<u16>,
+}
+
+/// Ports whose upstream is a named host, each with its route. Cheap to clone.
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamHosts(Arc<HashMap<u16, HostRoute>>);
+
+impl UpstreamHosts {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Route for requests addressed to `port` (before replica selection).
+    pub(crate) fn route_for(&self, port: u16) -> Option<&HostRoute> {
+        self.0.get(&port)
+    }
+}
+
+impl FromStr for UpstreamHosts {
+    type Err = String;
+
+    /// Parse `PORT=HOST[:PORT][;PORT...]`, e.g. `5432=db;8080=api.internal:80`.
+    /// Without a port the requested port is kept.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = HashMap::new();
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (port, target) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("missing host in {entry:?}"))?;
+            let port: u16 = port
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid port in {entry:?}"))?;
+            let target = target.trim();
+            let (host, target_port) = match target.rsplit_once(':') {
+                Some((host, p)) => {
+                    let p = p
+                        .parse::<u16>()
+                        .map_err(|_| format!("invalid upstream port in {entry:?}"))?;
+                    (host, Some(p))
+                }
+                None => (target, None),
+            };
+            let valid = !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+            if !valid {
+                return Err(format!("invalid upstream host in {entry:?}"));
+            }
+            map.insert(
+                port,
+                HostRoute {
+                    host: host.to_ascii_lowercase(),
+                    port: target_port,
+                },
+            );
+        }
+        Ok(Self(Arc::new(map)))
+    }
+}
+
+struct CachedAddrs {
+    addrs: Arc<[IpAddr]>,
+    resolved_at: Instant,
+}
+
+/// Resolved addresses by hostname.
+#[derive(Default)]
+struct DnsCache {
+    entries: Mutex<HashMap<String, CachedAddrs>>,
+}
+
+impl DnsCache {
+    fn shared() -> &'static DnsCache {
+        static CACHE: OnceLock<DnsCache> = OnceLock::new();
+        CACHE.get_or_init(DnsCache::default)
+    }
+
+    async fn lookup(&self, host: &str) -> io::Result<Arc<[IpAddr]>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(Arc::new([ip]));
+        }
+        let stale = {
+            let entries = self.entries.lock().unwrap();
+            match entries.get(host) {
+                Some(entry) if entry.resolved_at.elapsed() < DNS_REFRESH_INTERVAL => {
+                    return Ok(entry.addrs.clone());
+                }
+                entry => entry.map(|entry| entry.addrs.clone()),
+            }
+        };
+        let resolved = tokio::net::lookup_host((host, 0)).await.and_then(|addrs| {
+            let addrs: Arc<[IpAddr]> = addrs.map(|addr| addr.ip()).collect();
+            if addrs.is_empty() {
+                Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no addresses for {host}"),
+                ))
+            } else {
+                Ok(addrs)
+            }
+        });
+        match (resolved, stale) {
+            (Ok(addrs), _) => {
+                self.entries.lock().unwrap().insert(
+                    host.to_string(),
+                    CachedAddrs {
+                        addrs: addrs.clone(),
+                        resolved_at: Instant::now(),
+                    },
+                );
+                Ok(addrs)
+            }
+            (Err(e), Some(addrs)) => {
+                // Leave the entry expired so the next request retries.
+                warn!(%e, %host, "re-resolving upstream failed, using stale addresses");
+                Ok(addrs)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+}
+
+/// Resolver for the proxy's HTTP connectors, backed by the shared cache.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CachedResolver;
+
+impl Service<Name> for CachedResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        Box::pin(async move {
+            let addrs = DnsCache::shared().lookup(name.as_str()).await?;
+            // The connector fills in the port from the URI.
+            let addrs: Vec<SocketAddr> = addrs.iter().map(|ip| SocketAddr::new(*ip, 0)).collect();
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// Connect to `host:port` through the shared cache, trying each address in turn.
+pub(crate) async fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for ip in DnsCache::shared().lookup(host).await?.iter() {
+        match TcpStream::connect(SocketAddr::new(*ip, port)).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, host.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_host_rules() {
+        let hosts: UpstreamHosts = "5432=db; 8080=API.internal:80".parse().unwrap();
+        assert_eq!(
+            hosts.route_for(5432),
+            Some(&HostRoute {
+                host: "db".into(),
+                port: None
+            })
+        );
+        assert_eq!(
+            hosts.route_for(8080),
+            Some(&HostRoute {
+                host: "api.internal".into(),
+                port: Some(80)
+            })
+        );
+        assert!(hosts.route_for(3000).is_none());
+        assert!("".parse::<UpstreamHosts>().unwrap().is_empty());
+
+        for bad in [
+            "5432",
+            "x=db",
+            "5432=",
+            "5432=db:x",
+            "5432=db/x",
+            "5432=:80",
+        ] {
+            assert!(bad.parse::<UpstreamHosts>().is_err(), "{bad} should fail");
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_serves_fresh_entries_and_refreshes_expired_ones() {
+        let cache = DnsCache::default();
+        let ip: IpAddr = "10.1.2.3".parse().unwrap();
+        cache.entries.lock().unwrap().insert(
+            "svc.invalid".into(),
+            CachedAddrs {
+                addrs: Arc::new([ip]),
+                resolved_at: Instant::now(),
+            },
+        );
+        assert_eq!(&*cache.lookup("svc.invalid").await.unwrap(), &[ip]);
+
+        let literal = cache.lookup("127.0.0.1").await.unwrap();
+        assert_eq!(&*literal, &[IpAddr::from([127, 0, 0, 1])]);
+
+        let stale = Instant::now() - DNS_REFRESH_INTERVAL * 2;
+        cache.entries.lock().unwrap().insert(
+            "localhost".into(),
+            CachedAddrs {
+                addrs: Arc::new([ip]),
+                resolved_at: stale,
+            },
+        );
+        let refreshed = cache.lookup("localhost").await.unwrap();
+        assert!(refreshed.iter().all(IpAddr::is_loopback));
+        assert!(cache.entries.lock().unwrap()["localhost"].resolved_at > stale);
+    }
+}
@@ -0,0 +1,182 @@
+//! Per-request upstream overrides.
+//!
+//! Internal tools sometimes need to reach a service bound on an interface no
+//! routing rule covers, such as a sidecar on a pod IP or a debug server on an
+//! odd port. An `X-Cmux-Upstream: HOST:PORT` header sends one request there
+//! instead of wherever the other headers would route it, but only when the
+//! target matches a configured safelist pattern; anything else is refused.
+//! With no patterns configured the header is always refused. Every override,
+//! allowed or not, is logged under the [`AUDIT_TARGET`] tracing target.
+
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub(crate) const UPSTREAM_OVERRIDE_HEADER: &str = "X-Cmux-Upstream";
+
+/// Tracing target of override audit events, e.g. `RUST_LOG=cmux_proxy::audit=info`.
+pub const AUDIT_TARGET: &str = "cmux_proxy::audit";
+
+/// One safelist entry: a host glob and a port range.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct TargetPattern {
+    /// Lowercase host, where `*` matches any run of characters
+    host: String,
+    ports: RangeInclusive<u16>,
+}
+
+impl TargetPattern {
+    fn matches(&self, host: &str, port: u16) -> bool {
+        self.ports.contains(&port) && glob_match(&self.host, host)
+    }
+}
+
+/// Targets the `X-Cmux-Upstream` header may route to. Cheap to clone.
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamOverrides(Arc<Vec<TargetPattern>>);
+
+impl UpstreamOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The host and port a header value asks for, if the safelist allows it;
+    /// otherwise why it was refused.
+    pub(crate) fn check(&self, value: &str) -> Result<(String, u16), &'static str> {
+        if self.is_empty() {
+            return Err("overrides are disabled");
+        }
+        let (host, port) = parse_target(value).ok_or("must be HOST:PORT")?;
+        if !self.0.iter().any(|pattern| pattern.matches(&host, port)) {
+            return Err("target is not safelisted");
+        }
+        Ok((host, port))
+    }
+}
+
+impl FromStr for UpstreamOverrides {
+    type Err = String;
+
+    /// Parse `HOST:PORTS[;HOST:PORTS...]`, where `HOST` may contain `*`
+    /// wildcards and `PORTS` is a port, a `LOW-HIGH` range or `*`, e.g.
+    /// `10.0.*:8000-8999;*.internal:*`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut patterns = Vec::new();
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (host, ports) = entry
+                .rsplit_once(':')
+                .ok_or_else(|| format!("missing port in {entry:?}"))?;
+            let host = host.trim();
+            if host.is_empty() || !host.chars().all(|c| is_host_char(c) || c == '*') {
+                return Err(format!("invalid host pattern in {entry:?}"));
+            }
+            let ports = match ports.trim() {
+                "*" => 1..=u16::MAX,
+                ports => {
+                    let (low, high) = ports.split_once('-').unwrap_or((ports, ports));
+                    let parse = |p: &str| p.trim().parse::<u16>().ok().filter(|p| *p != 0);
+                    match (parse(low), parse(high)) {
+                        (Some(low), Some(high)) if low <= high => low..=high,
+                        _ => return Err(format!("invalid port range in {entry:?}")),
+                    }
+                }
+            };
+            patterns.push(TargetPattern {
+                host: host.to_ascii_lowercase(),
+                ports,
+            });
+        }
+        Ok(Self(Arc::new(patterns)))
+    }
+}
+
+fn is_host_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')
+}
+
+/// Split a `HOST:PORT` header value, lowercasing the host.
+fn parse_target(value: &str) -> Option<(String, u16)> {
+    let (host, port) = value.trim().rsplit_once(':')?;
+    let port = port.parse::<u16>().ok().filter(|p| *p != 0)?;
+    if host.is_empty() || !host.chars().all(is_host_char) {
+        return None;
+    }
+    Some((host.to_ascii_lowercase(), port))
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard: the whole text must match
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_patterns_and_checks_targets() {
+        let overrides: UpstreamOverrides =
+            "10.0.*:8000-8999; *.Internal:* ;db:5432".parse().unwrap();
+        assert_eq!(
+            overrides.check("10.0.3.7:8080"),
+            Ok(("10.0.3.7".into(), 8080))
+        );
+        assert_eq!(
+            overrides.check(" API.internal:1 "),
+            Ok(("api.internal".into(), 1))
+        );
+        assert!(overrides.check("db:5432").is_ok());
+        assert_eq!(
+            overrides.check("10.0.3.7:9000"),
+            Err("target is not safelisted")
+        );
+        assert_eq!(
+            overrides.check("internal:80"),
+            Err("target is not safelisted")
+        );
+        assert_eq!(overrides.check("db:5433"), Err("target is not safelisted"));
+        assert_eq!(overrides.check("xdb:5432"), Err("target is not safelisted"));
+        for bad in ["db", "db:", ":5432", "db:0", "db/x:5432", "db:x"] {
+            assert_eq!(overrides.check(bad), Err("must be HOST:PORT"), "{bad}");
+        }
+
+        let disabled: UpstreamOverrides = "".parse().unwrap();
+        assert!(disabled.is_empty());
+        assert_eq!(disabled.check("db:5432"), Err("overrides are disabled"));
+
+        for bad in ["db", "db:", ":80", "db:0", "db:90-80", "db:x", "d/b:80"] {
+            assert!(
+                bad.parse::<UpstreamOverrides>().is_err(),
+                "{bad} should fail"
+            );
+        }
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*c", "abbc"));
+        assert!(glob_match("a*c", "ac"));
+        assert!(!glob_match("a*c", "abcd"));
+        assert!(glob_match("*.svc.*", "api.svc.cluster"));
+        assert!(!glob_match("a*a", "a"));
+        assert!(!glob_match("abc", "abcd"));
+    }
+}
@@ -0,0 +1,308 @@
+//! Outbound TLS for upstreams that only listen with HTTPS.
+//!
+//! Some workspace services (often with a self-signed certificate) refuse
+//! plaintext. Ports with a TLS rule are proxied over HTTPS instead, verifying
+//! the upstream against a configured CA (or not at all, when the rule says
+//! `insecure`) and optionally presenting a client certificate for mTLS.
+//! CONNECT tunnels and TLS passthrough are unaffected: they never terminate TLS.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+
+use crate::upstream_hosts::CachedResolver;
+use crate::{configure_http_client_builder, BoxBody};
+
+pub(crate) type HttpsClient = Client<HttpsConnector<HttpConnector<CachedResolver>>, BoxBody>;
+
+#[derive(Clone)]
+struct TlsRule {
+    client: HttpsClient,
+    /// Kept for `Debug`, so logs show how each port is reached.
+    summary: String,
+}
+
+/// Ports whose upstreams are reached over HTTPS, each with its own client.
+/// Cheap to clone.
+#[derive(Clone, Default)]
+pub struct UpstreamTls(Arc<HashMap<u16, TlsRule>>);
+
+impl UpstreamTls {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// HTTPS client for requests addressed to `port` (before replica selection).
+    pub(crate) fn client_for(&self, port: u16) -> Option<&HttpsClient> {
+        self.0.get(&port).map(|rule| &rule.client)
+    }
+}
+
+impl fmt::Debug for UpstreamTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ports: Vec<_> = self.0.iter().collect();
+        ports.sort_by_key(|(port, _)| **port);
+        f.debug_map()
+            .entries(ports.into_iter().map(|(port, rule)| (port, &rule.summary)))
+            .finish()
+    }
+}
+
+/// Options of a single rule, before certificates are loaded.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RuleSpec {
+    insecure: bool,
+    ca: Option<String>,
+    cert: Option<String>,
+    key: Option<String>,
+    sni: Option<String>,
+}
+
+impl RuleSpec {
+    fn parse(entry: &str, options: &str) -> Result<Self, String> {
+        let mut spec = RuleSpec::default();
+        for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().to_string())),
+                None => (option, None),
+            };
+            let slot = match name {
+                "insecure" if value.is_none() => {
+                    spec.insecure = true;
+                    continue;
+                }
+                "ca" => &mut spec.ca,
+                "cert" => &mut spec.cert,
+                "key" => &mut spec.key,
+                "sni" => &mut spec.sni,
+                _ => return Err(format!("unknown TLS option {option:?} in {entry:?}")),
+            };
+            *slot = Some(value.ok_or_else(|| format!("{name} needs a value in {entry:?}"))?);
+        }
+        if spec.cert.is_some() != spec.key.is_some() {
+            return Err(format!("cert and key must be given together in {entry:?}"));
+        }
+        if spec.insecure && spec.ca.is_some() {
+            return Err(format!(
+                "insecure and ca are mutually exclusive in {entry:?}"
+            ));
+        }
+        Ok(spec)
+    }
+
+    fn summary(&self) -> String {
+        let verify = match (&self.ca, self.insecure) {
+            (_, true) => "insecure".to_string(),
+            (Some(ca), _) => format!("ca={ca}"),
+            (None, false) => "webpki-roots".to_string(),
+        };
+        let mut out = format!("https ({verify}");
+        if self.cert.is_some() {
+            out.push_str(", mtls");
+        }
+        if let Some(sni) = &self.sni {
+            out.push_str(&format!(", sni={sni}"));
+        }
+        out.push(')');
+        out
+    }
+
+    fn client_config(&self) -> Result<ClientConfig, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?;
+        let builder = if self.insecure {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(SkipVerify(provider)))
+        } else {
+            let mut roots = RootCertStore::empty();
+            match &self.ca {
+                Some(path) => {
+                    for cert in read_certs(path)? {
+                        roots
+                            .add(cert)
+                            .map_err(|e| format!("invalid CA certificate in {path}: {e}"))?;
+                    }
+                }
+                None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+            }
+            builder.with_root_certificates(roots)
+        };
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                let key = PrivateKeyDer::from_pem_file(key)
+                    .map_err(|e| format!("reading private key {key}: {e}"))?;
+                builder
+                    .with_client_auth_cert(read_certs(cert)?, key)
+                    .map_err(|e| format!("invalid client identity: {e}"))
+            }
+            _ => Ok(builder.with_no_client_auth()),
+        }
+    }
+
+    fn build(self) -> Result<TlsRule, String> {
+        let config = self.client_config()?;
+        let builder = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(config)
+            .https_only();
+        let builder = match &self.sni {
+            Some(sni) => {
+                let name = ServerName::try_from(sni.clone())
+                    .map_err(|_| format!("invalid sni {sni:?}"))?;
+                builder.with_server_name_resolver(FixedServerNameResolver::new(name))
+            }
+            None => builder,
+        };
+        let mut http = HttpConnector::new_with_resolver(CachedResolver);
+        http.set_connect_timeout(Some(Duration::from_secs(5)));
+        http.enforce_http(false);
+        let connector = builder.enable_http1().wrap_connector(http);
+
+        let mut client_builder = Client::builder(TokioExecutor::new());
+        configure_http_client_builder(&mut client_builder);
+        Ok(TlsRule {
+            client: client_builder.build(connector),
+            summary: self.summary(),
+        })
+    }
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("reading certificates {path}: {e}"))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {path}"));
+    }
+    Ok(certs)
+}
+
+impl FromStr for UpstreamTls {
+    type Err = String;
+
+    /// Parse `PORT[=OPT,OPT...][;PORT...]`, e.g.
+    /// `8443=insecure;9443=ca=/etc/ca.pem,cert=/etc/c.pem,key=/etc/k.pem`.
+    /// Options are `insecure`, `ca=PATH`, `cert=PATH`, `key=PATH` and
+    /// `sni=NAME` (the name to verify instead of the upstream host). A bare
+    /// port verifies against the public webpki roots. Certificates are read
+    /// here so bad paths fail at startup.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = HashMap::new();
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (port, options) = entry.split_once('=').unwrap_or((entry, ""));
+            let port: u16 = port
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid port in {entry:?}"))?;
+            map.insert(port, RuleSpec::parse(entry, options)?.build()?);
+        }
+        Ok(Self(Arc::new(map)))
+    }
+}
+
+/// Accepts any server certificate, for rules marked `insecure`. Handshake
+/// signatures are still checked so the connection is at least consistent.
+#[derive(Debug)]
+struct SkipVerify(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipVerify {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rule_options() {
+        assert_eq!(
+            RuleSpec::parse("e", "ca=/ca.pem, cert=/c.pem,key=/k.pem,sni=svc.local").unwrap(),
+            RuleSpec {
+                insecure: false,
+                ca: Some("/ca.pem".into()),
+                cert: Some("/c.pem".into()),
+                key: Some("/k.pem".into()),
+                sni: Some("svc.local".into()),
+            }
+        );
+        assert!(RuleSpec::parse("e", "insecure").unwrap().insecure);
+        assert_eq!(RuleSpec::parse("e", "").unwrap(), RuleSpec::default());
+
+        for bad in ["cert=/c.pem", "insecure,ca=/ca.pem", "ca", "verify=no"] {
+            assert!(RuleSpec::parse("e", bad).is_err(), "{bad} should fail");
+        }
+    }
+
+    #[test]
+    fn builds_clients_per_port() {
+        let tls: UpstreamTls = "8443=insecure; 9443".parse().unwrap();
+        assert!(tls.client_for(8443).is_some());
+        assert!(tls.client_for(9443).is_some());
+        assert!(tls.client_for(3000).is_none());
+        assert_eq!(
+            format!("{tls:?}"),
+            r#"{8443: "https (insecure)", 9443: "https (webpki-roots)"}"#
+        );
+
+        assert!("x=insecure".parse::<UpstreamTls>().is_err());
+        assert!("8443=ca=/nonexistent/ca.pem"
+            .parse::<UpstreamTls>()
+            .unwrap_err()
+            .contains("/nonexistent/ca.pem"));
+        assert!("".parse::<UpstreamTls>().unwrap().is_empty());
+    }
+}
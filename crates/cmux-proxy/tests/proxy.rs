@@ -272,6 +272,13 @@ async fn start_proxy(
         listen,
         upstream_host: upstream_host.to_string(),
         allow_default_upstream,
+        ssh_upstream: None,
+        replicas: Default::default(),
+        rewrites: Default::default(),
+        upstream_tls: Default::default(),
+        upstream_hosts: Default::default(),
+        upstream_overrides: Default::default(),
+        port_fallback: Default::default(),
     };
     let (tx, rx) = oneshot::channel::<()>();
     let (bound, handle) = cmux_proxy::spawn_proxy(
@@ -772,3 +779,694 @@ async fn test_missing_host_override_keeps_host_header() {
     let _ = shutdown.send(());
     let _ = handle.await;
 }
+
+fn client_hello_with_sni(sni: &str) -> Vec<u8> {
+    let name = sni.as_bytes();
+    let mut ext = vec![0x00, 0x00];
+    ext.extend_from_slice(&((name.len() + 5) as u16).to_be_bytes());
+    ext.extend_from_slice(&((name.len() + 3) as u16).to_be_bytes());
+    ext.push(0);
+    ext.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    ext.extend_from_slice(name);
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&[0u8; 32]);
+    body.extend_from_slice(&[0x00, 0x00, 0x02, 0x13, 0x01, 0x01, 0x00]);
+    body.extend_from_slice(&(ext.len() as u16).to_be_bytes());
+    body.extend_from_slice(&ext);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend_from_slice(&((body.len() + 4) as u16).to_be_bytes());
+    record.push(0x01);
+    record.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    record.extend_from_slice(&body);
+    record
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tls_passthrough_routes_by_sni() {
+    let (echo_addr, _echo_handle) = start_upstream_tcp_echo().await;
+    let (proxy_addr, shutdown, handle) = start_proxy(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        "127.0.0.1",
+        true,
+    )
+    .await;
+
+    // The "TLS server" is a TCP echo, so the ClientHello comes straight back
+    let hello = client_hello_with_sni(&format!("ws-{}.localhost", echo_addr.port()));
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(&hello).await.unwrap();
+    let mut recv = vec![0u8; hello.len()];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut recv))
+        .await
+        .expect("echo timeout")
+        .unwrap();
+    assert_eq!(recv, hello);
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_ssh_passthrough_to_configured_upstream() {
+    let (echo_addr, _echo_handle) = start_upstream_tcp_echo().await;
+    let cfg = ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        upstream_host: "127.0.0.1".to_string(),
+        allow_default_upstream: false,
+        ssh_upstream: Some(echo_addr),
+        replicas: Default::default(),
+        rewrites: Default::default(),
+        upstream_tls: Default::default(),
+        upstream_hosts: Default::default(),
+        upstream_overrides: Default::default(),
+        port_fallback: Default::default(),
+    };
+    let (tx, rx) = oneshot::channel::<()>();
+    let (proxy_addr, handle) = cmux_proxy::spawn_proxy(
+        cfg,
+        async move {
+            let _ = rx.await;
+        }
+        .boxed(),
+    );
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let banner = b"SSH-2.0-OpenSSH_9.6\r\n";
+    stream.write_all(banner).await.unwrap();
+    let mut recv = vec![0u8; banner.len()];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut recv))
+        .await
+        .expect("echo timeout")
+        .unwrap();
+    assert_eq!(&recv, banner);
+
+    let _ = tx.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_replicas_round_robin_and_stick_by_cookie() {
+    let first = start_upstream_http().await;
+    let second = start_upstream_http().await;
+    let cfg = ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        upstream_host: "127.0.0.1".to_string(),
+        allow_default_upstream: true,
+        ssh_upstream: None,
+        replicas: format!("1={},{}", first.port(), second.port())
+            .parse()
+            .unwrap(),
+        rewrites: Default::default(),
+        upstream_tls: Default::default(),
+        upstream_hosts: Default::default(),
+        upstream_overrides: Default::default(),
+        port_fallback: Default::default(),
+    };
+    let (tx, rx) = oneshot::channel::<()>();
+    let (proxy_addr, handle) = cmux_proxy::spawn_proxy(
+        cfg,
+        async move {
+            let _ = rx.await;
+        }
+        .boxed(),
+    );
+
+    let client = new_test_client();
+    let send = |cookie: Option<String>| {
+        let mut req = Request::builder()
+            .uri(format!("http://{}/r", proxy_addr))
+            .header("X-Cmux-Port-Internal", "1");
+        if let Some(cookie) = cookie {
+            req = req.header("Cookie", cookie);
+        }
+        client.request(req.body(Empty::new()).unwrap())
+    };
+
+    let mut pinned = Vec::new();
+    for _ in 0..2 {
+        let resp = timeout(Duration::from_secs(5), send(None))
+            .await
+            .expect("resp timeout")
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cookie = resp.headers()["set-cookie"].to_str().unwrap().to_string();
+        pinned.push(cookie.split(';').next().unwrap().to_string());
+    }
+    assert_eq!(
+        pinned,
+        vec![
+            format!("cmux_affinity={}", first.port()),
+            format!("cmux_affinity={}", second.port()),
+        ]
+    );
+
+    // With the cookie the same replica is reused and no new cookie is issued
+    let resp = timeout(Duration::from_secs(5), send(Some(pinned[1].clone())))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!resp.headers().contains_key("set-cookie"));
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"ok:GET:/r");
+
+    let _ = tx.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_upstream_host_rules_route_by_name() {
+    let upstream = start_upstream_http().await;
+    let (echo_addr, _echo_handle) = start_upstream_tcp_echo().await;
+    let (tls_echo_addr, _tls_echo_handle) = start_upstream_tcp_echo().await;
+    let cfg = ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        // Unroutable, so only the host rules can reach the upstreams
+        upstream_host: "192.0.2.1".to_string(),
+        allow_default_upstream: true,
+        ssh_upstream: None,
+        replicas: Default::default(),
+        rewrites: Default::default(),
+        upstream_tls: Default::default(),
+        upstream_hosts: format!(
+            "1=localhost:{};2=localhost:{};3=localhost:{}",
+            upstream.port(),
+            echo_addr.port(),
+            tls_echo_addr.port()
+        )
+        .parse()
+        .unwrap(),
+        upstream_overrides: Default::default(),
+        port_fallback: Default::default(),
+    };
+    let (tx, rx) = oneshot::channel::<()>();
+    let (proxy_addr, handle) = cmux_proxy::spawn_proxy(
+        cfg,
+        async move {
+            let _ = rx.await;
+        }
+        .boxed(),
+    );
+
+    // Host rules take precedence over the workspace header
+    let client = new_test_client();
+    let req = Request::builder()
+        .uri(format!("http://{}/named", proxy_addr))
+        .header("X-Cmux-Port-Internal", "1")
+        .header("X-Cmux-Workspace-Internal", "workspace-1")
+        .body(Empty::new())
+        .unwrap();
+    let resp = timeout(Duration::from_secs(5), client.request(req))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"ok:GET:/named");
+
+    // CONNECT tunnels resolve the name too
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(b"CONNECT foo HTTP/1.1\r\nHost: foo\r\nX-Cmux-Port-Internal: 2\r\n\r\n")
+        .await
+        .unwrap();
+    let mut resp_buf = Vec::new();
+    let mut tmp = [0u8; 1024];
+    while !resp_buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = timeout(Duration::from_secs(5), stream.read(&mut tmp))
+            .await
+            .expect("read timeout")
+            .unwrap();
+        assert!(n > 0);
+        resp_buf.extend_from_slice(&tmp[..n]);
+    }
+    assert!(resp_buf.starts_with(b"HTTP/1.1 200"));
+    stream.write_all(b"ping\n").await.unwrap();
+    let mut recv = [0u8; 5];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut recv))
+        .await
+        .expect("echo timeout")
+        .unwrap();
+    assert_eq!(&recv, b"ping\n");
+
+    // So does TLS passthrough, where the port comes from the SNI
+    let hello = client_hello_with_sni("ws-3.example.test");
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(&hello).await.unwrap();
+    let mut recv = vec![0u8; hello.len()];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut recv))
+        .await
+        .expect("echo timeout")
+        .unwrap();
+    assert_eq!(recv, hello);
+
+    let _ = tx.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_upstream_override_header_needs_safelisted_target() {
+    let upstream = start_upstream_http().await;
+    let (echo_addr, _echo_handle) = start_upstream_tcp_echo().await;
+    let cfg = ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        // Unroutable, so only the override can reach the upstreams
+        upstream_host: "192.0.2.1".to_string(),
+        allow_default_upstream: true,
+        ssh_upstream: None,
+        replicas: Default::default(),
+        rewrites: Default::default(),
+        // Port 1's own upstream speaks TLS; the override target doesn't
+        upstream_tls: "1=insecure".parse().unwrap(),
+        upstream_hosts: "1=192.0.2.2".parse().unwrap(),
+        upstream_overrides: format!("127.0.0.*:{};localhost:*", upstream.port())
+            .parse()
+            .unwrap(),
+        port_fallback: Default::default(),
+    };
+    let (tx, rx) = oneshot::channel::<()>();
+    let (proxy_addr, handle) = cmux_proxy::spawn_proxy(
+        cfg,
+        async move {
+            let _ = rx.await;
+        }
+        .boxed(),
+    );
+
+    let client = new_test_client();
+    let get = |target: String| {
+        let req = Request::builder()
+            .uri(format!("http://{}/override", proxy_addr))
+            .header("X-Cmux-Port-Internal", "1")
+            .header("X-Cmux-Upstream", target)
+            .body(Empty::new())
+            .unwrap();
+        let client = client.clone();
+        async move {
+            let resp = timeout(Duration::from_secs(5), client.request(req))
+                .await
+                .expect("resp timeout")
+                .unwrap();
+            let status = resp.status();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            (status, String::from_utf8_lossy(&body).into_owned())
+        }
+    };
+
+    // The override wins over the host rule for the port
+    let (status, body) = get(format!("127.0.0.1:{}", upstream.port())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "ok:GET:/override");
+
+    let (status, body) = get(format!("127.0.0.1:{}", echo_addr.port())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body, "X-Cmux-Upstream: target is not safelisted");
+    let (status, _) = get("192.0.2.1".to_string()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // CONNECT tunnels can be overridden too
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(
+            format!(
+                "CONNECT foo HTTP/1.1\r\nHost: foo\r\nX-Cmux-Port-Internal: 1\r\nX-Cmux-Upstream: localhost:{}\r\n\r\n",
+                echo_addr.port()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut resp_buf = Vec::new();
+    let mut tmp = [0u8; 1024];
+    while !resp_buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = timeout(Duration::from_secs(5), stream.read(&mut tmp))
+            .await
+            .expect("read timeout")
+            .unwrap();
+        assert!(n > 0);
+        resp_buf.extend_from_slice(&tmp[..n]);
+    }
+    assert!(resp_buf.starts_with(b"HTTP/1.1 200"));
+    stream.write_all(b"ping\n").await.unwrap();
+    let mut recv = [0u8; 5];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut recv))
+        .await
+        .expect("echo timeout")
+        .unwrap();
+    assert_eq!(&recv, b"ping\n");
+
+    let _ = tx.send(());
+    let _ = handle.await;
+}
+
+async fn start_upstream_html(page: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap();
+    let local = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<Incoming>| async move {
+                    // Echo Accept-Encoding so the test can see it was dropped
+                    let encoding = req
+                        .headers()
+                        .get("accept-encoding")
+                        .map(|v| v.to_str().unwrap().to_string())
+                        .unwrap_or_default();
+                    let resp = Response::builder()
+                        .header("content-type", "text/html; charset=utf-8")
+                        .header("x-accept-encoding", encoding)
+                        .body(Full::new(Bytes::from(page)))
+                        .unwrap();
+                    Ok::<_, Infallible>(resp)
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    local
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_rewrites_localhost_urls_for_opted_in_ports() {
+    const PAGE: &str = r#"<script src="http://localhost:1/app.js"></script><a href="http://127.0.0.1:2/api">api</a>"#;
+    let upstream = start_upstream_html(PAGE).await;
+    let cfg = ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        upstream_host: "127.0.0.1".to_string(),
+        allow_default_upstream: true,
+        ssh_upstream: None,
+        // Port 1 is served by the upstream and rewritten; port 2 passes through
+        replicas: format!("1={};2={}", upstream.port(), upstream.port())
+            .parse()
+            .unwrap(),
+        rewrites: "1=1,2".parse().unwrap(),
+        upstream_tls: Default::default(),
+        upstream_hosts: Default::default(),
+        upstream_overrides: Default::default(),
+        port_fallback: Default::default(),
+    };
+    let (tx, rx) = oneshot::channel::<()>();
+    let (proxy_addr, handle) = cmux_proxy::spawn_proxy(
+        cfg,
+        async move {
+            let _ = rx.await;
+        }
+        .boxed(),
+    );
+
+    let client = new_test_client();
+    let get = |port: &str| {
+        let req = Request::builder()
+            .uri(format!("http://{}/", proxy_addr))
+            .header("X-Cmux-Port-Internal", port)
+            .header("X-Forwarded-Host", "port-1-abc.example.com")
+            .header("X-Forwarded-Proto", "https")
+            .header("Accept-Encoding", "gzip")
+            .body(Empty::new())
+            .unwrap();
+        client.request(req)
+    };
+
+    let resp = timeout(Duration::from_secs(5), get("1"))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["x-accept-encoding"], "");
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        std::str::from_utf8(&body).unwrap(),
+        r#"<script src="https://port-1-abc.example.com/app.js"></script><a href="https://port-2-abc.example.com/api">api</a>"#
+    );
+
+    let resp = timeout(Duration::from_secs(5), get("2"))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.headers()["x-accept-encoding"], "gzip");
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], PAGE.as_bytes());
+
+    let _ = tx.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test]
+async fn test_port_fallback_lists_or_redirects_to_live_ports() {
+    let live = start_upstream_http().await;
+    let closed = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let start = |fallback: String| {
+        let cfg = ProxyConfig {
+            listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            upstream_host: "127.0.0.1".to_string(),
+            allow_default_upstream: true,
+            ssh_upstream: None,
+            replicas: Default::default(),
+            rewrites: Default::default(),
+            upstream_tls: Default::default(),
+            upstream_hosts: Default::default(),
+            upstream_overrides: Default::default(),
+            port_fallback: fallback.parse().unwrap(),
+        };
+        let (tx, rx) = oneshot::channel::<()>();
+        let (proxy_addr, handle) = cmux_proxy::spawn_proxy(
+            cfg,
+            async move {
+                let _ = rx.await;
+            }
+            .boxed(),
+        );
+        (proxy_addr, tx, handle)
+    };
+    let client = new_test_client();
+    let get = |proxy_addr: SocketAddr, method: &str| {
+        let req = Request::builder()
+            .method(method)
+            .uri(format!("http://{}/app?x=1", proxy_addr))
+            .header("X-Cmux-Port-Internal", closed.to_string())
+            .header("X-Forwarded-Host", format!("ws-{closed}.example.com"))
+            .body(Empty::new())
+            .unwrap();
+        client.request(req)
+    };
+
+    let (proxy_addr, tx, handle) = start(format!("{closed},1,{}", live.port()));
+    let resp = timeout(Duration::from_secs(5), get(proxy_addr, "GET"))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(resp.headers()["content-type"], "application/json");
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        std::str::from_utf8(&body).unwrap(),
+        format!(
+            r#"{{"error":"nothing is listening on port {closed}","port":{closed},"livePorts":[{}]}}"#,
+            live.port()
+        )
+    );
+    let _ = tx.send(());
+    let _ = handle.await;
+
+    let (proxy_addr, tx, handle) = start(format!("redirect:{}", live.port()));
+    let resp = timeout(Duration::from_secs(5), get(proxy_addr, "GET"))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        resp.headers()["location"],
+        format!("http://ws-{}.example.com/app?x=1", live.port()).as_str()
+    );
+    // Only safe methods are redirected; the rest get the listing
+    let resp = timeout(Duration::from_secs(5), get(proxy_addr, "POST"))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(resp.headers()["content-type"], "application/json");
+    let _ = tx.send(());
+    let _ = handle.await;
+}
+
+struct TestPki {
+    dir: std::path::PathBuf,
+    server: tokio_rustls::rustls::ServerConfig,
+    mtls_server: tokio_rustls::rustls::ServerConfig,
+}
+
+/// A CA, a server certificate for 127.0.0.1 and a client identity, written to
+/// `dir` as ca.pem, client.pem and client.key.
+fn test_pki(name: &str) -> TestPki {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use std::sync::Arc;
+    use tokio_rustls::rustls::{
+        crypto::ring::default_provider, pki_types::PrivateKeyDer, server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    };
+
+    let mut ca_params = CertificateParams::new(vec![]).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_key = KeyPair::generate().unwrap();
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let server_key = KeyPair::generate().unwrap();
+    let server_cert = CertificateParams::new(vec!["127.0.0.1".to_string()])
+        .unwrap()
+        .signed_by(&server_key, &ca, &ca_key)
+        .unwrap();
+    let client_key = KeyPair::generate().unwrap();
+    let client_cert = CertificateParams::new(vec!["client".to_string()])
+        .unwrap()
+        .signed_by(&client_key, &ca, &ca_key)
+        .unwrap();
+
+    let dir = std::env::temp_dir().join(format!("cmux-proxy-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+    std::fs::write(dir.join("client.pem"), client_cert.pem()).unwrap();
+    std::fs::write(dir.join("client.key"), client_key.serialize_pem()).unwrap();
+
+    let provider = Arc::new(default_provider());
+    let server_config = |client_verifier: Option<
+        Arc<dyn tokio_rustls::rustls::server::danger::ClientCertVerifier>,
+    >| {
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = match client_verifier {
+            Some(verifier) => builder.with_client_cert_verifier(verifier),
+            None => builder.with_no_client_auth(),
+        };
+        builder
+            .with_single_cert(
+                vec![server_cert.der().clone()],
+                PrivateKeyDer::try_from(server_key.serialize_der()).unwrap(),
+            )
+            .unwrap()
+    };
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone()).unwrap();
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .unwrap();
+
+    TestPki {
+        dir,
+        server: server_config(None),
+        mtls_server: server_config(Some(verifier)),
+    }
+}
+
+async fn start_upstream_https(config: tokio_rustls::rustls::ServerConfig) -> SocketAddr {
+    let acceptor = tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(config));
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap();
+    let local = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let service = service_fn(|req: Request<Incoming>| async move {
+                    let body = format!("tls:{}", req.uri().path());
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(body))))
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    local
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_upstream_tls_rules_with_ca_mtls_and_insecure() {
+    let pki = test_pki("upstream-tls");
+    let tls_upstream = start_upstream_https(pki.server).await;
+    let mtls_upstream = start_upstream_https(pki.mtls_server).await;
+    let pem = |file: &str| pki.dir.join(file).display().to_string();
+
+    let cfg = ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        upstream_host: "127.0.0.1".to_string(),
+        allow_default_upstream: true,
+        ssh_upstream: None,
+        // 1: mTLS with the CA; 2: self-signed accepted blindly; 3: CA but no
+        // client identity; 4: no rule, so plaintext hits a TLS listener
+        replicas: format!(
+            "1={m};2={t};3={m};4={t}",
+            m = mtls_upstream.port(),
+            t = tls_upstream.port()
+        )
+        .parse()
+        .unwrap(),
+        rewrites: Default::default(),
+        upstream_tls: format!(
+            "1=ca={ca},cert={cert},key={key};2=insecure;3=ca={ca}",
+            ca = pem("ca.pem"),
+            cert = pem("client.pem"),
+            key = pem("client.key"),
+        )
+        .parse()
+        .unwrap(),
+        upstream_hosts: Default::default(),
+        upstream_overrides: Default::default(),
+        port_fallback: Default::default(),
+    };
+    let (tx, rx) = oneshot::channel::<()>();
+    let (proxy_addr, handle) = cmux_proxy::spawn_proxy(
+        cfg,
+        async move {
+            let _ = rx.await;
+        }
+        .boxed(),
+    );
+
+    let client = new_test_client();
+    let get = |port: &str| {
+        let req = Request::builder()
+            .uri(format!("http://{}/hello", proxy_addr))
+            .header("X-Cmux-Port-Internal", port)
+            .body(Empty::new())
+            .unwrap();
+        client.request(req)
+    };
+
+    for port in ["1", "2"] {
+        let resp = timeout(Duration::from_secs(5), get(port))
+            .await
+            .expect("resp timeout")
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "port {port}");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"tls:/hello");
+    }
+    for port in ["3", "4"] {
+        let resp = timeout(Duration::from_secs(5), get(port))
+            .await
+            .expect("resp timeout")
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY, "port {port}");
+    }
+
+    let _ = std::fs::remove_dir_all(&pki.dir);
+    let _ = tx.send(());
+    let _ = handle.await;
+}
@@ -85,6 +85,13 @@ async fn start_proxy(
         listen,
         upstream_host: upstream_host.to_string(),
         allow_default_upstream,
+        ssh_upstream: None,
+        replicas: Default::default(),
+        rewrites: Default::default(),
+        upstream_tls: Default::default(),
+        upstream_hosts: Default::default(),
+        upstream_overrides: Default::default(),
+        port_fallback: Default::default(),
     };
     let (tx, rx) = oneshot::channel::<()>();
     let (bound, handle) = cmux_proxy::spawn_proxy(
@@ -31,6 +31,8 @@ pub struct SessionInfo {
     pub created_at: f64,
     pub alive: bool,
     pub pid: u32,
+    #[serde(default)]
+    pub group: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +52,13 @@ pub struct CreateSessionRequest {
     pub rows: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloseGroupResponse {
+    pub ids: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,6 +116,7 @@ impl PtyClient {
         cwd: Option<String>,
         cols: Option<u16>,
         rows: Option<u16>,
+        group: Option<String>,
     ) -> Result<SessionInfo> {
         let url = format!("{}/sessions", self.base_url);
         let request = CreateSessionRequest {
@@ -115,6 +125,7 @@ impl PtyClient {
             cols,
             rows,
             name,
+            group,
         };
 
         let resp = self
@@ -135,6 +146,46 @@ impl PtyClient {
         Ok(session)
     }
 
+    /// List the sessions of a workspace/task group, dead ones included
+    pub async fn list_group(&self, group: &str) -> Result<Vec<SessionInfo>> {
+        let url = format!("{}/groups/{}", self.base_url, group);
+        let resp = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to connect to server")?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("Server returned {}: {}", status, body);
+        }
+
+        let data: SessionListResponse = resp.json().await.context("Failed to parse response")?;
+        Ok(data.sessions)
+    }
+
+    /// Kill every session in a group, returning the closed session IDs
+    pub async fn close_group(&self, group: &str) -> Result<Vec<String>> {
+        let url = format!("{}/groups/{}", self.base_url, group);
+        let resp = self
+            .client
+            .delete(&url)
+            .send()
+            .await
+            .context("Failed to connect to server")?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("Server returned {}: {}", status, body);
+        }
+
+        let data: CloseGroupResponse = resp.json().await.context("Failed to parse response")?;
+        Ok(data.ids)
+    }
+
     /// Kill/delete a session
     pub async fn kill_session(&self, session_id: &str) -> Result<()> {
         // First try to find by name if it's not a UUID
@@ -480,9 +531,12 @@ fn key_event_to_bytes(key: &event::KeyEvent) -> Vec<u8> {
 // CLI Commands
 // =============================================================================
 
-pub async fn cmd_list(server: &str, json: bool) -> Result<()> {
+pub async fn cmd_list(server: &str, group: Option<&str>, json: bool) -> Result<()> {
     let client = PtyClient::new(server);
-    let sessions = client.list_sessions().await?;
+    let sessions = match group {
+        Some(group) => client.list_group(group).await?,
+        None => client.list_sessions().await?,
+    };
 
     if json {
         println!("{}", serde_json::to_string_pretty(&sessions)?);
@@ -530,6 +584,7 @@ pub async fn cmd_new(
     name: Option<String>,
     shell: Option<String>,
     cwd: Option<String>,
+    group: Option<String>,
     detached: bool,
 ) -> Result<()> {
     let client = PtyClient::new(server);
@@ -538,7 +593,7 @@ pub async fn cmd_new(
     let (cols, rows) = terminal::size().unwrap_or((80, 24));
 
     let session = client
-        .create_session(name, shell, cwd, Some(cols), Some(rows))
+        .create_session(name, shell, cwd, Some(cols), Some(rows), group)
         .await?;
 
     println!("Created session: {} ({})", session.id, session.name);
@@ -555,9 +610,14 @@ pub async fn cmd_attach(server: &str, session: &str) -> Result<()> {
     client.attach(session).await
 }
 
-pub async fn cmd_kill(server: &str, sessions: &[String]) -> Result<()> {
+pub async fn cmd_kill(server: &str, sessions: &[String], group: Option<&str>) -> Result<()> {
     let client = PtyClient::new(server);
 
+    if let Some(group) = group {
+        let ids = client.close_group(group).await?;
+        println!("Killed {} session(s) in group {}", ids.len(), group);
+    }
+
     for session_id in sessions {
         match client.kill_session(session_id).await {
             Ok(()) => println!("Killed session: {}", session_id),
@@ -6,16 +6,23 @@
 //! Also provides a CLI client for managing PTY sessions (tmux-like interface).
 
 mod cli;
+mod persist;
+mod scratch;
+mod usage;
 
 // Re-export terminal emulation library
-use cmux_terminal::{DaFilter, VirtualTerminal};
+use cmux_terminal::{detect_links, DaFilter, LinkKind, TerminalLink, VirtualTerminal};
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     env,
     io::{Read, Write as IoWrite},
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result};
@@ -26,7 +33,7 @@ use axum::{
     },
     http::StatusCode,
     response::{Html, IntoResponse, Json},
-    routing::{delete, get, patch, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use clap::{Parser, Subcommand};
@@ -34,11 +41,15 @@ use futures::{SinkExt, StreamExt};
 use parking_lot::{Mutex, RwLock};
 use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize};
 use serde::{Deserialize, Serialize};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
 use tower_http::cors::CorsLayer;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use persist::{PersistedSession, SessionStore};
+use scratch::{ScratchConfig, SCRATCH_ENV};
+use usage::{SessionUsage, UsageSampler};
+
 // =============================================================================
 // CLI Argument Parsing
 // =============================================================================
@@ -72,11 +83,30 @@ enum Commands {
         /// Port to listen on
         #[arg(short, long, env = "PTY_SERVER_PORT", default_value = "39383")]
         port: u16,
+
+        /// Directory the session layout is saved to for layout restore: after a
+        /// server restart the sessions come back under the same ids, but their
+        /// shells and running processes are lost and each gets a new shell
+        #[arg(long, env = "PTY_STATE_DIR")]
+        state_dir: Option<PathBuf>,
+
+        /// Directory conversations' scratch directories are created in
+        #[arg(long, env = "PTY_SCRATCH_ROOT")]
+        scratch_root: Option<PathBuf>,
+
+        /// Mount each conversation's scratch directory as a tmpfs of this
+        /// size, e.g. 512M
+        #[arg(long, env = "PTY_SCRATCH_TMPFS_SIZE", value_parser = scratch::parse_size)]
+        scratch_tmpfs_size: Option<u64>,
     },
 
     /// List all sessions
     #[command(visible_alias = "ls")]
     List {
+        /// Only sessions in this workspace/task group (dead ones included)
+        #[arg(short, long)]
+        group: Option<String>,
+
         /// Output as JSON
         #[arg(long)]
         json: bool,
@@ -96,6 +126,10 @@ enum Commands {
         #[arg(short, long)]
         cwd: Option<String>,
 
+        /// Workspace/task group to tag the session with
+        #[arg(short, long)]
+        group: Option<String>,
+
         /// Create session but don't attach
         #[arg(short, long)]
         detached: bool,
@@ -111,6 +145,10 @@ enum Commands {
     Kill {
         /// Session IDs, names, or indices
         sessions: Vec<String>,
+
+        /// Also kill every session in this workspace/task group
+        #[arg(short, long)]
+        group: Option<String>,
     },
 
     /// Send keys to a session
@@ -154,6 +192,15 @@ const MAX_SCROLLBACK: usize = 100_000;
 const PTY_READ_BUFFER_SIZE: usize = 4096;
 const PTY_WRITE_CHUNK_SIZE: usize = 512; // Small chunks for smooth writes
 const PTY_INPUT_CHANNEL_SIZE: usize = 1024; // Bounded channel for backpressure
+const SHARE_TOKEN_DEFAULT_TTL_SECS: u64 = 60 * 60;
+const SHARE_TOKEN_MAX_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+/// How often links are recomputed for sessions that printed since.
+const LINKS_INTERVAL: Duration = Duration::from_millis(250);
+/// How often the session layout is compared with the saved one.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(5);
+/// Shown in a restored session above its new shell's prompt.
+const RESTORED_NOTICE: &str =
+    "\x1b[2m[session restored after a server restart; the previous shell and its output are gone]\x1b[0m\r\n";
 
 // =============================================================================
 // Error Types
@@ -166,6 +213,9 @@ enum ServerError {
 
     #[error("Failed to spawn PTY: {0}")]
     PtySpawnError(String),
+
+    #[error("Share link is invalid or has expired")]
+    InvalidShareToken,
 }
 
 impl IntoResponse for ServerError {
@@ -173,6 +223,7 @@ impl IntoResponse for ServerError {
         let (status, message) = match &self {
             ServerError::SessionNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
             ServerError::PtySpawnError(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
+            ServerError::InvalidShareToken => (StatusCode::FORBIDDEN, self.to_string()),
         };
 
         let body = serde_json::json!({ "error": message });
@@ -201,6 +252,19 @@ struct CreateSessionRequest {
     /// Flexible metadata - clients can store any JSON here.
     /// Example: {"location": "editor", "type": "agent", "managed": true}
     metadata: Option<serde_json::Value>,
+    /// Inherit the environment registered for this ACP conversation.
+    /// Variables in `env` take precedence over the conversation's.
+    conversation_id: Option<String>,
+    /// Workspace or task that owns the session, so its terminals can be
+    /// listed and closed together via `/groups/:group`.
+    group: Option<String>,
+}
+
+/// Environment registered for an ACP conversation (proxy base URLs, tokens,
+/// TRACEPARENT, ...). Keys set to null are removed.
+#[derive(Debug, Clone, Deserialize)]
+struct ConversationEnvRequest {
+    env: HashMap<String, Option<String>>,
 }
 
 fn default_shell() -> String {
@@ -336,6 +400,8 @@ impl Default for CreateSessionRequest {
             name: None,
             client_id: None,
             metadata: None,
+            conversation_id: None,
+            group: None,
         }
     }
 }
@@ -346,6 +412,8 @@ struct UpdateSessionRequest {
     index: Option<usize>,
     /// Update metadata - merges with existing metadata (use null to remove keys)
     metadata: Option<serde_json::Value>,
+    /// Move the session to another group (empty string to ungroup)
+    group: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -363,6 +431,18 @@ struct SessionInfo {
     /// Flexible metadata for client use (location, type, managed flag, etc.)
     #[serde(skip_serializing_if = "Option::is_none")]
     metadata: Option<serde_json::Value>,
+    /// Workspace or task the session belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// ACP conversation the session was created for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    conversation_id: Option<String>,
+    /// Recreated after a server restart; the shell is a new process
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    restored: bool,
+    /// CPU and memory of the shell and its descendants, once sampled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<SessionUsage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -394,6 +474,79 @@ enum ServerEvent {
 
     #[serde(rename = "error")]
     Error { error: String },
+
+    #[serde(rename = "links")]
+    Links { links: Vec<LinkAnnotation> },
+
+    /// Periodic resource usage of every live session, keyed by id.
+    #[serde(rename = "usage")]
+    Usage {
+        sessions: HashMap<String, SessionUsage>,
+    },
+
+    /// Last event for a conversation: its terminals are gone and its temp
+    /// state removed.
+    #[serde(rename = "conversation_closed")]
+    ConversationClosed {
+        conversation_id: String,
+        pty_ids: Vec<String>,
+    },
+}
+
+/// A clickable region of the viewport, detected server-side after escape
+/// sequences have been applied. File paths are resolved against the shell's
+/// current directory so the editor can open them directly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LinkAnnotation {
+    start_row: usize,
+    start_col: usize,
+    end_row: usize,
+    /// Exclusive
+    end_col: usize,
+    text: String,
+    /// `"url"`, `"file"` or `"sha"`
+    kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<u32>,
+}
+
+impl LinkAnnotation {
+    /// Build an annotation, or `None` for file references that don't exist on disk.
+    fn resolve(link: TerminalLink, cwd: &std::path::Path) -> Option<Self> {
+        let (kind, url, path, line, column) = match link.kind {
+            LinkKind::Url => ("url", Some(link.text.clone()), None, None, None),
+            LinkKind::Sha => ("sha", None, None, None, None),
+            LinkKind::File { path, line, column } => {
+                let path = match path.strip_prefix("~/") {
+                    Some(rest) => std::path::PathBuf::from(env::var("HOME").ok()?).join(rest),
+                    None => cwd.join(path),
+                };
+                if !path.exists() {
+                    return None;
+                }
+                let path = path.to_string_lossy().into_owned();
+                ("file", None, Some(path), line, column)
+            }
+        };
+        Some(Self {
+            start_row: link.start_row,
+            start_col: link.start_col,
+            end_row: link.end_row,
+            end_col: link.end_col,
+            text: link.text,
+            kind: kind.to_string(),
+            url,
+            path,
+            line,
+            column,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -411,6 +564,8 @@ enum ClientMessage {
         name: Option<String>,
         client_id: Option<String>,
         metadata: Option<serde_json::Value>,
+        conversation_id: Option<String>,
+        group: Option<String>,
     },
 
     #[serde(rename = "rename_pty")]
@@ -447,6 +602,11 @@ struct PtySession {
     input_tx: std::sync::mpsc::SyncSender<Vec<u8>>, // Bounded channel for backpressure
     pid: u32,
     metadata: RwLock<Option<serde_json::Value>>,
+    group: RwLock<Option<String>>,
+    /// ACP conversation the session was created for, if any
+    conversation_id: Option<String>,
+    /// Recreated from the state directory after a server restart
+    restored: bool,
     /// DA (Device Attributes) filter to prevent feedback loops with nested terminals.
     /// Filters DA1/DA2 queries and responses that can cause infinite loops when
     /// running terminal emulators inside terminal emulators.
@@ -454,6 +614,15 @@ struct PtySession {
     /// Virtual terminal emulator for tracking terminal state.
     /// Provides server-side ANSI sequence parsing and grid-based storage.
     terminal: Mutex<VirtualTerminal>,
+    /// Link annotations for subscribers that asked for them; only computed
+    /// while someone is listening.
+    links_tx: broadcast::Sender<Vec<LinkAnnotation>>,
+    last_links: Mutex<Vec<LinkAnnotation>>,
+    /// Set on output so links are recomputed on the next tick rather than on
+    /// every read.
+    links_stale: AtomicBool,
+    /// Latest usage of the process tree, set by the sampler.
+    usage: RwLock<Option<SessionUsage>>,
 }
 
 impl PtySession {
@@ -475,6 +644,27 @@ impl PtySession {
             alive,
             pid: self.pid,
             metadata: self.metadata.read().clone(),
+            group: self.group.read().clone(),
+            conversation_id: self.conversation_id.clone(),
+            restored: self.restored,
+            usage: *self.usage.read(),
+        }
+    }
+
+    /// State saved so the session can be recreated after a restart.
+    fn to_persisted(&self) -> PersistedSession {
+        PersistedSession {
+            id: self.id.clone(),
+            name: self.name.read().clone(),
+            index: self.get_index(),
+            shell: self.shell.clone(),
+            cwd: self.current_cwd().to_string_lossy().into_owned(),
+            cols: *self.cols.read(),
+            rows: *self.rows.read(),
+            created_at: self.created_at,
+            metadata: self.metadata.read().clone(),
+            group: self.group.read().clone(),
+            conversation_id: self.conversation_id.clone(),
         }
     }
 
@@ -568,6 +758,14 @@ impl PtySession {
         *self.metadata.write() = metadata;
     }
 
+    fn in_group(&self, group: &str) -> bool {
+        self.group.read().as_deref() == Some(group)
+    }
+
+    fn set_group(&self, group: Option<String>) {
+        *self.group.write() = group;
+    }
+
     /// Process bytes through the virtual terminal emulator and collect responses.
     fn process_terminal(&self, data: &[u8]) -> Vec<Vec<u8>> {
         let mut terminal = self.terminal.lock();
@@ -592,6 +790,39 @@ impl PtySession {
         let terminal = self.terminal.lock();
         terminal.viewport_lines()
     }
+
+    /// Directory relative file references are resolved against: the shell's
+    /// live cwd where the platform exposes it, else the cwd it started in.
+    fn current_cwd(&self) -> std::path::PathBuf {
+        std::fs::read_link(format!("/proc/{}/cwd", self.pid))
+            .unwrap_or_else(|_| std::path::PathBuf::from(&self.cwd))
+    }
+
+    /// Detect links in the current viewport. Only copying the rows holds the
+    /// terminal lock; detection and resolving paths happen after.
+    fn get_terminal_links(&self) -> Vec<LinkAnnotation> {
+        let rows = self.terminal.lock().viewport_rows();
+        let links = detect_links(&rows);
+        let cwd = self.current_cwd();
+        links
+            .into_iter()
+            .filter_map(|link| LinkAnnotation::resolve(link, &cwd))
+            .collect()
+    }
+
+    /// Recompute links and notify subscribers if they changed, when output
+    /// arrived since the last time and someone is listening.
+    fn publish_links(&self) {
+        if self.links_tx.receiver_count() == 0 || !self.links_stale.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let links = self.get_terminal_links();
+        let mut last = self.last_links.lock();
+        if *last != links {
+            *last = links.clone();
+            let _ = self.links_tx.send(links);
+        }
+    }
 }
 
 // =============================================================================
@@ -602,6 +833,37 @@ struct AppState {
     sessions: RwLock<HashMap<String, Arc<PtySession>>>,
     terminal_counter: RwLock<u32>,
     event_tx: broadcast::Sender<ServerEvent>,
+    /// Environment new sessions inherit, keyed by ACP conversation id.
+    conversation_env: RwLock<HashMap<String, HashMap<String, String>>>,
+    /// Where each conversation's scratch directory goes.
+    scratch: ScratchConfig,
+    /// Read-only share links, keyed by token.
+    share_tokens: RwLock<HashMap<String, ShareToken>>,
+    /// Where sessions are saved across restarts, if anywhere.
+    store: Option<SessionStore>,
+}
+
+struct ShareToken {
+    session_id: String,
+    /// Unix seconds after which the token no longer attaches.
+    expires_at: u64,
+    /// Dropped with the token, which disconnects its viewers.
+    live: watch::Sender<()>,
+}
+
+/// What a viewer attached through a share link is held to.
+struct ShareGrant {
+    session_id: String,
+    expires_at: u64,
+    /// Closed once the token is revoked
+    revoked: watch::Receiver<()>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
 
 impl AppState {
@@ -611,9 +873,82 @@ impl AppState {
             sessions: RwLock::new(HashMap::new()),
             terminal_counter: RwLock::new(0),
             event_tx,
+            conversation_env: RwLock::new(HashMap::new()),
+            scratch: ScratchConfig::default(),
+            share_tokens: RwLock::new(HashMap::new()),
+            store: None,
+        }
+    }
+
+    fn with_store(store: SessionStore) -> Self {
+        Self {
+            store: Some(store),
+            ..Self::new()
+        }
+    }
+
+    /// Save live sessions to the store; the store skips the write when
+    /// nothing changed since the last save.
+    fn save_layout(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let sessions: Vec<PersistedSession> = self
+            .sessions
+            .read()
+            .values()
+            .filter(|s| s.is_alive())
+            .map(|s| s.to_persisted())
+            .collect();
+        if let Err(e) = store.save(sessions) {
+            warn!(
+                "Failed to save sessions to {}: {}",
+                store.dir().display(),
+                e
+            );
         }
     }
 
+    /// Mint a read-only share token for a session, dropping expired ones.
+    fn mint_share_token(&self, session_id: &str, ttl_secs: u64) -> (String, u64) {
+        let now = unix_now();
+        let expires_at = now + ttl_secs.clamp(1, SHARE_TOKEN_MAX_TTL_SECS);
+        let token = Uuid::new_v4().simple().to_string();
+        let mut tokens = self.share_tokens.write();
+        tokens.retain(|_, share| share.expires_at > now);
+        tokens.insert(
+            token.clone(),
+            ShareToken {
+                session_id: session_id.to_string(),
+                expires_at,
+                live: watch::channel(()).0,
+            },
+        );
+        (token, expires_at)
+    }
+
+    /// The session a live token shares, when the token expires, and a signal
+    /// for its revocation.
+    fn check_share_token(&self, token: &str) -> Result<ShareGrant, ServerError> {
+        match self.share_tokens.read().get(token) {
+            Some(share) if share.expires_at > unix_now() => Ok(ShareGrant {
+                session_id: share.session_id.clone(),
+                expires_at: share.expires_at,
+                revoked: share.live.subscribe(),
+            }),
+            _ => Err(ServerError::InvalidShareToken),
+        }
+    }
+
+    /// Forget a session's tokens; viewers attached through them are
+    /// disconnected.
+    fn revoke_share_tokens(&self, session_id: &str) -> usize {
+        let mut tokens = self.share_tokens.write();
+        let before = tokens.len();
+        tokens.retain(|_, share| share.session_id != session_id);
+        before - tokens.len()
+    }
+
     fn get_next_terminal_name(&self, shell: &str) -> String {
         let mut counter = self.terminal_counter.write();
         *counter += 1;
@@ -636,6 +971,10 @@ impl AppState {
         infos
     }
 
+    fn group_sessions(&self, group: &str) -> Vec<Arc<PtySession>> {
+        group_members(&self.sessions.read(), group)
+    }
+
     fn get_full_state(&self) -> ServerEvent {
         ServerEvent::StateSync {
             terminals: self.get_ordered_sessions(),
@@ -666,6 +1005,36 @@ impl AppState {
     fn broadcast_state_sync(&self) {
         self.broadcast_event(self.get_full_state());
     }
+
+    /// Record the usage of every live session and report it to subscribers.
+    fn sample_usage(&self, sampler: &mut UsageSampler) {
+        let sessions: Vec<Arc<PtySession>> = self.sessions.read().values().cloned().collect();
+        let roots: Vec<u32> = sessions.iter().map(|s| s.pid).collect();
+        let usages = sampler.sample(&roots);
+        let mut report = HashMap::new();
+        for session in sessions {
+            let usage = usages.get(&session.pid).copied();
+            *session.usage.write() = usage;
+            if let Some(usage) = usage {
+                report.insert(session.id.clone(), usage);
+            }
+        }
+        if !report.is_empty() {
+            // Not a change to the sessions, so nothing to persist
+            let _ = self.event_tx.send(ServerEvent::Usage { sessions: report });
+        }
+    }
+}
+
+/// Every session in `group`, dead ones included, in tab order.
+fn group_members(sessions: &HashMap<String, Arc<PtySession>>, group: &str) -> Vec<Arc<PtySession>> {
+    let mut members: Vec<_> = sessions
+        .values()
+        .filter(|s| s.in_group(group))
+        .cloned()
+        .collect();
+    members.sort_by_key(|s| s.get_index());
+    members
 }
 
 // =============================================================================
@@ -800,6 +1169,7 @@ async fn spawn_pty_reader(
     let mut utf8_buffer: Vec<u8> = Vec::new(); // Buffer for incomplete UTF-8 sequences
 
     info!("[reader:{}] Reader task started", session_id);
+    let links_task = tokio::spawn(publish_links_periodically(session.clone()));
 
     let mut total_bytes_read: usize = 0;
     let mut read_count: usize = 0;
@@ -891,6 +1261,8 @@ async fn spawn_pty_reader(
                     // Keep any incomplete bytes for the next read
                     utf8_buffer = utf8_buffer[valid_up_to..].to_vec();
                 }
+
+                session.links_stale.store(true, Ordering::Relaxed);
                 // If valid_up_to is 0, we're still accumulating an incomplete char
             }
             Err(e) => {
@@ -910,6 +1282,8 @@ async fn spawn_pty_reader(
         }
     }
 
+    links_task.abort();
+
     // Get exit code
     let exit_code = {
         let mut inner = session.inner.lock();
@@ -962,6 +1336,17 @@ async fn spawn_pty_reader(
 fn create_pty_session_inner(
     state: &AppState,
     request: &CreateSessionRequest,
+) -> Result<(Arc<PtySession>, Box<dyn Read + Send>), ServerError> {
+    spawn_pty_session(state, request, None)
+}
+
+/// Spawn a shell for `request`. With `restore`, the session takes over the
+/// saved session's id, position and creation time; nothing of the old shell
+/// carries over.
+fn spawn_pty_session(
+    state: &AppState,
+    request: &CreateSessionRequest,
+    restore: Option<&PersistedSession>,
 ) -> Result<(Arc<PtySession>, Box<dyn Read + Send>), ServerError> {
     // Security: Validate shell against whitelist
     let validated_shell = validate_shell(&request.shell)
@@ -988,6 +1373,27 @@ fn create_pty_session_inner(
     cmd.env("COLORTERM", "truecolor");
     cmd.env("SHELL", validated_shell);
 
+    if let Some(conversation_id) = &request.conversation_id {
+        // Temp files and pasted images land where closing the conversation
+        // can find them
+        match state.scratch.provision(conversation_id) {
+            Ok(dir) => {
+                cmd.env("TMPDIR", &dir);
+                cmd.env(SCRATCH_ENV, &dir);
+            }
+            Err(e) => warn!(
+                "Failed to create {}: {}",
+                state.scratch.dir(conversation_id).display(),
+                e
+            ),
+        }
+        if let Some(env) = state.conversation_env.read().get(conversation_id) {
+            for (key, value) in env {
+                cmd.env(key, value);
+            }
+        }
+    }
+
     if let Some(env) = &request.env {
         for (key, value) in env {
             cmd.env(key, value);
@@ -1011,18 +1417,23 @@ fn create_pty_session_inner(
         .take_writer()
         .map_err(|e| ServerError::PtySpawnError(e.to_string()))?;
 
-    let session_id = Uuid::new_v4().to_string();
+    let session_id = restore
+        .map(|saved| saved.id.clone())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
     let name = request
         .name
         .clone()
         .unwrap_or_else(|| state.get_next_terminal_name(validated_shell));
 
-    let created_at = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_secs_f64())
-        .unwrap_or(0.0);
+    let created_at = restore.map(|saved| saved.created_at).unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0)
+    });
 
     let (output_tx, _) = broadcast::channel(1024);
+    let (links_tx, _) = broadcast::channel(16);
 
     // Create bounded channel for input with backpressure
     let (input_tx, input_rx) = std::sync::mpsc::sync_channel(PTY_INPUT_CHANNEL_SIZE);
@@ -1030,7 +1441,10 @@ fn create_pty_session_inner(
     // Spawn dedicated writer thread
     spawn_pty_writer_thread(session_id.clone(), writer, input_rx);
 
-    let index = state.sessions.read().len();
+    let index = restore
+        .map(|saved| saved.index)
+        .unwrap_or_else(|| state.sessions.read().len());
+    let terminal = VirtualTerminal::new(request.rows as usize, request.cols as usize);
 
     let session = Arc::new(PtySession {
         id: session_id,
@@ -1050,16 +1464,111 @@ fn create_pty_session_inner(
         input_tx,
         pid,
         metadata: RwLock::new(request.metadata.clone()),
+        group: RwLock::new(request.group.clone().filter(|g| !g.is_empty())),
+        conversation_id: request.conversation_id.clone(),
+        restored: restore.is_some(),
         da_filter: Mutex::new(DaFilter::new()),
-        terminal: Mutex::new(VirtualTerminal::new(
-            request.rows as usize,
-            request.cols as usize,
-        )),
+        terminal: Mutex::new(terminal),
+        links_tx,
+        last_links: Mutex::new(Vec::new()),
+        links_stale: AtomicBool::new(false),
+        usage: RwLock::new(None),
     });
 
     Ok((session, reader))
 }
 
+/// Layout restore: recreate the sessions saved in the state directory, each
+/// with a new shell, since the processes that ran in them died with the
+/// previous server. Sessions that can't be respawned are dropped with a
+/// warning.
+fn restore_layout(state: &Arc<AppState>) {
+    let Some(store) = &state.store else {
+        return;
+    };
+    for saved in store.load() {
+        let request = CreateSessionRequest {
+            shell: saved.shell.clone(),
+            // The directory may be gone by now
+            cwd: validate_cwd(&saved.cwd)
+                .map(|_| saved.cwd.clone())
+                .unwrap_or_else(|_| default_cwd()),
+            cols: saved.cols,
+            rows: saved.rows,
+            name: Some(saved.name.clone()),
+            metadata: saved.metadata.clone(),
+            conversation_id: saved.conversation_id.clone(),
+            group: saved.group.clone(),
+            ..CreateSessionRequest::default()
+        };
+        let (session, reader) = match spawn_pty_session(state, &request, Some(&saved)) {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                warn!("Failed to restore session {}: {}", saved.id, e);
+                continue;
+            }
+        };
+        session.process_terminal(RESTORED_NOTICE.as_bytes());
+        session.append_scrollback(RESTORED_NOTICE);
+        info!(
+            "Restored session {} ({}, pid: {})",
+            session.id, saved.name, session.pid
+        );
+        state
+            .sessions
+            .write()
+            .insert(session.id.clone(), session.clone());
+        tokio::spawn(spawn_pty_reader(session, reader, state.clone()));
+    }
+    state.reindex_sessions();
+}
+
+/// Publish a session's links every [`LINKS_INTERVAL`] while its reader runs.
+async fn publish_links_periodically(session: Arc<PtySession>) {
+    let mut interval = tokio::time::interval(LINKS_INTERVAL);
+    loop {
+        interval.tick().await;
+        let session = session.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || session.publish_links()).await {
+            error!("Link detection task panicked: {}", e);
+        }
+    }
+}
+
+/// Save the session layout every [`PERSIST_INTERVAL`] while the server runs.
+async fn save_layout_periodically(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(PERSIST_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || state.save_layout()).await {
+            error!("Layout save task panicked: {}", e);
+        }
+    }
+}
+
+/// Sample session usage every [`usage::SAMPLE_INTERVAL`] while the server runs.
+async fn sample_usage_periodically(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(usage::SAMPLE_INTERVAL);
+    let mut sampler = UsageSampler::new();
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        match tokio::task::spawn_blocking(move || {
+            state.sample_usage(&mut sampler);
+            sampler
+        })
+        .await
+        {
+            Ok(returned) => sampler = returned,
+            Err(e) => {
+                error!("Usage sampling task panicked: {}", e);
+                return;
+            }
+        }
+    }
+}
+
 // =============================================================================
 // HTTP Handlers
 // =============================================================================
@@ -1120,6 +1629,127 @@ async fn create_session(
     Ok(Json(info))
 }
 
+async fn get_conversation_env(
+    State(state): State<Arc<AppState>>,
+    Path(conversation_id): Path<String>,
+) -> impl IntoResponse {
+    // Only report keys; values are often credentials
+    let mut keys: Vec<String> = state
+        .conversation_env
+        .read()
+        .get(&conversation_id)
+        .map(|env| env.keys().cloned().collect())
+        .unwrap_or_default();
+    keys.sort();
+    Json(serde_json::json!({
+        "conversation_id": conversation_id,
+        "keys": keys
+    }))
+}
+
+async fn set_conversation_env(
+    State(state): State<Arc<AppState>>,
+    Path(conversation_id): Path<String>,
+    Json(request): Json<ConversationEnvRequest>,
+) -> impl IntoResponse {
+    let mut conversations = state.conversation_env.write();
+    let env = conversations.entry(conversation_id.clone()).or_default();
+    for (key, value) in request.env {
+        match value {
+            Some(value) => env.insert(key, value),
+            None => env.remove(&key),
+        };
+    }
+    let count = env.len();
+    if count == 0 {
+        conversations.remove(&conversation_id);
+    }
+
+    info!(
+        "[http] PUT /conversations/{}/env - {} variables",
+        conversation_id, count
+    );
+
+    Json(serde_json::json!({
+        "conversation_id": conversation_id,
+        "count": count
+    }))
+}
+
+async fn delete_conversation_env(
+    State(state): State<Arc<AppState>>,
+    Path(conversation_id): Path<String>,
+) -> impl IntoResponse {
+    state.conversation_env.write().remove(&conversation_id);
+    StatusCode::NO_CONTENT
+}
+
+/// Tear down everything left behind by a deleted conversation. Idempotent, so
+/// the server can call it whenever a conversation goes away.
+async fn delete_conversation(
+    State(state): State<Arc<AppState>>,
+    Path(conversation_id): Path<String>,
+) -> impl IntoResponse {
+    info!("[http] DELETE /conversations/{}", conversation_id);
+    Json(close_conversation(&state, &conversation_id))
+}
+
+/// Kill the conversation's terminals, forget its environment and remove its
+/// scratch directory, then tell subscribers it's gone.
+fn close_conversation(state: &AppState, conversation_id: &str) -> serde_json::Value {
+    // Removed under one lock so a session can't join mid-teardown; their
+    // scrollback goes with them
+    let closed: Vec<Arc<PtySession>> = {
+        let mut sessions = state.sessions.write();
+        let ids: Vec<String> = sessions
+            .values()
+            .filter(|s| s.conversation_id.as_deref() == Some(conversation_id))
+            .map(|s| s.id.clone())
+            .collect();
+        ids.iter().filter_map(|id| sessions.remove(id)).collect()
+    };
+
+    let mut ids = Vec::with_capacity(closed.len());
+    for session in &closed {
+        session.kill();
+        state.broadcast_event(ServerEvent::PtyDeleted {
+            pty_id: session.id.clone(),
+        });
+        ids.push(session.id.clone());
+    }
+    state.reindex_sessions();
+    state.conversation_env.write().remove(conversation_id);
+
+    let removed_tmp = match state.scratch.remove(conversation_id) {
+        Ok(removed) => removed,
+        Err(e) => {
+            warn!(
+                "Failed to remove {}: {}",
+                state.scratch.dir(conversation_id).display(),
+                e
+            );
+            false
+        }
+    };
+
+    info!(
+        "[http] Conversation {} closed ({} sessions)",
+        conversation_id,
+        ids.len()
+    );
+    state.broadcast_event(ServerEvent::ConversationClosed {
+        conversation_id: conversation_id.to_string(),
+        pty_ids: ids.clone(),
+    });
+
+    serde_json::json!({
+        "status": "terminated",
+        "conversation_id": conversation_id,
+        "ids": ids,
+        "removed_tmp": removed_tmp
+    })
+}
+
 async fn update_session(
     State(state): State<Arc<AppState>>,
     Path(session_id): Path<String>,
@@ -1177,6 +1807,14 @@ async fn update_session(
         changes.insert("metadata".to_string(), new_metadata);
     }
 
+    if let Some(new_group) = request.group {
+        let new_group = Some(new_group).filter(|g| !g.is_empty());
+        if *session.group.read() != new_group {
+            changes.insert("group".to_string(), serde_json::json!(new_group));
+            session.set_group(new_group);
+        }
+    }
+
     state.reindex_sessions();
 
     let info = session.to_info();
@@ -1212,6 +1850,7 @@ async fn delete_session(
     );
 
     session.kill();
+    state.revoke_share_tokens(&session_id);
 
     state.reindex_sessions();
     state.broadcast_event(ServerEvent::PtyDeleted {
@@ -1226,6 +1865,72 @@ async fn delete_session(
     })))
 }
 
+async fn list_groups(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for session in state.sessions.read().values() {
+        if let Some(group) = session.group.read().clone() {
+            *counts.entry(group).or_default() += 1;
+        }
+    }
+    let groups: Vec<_> = counts
+        .into_iter()
+        .map(|(group, sessions)| serde_json::json!({ "group": group, "sessions": sessions }))
+        .collect();
+    Json(serde_json::json!({ "groups": groups }))
+}
+
+async fn list_group_sessions(
+    State(state): State<Arc<AppState>>,
+    Path(group): Path<String>,
+) -> impl IntoResponse {
+    let sessions: Vec<SessionInfo> = state
+        .group_sessions(&group)
+        .iter()
+        .map(|s| s.to_info())
+        .collect();
+    Json(serde_json::json!({
+        "group": group,
+        "sessions": sessions
+    }))
+}
+
+/// Close every terminal of a workspace or task. Idempotent: an unknown or
+/// already-empty group succeeds with no ids, so teardown can always call it.
+async fn delete_group(
+    State(state): State<Arc<AppState>>,
+    Path(group): Path<String>,
+) -> impl IntoResponse {
+    info!("[http] DELETE /groups/{}", group);
+
+    // Removed under one lock so a session can't join the group mid-teardown
+    let closed = {
+        let mut sessions = state.sessions.write();
+        let members = group_members(&sessions, &group);
+        for session in &members {
+            sessions.remove(&session.id);
+        }
+        members
+    };
+
+    let mut ids = Vec::with_capacity(closed.len());
+    for session in closed {
+        session.kill();
+        state.broadcast_event(ServerEvent::PtyDeleted {
+            pty_id: session.id.clone(),
+        });
+        ids.push(session.id.clone());
+    }
+    state.reindex_sessions();
+
+    info!("[http] Group {} closed ({} sessions)", group, ids.len());
+
+    Json(serde_json::json!({
+        "status": "terminated",
+        "group": group,
+        "ids": ids
+    }))
+}
+
 async fn capture_session(
     State(state): State<Arc<AppState>>,
     Path(session_id): Path<String>,
@@ -1267,6 +1972,47 @@ async fn capture_session(
     }
 }
 
+/// Usage of every session that has been sampled, heaviest CPU user first.
+async fn list_usage(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut sessions: Vec<SessionInfo> = state
+        .get_ordered_sessions()
+        .into_iter()
+        .filter(|s| s.usage.is_some())
+        .collect();
+    sessions.sort_by(|a, b| {
+        let (a, b) = (a.usage.unwrap_or_default(), b.usage.unwrap_or_default());
+        b.cpu_percent
+            .total_cmp(&a.cpu_percent)
+            .then(b.rss_bytes.cmp(&a.rss_bytes))
+    });
+    let usage: Vec<serde_json::Value> = sessions
+        .into_iter()
+        .map(|s| {
+            serde_json::json!({
+                "id": s.id,
+                "name": s.name,
+                "group": s.group,
+                "usage": s.usage,
+            })
+        })
+        .collect();
+    Json(serde_json::json!({ "sessions": usage }))
+}
+
+async fn session_usage(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+) -> Result<impl IntoResponse, ServerError> {
+    let sessions = state.sessions.read();
+    let session = sessions
+        .get(&session_id)
+        .ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
+    Ok(Json(serde_json::json!({
+        "id": session.id,
+        "usage": *session.usage.read(),
+    })))
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct ResizeRequest {
     cols: u16,
@@ -1328,6 +2074,52 @@ async fn send_input(
     })))
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ShareRequest {
+    /// Lifetime of the link in seconds (default one hour, at most a week)
+    ttl_secs: Option<u64>,
+}
+
+/// Mint a read-only share link. Viewers attach at `/shares/<token>/ws`, which
+/// never reveals the session id: they see live output but their input and
+/// resizes are ignored, and the socket closes when the token expires.
+async fn create_share(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    request: Option<Json<ShareRequest>>,
+) -> Result<impl IntoResponse, ServerError> {
+    if !state.sessions.read().contains_key(&session_id) {
+        return Err(ServerError::SessionNotFound(session_id));
+    }
+    let ttl_secs = request
+        .and_then(|Json(request)| request.ttl_secs)
+        .unwrap_or(SHARE_TOKEN_DEFAULT_TTL_SECS);
+    let (token, expires_at) = state.mint_share_token(&session_id, ttl_secs);
+
+    info!(
+        "[http] Share link for {} expires at {}",
+        session_id, expires_at
+    );
+
+    Ok(Json(serde_json::json!({
+        "session_id": session_id,
+        "token": token,
+        "expires_at": expires_at,
+        "path": format!("/shares/{}/ws", token),
+    })))
+}
+
+async fn revoke_shares(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse {
+    let revoked = state.revoke_share_tokens(&session_id);
+    Json(serde_json::json!({
+        "session_id": session_id,
+        "revoked": revoked,
+    }))
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct SignalRequest {
     /// Signal number to send (e.g., 10 for SIGUSR1, 12 for SIGUSR2)
@@ -1454,6 +2246,9 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
                 ServerEvent::Output { .. } => "output",
                 ServerEvent::Exit { .. } => "exit",
                 ServerEvent::Error { .. } => "error",
+                ServerEvent::Links { .. } => "links",
+                ServerEvent::ConversationClosed { .. } => "conversation_closed",
+                ServerEvent::Usage { .. } => "usage",
             };
             info!(
                 "[events-ws:{}] Forwarding event #{}: {}",
@@ -1505,6 +2300,8 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
                 name,
                 client_id,
                 metadata,
+                conversation_id,
+                group,
             } => {
                 let request = CreateSessionRequest {
                     shell: shell.unwrap_or_else(default_shell),
@@ -1515,6 +2312,8 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
                     name,
                     client_id: client_id.clone(),
                     metadata,
+                    conversation_id,
+                    group,
                 };
 
                 match create_pty_session_inner(&state, &request) {
@@ -1616,15 +2415,49 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
 async fn websocket_terminal(
     ws: WebSocketUpgrade,
     Path(session_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, ServerError> {
+    attach_terminal(ws, &state, session_id, &params, None)
+}
+
+/// Read-only attach through a share link. The token stands in for the
+/// session id so holders can't reach the writable `/sessions/:id/ws`.
+async fn websocket_share(
+    ws: WebSocketUpgrade,
+    Path(token): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ServerError> {
+    let share = state.check_share_token(&token)?;
+    let session_id = share.session_id.clone();
+    attach_terminal(ws, &state, session_id, &params, Some(share))
+}
+
+/// Upgrade to a terminal socket; a `share` makes it read-only.
+fn attach_terminal(
+    ws: WebSocketUpgrade,
+    state: &AppState,
+    session_id: String,
+    params: &HashMap<String, String>,
+    share: Option<ShareGrant>,
+) -> Result<impl IntoResponse, ServerError> {
+    // With `replay=screen` the client gets a repaint of the current screen
+    // instead of the raw output history
+    let replay_screen = params.get("replay").is_some_and(|v| v == "screen");
+
     // Verify session exists and get data
     let (scrollback, output_rx) = {
         let sessions = state.sessions.read();
         let session = sessions
             .get(&session_id)
             .ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
-        (session.get_scrollback(), session.output_tx.subscribe())
+        let scrollback = if replay_screen {
+            session.terminal.lock().to_ansi()
+        } else {
+            session.get_scrollback()
+        };
+        (scrollback, session.output_tx.subscribe())
     };
 
     let session = {
@@ -1634,24 +2467,35 @@ async fn websocket_terminal(
 
     let session = session.ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
 
+    // Opt-in so raw xterm attach clients never see JSON text frames
+    let links_rx = params
+        .get("links")
+        .is_some_and(|v| v == "true")
+        .then(|| session.links_tx.subscribe());
+
     Ok(ws.on_upgrade(move |socket| {
-        handle_terminal_websocket(socket, session, scrollback, output_rx)
+        handle_terminal_websocket(socket, session, scrollback, output_rx, links_rx, share)
     }))
 }
 
+/// `share` is set for read-only viewers attached via a share link.
 async fn handle_terminal_websocket(
     socket: WebSocket,
     session: Arc<PtySession>,
     scrollback: String,
     mut output_rx: broadcast::Receiver<String>,
+    mut links_rx: Option<broadcast::Receiver<Vec<LinkAnnotation>>>,
+    share: Option<ShareGrant>,
 ) {
     let (mut sender, mut receiver) = socket.split();
     let session_id = session.id.clone();
+    let read_only = share.is_some();
 
     info!(
-        "[term-ws:{}] Terminal WebSocket connected (scrollback: {} bytes)",
+        "[term-ws:{}] Terminal WebSocket connected (scrollback: {} bytes, read-only: {})",
         session_id,
-        scrollback.len()
+        scrollback.len(),
+        read_only
     );
 
     // Send scrollback as raw binary (xterm expects raw data)
@@ -1671,13 +2515,48 @@ async fn handle_terminal_websocket(
         }
     }
 
+    // Link annotations go out as JSON text frames, starting with the current set
+    if links_rx.is_some() {
+        let event = ServerEvent::Links {
+            links: session.get_terminal_links(),
+        };
+        if let Ok(json) = serde_json::to_string(&event) {
+            if sender.send(Message::Text(json)).await.is_err() {
+                warn!("[term-ws:{}] Failed to send links", session_id);
+                return;
+            }
+        }
+    }
+
     // Spawn task to forward PTY output to WebSocket as raw binary
     let session_id_clone = session_id.clone();
     let send_task = tokio::spawn(async move {
         let mut output_count = 0usize;
         let mut total_bytes = 0usize;
 
-        while let Ok(data) = output_rx.recv().await {
+        loop {
+            let data = tokio::select! {
+                result = output_rx.recv() => match result {
+                    Ok(data) => data,
+                    Err(_) => break,
+                },
+                Some(result) = recv_links(&mut links_rx) => {
+                    match result {
+                        Ok(links) => {
+                            let event = ServerEvent::Links { links };
+                            if let Ok(json) = serde_json::to_string(&event) {
+                                if sender.send(Message::Text(json)).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Closed) => links_rx = None,
+                        // A newer set will follow
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    }
+                    continue;
+                }
+            };
             output_count += 1;
             total_bytes += data.len();
 
@@ -1705,7 +2584,36 @@ async fn handle_terminal_websocket(
     let mut input_count = 0usize;
     let mut input_bytes = 0usize;
 
-    while let Some(msg) = receiver.next().await {
+    let share_ended = async {
+        match share {
+            Some(mut share) => {
+                let expiry = Duration::from_secs(share.expires_at.saturating_sub(unix_now()));
+                tokio::select! {
+                    _ = tokio::time::sleep(expiry) => "expired",
+                    // Only ever closes: nothing is sent on it
+                    _ = share.revoked.changed() => "revoked",
+                }
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(share_ended);
+
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            reason = &mut share_ended => {
+                info!("[term-ws:{}] Share link {}, closing", session_id, reason);
+                break;
+            }
+        };
+        // Viewers only watch; keep-alives and close frames still apply
+        if read_only && matches!(msg, Ok(Message::Binary(_)) | Ok(Message::Text(_))) {
+            continue;
+        }
         match msg {
             Ok(Message::Binary(data)) => {
                 // Raw binary input from xterm
@@ -1804,6 +2712,16 @@ async fn handle_terminal_websocket(
     );
 }
 
+/// Receive from an optional links subscription; pending forever when absent.
+async fn recv_links(
+    links_rx: &mut Option<broadcast::Receiver<Vec<LinkAnnotation>>>,
+) -> Option<Result<Vec<LinkAnnotation>, broadcast::error::RecvError>> {
+    match links_rx {
+        Some(rx) => Some(rx.recv().await),
+        None => std::future::pending().await,
+    }
+}
+
 // =============================================================================
 // Main
 // =============================================================================
@@ -1814,7 +2732,19 @@ async fn main() -> Result<()> {
 
     match cli.command {
         // Server mode
-        Some(Commands::Server { host, port }) => run_server(&host, port).await,
+        Some(Commands::Server {
+            host,
+            port,
+            state_dir,
+            scratch_root,
+            scratch_tmpfs_size,
+        }) => {
+            let scratch = ScratchConfig {
+                root: scratch_root.unwrap_or_else(|| ScratchConfig::default().root),
+                tmpfs_size: scratch_tmpfs_size,
+            };
+            run_server(&host, port, state_dir, scratch).await
+        }
 
         // No command = server mode (for backwards compatibility)
         None => {
@@ -1823,22 +2753,39 @@ async fn main() -> Result<()> {
                 .unwrap_or_else(|_| "39383".to_string())
                 .parse()
                 .context("Invalid PTY_SERVER_PORT")?;
-            run_server(&host, port).await
+            let state_dir = env::var_os("PTY_STATE_DIR").map(PathBuf::from);
+            let mut scratch = ScratchConfig::default();
+            if let Some(root) = env::var_os("PTY_SCRATCH_ROOT") {
+                scratch.root = PathBuf::from(root);
+            }
+            if let Ok(size) = env::var("PTY_SCRATCH_TMPFS_SIZE") {
+                scratch.tmpfs_size = Some(
+                    scratch::parse_size(&size)
+                        .map_err(anyhow::Error::msg)
+                        .context("Invalid PTY_SCRATCH_TMPFS_SIZE")?,
+                );
+            }
+            run_server(&host, port, state_dir, scratch).await
         }
 
         // Client commands
-        Some(Commands::List { json }) => cli::cmd_list(&cli.server, json).await,
+        Some(Commands::List { group, json }) => {
+            cli::cmd_list(&cli.server, group.as_deref(), json).await
+        }
 
         Some(Commands::New {
             name,
             shell,
             cwd,
+            group,
             detached,
-        }) => cli::cmd_new(&cli.server, name, shell, cwd, detached).await,
+        }) => cli::cmd_new(&cli.server, name, shell, cwd, group, detached).await,
 
         Some(Commands::Attach { session }) => cli::cmd_attach(&cli.server, &session).await,
 
-        Some(Commands::Kill { sessions }) => cli::cmd_kill(&cli.server, &sessions).await,
+        Some(Commands::Kill { sessions, group }) => {
+            cli::cmd_kill(&cli.server, &sessions, group.as_deref()).await
+        }
 
         Some(Commands::SendKeys { session, keys }) => {
             cli::cmd_send_keys(&cli.server, &session, &keys).await
@@ -1856,7 +2803,12 @@ async fn main() -> Result<()> {
     }
 }
 
-async fn run_server(host: &str, port: u16) -> Result<()> {
+async fn run_server(
+    host: &str,
+    port: u16,
+    state_dir: Option<PathBuf>,
+    scratch: ScratchConfig,
+) -> Result<()> {
     // Debug output to ensure binary is running
     eprintln!("[pty-server] Starting...");
     std::io::Write::flush(&mut std::io::stderr()).ok();
@@ -1871,7 +2823,31 @@ async fn run_server(host: &str, port: u16) -> Result<()> {
 
     eprintln!("[pty-server] Logging initialized");
 
-    let state = Arc::new(AppState::new());
+    info!(
+        "Conversation scratch directories in {} (tmpfs size: {:?})",
+        scratch.root.display(),
+        scratch.tmpfs_size
+    );
+    let state = match state_dir {
+        Some(dir) => {
+            info!(
+                "Saving the session layout to {} (running processes don't survive a restart)",
+                dir.display()
+            );
+            let state = Arc::new(AppState {
+                scratch,
+                ..AppState::with_store(SessionStore::new(dir))
+            });
+            restore_layout(&state);
+            tokio::spawn(save_layout_periodically(state.clone()));
+            state
+        }
+        None => Arc::new(AppState {
+            scratch,
+            ..AppState::new()
+        }),
+    };
+    tokio::spawn(sample_usage_periodically(state.clone()));
 
     let app = Router::new()
         // Static frontend
@@ -1884,12 +2860,36 @@ async fn run_server(host: &str, port: u16) -> Result<()> {
         .route("/sessions/:session_id", patch(update_session))
         .route("/sessions/:session_id", delete(delete_session))
         .route("/sessions/:session_id/capture", get(capture_session))
+        .route("/sessions/:session_id/usage", get(session_usage))
         .route("/sessions/:session_id/resize", post(resize_session))
         .route("/sessions/:session_id/input", post(send_input))
+        .route("/sessions/:session_id/share", post(create_share))
+        .route("/sessions/:session_id/share", delete(revoke_shares))
         .route("/signal", post(send_signal))
+        .route("/usage", get(list_usage))
+        .route("/groups", get(list_groups))
+        .route("/groups/:group", get(list_group_sessions))
+        .route("/groups/:group", delete(delete_group))
+        .route(
+            "/conversations/:conversation_id/env",
+            get(get_conversation_env),
+        )
+        .route(
+            "/conversations/:conversation_id/env",
+            put(set_conversation_env),
+        )
+        .route(
+            "/conversations/:conversation_id/env",
+            delete(delete_conversation_env),
+        )
+        .route(
+            "/conversations/:conversation_id",
+            delete(delete_conversation),
+        )
         // WebSocket endpoints
         .route("/ws", get(websocket_events))
         .route("/sessions/:session_id/ws", get(websocket_terminal))
+        .route("/shares/:token/ws", get(websocket_share))
         .layer(CorsLayer::permissive())
         .with_state(state);
 
@@ -1921,19 +2921,52 @@ mod tests {
     use tower::ServiceExt;
 
     fn create_test_app() -> Router {
-        let state = Arc::new(AppState::new());
+        create_test_app_with_state(Arc::new(AppState::new()))
+    }
+
+    fn create_test_app_with_state(state: Arc<AppState>) -> Router {
         Router::new()
             .route("/health", get(health))
             .route("/sessions", get(list_sessions))
             .route("/sessions", post(create_session))
             .route("/sessions/:session_id", patch(update_session))
             .route("/sessions/:session_id", delete(delete_session))
+            .route("/sessions/:session_id/share", post(create_share))
+            .route("/sessions/:session_id/share", delete(revoke_shares))
+            .route("/groups", get(list_groups))
+            .route("/groups/:group", get(list_group_sessions))
+            .route("/groups/:group", delete(delete_group))
+            .route(
+                "/conversations/:conversation_id",
+                delete(delete_conversation),
+            )
             .route("/ws", get(websocket_events))
             .route("/sessions/:session_id/ws", get(websocket_terminal))
+            .route("/shares/:token/ws", get(websocket_share))
             .layer(CorsLayer::permissive())
             .with_state(state)
     }
 
+    async fn send_json(app: &Router, method: &str, uri: &str, body: &str) -> serde_json::Value {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{} {}", method, uri);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
     #[tokio::test]
     async fn test_health_endpoint() {
         let app = create_test_app();
@@ -1987,6 +3020,222 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[tokio::test]
+    async fn test_group_lists_and_closes_its_sessions() {
+        let app = create_test_app();
+        let create = |group: &str| {
+            format!(
+                r#"{{"shell": "/bin/sh", "cwd": "/tmp", "group": "{}"}}"#,
+                group
+            )
+        };
+
+        let first = send_json(&app, "POST", "/sessions", &create("task-1")).await;
+        assert_eq!(first["group"], "task-1");
+        send_json(&app, "POST", "/sessions", &create("task-1")).await;
+        let other = send_json(&app, "POST", "/sessions", &create("")).await;
+        assert!(other.get("group").is_none());
+
+        let groups = send_json(&app, "GET", "/groups", "").await;
+        assert_eq!(
+            groups["groups"],
+            serde_json::json!([{ "group": "task-1", "sessions": 2 }])
+        );
+        let members = send_json(&app, "GET", "/groups/task-1", "").await;
+        assert_eq!(members["sessions"][0]["id"], first["id"]);
+        assert_eq!(members["sessions"].as_array().unwrap().len(), 2);
+
+        let closed = send_json(&app, "DELETE", "/groups/task-1", "").await;
+        assert_eq!(closed["ids"].as_array().unwrap().len(), 2);
+        assert_eq!(closed["ids"][0], first["id"]);
+
+        let remaining = send_json(&app, "GET", "/sessions", "").await;
+        let remaining = remaining["sessions"].as_array().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0]["id"], other["id"]);
+        assert_eq!(remaining[0]["index"], 0);
+
+        // Closing again is a no-op, so teardown can be retried safely
+        let closed = send_json(&app, "DELETE", "/groups/task-1", "").await;
+        assert_eq!(closed["ids"], serde_json::json!([]));
+
+        let uri = format!("/sessions/{}", other["id"].as_str().unwrap());
+        send_json(&app, "DELETE", &uri, "").await;
+    }
+
+    #[tokio::test]
+    async fn test_closing_a_conversation_cleans_up_after_it() {
+        let root = std::env::temp_dir().join(format!("cmux-pty-scratch-{}", Uuid::new_v4()));
+        let state = Arc::new(AppState {
+            scratch: ScratchConfig {
+                root: root.clone(),
+                tmpfs_size: None,
+            },
+            ..AppState::new()
+        });
+        let mut events = state.event_tx.subscribe();
+        let create = |conversation_id: Option<&str>| CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            conversation_id: conversation_id.map(str::to_string),
+            ..Default::default()
+        };
+        let mut sessions = Vec::new();
+        for conversation_id in [Some("conv-gc"), Some("conv-gc"), None] {
+            let (session, reader) =
+                create_pty_session_inner(&state, &create(conversation_id)).unwrap();
+            state
+                .sessions
+                .write()
+                .insert(session.id.clone(), session.clone());
+            tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+            sessions.push(session);
+        }
+        state
+            .conversation_env
+            .write()
+            .insert("conv-gc".to_string(), HashMap::new());
+        assert_eq!(
+            sessions[0].to_info().conversation_id.as_deref(),
+            Some("conv-gc")
+        );
+        let tmp_dir = state.scratch.dir("conv-gc");
+        assert!(tmp_dir.join(".gitignore").exists());
+        std::fs::write(tmp_dir.join("image.png"), b"png").unwrap();
+
+        let closed = close_conversation(&state, "conv-gc");
+        assert_eq!(closed["ids"].as_array().unwrap().len(), 2);
+        assert_eq!(closed["removed_tmp"], true);
+        assert!(!tmp_dir.exists());
+        assert!(state.conversation_env.read().is_empty());
+        assert_eq!(state.sessions.read().len(), 1);
+        assert!(sessions[2].is_alive());
+
+        let mut last = None;
+        while let Ok(event) = events.try_recv() {
+            last = Some(event);
+        }
+        match last {
+            Some(ServerEvent::ConversationClosed {
+                conversation_id,
+                pty_ids,
+            }) => {
+                assert_eq!(conversation_id, "conv-gc");
+                assert_eq!(pty_ids.len(), 2);
+            }
+            other => panic!("expected conversation_closed, got {:?}", other),
+        }
+
+        // Closing again is a no-op
+        let closed = close_conversation(&state, "conv-gc");
+        assert_eq!(closed["ids"], serde_json::json!([]));
+
+        sessions[2].kill();
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_share_links_are_scoped_and_expire() {
+        let app = create_test_app();
+
+        let created = send_json(&app, "POST", "/sessions", r#"{"shell":"/bin/sh"}"#).await;
+        let id = created["id"].as_str().unwrap().to_string();
+        let share = send_json(
+            &app,
+            "POST",
+            &format!("/sessions/{}/share", id),
+            r#"{"ttl_secs":60}"#,
+        )
+        .await;
+        let token = share["token"].as_str().unwrap();
+        assert_eq!(share["path"], format!("/shares/{}/ws", token));
+
+        let state = AppState::new();
+        let (token, expires_at) = state.mint_share_token("a", 60);
+        let share = state.check_share_token(&token).unwrap();
+        assert_eq!(
+            (share.session_id, share.expires_at),
+            ("a".to_string(), expires_at)
+        );
+        assert!(state.check_share_token("nope").is_err());
+
+        state
+            .share_tokens
+            .write()
+            .get_mut(&token)
+            .unwrap()
+            .expires_at = unix_now();
+        assert!(state.check_share_token(&token).is_err());
+
+        let (token, _) = state.mint_share_token("a", 60);
+        assert_eq!(state.revoke_share_tokens("a"), 1);
+        assert!(state.check_share_token(&token).is_err());
+
+        let revoked = send_json(&app, "DELETE", &format!("/sessions/{}/share", id), "").await;
+        assert_eq!(revoked["revoked"], 1);
+        send_json(&app, "DELETE", &format!("/sessions/{}", id), "").await;
+    }
+
+    #[tokio::test]
+    async fn test_share_viewers_cannot_write() {
+        use std::time::Instant;
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let state = Arc::new(AppState::new());
+        let app = create_test_app_with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_app = app.clone();
+        let server = tokio::spawn(async move { axum::serve(listener, server_app).await });
+
+        let created = send_json(&app, "POST", "/sessions", r#"{"shell":"/bin/sh"}"#).await;
+        let id = created["id"].as_str().unwrap().to_string();
+        let share = send_json(&app, "POST", &format!("/sessions/{}/share", id), "").await;
+        let path = share["path"].as_str().unwrap();
+        assert!(!path.contains(&id));
+
+        let (mut viewer, _) = tokio_tungstenite::connect_async(format!("ws://{}{}", addr, path))
+            .await
+            .unwrap();
+        viewer
+            .send(WsMessage::Text("echo viewer-$((6*7))\n".into()))
+            .await
+            .unwrap();
+        viewer
+            .send(WsMessage::Binary(b"echo viewer-$((6*7))\n".to_vec()))
+            .await
+            .unwrap();
+        // Frames are handled in order, so the pong means the input was seen
+        viewer.send(WsMessage::Ping(Vec::new())).await.unwrap();
+        while !matches!(viewer.next().await, Some(Ok(WsMessage::Pong(_)))) {}
+        let session = state.sessions.read().get(&id).cloned().unwrap();
+        session.write_input("echo owner-$((6*7))\n").unwrap();
+
+        // The owner's command runs; the viewer's never reached the shell
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !session.get_scrollback().contains("owner-42") {
+            assert!(Instant::now() < deadline, "owner input never ran");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(!session.get_scrollback().contains("viewer"));
+
+        // A bad token attaches nothing
+        let bad = tokio_tungstenite::connect_async(format!("ws://{}/shares/nope/ws", addr)).await;
+        assert!(bad.is_err());
+
+        // Revoking the link disconnects the viewer already attached
+        let revoked = send_json(&app, "DELETE", &format!("/sessions/{}/share", id), "").await;
+        assert_eq!(revoked["revoked"], 1);
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(WsMessage::Binary(_) | WsMessage::Text(_))) = viewer.next().await {}
+        })
+        .await;
+        assert!(closed.is_ok(), "viewer stayed connected after revoke");
+
+        send_json(&app, "DELETE", &format!("/sessions/{}", id), "").await;
+        server.abort();
+    }
+
     #[tokio::test]
     async fn test_session_not_found() {
         let app = create_test_app();
@@ -2033,6 +3282,46 @@ mod tests {
         session.kill();
     }
 
+    #[tokio::test]
+    async fn test_usage_sampled_for_session_process_trees() {
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        state
+            .sessions
+            .write()
+            .insert(session.id.clone(), session.clone());
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+        session.write_input("sleep 5 &\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let mut events = state.event_tx.subscribe();
+        state.sample_usage(&mut UsageSampler::new());
+        let usage = session.usage.read().expect("usage sampled");
+        assert!(usage.processes >= 2, "shell and sleep: {:?}", usage);
+        assert!(usage.rss_bytes > 0);
+        match events.try_recv() {
+            Ok(ServerEvent::Usage { sessions }) => assert_eq!(sessions[&session.id], usage),
+            other => panic!("expected usage event, got {:?}", other),
+        }
+
+        let app = Router::new()
+            .route("/usage", get(list_usage))
+            .route("/sessions/:session_id/usage", get(session_usage))
+            .with_state(state.clone());
+        let body = send_json(&app, "GET", "/usage", "").await;
+        assert_eq!(body["sessions"][0]["id"], session.id);
+        let uri = format!("/sessions/{}/usage", session.id);
+        let body = send_json(&app, "GET", &uri, "").await;
+        assert_eq!(body["usage"]["processes"], usage.processes);
+
+        session.kill();
+    }
+
     /// Test sequential writes with small delay (simulates typing)
     #[tokio::test]
     async fn test_pty_sequential_writes() {
@@ -2329,4 +3618,189 @@ mod tests {
 
         session.kill();
     }
+
+    #[tokio::test]
+    async fn test_links_published_for_existing_files() {
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let mut links_rx = session.links_tx.subscribe();
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        let file_name = format!("cmux-links-{}.txt", Uuid::new_v4());
+        std::fs::write(format!("/tmp/{}", file_name), "x").unwrap();
+        session
+            .write_input(&format!(
+                "echo {}:3 missing.rs:1 https://example.com 9fceb02d\n",
+                file_name
+            ))
+            .unwrap();
+
+        let links = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let links = links_rx.recv().await.unwrap();
+                if links.iter().any(|l| l.kind == "file") {
+                    break links;
+                }
+            }
+        })
+        .await
+        .expect("links were published");
+
+        let file = links.iter().find(|l| l.kind == "file").unwrap();
+        assert_eq!(file.path.as_deref(), Some(&*format!("/tmp/{}", file_name)));
+        assert_eq!(file.line, Some(3));
+        assert!(links
+            .iter()
+            .any(|l| l.url.as_deref() == Some("https://example.com")));
+        assert!(!links.iter().any(|l| l.text.starts_with("missing.rs")));
+        assert!(links
+            .iter()
+            .any(|l| l.kind == "sha" && l.text == "9fceb02d"));
+
+        session.kill();
+        let _ = std::fs::remove_file(format!("/tmp/{}", file_name));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_inherit_conversation_env() {
+        let state = Arc::new(AppState::new());
+        let app = Router::new()
+            .route(
+                "/conversations/:conversation_id/env",
+                get(get_conversation_env),
+            )
+            .route(
+                "/conversations/:conversation_id/env",
+                put(set_conversation_env),
+            )
+            .with_state(state.clone());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/conversations/conv-1/env")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        r#"{"env": {"CMUX_TEST_PROXY": "http://proxy", "CMUX_TEST_TOKEN": "conv", "GONE": "x"}}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/conversations/conv-1/env")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"env": {"GONE": null}}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.conversation_env.read()["conv-1"].len(), 2);
+
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            env: Some(HashMap::from([(
+                "CMUX_TEST_TOKEN".to_string(),
+                "explicit".to_string(),
+            )])),
+            conversation_id: Some("conv-1".to_string()),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+        session
+            .write_input("echo \"[$CMUX_TEST_PROXY|$CMUX_TEST_TOKEN|$GONE]\"\n")
+            .unwrap();
+
+        // The prompt may land on the output line when the input beats it
+        let expected = "[http://proxy|explicit|]";
+        let printed = || {
+            session
+                .get_terminal_content()
+                .iter()
+                .any(|l| l.ends_with(expected) && !l.contains("echo"))
+        };
+        for _ in 0..50 {
+            if printed() {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+        assert!(printed());
+
+        session.kill();
+    }
+
+    #[tokio::test]
+    async fn test_layout_restored_after_restart() {
+        let dir = std::env::temp_dir().join(format!("cmux-pty-state-{}", Uuid::new_v4()));
+        let state = Arc::new(AppState::with_store(SessionStore::new(&dir)));
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            name: Some("build".to_string()),
+            group: Some("task-1".to_string()),
+            ..Default::default()
+        };
+        let (session, _reader) = create_pty_session_inner(&state, &request).unwrap();
+        session.append_scrollback("secret output");
+        state
+            .sessions
+            .write()
+            .insert(session.id.clone(), session.clone());
+        state.save_layout();
+
+        // Only the layout is saved, readable by the server's user alone, and
+        // an unchanged layout isn't rewritten
+        use std::os::unix::fs::PermissionsExt;
+        let file = dir.join("sessions.json");
+        let saved = std::fs::read_to_string(&file).unwrap();
+        assert!(saved.contains("build"));
+        assert!(!saved.contains("secret output"));
+        let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let store = state.store.as_ref().unwrap();
+        let layout = vec![session.to_persisted()];
+        assert!(!store.save(layout.clone()).unwrap());
+        assert!(SessionStore::new(&dir).save(layout).unwrap());
+        session.kill();
+
+        let restarted = Arc::new(AppState::with_store(SessionStore::new(&dir)));
+        restore_layout(&restarted);
+        let restored = restarted
+            .sessions
+            .read()
+            .get(&session.id)
+            .cloned()
+            .expect("session restored");
+        let info = restored.to_info();
+        assert_eq!(info.name, "build");
+        assert_eq!(info.group.as_deref(), Some("task-1"));
+        assert!(info.restored);
+        // The old shell is gone; the session runs a new one
+        assert_ne!(info.pid, session.pid);
+        assert!(!restored.get_scrollback().contains("secret output"));
+        assert!(restored.get_scrollback().contains("session restored"));
+        restored.kill();
+
+        // A corrupt or missing state file restores nothing
+        std::fs::write(dir.join("sessions.json"), "{not json").unwrap();
+        assert!(SessionStore::new(&dir).load().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(SessionStore::new(&dir).load().is_empty());
+    }
 }
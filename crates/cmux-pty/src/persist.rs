@@ -0,0 +1,115 @@
+//! Layout restore across server restarts.
+//!
+//! The sandbox supervisor restarts cmux-pty whenever it exits, and without
+//! this every terminal would disappear from `GET /sessions` with it. Given a
+//! state directory, the server periodically saves each live session's id,
+//! name, group, metadata, size and working directory there, and on startup
+//! recreates the sessions under the same ids so clients can reattach to them.
+//! Only the layout comes back: a shell can't outlive the server holding its
+//! PTY, so the shells and every process running in them are lost, and each
+//! restored session starts a fresh shell. Terminal output is never written to
+//! disk since it may hold secrets.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// File in the state directory holding the sessions.
+const STATE_FILE: &str = "sessions.json";
+
+/// Everything needed to bring a session back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedSession {
+    pub id: String,
+    pub name: String,
+    pub index: usize,
+    pub shell: String,
+    /// The shell's working directory when the session was saved
+    pub cwd: String,
+    pub cols: u16,
+    pub rows: u16,
+    pub created_at: f64,
+    pub metadata: Option<serde_json::Value>,
+    pub group: Option<String>,
+    pub conversation_id: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StateFile {
+    sessions: Vec<PersistedSession>,
+}
+
+/// Sessions saved in a state directory.
+pub struct SessionStore {
+    dir: PathBuf,
+    /// What the file last held, so unchanged sessions aren't rewritten
+    last_saved: Mutex<Option<Vec<u8>>>,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            last_saved: Mutex::new(None),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(STATE_FILE)
+    }
+
+    /// Saved sessions; none when nothing was saved yet or the file is
+    /// unreadable, so a bad file never keeps the server from starting.
+    pub fn load(&self) -> Vec<PersistedSession> {
+        let path = self.path();
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                return Vec::new();
+            }
+        };
+        match serde_json::from_slice::<StateFile>(&bytes) {
+            Ok(state) => state.sessions,
+            Err(e) => {
+                warn!("Ignoring unreadable {}: {}", path.display(), e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Replace the saved sessions unless they are unchanged since the last
+    /// save; returns whether the file was written. The file is only readable
+    /// by the server's user and is swapped in with a rename, so a crash
+    /// mid-write leaves the previous state intact.
+    pub fn save(&self, sessions: Vec<PersistedSession>) -> io::Result<bool> {
+        let bytes = serde_json::to_vec(&StateFile { sessions })?;
+        let mut last_saved = self.last_saved.lock().unwrap_or_else(|e| e.into_inner());
+        if last_saved.as_ref() == Some(&bytes) {
+            return Ok(false);
+        }
+        fs::create_dir_all(&self.dir)?;
+        fs::set_permissions(&self.dir, fs::Permissions::from_mode(0o700))?;
+        let tmp = self.dir.join(format!("{}.tmp", STATE_FILE));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        file.write_all(&bytes)?;
+        fs::rename(&tmp, self.path())?;
+        *last_saved = Some(bytes);
+        Ok(true)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
@@ -0,0 +1,206 @@
+//! Per-conversation scratch directories.
+//!
+//! Agents leave temp files, pasted images and build output lying around. Each
+//! ACP conversation gets its own scratch directory for them, handed to its
+//! terminals as `TMPDIR` and [`SCRATCH_ENV`]. It lives outside the workspace
+//! and ignores everything in it, so none of it shows up in diffs or collected
+//! artifacts. With a size limit configured, the directory is a tmpfs of that
+//! size, so a runaway agent fills its own scratch space rather than the
+//! sandbox's disk. Closing the conversation deletes it.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// Variable pointing a conversation's terminals at its scratch directory.
+pub const SCRATCH_ENV: &str = "CMUX_SCRATCH_DIR";
+
+/// Longest id named by its hex; any longer and the name would pass the
+/// 255-byte file name limit.
+const MAX_HEX_ID_BYTES: usize = 127;
+
+/// Where scratch directories are created and how big they may grow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScratchConfig {
+    /// Parent of every conversation's directory
+    pub root: PathBuf,
+    /// Mount each directory as a tmpfs of this many bytes
+    pub tmpfs_size: Option<u64>,
+}
+
+impl Default for ScratchConfig {
+    fn default() -> Self {
+        Self {
+            root: std::env::temp_dir().join("cmux-conversations"),
+            tmpfs_size: None,
+        }
+    }
+}
+
+/// Parse a size such as `512M`, `2G` or `1048576` (bytes).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, suffix) = s.split_at(split);
+    let shift = match suffix.to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        _ => return Err(format!("unknown size suffix in '{}'", s)),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("invalid size '{}'", s))
+}
+
+impl ScratchConfig {
+    /// Directory of `conversation_id`. The name is the id's bytes in hex, so
+    /// an id can't point outside the root and distinct ids never share a
+    /// directory. Ids too long for that are named by their SHA-256 instead,
+    /// behind a prefix no hex name has.
+    pub fn dir(&self, conversation_id: &str) -> PathBuf {
+        let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+        let name = if conversation_id.len() <= MAX_HEX_ID_BYTES {
+            hex(conversation_id.as_bytes())
+        } else {
+            format!("sha256-{}", hex(&Sha256::digest(conversation_id)))
+        };
+        self.root.join(name)
+    }
+
+    /// Create the conversation's directory if it doesn't exist yet. Where
+    /// tmpfs can't be mounted (no privileges, not Linux) a plain directory
+    /// is used instead.
+    pub fn provision(&self, conversation_id: &str) -> io::Result<PathBuf> {
+        let dir = self.dir(conversation_id);
+        fs::create_dir_all(&dir)?;
+        if let Some(size) = self.tmpfs_size {
+            if !is_mount_point(&dir) {
+                if let Err(e) = mount_tmpfs(&dir, size) {
+                    warn!(
+                        "Failed to mount tmpfs on {}, using a plain directory: {}",
+                        dir.display(),
+                        e
+                    );
+                }
+            }
+        }
+        // Keeps git from picking it up should the root be inside a worktree
+        let ignore = dir.join(".gitignore");
+        if !ignore.exists() {
+            fs::write(ignore, "*\n")?;
+        }
+        Ok(dir)
+    }
+
+    /// Unmount and delete the conversation's directory. Returns whether
+    /// there was one.
+    pub fn remove(&self, conversation_id: &str) -> io::Result<bool> {
+        let dir = self.dir(conversation_id);
+        if !dir.exists() {
+            return Ok(false);
+        }
+        if is_mount_point(&dir) {
+            unmount(&dir)?;
+        }
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Whether `path` is on a different filesystem than its parent.
+fn is_mount_point(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let (Some(parent), Ok(meta)) = (path.parent(), fs::metadata(path)) else {
+        return false;
+    };
+    fs::metadata(parent).is_ok_and(|parent| parent.dev() != meta.dev())
+}
+
+#[cfg(target_os = "linux")]
+fn mount_tmpfs(dir: &Path, size: u64) -> io::Result<()> {
+    use nix::mount::{mount, MsFlags};
+    let options = format!("size={},mode=0700", size);
+    mount(
+        Some("tmpfs"),
+        dir,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(options.as_str()),
+    )
+    .map_err(io::Error::from)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount_tmpfs(_dir: &Path, _size: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tmpfs is only supported on Linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn unmount(dir: &Path) -> io::Result<()> {
+    // Lazily, so a process still inside it doesn't keep the mount alive
+    nix::mount::umount2(dir, nix::mount::MntFlags::MNT_DETACH).map_err(io::Error::from)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unmount(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_accept_binary_suffixes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("2gib"), Ok(2 << 30));
+        assert_eq!(parse_size(" 64k "), Ok(64 << 10));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("10T").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn scratch_dirs_are_ignored_and_removed() {
+        let config = ScratchConfig {
+            root: std::env::temp_dir().join(format!("cmux-scratch-test-{}", std::process::id())),
+            tmpfs_size: None,
+        };
+        assert_ne!(config.dir("../x/y"), config.dir(".._x_y"));
+        assert_ne!(config.dir("a.b"), config.dir("a_b"));
+        assert_eq!(config.dir("../x"), config.root.join("2e2e2f78"));
+        // Long ids still fit in a file name and stay distinct
+        let long = "c".repeat(300);
+        let name = |id: &str| config.dir(id).file_name().unwrap().len();
+        assert_eq!(name(&"c".repeat(MAX_HEX_ID_BYTES)), 254);
+        assert_eq!(name(&long), 71);
+        assert_ne!(config.dir(&long), config.dir(&format!("{long}d")));
+
+        let dir = config.provision("conv-a").unwrap();
+        fs::write(dir.join("out.log"), b"junk").unwrap();
+        assert_eq!(fs::read_to_string(dir.join(".gitignore")).unwrap(), "*\n");
+        // Provisioning again keeps what's there
+        config.provision("conv-a").unwrap();
+        assert!(dir.join("out.log").exists());
+
+        assert!(config.remove("conv-a").unwrap());
+        assert!(!dir.exists());
+        assert!(!config.remove("conv-a").unwrap());
+        fs::remove_dir_all(&config.root).ok();
+    }
+}
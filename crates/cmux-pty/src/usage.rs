@@ -0,0 +1,211 @@
+//! CPU and memory usage of each session's process tree.
+//!
+//! A build or test run started from a terminal can pin every core or hold
+//! gigabytes while the shell itself sits idle, so usage is summed over the
+//! shell and all of its descendants. The server samples `/proc` every
+//! [`SAMPLE_INTERVAL`], keeps the latest figures on each session and
+//! broadcasts them to event subscribers, letting the UI badge busy terminals
+//! and the reaper pick the heaviest ones first. Where `/proc` is unavailable
+//! no usage is reported.
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use nix::unistd::{sysconf, SysconfVar};
+use serde::{Deserialize, Serialize};
+
+/// How often usage is sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Resource usage of one session's process tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionUsage {
+    /// CPU time used since the previous sample, as a percentage of one core
+    pub cpu_percent: f64,
+    /// Resident memory of all processes in the tree
+    pub rss_bytes: u64,
+    /// Processes in the tree, including the shell
+    pub processes: usize,
+}
+
+/// The fields of `/proc/<pid>/stat` usage is computed from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ProcStat {
+    ppid: u32,
+    /// User and system time of the process and its reaped children, so a
+    /// finished compiler's time still counts once its parent waits for it
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+/// Parse a `/proc/<pid>/stat` line. The command name is parenthesized and
+/// may itself contain spaces and parentheses, so fields are counted from
+/// the last `)`.
+fn parse_stat(stat: &str) -> Option<ProcStat> {
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    // Numbered as in proc(5); the first field after the name is field 3
+    let field = |n: usize| fields.get(n - 3)?.parse::<u64>().ok();
+    Some(ProcStat {
+        ppid: u32::try_from(field(4)?).ok()?,
+        cpu_ticks: field(14)? + field(15)? + field(16)? + field(17)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// Every running process, keyed by pid.
+fn scan_processes() -> HashMap<u32, ProcStat> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return HashMap::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse().ok()?;
+            // Processes can exit between listing and reading
+            let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+            Some((pid, parse_stat(&stat)?))
+        })
+        .collect()
+}
+
+/// Turns cumulative CPU time into a rate by remembering each tree's total
+/// from the previous sample.
+pub struct UsageSampler {
+    ticks_per_sec: f64,
+    page_size: u64,
+    previous: HashMap<u32, (u64, Instant)>,
+}
+
+impl UsageSampler {
+    pub fn new() -> Self {
+        let sysconf = |var| sysconf(var).ok().flatten().filter(|v| *v > 0);
+        Self {
+            ticks_per_sec: sysconf(SysconfVar::CLK_TCK).unwrap_or(100) as f64,
+            page_size: sysconf(SysconfVar::PAGE_SIZE).unwrap_or(4096) as u64,
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Usage of the process trees rooted at `roots`, keyed by root pid.
+    /// Roots that are no longer running are left out. A tree's first sample
+    /// reports no CPU use, since there is nothing to compare against yet.
+    pub fn sample(&mut self, roots: &[u32]) -> HashMap<u32, SessionUsage> {
+        let processes = scan_processes();
+        self.sample_from(&processes, roots, Instant::now())
+    }
+
+    fn sample_from(
+        &mut self,
+        processes: &HashMap<u32, ProcStat>,
+        roots: &[u32],
+        now: Instant,
+    ) -> HashMap<u32, SessionUsage> {
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        for (&pid, stat) in processes {
+            children.entry(stat.ppid).or_default().push(pid);
+        }
+
+        let mut previous = HashMap::new();
+        let mut usages = HashMap::new();
+        for &root in roots {
+            if !processes.contains_key(&root) {
+                continue;
+            }
+            let (mut ticks, mut pages, mut count) = (0, 0, 0);
+            let mut pending = vec![root];
+            while let Some(pid) = pending.pop() {
+                let Some(stat) = processes.get(&pid) else {
+                    continue;
+                };
+                ticks += stat.cpu_ticks;
+                pages += stat.rss_pages;
+                count += 1;
+                pending.extend(children.get(&pid).into_iter().flatten());
+            }
+
+            let cpu_percent = match self.previous.get(&root) {
+                Some(&(before, at)) if now > at => {
+                    // Children exiting before being reaped can shrink the total
+                    let used = ticks.saturating_sub(before) as f64 / self.ticks_per_sec;
+                    used / (now - at).as_secs_f64() * 100.0
+                }
+                _ => 0.0,
+            };
+            previous.insert(root, (ticks, now));
+            usages.insert(
+                root,
+                SessionUsage {
+                    cpu_percent,
+                    rss_bytes: pages * self.page_size,
+                    processes: count,
+                },
+            );
+        }
+        self.previous = previous;
+        usages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(ppid: u32, cpu_ticks: u64, rss_pages: u64) -> ProcStat {
+        ProcStat {
+            ppid,
+            cpu_ticks,
+            rss_pages,
+        }
+    }
+
+    #[test]
+    fn parses_stat_lines_with_awkward_command_names() {
+        let line = "4242 (make) -j (8)) R 4200 4242 4200 34816 4242 4194304 \
+                    120 0 0 0 300 50 7 3 20 0 1 0 123456 104857600 2560 \
+                    18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 17 3 0 0 0 0 0";
+        assert_eq!(parse_stat(line), Some(stat(4200, 360, 2560)));
+        assert_eq!(parse_stat("4242 (sh) R"), None);
+    }
+
+    #[test]
+    fn sums_process_trees_and_rates_cpu_between_samples() {
+        let mut sampler = UsageSampler {
+            ticks_per_sec: 100.0,
+            page_size: 4096,
+            previous: HashMap::new(),
+        };
+        let mut processes = HashMap::from([
+            (1, stat(0, 0, 10)),
+            (10, stat(1, 100, 1)),
+            (11, stat(10, 50, 2)),
+            (12, stat(11, 50, 3)),
+            (20, stat(1, 1000, 100)),
+        ]);
+        let start = Instant::now();
+        let first = sampler.sample_from(&processes, &[10, 30], start);
+        assert_eq!(
+            first,
+            HashMap::from([(
+                10,
+                SessionUsage {
+                    cpu_percent: 0.0,
+                    rss_bytes: 6 * 4096,
+                    processes: 3,
+                },
+            )])
+        );
+
+        // Two seconds later the tree used another 3s of CPU: 150% of a core
+        processes.insert(12, stat(11, 350, 3));
+        let second = sampler.sample_from(&processes, &[10], start + Duration::from_secs(2));
+        assert_eq!(second[&10].cpu_percent, 150.0);
+
+        // A child exiting unreaped never makes usage negative
+        processes.remove(&12);
+        let third = sampler.sample_from(&processes, &[10], start + Duration::from_secs(4));
+        assert_eq!(third[&10].cpu_percent, 0.0);
+        assert_eq!(third[&10].processes, 2);
+    }
+}
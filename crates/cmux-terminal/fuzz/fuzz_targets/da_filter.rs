@@ -0,0 +1,24 @@
+//! Checks that `DaFilter` output does not depend on how the stream is split
+//! and that filtering twice changes nothing. Run with
+//! `cargo +nightly fuzz run da_filter`.
+#![no_main]
+
+use cmux_terminal::{filter_da_queries, DaFilter};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk, rest)) = data.split_first() else {
+        return;
+    };
+    let whole = filter_da_queries(rest);
+
+    let mut filter = DaFilter::new();
+    let mut chunked = Vec::new();
+    for piece in rest.chunks(usize::from(chunk).max(1)) {
+        chunked.extend(filter.filter(piece));
+    }
+    chunked.extend(filter.flush());
+
+    assert_eq!(chunked, whole);
+    assert_eq!(filter_da_queries(&whole), whole);
+});
@@ -0,0 +1,20 @@
+//! Feeds arbitrary PTY output to `VirtualTerminal` in chunks chosen by the
+//! input itself. Run with `cargo +nightly fuzz run virtual_terminal`.
+#![no_main]
+
+use cmux_terminal::VirtualTerminal;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // First byte picks the chunk size so split sequences get exercised
+    let Some((&chunk, rest)) = data.split_first() else {
+        return;
+    };
+    let mut term = VirtualTerminal::new(8, 20);
+    for piece in rest.chunks(usize::from(chunk).max(1)) {
+        term.process(piece);
+        assert!(term.cursor_row() < term.rows() && term.cursor_col() < term.cols());
+    }
+    let _ = term.to_ansi_string(true);
+    let _ = term.drain_responses();
+});
@@ -0,0 +1,138 @@
+//! C1 control normalization.
+//!
+//! Legacy programs may emit 8-bit C1 controls (e.g. 0x9B for CSI, 0x9D for OSC)
+//! while everything else on the stream is UTF-8. Fed straight into the parser,
+//! those bytes are invalid UTF-8 and come out as replacement characters.
+//!
+//! [`C1Decoder`] sits in front of the parser and rewrites C1 controls into their
+//! 7-bit `ESC Fe` equivalents (or drops them), while leaving bytes that belong to
+//! a well-formed UTF-8 sequence untouched. Like xterm in UTF-8 mode, a UTF-8
+//! encoded C1 code point (U+0080..U+009F, i.e. `C2 80`..`C2 9F`) is also treated
+//! as a control rather than printed.
+
+use serde::{Deserialize, Serialize};
+
+/// How 8-bit C1 control codes in terminal input are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum C1Mode {
+    /// Execute C1 controls as their 7-bit `ESC Fe` equivalents (xterm default).
+    #[default]
+    Interpret,
+    /// Drop C1 controls from the stream.
+    Strip,
+}
+
+/// Stateful C1 rewriter. Keeps enough UTF-8 state to tell a C1 byte apart from a
+/// continuation byte, including across chunk boundaries.
+#[derive(Debug, Clone, Default)]
+pub struct C1Decoder {
+    mode: C1Mode,
+    /// Continuation bytes still expected for the current UTF-8 sequence
+    pending_continuations: u8,
+    /// A 0xC2 lead byte was held back to see whether it encodes a C1 control
+    held_c2: bool,
+}
+
+impl C1Decoder {
+    pub fn new(mode: C1Mode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    pub fn mode(&self) -> C1Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: C1Mode) {
+        self.mode = mode;
+    }
+
+    /// Rewrite a chunk of input, returning bytes safe to feed to the parser.
+    pub fn decode(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + 4);
+
+        for &byte in data {
+            if self.held_c2 {
+                self.held_c2 = false;
+                if (0x80..=0x9f).contains(&byte) {
+                    self.emit_c1(byte, &mut out);
+                    continue;
+                }
+                out.push(0xc2);
+                self.pending_continuations = 1;
+            }
+
+            match byte {
+                0x80..=0xbf if self.pending_continuations > 0 => {
+                    self.pending_continuations -= 1;
+                    out.push(byte);
+                }
+                0x80..=0x9f => {
+                    self.pending_continuations = 0;
+                    self.emit_c1(byte, &mut out);
+                }
+                0xc2 => {
+                    self.pending_continuations = 0;
+                    self.held_c2 = true;
+                }
+                _ => {
+                    self.pending_continuations = match byte {
+                        0xc3..=0xdf => 1,
+                        0xe0..=0xef => 2,
+                        0xf0..=0xf4 => 3,
+                        _ => 0,
+                    };
+                    out.push(byte);
+                }
+            }
+        }
+
+        out
+    }
+
+    fn emit_c1(&self, byte: u8, out: &mut Vec<u8>) {
+        if self.mode == C1Mode::Interpret {
+            out.extend_from_slice(&[0x1b, byte - 0x40]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interprets_raw_and_utf8_encoded_c1() {
+        let mut decoder = C1Decoder::new(C1Mode::Interpret);
+        assert_eq!(decoder.decode(b"\x9b31mX"), b"\x1b[31mX");
+        assert_eq!(decoder.decode(b"\x9d0;t\x9c"), b"\x1b]0;t\x1b\\");
+        assert_eq!(decoder.decode(b"\xc2\x9b1m"), b"\x1b[1m");
+    }
+
+    #[test]
+    fn strips_c1_when_configured() {
+        let mut decoder = C1Decoder::new(C1Mode::Strip);
+        assert_eq!(decoder.decode(b"a\x9b1mb\xc2\x85c"), b"a1mbc");
+    }
+
+    #[test]
+    fn leaves_utf8_continuation_bytes_alone() {
+        let mut decoder = C1Decoder::new(C1Mode::Interpret);
+        // "ě" is C4 9B, "€" is E2 82 AC, "¢" is C2 A2: all contain 0x80..0x9F or 0xC2
+        let text = "ě€¢ok".as_bytes();
+        assert_eq!(decoder.decode(text), text);
+    }
+
+    #[test]
+    fn handles_sequences_split_across_chunks() {
+        let mut decoder = C1Decoder::new(C1Mode::Interpret);
+        assert_eq!(decoder.decode(b"x\xc4"), b"x\xc4");
+        assert_eq!(decoder.decode(b"\x9b"), b"\x9b");
+        assert_eq!(decoder.decode(b"\xc2"), b"");
+        assert_eq!(decoder.decode(b"\x9b2J"), b"\x1b[2J");
+        assert_eq!(decoder.decode(b"\xc2"), b"");
+        assert_eq!(decoder.decode(b"\xa2"), "¢".as_bytes());
+    }
+}
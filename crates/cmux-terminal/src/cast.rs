@@ -0,0 +1,460 @@
+//! asciicast v2 recording and playback.
+//!
+//! A [`Recorder`] feeds PTY output to a [`VirtualTerminal`] and writes the
+//! same bytes, with their timing, as an asciinema cast: a JSON header line
+//! followed by one `[time, code, data]` line per event. Resizes are recorded
+//! as `r` events, so a [`Player`] can feed a cast back through the emulator
+//! and land on the screen the session had at any point.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::terminal::VirtualTerminal;
+
+/// The first line of a cast.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CastHeader {
+    /// Always 2
+    pub version: u32,
+    /// Columns at the start of the recording
+    pub width: usize,
+    /// Rows at the start of the recording
+    pub height: usize,
+    /// Start of the recording, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Environment of the recorded shell, such as `SHELL` and `TERM`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl CastHeader {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            version: 2,
+            width,
+            height,
+            timestamp: None,
+            title: None,
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+/// What happened at one point of a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CastData {
+    /// Output written to the terminal (`o`)
+    Output(String),
+    /// Input typed by the user (`i`)
+    Input(String),
+    /// The terminal was resized (`r`)
+    Resize { cols: usize, rows: usize },
+    /// A labelled point to jump to (`m`)
+    Marker(String),
+}
+
+/// An event and its time in seconds from the start of the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastEvent {
+    pub time: f64,
+    pub data: CastData,
+}
+
+impl CastEvent {
+    fn to_line(&self) -> io::Result<String> {
+        let resize;
+        let (code, data) = match &self.data {
+            CastData::Output(data) => ("o", data.as_str()),
+            CastData::Input(data) => ("i", data.as_str()),
+            CastData::Resize { cols, rows } => {
+                resize = format!("{}x{}", cols, rows);
+                ("r", resize.as_str())
+            }
+            CastData::Marker(label) => ("m", label.as_str()),
+        };
+        Ok(serde_json::to_string(&(self.time, code, data))?)
+    }
+
+    /// Parse one event line; `None` for event codes this crate doesn't know,
+    /// which players are expected to skip.
+    fn from_line(line: &str) -> io::Result<Option<Self>> {
+        let (time, code, data): (f64, String, String) = serde_json::from_str(line)?;
+        let data = match code.as_str() {
+            "o" => CastData::Output(data),
+            "i" => CastData::Input(data),
+            "r" => {
+                let size = data
+                    .split_once('x')
+                    .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)));
+                let Some((cols, rows)) = size else {
+                    return Err(invalid(format!("bad resize event: {:?}", data)));
+                };
+                CastData::Resize { cols, rows }
+            }
+            "m" => CastData::Marker(data),
+            _ => return Ok(None),
+        };
+        Ok(Some(Self { time, data }))
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A whole recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cast {
+    pub header: CastHeader,
+    pub events: Vec<CastEvent>,
+}
+
+impl Cast {
+    /// Read a cast file. Blank lines and unknown event codes are skipped.
+    pub fn read(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| invalid("empty cast".to_string()))??;
+        let header: CastHeader = serde_json::from_str(&header)?;
+        if header.version != 2 {
+            return Err(invalid(format!(
+                "unsupported cast version {}",
+                header.version
+            )));
+        }
+        let mut events = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            events.extend(CastEvent::from_line(&line)?);
+        }
+        Ok(Self { header, events })
+    }
+
+    /// Time of the last event.
+    pub fn duration(&self) -> f64 {
+        self.events.last().map_or(0.0, |event| event.time)
+    }
+}
+
+/// Seconds with microsecond precision, as asciinema writes them.
+fn seconds(time: Duration) -> f64 {
+    time.as_micros() as f64 / 1_000_000.0
+}
+
+/// Records a [`VirtualTerminal`] session to an asciicast v2 stream.
+///
+/// Output events carry text, so bytes that end partway through a UTF-8
+/// sequence are held back until the rest arrives; the terminal itself sees
+/// every byte as it comes. Invalid UTF-8 is recorded as U+FFFD.
+pub struct Recorder<W: Write> {
+    term: VirtualTerminal,
+    out: W,
+    start: Instant,
+    /// Incomplete UTF-8 sequence left over from the last output
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Start recording, timestamped now and titled with the terminal's
+    /// title if it has one.
+    pub fn new(term: VirtualTerminal, out: W) -> io::Result<Self> {
+        let mut header = CastHeader::new(term.cols(), term.rows());
+        header.timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|since| since.as_secs());
+        header.title = term.title().map(str::to_string);
+        Self::with_header(term, out, header)
+    }
+
+    /// Start recording with `header`; its size is taken from `term`.
+    pub fn with_header(
+        term: VirtualTerminal,
+        mut out: W,
+        mut header: CastHeader,
+    ) -> io::Result<Self> {
+        header.width = term.cols();
+        header.height = term.rows();
+        serde_json::to_writer(&mut out, &header)?;
+        out.write_all(b"\n")?;
+        Ok(Self {
+            term,
+            out,
+            start: Instant::now(),
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn terminal(&self) -> &VirtualTerminal {
+        &self.term
+    }
+
+    /// The terminal, for anything but output and resizes, which would be
+    /// missing from the recording.
+    pub fn terminal_mut(&mut self) -> &mut VirtualTerminal {
+        &mut self.term
+    }
+
+    /// Time since recording started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Process PTY output and record it.
+    pub fn process(&mut self, data: &[u8]) -> io::Result<()> {
+        self.process_at(self.elapsed(), data)
+    }
+
+    /// [`process`](Self::process) at `time` from the start, for callers
+    /// that timestamp output themselves. Times should not decrease.
+    pub fn process_at(&mut self, time: Duration, data: &[u8]) -> io::Result<()> {
+        self.term.process(data);
+        self.pending.extend_from_slice(data);
+        let text = self.take_text();
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.write_event(time, CastData::Output(text))
+    }
+
+    /// Resize the terminal and record it.
+    pub fn resize(&mut self, rows: usize, cols: usize) -> io::Result<()> {
+        self.resize_at(self.elapsed(), rows, cols)
+    }
+
+    pub fn resize_at(&mut self, time: Duration, rows: usize, cols: usize) -> io::Result<()> {
+        self.term.resize(rows, cols);
+        self.write_event(time, CastData::Resize { cols, rows })
+    }
+
+    /// Record input sent to the PTY.
+    pub fn input(&mut self, data: &str) -> io::Result<()> {
+        self.write_event(self.elapsed(), CastData::Input(data.to_string()))
+    }
+
+    /// Record a marker, e.g. where an agent started a step.
+    pub fn marker(&mut self, label: &str) -> io::Result<()> {
+        self.write_event(self.elapsed(), CastData::Marker(label.to_string()))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Stop recording, returning the terminal and the writer.
+    pub fn into_parts(self) -> (VirtualTerminal, W) {
+        (self.term, self.out)
+    }
+
+    fn write_event(&mut self, time: Duration, data: CastData) -> io::Result<()> {
+        let event = CastEvent {
+            time: seconds(time),
+            data,
+        };
+        self.out.write_all(event.to_line()?.as_bytes())?;
+        self.out.write_all(b"\n")
+    }
+
+    /// Decode `pending` up to any incomplete sequence at its end.
+    fn take_text(&mut self) -> String {
+        let mut text = String::new();
+        let mut rest = &self.pending[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending = rest.to_vec();
+        text
+    }
+}
+
+/// Replays a [`Cast`] through a [`VirtualTerminal`].
+///
+/// Playback is driven by the caller, event by event or up to a time, so it
+/// can run as fast as possible in tests or be paced against a clock.
+pub struct Player {
+    cast: Cast,
+    term: VirtualTerminal,
+    /// Index of the next event to apply
+    next: usize,
+}
+
+impl Player {
+    pub fn new(cast: Cast) -> Self {
+        let term = VirtualTerminal::new(cast.header.height, cast.header.width);
+        Self {
+            cast,
+            term,
+            next: 0,
+        }
+    }
+
+    pub fn cast(&self) -> &Cast {
+        &self.cast
+    }
+
+    pub fn terminal(&self) -> &VirtualTerminal {
+        &self.term
+    }
+
+    /// Time of the last applied event.
+    pub fn position(&self) -> f64 {
+        self.next
+            .checked_sub(1)
+            .map_or(0.0, |i| self.cast.events[i].time)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.cast.events.len()
+    }
+
+    /// Apply the next event, returning it.
+    pub fn step(&mut self) -> Option<&CastEvent> {
+        let event = self.cast.events.get(self.next)?;
+        match &event.data {
+            CastData::Output(data) => {
+                self.term.process(data.as_bytes());
+                // Nobody answers queries during playback
+                self.term.drain_responses();
+            }
+            CastData::Resize { cols, rows } => self.term.resize(*rows, *cols),
+            CastData::Input(_) | CastData::Marker(_) => {}
+        }
+        self.next += 1;
+        Some(event)
+    }
+
+    /// Apply every event up to and including `time`. Seeking backwards
+    /// replays from the start.
+    pub fn seek(&mut self, time: f64) {
+        if time < self.position() {
+            self.term = VirtualTerminal::new(self.cast.header.height, self.cast.header.width);
+            self.next = 0;
+        }
+        while self
+            .cast
+            .events
+            .get(self.next)
+            .is_some_and(|event| event.time <= time)
+        {
+            self.step();
+        }
+    }
+
+    /// Apply every remaining event.
+    pub fn finish(&mut self) -> &VirtualTerminal {
+        while self.step().is_some() {}
+        &self.term
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn recording_replays_to_the_same_screen() {
+        let mut header = CastHeader::new(0, 0);
+        header.env.insert("TERM".into(), "xterm-256color".into());
+        let mut rec =
+            Recorder::with_header(VirtualTerminal::new(3, 10), Vec::new(), header).unwrap();
+        rec.process_at(ms(0), b"$ echo hi\r\n").unwrap();
+        // 日 split across two reads
+        rec.process_at(ms(250), b"hi \xe6\x97").unwrap();
+        rec.process_at(ms(500), b"\xa5\r\n$ ").unwrap();
+        rec.resize_at(ms(1500), 4, 6).unwrap();
+        rec.process_at(ms(1750), b"\x1b[31mred\x1b[0m").unwrap();
+        let (term, out) = rec.into_parts();
+
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"version":2,"width":10,"height":3,"env":{"TERM":"xterm-256color"}}"#
+        );
+        assert_eq!(lines[2], r#"[0.25,"o","hi "]"#);
+        assert_eq!(lines[3], r#"[0.5,"o","日\r\n$ "]"#);
+        assert_eq!(lines[4], r#"[1.5,"r","6x4"]"#);
+
+        let cast = Cast::read(text.as_bytes()).unwrap();
+        assert_eq!(cast.events.len(), 5);
+        assert_eq!(cast.duration(), 1.75);
+        let mut player = Player::new(cast);
+        player.seek(0.6);
+        assert_eq!(player.terminal().get_lines(), ["$ echo hi", "hi 日", "$"]);
+        assert_eq!(player.terminal().cols(), 10);
+
+        assert_eq!(player.finish().get_lines(), term.get_lines());
+        assert_eq!(player.terminal().to_ansi(), term.to_ansi());
+        assert_eq!((player.terminal().rows(), player.terminal().cols()), (4, 6));
+
+        // Back to the start replays from scratch
+        player.seek(0.0);
+        assert_eq!(player.position(), 0.0);
+        assert_eq!(player.terminal().get_lines()[0], "$ echo hi");
+        assert_eq!(player.terminal().get_lines()[1], "");
+    }
+
+    #[test]
+    fn reading_skips_unknown_events_and_rejects_bad_ones() {
+        let cast = "{\"version\":2,\"width\":80,\"height\":24,\"timestamp\":1700000000}\n\
+                    [0.1,\"o\",\"a\"]\n\
+                    \n\
+                    [0.2,\"x\",\"future\"]\n\
+                    [0.3,\"m\",\"step 1\"]\n";
+        let cast = Cast::read(cast.as_bytes()).unwrap();
+        assert_eq!(cast.header.timestamp, Some(1_700_000_000));
+        assert_eq!(
+            cast.events,
+            [
+                CastEvent {
+                    time: 0.1,
+                    data: CastData::Output("a".into())
+                },
+                CastEvent {
+                    time: 0.3,
+                    data: CastData::Marker("step 1".into())
+                },
+            ]
+        );
+
+        let bad_resize = "{\"version\":2,\"width\":80,\"height\":24}\n[0.1,\"r\",\"80\"]\n";
+        assert!(Cast::read(bad_resize.as_bytes()).is_err());
+        let v1 = "{\"version\":1,\"width\":80,\"height\":24}\n";
+        assert!(Cast::read(v1.as_bytes()).is_err());
+        assert!(Cast::read(&b""[..]).is_err());
+    }
+}
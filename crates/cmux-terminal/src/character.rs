@@ -1,67 +1,198 @@
 //! Terminal character and row types optimized for performance.
 //!
 //! This module implements a zellij-inspired approach to terminal character storage:
-//! - Shared styles via Arc to reduce memory duplication (Arc for thread safety)
+//! - Styles stored once per grid in a [`StyleTable`], with cells holding a
+//!   4-byte [`StyleId`] into it
 //! - Precomputed character width to avoid repeated unicode_width calls
+//! - Grapheme clusters (combining marks, emoji ZWJ sequences) kept whole, with
+//!   the part after the base character interned so cells stay small
 //! - Row structure with canonical line tracking for proper resize/rewrap
 
 use ratatui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::VecDeque;
-use std::sync::Arc;
-use unicode_width::UnicodeWidthChar;
+use std::collections::{HashMap, VecDeque};
+use std::num::NonZeroU16;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::SystemTime;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 /// Type alias for a 256-color palette where each entry is an optional RGB tuple.
 /// None means use the default palette color, Some((r, g, b)) is a custom color.
 pub type ColorPalette = [Option<(u8, u8, u8)>; 256];
 
-/// Shared character styles using atomic reference counting.
-/// This avoids duplicating style data across millions of cells.
-/// Uses Arc for thread safety in async contexts.
-#[derive(Clone, Debug, PartialEq, Default)]
-pub enum SharedStyles {
-    /// Default style (no allocation needed)
-    #[default]
-    Default,
-    /// Custom style (atomic reference counted for thread safety)
-    Custom(Arc<CharacterStyles>),
+/// Handle for a style in a [`StyleTable`]. Cells store this instead of the
+/// style itself, so a cell stays small and equal styles are stored once.
+/// A handle only means something in the table of the grid holding the cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct StyleId(u32);
+
+impl StyleId {
+    /// The default style, present in every table.
+    pub const DEFAULT: StyleId = StyleId(0);
+
+    /// Check if this is the default style.
+    pub fn is_default(self) -> bool {
+        self == StyleId::DEFAULT
+    }
 }
 
-/// Static default style to avoid allocations for the common case.
+/// Static default style, returned for handles a table doesn't know.
 static DEFAULT_STYLES: std::sync::LazyLock<CharacterStyles> =
     std::sync::LazyLock::new(CharacterStyles::default);
 
-impl SharedStyles {
-    /// Create a new shared style from a CharacterStyles struct.
-    pub fn new(styles: CharacterStyles) -> Self {
+/// New styles interned between automatic compactions of a [`StyleTable`].
+pub(crate) const STYLE_COMPACTION_INTERVAL: usize = 1024;
+
+/// The styles of a grid's cells, indexed by [`StyleId`].
+///
+/// Every distinct style is stored once and handed out as a `u32` handle,
+/// with slot 0 holding the default style. Cells don't keep a reference
+/// count, so styles no cell uses any more are found by the owning
+/// [`Grid`](crate::Grid), which marks the handles still in use and frees the
+/// rest for reuse. That happens automatically every
+/// `STYLE_COMPACTION_INTERVAL` new styles.
+#[derive(Clone, Debug)]
+pub struct StyleTable {
+    styles: Vec<CharacterStyles>,
+    ids: HashMap<CharacterStyles, StyleId>,
+    free: Vec<u32>,
+    interned_since_compaction: usize,
+    compactions: u64,
+}
+
+/// Size of a [`StyleTable`], for embedders bounding style growth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct StyleStats {
+    /// Distinct non-default styles currently interned.
+    pub unique_styles: usize,
+    /// Styles no cell uses, which the next compaction will drop.
+    pub unreferenced_styles: usize,
+    /// Approximate heap bytes used by the table.
+    pub memory_bytes: usize,
+    /// Compactions run so far.
+    pub compactions: u64,
+}
+
+impl Default for StyleTable {
+    fn default() -> Self {
+        Self {
+            styles: vec![CharacterStyles::default()],
+            ids: HashMap::new(),
+            free: Vec::new(),
+            interned_since_compaction: 0,
+            compactions: 0,
+        }
+    }
+}
+
+impl StyleTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle for `styles`, reusing the existing entry if any.
+    pub fn intern(&mut self, styles: CharacterStyles) -> StyleId {
         if styles == CharacterStyles::default() {
-            SharedStyles::Default
-        } else {
-            SharedStyles::Custom(Arc::new(styles))
+            return StyleId::DEFAULT;
         }
+        if let Some(&id) = self.ids.get(&styles) {
+            return id;
+        }
+        let id = match self.free.pop() {
+            Some(slot) => {
+                self.styles[slot as usize] = styles;
+                StyleId(slot)
+            }
+            None => {
+                self.styles.push(styles);
+                StyleId(self.styles.len() as u32 - 1)
+            }
+        };
+        self.ids.insert(styles, id);
+        self.interned_since_compaction += 1;
+        id
     }
 
-    /// Get the underlying CharacterStyles.
-    pub fn get(&self) -> &CharacterStyles {
-        match self {
-            SharedStyles::Default => &DEFAULT_STYLES,
-            SharedStyles::Custom(arc) => arc.as_ref(),
+    /// The style behind `id`. Unknown handles resolve to the default style.
+    pub fn get(&self, id: StyleId) -> &CharacterStyles {
+        self.styles.get(id.0 as usize).unwrap_or(&DEFAULT_STYLES)
+    }
+
+    /// Convert the style behind `id` to a ratatui Style.
+    pub fn to_ratatui_style(&self, id: StyleId) -> Style {
+        self.get(id).to_ratatui_style()
+    }
+
+    /// Whether enough styles were interned since the last compaction that
+    /// the owner should run another.
+    pub(crate) fn needs_compaction(&self) -> bool {
+        self.interned_since_compaction >= STYLE_COMPACTION_INTERVAL
+    }
+
+    /// Which slots `in_use` refers to, for [`compact`](Self::compact) and
+    /// [`stats`](Self::stats).
+    pub(crate) fn mark(&self, in_use: impl IntoIterator<Item = StyleId>) -> Vec<bool> {
+        let mut marks = vec![false; self.styles.len()];
+        for id in in_use {
+            if let Some(mark) = marks.get_mut(id.0 as usize) {
+                *mark = true;
+            }
         }
+        marks
     }
 
-    /// Check if this is the default style.
-    pub fn is_default(&self) -> bool {
-        matches!(self, SharedStyles::Default)
+    /// Whether slot `index` holds a style rather than waiting for reuse.
+    fn is_occupied(&self, index: usize) -> bool {
+        index > 0 && self.ids.get(&self.styles[index]) == Some(&StyleId(index as u32))
     }
 
-    /// Convert to a ratatui Style.
-    pub fn to_ratatui_style(&self) -> Style {
-        self.get().to_ratatui_style()
+    /// Free the styles not marked in `marks`. Returns how many were removed.
+    pub(crate) fn compact(&mut self, marks: &[bool]) -> usize {
+        let mut removed = 0;
+        for index in 1..self.styles.len() {
+            if self.is_occupied(index) && !marks.get(index).copied().unwrap_or(false) {
+                self.ids.remove(&self.styles[index]);
+                self.free.push(index as u32);
+                removed += 1;
+            }
+        }
+        // Give back the free slots at the end
+        while self.styles.len() > 1 && !self.is_occupied(self.styles.len() - 1) {
+            self.styles.pop();
+        }
+        let len = self.styles.len() as u32;
+        self.free.retain(|&slot| slot < len);
+        self.styles.shrink_to_fit();
+        self.ids.shrink_to_fit();
+        self.free.shrink_to_fit();
+        self.interned_since_compaction = 0;
+        self.compactions += 1;
+        removed
+    }
+
+    /// Size of the table, counting the styles not marked in `marks` as
+    /// unreferenced.
+    pub(crate) fn stats(&self, marks: &[bool]) -> StyleStats {
+        let slot = std::mem::size_of::<CharacterStyles>();
+        let entry = std::mem::size_of::<(CharacterStyles, StyleId)>();
+        StyleStats {
+            unique_styles: self.ids.len(),
+            unreferenced_styles: self
+                .ids
+                .values()
+                .filter(|id| !marks.get(id.0 as usize).copied().unwrap_or(false))
+                .count(),
+            memory_bytes: self.styles.capacity() * slot
+                + self.ids.capacity() * entry
+                + self.free.capacity() * std::mem::size_of::<u32>(),
+            compactions: self.compactions,
+        }
     }
 }
 
 /// Character styles - similar to ratatui's Style but designed for sharing.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct CharacterStyles {
     pub foreground: Option<Color>,
     pub background: Option<Color>,
@@ -116,34 +247,108 @@ impl CharacterStyles {
     }
 }
 
+/// How characters of East Asian Ambiguous width (UAX #11), such as box
+/// drawing, Greek and Cyrillic letters, are measured. Terminals in CJK
+/// locales usually draw them two cells wide.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AmbiguousWidth {
+    #[default]
+    Narrow,
+    Wide,
+}
+
+impl AmbiguousWidth {
+    /// Display width of `c`; 0 for combining marks and other zero-width
+    /// characters, 1 for controls.
+    pub fn char_width(self, c: char) -> usize {
+        match self {
+            AmbiguousWidth::Narrow => c.width(),
+            AmbiguousWidth::Wide => c.width_cjk(),
+        }
+        .unwrap_or(1)
+    }
+
+    /// Display width of a grapheme cluster, at most two cells.
+    pub fn grapheme_width(self, grapheme: &str) -> usize {
+        match self {
+            AmbiguousWidth::Narrow => grapheme.width(),
+            AmbiguousWidth::Wide => grapheme.width_cjk(),
+        }
+        .min(2)
+    }
+}
+
+/// Longest grapheme cluster kept in one cell, in bytes. Family emoji need
+/// about 40; further combining marks (as in "zalgo" text) are dropped.
+pub(crate) const MAX_GRAPHEME_BYTES: usize = 64;
+
+/// Interned grapheme tails: whatever follows a cell's base character.
+///
+/// Cells only store an index so they stay 16 bytes. Distinct tails are few
+/// in practice (combining marks, skin tones, ZWJ and flag sequences), so the
+/// table is global and never shrinks; once it holds `u16::MAX` tails, new
+/// ones are dropped and their cells show just the base character.
+#[derive(Default)]
+struct GraphemeTails {
+    tails: Vec<Arc<str>>,
+    index: HashMap<Arc<str>, NonZeroU16>,
+}
+
+static GRAPHEME_TAILS: LazyLock<RwLock<GraphemeTails>> = LazyLock::new(Default::default);
+
+fn intern_grapheme_tail(tail: &str) -> Option<NonZeroU16> {
+    if tail.is_empty() {
+        return None;
+    }
+    if let Some(&id) = GRAPHEME_TAILS.read().unwrap().index.get(tail) {
+        return Some(id);
+    }
+    let mut table = GRAPHEME_TAILS.write().unwrap();
+    if let Some(&id) = table.index.get(tail) {
+        return Some(id);
+    }
+    let id = NonZeroU16::new(u16::try_from(table.tails.len() + 1).ok()?)?;
+    let tail: Arc<str> = tail.into();
+    table.tails.push(tail.clone());
+    table.index.insert(tail, id);
+    Some(id)
+}
+
+fn grapheme_tail(id: NonZeroU16) -> Arc<str> {
+    GRAPHEME_TAILS.read().unwrap().tails[usize::from(id.get() - 1)].clone()
+}
+
 /// A single character in the terminal grid.
-/// Designed to be exactly 16 bytes for cache efficiency (following zellij's approach).
+/// Designed to be exactly 12 bytes for cache efficiency (following zellij's approach).
 ///
 /// Memory layout:
 /// - character: 4 bytes (char)
-/// - styles: 8 bytes (enum with Arc pointer or Default variant)
+/// - styles: 4 bytes (handle into the grid's style table)
 /// - width: 1 byte (precomputed character width)
 /// - wide_spacer: 1 byte (bool, indicates this is a spacer for a wide char)
-/// - padding: 2 bytes
+/// - combining: 2 bytes (interned rest of the grapheme cluster)
 #[derive(Clone, Debug)]
 pub struct TerminalCharacter {
-    /// The Unicode character.
+    /// The Unicode character (the first of its grapheme cluster).
     pub character: char,
-    /// Shared styles for this character.
-    pub styles: SharedStyles,
+    /// Style of this character, in the style table of its grid.
+    pub styles: StyleId,
     /// Precomputed display width (0, 1, or 2).
     width: u8,
     /// True if this cell is a spacer for a wide character (the cell to the right of a double-width char).
     pub wide_spacer: bool,
+    /// Characters following `character` in its grapheme cluster, if any.
+    combining: Option<NonZeroU16>,
 }
 
 impl Default for TerminalCharacter {
     fn default() -> Self {
         Self {
             character: ' ',
-            styles: SharedStyles::Default,
+            styles: StyleId::DEFAULT,
             width: 1,
             wide_spacer: false,
+            combining: None,
         }
     }
 }
@@ -151,6 +356,7 @@ impl Default for TerminalCharacter {
 impl PartialEq for TerminalCharacter {
     fn eq(&self, other: &Self) -> bool {
         self.character == other.character
+            && self.combining == other.combining
             && self.styles == other.styles
             && self.wide_spacer == other.wide_spacer
     }
@@ -158,33 +364,36 @@ impl PartialEq for TerminalCharacter {
 
 impl TerminalCharacter {
     /// Create a new terminal character with precomputed width.
-    pub fn new(character: char, styles: SharedStyles) -> Self {
+    pub fn new(character: char, styles: StyleId) -> Self {
         let width = character.width().unwrap_or(1) as u8;
         Self {
             character,
             styles,
             width,
             wide_spacer: false,
+            combining: None,
         }
     }
 
     /// Create a new terminal character with explicit width.
-    pub fn with_width(character: char, styles: SharedStyles, width: u8) -> Self {
+    pub fn with_width(character: char, styles: StyleId, width: u8) -> Self {
         Self {
             character,
             styles,
             width,
             wide_spacer: false,
+            combining: None,
         }
     }
 
     /// Create a wide character spacer cell.
-    pub fn wide_spacer(styles: SharedStyles) -> Self {
+    pub fn wide_spacer(styles: StyleId) -> Self {
         Self {
             character: ' ',
             styles,
             width: 0,
             wide_spacer: true,
+            combining: None,
         }
     }
 
@@ -207,12 +416,99 @@ impl TerminalCharacter {
     }
 
     /// Create a blank character with the given style (for erase operations).
-    pub fn blank_with_style(styles: SharedStyles) -> Self {
+    pub fn blank_with_style(styles: StyleId) -> Self {
         Self {
             character: ' ',
             styles,
             width: 1,
             wide_spacer: false,
+            combining: None,
+        }
+    }
+
+    /// Whether two cells draw the same, with this cell's style in `styles`
+    /// and `other`'s in `other_styles`. Style handles from different grids
+    /// can't be compared directly.
+    pub fn draws_like(&self, styles: &StyleTable, other: &Self, other_styles: &StyleTable) -> bool {
+        self.character == other.character
+            && self.combining == other.combining
+            && self.wide_spacer == other.wide_spacer
+            && styles.get(self.styles) == other_styles.get(other.styles)
+    }
+
+    /// Characters following [`character`](Self::character) in the cell's
+    /// grapheme cluster, e.g. combining accents or the rest of a ZWJ sequence.
+    pub fn combining(&self) -> Option<Arc<str>> {
+        self.combining.map(grapheme_tail)
+    }
+
+    /// Append the cell's whole grapheme cluster to `out`.
+    pub fn push_grapheme(&self, out: &mut String) {
+        out.push(self.character);
+        if let Some(id) = self.combining {
+            out.push_str(&grapheme_tail(id));
+        }
+    }
+
+    /// The cell's whole grapheme cluster.
+    pub fn grapheme(&self) -> String {
+        let mut out = String::new();
+        self.push_grapheme(&mut out);
+        out
+    }
+
+    /// Replace the cell's content with the cluster `grapheme`, drawn `width`
+    /// cells wide.
+    pub fn set_grapheme(&mut self, grapheme: &str, width: u8) {
+        let mut chars = grapheme.chars();
+        self.character = chars.next().unwrap_or(' ');
+        self.combining = intern_grapheme_tail(chars.as_str());
+        self.width = width;
+    }
+}
+
+/// DEC line size attribute (`ESC # 3` .. `ESC # 6`).
+///
+/// Double-size lines draw every cell two columns wide, so only half the
+/// terminal's columns fit. Double-height lines come in pairs: the top half on
+/// one row and the bottom half, with the same text, on the row below.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LineSize {
+    /// DECSWL
+    #[default]
+    Normal,
+    /// DECDWL
+    DoubleWidth,
+    /// DECDHL, top half
+    DoubleHeightTop,
+    /// DECDHL, bottom half
+    DoubleHeightBottom,
+}
+
+impl LineSize {
+    /// Whether cells on the line are drawn two columns wide.
+    #[inline]
+    pub fn is_double_width(self) -> bool {
+        self != LineSize::Normal
+    }
+
+    /// Number of cells that fit on a line of this size in `cols` columns.
+    #[inline]
+    pub fn columns(self, cols: usize) -> usize {
+        if self.is_double_width() {
+            (cols / 2).max(1)
+        } else {
+            cols
+        }
+    }
+
+    /// The `ESC #` sequence selecting this line size.
+    pub fn escape_sequence(self) -> &'static str {
+        match self {
+            LineSize::Normal => "\x1b#5",
+            LineSize::DoubleWidth => "\x1b#6",
+            LineSize::DoubleHeightTop => "\x1b#3",
+            LineSize::DoubleHeightBottom => "\x1b#4",
         }
     }
 }
@@ -226,6 +522,10 @@ pub struct Row {
     /// True if this is the start of a logical line (after a newline).
     /// False if this row is a wrapped continuation of the previous line.
     pub is_canonical: bool,
+    /// Double-width/double-height attribute; renderers scale the row by it.
+    pub size: LineSize,
+    /// When output first landed on this row; `None` until something is printed.
+    pub written_at: Option<SystemTime>,
 }
 
 impl Default for Row {
@@ -233,13 +533,18 @@ impl Default for Row {
         Self {
             columns: VecDeque::new(),
             is_canonical: true,
+            size: LineSize::Normal,
+            written_at: None,
         }
     }
 }
 
+// Rows compare by content; when they were written doesn't matter
 impl PartialEq for Row {
     fn eq(&self, other: &Self) -> bool {
-        self.columns == other.columns && self.is_canonical == other.is_canonical
+        self.columns == other.columns
+            && self.is_canonical == other.is_canonical
+            && self.size == other.size
     }
 }
 
@@ -254,16 +559,18 @@ impl Row {
         Self {
             columns: VecDeque::with_capacity(capacity),
             is_canonical: true,
+            size: LineSize::Normal,
+            written_at: None,
         }
     }
 
     /// Create a new row filled with default characters.
     pub fn filled(width: usize) -> Self {
-        Self::filled_with_style(width, SharedStyles::Default)
+        Self::filled_with_style(width, StyleId::DEFAULT)
     }
 
     /// Create a new row filled with blank characters using the given style.
-    pub fn filled_with_style(width: usize, style: SharedStyles) -> Self {
+    pub fn filled_with_style(width: usize, style: StyleId) -> Self {
         let mut row = Self::with_capacity(width);
         let blank = TerminalCharacter::blank_with_style(style);
         for _ in 0..width {
@@ -320,7 +627,7 @@ impl Row {
                 self.columns.push_back(character);
                 if char_width == 2 {
                     self.columns
-                        .push_back(TerminalCharacter::wide_spacer(SharedStyles::Default));
+                        .push_back(TerminalCharacter::wide_spacer(StyleId::DEFAULT));
                 }
             }
             Ordering::Less => {
@@ -331,7 +638,7 @@ impl Row {
                 self.columns.push_back(character);
                 if char_width == 2 {
                     self.columns
-                        .push_back(TerminalCharacter::wide_spacer(SharedStyles::Default));
+                        .push_back(TerminalCharacter::wide_spacer(StyleId::DEFAULT));
                 }
             }
             Ordering::Greater => {
@@ -357,10 +664,10 @@ impl Row {
                         if x + 2 < self.columns.len() && self.columns[x + 2].wide_spacer {
                             self.columns[x + 2] = TerminalCharacter::default();
                         }
-                        self.columns[x + 1] = TerminalCharacter::wide_spacer(SharedStyles::Default);
+                        self.columns[x + 1] = TerminalCharacter::wide_spacer(StyleId::DEFAULT);
                     } else {
                         self.columns
-                            .push_back(TerminalCharacter::wide_spacer(SharedStyles::Default));
+                            .push_back(TerminalCharacter::wide_spacer(StyleId::DEFAULT));
                     }
                 }
             }
@@ -371,7 +678,7 @@ impl Row {
 
     /// Insert blank characters at the given position, shifting existing chars right.
     pub fn insert_blank(&mut self, x: usize, count: usize, max_width: usize) {
-        self.insert_blank_with_style(x, count, max_width, SharedStyles::Default);
+        self.insert_blank_with_style(x, count, max_width, StyleId::DEFAULT);
     }
 
     /// Insert blank characters at the given position, shifting existing chars right.
@@ -381,14 +688,15 @@ impl Row {
         x: usize,
         count: usize,
         max_width: usize,
-        style: SharedStyles,
+        style: StyleId,
     ) {
         let blank = TerminalCharacter::blank_with_style(style);
         for _ in 0..count {
             if self.columns.len() >= max_width {
                 self.columns.pop_back();
             }
-            if x < self.columns.len() {
+            // x == len happens at the last column, right after the pop
+            if x <= self.columns.len() {
                 self.columns.insert(x, blank.clone());
             }
         }
@@ -396,7 +704,7 @@ impl Row {
 
     /// Delete characters at the given position, shifting remaining chars left.
     pub fn delete_chars(&mut self, x: usize, count: usize, max_width: usize) {
-        self.delete_chars_with_style(x, count, max_width, SharedStyles::Default);
+        self.delete_chars_with_style(x, count, max_width, StyleId::DEFAULT);
     }
 
     /// Delete characters at the given position, shifting remaining chars left.
@@ -406,7 +714,7 @@ impl Row {
         x: usize,
         count: usize,
         max_width: usize,
-        style: SharedStyles,
+        style: StyleId,
     ) {
         for _ in 0..count {
             if x < self.columns.len() {
@@ -429,11 +737,11 @@ impl Row {
 
     /// Clear characters from the start to the given position (inclusive).
     pub fn clear_to(&mut self, x: usize) {
-        self.clear_to_with_style(x, SharedStyles::Default);
+        self.clear_to_with_style(x, StyleId::DEFAULT);
     }
 
     /// Clear characters from the start to the given position (inclusive), using given style.
-    pub fn clear_to_with_style(&mut self, x: usize, style: SharedStyles) {
+    pub fn clear_to_with_style(&mut self, x: usize, style: StyleId) {
         let blank = TerminalCharacter::blank_with_style(style);
         for i in 0..=x.min(self.columns.len().saturating_sub(1)) {
             self.columns[i] = blank.clone();
@@ -447,11 +755,11 @@ impl Row {
 
     /// Fill the row to a given width with default characters.
     pub fn fill_to_width(&mut self, width: usize) {
-        self.fill_to_width_with_style(width, SharedStyles::Default);
+        self.fill_to_width_with_style(width, StyleId::DEFAULT);
     }
 
     /// Fill the row to a given width with blank characters using the given style.
-    pub fn fill_to_width_with_style(&mut self, width: usize, style: SharedStyles) {
+    pub fn fill_to_width_with_style(&mut self, width: usize, style: StyleId) {
         let blank = TerminalCharacter::blank_with_style(style);
         while self.columns.len() < width {
             self.columns.push_back(blank.clone());
@@ -475,6 +783,7 @@ impl Row {
         let mut result = Vec::new();
         let mut current_row = Row::with_capacity(max_row_length);
         current_row.is_canonical = self.is_canonical;
+        current_row.size = self.size;
         let mut current_width = 0;
 
         for character in &self.columns {
@@ -486,6 +795,7 @@ impl Row {
                 result.push(current_row);
                 current_row = Row::with_capacity(max_row_length);
                 current_row.is_canonical = false; // Continuation row
+                current_row.size = self.size;
                 current_width = 0;
             }
 
@@ -507,28 +817,39 @@ impl Row {
 
     /// Convert row to a string (for debugging and URL detection).
     pub fn as_string(&self) -> String {
-        self.columns.iter().map(|c| c.character).collect()
+        let mut out = String::with_capacity(self.columns.len());
+        for c in &self.columns {
+            c.push_grapheme(&mut out);
+        }
+        out
     }
 
-    /// Convert row contents to a ratatui Line for rendering.
-    pub fn to_ratatui_line(&self) -> ratatui::text::Line<'static> {
-        self.to_ratatui_line_with_defaults(None, None)
+    /// Convert row contents to a ratatui Line for rendering. `styles` is the
+    /// style table of the grid holding the row.
+    pub fn to_ratatui_line(&self, styles: &StyleTable) -> ratatui::text::Line<'static> {
+        self.to_ratatui_line_with_defaults(styles, None, None)
     }
 
     /// Convert row contents to a ratatui Line, using default colors for cells without explicit colors.
     pub fn to_ratatui_line_with_defaults(
         &self,
+        styles: &StyleTable,
         default_fg: Option<Color>,
         default_bg: Option<Color>,
     ) -> ratatui::text::Line<'static> {
-        self.to_ratatui_line_with_palette(default_fg, default_bg, None)
+        self.to_ratatui_line_with_palette(styles, default_fg, default_bg, None)
     }
 
     /// Convert row contents to a ratatui Line, applying a custom color palette.
     /// The palette is an array of 256 optional RGB colors. When a Color::Indexed is encountered,
     /// if the palette entry is Some, the indexed color is converted to RGB.
+    ///
+    /// A cell grid can't scale glyphs, so double-width lines are spaced out
+    /// to span the row, and the bottom half of a double-height line shows
+    /// only its colors since the top half above already shows the text.
     pub fn to_ratatui_line_with_palette(
         &self,
+        styles: &StyleTable,
         default_fg: Option<Color>,
         default_bg: Option<Color>,
         palette: Option<&ColorPalette>,
@@ -549,7 +870,7 @@ impl Row {
                 continue;
             }
 
-            let mut char_style = character.styles.to_ratatui_style();
+            let mut char_style = styles.to_ratatui_style(character.styles);
 
             // Apply palette to indexed colors
             char_style.fg = apply_palette(char_style.fg);
@@ -563,9 +884,7 @@ impl Row {
                 char_style.bg = default_bg;
             }
 
-            if char_style == current_style {
-                current_text.push(character.character);
-            } else {
+            if char_style != current_style {
                 if !current_text.is_empty() {
                     spans.push(ratatui::text::Span::styled(
                         std::mem::take(&mut current_text),
@@ -573,7 +892,15 @@ impl Row {
                     ));
                 }
                 current_style = char_style;
-                current_text.push(character.character);
+            }
+            let blank = if character.is_wide() { "  " } else { " " };
+            if self.size == LineSize::DoubleHeightBottom {
+                current_text.push_str(blank);
+            } else {
+                character.push_grapheme(&mut current_text);
+            }
+            if self.size.is_double_width() {
+                current_text.push_str(blank);
             }
         }
 
@@ -589,6 +916,35 @@ impl Row {
 mod tests {
     use super::*;
 
+    #[test]
+    fn style_table_shares_and_reuses_slots() {
+        let mut table = StyleTable::new();
+        let red = CharacterStyles::default().fg(Color::Red);
+        let a = table.intern(red);
+        assert_eq!(table.intern(red), a);
+        assert_eq!(*table.get(a), red);
+        assert!(table.intern(CharacterStyles::default()).is_default());
+
+        let blue = table.intern(CharacterStyles::default().fg(Color::Blue));
+        let marks = table.mark([blue]);
+        let stats = table.stats(&marks);
+        assert_eq!(stats.unique_styles, 2);
+        assert_eq!(stats.unreferenced_styles, 1);
+        assert!(stats.memory_bytes > 0);
+
+        assert_eq!(table.compact(&marks), 1);
+        assert_eq!(table.stats(&marks).unique_styles, 1);
+        // The freed slot is handed out again
+        let green = table.intern(CharacterStyles::default().fg(Color::Green));
+        assert_eq!(green, a);
+        assert_eq!(table.get(green).foreground, Some(Color::Green));
+
+        assert_eq!(table.compact(&table.mark([])), 2);
+        assert_eq!(table.stats(&[]).compactions, 2);
+        // Unknown handles fall back to the default style
+        assert_eq!(*table.get(blue), CharacterStyles::default());
+    }
+
     #[test]
     fn test_terminal_character_default() {
         let c = TerminalCharacter::default();
@@ -601,26 +957,51 @@ mod tests {
     #[test]
     fn test_terminal_character_width() {
         // ASCII character
-        let c = TerminalCharacter::new('A', SharedStyles::Default);
+        let c = TerminalCharacter::new('A', StyleId::DEFAULT);
         assert_eq!(c.width(), 1);
 
         // Wide character (CJK)
-        let c = TerminalCharacter::new('中', SharedStyles::Default);
+        let c = TerminalCharacter::new('中', StyleId::DEFAULT);
         assert_eq!(c.width(), 2);
     }
 
+    #[test]
+    fn graphemes_keep_cells_small_and_measure_clusters() {
+        assert_eq!(std::mem::size_of::<TerminalCharacter>(), 12);
+
+        let mut c = TerminalCharacter::new('e', StyleId::DEFAULT);
+        c.set_grapheme("e\u{301}", 1);
+        assert_eq!(c.character, 'e');
+        assert_eq!(c.combining().as_deref(), Some("\u{301}"));
+        assert_eq!(c.grapheme(), "e\u{301}");
+        assert_ne!(c, TerminalCharacter::new('e', StyleId::DEFAULT));
+
+        let narrow = AmbiguousWidth::Narrow;
+        assert_eq!(narrow.grapheme_width("e\u{301}"), 1);
+        assert_eq!(
+            narrow.grapheme_width("\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}"),
+            2
+        );
+        assert_eq!(narrow.grapheme_width("\u{2764}\u{FE0F}"), 2);
+        assert_eq!(narrow.grapheme_width("\u{1F1FA}\u{1F1F8}"), 2);
+        assert_eq!(narrow.char_width('\u{301}'), 0);
+        assert_eq!(narrow.char_width('\u{2500}'), 1);
+        assert_eq!(AmbiguousWidth::Wide.char_width('\u{2500}'), 2);
+        assert_eq!(AmbiguousWidth::Wide.char_width('A'), 1);
+    }
+
     #[test]
     fn test_row_add_character() {
         let mut row = Row::new();
 
         // Add single-width character
-        let next = row.add_character_at(TerminalCharacter::new('A', SharedStyles::Default), 0);
+        let next = row.add_character_at(TerminalCharacter::new('A', StyleId::DEFAULT), 0);
         assert_eq!(next, 1);
         assert_eq!(row.len(), 1);
         assert_eq!(row.columns[0].character, 'A');
 
         // Add wide character
-        let next = row.add_character_at(TerminalCharacter::new('中', SharedStyles::Default), 1);
+        let next = row.add_character_at(TerminalCharacter::new('中', StyleId::DEFAULT), 1);
         assert_eq!(next, 3);
         assert_eq!(row.len(), 3);
         assert_eq!(row.columns[1].character, '中');
@@ -632,7 +1013,7 @@ mod tests {
         let mut row = Row::with_capacity(10);
         for c in "Hello World".chars() {
             row.columns
-                .push_back(TerminalCharacter::new(c, SharedStyles::Default));
+                .push_back(TerminalCharacter::new(c, StyleId::DEFAULT));
         }
         row.is_canonical = true;
 
@@ -644,14 +1025,24 @@ mod tests {
     }
 
     #[test]
-    fn test_shared_styles() {
-        let default = SharedStyles::Default;
-        assert!(default.is_default());
+    fn test_style_ids() {
+        assert!(StyleId::DEFAULT.is_default());
+        assert!(TerminalCharacter::default().styles.is_default());
 
-        let custom = SharedStyles::new(CharacterStyles::default().fg(Color::Red));
+        let mut table = StyleTable::new();
+        let custom = table.intern(CharacterStyles::default().fg(Color::Red));
         assert!(!custom.is_default());
 
-        let style = custom.to_ratatui_style();
+        let style = table.to_ratatui_style(custom);
         assert_eq!(style.fg, Some(Color::Red));
     }
+
+    #[test]
+    fn test_row_insert_blank_at_last_column_keeps_width() {
+        let mut row = Row::filled_with_style(4, StyleId::DEFAULT);
+        row.columns[3] = TerminalCharacter::new('x', StyleId::DEFAULT);
+        row.insert_blank_with_style(3, 1, 4, StyleId::DEFAULT);
+        assert_eq!(row.columns.len(), 4);
+        assert_eq!(row.columns[3].character, ' ');
+    }
 }
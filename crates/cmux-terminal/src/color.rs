@@ -0,0 +1,295 @@
+//! Color reduction for consumers that can't show 24-bit color.
+//!
+//! Applications freely mix SGR 38;2 truecolor with indexed colors, and the
+//! grid keeps them as sent. A consumer drawing into a terminal without
+//! truecolor support, such as the ratatui preview over an old SSH client,
+//! gets wrong or missing colors from `Color::Rgb`. A [`ColorReducer`]
+//! rewrites colors for a target [`ColorDepth`], picking the nearest entry of
+//! the terminal's [`ColorPalette`] so OSC 4 overrides are honored.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use serde::{Deserialize, Serialize};
+
+use crate::character::{CharacterStyles, ColorPalette, StyleId};
+use crate::grid::Grid;
+use crate::terminal::default_palette_color;
+
+/// How many colors a consumer can display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ColorDepth {
+    /// 24-bit RGB; colors pass through unchanged
+    #[default]
+    TrueColor,
+    /// The xterm 256-color palette (SGR 38;5)
+    Ansi256,
+    /// The 16 ANSI colors (SGR 30-37 and 90-97)
+    Ansi16,
+}
+
+impl ColorDepth {
+    /// Depth advertised by `COLORTERM` and `TERM`.
+    pub fn from_env() -> Self {
+        Self::detect(
+            std::env::var("COLORTERM").ok().as_deref(),
+            std::env::var("TERM").ok().as_deref(),
+        )
+    }
+
+    /// Depth advertised by the given `COLORTERM` and `TERM` values.
+    pub fn detect(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        let term = term.unwrap_or_default();
+        if matches!(colorterm, Some("truecolor" | "24bit")) || term.ends_with("-direct") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
+/// Lookups on a [`ColorPalette`], whose unset entries are the xterm defaults.
+pub trait ColorPaletteExt {
+    /// Color of palette entry `index`.
+    fn rgb(&self, index: u8) -> (u8, u8, u8);
+
+    /// The entry in `candidates` closest to `rgb` as the eye sees it.
+    fn nearest(&self, rgb: (u8, u8, u8), candidates: RangeInclusive<u8>) -> u8;
+}
+
+impl ColorPaletteExt for ColorPalette {
+    fn rgb(&self, index: u8) -> (u8, u8, u8) {
+        self[index as usize].unwrap_or_else(|| default_palette_color(index))
+    }
+
+    fn nearest(&self, rgb: (u8, u8, u8), candidates: RangeInclusive<u8>) -> u8 {
+        let start = *candidates.start();
+        candidates
+            .min_by_key(|&index| distance(rgb, self.rgb(index)))
+            .unwrap_or(start)
+    }
+}
+
+/// Squared "redmean" distance, a cheap approximation of perceived
+/// difference that weighs green most and red or blue by how red the pair is.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let red_mean = (a.0 as i32 + b.0 as i32) / 2;
+    let (dr, dg, db) = (
+        a.0 as i32 - b.0 as i32,
+        a.1 as i32 - b.1 as i32,
+        a.2 as i32 - b.2 as i32,
+    );
+    ((((512 + red_mean) * dr * dr) >> 8) + 4 * dg * dg + (((767 - red_mean) * db * db) >> 8)) as u32
+}
+
+/// The named color a 16-color terminal draws for palette entry `index`.
+fn ansi16(index: u8) -> Color {
+    match index {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        7 => Color::Gray,
+        8 => Color::DarkGray,
+        9 => Color::LightRed,
+        10 => Color::LightGreen,
+        11 => Color::LightYellow,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+/// Rewrites colors so a consumer limited to some [`ColorDepth`] draws the
+/// closest colors it can.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorReducer {
+    depth: ColorDepth,
+    palette: ColorPalette,
+}
+
+impl ColorReducer {
+    /// Reduce to `depth`, matching against `palette`, e.g.
+    /// [`VirtualTerminal::color_palette`](crate::VirtualTerminal::color_palette).
+    pub fn new(depth: ColorDepth, palette: &ColorPalette) -> Self {
+        Self {
+            depth,
+            palette: *palette,
+        }
+    }
+
+    pub fn depth(&self) -> ColorDepth {
+        self.depth
+    }
+
+    /// `color` as the target can show it. Named colors are within every
+    /// depth and pass through. RGB maps into the 6x6x6 cube and gray ramp of
+    /// the 256-color palette rather than its first 16 entries, which themes
+    /// redefine freely.
+    pub fn color(&self, color: Color) -> Color {
+        match (self.depth, color) {
+            (ColorDepth::Ansi256, Color::Rgb(r, g, b)) => {
+                Color::Indexed(self.palette.nearest((r, g, b), 16..=255))
+            }
+            (ColorDepth::Ansi16, Color::Rgb(r, g, b)) => {
+                ansi16(self.palette.nearest((r, g, b), 0..=15))
+            }
+            (ColorDepth::Ansi16, Color::Indexed(index)) if index < 16 => ansi16(index),
+            (ColorDepth::Ansi16, Color::Indexed(index)) => {
+                ansi16(self.palette.nearest(self.palette.rgb(index), 0..=15))
+            }
+            (_, color) => color,
+        }
+    }
+
+    pub fn styles(&self, styles: CharacterStyles) -> CharacterStyles {
+        CharacterStyles {
+            foreground: styles.foreground.map(|c| self.color(c)),
+            background: styles.background.map(|c| self.color(c)),
+            ..styles
+        }
+    }
+
+    pub fn style(&self, style: Style) -> Style {
+        Style {
+            fg: style.fg.map(|c| self.color(c)),
+            bg: style.bg.map(|c| self.color(c)),
+            ..style
+        }
+    }
+
+    /// Reduce the colors of a rendered line, such as one from
+    /// [`Row::to_ratatui_line`](crate::Row::to_ratatui_line).
+    pub fn line<'a>(&self, mut line: Line<'a>) -> Line<'a> {
+        line.style = self.style(line.style);
+        for span in &mut line.spans {
+            span.style = self.style(span.style);
+        }
+        line
+    }
+
+    /// Reduce the colors of every cell in `grid`, scrollback included.
+    pub fn grid(&self, grid: &mut Grid) {
+        if self.depth == ColorDepth::TrueColor {
+            return;
+        }
+        // Grids hold few distinct styles, so each is reduced once
+        let mut reduced: HashMap<StyleId, StyleId> = HashMap::new();
+        let Grid {
+            lines_above,
+            viewport,
+            lines_below,
+            style_table,
+            ..
+        } = grid;
+        let rows = lines_above
+            .iter_mut()
+            .chain(viewport.iter_mut())
+            .chain(lines_below.iter_mut());
+        for row in rows {
+            for cell in &mut row.columns {
+                if cell.styles.is_default() {
+                    continue;
+                }
+                cell.styles = *reduced.entry(cell.styles).or_insert_with(|| {
+                    let styles = *style_table.get(cell.styles);
+                    style_table.intern(self.styles(styles))
+                });
+            }
+        }
+        grid.set_current_styles(self.styles(grid.current_styles));
+        grid.mark_all_changed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VirtualTerminal;
+
+    #[test]
+    fn truecolor_is_matched_to_the_nearest_palette_entry() {
+        let palette: ColorPalette = [None; 256];
+        let ansi256 = ColorReducer::new(ColorDepth::Ansi256, &palette);
+        // Exact cube and gray ramp entries
+        assert_eq!(ansi256.color(Color::Rgb(255, 0, 0)), Color::Indexed(196));
+        assert_eq!(ansi256.color(Color::Rgb(95, 135, 175)), Color::Indexed(67));
+        assert_eq!(
+            ansi256.color(Color::Rgb(128, 128, 128)),
+            Color::Indexed(244)
+        );
+        assert_eq!(ansi256.color(Color::Rgb(250, 10, 5)), Color::Indexed(196));
+        assert_eq!(ansi256.color(Color::Indexed(3)), Color::Indexed(3));
+        assert_eq!(ansi256.color(Color::Green), Color::Green);
+
+        let ansi16 = ColorReducer::new(ColorDepth::Ansi16, &palette);
+        assert_eq!(ansi16.color(Color::Rgb(250, 10, 5)), Color::LightRed);
+        assert_eq!(ansi16.color(Color::Rgb(20, 20, 20)), Color::Black);
+        assert_eq!(ansi16.color(Color::Indexed(4)), Color::Blue);
+        assert_eq!(ansi16.color(Color::Indexed(214)), Color::Yellow);
+        assert_eq!(ansi16.color(Color::Reset), Color::Reset);
+
+        // OSC 4 overrides move the match
+        let mut themed = palette;
+        themed[4] = Some((40, 40, 60));
+        let ansi16 = ColorReducer::new(ColorDepth::Ansi16, &themed);
+        assert_eq!(ansi16.color(Color::Rgb(35, 35, 70)), Color::Blue);
+
+        let truecolor = ColorReducer::new(ColorDepth::TrueColor, &palette);
+        assert_eq!(truecolor.color(Color::Rgb(1, 2, 3)), Color::Rgb(1, 2, 3));
+    }
+
+    #[test]
+    fn grids_and_lines_are_reduced() {
+        let mut term = VirtualTerminal::new(2, 10);
+        term.process(b"\x1b[38;2;0;0;238;48;5;46mab\x1b[0m c\r\n\x1b[38;2;255;255;255md");
+        let reducer = ColorReducer::new(ColorDepth::Ansi16, term.color_palette());
+
+        let grid = term.grid();
+        let line = reducer.line(grid.viewport[0].to_ratatui_line(grid.style_table()));
+        assert_eq!(line.spans[0].style.fg, Some(Color::Blue));
+        assert_eq!(line.spans[0].style.bg, Some(Color::LightGreen));
+
+        let mut grid = term.grid().clone();
+        reducer.grid(&mut grid);
+        let cell = grid
+            .style_table
+            .get(grid.viewport[0].get(1).unwrap().styles);
+        assert_eq!(cell.foreground, Some(Color::Blue));
+        assert_eq!(cell.background, Some(Color::LightGreen));
+        assert!(grid.viewport[0].get(3).unwrap().styles.is_default());
+        assert_eq!(grid.current_styles.foreground, Some(Color::White));
+        // Text written afterwards gets the reduced style too
+        grid.put_char('e');
+        let cell = grid
+            .style_table
+            .get(grid.viewport[1].get(1).unwrap().styles);
+        assert_eq!(cell.foreground, Some(Color::White));
+    }
+
+    #[test]
+    fn depth_is_detected_from_the_environment() {
+        assert_eq!(
+            ColorDepth::detect(Some("truecolor"), Some("xterm-256color")),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(
+            ColorDepth::detect(None, Some("xterm-direct")),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(
+            ColorDepth::detect(None, Some("screen-256color")),
+            ColorDepth::Ansi256
+        );
+        assert_eq!(ColorDepth::detect(None, Some("vt100")), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::detect(None, None), ColorDepth::Ansi16);
+    }
+}
@@ -0,0 +1,212 @@
+//! Command blocks assembled from OSC 133 shell-integration marks.
+//!
+//! A shell with integration enabled brackets each prompt, its input and the
+//! command's output with `A`, `B`, `C` and `D` marks. Each grid folds them
+//! into one [`CommandBlock`] per command so the UI can jump between prompts
+//! and collapse output. Lines count from the oldest scrollback line and
+//! follow it as scrollback is trimmed or rewrapped, like inline images.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::terminal::SemanticMarkKind;
+
+/// Blocks kept per grid before the oldest are dropped.
+const MAX_COMMAND_BLOCKS: usize = 1024;
+
+/// One prompt and the command run from it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandBlock {
+    /// Line the prompt starts on (`A`)
+    pub prompt_line: usize,
+    /// Line the user's input starts on (`B`), if the shell marked it
+    pub input_line: Option<usize>,
+    /// First line of output (`C`); unset until the command is submitted
+    pub output_start: Option<usize>,
+    /// Line after the last line of output (`D`); unset while running
+    pub output_end: Option<usize>,
+    /// Exit status, when the shell reported one
+    pub exit_code: Option<i32>,
+}
+
+impl CommandBlock {
+    fn new(prompt_line: usize) -> Self {
+        Self {
+            prompt_line,
+            input_line: None,
+            output_start: None,
+            output_end: None,
+            exit_code: None,
+        }
+    }
+
+    /// Lines the command printed, once it has finished.
+    pub fn output(&self) -> Option<Range<usize>> {
+        Some(self.output_start?..self.output_end?)
+    }
+
+    /// Submitted and not finished yet.
+    pub fn is_running(&self) -> bool {
+        self.output_start.is_some() && self.output_end.is_none()
+    }
+
+    fn lines_mut(&mut self) -> impl Iterator<Item = &mut usize> {
+        std::iter::once(&mut self.prompt_line).chain(
+            [
+                &mut self.input_line,
+                &mut self.output_start,
+                &mut self.output_end,
+            ]
+            .into_iter()
+            .flatten(),
+        )
+    }
+}
+
+/// The command blocks of one grid, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CommandLog {
+    blocks: Vec<CommandBlock>,
+}
+
+impl CommandLog {
+    pub(crate) fn blocks(&self) -> &[CommandBlock] {
+        &self.blocks
+    }
+
+    /// Apply a mark received with the cursor on `line`. `at_line_start` says
+    /// whether the cursor was in the first column, i.e. whether output
+    /// ending with `D` includes `line`.
+    pub(crate) fn record(&mut self, kind: SemanticMarkKind, line: usize, at_line_start: bool) {
+        let last = self.blocks.last_mut();
+        match kind {
+            SemanticMarkKind::PromptStart => {
+                match last {
+                    // Nothing ran from the last prompt (empty input, ^C or
+                    // a redraw), so it isn't a command
+                    Some(last) if last.output_start.is_none() => {
+                        self.blocks.pop();
+                    }
+                    // The shell skipped `D`; the output ends here
+                    Some(last) if last.output_end.is_none() => {
+                        last.output_end = Some(line);
+                    }
+                    _ => {}
+                }
+                if self.blocks.len() >= MAX_COMMAND_BLOCKS {
+                    self.blocks.remove(0);
+                }
+                self.blocks.push(CommandBlock::new(line));
+            }
+            SemanticMarkKind::InputStart => {
+                if let Some(last) = last.filter(|last| last.output_start.is_none()) {
+                    last.input_line = Some(line);
+                }
+            }
+            SemanticMarkKind::OutputStart => {
+                if let Some(last) = last.filter(|last| last.output_start.is_none()) {
+                    last.output_start = Some(line);
+                }
+            }
+            SemanticMarkKind::CommandFinished { exit_code } => {
+                if let Some(last) = last.filter(|last| last.is_running()) {
+                    let end = if at_line_start { line } else { line + 1 };
+                    last.output_end = Some(end.max(last.output_start.unwrap_or(end)));
+                    last.exit_code = exit_code;
+                }
+            }
+        }
+    }
+
+    /// Follow the removal of the `count` oldest scrollback rows; blocks
+    /// whose prompt went with them are dropped.
+    pub(crate) fn drop_oldest_lines(&mut self, count: usize) {
+        self.remap(|line| line.checked_sub(count));
+    }
+
+    /// Forget blocks starting at or after `line` once those lines were
+    /// erased, and cut earlier output off there.
+    pub(crate) fn clear_from(&mut self, line: usize) {
+        self.blocks.retain(|block| block.prompt_line < line);
+        for block in &mut self.blocks {
+            for l in block.lines_mut() {
+                *l = (*l).min(line);
+            }
+        }
+    }
+
+    /// Move every line through `map`, dropping blocks whose prompt maps to
+    /// `None`. `map` must keep lines in order.
+    pub(crate) fn remap(&mut self, map: impl Fn(usize) -> Option<usize>) {
+        self.blocks.retain_mut(|block| {
+            if map(block.prompt_line).is_none() {
+                return false;
+            }
+            let prompt = block.prompt_line;
+            for line in block.lines_mut() {
+                *line = map(*line).unwrap_or(prompt);
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(marks: &[(SemanticMarkKind, usize, bool)]) -> CommandLog {
+        let mut log = CommandLog::default();
+        for &(kind, line, at_line_start) in marks {
+            log.record(kind, line, at_line_start);
+        }
+        log
+    }
+
+    #[test]
+    fn prompts_without_a_command_are_dropped() {
+        use SemanticMarkKind::*;
+        let log = log(&[
+            (PromptStart, 0, true),
+            (InputStart, 0, false),
+            (CommandFinished { exit_code: None }, 1, true),
+            (PromptStart, 1, true),
+            (InputStart, 1, false),
+            (OutputStart, 2, true),
+            (PromptStart, 5, true),
+        ]);
+        let blocks = log.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].prompt_line, 1);
+        assert_eq!(blocks[0].output(), Some(2..5));
+        assert_eq!(blocks[0].exit_code, None);
+        assert!(!blocks[1].is_running());
+        assert_eq!(blocks[1].output(), None);
+    }
+
+    #[test]
+    fn lines_follow_trimmed_and_cleared_rows() {
+        use SemanticMarkKind::*;
+        let mut log = log(&[
+            (PromptStart, 0, true),
+            (OutputStart, 1, true),
+            (CommandFinished { exit_code: Some(0) }, 3, false),
+            (PromptStart, 4, true),
+            (OutputStart, 5, true),
+            (CommandFinished { exit_code: Some(1) }, 9, true),
+            (PromptStart, 9, true),
+        ]);
+        assert_eq!(log.blocks()[0].output(), Some(1..4));
+
+        log.drop_oldest_lines(2);
+        assert_eq!(log.blocks().len(), 2);
+        assert_eq!(log.blocks()[0].prompt_line, 2);
+        assert_eq!(log.blocks()[0].output(), Some(3..7));
+
+        log.clear_from(5);
+        assert_eq!(log.blocks().len(), 1);
+        assert_eq!(log.blocks()[0].output(), Some(3..5));
+        assert_eq!(log.blocks()[0].exit_code, Some(1));
+    }
+}
@@ -0,0 +1,458 @@
+//! Cell-level deltas between two states of a grid, for remote rendering.
+//!
+//! Sending every viewer the raw PTY stream makes each one run its own
+//! emulator and costs the full output, escape sequences and all, per viewer.
+//! A [`GridDelta`] instead describes how the visible screen changed between
+//! two states of a [`Grid`]: a scroll of the scroll region, runs of changed
+//! cells sharing a style, and the cursor. It is computed once and sent to
+//! every viewer at the older state; a viewer joining late starts from
+//! [`GridDelta::full`].
+
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::character::{CharacterStyles, LineSize, Row, StyleId, StyleTable, TerminalCharacter};
+use crate::grid::Grid;
+use crate::snapshot::StyleEncoder;
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// How the viewport changed from one state of a grid to another. Applied in
+/// field order: reset, scroll, line sizes, runs, cursor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridDelta {
+    pub rows: usize,
+    pub cols: usize,
+    /// Start from a blank screen of `rows` by `cols`: the first delta a
+    /// viewer gets, or the first after a resize
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub reset: bool,
+    /// Styles of the runs; index 0 is always the default style
+    pub styles: Vec<CharacterStyles>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll: Option<ScrollDelta>,
+    /// Rows whose DEC line size changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub line_sizes: Vec<(usize, LineSize)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<CellRun>,
+    /// New cursor position as `(row, col)`, when it moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<(usize, usize)>,
+}
+
+/// Rows `top..=bottom` moved up by `lines`, or down when negative, with
+/// blank rows taking the place of those shifted out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrollDelta {
+    pub top: usize,
+    pub bottom: usize,
+    pub lines: isize,
+}
+
+/// Consecutive changed cells on one row with the same style.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CellRun {
+    pub row: usize,
+    pub col: usize,
+    /// One grapheme cluster per cell
+    pub text: String,
+    /// Index into [`GridDelta::styles`]
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub style: u32,
+    /// Every cluster is two cells wide
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub wide: bool,
+}
+
+impl GridDelta {
+    /// Everything needed to draw `grid` on a blank screen.
+    pub fn full(grid: &Grid) -> Self {
+        let blank = Grid::new(grid.rows, grid.cols);
+        let mut delta = Self::between(&blank, grid);
+        delta.reset = true;
+        delta.cursor = Some((grid.cursor_row, grid.cursor_col));
+        delta
+    }
+
+    /// The changes that turn `old`'s viewport into `new`'s. A size change
+    /// resets the screen and sends every non-blank cell.
+    pub fn between(old: &Grid, new: &Grid) -> Self {
+        if (old.rows, old.cols) != (new.rows, new.cols) {
+            return Self::full(new);
+        }
+        let mut before: Vec<Row> = old.viewport.clone();
+        let scroll = detect_scroll(&before, &old.style_table, new);
+        if let Some(scroll) = scroll {
+            scroll_rows(&mut before, scroll, new.cols);
+        }
+
+        let mut styles = StyleEncoder::new();
+        let mut line_sizes = Vec::new();
+        let mut runs = Vec::new();
+        for (row, (before, after)) in before.iter().zip(&new.viewport).enumerate() {
+            if before.size != after.size {
+                line_sizes.push((row, after.size));
+            }
+            diff_row(
+                row,
+                (before, &old.style_table),
+                (after, &new.style_table),
+                new.cols,
+                &mut styles,
+                &mut runs,
+            );
+        }
+        let cursor = (new.cursor_row, new.cursor_col);
+        Self {
+            rows: new.rows,
+            cols: new.cols,
+            reset: false,
+            styles: styles.finish(),
+            scroll,
+            line_sizes,
+            runs,
+            cursor: (cursor != (old.cursor_row, old.cursor_col)).then_some(cursor),
+        }
+    }
+
+    /// Nothing changed.
+    pub fn is_empty(&self) -> bool {
+        !self.reset
+            && self.scroll.is_none()
+            && self.line_sizes.is_empty()
+            && self.runs.is_empty()
+            && self.cursor.is_none()
+    }
+
+    /// Apply to the viewport of `grid`, the viewer's copy of the screen.
+    pub fn apply(&self, grid: &mut Grid) {
+        if self.reset || (grid.rows, grid.cols) != (self.rows, self.cols) {
+            *grid = Grid::new(self.rows.max(1), self.cols.max(1));
+        }
+        let cols = grid.cols;
+        if let Some(scroll) = self.scroll {
+            scroll_rows(&mut grid.viewport, scroll, cols);
+        }
+        for &(row, size) in &self.line_sizes {
+            if let Some(row) = grid.viewport.get_mut(row) {
+                row.size = size;
+            }
+        }
+        let shared: Vec<StyleId> = self
+            .styles
+            .iter()
+            .map(|styles| grid.style_table.intern(*styles))
+            .collect();
+        for run in &self.runs {
+            let Some(row) = grid.viewport.get_mut(run.row) else {
+                continue;
+            };
+            let styles = shared.get(run.style as usize).copied().unwrap_or_default();
+            let width = if run.wide { 2 } else { 1 };
+            let mut col = run.col;
+            for grapheme in run.text.graphemes(true) {
+                if col + width > cols {
+                    break;
+                }
+                let mut cell = TerminalCharacter::with_width(' ', styles, width as u8);
+                cell.set_grapheme(grapheme, width as u8);
+                row.set(col, cell);
+                if run.wide {
+                    row.set(col + 1, TerminalCharacter::wide_spacer(styles));
+                }
+                col += width;
+            }
+        }
+        if let Some((row, col)) = self.cursor {
+            grid.cursor_row = row.min(grid.rows - 1);
+            grid.cursor_col = col.min(cols - 1);
+        }
+        grid.mark_all_changed();
+    }
+}
+
+/// A row with the style table its cells' handles refer to.
+type StyledRow<'a> = (&'a Row, &'a StyleTable);
+
+/// Rows compare by what a viewer draws, so a row that only rewrapped or was
+/// rewritten unchanged still matches.
+fn same_row((a, a_styles): StyledRow, (b, b_styles): StyledRow) -> bool {
+    a.size == b.size
+        && a.columns.len() == b.columns.len()
+        && a.columns
+            .iter()
+            .zip(&b.columns)
+            .all(|(x, y)| x.draws_like(a_styles, y, b_styles))
+}
+
+/// The scroll of `new`'s scroll region that lines up the most of `old`'s
+/// rows with `new`'s, if it lines up more than leaving them in place.
+fn detect_scroll(old: &[Row], old_styles: &StyleTable, new: &Grid) -> Option<ScrollDelta> {
+    let (top, bottom) = new.scroll_region;
+    if bottom >= old.len() || bottom >= new.viewport.len() || top >= bottom {
+        return None;
+    }
+    let height = bottom - top + 1;
+    let matches = |lines: isize| {
+        (top..=bottom)
+            .filter(|&row| {
+                let from = row as isize + lines;
+                (top as isize..=bottom as isize).contains(&from)
+                    && same_row(
+                        (&old[from as usize], old_styles),
+                        (&new.viewport[row], &new.style_table),
+                    )
+            })
+            .count()
+    };
+    let mut best = (matches(0), 0);
+    for shift in 1..height as isize {
+        for lines in [shift, -shift] {
+            let found = matches(lines);
+            if found > best.0 {
+                best = (found, lines);
+            }
+        }
+        // No larger shift can line up more rows than it keeps
+        if height - shift as usize <= best.0 {
+            break;
+        }
+    }
+    (best.1 != 0).then_some(ScrollDelta {
+        top,
+        bottom,
+        lines: best.1,
+    })
+}
+
+fn scroll_rows(rows: &mut [Row], scroll: ScrollDelta, cols: usize) {
+    let Some(region) = rows.get_mut(scroll.top..=scroll.bottom) else {
+        return;
+    };
+    let count = scroll.lines.unsigned_abs().min(region.len());
+    if scroll.lines > 0 {
+        region.rotate_left(count);
+        let len = region.len();
+        region[len - count..].fill_with(|| Row::filled(cols));
+    } else {
+        region.rotate_right(count);
+        region[..count].fill_with(|| Row::filled(cols));
+    }
+}
+
+/// Longest stretch of unchanged cells a run spans to reach the next change
+/// in the same style.
+const MAX_GAP: usize = 3;
+
+/// Append runs for the cells of `after` that differ from `before`.
+fn diff_row(
+    row: usize,
+    (before, before_styles): StyledRow,
+    (after, after_styles): StyledRow,
+    cols: usize,
+    styles: &mut StyleEncoder,
+    runs: &mut Vec<CellRun>,
+) {
+    let blank = TerminalCharacter::default();
+    let cell = |row: &Row, col: usize| row.get(col).cloned().unwrap_or_else(|| blank.clone());
+    let mut changed: Vec<bool> = (0..cols)
+        .map(|col| !cell(before, col).draws_like(before_styles, &cell(after, col), after_styles))
+        .collect();
+    // Resending a few unchanged cells is cheaper than starting another run
+    let mut last_changed = None;
+    for col in 0..cols {
+        if !changed[col] {
+            continue;
+        }
+        if let Some(last) = last_changed {
+            let styles = cell(after, last).styles;
+            let bridges = col - last - 1 <= MAX_GAP
+                && (last + 1..=col).all(|col| {
+                    let cell = cell(after, col);
+                    cell.width() == 1 && cell.styles == styles
+                });
+            if bridges {
+                changed[last + 1..col].fill(true);
+            }
+        }
+        last_changed = Some(col);
+    }
+    // A changed half of a wide character redraws the whole character
+    for col in 1..cols {
+        if changed[col] && cell(after, col).wide_spacer {
+            changed[col - 1] = true;
+        }
+    }
+
+    let mut current: Option<CellRun> = None;
+    let mut col = 0;
+    while col < cols {
+        let this = cell(after, col);
+        let wide = this.is_wide() && col + 1 < cols;
+        let span = if wide { 2 } else { 1 };
+        if !changed[col] || this.wide_spacer {
+            runs.extend(current.take());
+            col += 1;
+            continue;
+        }
+        let style = styles.encode(after_styles.get(this.styles));
+        match &mut current {
+            Some(run) if run.style == style && run.wide == wide => {
+                this.push_grapheme(&mut run.text);
+            }
+            _ => {
+                runs.extend(current.take());
+                current = Some(CellRun {
+                    row,
+                    col,
+                    text: this.grapheme(),
+                    style,
+                    wide,
+                });
+            }
+        }
+        col += span;
+    }
+    runs.extend(current);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VirtualTerminal;
+    use ratatui::style::Color;
+
+    type Screen = Vec<(LineSize, Vec<(String, CharacterStyles, bool)>)>;
+
+    /// What a viewer would draw for each viewport row.
+    fn screen(grid: &Grid) -> Screen {
+        grid.viewport
+            .iter()
+            .map(|row| {
+                let cells = row
+                    .columns
+                    .iter()
+                    .map(|cell| {
+                        let styles = *grid.style_table.get(cell.styles);
+                        (cell.grapheme(), styles, cell.wide_spacer)
+                    })
+                    .collect();
+                (row.size, cells)
+            })
+            .collect()
+    }
+
+    /// Feed `chunks` to a terminal, keeping a viewer in sync through deltas
+    /// that round-trip through JSON.
+    fn replay(rows: usize, cols: usize, chunks: &[&[u8]]) -> Vec<GridDelta> {
+        let mut term = VirtualTerminal::new(rows, cols);
+        let mut viewer = Grid::new(1, 1);
+        GridDelta::full(term.grid()).apply(&mut viewer);
+        let mut deltas = Vec::new();
+        for chunk in chunks {
+            let old = term.grid().clone();
+            term.process(chunk);
+            let delta = GridDelta::between(&old, term.grid());
+            let json = serde_json::to_string(&delta).unwrap();
+            let delta: GridDelta = serde_json::from_str(&json).unwrap();
+            delta.apply(&mut viewer);
+            assert_eq!(screen(&viewer), screen(term.grid()), "after {chunk:?}");
+            assert_eq!(
+                (viewer.cursor_row, viewer.cursor_col),
+                (term.cursor_row(), term.cursor_col())
+            );
+            deltas.push(delta);
+        }
+        deltas
+    }
+
+    #[test]
+    fn viewers_follow_output_styles_and_wide_chars() {
+        let deltas = replay(
+            4,
+            12,
+            &[
+                b"$ ls\r\n",
+                "\x1b[1;32mbin\x1b[0m  日本\r\n".as_bytes(),
+                "e\u{301}x\x1b[2D\x1b[7mZ".as_bytes(),
+                b"\x1b[H\x1b#6wide\x1b[2;1H\x1b[K",
+                b"\x1b[8;20t\x1b[2J",
+            ],
+        );
+        assert_eq!(
+            deltas[1].runs,
+            [
+                CellRun {
+                    row: 1,
+                    col: 0,
+                    text: "bin".into(),
+                    style: 1,
+                    wide: false,
+                },
+                CellRun {
+                    row: 1,
+                    col: 5,
+                    text: "日本".into(),
+                    style: 0,
+                    wide: true,
+                },
+            ]
+        );
+        // Only the changed cells are resent, not the row
+        let texts: Vec<&str> = deltas[2].runs.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(texts, ["Z", "x"]);
+        assert_eq!(deltas[3].line_sizes, [(0, LineSize::DoubleWidth)]);
+        assert!(replay(2, 4, &[b"\x1b[H"])[0].is_empty());
+    }
+
+    #[test]
+    fn scrolling_output_sends_a_scroll_and_the_new_line() {
+        let mut lines = Vec::new();
+        for n in 0..8 {
+            lines.push(format!("line {n}\r\n").into_bytes());
+        }
+        // Reverse index at the top scrolls down
+        lines.push(b"\x1b[H\x1bMtop".to_vec());
+        let chunks: Vec<&[u8]> = lines.iter().map(Vec::as_slice).collect();
+        let deltas = replay(5, 10, &chunks);
+
+        let delta = &deltas[6];
+        assert_eq!(
+            delta.scroll,
+            Some(ScrollDelta {
+                top: 0,
+                bottom: 4,
+                lines: 1
+            })
+        );
+        assert_eq!(delta.runs.len(), 1);
+        assert_eq!(delta.runs[0].row, 3);
+        assert_eq!(delta.runs[0].text, "line 6");
+
+        let delta = &deltas[8];
+        assert_eq!(delta.scroll.map(|s| s.lines), Some(-1));
+        assert_eq!(delta.runs.len(), 1);
+    }
+
+    #[test]
+    fn cells_compare_by_style_not_by_handle() {
+        // The same handle names a different style in each grid
+        let mut old = Grid::new(1, 4);
+        old.set_current_styles(CharacterStyles::default().fg(Color::Red));
+        old.put_char('a');
+        let mut new = Grid::new(1, 4);
+        new.set_current_styles(CharacterStyles::default().fg(Color::Blue));
+        new.put_char('a');
+        assert_eq!(old.viewport[0].columns[0], new.viewport[0].columns[0]);
+
+        let delta = GridDelta::between(&old, &new);
+        assert_eq!(delta.runs.len(), 1);
+        assert_eq!(
+            delta.styles[delta.runs[0].style as usize].foreground,
+            Some(Color::Blue)
+        );
+    }
+}
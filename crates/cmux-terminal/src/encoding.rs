@@ -0,0 +1,74 @@
+//! Input encodings other than UTF-8.
+//!
+//! ANSI art and DOS-era programs write code page 437, where every byte from
+//! 0x80 up is a glyph (box drawing, shading, accented letters) rather than
+//! part of a UTF-8 sequence. Fed to the parser as is, those bytes come out as
+//! replacement characters. In [`InputEncoding::Cp437`] they are translated to
+//! the matching Unicode characters first. Controls and escape sequences are
+//! ASCII in both encodings, so colors and cursor movement work unchanged.
+
+use serde::{Deserialize, Serialize};
+
+/// How bytes written to the terminal are decoded into characters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputEncoding {
+    /// UTF-8, with 8-bit C1 controls handled by the [`C1Decoder`](crate::C1Decoder).
+    #[default]
+    Utf8,
+    /// IBM code page 437. It has no C1 controls; 0x80..=0x9F are letters.
+    Cp437,
+}
+
+/// Code page 437 glyphs for bytes 0x80..=0xFF.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The character a code page 437 byte stands for. ASCII maps to itself.
+pub fn cp437_char(byte: u8) -> char {
+    match byte {
+        0x00..=0x7f => byte as char,
+        _ => CP437_HIGH[(byte - 0x80) as usize],
+    }
+}
+
+/// Re-encode code page 437 input as UTF-8 for the parser.
+pub(crate) fn decode_cp437(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 2);
+    let mut buf = [0; 4];
+    for &byte in data {
+        if byte.is_ascii() {
+            out.push(byte);
+        } else {
+            out.extend_from_slice(cp437_char(byte).encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_bytes_become_their_glyphs() {
+        assert_eq!(cp437_char(b'A'), 'A');
+        assert_eq!(cp437_char(0x80), 'Ç');
+        assert_eq!(cp437_char(0x9b), '¢');
+        assert_eq!(cp437_char(0xc9), '╔');
+        assert_eq!(cp437_char(0xdb), '█');
+        assert_eq!(cp437_char(0xff), '\u{a0}');
+        // Escape sequences pass through untouched
+        assert_eq!(
+            decode_cp437(b"\x1b[31m\xc9\xcd\xbb"),
+            "\x1b[31m╔═╗".as_bytes()
+        );
+    }
+}
@@ -3,6 +3,9 @@
 //! This module provides filtering for DA1 and DA2 query/response sequences
 //! to prevent feedback loops when terminal applications query capabilities.
 
+/// Longest sequence the filter will buffer while deciding whether it is DA.
+const MAX_SEQUENCE_LEN: usize = 64;
+
 /// Stateful filter for DA (Device Attributes) queries.
 ///
 /// This filter removes DA1 and DA2 query/response sequences from terminal output
@@ -101,6 +104,7 @@ impl DaFilter {
                     } else if byte.is_ascii_digit() || byte == b';' {
                         // Continue accumulating params
                         self.buffer.push(byte);
+                        self.flush_if_too_long(&mut result);
                     } else {
                         // Not a DA1 response (e.g., ESC[?25h for cursor)
                         // Flush buffer INCLUDING the current byte
@@ -119,6 +123,7 @@ impl DaFilter {
                     } else if byte.is_ascii_digit() || byte == b';' {
                         // Continue accumulating params (DA2 response)
                         self.buffer.push(byte);
+                        self.flush_if_too_long(&mut result);
                     } else {
                         // Not a DA2 sequence, flush buffer INCLUDING the current byte
                         result.extend(&self.buffer);
@@ -136,6 +141,7 @@ impl DaFilter {
                     } else if byte.is_ascii_digit() || byte == b';' {
                         // Continue accumulating params
                         self.buffer.push(byte);
+                        self.flush_if_too_long(&mut result);
                     } else {
                         // Not a DA sequence, flush buffer INCLUDING the current byte
                         result.extend(&self.buffer);
@@ -150,6 +156,15 @@ impl DaFilter {
         result
     }
 
+    /// Give up on sequences longer than any real DA reply, so an endless run
+    /// of parameter bytes cannot grow the buffer without bound.
+    fn flush_if_too_long(&mut self, result: &mut Vec<u8>) {
+        if self.buffer.len() >= MAX_SEQUENCE_LEN {
+            result.append(&mut self.buffer);
+            self.state = DaFilterState::Normal;
+        }
+    }
+
     /// Flush any remaining buffered data.
     /// Call this when the stream ends to ensure no data is lost.
     pub fn flush(&mut self) -> Vec<u8> {
@@ -263,4 +278,67 @@ mod tests {
         let result = filter_da_queries(b"Before\x1b[cAfter");
         assert_eq!(result, b"BeforeAfter");
     }
+
+    #[test]
+    fn test_long_params_are_not_buffered() {
+        let mut filter = DaFilter::new();
+        let mut input = b"\x1b[?".to_vec();
+        input.extend(std::iter::repeat_n(b'1', MAX_SEQUENCE_LEN * 4));
+        let result = filter.filter(&input);
+        assert!(filter.buffer.len() <= MAX_SEQUENCE_LEN);
+        assert_eq!(result.len() + filter.flush().len(), input.len());
+    }
+
+    mod props {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Bytes biased towards the pieces DA sequences are made of.
+        fn escape_heavy_bytes() -> impl Strategy<Value = Vec<u8>> {
+            let fragment = prop_oneof![
+                any::<u8>().prop_map(|b| vec![b]),
+                Just(b"\x1b".to_vec()),
+                Just(b"\x1b[".to_vec()),
+                Just(b"?".to_vec()),
+                Just(b">".to_vec()),
+                Just(b"0".to_vec()),
+                Just(b";".to_vec()),
+                Just(b"c".to_vec()),
+                Just(b"\x1b[?64;1c".to_vec()),
+            ];
+            prop::collection::vec(fragment, 0..64).prop_map(|parts| parts.concat())
+        }
+
+        fn filter_chunked(data: &[u8], splits: &[usize]) -> Vec<u8> {
+            let mut filter = DaFilter::new();
+            let mut out = Vec::new();
+            let mut start = 0;
+            for &split in splits {
+                let end = (start + split).min(data.len());
+                out.extend(filter.filter(&data[start..end]));
+                assert!(filter.buffer.len() <= MAX_SEQUENCE_LEN);
+                start = end;
+            }
+            out.extend(filter.filter(&data[start..]));
+            out.extend(filter.flush());
+            out
+        }
+
+        proptest! {
+            #[test]
+            fn chunk_splits_do_not_change_output(
+                data in escape_heavy_bytes(),
+                splits in prop::collection::vec(0usize..16, 0..16),
+            ) {
+                prop_assert_eq!(filter_chunked(&data, &splits), filter_da_queries(&data));
+            }
+
+            #[test]
+            fn filtering_is_idempotent(data in escape_heavy_bytes()) {
+                let once = filter_da_queries(&data);
+                prop_assert_eq!(filter_da_queries(&once), once.clone());
+                prop_assert!(once.len() <= data.len());
+            }
+        }
+    }
 }
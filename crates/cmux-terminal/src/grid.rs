@@ -8,11 +8,30 @@
 //! This design enables efficient scrolling without reallocating large buffers.
 
 use std::collections::{HashSet, VecDeque};
-
-use crate::character::{CharacterStyles, Row, SharedStyles, TerminalCharacter};
+use std::time::SystemTime;
+
+use crate::character::{
+    CharacterStyles, LineSize, Row, StyleId, StyleStats, StyleTable, TerminalCharacter,
+};
+use crate::commands::CommandLog;
+use crate::image::InlineImage;
+use crate::links::{self, TerminalLink};
+use crate::selection::{self, Selection, SelectionMode, SelectionPoint};
+
+/// A rectangle of viewport cells, from its top-left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridRegion {
+    pub row: usize,
+    pub col: usize,
+    pub rows: usize,
+    pub cols: usize,
+}
 
 /// Maximum number of lines to keep in scrollback.
-const MAX_SCROLLBACK_LINES: usize = 10_000;
+pub(crate) const MAX_SCROLLBACK_LINES: usize = 10_000;
+
+/// Inline images kept per grid before the oldest are dropped.
+const MAX_INLINE_IMAGES: usize = 64;
 
 /// Terminal grid with tripartite design for efficient scrolling.
 #[derive(Clone, Debug)]
@@ -33,8 +52,11 @@ pub struct Grid {
     pub cursor_col: usize,
     /// Current style for new characters.
     pub current_styles: CharacterStyles,
-    /// Shared style instance for current_styles (cached).
-    current_shared_styles: SharedStyles,
+    /// Interned handle for current_styles, or `None` until a cell needs it,
+    /// so a run of SGR sequences between two prints is interned only once.
+    current_shared_styles: Option<StyleId>,
+    /// Styles of the grid's cells, which hold handles into it.
+    pub(crate) style_table: StyleTable,
     /// Scroll region (top, bottom) - 0-indexed, inclusive.
     pub scroll_region: (usize, usize),
     /// Left margin (0-indexed, inclusive) for DECSLRM.
@@ -45,6 +67,13 @@ pub struct Grid {
     pub changed_lines: HashSet<usize>,
     /// Flag to indicate full redraw is needed.
     pub needs_full_redraw: bool,
+    /// Images placed by the application, oldest first. Rows count from the
+    /// oldest scrollback line and follow it as scrollback is trimmed.
+    pub images: Vec<InlineImage>,
+    /// Commands marked by shell integration, numbered like `images`.
+    pub(crate) commands: CommandLog,
+    /// Selected text, numbered like `images`.
+    selection: Option<Selection>,
 }
 
 impl Grid {
@@ -61,12 +90,16 @@ impl Grid {
             cursor_row: 0,
             cursor_col: 0,
             current_styles: CharacterStyles::default(),
-            current_shared_styles: SharedStyles::Default,
+            current_shared_styles: Some(StyleId::DEFAULT),
+            style_table: StyleTable::new(),
             scroll_region: (0, rows.saturating_sub(1)),
             left_margin: 0,
             right_margin: cols.saturating_sub(1),
             changed_lines: HashSet::new(),
             needs_full_redraw: true,
+            images: Vec::new(),
+            commands: CommandLog::default(),
+            selection: None,
         }
     }
 
@@ -103,15 +136,54 @@ impl Grid {
         self.needs_full_redraw
     }
 
-    /// Update the current style and cache the shared version.
+    /// Update the current style. It is interned lazily by
+    /// [`Grid::current_shared_styles`].
     pub fn set_current_styles(&mut self, styles: CharacterStyles) {
         self.current_styles = styles;
-        self.current_shared_styles = SharedStyles::new(styles);
+        self.current_shared_styles = None;
+    }
+
+    /// Get the current shared styles, interning them on first use.
+    pub fn current_shared_styles(&mut self) -> StyleId {
+        if let Some(id) = self.current_shared_styles {
+            return id;
+        }
+        // Only handles in cells are live here, so it is safe to free the rest
+        if self.style_table.needs_compaction() {
+            self.compact_styles();
+        }
+        let id = self.style_table.intern(self.current_styles);
+        self.current_shared_styles = Some(id);
+        id
+    }
+
+    /// The style table the grid's cells refer to, for resolving their
+    /// [`StyleId`]s.
+    pub fn style_table(&self) -> &StyleTable {
+        &self.style_table
+    }
+
+    /// Handles of every cell in the grid, scrollback included, and of the
+    /// current style.
+    fn styles_in_use(&self) -> impl Iterator<Item = StyleId> + '_ {
+        self.lines_above
+            .iter()
+            .chain(&self.viewport)
+            .chain(&self.lines_below)
+            .flat_map(|row| row.columns.iter().map(|cell| cell.styles))
+            .chain(self.current_shared_styles)
+    }
+
+    /// Free styles no cell uses any more. Returns how many were removed.
+    pub fn compact_styles(&mut self) -> usize {
+        let marks = self.style_table.mark(self.styles_in_use());
+        self.style_table.compact(&marks)
     }
 
-    /// Get the current shared styles.
-    pub fn current_shared_styles(&self) -> SharedStyles {
-        self.current_shared_styles.clone()
+    /// Size of the style table.
+    pub fn style_stats(&self) -> StyleStats {
+        self.style_table
+            .stats(&self.style_table.mark(self.styles_in_use()))
     }
 
     /// Get a reference to a row in the viewport.
@@ -147,7 +219,7 @@ impl Grid {
             return (self.cursor_row, self.cursor_col);
         }
 
-        let character = TerminalCharacter::new(c, self.current_shared_styles.clone());
+        let character = TerminalCharacter::new(c, self.current_shared_styles());
         let char_width = character.width();
 
         // Handle wide character that doesn't fit at the end of line
@@ -294,16 +366,73 @@ impl Grid {
     /// Push a line to the scrollback buffer, respecting the maximum size.
     fn push_to_scrollback(&mut self, line: Row) {
         if self.lines_above.len() >= MAX_SCROLLBACK_LINES {
-            self.lines_above.pop_front();
+            self.drop_oldest_scrollback(1);
         }
         self.lines_above.push_back(line);
     }
 
+    /// Remove the `count` oldest scrollback rows.
+    fn drop_oldest_scrollback(&mut self, count: usize) {
+        self.lines_above.drain(..count);
+        // Images starting on a dropped line go with it
+        self.images.retain_mut(|image| {
+            image.row = match image.row.checked_sub(count) {
+                Some(row) => row,
+                None => return false,
+            };
+            true
+        });
+        self.commands.drop_oldest_lines(count);
+        self.selection = self.selection.and_then(|s| s.scrolled(count));
+    }
+
+    /// Drop scrollback rows, oldest first, up to the first one written at or
+    /// after `cutoff`. Rows never written and the soft-wrapped rest of a
+    /// dropped line go too. Returns how many rows were removed.
+    pub fn prune_scrollback_before(&mut self, cutoff: SystemTime) -> usize {
+        let count = self
+            .lines_above
+            .iter()
+            .enumerate()
+            .take_while(|(i, row)| {
+                row.written_at.is_none_or(|at| at < cutoff) || (*i > 0 && !row.is_canonical)
+            })
+            .count();
+        self.drop_oldest_scrollback(count);
+        count
+    }
+
+    /// Record the first write to a viewport row.
+    pub fn stamp_row(&mut self, row: usize) {
+        if let Some(line) = self.viewport.get_mut(row) {
+            if line.written_at.is_none() {
+                line.written_at = Some(SystemTime::now());
+            }
+        }
+    }
+
+    /// Record an image, dropping the oldest past `MAX_INLINE_IMAGES`.
+    pub fn add_image(&mut self, image: InlineImage) {
+        if self.images.len() >= MAX_INLINE_IMAGES {
+            self.images.remove(0);
+        }
+        self.images.push(image);
+    }
+
+    /// The most recent image covering a cell; `row` counts from the oldest
+    /// scrollback line.
+    pub fn image_at(&self, row: usize, col: usize) -> Option<&InlineImage> {
+        self.images
+            .iter()
+            .rev()
+            .find(|image| image.contains(row, col))
+    }
+
     /// Clear from cursor to end of line.
     pub fn clear_to_end_of_line(&mut self) {
         if self.cursor_row < self.viewport.len() {
             self.mark_line_changed(self.cursor_row);
-            let style = self.current_shared_styles.clone();
+            let style = self.current_shared_styles();
             self.viewport[self.cursor_row].clear_from(self.cursor_col);
             self.viewport[self.cursor_row].fill_to_width_with_style(self.cols, style);
         }
@@ -313,7 +442,7 @@ impl Grid {
     pub fn clear_to_start_of_line(&mut self) {
         if self.cursor_row < self.viewport.len() {
             self.mark_line_changed(self.cursor_row);
-            let style = self.current_shared_styles.clone();
+            let style = self.current_shared_styles();
             self.viewport[self.cursor_row].clear_to_with_style(self.cursor_col, style);
         }
     }
@@ -322,19 +451,22 @@ impl Grid {
     pub fn clear_line(&mut self) {
         if self.cursor_row < self.viewport.len() {
             self.mark_line_changed(self.cursor_row);
-            let style = self.current_shared_styles.clone();
+            let style = self.current_shared_styles();
+            // EL keeps the line's size; only erasing the display resets it
+            let size = self.viewport[self.cursor_row].size;
             self.viewport[self.cursor_row] = Row::filled_with_style(self.cols, style);
+            self.viewport[self.cursor_row].size = size;
         }
     }
 
     /// Clear from cursor to end of screen.
     pub fn clear_to_end_of_screen(&mut self) {
         self.clear_to_end_of_line();
-        let style = self.current_shared_styles.clone();
+        let style = self.current_shared_styles();
         for row in (self.cursor_row + 1)..self.rows {
             if row < self.viewport.len() {
                 self.mark_line_changed(row);
-                self.viewport[row] = Row::filled_with_style(self.cols, style.clone());
+                self.viewport[row] = Row::filled_with_style(self.cols, style);
             }
         }
     }
@@ -342,22 +474,26 @@ impl Grid {
     /// Clear from cursor to beginning of screen.
     pub fn clear_to_start_of_screen(&mut self) {
         self.clear_to_start_of_line();
-        let style = self.current_shared_styles.clone();
+        let style = self.current_shared_styles();
         for row in 0..self.cursor_row {
             if row < self.viewport.len() {
                 self.mark_line_changed(row);
-                self.viewport[row] = Row::filled_with_style(self.cols, style.clone());
+                self.viewport[row] = Row::filled_with_style(self.cols, style);
             }
         }
     }
 
     /// Clear entire screen.
     pub fn clear_screen(&mut self) {
-        let style = self.current_shared_styles.clone();
+        let first_viewport_row = self.lines_above.len();
+        self.images
+            .retain(|image| image.row + image.rows <= first_viewport_row);
+        self.commands.clear_from(first_viewport_row);
+        let style = self.current_shared_styles();
         for row in 0..self.rows {
             if row < self.viewport.len() {
                 self.mark_line_changed(row);
-                self.viewport[row] = Row::filled_with_style(self.cols, style.clone());
+                self.viewport[row] = Row::filled_with_style(self.cols, style);
             }
         }
     }
@@ -366,7 +502,7 @@ impl Grid {
     pub fn insert_chars(&mut self, count: usize) {
         if self.cursor_row < self.viewport.len() {
             self.mark_line_changed(self.cursor_row);
-            let style = self.current_shared_styles.clone();
+            let style = self.current_shared_styles();
             self.viewport[self.cursor_row].insert_blank_with_style(
                 self.cursor_col,
                 count,
@@ -380,7 +516,7 @@ impl Grid {
     pub fn delete_chars(&mut self, count: usize) {
         if self.cursor_row < self.viewport.len() {
             self.mark_line_changed(self.cursor_row);
-            let style = self.current_shared_styles.clone();
+            let style = self.current_shared_styles();
             self.viewport[self.cursor_row].delete_chars_with_style(
                 self.cursor_col,
                 count,
@@ -394,7 +530,7 @@ impl Grid {
     pub fn erase_chars(&mut self, count: usize) {
         if self.cursor_row < self.viewport.len() {
             self.mark_line_changed(self.cursor_row);
-            let blank = TerminalCharacter::blank_with_style(self.current_shared_styles.clone());
+            let blank = TerminalCharacter::blank_with_style(self.current_shared_styles());
             for i in 0..count {
                 let col = self.cursor_col + i;
                 if col < self.cols {
@@ -417,8 +553,7 @@ impl Grid {
 
         // Handle width change
         if new_cols != old_cols {
-            // Rewrap lines if width changed
-            self.rewrap_lines(new_cols);
+            self.reflow(new_rows, new_cols);
         }
 
         // Handle height change
@@ -445,44 +580,124 @@ impl Grid {
         self.mark_all_changed();
     }
 
-    /// Rewrap lines when the terminal width changes.
-    fn rewrap_lines(&mut self, new_cols: usize) {
-        // Rewrap viewport lines
-        let mut new_viewport = Vec::new();
-        for row in &self.viewport {
-            if new_cols < self.cols {
-                // Narrower: split long lines
-                let split_rows = row.split_to_rows_of_length(new_cols);
-                for mut split_row in split_rows {
-                    split_row.truncate(new_cols);
-                    split_row.fill_to_width(new_cols);
-                    new_viewport.push(split_row);
-                }
-            } else {
-                // Wider: just extend/truncate
-                let mut new_row = row.clone();
-                new_row.fill_to_width(new_cols);
-                new_row.truncate(new_cols);
-                new_viewport.push(new_row);
+    /// Rewrap soft-wrapped lines to a new width, as xterm and kitty do.
+    ///
+    /// Rows chained by `is_canonical == false` form one logical line, which
+    /// is rewrapped at `new_cols` with its trailing blanks dropped. The cursor
+    /// keeps its place within its logical line, and the viewport is refilled
+    /// from the bottom of the buffer so the cursor stays on screen.
+    fn reflow(&mut self, new_rows: usize, new_cols: usize) {
+        // Images are laid out for the old width; rewrapped rows can't carry them
+        self.images.clear();
+        self.selection = None;
+        let cursor_abs = self.lines_above.len() + self.cursor_row;
+        let cursor_col = self.cursor_col;
+        let rows: Vec<Row> = self
+            .lines_above
+            .drain(..)
+            .chain(self.viewport.drain(..))
+            .collect();
+
+        let mut reflowed: Vec<Row> = Vec::with_capacity(rows.len());
+        let mut cursor = None;
+        // (first old row, first new row) of each logical line
+        let mut moved: Vec<(usize, usize)> = Vec::new();
+        let mut start = 0;
+        while start < rows.len() {
+            moved.push((start, reflowed.len()));
+            // Double-width and double-height rows are never rewrapped
+            let sized = rows[start].size != LineSize::Normal;
+            let mut end = start + 1;
+            while !sized
+                && end < rows.len()
+                && !rows[end].is_canonical
+                && rows[end].size == LineSize::Normal
+            {
+                end += 1;
             }
-        }
-        self.viewport = new_viewport;
+            let line = &rows[start..end];
+            let holds_cursor = (start..end).contains(&cursor_abs);
+            start = end;
 
-        // Also rewrap scrollback
-        let old_lines_above = std::mem::take(&mut self.lines_above);
-        for row in old_lines_above {
-            if new_cols < self.cols {
-                let split_rows = row.split_to_rows_of_length(new_cols);
-                for split_row in split_rows {
-                    self.lines_above.push_back(split_row);
+            if sized {
+                if holds_cursor {
+                    cursor = Some((reflowed.len(), cursor_col));
                 }
-            } else {
-                let mut new_row = row;
-                new_row.fill_to_width(new_cols);
-                new_row.truncate(new_cols);
-                self.lines_above.push_back(new_row);
+                let mut row = line[0].clone();
+                row.fill_to_width(new_cols);
+                row.truncate(new_cols);
+                reflowed.push(row);
+                continue;
+            }
+
+            let cursor_offset = holds_cursor.then(|| {
+                let before: usize = line[..cursor_abs + line.len() - end]
+                    .iter()
+                    .map(Row::len)
+                    .sum();
+                before + cursor_col
+            });
+            let mut cells: Vec<TerminalCharacter> = line
+                .iter()
+                .flat_map(|row| row.columns.iter().cloned())
+                .collect();
+            let keep = cursor_offset.unwrap_or(0);
+            while cells.len() > keep && cells.last().is_some_and(is_unused_cell) {
+                cells.pop();
+            }
+
+            let (wrapped, position) = wrap_cells(&cells, new_cols, cursor_offset);
+            if let Some((row, col)) = position {
+                cursor = Some((reflowed.len() + row, col));
             }
+            let written_at = line.iter().find_map(|row| row.written_at);
+            reflowed.extend(wrapped.into_iter().map(|row| Row { written_at, ..row }));
+        }
+
+        let old_len = rows.len();
+        let new_len = reflowed.len();
+        let (cursor_row, cursor_col) =
+            cursor.unwrap_or((reflowed.len().saturating_sub(1), cursor_col));
+        // Blank rows below the cursor are unused screen, not content to keep
+        while reflowed.len() > cursor_row + 1
+            && reflowed
+                .last()
+                .is_some_and(|row| row.is_canonical && row.iter().all(is_unused_cell))
+        {
+            reflowed.pop();
+        }
+
+        let viewport_start = reflowed.len().saturating_sub(new_rows).min(cursor_row);
+        let mut below = reflowed.split_off(viewport_start);
+        let overflow = below.split_off(new_rows.min(below.len()));
+        self.viewport = below;
+        while self.viewport.len() < new_rows {
+            self.viewport.push(Row::filled(new_cols));
+        }
+
+        for row in &mut self.lines_below {
+            row.fill_to_width(new_cols);
+            row.truncate(new_cols);
         }
+        self.lines_below.splice(0..0, overflow);
+
+        let excess = reflowed.len().saturating_sub(MAX_SCROLLBACK_LINES);
+        self.lines_above = reflowed.into_iter().skip(excess).collect();
+        // A marked line keeps its offset into its logical line, as far as
+        // the rewrapped line reaches
+        self.commands.remap(|line| {
+            let new = if line >= old_len {
+                new_len + (line - old_len)
+            } else {
+                let i = moved.partition_point(|&(old, _)| old <= line) - 1;
+                let (old_start, new_start) = moved[i];
+                let next = moved.get(i + 1).map_or(new_len, |&(_, new)| new);
+                new_start + (line - old_start).min(next.saturating_sub(new_start + 1))
+            };
+            new.checked_sub(excess)
+        });
+        self.cursor_row = cursor_row - viewport_start;
+        self.cursor_col = cursor_col;
     }
 
     /// Fix wide characters that are split at the edge after resize.
@@ -555,11 +770,182 @@ impl Grid {
     pub fn viewport_iter(&self) -> impl Iterator<Item = &Row> {
         self.viewport.iter()
     }
+
+    /// A scrollback or viewport row, counting from the oldest scrollback line.
+    pub fn row_at(&self, row: usize) -> Option<&Row> {
+        match row.checked_sub(self.lines_above.len()) {
+            Some(row) => self.viewport.get(row),
+            None => self.lines_above.get(row),
+        }
+    }
+
+    /// Start a selection at `point`, replacing any other. `mode` decides
+    /// whether it grows by cells, words, lines or as a rectangle.
+    pub fn start_selection(&mut self, point: SelectionPoint, mode: SelectionMode) {
+        self.selection = Some(Selection::new(self, point, point, mode));
+    }
+
+    /// Move the free end of the selection to `point`.
+    pub fn extend_selection(&mut self, point: SelectionPoint) {
+        if let Some(current) = self.selection {
+            self.selection = Some(Selection::new(self, current.anchor, point, current.mode));
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+
+    /// The current selection, for highlighting with [`Selection::contains`].
+    pub fn selection(&self) -> Option<&Selection> {
+        self.selection.as_ref()
+    }
+
+    /// Text of the current selection, ready for the clipboard.
+    pub fn selected_text(&self) -> Option<String> {
+        self.selection
+            .as_ref()
+            .map(|current| selection::selected_text(self, current))
+    }
+
+    /// URLs, `file:line:col` references and commit hashes in scrollback and
+    /// the viewport, e.g. to make stack traces clickable. Soft-wrapped rows
+    /// are scanned as one line. Rows count from the oldest scrollback line,
+    /// like [`SearchMatch`](crate::SearchMatch) rows.
+    pub fn detect_spans(&self) -> Vec<TerminalLink> {
+        links::detect_in_rows(self.lines_above.iter().chain(self.viewport.iter()))
+    }
+
+    /// Copy `region` of `source`'s viewport into this viewport with its
+    /// top-left corner at `(row, col)`, keeping each cell's style, e.g. to
+    /// compose several terminals into one screen. The copy is clipped to
+    /// both grids. A wide character split by the edge of the region, on
+    /// either side, is blanked rather than left half drawn. Returns the
+    /// rectangle written here.
+    pub fn copy_region_from(
+        &mut self,
+        source: &Grid,
+        region: GridRegion,
+        row: usize,
+        col: usize,
+    ) -> GridRegion {
+        let rows = region
+            .rows
+            .min(source.viewport.len().saturating_sub(region.row))
+            .min(self.viewport.len().saturating_sub(row));
+        let cols = region
+            .cols
+            .min(source.cols.saturating_sub(region.col))
+            .min(self.cols.saturating_sub(col));
+        for r in 0..rows {
+            let from = &source.viewport[region.row + r];
+            let dest = row + r;
+            for c in 0..cols {
+                let mut cell = from.get(region.col + c).cloned().unwrap_or_default();
+                // Style handles are per grid, so re-intern them here
+                let styles = self
+                    .style_table
+                    .intern(*source.style_table.get(cell.styles));
+                if (cell.is_wide() && c + 1 == cols) || (cell.wide_spacer && c == 0) {
+                    cell = TerminalCharacter::blank_with_style(styles);
+                } else {
+                    cell.styles = styles;
+                }
+                self.viewport[dest].set(col + c, cell);
+            }
+            if cols > 0 {
+                self.blank_split_wide_chars(dest, col, col + cols);
+            }
+            self.mark_line_changed(dest);
+        }
+        GridRegion {
+            row,
+            col,
+            rows,
+            cols,
+        }
+    }
+
+    /// Blank wide characters in `row` that lost a half when the columns
+    /// `start..end` were overwritten. The copy never leaves a wide character
+    /// at `end - 1` or a spacer at `start`, so neighbours there are split.
+    fn blank_split_wide_chars(&mut self, row: usize, start: usize, end: usize) {
+        let line = &mut self.viewport[row];
+        let split = [
+            start
+                .checked_sub(1)
+                .filter(|&c| line.get(c).is_some_and(|cell| cell.is_wide())),
+            Some(end).filter(|&c| line.get(c).is_some_and(|cell| cell.wide_spacer)),
+        ];
+        for c in split.into_iter().flatten() {
+            let styles = line.columns[c].styles;
+            line.columns[c] = TerminalCharacter::blank_with_style(styles);
+        }
+    }
+}
+
+/// A cell nothing was written to, which reflow may drop from a line's end.
+fn is_unused_cell(cell: &TerminalCharacter) -> bool {
+    cell.character == ' ' && !cell.wide_spacer && cell.styles.is_default()
+}
+
+/// Lay a logical line's cells out in rows of `cols`, moving wide characters
+/// that would straddle the edge to the next row. Also returns where the cell
+/// at `cursor` landed; a cursor past the end sits after the last cell.
+fn wrap_cells(
+    cells: &[TerminalCharacter],
+    cols: usize,
+    cursor: Option<usize>,
+) -> (Vec<Row>, Option<(usize, usize)>) {
+    let mut rows = vec![Row::with_capacity(cols)];
+    let mut position = None;
+    let mut index = 0;
+    while index < cells.len() {
+        let cell = &cells[index];
+        let span = if cell.is_wide() && cols >= 2 { 2 } else { 1 };
+        if rows[rows.len() - 1].len() + span > cols {
+            let mut next = Row::with_capacity(cols);
+            next.is_canonical = false;
+            rows.push(next);
+        }
+        let row_index = rows.len() - 1;
+        let row = &mut rows[row_index];
+        if cursor == Some(index) {
+            position = Some((row_index, row.len()));
+        }
+        row.columns.push_back(cell.clone());
+        index += 1;
+
+        if span == 2 {
+            if cursor == Some(index) {
+                position = Some((row_index, row.len()));
+            }
+            let spacer = match cells.get(index) {
+                Some(next) if next.wide_spacer => {
+                    index += 1;
+                    next.clone()
+                }
+                _ => TerminalCharacter::wide_spacer(StyleId::DEFAULT),
+            };
+            row.columns.push_back(spacer);
+        }
+    }
+
+    if cursor.is_some() && position.is_none() {
+        let last = rows.len() - 1;
+        position = Some((last, rows[last].len().min(cols.saturating_sub(1))));
+    }
+    for row in &mut rows {
+        row.fill_to_width(cols);
+    }
+    (rows, position)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::character::STYLE_COMPACTION_INTERVAL;
+    use ratatui::style::{Color, Modifier};
 
     #[test]
     fn test_grid_new() {
@@ -641,4 +1027,148 @@ mod tests {
         // Original content should be preserved
         assert_eq!(grid.viewport[0].columns[0].character, 'A');
     }
+
+    fn row_text(row: &Row) -> String {
+        row.iter()
+            .map(|cell| cell.character)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    #[test]
+    fn test_grid_reflow_rewraps_and_keeps_cursor() {
+        let mut grid = Grid::new(3, 6);
+        for c in "abcdef".chars() {
+            grid.put_char(c);
+        }
+        grid.cursor_row = 1;
+        grid.cursor_col = 0;
+        grid.viewport[1].is_canonical = false;
+        for c in "gh".chars() {
+            grid.put_char(c);
+        }
+
+        // Narrower: the 8-character line takes three rows
+        grid.resize(3, 3);
+        let rows: Vec<String> = grid.viewport.iter().map(row_text).collect();
+        assert_eq!(rows, ["abc", "def", "gh"]);
+        assert!(grid.viewport[0].is_canonical);
+        assert!(!grid.viewport[1].is_canonical && !grid.viewport[2].is_canonical);
+        assert_eq!((grid.cursor_row, grid.cursor_col), (2, 2));
+
+        // Wider: the line joins back up instead of staying split
+        grid.resize(3, 10);
+        assert_eq!(row_text(&grid.viewport[0]), "abcdefgh");
+        assert_eq!(row_text(&grid.viewport[1]), "");
+        assert!(grid.viewport[1].is_canonical);
+        assert_eq!((grid.cursor_row, grid.cursor_col), (0, 8));
+        assert!(grid.lines_above.is_empty());
+    }
+
+    #[test]
+    fn test_grid_reflow_moves_wide_chars_whole() {
+        let mut grid = Grid::new(2, 4);
+        for c in "a中b".chars() {
+            grid.put_char(c);
+        }
+        // "a中" would straddle the edge, so the wide char moves down whole
+        grid.resize(2, 2);
+        assert_eq!(grid.lines_above.len(), 1);
+        assert_eq!(row_text(&grid.lines_above[0]), "a");
+        assert_eq!(grid.viewport[0].columns[0].character, '中');
+        assert!(grid.viewport[0].columns[1].wide_spacer);
+        assert_eq!(row_text(&grid.viewport[1]), "b");
+        assert_eq!((grid.cursor_row, grid.cursor_col), (1, 1));
+    }
+
+    #[test]
+    fn copy_region_keeps_styles_and_blanks_split_wide_chars() {
+        let mut source = Grid::new(3, 6);
+        source.set_current_styles(CharacterStyles {
+            modifiers: Modifier::BOLD,
+            ..Default::default()
+        });
+        for c in "ab日c".chars() {
+            source.put_char(c);
+        }
+        let mut dest = Grid::new(2, 8);
+        for c in "xy本z".chars() {
+            dest.put_char(c);
+        }
+
+        // Columns 1..3 of "ab日c" end inside 日, and column 3 of "xy本z"
+        // starts inside 本
+        let written = dest.copy_region_from(
+            &source,
+            GridRegion {
+                row: 0,
+                col: 1,
+                rows: 5,
+                cols: 2,
+            },
+            0,
+            3,
+        );
+        assert_eq!(
+            written,
+            GridRegion {
+                row: 0,
+                col: 3,
+                rows: 2,
+                cols: 2
+            }
+        );
+        assert_eq!(row_text(&dest.viewport[0]), "xy b");
+        assert!(!dest.viewport[0].columns[2].is_wide());
+        let bold = |cell: &TerminalCharacter| {
+            dest.style_table
+                .get(cell.styles)
+                .modifiers
+                .contains(Modifier::BOLD)
+        };
+        assert!(bold(&dest.viewport[0].columns[3]));
+        assert!(bold(&dest.viewport[0].columns[4]));
+        assert_eq!(dest.style_stats().unique_styles, 1);
+
+        // Clipped at the destination's edge
+        let written = dest.copy_region_from(
+            &source,
+            GridRegion {
+                row: 0,
+                col: 0,
+                rows: 1,
+                cols: 6,
+            },
+            1,
+            5,
+        );
+        assert_eq!((written.rows, written.cols), (1, 3));
+        assert_eq!(row_text(&dest.viewport[1]), "     ab");
+    }
+
+    #[test]
+    fn unused_styles_are_freed_as_new_ones_are_interned() {
+        let mut grid = Grid::new(2, 4);
+        grid.set_current_styles(CharacterStyles {
+            modifiers: Modifier::BOLD,
+            ..Default::default()
+        });
+        grid.put_char('b');
+        for i in 0..3 * STYLE_COMPACTION_INTERVAL {
+            let rgb = Color::Rgb(1, (i >> 8) as u8, i as u8);
+            grid.set_current_styles(CharacterStyles::default().fg(rgb));
+            grid.cursor_col = 1;
+            grid.put_char('x');
+        }
+        // Only the overwritten styles went, and the table stays bounded
+        let stats = grid.style_stats();
+        assert!(stats.compactions >= 2);
+        assert!(stats.unique_styles <= STYLE_COMPACTION_INTERVAL + 2);
+        let styles = |col: usize| grid.style_table.get(grid.viewport[0].columns[col].styles);
+        assert!(styles(0).modifiers.contains(Modifier::BOLD));
+        let last = 3 * STYLE_COMPACTION_INTERVAL - 1;
+        let rgb = Color::Rgb(1, (last >> 8) as u8, last as u8);
+        assert_eq!(styles(1).foreground, Some(rgb));
+    }
 }
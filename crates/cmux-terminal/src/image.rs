@@ -0,0 +1,495 @@
+//! Inline images sent by applications.
+//!
+//! Two protocols are understood: sixel graphics (`DCS P1;P2 q ... ST`),
+//! decoded here into RGBA pixels, and iTerm2's `OSC 1337 ; File=...` whose
+//! payload is kept in its original format (PNG, JPEG, GIF, ...) for the
+//! renderer to decode. Either way the terminal records the cells the image
+//! covers and moves the cursor below it, as xterm and iTerm2 do.
+
+use std::sync::Arc;
+
+use base64::Engine;
+
+/// Largest sixel image, in pixels per side; drawing past it is clipped.
+const MAX_SIXEL_DIMENSION: usize = 2048;
+
+/// Parameters kept per sixel command; color definitions use five.
+const MAX_SIXEL_PARAMS: usize = 8;
+
+/// Largest OSC 1337 file accepted, after base64 decoding.
+pub(crate) const MAX_IMAGE_FILE_BYTES: usize = 16 * 1024 * 1024;
+
+/// An image placed on the grid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineImage {
+    pub id: u64,
+    /// Top-left cell. `row` counts from the oldest scrollback line, so the
+    /// first viewport row is `scrollback_len()`.
+    pub row: usize,
+    pub col: usize,
+    /// Size in cells
+    pub rows: usize,
+    pub cols: usize,
+    /// Size in pixels, when known
+    pub pixel_size: Option<(u32, u32)>,
+    pub data: ImageData,
+}
+
+impl InlineImage {
+    /// Whether the image covers the cell at `row` (counted like
+    /// [`InlineImage::row`]) and `col`.
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        (self.row..self.row + self.rows).contains(&row)
+            && (self.col..self.col + self.cols).contains(&col)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageData {
+    /// Decoded sixel pixels, row-major RGBA
+    Rgba(Arc<[u8]>),
+    /// File sent with OSC 1337, still encoded
+    File {
+        name: Option<String>,
+        bytes: Arc<[u8]>,
+    },
+}
+
+/// An image decoded from an escape sequence, before it is placed.
+pub(crate) struct PendingImage {
+    pub(crate) data: ImageData,
+    pub(crate) pixel_size: Option<(u32, u32)>,
+    /// Requested size in cells; `None` sizes from the pixels
+    pub(crate) cols: Option<usize>,
+    pub(crate) rows: Option<usize>,
+    /// With both sides requested, shrink one so the image keeps its shape
+    pub(crate) preserve_aspect_ratio: bool,
+    /// iTerm2 puts the cursor at the start of the next line, sixel keeps
+    /// the image's column
+    pub(crate) return_to_first_column: bool,
+}
+
+impl PendingImage {
+    /// Size in cells for a terminal of `term_cols` x `term_rows` whose cells
+    /// are `cell` pixels, scaling a missing side to keep the aspect ratio.
+    pub(crate) fn cell_size(
+        &self,
+        cell: (u16, u16),
+        term_cols: usize,
+        term_rows: usize,
+    ) -> (usize, usize) {
+        let (cell_w, cell_h) = (cell.0.max(1) as f64, cell.1.max(1) as f64);
+        let cells = |px: f64, per_cell: f64| (px / per_cell).ceil().max(1.0) as usize;
+        let rows_for = |cols: usize, (w, h): (u32, u32)| {
+            cells(cols as f64 * cell_w * h as f64 / w.max(1) as f64, cell_h)
+        };
+        let cols_for = |rows: usize, (w, h): (u32, u32)| {
+            cells(rows as f64 * cell_h * w as f64 / h.max(1) as f64, cell_w)
+        };
+        let (cols, rows) = match (self.cols, self.rows, self.pixel_size) {
+            (Some(cols), Some(rows), Some(px)) if self.preserve_aspect_ratio => {
+                // Fit inside the requested box
+                if rows_for(cols, px) <= rows {
+                    (cols, rows_for(cols, px))
+                } else {
+                    (cols_for(rows, px), rows)
+                }
+            }
+            (Some(cols), Some(rows), _) => (cols, rows),
+            (Some(cols), None, Some(px)) => (cols, rows_for(cols, px)),
+            (None, Some(rows), Some(px)) => (cols_for(rows, px), rows),
+            (None, None, Some((w, h))) => (cells(w as f64, cell_w), cells(h as f64, cell_h)),
+            (cols, rows, None) => (cols.unwrap_or(1), rows.unwrap_or(1)),
+        };
+        (
+            cols.clamp(1, term_cols.max(1)),
+            rows.clamp(1, term_rows.max(1) * 4),
+        )
+    }
+}
+
+/// Sixel color registers a VT340 starts with, as RGB percentages.
+const VT340_PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (20, 20, 80),
+    (80, 13, 13),
+    (20, 80, 20),
+    (80, 20, 80),
+    (20, 80, 80),
+    (80, 80, 20),
+    (53, 53, 53),
+    (26, 26, 26),
+    (33, 33, 60),
+    (60, 26, 26),
+    (33, 60, 33),
+    (60, 33, 60),
+    (33, 60, 60),
+    (60, 60, 33),
+    (80, 80, 80),
+];
+
+fn percent_to_byte(value: u32) -> u8 {
+    (value.min(100) * 255 / 100) as u8
+}
+
+/// Sixel HLS, where hue 0 is blue rather than red.
+fn hls_to_rgb(hue: u32, lightness: u32, saturation: u32) -> (u8, u8, u8) {
+    let h = ((hue + 240) % 360) as f64 / 360.0;
+    let l = lightness.min(100) as f64 / 100.0;
+    let s = saturation.min(100) as f64 / 100.0;
+    if s == 0.0 {
+        let v = (l * 255.0).round() as u8;
+        return (v, v, v);
+    }
+    let q = if l < 0.5 {
+        l * (1.0 + s)
+    } else {
+        l + s - l * s
+    };
+    let p = 2.0 * l - q;
+    let channel = |mut t: f64| {
+        t = t.rem_euclid(1.0);
+        let v = if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        };
+        (v * 255.0).round() as u8
+    };
+    (channel(h + 1.0 / 3.0), channel(h), channel(h - 1.0 / 3.0))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SixelCommand {
+    Data,
+    Raster,
+    Color,
+    Repeat,
+}
+
+/// Streaming sixel decoder fed one DCS byte at a time.
+#[derive(Debug, Clone)]
+pub(crate) struct SixelDecoder {
+    /// Color registers, packed RGBA
+    palette: Vec<u32>,
+    color: usize,
+    x: usize,
+    /// Top pixel row of the current six-pixel band
+    y: usize,
+    /// Drawn pixels, packed RGBA with 0 for unset, one `Vec` per pixel row
+    pixels: Vec<Vec<u32>>,
+    /// Size from the raster attributes
+    raster: (usize, usize),
+    transparent_background: bool,
+    command: SixelCommand,
+    params: Vec<u32>,
+}
+
+fn pack((r, g, b): (u8, u8, u8)) -> u32 {
+    u32::from_be_bytes([r, g, b, 0xff])
+}
+
+impl SixelDecoder {
+    /// `P2 = 1` in the DCS leaves unset pixels transparent; otherwise they
+    /// take color register 0.
+    pub(crate) fn new(transparent_background: bool) -> Self {
+        let mut palette = vec![pack((0, 0, 0)); 256];
+        for (slot, &(r, g, b)) in palette.iter_mut().zip(VT340_PALETTE.iter()) {
+            *slot = pack((
+                percent_to_byte(r as u32),
+                percent_to_byte(g as u32),
+                percent_to_byte(b as u32),
+            ));
+        }
+        Self {
+            palette,
+            color: 0,
+            x: 0,
+            y: 0,
+            pixels: Vec::new(),
+            raster: (0, 0),
+            transparent_background,
+            command: SixelCommand::Data,
+            params: Vec::new(),
+        }
+    }
+
+    pub(crate) fn put(&mut self, byte: u8) {
+        match byte {
+            b'0'..=b'9' if self.command != SixelCommand::Data => {
+                let digit = (byte - b'0') as u32;
+                match self.params.last_mut() {
+                    Some(param) => *param = param.saturating_mul(10).saturating_add(digit),
+                    None => self.params.push(digit),
+                }
+            }
+            b';' if self.command != SixelCommand::Data => {
+                if self.params.is_empty() {
+                    self.params.push(0);
+                }
+                if self.params.len() < MAX_SIXEL_PARAMS {
+                    self.params.push(0);
+                }
+            }
+            _ => {
+                let count = self.finish_command();
+                match byte {
+                    b'"' => self.command = SixelCommand::Raster,
+                    b'#' => self.command = SixelCommand::Color,
+                    b'!' => self.command = SixelCommand::Repeat,
+                    b'$' => self.x = 0,
+                    b'-' => {
+                        self.x = 0;
+                        self.y += 6;
+                    }
+                    b'?'..=b'~' => self.draw(byte - b'?', count),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Apply the parameters of the command that just ended. Returns the
+    /// repeat count for the sixel that follows.
+    fn finish_command(&mut self) -> usize {
+        let params = std::mem::take(&mut self.params);
+        let command = std::mem::replace(&mut self.command, SixelCommand::Data);
+        match command {
+            SixelCommand::Data => 1,
+            SixelCommand::Repeat => params.first().copied().unwrap_or(1).max(1) as usize,
+            SixelCommand::Raster => {
+                if let [_, _, width, height, ..] = params[..] {
+                    self.raster = (
+                        (width as usize).min(MAX_SIXEL_DIMENSION),
+                        (height as usize).min(MAX_SIXEL_DIMENSION),
+                    );
+                }
+                1
+            }
+            SixelCommand::Color => {
+                if let Some(&register) = params.first() {
+                    self.color = (register as usize).min(self.palette.len() - 1);
+                }
+                if let [_, space, a, b, c, ..] = params[..] {
+                    let rgb = match space {
+                        1 => Some(hls_to_rgb(a, b, c)),
+                        2 => Some((percent_to_byte(a), percent_to_byte(b), percent_to_byte(c))),
+                        _ => None,
+                    };
+                    if let Some(rgb) = rgb {
+                        self.palette[self.color] = pack(rgb);
+                    }
+                }
+                1
+            }
+        }
+    }
+
+    fn draw(&mut self, bits: u8, count: usize) {
+        let color = self.palette[self.color];
+        let end = (self.x + count).min(MAX_SIXEL_DIMENSION);
+        for bit in 0..6 {
+            let y = self.y + bit;
+            if bits & (1 << bit) == 0 || y >= MAX_SIXEL_DIMENSION || self.x >= end {
+                continue;
+            }
+            if self.pixels.len() <= y {
+                self.pixels.resize_with(y + 1, Vec::new);
+            }
+            let row = &mut self.pixels[y];
+            if row.len() < end {
+                row.resize(end, 0);
+            }
+            row[self.x..end].fill(color);
+        }
+        self.x += count;
+    }
+
+    /// The decoded image, or `None` when nothing was drawn.
+    pub(crate) fn finish(mut self) -> Option<PendingImage> {
+        self.finish_command();
+        let drawn_width = self.pixels.iter().map(Vec::len).max().unwrap_or(0);
+        let width = drawn_width.max(self.raster.0);
+        let height = self.pixels.len().max(self.raster.1);
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let background = if self.transparent_background {
+            0
+        } else {
+            self.palette[0]
+        };
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let row = self.pixels.get(y).map(Vec::as_slice).unwrap_or_default();
+            for x in 0..width {
+                let pixel = row.get(x).copied().filter(|&p| p != 0);
+                rgba.extend_from_slice(&pixel.unwrap_or(background).to_be_bytes());
+            }
+        }
+        Some(PendingImage {
+            data: ImageData::Rgba(rgba.into()),
+            pixel_size: Some((width as u32, height as u32)),
+            cols: None,
+            rows: None,
+            preserve_aspect_ratio: true,
+            return_to_first_column: false,
+        })
+    }
+}
+
+/// Pixel size read from a PNG, GIF or JPEG header.
+fn encoded_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    if bytes.len() >= 24 && bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((be32(&bytes[16..20]), be32(&bytes[20..24])));
+    }
+    if bytes.len() >= 10 && (bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a")) {
+        let le16 = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]) as u32;
+        return Some((le16(&bytes[6..8]), le16(&bytes[8..10])));
+    }
+    if bytes.starts_with(&[0xff, 0xd8]) {
+        // Walk the JPEG segments to the first start-of-frame marker
+        let mut i = 2;
+        while i + 9 < bytes.len() {
+            if bytes[i] != 0xff {
+                return None;
+            }
+            let marker = bytes[i + 1];
+            let len = u16::from_be_bytes([bytes[i + 2], bytes[i + 3]]) as usize;
+            if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+                let h = u16::from_be_bytes([bytes[i + 5], bytes[i + 6]]) as u32;
+                let w = u16::from_be_bytes([bytes[i + 7], bytes[i + 8]]) as u32;
+                return Some((w, h));
+            }
+            i += 2 + len;
+        }
+    }
+    None
+}
+
+/// Cells for an iTerm2 `width`/`height` value: `N` cells, `Npx`, `N%` of
+/// the terminal, or `auto` (`None`).
+fn iterm2_length(value: &str, cell_px: u16, term_cells: usize) -> Option<usize> {
+    let value = value.trim();
+    if let Some(px) = value.strip_suffix("px") {
+        let px: f64 = px.parse().ok()?;
+        return Some((px / cell_px.max(1) as f64).ceil() as usize);
+    }
+    if let Some(percent) = value.strip_suffix('%') {
+        let percent: f64 = percent.parse().ok()?;
+        return Some((term_cells as f64 * percent / 100.0).ceil() as usize);
+    }
+    value.parse().ok()
+}
+
+/// Parse the arguments of `OSC 1337 ; File=args:base64`. Files not marked
+/// `inline=1` are downloads, not images, and are ignored.
+pub(crate) fn parse_iterm2_file(
+    payload: &[u8],
+    cell: (u16, u16),
+    term_cols: usize,
+    term_rows: usize,
+) -> Option<PendingImage> {
+    let payload = payload.strip_prefix(b"File=")?;
+    let split = payload.iter().position(|&b| b == b':')?;
+    let args = std::str::from_utf8(&payload[..split]).ok()?;
+    let data = &payload[split + 1..];
+    if data.len() / 4 * 3 > MAX_IMAGE_FILE_BYTES {
+        return None;
+    }
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let mut inline = false;
+    let mut name = None;
+    let mut cols = None;
+    let mut rows = None;
+    let mut preserve_aspect_ratio = true;
+    for arg in args.split(';') {
+        let Some((key, value)) = arg.split_once('=') else {
+            continue;
+        };
+        match key {
+            "inline" => inline = value == "1",
+            "name" => {
+                name = engine
+                    .decode(value)
+                    .ok()
+                    .and_then(|name| String::from_utf8(name).ok())
+            }
+            "width" => cols = iterm2_length(value, cell.0, term_cols),
+            "height" => rows = iterm2_length(value, cell.1, term_rows),
+            "preserveAspectRatio" => preserve_aspect_ratio = value != "0",
+            _ => {}
+        }
+    }
+    if !inline {
+        return None;
+    }
+    let bytes = engine.decode(data).ok()?;
+    let pixel_size = encoded_dimensions(&bytes);
+    Some(PendingImage {
+        data: ImageData::File {
+            name,
+            bytes: bytes.into(),
+        },
+        pixel_size,
+        cols,
+        rows,
+        preserve_aspect_ratio,
+        return_to_first_column: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sixel_decodes_colors_repeats_and_bands() {
+        let mut decoder = SixelDecoder::new(true);
+        // Register 1 set to pure red, two full columns, then one pixel on
+        // the second band
+        for &byte in b"\"1;1;3;7#1;2;100;0;0!2~-#0@" {
+            decoder.put(byte);
+        }
+        let image = decoder.finish().unwrap();
+        assert_eq!(image.pixel_size, Some((3, 7)));
+        let ImageData::Rgba(rgba) = image.data else {
+            panic!("sixel images are decoded");
+        };
+        let pixel = |x: usize, y: usize| &rgba[(y * 3 + x) * 4..(y * 3 + x) * 4 + 4];
+        assert_eq!(pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(1, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(2, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(0, 6), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn iterm2_files_are_sized_from_headers_and_arguments() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&40u32.to_be_bytes());
+        png.extend_from_slice(&60u32.to_be_bytes());
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&png);
+
+        let payload = format!("File=inline=1:{encoded}");
+        let image = parse_iterm2_file(payload.as_bytes(), (10, 20), 80, 24).unwrap();
+        assert_eq!(image.pixel_size, Some((40, 60)));
+        assert_eq!(image.cell_size((10, 20), 80, 24), (4, 3));
+
+        let payload = format!("File=inline=1;width=8:{encoded}");
+        let image = parse_iterm2_file(payload.as_bytes(), (10, 20), 80, 24).unwrap();
+        assert_eq!(image.cell_size((10, 20), 80, 24), (8, 6));
+
+        let payload = format!("File=inline=1;width=8;height=3:{encoded}");
+        let image = parse_iterm2_file(payload.as_bytes(), (10, 20), 80, 24).unwrap();
+        assert_eq!(image.cell_size((10, 20), 80, 24), (4, 3));
+
+        let payload = format!("File=name=YS5wbmc=:{encoded}");
+        assert!(parse_iterm2_file(payload.as_bytes(), (10, 20), 80, 24).is_none());
+    }
+}
@@ -0,0 +1,63 @@
+//! Kitty keyboard protocol mode tracking.
+//!
+//! Applications opt into the protocol with `CSI > flags u` and expect keys
+//! encoded accordingly until they pop the flags again. The terminal only
+//! records the flags; encoding keys is up to the host, which reads them from
+//! [`TerminalModes::keyboard_flags`](crate::TerminalModes::keyboard_flags).
+//!
+//! Each screen keeps its own stack, as the protocol requires, so a TUI on the
+//! alternate screen can't leave the shell with the protocol enabled.
+
+use serde::{Deserialize, Serialize};
+
+/// Entries kept on a stack before the oldest is evicted.
+const MAX_PUSHED_FLAGS: usize = 32;
+
+/// Bits the protocol defines (disambiguate, event types, alternate keys,
+/// all keys as escapes, associated text).
+const KNOWN_FLAGS: u8 = 0b1_1111;
+
+/// The active flags of one screen and those saved by pushes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct KeyboardFlagStack {
+    current: u8,
+    pushed: Vec<u8>,
+}
+
+impl KeyboardFlagStack {
+    pub(crate) fn current(&self) -> u8 {
+        self.current
+    }
+
+    /// `CSI > flags u`
+    pub(crate) fn push(&mut self, flags: u16) {
+        if self.pushed.len() >= MAX_PUSHED_FLAGS {
+            self.pushed.remove(0);
+        }
+        self.pushed.push(self.current);
+        self.current = (flags & u16::from(KNOWN_FLAGS)) as u8;
+    }
+
+    /// `CSI < count u`; popping past the bottom clears the flags.
+    pub(crate) fn pop(&mut self, count: u16) {
+        let count = usize::from(count.max(1));
+        match self.pushed.len().checked_sub(count) {
+            Some(keep) => {
+                self.current = self.pushed[keep];
+                self.pushed.truncate(keep);
+            }
+            None => *self = Self::default(),
+        }
+    }
+
+    /// `CSI = flags ; mode u`: 1 replaces the flags, 2 sets the given bits
+    /// and 3 clears them.
+    pub(crate) fn set(&mut self, flags: u16, mode: u16) {
+        let flags = (flags & u16::from(KNOWN_FLAGS)) as u8;
+        match mode {
+            2 => self.current |= flags,
+            3 => self.current &= !flags,
+            _ => self.current = flags,
+        }
+    }
+}
@@ -3,7 +3,25 @@
 //! This crate provides:
 //! - `VirtualTerminal`: Full ANSI/VT100 terminal emulator with scrollback
 //! - `DaFilter`: Filter for Device Attributes queries to prevent feedback loops
-//! - `Grid`, `Row`, `TerminalCharacter`: Terminal buffer types
+//! - `C1Decoder`: Normalizes 8-bit C1 controls ahead of the parser
+//! - `InputEncoding`: UTF-8 or code page 437 input, for ANSI art and DOS programs
+//! - `TerminalLink`: URLs, `file:line` references and commit hashes found on screen
+//! - `SearchMatch`: Results of searching scrollback and the screen
+//! - `Selection`: Char, word, line and block selection with text extraction
+//! - `TerminalModes`: Paste, mouse, focus and kitty keyboard modes the application enabled
+//! - `CursorStyle`: Cursor shape, blinking and visibility set by DECSCUSR and DECTCEM
+//! - `SemanticMark`: OSC 133 prompt, input and output boundaries
+//! - `CommandBlock`: Prompts and their commands' output and exit status
+//! - `InlineImage`: Sixel and iTerm2 images placed on the grid
+//! - `render::html`: Styled HTML export of scrollback and the screen
+//! - `TerminalSnapshot`: Serializable checkpoint for moving a terminal between processes
+//! - `Recorder`, `Player`: asciicast v2 recording and replay of sessions
+//! - `GridDelta`: Compact cell diffs between screen states for remote viewers
+//! - `Grid`, `Row`, `LineSize`, `TerminalCharacter`: Terminal buffer types
+//! - `GridRegion`: Rectangles copied between grids to compose several terminals
+//! - `ColorReducer`: Downgrades truecolor to 256 or 16 colors for limited consumers
+//! - `ColorChange`: Palette and default colors redefined by the application (OSC 4/10-12)
+//! - `TmuxControl`: tmux control mode (`-CC`) client mirroring each pane in a `VirtualTerminal`
 //!
 //! # Usage
 //!
@@ -19,15 +37,47 @@
 //! let filtered = filter.filter(b"\x1b[c"); // DA1 query filtered out
 //! ```
 
+mod c1;
+mod cast;
 mod character;
+mod color;
+mod commands;
+mod delta;
+mod encoding;
 mod filter;
 mod grid;
+mod image;
+mod keyboard;
+mod links;
+pub mod render;
+mod search;
+mod selection;
+mod snapshot;
 mod terminal;
+mod tmux_control;
 
-pub use character::{CharacterStyles, ColorPalette, Row, SharedStyles, TerminalCharacter};
+pub use c1::{C1Decoder, C1Mode};
+pub use cast::{Cast, CastData, CastEvent, CastHeader, Player, Recorder};
+pub use character::{
+    AmbiguousWidth, CharacterStyles, ColorPalette, LineSize, Row, StyleId, StyleStats, StyleTable,
+    TerminalCharacter,
+};
+pub use color::{ColorDepth, ColorPaletteExt, ColorReducer};
+pub use commands::CommandBlock;
+pub use delta::{CellRun, GridDelta, ScrollDelta};
+pub use encoding::{cp437_char, InputEncoding};
 pub use filter::{filter_da_queries, DaFilter};
-pub use grid::Grid;
-pub use terminal::{Cell, VirtualTerminal};
+pub use grid::{Grid, GridRegion};
+pub use image::{ImageData, InlineImage};
+pub use links::{detect_links, find_links, LinkKind, TerminalLink};
+pub use search::{SearchMatch, SearchOptions};
+pub use selection::{Selection, SelectionMode, SelectionPoint};
+pub use snapshot::TerminalSnapshot;
+pub use terminal::{
+    Cell, ColorChange, ColorSlot, Continuation, CursorShape, CursorStyle, MouseTracking,
+    ProcessBudget, SemanticMark, SemanticMarkKind, TerminalModes, VirtualTerminal,
+};
+pub use tmux_control::{parse_layout, ControlEvent, PaneLayout, TmuxControl};
 
 // Re-export ratatui types that are used in the public API
 pub use ratatui::style::{Color, Modifier, Style};
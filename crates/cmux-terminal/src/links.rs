@@ -0,0 +1,257 @@
+//! Link detection over rendered terminal text.
+//!
+//! Scanning raw PTY bytes for URLs breaks as soon as escape sequences or cursor
+//! movement land inside a path, so detection runs over the grid after the
+//! parser has applied them. [`find_links`] works on a single logical line;
+//! [`Grid::detect_spans`](crate::Grid::detect_spans) and
+//! `VirtualTerminal::viewport_links` join soft-wrapped rows into lines and map
+//! the matches back to cell positions.
+
+use std::ops::Range;
+
+use crate::character::Row;
+
+/// What a detected link points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkKind {
+    /// An `http`, `https` or `file` URL.
+    Url,
+    /// A file path, optionally followed by `:line` and `:column`.
+    File {
+        path: String,
+        line: Option<u32>,
+        column: Option<u32>,
+    },
+    /// A git commit hash, full or abbreviated.
+    Sha,
+}
+
+/// A link on screen. Rows are numbered within the rows scanned: from the top
+/// of the viewport for `VirtualTerminal::viewport_links`, from the oldest
+/// scrollback line for [`Grid::detect_spans`](crate::Grid::detect_spans).
+/// `end_col` is exclusive and belongs to `end_row`, which differs from
+/// `start_row` when the link runs across a soft wrap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminalLink {
+    pub start_row: usize,
+    pub start_col: usize,
+    pub end_row: usize,
+    pub end_col: usize,
+    pub text: String,
+    pub kind: LinkKind,
+}
+
+const URL_SCHEMES: &[&str] = &["https://", "http://", "file://"];
+
+/// Characters that never appear inside a link and so split candidates.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '"' | '\'' | '`' | '<' | '>' | '(' | ')' | '[' | ']' | '{' | '}' | '|'
+        )
+}
+
+/// Find links in one logical line. Ranges are char indices into `line`.
+pub fn find_links(line: &[char]) -> Vec<(Range<usize>, LinkKind)> {
+    let mut links = Vec::new();
+    let mut i = 0;
+    while i < line.len() {
+        if is_delimiter(line[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < line.len() && !is_delimiter(line[i]) {
+            i += 1;
+        }
+        let mut end = i;
+        // Sentence punctuation and the colon compilers put after `file:line:col`
+        while end > start && matches!(line[end - 1], '.' | ',' | ';' | ':' | '!' | '?') {
+            end -= 1;
+        }
+        let token: String = line[start..end].iter().collect();
+        if let Some(kind) = classify(&token) {
+            links.push((start..end, kind));
+        }
+    }
+    links
+}
+
+/// Detect links in rows copied out of a terminal, e.g. with
+/// `VirtualTerminal::viewport_rows`, so the scan can run without holding
+/// whatever guards the terminal. Rows are numbered by their position.
+pub fn detect_links(rows: &[Row]) -> Vec<TerminalLink> {
+    detect_in_rows(rows.iter())
+}
+
+/// Detect links in `rows`, joining each row onto the previous one when it is
+/// a soft-wrapped continuation so links broken across a wrap are found whole.
+/// Rows are numbered by their position in `rows`.
+pub(crate) fn detect_in_rows<'a>(rows: impl Iterator<Item = &'a Row>) -> Vec<TerminalLink> {
+    // (character, row, column, display width) per visible cell
+    let mut lines: Vec<Vec<(char, usize, usize, usize)>> = Vec::new();
+    for (row_idx, row) in rows.enumerate() {
+        let cells = row
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| !cell.wide_spacer)
+            .map(|(col, cell)| (cell.character, row_idx, col, cell.width().max(1)));
+        match lines.last_mut() {
+            Some(line) if !row.is_canonical => line.extend(cells),
+            _ => lines.push(cells.collect()),
+        }
+    }
+
+    let mut links = Vec::new();
+    for line in lines {
+        let chars: Vec<char> = line.iter().map(|cell| cell.0).collect();
+        for (range, kind) in find_links(&chars) {
+            let (_, start_row, start_col, _) = line[range.start];
+            let (_, end_row, last_col, last_width) = line[range.end - 1];
+            links.push(TerminalLink {
+                start_row,
+                start_col,
+                end_row,
+                end_col: last_col + last_width,
+                text: chars[range].iter().collect(),
+                kind,
+            });
+        }
+    }
+    links
+}
+
+fn classify(token: &str) -> Option<LinkKind> {
+    if URL_SCHEMES
+        .iter()
+        .any(|scheme| token.len() > scheme.len() && token.starts_with(scheme))
+    {
+        return Some(LinkKind::Url);
+    }
+    if is_sha(token) {
+        return Some(LinkKind::Sha);
+    }
+
+    let mut parts = token.split(':');
+    let path = parts.next()?;
+    let numbers = parts
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if numbers.len() > 2 || !is_plausible_path(path) {
+        return None;
+    }
+    // Without a line number, only accept things that clearly look like paths
+    if numbers.is_empty() && !path.contains('/') {
+        return None;
+    }
+    Some(LinkKind::File {
+        path: path.to_string(),
+        line: numbers.first().copied(),
+        column: numbers.get(1).copied(),
+    })
+}
+
+/// 7 to 40 lowercase hex digits, as git prints them. Both a digit and a
+/// letter are required so plain numbers and hex-only words like `defaced`
+/// aren't taken for hashes.
+fn is_sha(token: &str) -> bool {
+    (7..=40).contains(&token.len())
+        && token.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+        && token.chars().any(|c| c.is_ascii_digit())
+        && token.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+fn is_plausible_path(path: &str) -> bool {
+    if path.is_empty()
+        || path.starts_with("//")
+        || !path
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | '~' | '@' | '+'))
+    {
+        return false;
+    }
+    // Require an extension with a letter in it, which rules out version
+    // numbers, dates and ratios such as `1.2.3` or `12/05`.
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    match file_name.rsplit_once('.') {
+        Some((stem, ext)) => {
+            !stem.is_empty()
+                && !ext.is_empty()
+                && ext.chars().all(|c| c.is_ascii_alphanumeric())
+                && ext.chars().any(|c| c.is_ascii_alphabetic())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(line: &str) -> Vec<(String, LinkKind)> {
+        let chars: Vec<char> = line.chars().collect();
+        find_links(&chars)
+            .into_iter()
+            .map(|(range, kind)| (chars[range].iter().collect(), kind))
+            .collect()
+    }
+
+    fn file(path: &str, line: Option<u32>, column: Option<u32>) -> LinkKind {
+        LinkKind::File {
+            path: path.to_string(),
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn finds_urls_and_compiler_locations() {
+        assert_eq!(
+            links("  --> src/main.rs:12:5"),
+            vec![(
+                "src/main.rs:12:5".into(),
+                file("src/main.rs", Some(12), Some(5))
+            )]
+        );
+        assert_eq!(
+            links("see (https://example.com/docs?a=1), or app.tsx:40: error"),
+            vec![
+                ("https://example.com/docs?a=1".into(), LinkKind::Url),
+                ("app.tsx:40".into(), file("app.tsx", Some(40), None)),
+            ]
+        );
+        assert_eq!(
+            links("modified: ./packages/sandbox/Cargo.toml."),
+            vec![(
+                "./packages/sandbox/Cargo.toml".into(),
+                file("./packages/sandbox/Cargo.toml", None, None)
+            )]
+        );
+    }
+
+    #[test]
+    fn finds_commit_hashes() {
+        assert_eq!(
+            links("a1b2c3d Fix wrap (cherry picked from 0123456789abcdef0123456789abcdef01234567)"),
+            vec![
+                ("a1b2c3d".into(), LinkKind::Sha),
+                (
+                    "0123456789abcdef0123456789abcdef01234567".into(),
+                    LinkKind::Sha
+                ),
+            ]
+        );
+        assert!(
+            links("defaced 1234567 a1b2c3 A1B2C3D4 a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2c")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn ignores_lookalikes() {
+        assert!(links("version 1.2.3 released 12/05/2024 at 10:30").is_empty());
+        assert!(links("and/or main.rs https:// foo:bar.rs").is_empty());
+    }
+}
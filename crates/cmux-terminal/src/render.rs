@@ -0,0 +1,3 @@
+//! Export of terminal contents to other formats.
+
+pub mod html;
@@ -0,0 +1,298 @@
+//! HTML export of terminal rows.
+//!
+//! Rows become a `<pre>` block with inline styles only, so the output keeps
+//! its colors where stylesheets are stripped, such as PR comments and run
+//! reports. A page has no terminal theme to defer to, so indexed colors are
+//! resolved to RGB through [`HtmlOptions::palette`].
+//!
+//! Double-width lines are stretched horizontally. A double-height pair is
+//! drawn once at twice the font size on its top row, with the bottom row
+//! (which repeats the same text) left out so banners aren't doubled.
+
+use std::ops::Range;
+
+use ratatui::style::{Color, Modifier};
+
+use crate::character::{
+    CharacterStyles, ColorPalette, LineSize, Row, StyleTable, TerminalCharacter,
+};
+use crate::grid::Grid;
+use crate::links::{find_links, LinkKind};
+use crate::terminal::default_palette_color;
+
+/// How [`rows_to_html`] colors and lays out its output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlOptions {
+    /// OSC 4 overrides; unset entries use the xterm defaults.
+    pub palette: ColorPalette,
+    /// Color of cells without an explicit foreground.
+    pub foreground: (u8, u8, u8),
+    /// Color of cells without an explicit background.
+    pub background: (u8, u8, u8),
+    /// Append soft-wrapped rows to the line they continue instead of breaking
+    /// them where the terminal wrapped.
+    pub join_wrapped: bool,
+    /// Wrap `http`, `https` and `file` URLs in links.
+    pub links: bool,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        Self {
+            palette: [None; 256],
+            foreground: (255, 255, 255),
+            background: (0, 0, 0),
+            join_wrapped: false,
+            links: true,
+        }
+    }
+}
+
+impl HtmlOptions {
+    fn rgb(&self, color: Color) -> Option<(u8, u8, u8)> {
+        let index = match color {
+            Color::Reset => return None,
+            Color::Rgb(r, g, b) => return Some((r, g, b)),
+            Color::Indexed(n) => n,
+            Color::Black => 0,
+            Color::Red => 1,
+            Color::Green => 2,
+            Color::Yellow => 3,
+            Color::Blue => 4,
+            Color::Magenta => 5,
+            Color::Cyan => 6,
+            Color::White | Color::Gray => 7,
+            Color::DarkGray => 8,
+            Color::LightRed => 9,
+            Color::LightGreen => 10,
+            Color::LightYellow => 11,
+            Color::LightBlue => 12,
+            Color::LightMagenta => 13,
+            Color::LightCyan => 14,
+        };
+        Some(self.palette[index as usize].unwrap_or_else(|| default_palette_color(index)))
+    }
+
+    /// Inline CSS for a cell; empty for the default style.
+    fn css(&self, styles: &CharacterStyles) -> String {
+        let mut fg = styles.foreground.and_then(|color| self.rgb(color));
+        let mut bg = styles.background.and_then(|color| self.rgb(color));
+        let modifiers = styles.modifiers;
+        if modifiers.contains(Modifier::REVERSED) {
+            (fg, bg) = (
+                Some(bg.unwrap_or(self.background)),
+                Some(fg.unwrap_or(self.foreground)),
+            );
+        }
+        if modifiers.contains(Modifier::HIDDEN) {
+            fg = Some(bg.unwrap_or(self.background));
+        }
+
+        let mut decls = Vec::new();
+        if let Some(fg) = fg {
+            decls.push(format!("color:{}", hex(fg)));
+        }
+        if let Some(bg) = bg {
+            decls.push(format!("background-color:{}", hex(bg)));
+        }
+        if modifiers.contains(Modifier::BOLD) {
+            decls.push("font-weight:bold".to_string());
+        }
+        if modifiers.contains(Modifier::DIM) {
+            decls.push("opacity:0.5".to_string());
+        }
+        if modifiers.contains(Modifier::ITALIC) {
+            decls.push("font-style:italic".to_string());
+        }
+        let mut decorations = Vec::new();
+        if modifiers.contains(Modifier::UNDERLINED) {
+            decorations.push("underline");
+        }
+        if modifiers.contains(Modifier::CROSSED_OUT) {
+            decorations.push("line-through");
+        }
+        if !decorations.is_empty() {
+            decls.push(format!("text-decoration:{}", decorations.join(" ")));
+        }
+        decls.join(";")
+    }
+}
+
+fn hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn push_escaped(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// A hard-wrapped line: its cells and where the terminal soft-wrapped it.
+struct Line<'a> {
+    /// DECDWL/DECDHL size of the line's first row
+    size: LineSize,
+    cells: Vec<&'a TerminalCharacter>,
+    /// Cell indices that start a soft-wrapped row
+    wraps: Vec<usize>,
+}
+
+/// Group rows into hard-wrapped lines so links are found across soft wraps,
+/// trimming trailing blanks as [`VirtualTerminal::capture`](crate::VirtualTerminal::capture) does.
+fn lines<'a>(rows: impl IntoIterator<Item = &'a Row>) -> Vec<Line<'a>> {
+    let mut lines: Vec<Line<'a>> = Vec::new();
+    for row in rows {
+        let cells = row.columns.iter().filter(|cell| !cell.wide_spacer);
+        match lines.last_mut() {
+            Some(line) if !row.is_canonical && row.size == line.size => {
+                line.wraps.push(line.cells.len());
+                line.cells.extend(cells);
+            }
+            _ => lines.push(Line {
+                size: row.size,
+                cells: cells.collect(),
+                wraps: Vec::new(),
+            }),
+        }
+    }
+    for line in &mut lines {
+        while line
+            .cells
+            .last()
+            .is_some_and(|cell| cell.character == ' ' && cell.styles.is_default())
+        {
+            line.cells.pop();
+        }
+        let len = line.cells.len();
+        line.wraps.retain(|&wrap| wrap < len);
+    }
+    lines
+}
+
+fn push_line(out: &mut String, line: &Line, styles: &StyleTable, options: &HtmlOptions) {
+    let chars: Vec<char> = line.cells.iter().map(|cell| cell.character).collect();
+    let links: Vec<Range<usize>> = if options.links {
+        find_links(&chars)
+            .into_iter()
+            .filter(|(_, kind)| *kind == LinkKind::Url)
+            .map(|(range, _)| range)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut links = links.into_iter().peekable();
+    let mut wraps = line.wraps.iter().peekable();
+    // Style of the open span, empty when none is open
+    let mut span = String::new();
+    let mut link_end = None;
+
+    for (i, cell) in line.cells.iter().enumerate() {
+        if wraps.next_if(|&&wrap| wrap == i).is_some() && !options.join_wrapped {
+            out.push('\n');
+        }
+        if link_end == Some(i) {
+            close_span(out, &mut span);
+            out.push_str("</a>");
+            link_end = None;
+        }
+        if let Some(link) = links.next_if(|link| link.start == i) {
+            close_span(out, &mut span);
+            let href: String = chars[link.clone()].iter().collect();
+            out.push_str("<a href=\"");
+            push_escaped(out, &href);
+            out.push_str("\">");
+            link_end = Some(link.end);
+        }
+        let css = options.css(styles.get(cell.styles));
+        if css != span {
+            close_span(out, &mut span);
+            if !css.is_empty() {
+                out.push_str("<span style=\"");
+                out.push_str(&css);
+                out.push_str("\">");
+                span = css;
+            }
+        }
+        push_escaped(out, &cell.grapheme());
+    }
+    close_span(out, &mut span);
+    if link_end.is_some() {
+        out.push_str("</a>");
+    }
+}
+
+fn close_span(out: &mut String, span: &mut String) {
+    if !span.is_empty() {
+        out.push_str("</span>");
+        span.clear();
+    }
+}
+
+/// Render rows as a styled `<pre>` block, resolving cell styles in the
+/// `styles` of the grid holding the rows.
+pub fn rows_to_html<'a>(
+    rows: impl IntoIterator<Item = &'a Row>,
+    styles: &StyleTable,
+    options: &HtmlOptions,
+) -> String {
+    let mut out = format!(
+        "<pre style=\"color:{};background-color:{};font-family:monospace\">",
+        hex(options.foreground),
+        hex(options.background)
+    );
+    let mut previous = None;
+    for line in lines(rows) {
+        let paired = previous == Some(LineSize::DoubleHeightTop);
+        if previous.is_some() && !(paired && line.size == LineSize::DoubleHeightBottom) {
+            out.push('\n');
+        }
+        previous = Some(line.size);
+        match line_size_css(line.size, paired) {
+            // The top half already drew this text
+            None => continue,
+            Some("") => push_line(&mut out, &line, styles, options),
+            Some(css) => {
+                out.push_str("<span style=\"");
+                out.push_str(css);
+                out.push_str("\">");
+                push_line(&mut out, &line, styles, options);
+                out.push_str("</span>");
+            }
+        }
+    }
+    out.push_str("</pre>");
+    out
+}
+
+/// Inline CSS sizing a line, empty for normal lines. `None` for the bottom
+/// half of a double-height pair whose top half came just before.
+fn line_size_css(size: LineSize, after_top_half: bool) -> Option<&'static str> {
+    match size {
+        LineSize::Normal => Some(""),
+        LineSize::DoubleHeightTop => Some("font-size:200%"),
+        LineSize::DoubleHeightBottom if after_top_half => None,
+        LineSize::DoubleWidth | LineSize::DoubleHeightBottom => {
+            Some("display:inline-block;transform:scaleX(2);transform-origin:left")
+        }
+    }
+}
+
+/// Render a range of a grid's rows. Rows count from the oldest scrollback
+/// line, so the first viewport row is `scrollback_len()`; the range is
+/// clamped to the rows the grid has.
+pub fn grid_to_html(grid: &Grid, rows: Range<usize>, options: &HtmlOptions) -> String {
+    let all = grid.lines_above.iter().chain(grid.viewport.iter());
+    rows_to_html(
+        all.skip(rows.start)
+            .take(rows.end.saturating_sub(rows.start)),
+        &grid.style_table,
+        options,
+    )
+}
@@ -0,0 +1,75 @@
+//! Search over scrollback and the active screen.
+//!
+//! Matching runs row by row over the grid's cells, so the UI can highlight
+//! history without dumping the whole buffer to strings on every keystroke.
+//! Plain patterns are escaped and go through the same regex engine as
+//! [`SearchOptions::regex`] ones.
+
+use regex::{Regex, RegexBuilder};
+
+use crate::character::Row;
+
+/// How [`VirtualTerminal::search`](crate::VirtualTerminal::search) interprets
+/// its pattern.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// Treat the pattern as a regular expression instead of literal text.
+    pub regex: bool,
+    /// Ignore case when matching.
+    pub case_insensitive: bool,
+}
+
+/// A match, in cells. `row` counts from the oldest scrollback line, so the
+/// first viewport row is `scrollback_len()`. `col` and `len` are in display
+/// columns, so wide characters count twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub row: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+/// Compile `pattern` according to `options`.
+pub(crate) fn compile(pattern: &str, options: SearchOptions) -> Result<Regex, regex::Error> {
+    let source = if options.regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
+    };
+    RegexBuilder::new(&source)
+        .case_insensitive(options.case_insensitive)
+        .build()
+}
+
+/// Matches in one row, appended to `out`. Empty matches are skipped.
+pub(crate) fn search_row(regex: &Regex, row_index: usize, row: &Row, out: &mut Vec<SearchMatch>) {
+    // Byte offset in `text` -> display column of the cell it came from
+    let mut text = String::with_capacity(row.len());
+    let mut columns = Vec::with_capacity(row.len() + 1);
+    let mut col = 0;
+    for cell in &row.columns {
+        if cell.wide_spacer {
+            continue;
+        }
+        columns.push((text.len(), col));
+        cell.push_grapheme(&mut text);
+        col += cell.width().max(1);
+    }
+    columns.push((text.len(), col));
+
+    let column_at = |byte: usize| {
+        let idx = columns.partition_point(|&(offset, _)| offset < byte);
+        columns[idx.min(columns.len() - 1)].1
+    };
+    for found in regex.find_iter(&text) {
+        if found.is_empty() {
+            continue;
+        }
+        let start = column_at(found.start());
+        out.push(SearchMatch {
+            row: row_index,
+            col: start,
+            len: column_at(found.end()) - start,
+        });
+    }
+}
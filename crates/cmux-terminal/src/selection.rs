@@ -0,0 +1,341 @@
+//! Text selection over scrollback and the active screen.
+//!
+//! Frontends report where a drag started and where it is now, in cells. The
+//! grid grows that to whole words or lines as the [`SelectionMode`] asks,
+//! says which cells are selected for highlighting, and extracts the text the
+//! way a copy should see it: wide characters and grapheme clusters once,
+//! soft-wrapped rows rejoined, trailing blanks dropped. Rows count from the
+//! oldest scrollback line, as in [`SearchMatch`](crate::SearchMatch).
+
+use crate::character::TerminalCharacter;
+use crate::grid::Grid;
+
+/// Characters that end a word besides whitespace, so selecting a word in
+/// `(src/main.rs:3)` takes the path alone.
+const WORD_SEPARATORS: &str = "()[]{}<>'\"`,;|&";
+
+/// How a selection grows from the cells it was given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SelectionMode {
+    /// Cell by cell, as with a plain drag
+    #[default]
+    Char,
+    /// Whole words, as with a double-click
+    Word,
+    /// Whole lines, following soft wraps, as with a triple-click
+    Line,
+    /// The rectangle between the two corners
+    Block,
+}
+
+/// A cell; `row` counts from the oldest scrollback line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SelectionPoint {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl SelectionPoint {
+    pub fn new(row: usize, col: usize) -> Self {
+        Self { row, col }
+    }
+}
+
+/// The selection of a [`Grid`], with the cells it covers once expanded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    /// Where the selection started
+    pub anchor: SelectionPoint,
+    /// The end that moves as the selection is extended
+    pub head: SelectionPoint,
+    pub mode: SelectionMode,
+    /// First selected cell, or the top-left corner of a block
+    pub start: SelectionPoint,
+    /// Last selected cell, or the bottom-right corner of a block
+    pub end: SelectionPoint,
+}
+
+impl Selection {
+    pub(crate) fn new(
+        grid: &Grid,
+        anchor: SelectionPoint,
+        head: SelectionPoint,
+        mode: SelectionMode,
+    ) -> Self {
+        let (first, last) = (anchor.min(head), anchor.max(head));
+        let (start, end) = match mode {
+            SelectionMode::Char => (cell_start(grid, first), cell_end(grid, last)),
+            SelectionMode::Word => (word_start(grid, first), word_end(grid, last)),
+            SelectionMode::Line => (
+                SelectionPoint::new(logical_line(grid, first.row).0, 0),
+                SelectionPoint::new(logical_line(grid, last.row).1, grid.cols.saturating_sub(1)),
+            ),
+            SelectionMode::Block => (
+                SelectionPoint::new(first.row, anchor.col.min(head.col)),
+                SelectionPoint::new(last.row, anchor.col.max(head.col)),
+            ),
+        };
+        Self {
+            anchor,
+            head,
+            mode,
+            start,
+            end,
+        }
+    }
+
+    /// Whether the cell at `row`, `col` is selected.
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        if self.mode == SelectionMode::Block {
+            (self.start.row..=self.end.row).contains(&row)
+                && (self.start.col..=self.end.col).contains(&col)
+        } else {
+            (self.start..=self.end).contains(&SelectionPoint::new(row, col))
+        }
+    }
+
+    /// The same selection after the `count` oldest rows were dropped, or
+    /// `None` once part of it went with them.
+    pub(crate) fn scrolled(self, count: usize) -> Option<Self> {
+        let up = |point: SelectionPoint| {
+            point
+                .row
+                .checked_sub(count)
+                .map(|row| SelectionPoint { row, ..point })
+        };
+        Some(Self {
+            anchor: up(self.anchor)?,
+            head: up(self.head)?,
+            start: up(self.start)?,
+            end: up(self.end)?,
+            ..self
+        })
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum CharClass {
+    Space,
+    Separator,
+    Word,
+}
+
+fn class(cell: Option<&TerminalCharacter>) -> CharClass {
+    match cell.map(|cell| cell.character) {
+        None => CharClass::Space,
+        Some(c) if c.is_whitespace() => CharClass::Space,
+        Some(c) if WORD_SEPARATORS.contains(c) => CharClass::Separator,
+        Some(_) => CharClass::Word,
+    }
+}
+
+fn cell_at(grid: &Grid, point: SelectionPoint) -> Option<&TerminalCharacter> {
+    grid.row_at(point.row)?.get(point.col)
+}
+
+/// Step left off the spacer half of a wide character.
+fn cell_start(grid: &Grid, point: SelectionPoint) -> SelectionPoint {
+    match cell_at(grid, point) {
+        Some(cell) if cell.wide_spacer && point.col > 0 => {
+            SelectionPoint::new(point.row, point.col - 1)
+        }
+        _ => point,
+    }
+}
+
+/// Step right onto the spacer half of a wide character.
+fn cell_end(grid: &Grid, point: SelectionPoint) -> SelectionPoint {
+    match cell_at(grid, point) {
+        Some(cell) if cell.is_wide() => SelectionPoint::new(point.row, point.col + 1),
+        _ => point,
+    }
+}
+
+/// The cell before `point`, continuing onto the row a soft wrap came from.
+fn prev_cell(grid: &Grid, point: SelectionPoint) -> Option<SelectionPoint> {
+    if point.col > 0 {
+        return Some(cell_start(
+            grid,
+            SelectionPoint::new(point.row, point.col - 1),
+        ));
+    }
+    let wrapped = grid.row_at(point.row).is_some_and(|row| !row.is_canonical);
+    let above = point.row.checked_sub(1).filter(|_| wrapped)?;
+    let len = grid.row_at(above)?.len();
+    Some(cell_start(
+        grid,
+        SelectionPoint::new(above, len.checked_sub(1)?),
+    ))
+}
+
+/// The cell after `point` (past a wide character's spacer), continuing onto
+/// the row `point`'s row soft-wraps into.
+fn next_cell(grid: &Grid, point: SelectionPoint) -> Option<SelectionPoint> {
+    let row = grid.row_at(point.row)?;
+    let col = point.col + cell_at(grid, point).map_or(1, |cell| cell.width().max(1));
+    if col < row.len() {
+        return Some(SelectionPoint::new(point.row, col));
+    }
+    let below = grid.row_at(point.row + 1)?;
+    (!below.is_canonical).then(|| SelectionPoint::new(point.row + 1, 0))
+}
+
+fn word_start(grid: &Grid, point: SelectionPoint) -> SelectionPoint {
+    let mut point = cell_start(grid, point);
+    let kind = class(cell_at(grid, point));
+    if kind == CharClass::Separator {
+        return point;
+    }
+    while let Some(prev) = prev_cell(grid, point) {
+        if class(cell_at(grid, prev)) != kind {
+            break;
+        }
+        point = prev;
+    }
+    point
+}
+
+fn word_end(grid: &Grid, point: SelectionPoint) -> SelectionPoint {
+    let mut point = cell_start(grid, point);
+    let kind = class(cell_at(grid, point));
+    if kind != CharClass::Separator {
+        while let Some(next) = next_cell(grid, point) {
+            if class(cell_at(grid, next)) != kind {
+                break;
+            }
+            point = next;
+        }
+    }
+    cell_end(grid, point)
+}
+
+/// First and last row of the logical line `row` belongs to.
+fn logical_line(grid: &Grid, row: usize) -> (usize, usize) {
+    let mut top = row;
+    while top > 0 && grid.row_at(top).is_some_and(|r| !r.is_canonical) {
+        top -= 1;
+    }
+    let mut bottom = row;
+    while grid.row_at(bottom + 1).is_some_and(|r| !r.is_canonical) {
+        bottom += 1;
+    }
+    (top, bottom)
+}
+
+/// The selected text. Rows end with `\n` unless they soft-wrap into the
+/// next one, except in block selections, where every row is its own line.
+pub(crate) fn selected_text(grid: &Grid, selection: &Selection) -> String {
+    let (start, end) = (selection.start, selection.end);
+    let block = selection.mode == SelectionMode::Block;
+    let mut out = String::new();
+    for row_index in start.row..=end.row {
+        let Some(row) = grid.row_at(row_index) else {
+            break;
+        };
+        let (from, to) = if block {
+            (start.col, end.col)
+        } else {
+            (
+                if row_index == start.row { start.col } else { 0 },
+                if row_index == end.row {
+                    end.col
+                } else {
+                    usize::MAX
+                },
+            )
+        };
+        let line_start = out.len();
+        for (col, cell) in row.columns.iter().enumerate() {
+            let last_col = col + cell.width().max(1) - 1;
+            if !cell.wide_spacer && col <= to && last_col >= from {
+                cell.push_grapheme(&mut out);
+            }
+        }
+        let joined = !block
+            && row_index < end.row
+            && grid
+                .row_at(row_index + 1)
+                .is_some_and(|next| !next.is_canonical);
+        if !joined {
+            let kept = out[line_start..].trim_end_matches(' ').len();
+            out.truncate(line_start + kept);
+            if row_index < end.row {
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VirtualTerminal;
+
+    fn select(
+        term: &mut VirtualTerminal,
+        from: (usize, usize),
+        to: (usize, usize),
+        mode: SelectionMode,
+    ) -> String {
+        term.start_selection(SelectionPoint::new(from.0, from.1), mode);
+        term.extend_selection(SelectionPoint::new(to.0, to.1));
+        term.selected_text().unwrap()
+    }
+
+    #[test]
+    fn char_and_word_selection_handle_wide_chars_and_wraps() {
+        let mut term = VirtualTerminal::new(4, 10);
+        // "see (src/main.rs)" wraps after "see (src/m"
+        term.process("see (src/main.rs) 日本語\r\nnext  \r\n".as_bytes());
+        assert_eq!(term.get_lines()[..2], ["see (src/m", "ain.rs) 日"]);
+
+        // Ending on the spacer half of 日 still takes the whole character
+        assert_eq!(
+            select(&mut term, (0, 5), (1, 9), SelectionMode::Char),
+            "src/main.rs) 日"
+        );
+        // Backwards drags select the same cells
+        assert_eq!(
+            select(&mut term, (1, 2), (0, 8), SelectionMode::Char),
+            "/main"
+        );
+        // Words stop at separators and follow the soft wrap
+        assert_eq!(
+            select(&mut term, (1, 1), (1, 1), SelectionMode::Word),
+            "src/main.rs"
+        );
+        assert_eq!(select(&mut term, (0, 4), (0, 4), SelectionMode::Word), "(");
+        assert_eq!(
+            select(&mut term, (2, 1), (2, 1), SelectionMode::Word),
+            "日本語"
+        );
+        let selection = *term.grid().selection().unwrap();
+        assert_eq!(
+            (selection.start, selection.end),
+            (SelectionPoint::new(1, 8), SelectionPoint::new(2, 3))
+        );
+        assert!(selection.contains(1, 9) && !selection.contains(1, 7));
+    }
+
+    #[test]
+    fn line_and_block_selection() {
+        let mut term = VirtualTerminal::new(4, 10);
+        term.process(b"first line wraps\r\nab  cd\r\nxyz");
+
+        assert_eq!(
+            select(&mut term, (1, 3), (2, 0), SelectionMode::Line),
+            "first line wraps\nab  cd"
+        );
+        // Every row is its own line, trimmed, even across the wrap
+        assert_eq!(
+            select(&mut term, (0, 1), (3, 3), SelectionMode::Block),
+            "irs\nwra\nb\nyz"
+        );
+        let selection = *term.grid().selection().unwrap();
+        assert!(selection.contains(2, 2) && !selection.contains(2, 4));
+
+        term.clear_selection();
+        assert!(term.selected_text().is_none());
+    }
+}
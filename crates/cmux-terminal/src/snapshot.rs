@@ -0,0 +1,320 @@
+//! Serializable checkpoints of a [`VirtualTerminal`](crate::VirtualTerminal).
+//!
+//! A snapshot holds what is needed to resume a session in another process:
+//! every screen row including scrollback, the cursor, the main screen saved
+//! behind an active alternate screen, and the modes the application set.
+//! Styles are stored once and referenced by index from each cell.
+//!
+//! Parser state is not kept, so a restored terminal starts between escape
+//! sequences, as a clone does. Undrained responses and the bell and
+//! alternate-screen UI flags are not kept either, nor are inline images.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::c1::C1Mode;
+use crate::character::{
+    AmbiguousWidth, CharacterStyles, LineSize, Row, StyleId, StyleTable, TerminalCharacter,
+};
+use crate::commands::CommandLog;
+use crate::encoding::InputEncoding;
+use crate::grid::Grid;
+use crate::keyboard::KeyboardFlagStack;
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Checkpoint taken by [`VirtualTerminal::snapshot`](crate::VirtualTerminal::snapshot)
+/// and resumed with [`VirtualTerminal::restore`](crate::VirtualTerminal::restore).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TerminalSnapshot {
+    /// Distinct styles; index 0 is always the default style.
+    pub(crate) styles: Vec<CharacterStyles>,
+    pub(crate) screen: GridSnapshot,
+    /// Main screen while the alternate screen is active
+    pub(crate) primary: Option<PrimaryScreenSnapshot>,
+    pub(crate) saved_cursor: Option<SavedCursorSnapshot>,
+    pub(crate) max_scrollback: usize,
+    pub(crate) cursor_visible: bool,
+    pub(crate) cursor_blink: bool,
+    pub(crate) cursor_style: u8,
+    pub(crate) insert_mode: bool,
+    pub(crate) origin_mode: bool,
+    pub(crate) auto_wrap: bool,
+    pub(crate) pending_wrap: bool,
+    pub(crate) newline_mode: bool,
+    pub(crate) reverse_wraparound: bool,
+    pub(crate) enable_left_right_margins: bool,
+    pub(crate) tab_stops: Vec<usize>,
+    pub(crate) charsets: CharsetSnapshot,
+    pub(crate) application_cursor_keys: bool,
+    pub(crate) application_keypad: bool,
+    pub(crate) bracketed_paste: bool,
+    pub(crate) mouse_tracking: Option<u16>,
+    pub(crate) sgr_mouse_mode: bool,
+    pub(crate) focus_events: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub(crate) keyboard_flags: KeyboardFlagStack,
+    pub(crate) c1_mode: C1Mode,
+    pub(crate) title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) icon_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cwd: Option<PathBuf>,
+    pub(crate) default_fg_color: Option<(u8, u8, u8)>,
+    pub(crate) default_bg_color: Option<(u8, u8, u8)>,
+    pub(crate) cursor_color: Option<(u8, u8, u8)>,
+    /// OSC 4 overrides as `(index, rgb)`
+    pub(crate) palette: Vec<(u8, (u8, u8, u8))>,
+    pub(crate) last_printed_char: Option<char>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub(crate) ambiguous_width: AmbiguousWidth,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub(crate) input_encoding: InputEncoding,
+}
+
+impl TerminalSnapshot {
+    /// Viewport size as `(rows, cols)`.
+    pub fn size(&self) -> (usize, usize) {
+        (self.screen.rows, self.screen.cols)
+    }
+}
+
+/// Selected charset and the line-drawing flags of G0 and G1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CharsetSnapshot {
+    pub(crate) index: usize,
+    pub(crate) g0_line_drawing: bool,
+    pub(crate) g1_line_drawing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SavedCursorSnapshot {
+    pub(crate) row: usize,
+    pub(crate) col: usize,
+    pub(crate) style: u32,
+    pub(crate) origin_mode: bool,
+    pub(crate) auto_wrap: bool,
+    pub(crate) pending_wrap: bool,
+    pub(crate) charsets: CharsetSnapshot,
+}
+
+/// Main-screen state parked while the alternate screen is shown.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PrimaryScreenSnapshot {
+    pub(crate) grid: GridSnapshot,
+    pub(crate) origin_mode: bool,
+    pub(crate) auto_wrap: bool,
+    pub(crate) pending_wrap: bool,
+    pub(crate) cursor_visible: bool,
+    pub(crate) cursor_blink: bool,
+    pub(crate) charsets: CharsetSnapshot,
+    pub(crate) saved_cursor: Option<SavedCursorSnapshot>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub(crate) keyboard_flags: KeyboardFlagStack,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GridSnapshot {
+    rows: usize,
+    cols: usize,
+    lines_above: Vec<RowSnapshot>,
+    viewport: Vec<RowSnapshot>,
+    lines_below: Vec<RowSnapshot>,
+    cursor_row: usize,
+    cursor_col: usize,
+    style: u32,
+    scroll_region: (usize, usize),
+    left_margin: usize,
+    right_margin: usize,
+    #[serde(default, skip_serializing_if = "is_default")]
+    commands: CommandLog,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RowSnapshot {
+    cells: Vec<CellSnapshot>,
+    /// Soft-wrapped continuation of the row above
+    #[serde(default, skip_serializing_if = "is_default")]
+    wrapped: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    size: LineSize,
+    /// First write, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    written_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CellSnapshot {
+    c: char,
+    #[serde(default, skip_serializing_if = "is_default")]
+    style: u32,
+    width: u8,
+    #[serde(default, skip_serializing_if = "is_default")]
+    spacer: bool,
+    /// Rest of the grapheme cluster after `c`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    combining: Option<String>,
+}
+
+/// Assigns each distinct style an index while a snapshot is built.
+#[derive(Default)]
+pub(crate) struct StyleEncoder {
+    styles: Vec<CharacterStyles>,
+    index: HashMap<CharacterStyles, u32>,
+}
+
+impl StyleEncoder {
+    pub(crate) fn new() -> Self {
+        let mut encoder = Self::default();
+        encoder.encode(&CharacterStyles::default());
+        encoder
+    }
+
+    pub(crate) fn encode(&mut self, styles: &CharacterStyles) -> u32 {
+        if let Some(&index) = self.index.get(styles) {
+            return index;
+        }
+        let index = self.styles.len() as u32;
+        self.styles.push(*styles);
+        self.index.insert(*styles, index);
+        index
+    }
+
+    pub(crate) fn finish(self) -> Vec<CharacterStyles> {
+        self.styles
+    }
+
+    fn encode_row(&mut self, row: &Row, styles: &StyleTable) -> RowSnapshot {
+        RowSnapshot {
+            cells: row
+                .columns
+                .iter()
+                .map(|cell| CellSnapshot {
+                    c: cell.character,
+                    style: self.encode(styles.get(cell.styles)),
+                    width: cell.width() as u8,
+                    spacer: cell.wide_spacer,
+                    combining: cell.combining().map(|tail| tail.to_string()),
+                })
+                .collect(),
+            wrapped: !row.is_canonical,
+            size: row.size,
+            written_at: row
+                .written_at
+                .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_millis() as u64),
+        }
+    }
+
+    pub(crate) fn encode_grid(&mut self, grid: &Grid) -> GridSnapshot {
+        GridSnapshot {
+            rows: grid.rows,
+            cols: grid.cols,
+            lines_above: grid
+                .lines_above
+                .iter()
+                .map(|r| self.encode_row(r, &grid.style_table))
+                .collect(),
+            viewport: grid
+                .viewport
+                .iter()
+                .map(|r| self.encode_row(r, &grid.style_table))
+                .collect(),
+            lines_below: grid
+                .lines_below
+                .iter()
+                .map(|r| self.encode_row(r, &grid.style_table))
+                .collect(),
+            cursor_row: grid.cursor_row,
+            cursor_col: grid.cursor_col,
+            style: self.encode(&grid.current_styles),
+            scroll_region: grid.scroll_region,
+            left_margin: grid.left_margin,
+            right_margin: grid.right_margin,
+            commands: grid.commands.clone(),
+        }
+    }
+}
+
+/// Style table of a snapshot being restored. Unknown indices fall back to
+/// the default style rather than failing the restore.
+pub(crate) struct StyleDecoder<'a> {
+    styles: &'a [CharacterStyles],
+}
+
+impl<'a> StyleDecoder<'a> {
+    pub(crate) fn new(styles: &'a [CharacterStyles]) -> Self {
+        Self { styles }
+    }
+
+    pub(crate) fn decode(&self, index: u32) -> CharacterStyles {
+        self.styles.get(index as usize).copied().unwrap_or_default()
+    }
+
+    fn decode_row(&self, row: RowSnapshot, shared: &[StyleId]) -> Row {
+        let mut restored = Row::with_capacity(row.cells.len());
+        restored.columns.extend(row.cells.into_iter().map(|cell| {
+            let styles = shared.get(cell.style as usize).copied().unwrap_or_default();
+            let mut restored = TerminalCharacter::with_width(cell.c, styles, cell.width);
+            if let Some(tail) = cell.combining {
+                restored.set_grapheme(&format!("{}{tail}", cell.c), cell.width);
+            }
+            restored.wide_spacer = cell.spacer;
+            restored
+        }));
+        restored.is_canonical = !row.wrapped;
+        restored.size = row.size;
+        restored.written_at = row
+            .written_at
+            .map(|millis| UNIX_EPOCH + Duration::from_millis(millis));
+        restored
+    }
+
+    /// Rebuild a grid, clamping the cursor, scroll region and margins to its
+    /// size so a damaged snapshot cannot leave them out of bounds.
+    pub(crate) fn decode_grid(&self, snapshot: GridSnapshot) -> Grid {
+        let rows = snapshot.rows.max(1);
+        let cols = snapshot.cols.max(1);
+        let mut grid = Grid::new(rows, cols);
+        let shared: Vec<StyleId> = self
+            .styles
+            .iter()
+            .map(|styles| grid.style_table.intern(*styles))
+            .collect();
+
+        grid.lines_above = snapshot
+            .lines_above
+            .into_iter()
+            .map(|row| self.decode_row(row, &shared))
+            .collect();
+        grid.viewport = snapshot
+            .viewport
+            .into_iter()
+            .map(|row| self.decode_row(row, &shared))
+            .collect();
+        grid.viewport.resize_with(rows, || Row::filled(cols));
+        grid.lines_below = snapshot
+            .lines_below
+            .into_iter()
+            .map(|row| self.decode_row(row, &shared))
+            .collect();
+
+        grid.cursor_row = snapshot.cursor_row.min(rows - 1);
+        grid.cursor_col = snapshot.cursor_col.min(cols - 1);
+        grid.set_current_styles(self.decode(snapshot.style));
+        let (top, bottom) = snapshot.scroll_region;
+        let bottom = bottom.min(rows - 1);
+        grid.scroll_region = (top.min(bottom), bottom);
+        let right = snapshot.right_margin.min(cols - 1);
+        grid.left_margin = snapshot.left_margin.min(right);
+        grid.right_margin = right;
+        grid.commands = snapshot.commands;
+        grid.mark_all_changed();
+        grid
+    }
+}
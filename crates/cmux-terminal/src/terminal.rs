@@ -3,11 +3,42 @@
 //! This module provides a complete terminal emulator that can parse and execute
 //! ANSI escape sequences, maintain cursor state, handle scrollback, and more.
 
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
 use ratatui::style::{Color, Modifier, Style};
+use unicode_segmentation::UnicodeSegmentation;
 use vte::{Params, Parser, Perform};
 
-use crate::character::{CharacterStyles, Row, TerminalCharacter};
+use crate::c1::{C1Decoder, C1Mode};
+use crate::character::{
+    AmbiguousWidth, CharacterStyles, LineSize, Row, StyleStats, StyleTable, TerminalCharacter,
+    MAX_GRAPHEME_BYTES,
+};
+use crate::commands::CommandBlock;
+use crate::encoding::{decode_cp437, InputEncoding};
 use crate::grid::Grid;
+use crate::image::{parse_iterm2_file, InlineImage, PendingImage, SixelDecoder};
+use crate::keyboard::KeyboardFlagStack;
+use crate::links::{self, TerminalLink};
+use crate::render::html::{self, HtmlOptions};
+use crate::search::{self, SearchMatch, SearchOptions};
+use crate::selection::{SelectionMode, SelectionPoint};
+use crate::snapshot::{
+    CharsetSnapshot, PrimaryScreenSnapshot, SavedCursorSnapshot, StyleDecoder, StyleEncoder,
+    TerminalSnapshot,
+};
+
+/// Longest DCS payload buffered for a request (DECRQSS selectors are a few bytes).
+const MAX_DCS_DATA: usize = 256;
+
+/// OSC 133 marks kept for the embedder before the oldest are dropped.
+const MAX_SEMANTIC_MARKS: usize = 256;
+
+/// Input bytes [`VirtualTerminal::process_chunked`] processes between budget checks
+const CHUNK_STEP: usize = 1024;
 
 /// Default foreground color for OSC 10 queries when no color is set.
 /// Subpixel values used for xterm-style scaling.
@@ -40,39 +71,156 @@ impl Default for Cell {
     }
 }
 
-impl From<&TerminalCharacter> for Cell {
-    fn from(tc: &TerminalCharacter) -> Self {
+impl Cell {
+    /// Convert a grid cell, resolving its style in the grid's `styles`.
+    pub fn from_character(tc: &TerminalCharacter, styles: &StyleTable) -> Self {
         Cell {
             c: tc.character,
-            style: tc.styles.to_ratatui_style(),
+            style: styles.to_ratatui_style(tc.styles),
             wide_spacer: tc.wide_spacer,
         }
     }
 }
 
+/// Which mouse events the application asked to receive (DECSET 1000/1002/1003).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MouseTracking {
+    #[default]
+    Off,
+    /// 1000 - button presses and releases
+    Click,
+    /// 1002 - also motion while a button is held
+    Drag,
+    /// 1003 - all motion
+    Motion,
+}
+
+impl MouseTracking {
+    fn from_mode(mode: Option<u16>) -> Self {
+        match mode {
+            Some(1000) => MouseTracking::Click,
+            Some(1002) => MouseTracking::Drag,
+            Some(1003) => MouseTracking::Motion,
+            _ => MouseTracking::Off,
+        }
+    }
+}
+
+/// Input-related DEC private modes, so the host knows how to encode what it
+/// forwards to the application.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TerminalModes {
+    /// 2004 - wrap pastes in `ESC [200~` / `ESC [201~`
+    pub bracketed_paste: bool,
+    pub mouse_tracking: MouseTracking,
+    /// 1006 - encode mouse events as SGR (`ESC [<b;x;yM`)
+    pub sgr_mouse: bool,
+    /// 1004 - send `ESC [I` / `ESC [O` on focus changes
+    pub focus_events: bool,
+    /// Kitty keyboard protocol flags (`CSI > flags u`): 1 disambiguate
+    /// escape codes, 2 report event types, 4 report alternate keys, 8 report
+    /// all keys as escape codes, 16 report associated text. 0 means legacy
+    /// encoding.
+    pub keyboard_flags: u8,
+}
+
+/// Cursor shapes selectable with DECSCUSR (`CSI Ps SP q`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+/// How the application wants the cursor drawn: DECSCUSR picks the shape and
+/// whether it blinks (as does mode 12), DECTCEM (mode 25) shows or hides it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+    pub visible: bool,
+}
+
+/// Shell integration marks (OSC 133) splitting output into prompt, input
+/// and command-output zones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SemanticMarkKind {
+    /// `A` - a prompt is about to be drawn
+    PromptStart,
+    /// `B` - the prompt is done; user input follows
+    InputStart,
+    /// `C` - the input was submitted; command output follows
+    OutputStart,
+    /// `D` - the command finished, with its exit status when reported
+    CommandFinished { exit_code: Option<i32> },
+}
+
+/// An OSC 133 mark and the line the cursor was on when it arrived, numbered
+/// like [`VirtualTerminal::get_lines`]: scrollback first, then the viewport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SemanticMark {
+    pub kind: SemanticMarkKind,
+    pub line: usize,
+}
+
+/// A color applications can redefine at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSlot {
+    /// Entry of the 256-color palette (OSC 4)
+    Palette(u8),
+    /// Default foreground (OSC 10)
+    Foreground,
+    /// Default background (OSC 11)
+    Background,
+    /// Cursor (OSC 12)
+    Cursor,
+}
+
+/// A color the application redefined with OSC 4, 5 or 10-12, or reset with
+/// OSC 104-112 or RIS. `color` is `None` once the slot is back to the
+/// terminal's default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorChange {
+    pub slot: ColorSlot,
+    pub color: Option<(u8, u8, u8)>,
+}
+
 /// Line drawing character mapping (DEC Special Graphics)
 fn line_drawing_char(c: char) -> char {
     match c {
-        'j' => '┘', // Lower right corner
-        'k' => '┐', // Upper right corner
-        'l' => '┌', // Upper left corner
-        'm' => '└', // Lower left corner
-        'n' => '┼', // Crossing lines
-        'q' => '─', // Horizontal line
-        't' => '├', // Left tee
-        'u' => '┤', // Right tee
-        'v' => '┴', // Bottom tee
-        'w' => '┬', // Top tee
-        'x' => '│', // Vertical line
-        'a' => '▒', // Checker board
-        'f' => '°', // Degree symbol
-        'g' => '±', // Plus/minus
-        'y' => '≤', // Less than or equal
-        'z' => '≥', // Greater than or equal
-        '{' => 'π', // Pi
-        '|' => '≠', // Not equal
-        '}' => '£', // Pound sign
-        '~' => '·', // Middle dot
+        '_' => '\u{a0}', // Blank
+        '`' => '◆',      // Diamond
+        'a' => '▒',      // Checker board
+        'b' => '␉',      // HT symbol
+        'c' => '␌',      // FF symbol
+        'd' => '␍',      // CR symbol
+        'e' => '␊',      // LF symbol
+        'f' => '°',      // Degree symbol
+        'g' => '±',      // Plus/minus
+        'h' => '␤',      // NL symbol
+        'i' => '␋',      // VT symbol
+        'j' => '┘',      // Lower right corner
+        'k' => '┐',      // Upper right corner
+        'l' => '┌',      // Upper left corner
+        'm' => '└',      // Lower left corner
+        'n' => '┼',      // Crossing lines
+        'o' => '⎺',      // Scan line 1
+        'p' => '⎻',      // Scan line 3
+        'q' => '─',      // Horizontal line (scan line 5)
+        'r' => '⎼',      // Scan line 7
+        's' => '⎽',      // Scan line 9
+        't' => '├',      // Left tee
+        'u' => '┤',      // Right tee
+        'v' => '┴',      // Bottom tee
+        'w' => '┬',      // Top tee
+        'x' => '│',      // Vertical line
+        'y' => '≤',      // Less than or equal
+        'z' => '≥',      // Greater than or equal
+        '{' => 'π',      // Pi
+        '|' => '≠',      // Not equal
+        '}' => '£',      // Pound sign
+        '~' => '·',      // Middle dot
         _ => c,
     }
 }
@@ -562,7 +710,7 @@ fn parse_osc_color(s: &str) -> Option<(u8, u8, u8)> {
 
 /// Get the default color for a 256-color palette index.
 /// Returns (R, G, B) as 8-bit values.
-fn default_palette_color(index: u8) -> (u8, u8, u8) {
+pub(crate) fn default_palette_color(index: u8) -> (u8, u8, u8) {
     match index {
         // Standard ANSI colors (0-7)
         0 => (0, 0, 0),       // Black
@@ -641,14 +789,32 @@ pub struct VirtualTerminal {
     pub mouse_tracking: Option<u16>,
     /// SGR extended mouse mode (1006) - affects encoding of mouse events
     pub sgr_mouse_mode: bool,
+    /// Focus event reporting (1004)
+    pub focus_events: bool,
+    /// Kitty keyboard protocol flags of the current screen
+    keyboard_flags: KeyboardFlagStack,
     /// Bell triggered flag (for UI notification)
     pub bell_pending: bool,
     /// Window title (set via OSC)
     pub title: Option<String>,
+    /// Icon name (OSC 0 and 1), which terminals show on minimized windows
+    icon_name: Option<String>,
+    /// Called when the window title changes
+    title_callback: Callback<str>,
+    /// Called when the icon name changes
+    icon_name_callback: Callback<str>,
+    /// Working directory the shell last reported (OSC 7)
+    cwd: Option<PathBuf>,
+    /// Called when the reported working directory changes
+    cwd_callback: Callback<Path>,
     /// Last printed character (for REP - repeat)
     last_printed_char: Option<char>,
     /// Pending responses to send back to the PTY (e.g., DSR cursor position report)
     pub pending_responses: Vec<Vec<u8>>,
+    /// Receives responses as they are generated instead of `pending_responses`
+    query_responder: Callback<[u8]>,
+    /// OSC 133 marks not yet drained by the embedder
+    semantic_marks: Vec<SemanticMark>,
     /// Default foreground color (OSC 10) - None means use terminal's native color
     pub default_fg_color: Option<(u8, u8, u8)>,
     /// Default background color (OSC 11) - None means use terminal's native color
@@ -657,6 +823,8 @@ pub struct VirtualTerminal {
     pub cursor_color: Option<(u8, u8, u8)>,
     /// 256-color palette (OSC 4) - stores custom colors, None means use default
     color_palette: [Option<(u8, u8, u8)>; 256],
+    /// Called when an OSC sequence changes a palette or dynamic color
+    color_change_callback: Callback<ColorChange>,
     /// Flag to signal alt screen was entered/exited (for UI to reset scroll state)
     pub alt_screen_toggled: bool,
     /// DECLRMM - Left/Right Margin Mode (mode 69)
@@ -674,6 +842,178 @@ pub struct VirtualTerminal {
     dcs_handler: DcsHandler,
     /// DCS data buffer - accumulates bytes during DCS sequence
     dcs_data: Vec<u8>,
+    /// Pixel size of one cell as `(width, height)`, used to size images
+    cell_pixel_size: (u16, u16),
+    /// Width of East Asian Ambiguous characters
+    ambiguous_width: AmbiguousWidth,
+    /// Id given to the next inline image
+    next_image_id: u64,
+    /// 8-bit C1 control normalization applied before parsing
+    c1_decoder: C1Decoder,
+    /// How input bytes are decoded into characters
+    input_encoding: InputEncoding,
+    /// Parser state carried across `process` calls
+    parser: StreamParser,
+    /// Characters printed so far, for [`ProcessBudget::max_cells`]
+    cells_printed: usize,
+}
+
+/// How much one [`VirtualTerminal::process_chunked`] call may do before it
+/// hands back a [`Continuation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessBudget {
+    /// Input bytes to consume
+    pub max_bytes: usize,
+    /// Characters to print; plain text is where a burst spends its time
+    pub max_cells: usize,
+}
+
+impl Default for ProcessBudget {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024,
+            max_cells: 16 * 1024,
+        }
+    }
+}
+
+/// Input a [`VirtualTerminal::process_chunked`] call ran out of budget for.
+#[must_use = "the rest of the input is only processed by resuming"]
+#[derive(Debug)]
+pub struct Continuation<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Continuation<'a> {
+    /// Bytes not yet processed.
+    pub fn remaining(&self) -> usize {
+        self.rest.len()
+    }
+
+    /// Process the next slice of the input, as the call that returned this did.
+    pub fn resume(
+        self,
+        terminal: &mut VirtualTerminal,
+        budget: ProcessBudget,
+    ) -> Option<Continuation<'a>> {
+        terminal.process_chunked(self.rest, budget)
+    }
+}
+
+/// VTE parser kept between `process` calls so an escape sequence split
+/// across two PTY reads is still recognised.
+#[derive(Default)]
+struct StreamParser(Parser);
+
+impl Clone for StreamParser {
+    /// `vte::Parser` is not `Clone`; a clone starts between sequences.
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl std::fmt::Debug for StreamParser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StreamParser")
+    }
+}
+
+type CallbackFn<A> = dyn Fn(&A) + Send + Sync;
+
+/// Embedder hook run when the application changes some reported state,
+/// such as the title or working directory. Clones share the hook.
+struct Callback<A: ?Sized>(Option<Arc<CallbackFn<A>>>);
+
+impl<A: ?Sized> Callback<A> {
+    fn set(&mut self, callback: impl Fn(&A) + Send + Sync + 'static) {
+        self.0 = Some(Arc::new(callback));
+    }
+
+    fn call(&self, value: &A) {
+        if let Some(callback) = &self.0 {
+            callback(value);
+        }
+    }
+}
+
+impl<A: ?Sized> Default for Callback<A> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<A: ?Sized> Clone for Callback<A> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<A: ?Sized> std::fmt::Debug for Callback<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+/// Path of an OSC 7 `file://host/path` URL (or kitty's
+/// `kitty-shell-cwd://host/path`), percent-decoded. The host is not checked,
+/// since a shell reached over ssh reports its own.
+fn parse_osc7_cwd(url: &[u8]) -> Option<PathBuf> {
+    let url = std::str::from_utf8(url).ok()?;
+    let rest = url
+        .strip_prefix("file://")
+        .or_else(|| url.strip_prefix("kitty-shell-cwd://"))?;
+    let path = &rest[rest.find('/')?..];
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// Terminfo string capabilities the emulator actually implements.
+fn termcap_value(name: &str) -> Option<&'static str> {
+    match name {
+        // Terminal name, matching the xterm identity reported by DA2
+        "TN" | "name" => Some("xterm-256color"),
+        "Co" | "colors" => Some("256"),
+        // 24-bit SGR 38;2 / 48;2 colors
+        "RGB" => Some("8/8/8"),
+        // DECSCUSR cursor shapes
+        "Ss" => Some("\x1b[%p1%d q"),
+        "Se" => Some("\x1b[0 q"),
+        _ => None,
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<String> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn encode_hex(text: &str) -> String {
+    text.bytes().map(|b| format!("{b:02X}")).collect()
 }
 
 /// DCS handler state for Device Control String sequences
@@ -683,6 +1023,10 @@ enum DcsHandler {
     None,
     /// DECRQSS - Request Status String (DCS $ q Pt ST)
     Decrqss,
+    /// XTGETTCAP - Request Termcap/Terminfo String (DCS + q Pt ST)
+    Xtgettcap,
+    /// Sixel graphics (DCS P1 ; P2 ; P3 q data ST)
+    Sixel(Box<SixelDecoder>),
 }
 
 /// Saved cursor state (DECSC/DECRC)
@@ -693,11 +1037,44 @@ struct SavedCursor {
     styles: CharacterStyles,
     origin_mode: bool,
     auto_wrap: bool,
+    pending_wrap: bool,
     charset_index: usize,
     g0_charset_line_drawing: bool,
     g1_charset_line_drawing: bool,
 }
 
+impl SavedCursor {
+    fn snapshot(&self, styles: &mut StyleEncoder) -> SavedCursorSnapshot {
+        SavedCursorSnapshot {
+            row: self.row,
+            col: self.col,
+            style: styles.encode(&self.styles),
+            origin_mode: self.origin_mode,
+            auto_wrap: self.auto_wrap,
+            pending_wrap: self.pending_wrap,
+            charsets: CharsetSnapshot {
+                index: self.charset_index,
+                g0_line_drawing: self.g0_charset_line_drawing,
+                g1_line_drawing: self.g1_charset_line_drawing,
+            },
+        }
+    }
+
+    fn restore(saved: SavedCursorSnapshot, styles: &StyleDecoder) -> Self {
+        Self {
+            row: saved.row,
+            col: saved.col,
+            styles: styles.decode(saved.style),
+            origin_mode: saved.origin_mode,
+            auto_wrap: saved.auto_wrap,
+            pending_wrap: saved.pending_wrap,
+            charset_index: saved.charsets.index,
+            g0_charset_line_drawing: saved.charsets.g0_line_drawing,
+            g1_charset_line_drawing: saved.charsets.g1_line_drawing,
+        }
+    }
+}
+
 /// Saved state for alternate screen buffer
 #[derive(Debug, Clone)]
 struct AlternateScreen {
@@ -716,12 +1093,21 @@ struct AlternateScreen {
     charset_index: usize,
     g0_charset_line_drawing: bool,
     g1_charset_line_drawing: bool,
+    // DECSC slot of the main screen; the alternate screen gets its own
+    saved_cursor: Option<SavedCursor>,
+    // Kitty keyboard flags are per screen too
+    keyboard_flags: KeyboardFlagStack,
+}
+
+/// Default tab stops (every 8 columns) among columns `from..to`
+fn default_tab_stops(from: usize, to: usize) -> Vec<usize> {
+    (from..to).filter(|&c| c % 8 == 0 && c > 0).collect()
 }
 
 impl VirtualTerminal {
     pub fn new(rows: usize, cols: usize) -> Self {
         // Initialize default tab stops every 8 columns
-        let tab_stops: Vec<usize> = (0..cols).filter(|&c| c % 8 == 0 && c > 0).collect();
+        let tab_stops = default_tab_stops(0, cols);
         Self {
             internal_grid: Grid::new(rows, cols),
             max_scrollback: 10000,
@@ -742,14 +1128,24 @@ impl VirtualTerminal {
             bracketed_paste: false,
             mouse_tracking: None,
             sgr_mouse_mode: false,
+            focus_events: false,
+            keyboard_flags: KeyboardFlagStack::default(),
             bell_pending: false,
             title: None,
+            icon_name: None,
+            title_callback: Callback::default(),
+            icon_name_callback: Callback::default(),
+            cwd: None,
+            cwd_callback: Callback::default(),
             last_printed_char: None,
             pending_responses: Vec::new(),
+            query_responder: Callback::default(),
+            semantic_marks: Vec::new(),
             default_fg_color: None,     // Use terminal's native color
             default_bg_color: None,     // Use terminal's native color
             cursor_color: None,         // Use terminal's native cursor color
             color_palette: [None; 256], // Use default 256-color palette
+            color_change_callback: Callback::default(),
             alt_screen_toggled: false,
             enable_left_right_margins: false,
             reverse_wraparound: false,
@@ -757,6 +1153,13 @@ impl VirtualTerminal {
             cursor_style: 0,    // Default cursor style (blinking block)
             dcs_handler: DcsHandler::None,
             dcs_data: Vec::new(),
+            cell_pixel_size: (10, 20),
+            ambiguous_width: AmbiguousWidth::Narrow,
+            next_image_id: 1,
+            parser: StreamParser::default(),
+            c1_decoder: C1Decoder::default(),
+            input_encoding: InputEncoding::Utf8,
+            cells_printed: 0,
         }
     }
 
@@ -815,6 +1218,36 @@ impl VirtualTerminal {
         self.color_palette[index as usize].unwrap_or_else(|| default_palette_color(index))
     }
 
+    /// Call `callback` whenever the application redefines or resets a
+    /// palette entry or the default foreground, background or cursor color,
+    /// so the host can restyle around the terminal to match. Only actual
+    /// changes are reported. Runs inside [`process`](Self::process); clones
+    /// of the terminal share it.
+    pub fn set_color_change_callback(
+        &mut self,
+        callback: impl Fn(&ColorChange) + Send + Sync + 'static,
+    ) {
+        self.color_change_callback.set(callback);
+    }
+
+    fn color_slot_mut(&mut self, slot: ColorSlot) -> &mut Option<(u8, u8, u8)> {
+        match slot {
+            ColorSlot::Palette(index) => &mut self.color_palette[index as usize],
+            ColorSlot::Foreground => &mut self.default_fg_color,
+            ColorSlot::Background => &mut self.default_bg_color,
+            ColorSlot::Cursor => &mut self.cursor_color,
+        }
+    }
+
+    fn set_color(&mut self, slot: ColorSlot, color: Option<(u8, u8, u8)>) {
+        let current = self.color_slot_mut(slot);
+        if *current != color {
+            *current = color;
+            self.color_change_callback
+                .call(&ColorChange { slot, color });
+        }
+    }
+
     /// Get a reference to the full color palette for rendering.
     /// Returns an array of Option<(u8, u8, u8)> where Some = custom color, None = use default.
     pub fn color_palette(&self) -> &crate::ColorPalette {
@@ -832,7 +1265,7 @@ impl VirtualTerminal {
     /// Returns a Cell at the given position.
     pub fn get_cell(&self, row: usize, col: usize) -> Cell {
         if let Some(tc) = self.internal_grid.get_char(row, col) {
-            Cell::from(tc)
+            Cell::from_character(tc, &self.internal_grid.style_table)
         } else {
             Cell::default()
         }
@@ -846,7 +1279,12 @@ impl VirtualTerminal {
         self.internal_grid
             .viewport
             .iter()
-            .map(|row| row.columns.iter().map(Cell::from).collect())
+            .map(|row| {
+                row.columns
+                    .iter()
+                    .map(|tc| Cell::from_character(tc, &self.internal_grid.style_table))
+                    .collect()
+            })
             .collect()
     }
 
@@ -856,18 +1294,20 @@ impl VirtualTerminal {
         self.internal_grid
             .lines_above
             .iter()
-            .map(|row| row.columns.iter().map(Cell::from).collect())
+            .map(|row| {
+                row.columns
+                    .iter()
+                    .map(|tc| Cell::from_character(tc, &self.internal_grid.style_table))
+                    .collect()
+            })
             .collect()
     }
 
     // ===== Tab stop methods =====
 
     /// Initialize default tab stops (every 8 columns)
-    #[allow(dead_code)]
     fn reset_tab_stops(&mut self) {
-        self.tab_stops = (0..self.internal_grid.cols)
-            .filter(|&c| c % 8 == 0 && c > 0)
-            .collect();
+        self.tab_stops = default_tab_stops(0, self.internal_grid.cols);
     }
 
     /// Clear all tab stops
@@ -889,34 +1329,48 @@ impl VirtualTerminal {
         }
     }
 
-    /// Move cursor to next tab stop
-    fn tab_forward(&mut self) {
-        if let Some(&next_tab) = self
-            .tab_stops
-            .iter()
-            .find(|&&c| c > self.internal_grid.cursor_col)
+    /// Columns tabs are confined to: the left/right margins while the
+    /// cursor is inside them, otherwise the whole line
+    fn tab_bounds(&self) -> (usize, usize) {
+        let grid = &self.internal_grid;
+        if self.enable_left_right_margins
+            && grid.cursor_col >= grid.left_margin
+            && grid.cursor_col <= grid.right_margin
         {
-            self.internal_grid.cursor_col = next_tab.min(self.internal_grid.cols - 1);
+            (grid.left_margin, grid.right_margin)
         } else {
-            // No more tab stops, go to end of line
-            self.internal_grid.cursor_col = self.internal_grid.cols - 1;
+            (0, grid.cols - 1)
+        }
+    }
+
+    /// Move cursor forward `n` tab stops (HT, CHT), stopping at the right
+    /// margin or end of line when there are no more
+    fn tab_forward(&mut self, n: usize) {
+        let (_, right) = self.tab_bounds();
+        for _ in 0..n {
+            let col = self.internal_grid.cursor_col;
+            self.internal_grid.cursor_col = self
+                .tab_stops
+                .iter()
+                .find(|&&c| c > col)
+                .map_or(right, |&next_tab| next_tab.min(right))
+                .max(col);
         }
         self.pending_wrap = false;
     }
 
     /// Move cursor to previous tab stop (CBT)
     fn tab_backward(&mut self, n: usize) {
+        let (left, _) = self.tab_bounds();
         for _ in 0..n {
-            if let Some(&prev_tab) = self
+            let col = self.internal_grid.cursor_col;
+            self.internal_grid.cursor_col = self
                 .tab_stops
                 .iter()
                 .rev()
-                .find(|&&c| c < self.internal_grid.cursor_col)
-            {
-                self.internal_grid.cursor_col = prev_tab;
-            } else {
-                self.internal_grid.cursor_col = 0;
-            }
+                .find(|&&c| c < col)
+                .map_or(left, |&prev_tab| prev_tab.max(left))
+                .min(col);
         }
         self.pending_wrap = false;
     }
@@ -929,6 +1383,7 @@ impl VirtualTerminal {
             styles: self.internal_grid.current_styles,
             origin_mode: self.origin_mode,
             auto_wrap: self.auto_wrap,
+            pending_wrap: self.pending_wrap,
             charset_index: self.charset_index,
             g0_charset_line_drawing: self.g0_charset_line_drawing,
             g1_charset_line_drawing: self.g1_charset_line_drawing,
@@ -936,20 +1391,118 @@ impl VirtualTerminal {
     }
 
     /// Restore cursor position and attributes (DECRC)
+    ///
+    /// Without a prior DECSC this behaves like xterm: cursor to home with
+    /// default attributes, origin mode off and G0 selected.
     fn restore_cursor(&mut self) {
-        if let Some(saved) = &self.saved_cursor {
-            self.internal_grid.cursor_row =
-                saved.row.min(self.internal_grid.rows.saturating_sub(1));
-            self.internal_grid.cursor_col =
-                saved.col.min(self.internal_grid.cols.saturating_sub(1));
-            self.internal_grid.set_current_styles(saved.styles);
+        let Some(saved) = &self.saved_cursor else {
+            self.internal_grid.cursor_row = 0;
+            self.internal_grid.cursor_col = 0;
+            self.internal_grid
+                .set_current_styles(CharacterStyles::default());
+            self.origin_mode = false;
+            self.pending_wrap = false;
+            self.charset_index = 0;
+            self.g0_charset_line_drawing = false;
+            self.g1_charset_line_drawing = false;
+            return;
+        };
+        let row = saved.row.min(self.internal_grid.rows.saturating_sub(1));
+        let col = saved.col.min(self.internal_grid.cols.saturating_sub(1));
+        // A pending wrap only means something in the last column, which a
+        // resize since the save may have moved
+        let pending_wrap = saved.pending_wrap && col + 1 == self.internal_grid.cols;
+        self.internal_grid.cursor_row = row;
+        self.internal_grid.cursor_col = col;
+        self.internal_grid.set_current_styles(saved.styles);
+        self.origin_mode = saved.origin_mode;
+        self.auto_wrap = saved.auto_wrap;
+        self.pending_wrap = pending_wrap;
+        self.charset_index = saved.charset_index;
+        self.g0_charset_line_drawing = saved.g0_charset_line_drawing;
+        self.g1_charset_line_drawing = saved.g1_charset_line_drawing;
+    }
+
+    /// Switch to a blank alternate screen, keeping the main screen's state.
+    ///
+    /// Each screen has its own DECSC slot, so the alternate screen starts
+    /// without a saved cursor and anything it saves never reaches the main
+    /// screen.
+    fn enter_alternate_screen(&mut self) {
+        // Only enter if not already in alternate screen
+        // (prevents losing main screen if app sends 1049h twice)
+        if self.alternate_screen.is_some() {
+            return;
+        }
+        self.alternate_screen = Some(Box::new(AlternateScreen {
+            grid: self.internal_grid.clone(),
+            cursor_row: self.internal_grid.cursor_row,
+            cursor_col: self.internal_grid.cursor_col,
+            current_styles: self.internal_grid.current_styles,
+            // Save terminal modes that affect cursor positioning
+            origin_mode: self.origin_mode,
+            auto_wrap: self.auto_wrap,
+            pending_wrap: self.pending_wrap,
+            // Save cursor visibility (per-screen state)
+            cursor_visible: self.cursor_visible,
+            cursor_blink: self.cursor_blink,
+            // Save charset state
+            charset_index: self.charset_index,
+            g0_charset_line_drawing: self.g0_charset_line_drawing,
+            g1_charset_line_drawing: self.g1_charset_line_drawing,
+            saved_cursor: self.saved_cursor.take(),
+            keyboard_flags: std::mem::take(&mut self.keyboard_flags),
+        }));
+        let rows = self.internal_grid.rows;
+        let cols = self.internal_grid.cols;
+        self.internal_grid = Grid::new(rows, cols);
+        self.pending_wrap = false;
+        self.alt_screen_toggled = true;
+    }
+
+    /// Return to the main screen. With `restore_cursor` (mode 1049) the
+    /// cursor, attributes and modes from entry are restored too.
+    fn leave_alternate_screen(&mut self, restore_cursor: bool) {
+        let Some(saved) = self.alternate_screen.take() else {
+            return;
+        };
+        // Resize saved grid to current dimensions if needed
+        let mut restored = saved.grid;
+        restored.resize(self.internal_grid.rows, self.internal_grid.cols);
+        self.internal_grid = restored;
+        // Mark all lines as changed to force full redraw
+        // (resize only marks changed if dimensions actually change)
+        self.internal_grid.mark_all_changed();
+        // Cursor visibility is per-screen state
+        self.cursor_visible = saved.cursor_visible;
+        self.cursor_blink = saved.cursor_blink;
+        self.keyboard_flags = saved.keyboard_flags;
+        self.pending_wrap = false;
+        if restore_cursor {
+            self.internal_grid.cursor_row = saved
+                .cursor_row
+                .min(self.internal_grid.rows.saturating_sub(1));
+            self.internal_grid.cursor_col = saved
+                .cursor_col
+                .min(self.internal_grid.cols.saturating_sub(1));
+            self.internal_grid.set_current_styles(saved.current_styles);
             self.origin_mode = saved.origin_mode;
             self.auto_wrap = saved.auto_wrap;
+            self.pending_wrap =
+                saved.pending_wrap && saved.cursor_col + 1 == self.internal_grid.cols;
             self.charset_index = saved.charset_index;
             self.g0_charset_line_drawing = saved.g0_charset_line_drawing;
             self.g1_charset_line_drawing = saved.g1_charset_line_drawing;
+            // 1049h is DECSC + switch in xterm, so the main screen's slot
+            // would now hold the position we just restored. Leave it empty
+            // instead: when a TUI exits, a later RESTORE_CURSOR (like codex
+            // sends) must not jump back to where the TUI was launched.
+            self.saved_cursor = None;
+        } else {
+            // 47/1047 leave the main screen's DECSC slot untouched
+            self.saved_cursor = saved.saved_cursor;
         }
-        self.pending_wrap = false;
+        self.alt_screen_toggled = true;
     }
 
     /// Soft Terminal Reset (DECSTR) - CSI ! p
@@ -989,25 +1542,91 @@ impl VirtualTerminal {
         self.g1_charset_line_drawing = false;
 
         // Reset tab stops to default (every 8 columns)
-        self.tab_stops = (0..self.internal_grid.cols)
-            .filter(|&c| c % 8 == 0 && c > 0)
-            .collect();
+        self.reset_tab_stops();
     }
 
     /// Resize the terminal
     pub fn resize(&mut self, new_rows: usize, new_cols: usize) {
+        let old_cols = self.internal_grid.cols;
         self.internal_grid.resize(new_rows, new_cols);
-        // Update tab stops for new width
+        // Update tab stops for new width; added columns get the default stops
         self.tab_stops.retain(|&c| c < new_cols);
+        self.tab_stops.extend(default_tab_stops(old_cols, new_cols));
         self.internal_grid.fix_cursor_on_spacer();
     }
 
+    /// How 8-bit C1 controls (e.g. 0x9B CSI) are handled
+    pub fn c1_mode(&self) -> C1Mode {
+        self.c1_decoder.mode()
+    }
+
+    /// Choose whether 8-bit C1 controls are interpreted or stripped
+    pub fn set_c1_mode(&mut self, mode: C1Mode) {
+        self.c1_decoder.set_mode(mode);
+    }
+
+    /// How input bytes are decoded into characters
+    pub fn input_encoding(&self) -> InputEncoding {
+        self.input_encoding
+    }
+
+    /// Decode input as `encoding` from now on, e.g. [`InputEncoding::Cp437`]
+    /// to show ANSI art and DOS programs' box drawing.
+    pub fn set_input_encoding(&mut self, encoding: InputEncoding) {
+        self.input_encoding = encoding;
+    }
+
     /// Process raw terminal data
     pub fn process(&mut self, data: &[u8]) {
-        let mut parser = Parser::new();
+        let data = match self.input_encoding {
+            InputEncoding::Utf8 => self.c1_decoder.decode(data),
+            InputEncoding::Cp437 => decode_cp437(data),
+        };
+        let mut parser = std::mem::take(&mut self.parser);
         for byte in data {
-            parser.advance(self, *byte);
+            parser.0.advance(self, byte);
         }
+        self.parser = parser;
+    }
+
+    /// Process `data` until either limit of `budget` is reached, returning
+    /// what is left as a [`Continuation`] so a big burst (e.g. `cat` of a
+    /// large file) can be fed in slices with rendering, a lock release or a
+    /// yield to the runtime in between:
+    ///
+    /// ```rust
+    /// # use cmux_terminal::{ProcessBudget, VirtualTerminal};
+    /// # let mut term = VirtualTerminal::new(24, 80);
+    /// # let data = vec![b'x'; 100_000];
+    /// let mut next = term.process_chunked(&data, ProcessBudget::default());
+    /// while let Some(rest) = next {
+    ///     // render, or `tokio::task::yield_now().await`
+    ///     next = rest.resume(&mut term, ProcessBudget::default());
+    /// }
+    /// ```
+    ///
+    /// Limits are checked every 1 KiB of input, so a call may go over them
+    /// by that much, and always makes progress. Parser state carries
+    /// over, so stopping inside an escape sequence or a UTF-8 character gives
+    /// the same result as one [`process`](Self::process) call.
+    pub fn process_chunked<'a>(
+        &mut self,
+        mut data: &'a [u8],
+        budget: ProcessBudget,
+    ) -> Option<Continuation<'a>> {
+        let cells_before = self.cells_printed;
+        let mut consumed = 0;
+        while !data.is_empty() {
+            let (now, rest) = data.split_at(CHUNK_STEP.min(data.len()));
+            self.process(now);
+            consumed += now.len();
+            data = rest;
+            let cells = self.cells_printed.wrapping_sub(cells_before);
+            if !data.is_empty() && (consumed >= budget.max_bytes || cells >= budget.max_cells) {
+                return Some(Continuation { rest: data });
+            }
+        }
+        None
     }
 
     /// Drain pending responses that should be sent back to the PTY
@@ -1015,6 +1634,213 @@ impl VirtualTerminal {
         std::mem::take(&mut self.pending_responses)
     }
 
+    /// Write replies to queries (DA1/DA2, DSR, DECRQM, DECRQSS, XTGETTCAP,
+    /// color and size reports) to `callback` as soon as they are generated,
+    /// instead of queueing them for [`drain_responses`](Self::drain_responses).
+    /// Headless embedders pass a closure writing to the PTY, so applications
+    /// blocking on a reply get one mid-[`process`](Self::process) call.
+    /// Clones of the terminal share it.
+    pub fn set_query_responder(&mut self, callback: impl Fn(&[u8]) + Send + Sync + 'static) {
+        self.query_responder.set(callback);
+        for response in std::mem::take(&mut self.pending_responses) {
+            self.query_responder.call(&response);
+        }
+    }
+
+    fn respond(&mut self, response: Vec<u8>) {
+        if self.query_responder.0.is_some() {
+            self.query_responder.call(&response);
+        } else {
+            self.pending_responses.push(response);
+        }
+    }
+
+    /// The active screen's grid, e.g. to copy regions out of with
+    /// [`Grid::copy_region_from`].
+    pub fn grid(&self) -> &Grid {
+        &self.internal_grid
+    }
+
+    /// Start selecting on the active screen; see [`Grid::start_selection`].
+    pub fn start_selection(&mut self, point: SelectionPoint, mode: SelectionMode) {
+        self.internal_grid.start_selection(point, mode);
+    }
+
+    /// Move the free end of the selection to `point`.
+    pub fn extend_selection(&mut self, point: SelectionPoint) {
+        self.internal_grid.extend_selection(point);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.internal_grid.clear_selection();
+    }
+
+    /// Text of the active screen's selection, if any.
+    pub fn selected_text(&self) -> Option<String> {
+        self.internal_grid.selected_text()
+    }
+
+    /// Working directory the shell last reported with OSC 7, if any.
+    pub fn cwd(&self) -> Option<&Path> {
+        self.cwd.as_deref()
+    }
+
+    /// Call `callback` with the new directory whenever OSC 7 reports a
+    /// different one. Runs inside [`process`](Self::process), so it should
+    /// be quick; clones of the terminal share it.
+    pub fn set_cwd_callback(&mut self, callback: impl Fn(&Path) + Send + Sync + 'static) {
+        self.cwd_callback.set(callback);
+    }
+
+    /// Window title set with OSC 0 or 2, if any.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Icon name set with OSC 0 or 1, if any.
+    pub fn icon_name(&self) -> Option<&str> {
+        self.icon_name.as_deref()
+    }
+
+    /// Call `callback` with the new title whenever the application changes
+    /// it, e.g. to relabel a tab. Runs inside [`process`](Self::process).
+    pub fn set_title_callback(&mut self, callback: impl Fn(&str) + Send + Sync + 'static) {
+        self.title_callback.set(callback);
+    }
+
+    /// Call `callback` with the new icon name whenever the application
+    /// changes it. Runs inside [`process`](Self::process).
+    pub fn set_icon_name_callback(&mut self, callback: impl Fn(&str) + Send + Sync + 'static) {
+        self.icon_name_callback.set(callback);
+    }
+
+    /// Drain OSC 133 marks received since the last call, oldest first
+    pub fn drain_semantic_marks(&mut self) -> Vec<SemanticMark> {
+        std::mem::take(&mut self.semantic_marks)
+    }
+
+    /// Commands on the main screen marked by shell integration, oldest
+    /// first. Lines are numbered like [`SemanticMark::line`]. Unlike
+    /// [`drain_semantic_marks`](Self::drain_semantic_marks), this keeps the
+    /// history, following lines as scrollback is trimmed or rewrapped.
+    pub fn commands(&self) -> &[CommandBlock] {
+        match &self.alternate_screen {
+            Some(saved) => saved.grid.commands.blocks(),
+            None => self.internal_grid.commands.blocks(),
+        }
+    }
+
+    /// The last command whose prompt is above `line`, to jump back to.
+    pub fn previous_command(&self, line: usize) -> Option<&CommandBlock> {
+        self.commands()
+            .iter()
+            .rev()
+            .find(|block| block.prompt_line < line)
+    }
+
+    /// The first command whose prompt is below `line`.
+    pub fn next_command(&self, line: usize) -> Option<&CommandBlock> {
+        self.commands()
+            .iter()
+            .find(|block| block.prompt_line > line)
+    }
+
+    /// Inline images on the current screen, oldest first. Rows count from
+    /// the oldest scrollback line, as in [`SemanticMark::line`].
+    pub fn images(&self) -> &[InlineImage] {
+        &self.internal_grid.images
+    }
+
+    /// Set the pixel size of one cell, used to size images that give their
+    /// dimensions in pixels and to answer XTWINOPS size queries.
+    pub fn set_cell_pixel_size(&mut self, width: u16, height: u16) {
+        self.cell_pixel_size = (width.max(1), height.max(1));
+    }
+
+    /// Width used for East Asian Ambiguous characters.
+    pub fn ambiguous_width(&self) -> AmbiguousWidth {
+        self.ambiguous_width
+    }
+
+    /// Measure East Asian Ambiguous characters as `width` from now on, e.g.
+    /// [`AmbiguousWidth::Wide`] to match a CJK locale. Cells already on the
+    /// grid keep their width.
+    pub fn set_ambiguous_width(&mut self, width: AmbiguousWidth) {
+        self.ambiguous_width = width;
+    }
+
+    /// Place a decoded image at the cursor and move the cursor below it.
+    fn place_image(&mut self, pending: PendingImage) {
+        let (term_rows, term_cols) = (self.internal_grid.rows, self.internal_grid.cols);
+        let (cols, rows) = pending.cell_size(self.cell_pixel_size, term_cols, term_rows);
+        let start_col = self.internal_grid.cursor_col;
+        let image = InlineImage {
+            id: self.next_image_id,
+            row: self.internal_grid.lines_above.len() + self.internal_grid.cursor_row,
+            col: start_col,
+            rows,
+            cols: cols.min(term_cols - start_col),
+            pixel_size: pending.pixel_size,
+            data: pending.data,
+        };
+        self.next_image_id += 1;
+        self.internal_grid.add_image(image);
+        for _ in 0..rows {
+            let row = self.internal_grid.cursor_row;
+            self.internal_grid.mark_line_changed(row);
+            self.newline();
+        }
+        self.internal_grid.cursor_col = if pending.return_to_first_column {
+            0
+        } else {
+            start_col
+        };
+        self.pending_wrap = false;
+    }
+
+    /// Viewport rows whose contents changed since the last call, in order,
+    /// so a renderer can resend just those rows. Every row is reported after
+    /// a resize, a reset or an alternate-screen switch. Cursor movement alone
+    /// doesn't count as damage.
+    pub fn take_damage(&mut self) -> Vec<usize> {
+        let grid = &mut self.internal_grid;
+        let damage = if grid.needs_full_redraw() {
+            (0..grid.rows).collect()
+        } else {
+            let mut rows: Vec<usize> = grid.get_changed_lines().iter().copied().collect();
+            rows.sort_unstable();
+            rows
+        };
+        grid.clear_changed();
+        damage
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if self.title.as_deref() != Some(title) {
+            self.title_callback.call(title);
+            self.title = Some(title.to_string());
+        }
+    }
+
+    fn set_icon_name(&mut self, icon_name: &str) {
+        if self.icon_name.as_deref() != Some(icon_name) {
+            self.icon_name_callback.call(icon_name);
+            self.icon_name = Some(icon_name.to_string());
+        }
+    }
+
+    fn push_semantic_mark(&mut self, kind: SemanticMarkKind) {
+        if self.semantic_marks.len() >= MAX_SEMANTIC_MARKS {
+            self.semantic_marks.remove(0);
+        }
+        let line = self.internal_grid.lines_above.len() + self.internal_grid.cursor_row;
+        self.semantic_marks.push(SemanticMark { kind, line });
+        let at_line_start = self.internal_grid.cursor_col == 0 && !self.pending_wrap;
+        self.internal_grid
+            .commands
+            .record(kind, line, at_line_start);
+    }
+
     /// Get the current viewport content as plain text lines.
     /// Each line is trimmed of trailing spaces.
     pub fn viewport_lines(&self) -> Vec<String> {
@@ -1045,6 +1871,453 @@ impl VirtualTerminal {
         lines
     }
 
+    /// Size of the style table backing the terminal's cells.
+    pub fn style_stats(&self) -> StyleStats {
+        self.internal_grid.style_stats()
+    }
+
+    /// Drop interned styles no cell uses any more. Returns how many went.
+    pub fn compact_styles(&mut self) -> usize {
+        self.internal_grid.compact_styles()
+    }
+
+    /// DEC line size of a viewport row, for renderers that scale it.
+    pub fn line_size(&self, row: usize) -> LineSize {
+        self.internal_grid
+            .get_row(row)
+            .map_or(LineSize::Normal, |row| row.size)
+    }
+
+    /// Input modes the application has enabled
+    pub fn modes(&self) -> TerminalModes {
+        TerminalModes {
+            bracketed_paste: self.bracketed_paste,
+            mouse_tracking: MouseTracking::from_mode(self.mouse_tracking),
+            sgr_mouse: self.sgr_mouse_mode,
+            focus_events: self.focus_events,
+            keyboard_flags: self.keyboard_flags.current(),
+        }
+    }
+
+    /// Cursor shape, blinking and visibility the application asked for
+    pub fn cursor_style(&self) -> CursorStyle {
+        CursorStyle {
+            shape: match self.cursor_style {
+                3 | 4 => CursorShape::Underline,
+                5 | 6 => CursorShape::Bar,
+                _ => CursorShape::Block,
+            },
+            blinking: self.cursor_blink,
+            visible: self.cursor_visible,
+        }
+    }
+
+    /// Columns with a tab stop, ascending. Every 8th column by default; HTS,
+    /// TBC and CTC change them.
+    pub fn tab_stops(&self) -> &[usize] {
+        &self.tab_stops
+    }
+
+    /// Checkpoint the full terminal state; see [`TerminalSnapshot`].
+    pub fn snapshot(&self) -> TerminalSnapshot {
+        let mut styles = StyleEncoder::new();
+        let screen = styles.encode_grid(&self.internal_grid);
+        let saved_cursor = self
+            .saved_cursor
+            .as_ref()
+            .map(|saved| saved.snapshot(&mut styles));
+        let primary = self
+            .alternate_screen
+            .as_ref()
+            .map(|alt| PrimaryScreenSnapshot {
+                grid: styles.encode_grid(&alt.grid),
+                origin_mode: alt.origin_mode,
+                auto_wrap: alt.auto_wrap,
+                pending_wrap: alt.pending_wrap,
+                cursor_visible: alt.cursor_visible,
+                cursor_blink: alt.cursor_blink,
+                charsets: CharsetSnapshot {
+                    index: alt.charset_index,
+                    g0_line_drawing: alt.g0_charset_line_drawing,
+                    g1_line_drawing: alt.g1_charset_line_drawing,
+                },
+                saved_cursor: alt
+                    .saved_cursor
+                    .as_ref()
+                    .map(|saved| saved.snapshot(&mut styles)),
+                keyboard_flags: alt.keyboard_flags.clone(),
+            });
+        TerminalSnapshot {
+            styles: styles.finish(),
+            screen,
+            primary,
+            saved_cursor,
+            max_scrollback: self.max_scrollback,
+            cursor_visible: self.cursor_visible,
+            cursor_blink: self.cursor_blink,
+            cursor_style: self.cursor_style,
+            insert_mode: self.insert_mode,
+            origin_mode: self.origin_mode,
+            auto_wrap: self.auto_wrap,
+            pending_wrap: self.pending_wrap,
+            newline_mode: self.newline_mode,
+            reverse_wraparound: self.reverse_wraparound,
+            enable_left_right_margins: self.enable_left_right_margins,
+            tab_stops: self.tab_stops.clone(),
+            charsets: CharsetSnapshot {
+                index: self.charset_index,
+                g0_line_drawing: self.g0_charset_line_drawing,
+                g1_line_drawing: self.g1_charset_line_drawing,
+            },
+            application_cursor_keys: self.application_cursor_keys,
+            application_keypad: self.application_keypad,
+            bracketed_paste: self.bracketed_paste,
+            mouse_tracking: self.mouse_tracking,
+            sgr_mouse_mode: self.sgr_mouse_mode,
+            focus_events: self.focus_events,
+            keyboard_flags: self.keyboard_flags.clone(),
+            c1_mode: self.c1_decoder.mode(),
+            title: self.title.clone(),
+            icon_name: self.icon_name.clone(),
+            cwd: self.cwd.clone(),
+            default_fg_color: self.default_fg_color,
+            default_bg_color: self.default_bg_color,
+            cursor_color: self.cursor_color,
+            palette: self
+                .color_palette
+                .iter()
+                .enumerate()
+                .filter_map(|(index, rgb)| rgb.map(|rgb| (index as u8, rgb)))
+                .collect(),
+            last_printed_char: self.last_printed_char,
+            ambiguous_width: self.ambiguous_width,
+            input_encoding: self.input_encoding,
+        }
+    }
+
+    /// Rebuild a terminal from [`snapshot`](Self::snapshot). Positions that
+    /// fall outside the snapshot's size are clamped rather than rejected.
+    pub fn restore(snapshot: TerminalSnapshot) -> Self {
+        let styles = StyleDecoder::new(&snapshot.styles);
+        let internal_grid = styles.decode_grid(snapshot.screen);
+        let mut term = Self::new(internal_grid.rows, internal_grid.cols);
+        term.internal_grid = internal_grid;
+        term.saved_cursor = snapshot
+            .saved_cursor
+            .map(|saved| SavedCursor::restore(saved, &styles));
+        term.alternate_screen = snapshot.primary.map(|primary| {
+            let grid = styles.decode_grid(primary.grid);
+            Box::new(AlternateScreen {
+                cursor_row: grid.cursor_row,
+                cursor_col: grid.cursor_col,
+                current_styles: grid.current_styles,
+                grid,
+                origin_mode: primary.origin_mode,
+                auto_wrap: primary.auto_wrap,
+                pending_wrap: primary.pending_wrap,
+                cursor_visible: primary.cursor_visible,
+                cursor_blink: primary.cursor_blink,
+                charset_index: primary.charsets.index,
+                g0_charset_line_drawing: primary.charsets.g0_line_drawing,
+                g1_charset_line_drawing: primary.charsets.g1_line_drawing,
+                saved_cursor: primary
+                    .saved_cursor
+                    .map(|saved| SavedCursor::restore(saved, &styles)),
+                keyboard_flags: primary.keyboard_flags,
+            })
+        });
+        term.max_scrollback = snapshot.max_scrollback;
+        term.cursor_visible = snapshot.cursor_visible;
+        term.cursor_blink = snapshot.cursor_blink;
+        term.cursor_style = snapshot.cursor_style;
+        term.insert_mode = snapshot.insert_mode;
+        term.origin_mode = snapshot.origin_mode;
+        term.auto_wrap = snapshot.auto_wrap;
+        term.pending_wrap = snapshot.pending_wrap;
+        term.newline_mode = snapshot.newline_mode;
+        term.reverse_wraparound = snapshot.reverse_wraparound;
+        term.enable_left_right_margins = snapshot.enable_left_right_margins;
+        term.tab_stops = snapshot.tab_stops;
+        term.charset_index = snapshot.charsets.index;
+        term.g0_charset_line_drawing = snapshot.charsets.g0_line_drawing;
+        term.g1_charset_line_drawing = snapshot.charsets.g1_line_drawing;
+        term.application_cursor_keys = snapshot.application_cursor_keys;
+        term.application_keypad = snapshot.application_keypad;
+        term.bracketed_paste = snapshot.bracketed_paste;
+        term.mouse_tracking = snapshot.mouse_tracking;
+        term.sgr_mouse_mode = snapshot.sgr_mouse_mode;
+        term.focus_events = snapshot.focus_events;
+        term.keyboard_flags = snapshot.keyboard_flags;
+        term.c1_decoder = C1Decoder::new(snapshot.c1_mode);
+        term.title = snapshot.title;
+        term.icon_name = snapshot.icon_name;
+        term.cwd = snapshot.cwd;
+        term.default_fg_color = snapshot.default_fg_color;
+        term.default_bg_color = snapshot.default_bg_color;
+        term.cursor_color = snapshot.cursor_color;
+        for (index, rgb) in snapshot.palette {
+            term.color_palette[index as usize] = Some(rgb);
+        }
+        term.last_printed_char = snapshot.last_printed_char;
+        term.ambiguous_width = snapshot.ambiguous_width;
+        term.input_encoding = snapshot.input_encoding;
+        term
+    }
+
+    /// Whether a viewport row is a soft-wrapped continuation of the row above
+    pub fn is_wrapped(&self, row: usize) -> bool {
+        self.internal_grid
+            .get_row(row)
+            .is_some_and(|row| !row.is_canonical)
+    }
+
+    /// Group scrollback and viewport rows into output lines, trimming trailing
+    /// blanks. With `join_wrapped`, soft-wrapped rows are appended to the line
+    /// they continue so only hard line breaks separate lines.
+    fn capture_lines(&self, join_wrapped: bool) -> Vec<(LineSize, Vec<&TerminalCharacter>)> {
+        let mut lines: Vec<(LineSize, Vec<&TerminalCharacter>)> = Vec::new();
+        for row in self
+            .internal_grid
+            .lines_above
+            .iter()
+            .chain(self.internal_grid.viewport.iter())
+        {
+            let cells = row.columns.iter().filter(|cell| !cell.wide_spacer);
+            match lines.last_mut() {
+                Some((_, line)) if join_wrapped && !row.is_canonical => line.extend(cells),
+                _ => lines.push((row.size, cells.collect())),
+            }
+        }
+        for (_, line) in &mut lines {
+            while line
+                .last()
+                .is_some_and(|cell| cell.character == ' ' && cell.styles.is_default())
+            {
+                line.pop();
+            }
+        }
+        lines
+    }
+
+    /// Capture scrollback and viewport as plain text, one line per `\n`.
+    /// When `join_wrapped` is set, soft-wrapped rows are rejoined so that text
+    /// wider than the terminal (e.g. long shell commands) copies intact.
+    pub fn capture(&self, join_wrapped: bool) -> String {
+        self.capture_lines(join_wrapped)
+            .iter()
+            .map(|(_, line)| {
+                let mut text = String::with_capacity(line.len());
+                for cell in line {
+                    cell.push_grapheme(&mut text);
+                }
+                text.trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// When each line of [`capture`](Self::capture) was first written, for
+    /// showing output age. A joined line takes the time of its first row;
+    /// lines nothing was printed on are `None`.
+    pub fn capture_timestamps(&self, join_wrapped: bool) -> Vec<Option<SystemTime>> {
+        let mut times: Vec<Option<SystemTime>> = Vec::new();
+        for row in self
+            .internal_grid
+            .lines_above
+            .iter()
+            .chain(self.internal_grid.viewport.iter())
+        {
+            match times.last_mut() {
+                Some(time) if join_wrapped && !row.is_canonical => {
+                    *time = time.or(row.written_at);
+                }
+                _ => times.push(row.written_at),
+            }
+        }
+        times
+    }
+
+    /// Drop main-screen scrollback first written before `cutoff`, oldest
+    /// first, e.g. to keep only the last hour of output. Returns how many
+    /// rows were removed.
+    pub fn prune_scrollback_before(&mut self, cutoff: SystemTime) -> usize {
+        match &mut self.alternate_screen {
+            Some(saved) => saved.grid.prune_scrollback_before(cutoff),
+            None => self.internal_grid.prune_scrollback_before(cutoff),
+        }
+    }
+
+    /// Like [`capture`](Self::capture), but with SGR sequences reproducing cell
+    /// styles. Lines are separated by `\r\n` so the output can be replayed.
+    pub fn to_ansi_string(&self, join_wrapped: bool) -> String {
+        let mut out = String::new();
+        let mut current = CharacterStyles::default();
+        for (i, (size, line)) in self.capture_lines(join_wrapped).iter().enumerate() {
+            if i > 0 {
+                out.push_str("\r\n");
+            }
+            if *size != LineSize::Normal {
+                out.push_str(size.escape_sequence());
+            }
+            for cell in line {
+                let styles = self.internal_grid.style_table.get(cell.styles);
+                if *styles != current {
+                    out.push_str(&format!("\x1b[{}m", self.sgr_string_for(styles)));
+                    current = *styles;
+                }
+                cell.push_grapheme(&mut out);
+            }
+        }
+        if current != CharacterStyles::default() {
+            out.push_str("\x1b[0m");
+        }
+        out
+    }
+
+    /// Escape sequence that repaints the current screen on a blank terminal of
+    /// the same size: cell contents and styles, the pen, the cursor, and the
+    /// modes that change what the receiving terminal displays or sends back.
+    /// Scrollback is not included, so a late-joining client can be brought up
+    /// to date without replaying the whole output history.
+    pub fn to_ansi(&self) -> String {
+        let grid = &self.internal_grid;
+        let mut out = String::new();
+        if self.alternate_screen.is_some() {
+            out.push_str("\x1b[?1049h");
+        }
+        out.push_str("\x1b[0m\x1b[H\x1b[2J");
+
+        let mut current = CharacterStyles::default();
+        for (row_idx, row) in grid.viewport.iter().enumerate() {
+            let cells: Vec<&TerminalCharacter> = row
+                .columns
+                .iter()
+                .filter(|cell| !cell.wide_spacer)
+                .collect();
+            let end = cells
+                .iter()
+                .rposition(|cell| !(cell.character == ' ' && cell.styles.is_default()))
+                .map_or(0, |last| last + 1);
+            if end == 0 && row.size == LineSize::Normal {
+                continue;
+            }
+            out.push_str(&format!("\x1b[{};1H", row_idx + 1));
+            if row.size != LineSize::Normal {
+                out.push_str(row.size.escape_sequence());
+            }
+            for cell in &cells[..end] {
+                let styles = grid.style_table.get(cell.styles);
+                if *styles != current {
+                    out.push_str(&format!("\x1b[{}m", self.sgr_string_for(styles)));
+                    current = *styles;
+                }
+                cell.push_grapheme(&mut out);
+            }
+        }
+
+        let (top, bottom) = grid.scroll_region;
+        if (top, bottom) != (0, grid.rows.saturating_sub(1)) {
+            out.push_str(&format!("\x1b[{};{}r", top + 1, bottom + 1));
+        }
+        let mut cursor_row = grid.cursor_row;
+        if self.origin_mode {
+            out.push_str("\x1b[?6h");
+            cursor_row = cursor_row.saturating_sub(top);
+        }
+        out.push_str(&format!("\x1b[{};{}H", cursor_row + 1, grid.cursor_col + 1));
+        if grid.current_styles != current {
+            out.push_str(&format!(
+                "\x1b[{}m",
+                self.sgr_string_for(&grid.current_styles)
+            ));
+        }
+
+        if !self.auto_wrap {
+            out.push_str("\x1b[?7l");
+        }
+        if self.insert_mode {
+            out.push_str("\x1b[4h");
+        }
+        if self.application_cursor_keys {
+            out.push_str("\x1b[?1h");
+        }
+        if self.application_keypad {
+            out.push_str("\x1b=");
+        }
+        if let Some(mode) = self.mouse_tracking {
+            out.push_str(&format!("\x1b[?{}h", mode));
+        }
+        if self.sgr_mouse_mode {
+            out.push_str("\x1b[?1006h");
+        }
+        if self.focus_events {
+            out.push_str("\x1b[?1004h");
+        }
+        if self.bracketed_paste {
+            out.push_str("\x1b[?2004h");
+        }
+        if self.cursor_style != 0 {
+            out.push_str(&format!("\x1b[{} q", self.cursor_style));
+        }
+        if !self.cursor_visible {
+            out.push_str("\x1b[?25l");
+        }
+        out
+    }
+
+    /// [`HtmlOptions`] using this terminal's palette and default colors.
+    pub fn html_options(&self) -> HtmlOptions {
+        HtmlOptions {
+            palette: self.color_palette,
+            foreground: self.default_fg_color.unwrap_or_else(default_fg_color),
+            background: self.default_bg_color.unwrap_or_else(default_bg_color),
+            ..HtmlOptions::default()
+        }
+    }
+
+    /// Export rows as a styled HTML `<pre>` block. Rows are numbered like
+    /// [`get_lines`](Self::get_lines), so a range can reach into scrollback;
+    /// rows past the end are ignored.
+    pub fn to_html(&self, rows: Range<usize>, options: &HtmlOptions) -> String {
+        html::grid_to_html(&self.internal_grid, rows, options)
+    }
+
+    /// Find `pattern` in scrollback and the active screen, oldest row first.
+    /// Fails only when `options.regex` is set and the pattern doesn't compile.
+    pub fn search(
+        &self,
+        pattern: &str,
+        options: SearchOptions,
+    ) -> Result<Vec<SearchMatch>, regex::Error> {
+        let mut matches = Vec::new();
+        if pattern.is_empty() {
+            return Ok(matches);
+        }
+        let regex = search::compile(pattern, options)?;
+        let rows = self
+            .internal_grid
+            .lines_above
+            .iter()
+            .chain(self.internal_grid.viewport.iter());
+        for (index, row) in rows.enumerate() {
+            search::search_row(&regex, index, row, &mut matches);
+        }
+        Ok(matches)
+    }
+
+    /// Detect URLs, `file:line:col` references and commit hashes in the
+    /// viewport. Soft-wrapped rows are scanned together so links broken
+    /// across a wrap are found whole.
+    pub fn viewport_links(&self) -> Vec<TerminalLink> {
+        links::detect_in_rows(self.internal_grid.viewport.iter())
+    }
+
+    /// A copy of the viewport's rows, for scanning with
+    /// [`detect_links`](crate::detect_links) away from the terminal.
+    pub fn viewport_rows(&self) -> Vec<Row> {
+        self.internal_grid.viewport.clone()
+    }
+
     /// Scroll the screen up by one line within the scroll region
     fn scroll_up(&mut self) {
         self.internal_grid.scroll_up_in_region(1);
@@ -1058,6 +2331,54 @@ impl VirtualTerminal {
     /// Move cursor to new line, scrolling if necessary
     fn newline(&mut self) {
         self.internal_grid.newline();
+        self.clamp_cursor_to_line();
+    }
+
+    /// Cells that fit on `row`: half the columns on double-width lines.
+    fn line_cols(&self, row: usize) -> usize {
+        self.line_size(row).columns(self.internal_grid.cols)
+    }
+
+    /// Keep the cursor inside the cells its line can show.
+    fn clamp_cursor_to_line(&mut self) {
+        let last = self.line_cols(self.internal_grid.cursor_row) - 1;
+        if self.internal_grid.cursor_col > last {
+            self.internal_grid.cursor_col = last;
+        }
+    }
+
+    /// DECDHL/DECDWL/DECSWL on the cursor's line. Going double width drops the
+    /// cells that no longer fit, as on a VT100.
+    fn set_line_size(&mut self, size: LineSize) {
+        let row = self.internal_grid.cursor_row;
+        let visible = size.columns(self.internal_grid.cols);
+        let Some(line) = self.internal_grid.get_row_mut(row) else {
+            return;
+        };
+        line.size = size;
+        for cell in line.columns.iter_mut().skip(visible) {
+            *cell = TerminalCharacter::default();
+        }
+        if let Some(last) = line.columns.get_mut(visible - 1) {
+            // A wide character cut in half can't be shown
+            if last.is_wide() {
+                *last = TerminalCharacter::default();
+            }
+        }
+        self.internal_grid.mark_line_changed(row);
+        self.pending_wrap = false;
+        self.clamp_cursor_to_line();
+    }
+
+    /// Auto-wrap to the start of the next line, marking it as a soft-wrapped
+    /// continuation of the current one
+    fn wrap_line(&mut self) {
+        self.internal_grid.cursor_col = 0;
+        self.newline();
+        let row = self.internal_grid.cursor_row;
+        if let Some(row) = self.internal_grid.get_row_mut(row) {
+            row.is_canonical = false;
+        }
     }
 
     /// Carriage return - move cursor to beginning of line
@@ -1067,11 +2388,16 @@ impl VirtualTerminal {
 
     /// Put a character at cursor position and advance
     fn put_char(&mut self, c: char) {
+        // Combining marks, ZWJ sequences and the like join the previous cell.
+        // No ASCII character continues a cluster, so plain text skips the check.
+        if !c.is_ascii() && self.extend_previous_grapheme(c) {
+            return;
+        }
+
         // Handle pending wrap from previous character at edge
         if self.pending_wrap {
             self.pending_wrap = false;
-            self.internal_grid.cursor_col = 0;
-            self.newline();
+            self.wrap_line();
         }
 
         // Apply line drawing character set if active
@@ -1085,17 +2411,24 @@ impl VirtualTerminal {
         self.last_printed_char = Some(display_char);
 
         // Create the terminal character
-        let character =
-            TerminalCharacter::new(display_char, self.internal_grid.current_shared_styles());
-        let char_width = character.width();
+        let char_width = self.ambiguous_width.char_width(display_char);
+        let character = TerminalCharacter::with_width(
+            display_char,
+            self.internal_grid.current_shared_styles(),
+            char_width as u8,
+        );
 
-        // Handle zero-width characters (combining chars, etc.) - just skip them for now
+        // Zero-width characters with nothing before them to attach to
         if char_width == 0 {
             return;
         }
 
+        // Double-width lines only hold half as many cells
+        self.clamp_cursor_to_line();
+        let line_cols = self.line_cols(self.internal_grid.cursor_row);
+
         // For wide characters, check if we have room for both cells
-        if char_width == 2 && self.internal_grid.cursor_col + 1 >= self.internal_grid.cols {
+        if char_width == 2 && self.internal_grid.cursor_col + 1 >= line_cols {
             if self.auto_wrap {
                 // Clear the current cell (it would be orphaned) and wrap
                 self.internal_grid.set_char(
@@ -1103,8 +2436,7 @@ impl VirtualTerminal {
                     self.internal_grid.cursor_col,
                     TerminalCharacter::default(),
                 );
-                self.internal_grid.cursor_col = 0;
-                self.newline();
+                self.wrap_line();
             } else {
                 // Can't fit, don't print
                 return;
@@ -1113,7 +2445,7 @@ impl VirtualTerminal {
 
         let cursor_row = self.internal_grid.cursor_row;
         let cursor_col = self.internal_grid.cursor_col;
-        let cols = self.internal_grid.cols;
+        let cols = self.line_cols(cursor_row);
 
         // Defensive bounds check
         if cursor_row < self.internal_grid.rows && cursor_col < cols {
@@ -1147,6 +2479,7 @@ impl VirtualTerminal {
             }
 
             // Place the character
+            self.internal_grid.stamp_row(cursor_row);
             self.internal_grid
                 .set_char(cursor_row, cursor_col, character);
 
@@ -1165,11 +2498,10 @@ impl VirtualTerminal {
                         }
                     }
                 }
-                self.internal_grid.set_char(
-                    cursor_row,
-                    cursor_col + 1,
-                    TerminalCharacter::wide_spacer(self.internal_grid.current_shared_styles()),
-                );
+                let spacer =
+                    TerminalCharacter::wide_spacer(self.internal_grid.current_shared_styles());
+                self.internal_grid
+                    .set_char(cursor_row, cursor_col + 1, spacer);
             }
 
             // Advance cursor
@@ -1185,6 +2517,79 @@ impl VirtualTerminal {
         }
     }
 
+    /// Append `c` to the grapheme cluster of the cell just printed (left of
+    /// the cursor, or under it while a wrap is pending) if it continues that
+    /// cluster. A cluster that becomes wide, like `❤` followed by VS16 or a
+    /// pair of regional indicators, takes the next cell too when there is
+    /// room. Returns whether `c` was consumed.
+    fn extend_previous_grapheme(&mut self, c: char) -> bool {
+        let row = self.internal_grid.cursor_row;
+        let mut col = self.internal_grid.cursor_col;
+        if !self.pending_wrap {
+            match col.checked_sub(1) {
+                Some(prev) => col = prev,
+                None => return false,
+            }
+        }
+        if col > 0
+            && self
+                .internal_grid
+                .get_char(row, col)
+                .is_some_and(|cell| cell.wide_spacer)
+        {
+            col -= 1;
+        }
+        let Some(mut cell) = self.internal_grid.get_char(row, col).cloned() else {
+            return false;
+        };
+        if cell.wide_spacer {
+            return false;
+        }
+        let mut cluster = cell.grapheme();
+        cluster.push(c);
+        if cluster.graphemes(true).nth(1).is_some() {
+            return false;
+        }
+        if cluster.len() > MAX_GRAPHEME_BYTES {
+            // Part of the cluster, but too long to keep
+            return true;
+        }
+
+        let old_width = cell.width();
+        let line_cols = self.line_cols(row);
+        let mut width = self.ambiguous_width.grapheme_width(&cluster).max(old_width);
+        if col + width > line_cols {
+            width = old_width;
+        }
+        cell.set_grapheme(&cluster, width as u8);
+        self.internal_grid.stamp_row(row);
+        self.internal_grid.set_char(row, col, cell);
+
+        if width > old_width {
+            // Widened in place: the next cell becomes its spacer
+            let spacer = col + 1;
+            if self
+                .internal_grid
+                .get_char(row, spacer + 1)
+                .is_some_and(|cell| cell.wide_spacer)
+            {
+                self.internal_grid
+                    .set_char(row, spacer + 1, TerminalCharacter::default());
+            }
+            let cell = TerminalCharacter::wide_spacer(self.internal_grid.current_shared_styles());
+            self.internal_grid.set_char(row, spacer, cell);
+            if spacer + 1 >= line_cols {
+                if self.auto_wrap {
+                    self.pending_wrap = true;
+                }
+                self.internal_grid.cursor_col = line_cols - 1;
+            } else {
+                self.internal_grid.cursor_col = spacer + 1;
+            }
+        }
+        true
+    }
+
     /// Check if line drawing character set is active
     fn is_line_drawing_active(&self) -> bool {
         if self.charset_index == 0 {
@@ -1356,12 +2761,32 @@ impl VirtualTerminal {
             _ => "\x1bP0$r\x1b\\".to_string(),
         };
 
-        self.pending_responses.push(response.into_bytes());
+        self.respond(response.into_bytes());
+    }
+
+    /// Handle XTGETTCAP (Request Termcap/Terminfo String)
+    /// Request: DCS + q Pt ST (Pt is hex-encoded capability names separated by ;)
+    /// Response, per name: DCS 1 + r Pt = Pv ST (Pv hex-encoded) when known,
+    ///                     DCS 0 + r Pt ST otherwise
+    fn handle_xtgettcap(&mut self) {
+        let request = String::from_utf8_lossy(&self.dcs_data).to_ascii_uppercase();
+        for name in request.split(';') {
+            let value = decode_hex(name).and_then(|name| termcap_value(&name));
+            let response = match value {
+                Some(value) => format!("\x1bP1+r{}={}\x1b\\", name, encode_hex(value)),
+                None => format!("\x1bP0+r{}\x1b\\", name),
+            };
+            self.respond(response.into_bytes());
+        }
     }
 
     /// Generate SGR parameter string for current attributes
     fn get_sgr_string(&self) -> String {
-        let styles = &self.internal_grid.current_styles;
+        self.sgr_string_for(&self.internal_grid.current_styles)
+    }
+
+    /// Generate SGR parameter string for the given attributes
+    fn sgr_string_for(&self, styles: &CharacterStyles) -> String {
         let mut params = vec!["0".to_string()]; // Always start with reset
 
         if styles.modifiers.contains(Modifier::BOLD) {
@@ -1460,8 +2885,15 @@ impl VirtualTerminal {
     /// Parse SGR (Select Graphic Rendition) parameters
     /// Handles both semicolon-separated (38;2;r;g;b) and colon-separated (38:2:r:g:b) formats
     fn apply_sgr(&mut self, params: &Params) {
-        // Collect params, preserving subparameters for extended color handling
-        let raw_params: Vec<&[u16]> = params.iter().collect();
+        // Collect params, preserving subparameters for extended color handling.
+        // vte caps a sequence at 32 parameters, so they fit on the stack.
+        let mut buf: [&[u16]; 32] = [&[]; 32];
+        let mut len = 0;
+        for (slot, param) in buf.iter_mut().zip(params.iter()) {
+            *slot = param;
+            len += 1;
+        }
+        let raw_params = &buf[..len];
 
         if raw_params.is_empty() {
             self.internal_grid
@@ -1602,6 +3034,7 @@ impl VirtualTerminal {
 
 impl Perform for VirtualTerminal {
     fn print(&mut self, c: char) {
+        self.cells_printed = self.cells_printed.wrapping_add(1);
         self.put_char(c);
     }
 
@@ -1648,7 +3081,7 @@ impl Perform for VirtualTerminal {
             }
             // Tab
             0x09 => {
-                self.tab_forward();
+                self.tab_forward(1);
             }
             // Line feed, vertical tab, form feed
             0x0A..=0x0C => {
@@ -1672,31 +3105,53 @@ impl Perform for VirtualTerminal {
         }
     }
 
-    fn hook(&mut self, _params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+    fn hook(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
         // DECRQSS - Request Status String (DCS $ q Pt ST)
         if intermediates.contains(&b'$') && action == 'q' {
             self.dcs_handler = DcsHandler::Decrqss;
             self.dcs_data.clear();
+        } else if intermediates == [b'+'] && action == 'q' {
+            self.dcs_handler = DcsHandler::Xtgettcap;
+            self.dcs_data.clear();
+        } else if intermediates.is_empty() && action == 'q' {
+            // Sixel; P2 = 1 leaves unset pixels transparent
+            let transparent = params.iter().nth(1).and_then(|p| p.first()) == Some(&1);
+            self.dcs_handler = DcsHandler::Sixel(Box::new(SixelDecoder::new(transparent)));
         } else {
             self.dcs_handler = DcsHandler::None;
         }
     }
 
     fn put(&mut self, byte: u8) {
-        // Accumulate bytes during DCS sequence
-        if !matches!(self.dcs_handler, DcsHandler::None) {
-            self.dcs_data.push(byte);
+        match &mut self.dcs_handler {
+            // Sixel data is decoded as it arrives rather than buffered
+            DcsHandler::Sixel(decoder) => decoder.put(byte),
+            DcsHandler::None => {}
+            // Accumulate bytes during DCS sequence; anything past the cap
+            // cannot be a valid request, so it is dropped rather than buffered
+            DcsHandler::Decrqss | DcsHandler::Xtgettcap => {
+                if self.dcs_data.len() < MAX_DCS_DATA {
+                    self.dcs_data.push(byte);
+                }
+            }
         }
     }
 
     fn unhook(&mut self) {
-        match self.dcs_handler {
+        match std::mem::take(&mut self.dcs_handler) {
             DcsHandler::Decrqss => {
                 self.handle_decrqss();
             }
+            DcsHandler::Xtgettcap => {
+                self.handle_xtgettcap();
+            }
+            DcsHandler::Sixel(decoder) => {
+                if let Some(image) = decoder.finish() {
+                    self.place_image(image);
+                }
+            }
             DcsHandler::None => {}
         }
-        self.dcs_handler = DcsHandler::None;
         self.dcs_data.clear();
     }
 
@@ -1708,11 +3163,15 @@ impl Perform for VirtualTerminal {
         let cmd = params[0];
         if let Ok(cmd_str) = std::str::from_utf8(cmd) {
             match cmd_str {
-                // Window title (OSC 0 and OSC 2)
-                "0" | "2" => {
-                    if params.len() > 1 {
-                        if let Ok(title) = std::str::from_utf8(params[1]) {
-                            self.title = Some(title.to_string());
+                // Icon name and window title (OSC 0), icon name (OSC 1) and
+                // window title (OSC 2); the text may contain `;`
+                "0" | "1" | "2" if params.len() > 1 => {
+                    if let Ok(text) = std::str::from_utf8(&params[1..].join(&b';')) {
+                        if cmd_str != "2" {
+                            self.set_icon_name(text);
+                        }
+                        if cmd_str != "1" {
+                            self.set_title(text);
                         }
                     }
                 }
@@ -1739,10 +3198,13 @@ impl Perform for VirtualTerminal {
                                             (g as u16) * 257,
                                             (b as u16) * 257
                                         );
-                                        self.pending_responses.push(response.into_bytes());
+                                        self.respond(response.into_bytes());
                                     } else if let Some(color) = parse_osc_color(color_str) {
                                         // Set palette color
-                                        self.color_palette[index] = Some(color);
+                                        self.set_color(
+                                            ColorSlot::Palette(index as u8),
+                                            Some(color),
+                                        );
                                     }
                                 } else if index >= 256 {
                                     // Special colors: 256=fg, 257=bg, 258=cursor
@@ -1761,12 +3223,12 @@ impl Perform for VirtualTerminal {
                                             (color.1 as u16) * 257,
                                             (color.2 as u16) * 257
                                         );
-                                        self.pending_responses.push(response.into_bytes());
+                                        self.respond(response.into_bytes());
                                     } else if let Some(color) = parse_osc_color(color_str) {
                                         match special_index {
-                                            0 => self.default_fg_color = Some(color),
-                                            1 => self.default_bg_color = Some(color),
-                                            2 => self.cursor_color = Some(color),
+                                            0 => self.set_color(ColorSlot::Foreground, Some(color)),
+                                            1 => self.set_color(ColorSlot::Background, Some(color)),
+                                            2 => self.set_color(ColorSlot::Cursor, Some(color)),
                                             _ => {}
                                         }
                                     }
@@ -1801,12 +3263,12 @@ impl Perform for VirtualTerminal {
                                         (color.1 as u16) * 257,
                                         (color.2 as u16) * 257
                                     );
-                                    self.pending_responses.push(response.into_bytes());
+                                    self.respond(response.into_bytes());
                                 } else if let Some(color) = parse_osc_color(color_str) {
                                     match index {
-                                        0 => self.default_fg_color = Some(color),
-                                        1 => self.default_bg_color = Some(color),
-                                        2 => self.cursor_color = Some(color),
+                                        0 => self.set_color(ColorSlot::Foreground, Some(color)),
+                                        1 => self.set_color(ColorSlot::Background, Some(color)),
+                                        2 => self.set_color(ColorSlot::Cursor, Some(color)),
                                         _ => {}
                                     }
                                 }
@@ -1837,13 +3299,13 @@ impl Perform for VirtualTerminal {
                                     (g as u16) * 257,
                                     (b as u16) * 257
                                 );
-                                self.pending_responses.push(response.into_bytes());
+                                self.respond(response.into_bytes());
                             } else if let Some(color) = parse_osc_color(color_str) {
                                 // Set this dynamic color
                                 match color_index {
-                                    10 => self.default_fg_color = Some(color),
-                                    11 => self.default_bg_color = Some(color),
-                                    12 => self.cursor_color = Some(color),
+                                    10 => self.set_color(ColorSlot::Foreground, Some(color)),
+                                    11 => self.set_color(ColorSlot::Background, Some(color)),
+                                    12 => self.set_color(ColorSlot::Cursor, Some(color)),
                                     _ => {}
                                 }
                             }
@@ -1870,12 +3332,12 @@ impl Perform for VirtualTerminal {
                                     (g as u16) * 257,
                                     (b as u16) * 257
                                 );
-                                self.pending_responses.push(response.into_bytes());
+                                self.respond(response.into_bytes());
                             } else if let Some(color) = parse_osc_color(color_str) {
                                 // Set this dynamic color
                                 match color_index {
-                                    11 => self.default_bg_color = Some(color),
-                                    12 => self.cursor_color = Some(color),
+                                    11 => self.set_color(ColorSlot::Background, Some(color)),
+                                    12 => self.set_color(ColorSlot::Cursor, Some(color)),
                                     _ => {}
                                 }
                             }
@@ -1884,53 +3346,93 @@ impl Perform for VirtualTerminal {
                 }
                 // OSC 110 - Reset default foreground color to terminal default
                 "110" => {
-                    self.default_fg_color = None;
+                    self.set_color(ColorSlot::Foreground, None);
                 }
                 // OSC 111 - Reset default background color to terminal default
                 "111" => {
-                    self.default_bg_color = None;
+                    self.set_color(ColorSlot::Background, None);
                 }
                 // OSC 12 - Query/Set cursor color
-                "12" => {
-                    if params.len() > 1 {
-                        if let Ok(color_str) = std::str::from_utf8(params[1]) {
-                            if color_str == "?" {
-                                // Query - respond with current cursor color (default to white if not set)
-                                let (r, g, b) = self.cursor_color.unwrap_or((255, 255, 255));
-                                let response = format!(
-                                    "\x1b]12;rgb:{:04x}/{:04x}/{:04x}\x1b\\",
-                                    (r as u16) * 257,
-                                    (g as u16) * 257,
-                                    (b as u16) * 257
-                                );
-                                self.pending_responses.push(response.into_bytes());
-                            } else if color_str == "default" {
-                                // Special value "default" resets cursor color
-                                self.cursor_color = None;
-                            } else if let Some(color) = parse_osc_color(color_str) {
-                                // Set cursor color
-                                self.cursor_color = Some(color);
-                            }
+                "12" if params.len() > 1 => {
+                    if let Ok(color_str) = std::str::from_utf8(params[1]) {
+                        if color_str == "?" {
+                            // Query - respond with current cursor color (default to white if not set)
+                            let (r, g, b) = self.cursor_color.unwrap_or((255, 255, 255));
+                            let response = format!(
+                                "\x1b]12;rgb:{:04x}/{:04x}/{:04x}\x1b\\",
+                                (r as u16) * 257,
+                                (g as u16) * 257,
+                                (b as u16) * 257
+                            );
+                            self.respond(response.into_bytes());
+                        } else if color_str == "default" {
+                            // Special value "default" resets cursor color
+                            self.set_color(ColorSlot::Cursor, None);
+                        } else if let Some(color) = parse_osc_color(color_str) {
+                            // Set cursor color
+                            self.set_color(ColorSlot::Cursor, Some(color));
+                        }
+                    }
+                }
+                // OSC 133 - Shell integration (FinalTerm semantic prompts)
+                "133" => {
+                    let kind = match params.get(1).copied() {
+                        Some(b"A") => Some(SemanticMarkKind::PromptStart),
+                        Some(b"B") => Some(SemanticMarkKind::InputStart),
+                        Some(b"C") => Some(SemanticMarkKind::OutputStart),
+                        Some(b"D") => Some(SemanticMarkKind::CommandFinished {
+                            exit_code: params
+                                .get(2)
+                                .and_then(|code| std::str::from_utf8(code).ok())
+                                .and_then(|code| code.parse().ok()),
+                        }),
+                        _ => None,
+                    };
+                    if let Some(kind) = kind {
+                        self.push_semantic_mark(kind);
+                    }
+                }
+                // OSC 7 - Working directory as a file:// URL; a path may
+                // contain `;`, so rejoin the split parameters
+                "7" => {
+                    let url = params[1..].join(&b';');
+                    if let Some(cwd) = parse_osc7_cwd(&url) {
+                        if self.cwd.as_ref() != Some(&cwd) {
+                            self.cwd_callback.call(&cwd);
+                            self.cwd = Some(cwd);
                         }
                     }
                 }
+                // OSC 1337 File= - iTerm2 inline image; the base64 payload
+                // may itself contain `;`, so rejoin the split parameters
+                "1337" => {
+                    let payload = params[1..].join(&b';');
+                    let (rows, cols) = (self.internal_grid.rows, self.internal_grid.cols);
+                    if let Some(image) =
+                        parse_iterm2_file(&payload, self.cell_pixel_size, cols, rows)
+                    {
+                        self.place_image(image);
+                    }
+                }
                 // OSC 112 - Reset cursor color to terminal default
                 "112" => {
-                    self.cursor_color = None;
+                    self.set_color(ColorSlot::Cursor, None);
                 }
                 // OSC 104 - Reset palette color(s) to default
                 // Format: OSC 104 ; index ST (reset specific) or OSC 104 ST (reset all)
                 "104" => {
                     if params.len() == 1 {
                         // No index specified - reset all palette colors
-                        self.color_palette = [None; 256];
+                        for index in 0..=255 {
+                            self.set_color(ColorSlot::Palette(index), None);
+                        }
                     } else {
                         // Reset specific indices
                         for param in params.iter().skip(1) {
                             if let Ok(index_str) = std::str::from_utf8(param) {
                                 if let Ok(index) = index_str.parse::<usize>() {
                                     if index < 256 {
-                                        self.color_palette[index] = None;
+                                        self.set_color(ColorSlot::Palette(index as u8), None);
                                     }
                                 }
                             }
@@ -1942,17 +3444,17 @@ impl Perform for VirtualTerminal {
                 "105" => {
                     if params.len() == 1 {
                         // No index - reset all special colors
-                        self.default_fg_color = None;
-                        self.default_bg_color = None;
-                        self.cursor_color = None;
+                        self.set_color(ColorSlot::Foreground, None);
+                        self.set_color(ColorSlot::Background, None);
+                        self.set_color(ColorSlot::Cursor, None);
                     } else {
                         for param in params.iter().skip(1) {
                             if let Ok(index_str) = std::str::from_utf8(param) {
                                 if let Ok(index) = index_str.parse::<usize>() {
                                     match index {
-                                        0 => self.default_fg_color = None,
-                                        1 => self.default_bg_color = None,
-                                        2 => self.cursor_color = None,
+                                        0 => self.set_color(ColorSlot::Foreground, None),
+                                        1 => self.set_color(ColorSlot::Background, None),
+                                        2 => self.set_color(ColorSlot::Cursor, None),
                                         _ => {}
                                     }
                                 }
@@ -2123,7 +3625,7 @@ impl Perform for VirtualTerminal {
                 match mode {
                     5 => {
                         // Status Report - respond with "OK" (CSI 0 n)
-                        self.pending_responses.push(b"\x1b[0n".to_vec());
+                        self.respond(b"\x1b[0n".to_vec());
                     }
                     6 => {
                         // Cursor Position Report (CPR)
@@ -2141,7 +3643,7 @@ impl Perform for VirtualTerminal {
                             )
                         };
                         let response = format!("\x1b[{};{}R", row, col);
-                        self.pending_responses.push(response.into_bytes());
+                        self.respond(response.into_bytes());
                     }
                     _ => {}
                 }
@@ -2170,15 +3672,14 @@ impl Perform for VirtualTerminal {
                     // 22 = ANSI color
                     // 28 = rectangular editing
                     // 29 = ANSI text locator
-                    self.pending_responses
-                        .push(b"\x1b[?64;1;2;6;9;15;16;17;18;21;22;28;29c".to_vec());
+                    self.respond(b"\x1b[?64;1;2;6;9;15;16;17;18;21;22;28;29c".to_vec());
                 } else if intermediates == [b'>'] && is_query {
                     // Secondary Device Attributes (DA2): CSI > c or CSI > 0 c
                     // Respond as xterm version 314+:
                     // 41 = xterm terminal type
                     // 354 = version number (xterm 354+)
                     // 0 = ROM cartridge registration number (always 0)
-                    self.pending_responses.push(b"\x1b[>41;354;0c".to_vec());
+                    self.respond(b"\x1b[>41;354;0c".to_vec());
                 }
                 // DA1 responses (CSI ? params c) and DA2 responses (CSI > params c)
                 // are silently consumed - they have intermediates but multiple params
@@ -2202,7 +3703,8 @@ impl Perform for VirtualTerminal {
                 self.internal_grid.cursor_col = 0;
             }
             // DECSLRM (set left/right margin) or save cursor (ANSI.SYS style)
-            's' => {
+            // (private-marker forms like XTSAVE `CSI ? s` are not cursor saves)
+            's' if intermediates.is_empty() => {
                 if self.enable_left_right_margins {
                     // DECSLRM - Set Left and Right Margins
                     let left = params_vec.first().copied().unwrap_or(1).max(1) as usize;
@@ -2230,9 +3732,30 @@ impl Perform for VirtualTerminal {
                 }
             }
             // Restore cursor position (ANSI.SYS style)
-            'u' => {
+            'u' if intermediates.is_empty() => {
                 self.restore_cursor();
             }
+            // Kitty keyboard protocol: push, pop, set and query flags
+            'u' => {
+                let first = params_vec.first().copied().unwrap_or(0);
+                match intermediates {
+                    [b'>'] => self.keyboard_flags.push(first),
+                    [b'<'] => self.keyboard_flags.pop(first),
+                    [b'='] => self
+                        .keyboard_flags
+                        .set(first, params_vec.get(1).copied().unwrap_or(1)),
+                    [b'?'] => {
+                        let response = format!("\x1b[?{}u", self.keyboard_flags.current());
+                        self.respond(response.into_bytes());
+                    }
+                    _ => {}
+                }
+            }
+            // Cursor Forward Tabulation (CHT)
+            'I' => {
+                let n = params_vec.first().copied().unwrap_or(1).max(1) as usize;
+                self.tab_forward(n);
+            }
             // Cursor Backward Tabulation (CBT)
             'Z' => {
                 let n = params_vec.first().copied().unwrap_or(1).max(1) as usize;
@@ -2252,6 +3775,20 @@ impl Perform for VirtualTerminal {
                     _ => {}
                 }
             }
+            // Cursor Tabulation Control (CTC)
+            'W' if intermediates.is_empty() => {
+                let mode = params_vec.first().copied().unwrap_or(0);
+                match mode {
+                    0 => self.set_tab_stop_at_cursor(),
+                    2 => self.clear_tab_stop_at_cursor(),
+                    5 => self.clear_all_tab_stops(),
+                    _ => {}
+                }
+            }
+            // Set tab stops every 8 columns (DECST8C)
+            'W' if intermediates == [b'?'] && params_vec.first() == Some(&5) => {
+                self.reset_tab_stops();
+            }
             // Insert Characters (ICH)
             '@' => {
                 let n = params_vec.first().copied().unwrap_or(1).max(1) as usize;
@@ -2295,113 +3832,26 @@ impl Perform for VirtualTerminal {
                                 // Alternate screen buffer (save cursor + switch)
                                 // Per xterm, mode 1049 combines 1047 (alt screen) + 1048 (save/restore cursor)
                                 if enable {
-                                    // Only enter if not already in alternate screen
-                                    // (prevents losing main screen if app sends 1049h twice)
-                                    if self.alternate_screen.is_none() {
-                                        self.alternate_screen = Some(Box::new(AlternateScreen {
-                                            grid: self.internal_grid.clone(),
-                                            cursor_row: self.internal_grid.cursor_row,
-                                            cursor_col: self.internal_grid.cursor_col,
-                                            current_styles: self.internal_grid.current_styles,
-                                            // Save terminal modes that affect cursor positioning
-                                            origin_mode: self.origin_mode,
-                                            auto_wrap: self.auto_wrap,
-                                            pending_wrap: self.pending_wrap,
-                                            // Save cursor visibility (per-screen state)
-                                            cursor_visible: self.cursor_visible,
-                                            cursor_blink: self.cursor_blink,
-                                            // Save charset state
-                                            charset_index: self.charset_index,
-                                            g0_charset_line_drawing: self.g0_charset_line_drawing,
-                                            g1_charset_line_drawing: self.g1_charset_line_drawing,
-                                        }));
-                                        // Clear any saved cursor from before alt screen - it's now stale
-                                        self.saved_cursor = None;
-                                        let rows = self.internal_grid.rows;
-                                        let cols = self.internal_grid.cols;
-                                        self.internal_grid = Grid::new(rows, cols);
-                                        self.alt_screen_toggled = true;
-                                    }
-                                } else if let Some(saved) = self.alternate_screen.take() {
-                                    // Resize saved grid to current dimensions if needed
-                                    let mut restored = saved.grid;
-                                    restored
-                                        .resize(self.internal_grid.rows, self.internal_grid.cols);
-                                    self.internal_grid = restored;
-                                    // Mark all lines as changed to force full redraw
-                                    // (resize only marks changed if dimensions actually change)
-                                    self.internal_grid.mark_all_changed();
-                                    self.internal_grid.cursor_row = saved
-                                        .cursor_row
-                                        .min(self.internal_grid.rows.saturating_sub(1));
-                                    self.internal_grid.cursor_col = saved
-                                        .cursor_col
-                                        .min(self.internal_grid.cols.saturating_sub(1));
-                                    self.internal_grid.set_current_styles(saved.current_styles);
-                                    // Restore terminal modes
-                                    self.origin_mode = saved.origin_mode;
-                                    self.auto_wrap = saved.auto_wrap;
-                                    self.pending_wrap = saved.pending_wrap;
-                                    // Restore cursor visibility
-                                    self.cursor_visible = saved.cursor_visible;
-                                    self.cursor_blink = saved.cursor_blink;
-                                    // Restore charset state
-                                    self.charset_index = saved.charset_index;
-                                    self.g0_charset_line_drawing = saved.g0_charset_line_drawing;
-                                    self.g1_charset_line_drawing = saved.g1_charset_line_drawing;
-                                    // Clear saved_cursor instead of restoring stale state
-                                    // When a TUI exits, any cursor position it saved before entering
-                                    // alt screen is no longer relevant. If we restore it, subsequent
-                                    // commands that call RESTORE_CURSOR (like codex) would jump to
-                                    // that stale position.
-                                    self.saved_cursor = None;
-                                    self.alt_screen_toggled = true;
+                                    self.enter_alternate_screen();
+                                } else {
+                                    self.leave_alternate_screen(true);
                                 }
                             }
                             47 | 1047 => {
                                 // Alternate screen buffer (without save cursor)
                                 // Per xterm, mode 47/1047 switches screen but doesn't save/restore cursor
                                 if enable {
-                                    // Only enter if not already in alternate screen
-                                    if self.alternate_screen.is_none() {
-                                        self.alternate_screen = Some(Box::new(AlternateScreen {
-                                            grid: self.internal_grid.clone(),
-                                            cursor_row: self.internal_grid.cursor_row,
-                                            cursor_col: self.internal_grid.cursor_col,
-                                            current_styles: self.internal_grid.current_styles,
-                                            // Save terminal modes (struct fields required)
-                                            origin_mode: self.origin_mode,
-                                            auto_wrap: self.auto_wrap,
-                                            pending_wrap: self.pending_wrap,
-                                            cursor_visible: self.cursor_visible,
-                                            cursor_blink: self.cursor_blink,
-                                            charset_index: self.charset_index,
-                                            g0_charset_line_drawing: self.g0_charset_line_drawing,
-                                            g1_charset_line_drawing: self.g1_charset_line_drawing,
-                                        }));
-                                        // Clear any saved cursor from before alt screen - it's now stale
-                                        self.saved_cursor = None;
-                                        let rows = self.internal_grid.rows;
-                                        let cols = self.internal_grid.cols;
-                                        self.internal_grid = Grid::new(rows, cols);
-                                        self.alt_screen_toggled = true;
-                                    }
-                                } else if let Some(saved) = self.alternate_screen.take() {
-                                    let mut restored = saved.grid;
-                                    restored
-                                        .resize(self.internal_grid.rows, self.internal_grid.cols);
-                                    self.internal_grid = restored;
-                                    // Mark all lines as changed to force full redraw
-                                    // (resize only marks changed if dimensions actually change)
-                                    self.internal_grid.mark_all_changed();
-                                    // Note: modes 47/1047 don't restore cursor position or terminal modes
-                                    // Only the grid content is restored
-                                    // But we do restore cursor visibility
-                                    self.cursor_visible = saved.cursor_visible;
-                                    self.cursor_blink = saved.cursor_blink;
-                                    // Clear saved_cursor instead of restoring stale state
-                                    self.saved_cursor = None;
-                                    self.alt_screen_toggled = true;
+                                    self.enter_alternate_screen();
+                                } else {
+                                    self.leave_alternate_screen(false);
+                                }
+                            }
+                            1048 => {
+                                // Save/restore cursor as in DECSC/DECRC
+                                if enable {
+                                    self.save_cursor();
+                                } else {
+                                    self.restore_cursor();
                                 }
                             }
                             2004 => {
@@ -2420,6 +3870,10 @@ impl Perform for VirtualTerminal {
                                 // SGR extended mouse mode
                                 self.sgr_mouse_mode = enable;
                             }
+                            1004 => {
+                                // Focus event reporting
+                                self.focus_events = enable;
+                            }
                             45 => {
                                 // Reverse wraparound mode
                                 self.reverse_wraparound = enable;
@@ -2457,14 +3911,25 @@ impl Perform for VirtualTerminal {
             // Window manipulation (XTERM_WINOPS) - CSI Ps t
             't' => {
                 let op = params_vec.first().copied().unwrap_or(0);
-                if op == 18 {
-                    // Report text area size in characters
-                    // Response: CSI 8 ; height ; width t
-                    let response = format!(
+                let (cell_width, cell_height) = self.cell_pixel_size;
+                let response = match op {
+                    // Report text area size in pixels: CSI 4 ; height ; width t
+                    14 => Some(format!(
+                        "\x1b[4;{};{}t",
+                        self.internal_grid.rows * cell_height as usize,
+                        self.internal_grid.cols * cell_width as usize
+                    )),
+                    // Report cell size in pixels: CSI 6 ; height ; width t
+                    16 => Some(format!("\x1b[6;{cell_height};{cell_width}t")),
+                    // Report text area size in characters: CSI 8 ; height ; width t
+                    18 => Some(format!(
                         "\x1b[8;{};{}t",
                         self.internal_grid.rows, self.internal_grid.cols
-                    );
-                    self.pending_responses.push(response.into_bytes());
+                    )),
+                    _ => None,
+                };
+                if let Some(response) = response {
+                    self.respond(response.into_bytes());
                 }
             }
             // DECRQCRA - Request Checksum of Rectangular Area
@@ -2490,7 +3955,7 @@ impl Perform for VirtualTerminal {
 
                 // Response: DCS Pid ! ~ XXXX ST (where XXXX is 4-digit hex checksum)
                 let response = format!("\x1bP{}!~{:04X}\x1b\\", pid, checksum);
-                self.pending_responses.push(response.into_bytes());
+                self.respond(response.into_bytes());
             }
             // DECRQM - Request Mode (CSI Ps $ p for ANSI, CSI ? Ps $ p for DEC)
             // Note: intermediates order may vary in vte-rs, so check contains
@@ -2525,6 +3990,14 @@ impl Perform for VirtualTerminal {
                                 2
                             }
                         }
+                        12 => {
+                            // Cursor blink
+                            if self.cursor_blink {
+                                1
+                            } else {
+                                2
+                            }
+                        }
                         25 => {
                             // DECTCEM - Cursor Visible
                             if self.cursor_visible {
@@ -2565,6 +4038,22 @@ impl Perform for VirtualTerminal {
                                 2
                             }
                         }
+                        1004 => {
+                            // Focus events
+                            if self.focus_events {
+                                1
+                            } else {
+                                2
+                            }
+                        }
+                        1006 => {
+                            // SGR mouse encoding
+                            if self.sgr_mouse_mode {
+                                1
+                            } else {
+                                2
+                            }
+                        }
                         2004 => {
                             // Bracketed paste
                             if self.bracketed_paste {
@@ -2637,7 +4126,7 @@ impl Perform for VirtualTerminal {
                 } else {
                     format!("\x1b[{};{}$y", mode, status)
                 };
-                self.pending_responses.push(response.into_bytes());
+                self.respond(response.into_bytes());
             }
             // DECFRA - Fill Rectangular Area: CSI Pc ; Pt ; Pl ; Pb ; Pr $ x
             'x' if intermediates == [b'$'] => {
@@ -2689,7 +4178,7 @@ impl Perform for VirtualTerminal {
                     let shared_styles = self.internal_grid.current_shared_styles();
                     for row in top..=bottom {
                         for col in left..=right {
-                            let character = TerminalCharacter::new(ch, shared_styles.clone());
+                            let character = TerminalCharacter::new(ch, shared_styles);
                             self.internal_grid.set_char(row, col, character);
                         }
                     }
@@ -2767,6 +4256,7 @@ impl Perform for VirtualTerminal {
             'A' | 'B' | 'C' | 'D' | 'E' | 'F' | 'G' | 'H' | 'f' | 'd' | '`'
         ) {
             self.pending_wrap = false;
+            self.clamp_cursor_to_line();
         }
     }
 
@@ -2784,7 +4274,26 @@ impl Perform for VirtualTerminal {
             ([], b'c') => {
                 let rows = self.internal_grid.rows;
                 let cols = self.internal_grid.cols;
-                *self = VirtualTerminal::new(rows, cols);
+                let mut old = std::mem::replace(self, VirtualTerminal::new(rows, cols));
+                // Embedder hooks outlive the reset
+                self.title_callback = old.title_callback.clone();
+                self.icon_name_callback = old.icon_name_callback.clone();
+                self.cwd_callback = old.cwd_callback.clone();
+                self.query_responder = old.query_responder.clone();
+                self.color_change_callback = old.color_change_callback.clone();
+                // So does the encoding, which the application can't switch back
+                self.input_encoding = old.input_encoding;
+                let slots = (0..=255).map(ColorSlot::Palette).chain([
+                    ColorSlot::Foreground,
+                    ColorSlot::Background,
+                    ColorSlot::Cursor,
+                ]);
+                for slot in slots {
+                    if old.color_slot_mut(slot).is_some() {
+                        self.color_change_callback
+                            .call(&ColorChange { slot, color: None });
+                    }
+                }
             }
             // Index - move down one line, scroll if at bottom
             ([], b'D') => {
@@ -2806,7 +4315,13 @@ impl Perform for VirtualTerminal {
                 } else {
                     self.internal_grid.cursor_row = self.internal_grid.cursor_row.saturating_sub(1);
                 }
+                self.clamp_cursor_to_line();
             }
+            // Line size (DECDHL top/bottom, DECSWL, DECDWL)
+            ([b'#'], b'3') => self.set_line_size(LineSize::DoubleHeightTop),
+            ([b'#'], b'4') => self.set_line_size(LineSize::DoubleHeightBottom),
+            ([b'#'], b'5') => self.set_line_size(LineSize::Normal),
+            ([b'#'], b'6') => self.set_line_size(LineSize::DoubleWidth),
             // G0 charset designations
             ([b'('], b'0') => {
                 self.g0_charset_line_drawing = true;
@@ -2837,6 +4352,7 @@ impl Perform for VirtualTerminal {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::image::ImageData;
 
     #[test]
     fn virtual_terminal_handles_basic_text() {
@@ -2881,4 +4397,1001 @@ mod tests {
         assert_eq!(term.cols(), 100);
         assert_eq!(term.get_cell(0, 0).c, 'T');
     }
+
+    #[test]
+    fn capture_joins_soft_wrapped_lines() {
+        let mut term = VirtualTerminal::new(4, 10);
+        term.process(b"echo abcdefghij\r\nok");
+        assert!(term.is_wrapped(1));
+        assert!(!term.is_wrapped(2));
+        assert_eq!(term.capture(false), "echo abcde\nfghij\nok\n");
+        assert_eq!(term.capture(true), "echo abcdefghij\nok\n");
+    }
+
+    #[test]
+    fn viewport_links_span_soft_wraps_and_skip_styling() {
+        let mut term = VirtualTerminal::new(4, 12);
+        term.process(
+            b"err [1msrc/lib.rs[0m:7:3
+https://x.io",
+        );
+        let links = term.viewport_links();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].text, "src/lib.rs:7:3");
+        assert_eq!(
+            (links[0].start_row, links[0].start_col),
+            (0, 4),
+            "starts after the prefix"
+        );
+        assert_eq!((links[0].end_row, links[0].end_col), (1, 6));
+        assert_eq!(
+            links[0].kind,
+            crate::LinkKind::File {
+                path: "src/lib.rs".into(),
+                line: Some(7),
+                column: Some(3)
+            }
+        );
+        assert_eq!(links[1].kind, crate::LinkKind::Url);
+        assert_eq!((links[1].start_row, links[1].end_col), (2, 12));
+    }
+
+    #[test]
+    fn grid_spans_cover_scrollback_and_wraps_into_the_viewport() {
+        let mut term = VirtualTerminal::new(2, 10);
+        term.process(b"commit 9fceb02d\r\nat app/server.ts:42:7");
+        assert_eq!(term.scrollback_len(), 3);
+
+        let spans = term.grid().detect_spans();
+        let found: Vec<_> = spans
+            .iter()
+            .map(|s| {
+                (
+                    s.text.as_str(),
+                    s.start_row,
+                    s.start_col,
+                    s.end_row,
+                    s.end_col,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [("9fceb02d", 0, 7, 1, 5), ("app/server.ts:42:7", 2, 3, 4, 1)]
+        );
+        assert_eq!(spans[0].kind, crate::LinkKind::Sha);
+        // The viewport alone only sees the tail of the wrapped path
+        assert_eq!(term.viewport_links()[0].text, "ver.ts:42:7");
+    }
+
+    #[test]
+    fn to_ansi_string_keeps_styles_across_joined_lines() {
+        let mut term = VirtualTerminal::new(2, 4);
+        term.process(b"ab\x1b[31mcdef\x1b[0m");
+        assert_eq!(term.to_ansi_string(true), "ab\x1b[0;31mcdef\x1b[0m");
+        assert_eq!(term.to_ansi_string(false), "ab\x1b[0;31mcd\r\nef\x1b[0m");
+    }
+
+    #[test]
+    fn search_covers_scrollback_and_screen() {
+        let mut term = VirtualTerminal::new(2, 20);
+        term.process(b"error: one\r\nok\r\nERROR two\r\n\xe5\xad\x97error");
+        assert_eq!(term.scrollback_len(), 2);
+
+        let found = term.search("error", SearchOptions::default()).unwrap();
+        assert_eq!(
+            found,
+            [
+                SearchMatch {
+                    row: 0,
+                    col: 0,
+                    len: 5
+                },
+                // After a wide character, columns count display cells
+                SearchMatch {
+                    row: 3,
+                    col: 2,
+                    len: 5
+                },
+            ]
+        );
+
+        let insensitive = SearchOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert_eq!(term.search("error", insensitive).unwrap().len(), 3);
+
+        let regex = SearchOptions {
+            regex: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            term.search(r"[A-Z]+ \w+", regex).unwrap(),
+            [SearchMatch {
+                row: 2,
+                col: 0,
+                len: 9
+            }]
+        );
+        // Literal mode doesn't interpret metacharacters
+        assert!(term
+            .search("o.e", SearchOptions::default())
+            .unwrap()
+            .is_empty());
+        assert!(term.search("(", regex).is_err());
+        assert!(term.search("", regex).unwrap().is_empty());
+    }
+
+    #[test]
+    fn repeated_sgr_reuses_interned_styles() {
+        let mut term = VirtualTerminal::new(4, 20);
+        for _ in 0..100 {
+            term.process(b"\x1b[H\x1b[1;31mred\x1b[0m \x1b[32mgreen\x1b[0m");
+        }
+        assert_eq!(term.style_stats().unique_styles, 2);
+
+        // Once the styled text is cleared, compaction frees its styles
+        term.process(b"\x1b[2J");
+        assert_eq!(term.compact_styles(), 2);
+        assert_eq!(term.style_stats().unique_styles, 0);
+    }
+
+    #[test]
+    fn sgr_runs_are_interned_once() {
+        let mut term = VirtualTerminal::new(4, 20);
+        // Only the style a cell ends up with is interned, not each step to it
+        term.process(b"\x1b[1m\x1b[31m\x1b[4;48;2;1;2;3mx\x1b[7m\x1b[0m\x1b[32m");
+        assert_eq!(term.style_stats().unique_styles, 1);
+        let cell = term.internal_grid.get_char(0, 0).unwrap();
+        let styles = term.internal_grid.style_table.get(cell.styles);
+        assert_eq!(styles.background, Some(Color::Rgb(1, 2, 3)));
+    }
+
+    #[test]
+    fn line_drawing_and_cp437_render_box_characters() {
+        let mut term = VirtualTerminal::new(3, 10);
+        term.process(b"\x1b(0lqqk\x1b(B\r\n\x1b)0x\x0e`osx\x0f");
+        assert_eq!(term.viewport_lines()[0], "┌──┐");
+        assert_eq!(term.viewport_lines()[1], "x◆⎺⎽│");
+
+        // Without CP437 the high bytes are invalid UTF-8
+        term.process(b"\x1b[3;1H\xc9\xcd\xbb");
+        assert!(term.viewport_lines()[2].starts_with('\u{fffd}'));
+        term.set_input_encoding(InputEncoding::Cp437);
+        term.process(b"\x1b[3;1H\x1b[1;34m\xc9\xcd\xbb \x9b\xb0\xdb");
+        assert_eq!(term.viewport_lines()[2], "╔═╗ ¢░█");
+        let cell = term.internal_grid.get_char(2, 0).unwrap();
+        let styles = term.internal_grid.style_table.get(cell.styles);
+        assert_eq!(styles.foreground, Some(Color::Blue));
+
+        // The encoding is the embedder's choice, so it survives RIS
+        term.process(b"\x1bc\xc4");
+        assert_eq!(term.viewport_lines()[0], "─");
+    }
+
+    #[test]
+    fn cursor_style_follows_decscusr_and_dectcem() {
+        let mut term = VirtualTerminal::new(3, 10);
+        let style = |shape, blinking, visible| CursorStyle {
+            shape,
+            blinking,
+            visible,
+        };
+        assert_eq!(term.cursor_style(), style(CursorShape::Block, true, true));
+        term.process(b"\x1b[6 q");
+        assert_eq!(term.cursor_style(), style(CursorShape::Bar, false, true));
+        term.process(b"\x1b[3 q\x1b[?25l");
+        assert_eq!(
+            term.cursor_style(),
+            style(CursorShape::Underline, true, false)
+        );
+        // Mode 12 toggles blinking without changing the shape
+        term.process(b"\x1b[?12l\x1b[?25h");
+        assert_eq!(
+            term.cursor_style(),
+            style(CursorShape::Underline, false, true)
+        );
+        term.process(b"\x1b[ q");
+        assert_eq!(term.cursor_style(), style(CursorShape::Block, true, true));
+    }
+
+    #[test]
+    fn tabs_follow_custom_stops() {
+        let mut term = VirtualTerminal::new(3, 20);
+        assert_eq!(term.tab_stops(), [8, 16]);
+        // Clear all stops and set 3 and 11 with HTS
+        term.process(b"\x1b[3g\x1b[4G\x1bH\x1b[12G\x1bH\r");
+        assert_eq!(term.tab_stops(), [3, 11]);
+        term.process(b"a\tb\tc\td");
+        assert_eq!(term.get_cell(0, 3).c, 'b');
+        assert_eq!(term.get_cell(0, 11).c, 'c');
+        // Past the last stop HT goes to the end of the line
+        assert_eq!(term.get_cell(0, 19).c, 'd');
+
+        // CHT and CBT move several stops; TBC 0 and CTC edit single stops
+        term.process(b"\r\x1b[2I");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (0, 11));
+        term.process(b"\x1b[g\x1b[Z");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (0, 3));
+        term.process(b"\x1b[6G\x1b[W\x1b[4G\x1b[2W");
+        assert_eq!(term.tab_stops(), [5]);
+        term.process(b"\x1b[5W");
+        assert!(term.tab_stops().is_empty());
+
+        // DECST8C restores the defaults, which also cover added columns
+        term.process(b"\x1b[?5W");
+        assert_eq!(term.tab_stops(), [8, 16]);
+        term.resize(3, 30);
+        assert_eq!(term.tab_stops(), [8, 16, 24]);
+
+        // Tabs stop at the right margin
+        term.process(b"\x1b[?69h\x1b[1;12s\x1b[2;1H\t\t\t");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (1, 11));
+    }
+
+    #[test]
+    fn chunked_processing_matches_a_single_call() {
+        let mut data = Vec::new();
+        for i in 0..200 {
+            data.extend_from_slice(
+                format!("\x1b[38;2;{};80;160m{:04} héllo ─ 世界\x1b[0m\r\n", i, i).as_bytes(),
+            );
+        }
+        data.extend_from_slice(b"\x1b]0;done\x07\x1b[6n");
+
+        let mut whole = VirtualTerminal::new(10, 30);
+        whole.process(&data);
+        for max_bytes in [0, 1500, usize::MAX] {
+            let budget = ProcessBudget {
+                max_bytes,
+                max_cells: usize::MAX,
+            };
+            let mut chunked = VirtualTerminal::new(10, 30);
+            let mut calls = 1;
+            let mut next = chunked.process_chunked(&data, budget);
+            while let Some(rest) = next {
+                assert!(rest.remaining() > 0 && rest.remaining() < data.len());
+                next = rest.resume(&mut chunked, budget);
+                calls += 1;
+            }
+            let expected_calls = match max_bytes {
+                // Every call stops at the first budget check past the limit
+                0 => data.len().div_ceil(CHUNK_STEP),
+                1500 => data.len().div_ceil(2 * CHUNK_STEP),
+                _ => 1,
+            };
+            assert_eq!(calls, expected_calls, "{max_bytes} bytes");
+            // Text and styles of every row, scrollback included
+            let html = |term: &VirtualTerminal| term.to_html(0..usize::MAX, &term.html_options());
+            assert_eq!(html(&chunked), html(&whole));
+            assert_eq!(chunked.title(), whole.title());
+            assert_eq!(chunked.drain_responses(), whole.clone().drain_responses());
+        }
+    }
+
+    #[test]
+    fn chunked_processing_budgets_printed_cells() {
+        let calls = |data: &[u8], max_cells: usize| {
+            let budget = ProcessBudget {
+                max_bytes: usize::MAX,
+                max_cells,
+            };
+            let mut term = VirtualTerminal::new(10, 30);
+            let mut calls = 1;
+            let mut next = term.process_chunked(data, budget);
+            while let Some(rest) = next {
+                next = rest.resume(&mut term, budget);
+                calls += 1;
+            }
+            calls
+        };
+        let text = vec![b'x'; 10_000];
+        assert_eq!(calls(&text, 2 * CHUNK_STEP), 5);
+        // Escape sequences print nothing, so only the byte budget stops them
+        assert_eq!(calls(&b"\x1b[1m\x1b[0m".repeat(2_000), 1), 1);
+    }
+
+    #[test]
+    fn double_width_lines_hold_half_the_columns() {
+        let mut term = VirtualTerminal::new(3, 10);
+        term.process(b"0123456789\r\n\x1b#6abcdefgh");
+        assert_eq!(term.line_size(0), LineSize::Normal);
+        assert_eq!(term.line_size(1), LineSize::DoubleWidth);
+        // Five cells fit; the rest wrap onto the next (normal) line
+        assert_eq!(term.viewport_lines()[1], "abcde");
+        assert_eq!(term.viewport_lines()[2], "fgh");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (2, 3));
+
+        // Cursor movement stops at the line's last cell
+        term.process(b"\x1b[2;9Hz");
+        assert_eq!(term.viewport_lines()[1], "abcdz");
+        assert_eq!(term.cursor_col(), 4);
+
+        // Switching an existing line to double width drops what no longer fits
+        term.process(b"\x1b[1;8H\x1b#6");
+        assert_eq!(term.viewport_lines()[0], "01234");
+        assert_eq!(term.cursor_col(), 4);
+        term.process(b"\x1b#5");
+        assert_eq!(term.line_size(0), LineSize::Normal);
+    }
+
+    #[test]
+    fn double_height_pairs_survive_capture_and_reset_on_erase() {
+        let mut term = VirtualTerminal::new(3, 20);
+        term.process(b"\x1b#3Banner\r\n\x1b#4Banner\r\nplain");
+        assert_eq!(term.line_size(0), LineSize::DoubleHeightTop);
+        assert_eq!(term.line_size(1), LineSize::DoubleHeightBottom);
+        assert_eq!(
+            term.to_ansi_string(false),
+            "\x1b#3Banner\r\n\x1b#4Banner\r\nplain"
+        );
+
+        // Exports draw the pair once instead of repeating the half glyphs
+        let options = HtmlOptions {
+            links: false,
+            ..HtmlOptions::default()
+        };
+        assert!(term
+            .to_html(0..3, &options)
+            .ends_with("<span style=\"font-size:200%\">Banner</span>\nplain</pre>"));
+        let line = |row: usize| {
+            let grid = term.grid();
+            grid.viewport[row]
+                .to_ratatui_line(grid.style_table())
+                .to_string()
+        };
+        assert_eq!(line(0).trim_end(), "B a n n e r");
+        assert_eq!(line(1).trim_end(), "");
+        term.process(b"\x1b[3;1H\x1b[2K\x1b#6wide");
+        assert!(term.to_html(0..3, &options).ends_with(
+            "\n<span style=\"display:inline-block;transform:scaleX(2);transform-origin:left\">wide</span></pre>"
+        ));
+
+        // Erasing in line keeps the size, erasing the display resets it
+        term.process(b"\x1b[1;1H\x1b[2K");
+        assert_eq!(term.line_size(0), LineSize::DoubleHeightTop);
+        term.process(b"\x1b[2J");
+        assert_eq!(term.line_size(0), LineSize::Normal);
+        assert_eq!(term.line_size(1), LineSize::Normal);
+    }
+
+    #[test]
+    fn virtual_terminal_handles_8bit_c1_in_utf8_stream() {
+        let mut term = VirtualTerminal::new(24, 80);
+        let mut data = "é".as_bytes().to_vec();
+        data.extend_from_slice(b"\x9b31m");
+        data.extend_from_slice("ě".as_bytes());
+        data.extend_from_slice(b"\x9b0m\x9d0;");
+        data.extend_from_slice("tïtle".as_bytes());
+        data.extend_from_slice(b"\x9c");
+        data.extend_from_slice("€".as_bytes());
+        term.process(&data);
+        assert_eq!(term.get_cell(0, 0).c, 'é');
+        assert_eq!(term.get_cell(0, 1).c, 'ě');
+        assert_eq!(term.get_cell(0, 1).style.fg, Some(Color::Red));
+        assert_eq!(term.get_cell(0, 2).c, '€');
+        assert_eq!(term.get_cell(0, 2).style.fg, None);
+        assert_eq!(term.title.as_deref(), Some("tïtle"));
+    }
+
+    #[test]
+    fn virtual_terminal_strips_c1_when_configured() {
+        let mut term = VirtualTerminal::new(24, 80);
+        term.set_c1_mode(C1Mode::Strip);
+        term.process(b"a\x9b31mb");
+        assert_eq!(term.get_lines()[0].trim_end(), "a31mb");
+        assert_eq!(term.get_cell(0, 1).style.fg, None);
+    }
+
+    #[test]
+    fn decsc_decrc_restores_style_charset_and_pending_wrap() {
+        let mut term = VirtualTerminal::new(3, 4);
+        // Fill the first row so a wrap is pending, then save with red + line drawing
+        term.process(b"\x1b[31m\x1b(0abcd\x1b7");
+        term.process(b"\x1b[0m\x1b(B\x1b[3;1Hzz\x1b8q");
+        assert_eq!(term.get_cell(1, 0).c, '─');
+        assert_eq!(term.get_cell(1, 0).style.fg, Some(Color::Red));
+        assert_eq!((term.cursor_row(), term.cursor_col()), (1, 1));
+
+        // DECRC without a save homes the cursor with default attributes
+        let mut term = VirtualTerminal::new(3, 4);
+        term.process(b"\x1b[31m\x1b[2;3H\x1b8x");
+        assert_eq!(term.get_cell(0, 0).c, 'x');
+        assert_eq!(term.get_cell(0, 0).style.fg, None);
+    }
+
+    #[test]
+    fn csi_s_u_ignore_private_marker_forms() {
+        let mut term = VirtualTerminal::new(3, 10);
+        term.process(b"\x1b[2;3H\x1b[s\x1b[1;1H");
+        // Kitty keyboard query/push/pop and XTSAVE must not move the cursor
+        term.process(b"\x1b[?u\x1b[>1u\x1b[<u\x1b[?1049s");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (0, 0));
+        term.process(b"\x1b[u");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (1, 2));
+    }
+
+    #[test]
+    fn alternate_screen_has_its_own_saved_cursor() {
+        let mut term = VirtualTerminal::new(4, 10);
+        term.process(b"\x1b[2;5H\x1b7");
+        term.process(b"\x1b[?1047h\x1b[4;1H\x1b7\x1b[1;1H\x1b8");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (3, 0));
+        term.process(b"\x1b[?1047l\x1b[1;1H\x1b8");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (1, 4));
+
+        // 1049 restores the entry position and leaves no stale save behind
+        term.process(b"\x1b[3;3H\x1b[?1049h\x1b[1;1H\x1b7\x1b[?1049l");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (2, 2));
+        term.process(b"\x1b[4;9H\x1b8");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (0, 0));
+
+        term.process(b"\x1b[2;2H\x1b[?1048h\x1b[4;4H\x1b[?1048l");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (1, 1));
+    }
+
+    #[test]
+    fn kitty_keyboard_flags_are_stacked_per_screen() {
+        let mut term = VirtualTerminal::new(3, 10);
+        term.process(b"\x1b[>1u\x1b[>11u");
+        assert_eq!(term.modes().keyboard_flags, 11);
+        term.process(b"\x1b[=4;2u\x1b[?u");
+        assert_eq!(term.drain_responses(), vec![b"\x1b[?15u".to_vec()]);
+        term.process(b"\x1b[=8;3u");
+        assert_eq!(term.modes().keyboard_flags, 7);
+
+        // The alternate screen starts with legacy keys and keeps its own stack
+        term.process(b"\x1b[?1049h");
+        assert_eq!(term.modes().keyboard_flags, 0);
+        term.process(b"\x1b[>31u");
+        let restored = VirtualTerminal::restore(term.snapshot());
+        assert_eq!(restored.modes().keyboard_flags, 31);
+        term.process(b"\x1b[?1049l");
+        assert_eq!(term.modes().keyboard_flags, 7);
+
+        term.process(b"\x1b[<u");
+        assert_eq!(term.modes().keyboard_flags, 1);
+        term.process(b"\x1b[<5u");
+        assert_eq!(term.modes().keyboard_flags, 0);
+
+        // Plain `CSI u` still restores the cursor
+        term.process(b"\x1b[2;3H\x1b[s\x1b[H\x1b[u");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (1, 2));
+    }
+
+    #[test]
+    fn grapheme_clusters_share_a_cell_and_keep_columns_aligned() {
+        let mut term = VirtualTerminal::new(2, 12);
+        // Combining accent, ZWJ family, heart + VS16, a flag, then CJK
+        term.process(
+            "e\u{301}|\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}|\u{2764}\u{FE0F}|\u{1F1FA}\u{1F1F8}|"
+                .as_bytes(),
+        );
+        assert_eq!(term.cursor_col(), 12 - 1);
+        let row = &term.internal_grid.viewport[0];
+        assert_eq!(row.columns[0].grapheme(), "e\u{301}");
+        assert_eq!(row.columns[2].width(), 2);
+        assert!(row.columns[3].wide_spacer);
+        assert_eq!(row.columns[5].grapheme(), "\u{2764}\u{FE0F}");
+        assert!(row.columns[6].wide_spacer);
+        assert_eq!(row.columns[8].grapheme(), "\u{1F1FA}\u{1F1F8}");
+        assert_eq!(row.columns[10].character, '|');
+        assert_eq!(
+            term.capture(false).lines().next(),
+            Some("e\u{301}|\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}|\u{2764}\u{FE0F}|\u{1F1FA}\u{1F1F8}|")
+        );
+        let restored = VirtualTerminal::restore(term.snapshot());
+        assert_eq!(restored.capture(false), term.capture(false));
+
+        // Ambiguous-width characters follow the configured width
+        term.process(b"\r\n");
+        term.set_ambiguous_width(AmbiguousWidth::Wide);
+        term.process("\u{2500}x".as_bytes());
+        assert_eq!(term.cursor_col(), 3);
+        assert!(term.internal_grid.viewport[1].columns[1].wide_spacer);
+        term.set_ambiguous_width(AmbiguousWidth::Narrow);
+        term.process("\u{2500}\u{4E2D}|".as_bytes());
+        assert_eq!(term.cursor_col(), 7);
+    }
+
+    #[test]
+    fn modes_track_paste_mouse_and_focus() {
+        let mut term = VirtualTerminal::new(3, 10);
+        assert_eq!(term.modes(), TerminalModes::default());
+
+        term.process(b"\x1b[?2004h\x1b[?1002;1006h\x1b[?1004h");
+        assert_eq!(
+            term.modes(),
+            TerminalModes {
+                bracketed_paste: true,
+                mouse_tracking: MouseTracking::Drag,
+                sgr_mouse: true,
+                focus_events: true,
+                keyboard_flags: 0,
+            }
+        );
+        term.process(b"\x1b[?1004$p");
+        assert_eq!(term.drain_responses(), vec![b"\x1b[?1004;1$y".to_vec()]);
+
+        term.process(b"\x1b[?1002l\x1b[?2004l\x1b[?1004l");
+        let modes = term.modes();
+        assert_eq!(modes.mouse_tracking, MouseTracking::Off);
+        assert!(!modes.bracketed_paste && !modes.focus_events && modes.sgr_mouse);
+
+        term.process(b"\x1b[?1003h\x1bc");
+        assert_eq!(term.modes(), TerminalModes::default());
+    }
+
+    #[test]
+    fn query_responder_answers_as_queries_arrive() {
+        let mut term = VirtualTerminal::new(4, 10);
+        term.process(b"\x1b[c");
+        let replies = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = replies.clone();
+        // Replies queued before the responder was set are handed to it
+        term.set_query_responder(move |reply| sink.lock().unwrap().push(reply.to_vec()));
+
+        term.process(b"ab\x1b[6n\x1b[>c\x1b[?12l\x1b[?12$p");
+        // XTGETTCAP for TN, Co and an unknown name
+        term.process(b"\x1bP+q544e;436F;7878\x1b\\");
+        assert!(term.drain_responses().is_empty());
+        assert_eq!(
+            *replies.lock().unwrap(),
+            vec![
+                b"\x1b[?64;1;2;6;9;15;16;17;18;21;22;28;29c".to_vec(),
+                b"\x1b[1;3R".to_vec(),
+                b"\x1b[>41;354;0c".to_vec(),
+                b"\x1b[?12;2$y".to_vec(),
+                b"\x1bP1+r544E=787465726D2D323536636F6C6F72\x1b\\".to_vec(),
+                b"\x1bP1+r436F=323536\x1b\\".to_vec(),
+                b"\x1bP0+r7878\x1b\\".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn osc_133_marks_record_their_lines() {
+        let mut term = VirtualTerminal::new(3, 20);
+        term.process(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07");
+        term.process(b"a\r\nb\r\nc\r\n\x1b]133;D;2\x07\x1b]133;A;aid=1\x1b\\$ ");
+        term.process(b"\x1b]133;D\x07\x1b]133;P;k=i\x07");
+        let marks: Vec<_> = term
+            .drain_semantic_marks()
+            .into_iter()
+            .map(|mark| (mark.kind, mark.line))
+            .collect();
+        assert_eq!(
+            marks,
+            vec![
+                (SemanticMarkKind::PromptStart, 0),
+                (SemanticMarkKind::InputStart, 0),
+                (SemanticMarkKind::OutputStart, 1),
+                (SemanticMarkKind::CommandFinished { exit_code: Some(2) }, 4),
+                (SemanticMarkKind::PromptStart, 4),
+                (SemanticMarkKind::CommandFinished { exit_code: None }, 4),
+            ]
+        );
+        assert_eq!(term.get_lines()[1..4], ["a", "b", "c"]);
+        assert!(term.drain_semantic_marks().is_empty());
+    }
+
+    #[test]
+    fn osc_0_1_2_set_title_and_icon_name() {
+        let mut term = VirtualTerminal::new(3, 20);
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (titles, icons) = (seen.clone(), seen.clone());
+        term.set_title_callback(move |t| titles.lock().unwrap().push(format!("title {t}")));
+        term.set_icon_name_callback(move |i| icons.lock().unwrap().push(format!("icon {i}")));
+
+        term.process(b"\x1b]0;vim a;b\x07");
+        assert_eq!(
+            (term.title(), term.icon_name()),
+            (Some("vim a;b"), Some("vim a;b"))
+        );
+        term.process(b"\x1b]2;make\x1b\\\x1b]1;m\x07\x1b]2;make\x07");
+        assert_eq!((term.title(), term.icon_name()), (Some("make"), Some("m")));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["icon vim a;b", "title vim a;b", "title make", "icon m"]
+        );
+
+        let restored = VirtualTerminal::restore(term.snapshot());
+        assert_eq!(
+            (restored.title(), restored.icon_name()),
+            (Some("make"), Some("m"))
+        );
+    }
+
+    #[test]
+    fn osc_color_changes_are_reported() {
+        let mut term = VirtualTerminal::new(3, 20);
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        term.set_color_change_callback(move |change| log.lock().unwrap().push(*change));
+        let change = |slot, color| ColorChange { slot, color };
+
+        term.process(b"\x1b]4;1;#ff0000;2;rgb:00/80/00\x07\x1b]10;#eeeeee;#111111\x07");
+        // Queries and repeated values don't fire
+        term.process(b"\x1b]4;1;?\x07\x1b]11;?\x07\x1b]4;1;#ff0000\x07");
+        term.process(b"\x1b]12;#00ff00\x1b\\\x1b]104;2\x07\x1b]110\x07");
+        assert_eq!(term.get_palette_color(1), (255, 0, 0));
+        assert_eq!(term.default_bg_color, Some((0x11, 0x11, 0x11)));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                change(ColorSlot::Palette(1), Some((255, 0, 0))),
+                change(ColorSlot::Palette(2), Some((0, 0x80, 0))),
+                change(ColorSlot::Foreground, Some((0xee, 0xee, 0xee))),
+                change(ColorSlot::Background, Some((0x11, 0x11, 0x11))),
+                change(ColorSlot::Cursor, Some((0, 255, 0))),
+                change(ColorSlot::Palette(2), None),
+                change(ColorSlot::Foreground, None),
+            ]
+        );
+        assert_eq!(term.drain_responses().len(), 2);
+
+        // RIS keeps the callback and reports everything it resets
+        seen.lock().unwrap().clear();
+        term.process(b"\x1bc\x1b]12;#0000ff\x07");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                change(ColorSlot::Palette(1), None),
+                change(ColorSlot::Background, None),
+                change(ColorSlot::Cursor, None),
+                change(ColorSlot::Cursor, Some((0, 0, 255))),
+            ]
+        );
+    }
+
+    #[test]
+    fn osc_7_tracks_the_working_directory() {
+        let mut term = VirtualTerminal::new(3, 20);
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        term.set_cwd_callback(move |cwd| log.lock().unwrap().push(cwd.to_path_buf()));
+        assert_eq!(term.cwd(), None);
+
+        term.process(b"\x1b]7;file://box/home/me/my%20dir\x07");
+        assert_eq!(term.cwd(), Some(Path::new("/home/me/my dir")));
+        // Unchanged, unparseable and non-file reports don't fire
+        term.process(b"\x1b]7;file://box/home/me/my%20dir\x1b\\\x1b]7;/tmp\x07");
+        term.process(b"\x1b]7;http://box/tmp\x07\x1b]7;file://box/%ff\x07");
+        term.process(b"\x1b]7;kitty-shell-cwd://box/srv/a;b\x07");
+        assert_eq!(term.cwd(), Some(Path::new("/srv/a;b")));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [PathBuf::from("/home/me/my dir"), PathBuf::from("/srv/a;b")]
+        );
+
+        let restored = VirtualTerminal::restore(term.snapshot());
+        assert_eq!(restored.cwd(), Some(Path::new("/srv/a;b")));
+    }
+
+    #[test]
+    fn osc_133_commands_keep_their_output_lines() {
+        let mut term = VirtualTerminal::new(3, 20);
+        let run = |term: &mut VirtualTerminal, cmd: &str, output: &str, status: u8| {
+            term.process(format!("\x1b]133;A\x07$ \x1b]133;B\x07{cmd}\r\n").as_bytes());
+            term.process(format!("\x1b]133;C\x07{output}\x1b]133;D;{status}\x07").as_bytes());
+        };
+        run(&mut term, "ls", "a\r\nb\r\n", 0);
+        // An empty prompt isn't a command
+        term.process(b"\x1b]133;A\x07$ \x1b]133;B\x07\r\n\x1b]133;D\x07");
+        run(&mut term, "false", "nope", 1);
+        term.process(b"\r\n\x1b]133;A\x07$ \x1b]133;B\x07sleep 9\r\n\x1b]133;C\x07");
+        term.drain_semantic_marks();
+
+        let lines = term.get_lines();
+        let commands = term.commands();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(lines[commands[0].prompt_line], "$ ls");
+        assert_eq!(lines[commands[0].output().unwrap()], ["a", "b"]);
+        assert_eq!(commands[0].exit_code, Some(0));
+        // Output without a trailing newline still covers its line
+        assert_eq!(lines[commands[1].output().unwrap()], ["nope"]);
+        assert_eq!(commands[1].exit_code, Some(1));
+        assert!(commands[2].is_running());
+        assert_eq!(commands[2].input_line, Some(commands[2].prompt_line));
+
+        let last = commands[2].prompt_line;
+        assert_eq!(term.previous_command(last), Some(&commands[1]));
+        assert_eq!(term.next_command(0), Some(&commands[1]));
+        assert!(term.next_command(last).is_none());
+
+        // Rewrapping moves the lines along with the text
+        term.resize(3, 3);
+        let lines = term.get_lines();
+        let commands = term.commands().to_vec();
+        assert_eq!(lines[commands[0].prompt_line], "$ l");
+        assert_eq!(lines[commands[0].output().unwrap()], ["a", "b"]);
+        assert_eq!(lines[commands[1].prompt_line], "$ f");
+        assert_eq!(lines[commands[1].output().unwrap()], ["nop", "e"]);
+
+        let restored = VirtualTerminal::restore(term.snapshot());
+        assert_eq!(restored.commands(), commands.as_slice());
+        term.process(b"\x1b[?1049h");
+        assert_eq!(term.commands(), commands.as_slice());
+    }
+
+    #[test]
+    fn to_ansi_repaints_screen_cursor_and_modes() {
+        let mut term = VirtualTerminal::new(4, 12);
+        term.process(b"scrolled away\r\n\x1b[1;31mred\x1b[0m plain\r\n");
+        term.process("\x1b[44m  \x1b[0m日本\r\n\x1b#6wide".as_bytes());
+        term.process(b"\x1b[2;4r\x1b[?1h\x1b[?1002;1006h\x1b[?2004h\x1b[4 q");
+        term.process(b"\x1b[3;5H\x1b[4;32m");
+
+        let ansi = term.to_ansi();
+        assert!(!ansi.contains("scrolled away"));
+        let mut replayed = VirtualTerminal::new(4, 12);
+        replayed.process(ansi.as_bytes());
+        assert_eq!(replayed.to_ansi(), ansi);
+        assert_eq!(replayed.viewport_lines(), term.viewport_lines());
+        assert_eq!(
+            (replayed.cursor_row(), replayed.cursor_col()),
+            (term.cursor_row(), term.cursor_col())
+        );
+        assert_eq!(replayed.modes(), term.modes());
+        assert!(replayed.application_cursor_keys);
+        // Same pen: the next character comes out styled alike
+        for t in [&mut term, &mut replayed] {
+            t.process(b"x");
+        }
+        assert_eq!(replayed.to_ansi(), term.to_ansi());
+
+        term.process(b"\x1b[?1049h\x1b[?25lfull screen");
+        let mut replayed = VirtualTerminal::new(4, 12);
+        replayed.process(term.to_ansi().as_bytes());
+        assert_eq!(replayed.to_ansi(), term.to_ansi());
+        replayed.process(b"\x1b[?1049l");
+        assert_eq!(replayed.viewport_lines(), vec![""; 4]);
+    }
+
+    #[test]
+    fn take_damage_reports_changed_rows_once() {
+        let mut term = VirtualTerminal::new(4, 10);
+        assert_eq!(term.take_damage(), vec![0, 1, 2, 3]);
+        assert!(term.take_damage().is_empty());
+
+        term.process(b"\x1b[3;1Hx\x1b[2;1Hy");
+        assert_eq!(term.take_damage(), vec![1, 2]);
+        term.process(b"\x1b[4;5H");
+        assert!(term.take_damage().is_empty());
+
+        term.process(b"\x1b[2;3r\x1b[3;1H\n");
+        assert_eq!(term.take_damage(), vec![1, 2]);
+        term.process(b"\x1b[?1049h");
+        assert_eq!(term.take_damage(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rows_are_timestamped_and_old_scrollback_pruned() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let mut term = VirtualTerminal::new(2, 10);
+        let before = SystemTime::now();
+        term.process(b"one\r\n\r\nthree four five\r\nsix\r\n");
+        assert_eq!(term.scrollback_len(), 4);
+
+        let times = term.capture_timestamps(true);
+        assert_eq!(times.len(), term.capture(true).split('\n').count());
+        assert!(times[0].is_some_and(|at| at >= before));
+        assert_eq!(times[1], None);
+        assert_eq!(term.capture_timestamps(false).len(), 6);
+
+        // Age the first line and the start of the wrapped one past an hour
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let old = UNIX_EPOCH + Duration::from_millis(now.as_millis() as u64 - 2 * 3600 * 1000);
+        term.internal_grid.lines_above[0].written_at = Some(old);
+        term.internal_grid.lines_above[2].written_at = Some(old);
+        let restored = VirtualTerminal::restore(term.snapshot());
+        assert_eq!(restored.capture_timestamps(false)[2], Some(old));
+
+        let cutoff = SystemTime::now() - Duration::from_secs(3600);
+        assert_eq!(term.prune_scrollback_before(cutoff), 4);
+        // The wrapped row continuing the dropped line went with it
+        assert_eq!(term.capture(false), "six\n");
+        assert_eq!(term.prune_scrollback_before(cutoff), 0);
+    }
+
+    #[test]
+    fn sixel_and_iterm2_images_are_placed_at_the_cursor() {
+        let mut term = VirtualTerminal::new(10, 20);
+        term.process(b"ab\x1bPq#1;2;100;0;0#1~~~~\x1b\\");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (1, 2));
+
+        term.process(b"\x1b]1337;File=name=aGkudHh0;inline=1;width=3;height=2:aGk=\x07");
+        assert_eq!((term.cursor_row(), term.cursor_col()), (3, 0));
+
+        let images = term.images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(
+            (images[0].row, images[0].col, images[0].rows, images[0].cols),
+            (0, 2, 1, 1)
+        );
+        assert_eq!(images[0].pixel_size, Some((4, 6)));
+        let ImageData::Rgba(pixels) = &images[0].data else {
+            panic!("sixel should decode to pixels");
+        };
+        assert_eq!(&pixels[..4], &[255, 0, 0, 255]);
+
+        assert_eq!(
+            term.internal_grid.image_at(2, 4).map(|i| i.id),
+            Some(images[1].id)
+        );
+        assert_eq!(
+            images[1].data,
+            ImageData::File {
+                name: Some("hi.txt".to_string()),
+                bytes: b"hi".as_slice().into(),
+            }
+        );
+
+        term.process(b"\x1b[16t");
+        assert_eq!(term.drain_responses(), vec![b"\x1b[6;20;10t".to_vec()]);
+
+        term.resize(10, 30);
+        assert!(term.images().is_empty());
+    }
+
+    #[test]
+    fn to_html_exports_styles_links_and_scrollback() {
+        let mut term = VirtualTerminal::new(2, 20);
+        term.process(b"\x1b[1;31mfailed\x1b[0m <ok>\r\n");
+        term.process(b"see https://a.io/x\r\n\x1b[7minv\x1b[0m");
+        assert_eq!(term.scrollback_len(), 1);
+
+        let options = term.html_options();
+        assert_eq!(
+            term.to_html(0..1, &options),
+            "<pre style=\"color:#ffffff;background-color:#000000;font-family:monospace\">\
+             <span style=\"color:#cd0000;font-weight:bold\">failed</span> &lt;ok&gt;</pre>"
+        );
+        let html = term.to_html(1..10, &options);
+        assert!(html.contains("see <a href=\"https://a.io/x\">https://a.io/x</a>\n"));
+        assert!(html.contains("<span style=\"color:#000000;background-color:#ffffff\">inv</span>"));
+
+        term.process(b"\x1b]4;1;rgb:00/80/ff\x1b\\");
+        let html = term.to_html(0..1, &term.html_options());
+        assert!(html.contains("color:#0080ff;font-weight:bold"));
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_serde() {
+        let mut term = VirtualTerminal::new(4, 10);
+        for i in 0..6 {
+            term.process(format!("\x1b[3{}mline {i}\x1b[0m\r\n", i % 8).as_bytes());
+        }
+        term.process("wide 日本 wraps past the edge".as_bytes());
+        term.process(b"\x1b[2;5H\x1b7\x1b]0;build\x07\x1b]4;1;rgb:12/34/56\x07");
+        term.process(b"\x1b[?2004h\x1b[?1003;1006h\x1b[?1049h\x1b[1;32mtop\x1b[3;3H");
+
+        let json = serde_json::to_string(&term.snapshot()).unwrap();
+        let snapshot: TerminalSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.size(), (4, 10));
+        let mut restored = VirtualTerminal::restore(snapshot);
+
+        let state = |t: &VirtualTerminal| {
+            (
+                t.to_ansi_string(false),
+                (t.cursor_row(), t.cursor_col()),
+                t.modes(),
+                t.title.clone(),
+                t.color_palette()[1],
+            )
+        };
+        assert_eq!(state(&restored), state(&term));
+        assert_eq!(restored.snapshot(), term.snapshot());
+
+        // Both continue identically, including back on the main screen
+        for t in [&mut term, &mut restored] {
+            t.process(b"more\x1b[?1049l\x1b8after\r\nnext");
+        }
+        assert_eq!(state(&restored), state(&term));
+        assert_eq!(restored.capture(true), term.capture(true));
+    }
+
+    mod props {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Bytes biased towards escape sequences, including the malformed
+        /// DCS strings that used to panic the parser.
+        fn terminal_bytes() -> impl Strategy<Value = Vec<u8>> {
+            let fragment = prop_oneof![
+                any::<u8>().prop_map(|b| vec![b]),
+                "[ -~]{1,8}".prop_map(String::into_bytes),
+                Just(b"\x1b[".to_vec()),
+                Just(b"\x1bP".to_vec()),
+                Just(b"\x1bP$q".to_vec()),
+                Just(b"\x1bP+q".to_vec()),
+                Just(b"\x1b]".to_vec()),
+                Just(b"\x1b\\".to_vec()),
+                Just(b"\x07".to_vec()),
+                Just(b"\x90".to_vec()),
+                Just(b"\x9b".to_vec()),
+                Just(b"\x9c".to_vec()),
+                Just(b"\r\n".to_vec()),
+                Just(b"\x1b[?1049h".to_vec()),
+                Just(b"\x1b[?1049l".to_vec()),
+                Just(b"\x1b7".to_vec()),
+                Just(b"\x1b8".to_vec()),
+                (0u16..400, 0u16..400).prop_map(|(a, b)| format!("{a};{b}").into_bytes()),
+                "[@-~]".prop_map(String::into_bytes),
+                "\\PC{1,4}".prop_map(String::into_bytes),
+            ];
+            prop::collection::vec(fragment, 0..96).prop_map(|parts| parts.concat())
+        }
+
+        fn small_terminal() -> VirtualTerminal {
+            VirtualTerminal::new(6, 12)
+        }
+
+        fn assert_bounded(term: &VirtualTerminal) {
+            assert!(term.scrollback_len() <= crate::grid::MAX_SCROLLBACK_LINES);
+            let grid = term.grid_snapshot();
+            assert_eq!(grid.len(), term.rows());
+            assert!(grid.iter().all(|row| row.len() == term.cols()));
+            assert!(term.cursor_row() < term.rows() && term.cursor_col() < term.cols());
+            assert!(term.dcs_data.len() <= MAX_DCS_DATA);
+        }
+
+        proptest! {
+            #[test]
+            fn arbitrary_input_keeps_terminal_bounded(
+                data in terminal_bytes(),
+                splits in prop::collection::vec(0usize..24, 0..16),
+            ) {
+                let mut term = small_terminal();
+                let mut start = 0;
+                for split in splits {
+                    let end = (start + split).min(data.len());
+                    term.process(&data[start..end]);
+                    assert_bounded(&term);
+                    start = end;
+                }
+                term.process(&data[start..]);
+                assert_bounded(&term);
+                let _ = term.to_ansi_string(true);
+            }
+
+            #[test]
+            fn chunk_splits_do_not_change_screen(
+                data in terminal_bytes(),
+                splits in prop::collection::vec(0usize..24, 0..16),
+            ) {
+                let mut whole = small_terminal();
+                whole.process(&data);
+
+                let mut chunked = small_terminal();
+                let mut start = 0;
+                for split in splits {
+                    let end = (start + split).min(data.len());
+                    chunked.process(&data[start..end]);
+                    start = end;
+                }
+                chunked.process(&data[start..]);
+
+                prop_assert_eq!(chunked.to_ansi_string(false), whole.to_ansi_string(false));
+                prop_assert_eq!(
+                    (chunked.cursor_row(), chunked.cursor_col()),
+                    (whole.cursor_row(), whole.cursor_col())
+                );
+                prop_assert_eq!(chunked.drain_responses(), whole.drain_responses());
+            }
+        }
+
+        #[test]
+        fn oversized_decrqss_is_truncated() {
+            let mut term = small_terminal();
+            let mut data = b"\x1bP$q".to_vec();
+            data.extend(std::iter::repeat_n(b'm', MAX_DCS_DATA * 4));
+            data.extend_from_slice(b"\x1b\\");
+            term.process(&data);
+            assert!(term.dcs_data.is_empty());
+            assert_bounded(&term);
+        }
+    }
 }
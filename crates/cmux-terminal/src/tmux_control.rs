@@ -0,0 +1,472 @@
+//! tmux control mode (`tmux -CC`) client.
+//!
+//! In control mode tmux draws nothing itself. It reports each pane's output
+//! and every window and layout change as a line of text, and answers
+//! commands written to its stdin in `%begin`/`%end` blocks. [`TmuxControl`]
+//! parses that stream and keeps a [`VirtualTerminal`] per pane, sized from
+//! its window's layout, so a tmux session already running in a sandbox can
+//! be mirrored pane by pane instead of as one flattened screen.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::terminal::VirtualTerminal;
+
+/// Size given to a pane that sends output before any layout mentions it.
+const DEFAULT_SIZE: (usize, usize) = (24, 80);
+
+/// One pane's place in a window, from a tmux layout string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaneLayout {
+    pub pane: u32,
+    pub cols: usize,
+    pub rows: usize,
+    /// Column of the pane's left edge within the window
+    pub x: usize,
+    /// Row of the pane's top edge within the window
+    pub y: usize,
+}
+
+/// What a line from tmux changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlEvent {
+    /// `%output`: the pane's terminal processed new output
+    Output {
+        pane: u32,
+    },
+    /// `%layout-change`: the window's panes were split, closed or resized
+    LayoutChanged {
+        window: u32,
+    },
+    WindowAdded {
+        window: u32,
+    },
+    /// The window is gone, along with panes no other window shows
+    WindowClosed {
+        window: u32,
+    },
+    WindowRenamed {
+        window: u32,
+        name: String,
+    },
+    /// The client was switched to another session
+    SessionChanged {
+        session: u32,
+        name: String,
+    },
+    /// The reply to the `number`th command sent, one string per line
+    CommandDone {
+        number: u64,
+        output: Vec<String>,
+        error: bool,
+    },
+    /// `%exit`: tmux detached this client
+    Exit {
+        reason: Option<String>,
+    },
+}
+
+/// State of one control-mode connection.
+#[derive(Debug, Default)]
+pub struct TmuxControl {
+    /// Bytes of the line not yet terminated
+    line: Vec<u8>,
+    /// Command number and lines of the `%begin` block being read
+    block: Option<(u64, Vec<String>)>,
+    panes: HashMap<u32, VirtualTerminal>,
+    windows: BTreeMap<u32, Vec<PaneLayout>>,
+}
+
+impl TmuxControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process bytes read from tmux's stdout. Lines may be split across
+    /// calls; a partial line waits for the rest.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<ControlEvent> {
+        let mut events = Vec::new();
+        for &byte in bytes {
+            if byte == b'\n' {
+                let line = std::mem::take(&mut self.line);
+                self.handle_line(&line, &mut events);
+            } else {
+                self.line.push(byte);
+            }
+        }
+        events
+    }
+
+    /// Terminal mirroring `pane`.
+    pub fn pane(&self, pane: u32) -> Option<&VirtualTerminal> {
+        self.panes.get(&pane)
+    }
+
+    /// Every pane's terminal, in no particular order.
+    pub fn panes(&self) -> impl Iterator<Item = (u32, &VirtualTerminal)> {
+        self.panes.iter().map(|(&id, term)| (id, term))
+    }
+
+    /// Windows by id, with the panes of each as last laid out.
+    pub fn windows(&self) -> impl Iterator<Item = (u32, &[PaneLayout])> {
+        self.windows
+            .iter()
+            .map(|(&id, panes)| (id, panes.as_slice()))
+    }
+
+    pub fn layout(&self, window: u32) -> Option<&[PaneLayout]> {
+        self.windows.get(&window).map(Vec::as_slice)
+    }
+
+    fn handle_line(&mut self, line: &[u8], events: &mut Vec<ControlEvent>) {
+        let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+        // `tmux -CC` wraps the session in a DCS for terminals that take it
+        // over, opening it before the first line and closing it after the last
+        if let Some(rest) = line.strip_prefix(b"\x1bP1000p") {
+            line = rest;
+        }
+        if let Some(rest) = line.strip_prefix(b"\x1b\\") {
+            line = rest;
+        }
+
+        if self.block.is_some() {
+            let end = line.starts_with(b"%end ");
+            if end || line.starts_with(b"%error ") {
+                let (number, output) = self.block.take().unwrap_or_default();
+                events.push(ControlEvent::CommandDone {
+                    number,
+                    output,
+                    error: !end,
+                });
+            } else if let Some((_, output)) = &mut self.block {
+                output.push(String::from_utf8_lossy(line).into_owned());
+            }
+            return;
+        }
+
+        // Pane output isn't necessarily UTF-8, so it's split off as bytes
+        if let Some(rest) = line.strip_prefix(b"%output ") {
+            if let Some(space) = rest.iter().position(|&b| b == b' ') {
+                self.output(&rest[..space], &rest[space + 1..], events);
+            }
+            return;
+        }
+        if let Some(rest) = line.strip_prefix(b"%extended-output ") {
+            let pane_end = rest.iter().position(|&b| b == b' ').unwrap_or(rest.len());
+            if let Some(data) = rest.windows(3).position(|w| w == b" : ") {
+                self.output(&rest[..pane_end], &rest[data + 3..], events);
+            }
+            return;
+        }
+
+        let line = String::from_utf8_lossy(line);
+        let (name, args) = line.split_once(' ').unwrap_or((&line, ""));
+        match name {
+            "%begin" => {
+                let number = args.split(' ').nth(1).and_then(|n| n.parse().ok());
+                self.block = Some((number.unwrap_or_default(), Vec::new()));
+            }
+            "%layout-change" => {
+                let mut fields = args.split(' ');
+                let window = fields.next().and_then(|w| id(w, '@'));
+                let panes = fields.next().and_then(parse_layout);
+                if let (Some(window), Some(panes)) = (window, panes) {
+                    self.apply_layout(window, panes);
+                    events.push(ControlEvent::LayoutChanged { window });
+                }
+            }
+            "%window-add" => {
+                if let Some(window) = id(args, '@') {
+                    self.windows.entry(window).or_default();
+                    events.push(ControlEvent::WindowAdded { window });
+                }
+            }
+            "%window-close" | "%unlinked-window-close" => {
+                if let Some(window) = id(args, '@') {
+                    self.close_window(window);
+                    events.push(ControlEvent::WindowClosed { window });
+                }
+            }
+            "%window-renamed" => {
+                let (window, name) = args.split_once(' ').unwrap_or((args, ""));
+                if let Some(window) = id(window, '@') {
+                    events.push(ControlEvent::WindowRenamed {
+                        window,
+                        name: name.to_string(),
+                    });
+                }
+            }
+            "%session-changed" => {
+                let (session, name) = args.split_once(' ').unwrap_or((args, ""));
+                if let Some(session) = id(session, '$') {
+                    events.push(ControlEvent::SessionChanged {
+                        session,
+                        name: name.to_string(),
+                    });
+                }
+            }
+            "%exit" => events.push(ControlEvent::Exit {
+                reason: Some(args.to_string()).filter(|r| !r.is_empty()),
+            }),
+            _ => {}
+        }
+    }
+
+    fn output(&mut self, pane: &[u8], data: &[u8], events: &mut Vec<ControlEvent>) {
+        let Some(pane) = std::str::from_utf8(pane).ok().and_then(|p| id(p, '%')) else {
+            return;
+        };
+        self.panes
+            .entry(pane)
+            .or_insert_with(|| VirtualTerminal::new(DEFAULT_SIZE.0, DEFAULT_SIZE.1))
+            .process(&unescape(data));
+        events.push(ControlEvent::Output { pane });
+    }
+
+    fn apply_layout(&mut self, window: u32, panes: Vec<PaneLayout>) {
+        for layout in &panes {
+            let term = self
+                .panes
+                .entry(layout.pane)
+                .or_insert_with(|| VirtualTerminal::new(layout.rows, layout.cols));
+            let grid = term.grid();
+            if (grid.rows, grid.cols) != (layout.rows, layout.cols) {
+                term.resize(layout.rows, layout.cols);
+            }
+        }
+        // Panes closed by this change no longer appear in any layout
+        let old = self.windows.insert(window, panes).unwrap_or_default();
+        for layout in old {
+            self.drop_if_unused(layout.pane);
+        }
+    }
+
+    fn close_window(&mut self, window: u32) {
+        for layout in self.windows.remove(&window).unwrap_or_default() {
+            self.drop_if_unused(layout.pane);
+        }
+    }
+
+    fn drop_if_unused(&mut self, pane: u32) {
+        let shown = self
+            .windows
+            .values()
+            .any(|panes| panes.iter().any(|p| p.pane == pane));
+        if !shown {
+            self.panes.remove(&pane);
+        }
+    }
+}
+
+/// The number in a tmux id such as `@1` (window), `%3` (pane) or `$0`
+/// (session).
+fn id(s: &str, sigil: char) -> Option<u32> {
+    s.trim().strip_prefix(sigil)?.parse().ok()
+}
+
+/// Undo control mode's escaping of output, which writes control characters
+/// and backslashes as three-digit octal escapes.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let octal = data
+            .get(i + 1..i + 4)
+            .filter(|digits| data[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        match octal {
+            Some(digits) => {
+                let value = digits.iter().fold(0u32, |n, d| n * 8 + (d - b'0') as u32);
+                out.push(value as u8);
+                i += 4;
+            }
+            None => {
+                out.push(data[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Panes of a tmux layout string such as
+/// `bb62,159x48,0,0{79x48,0,0,1,79x48,80,0,2}`: a checksum, then a cell that
+/// is either a pane (`WxH,X,Y,ID`) or a row (`{...}`) or column (`[...]`)
+/// of cells.
+pub fn parse_layout(layout: &str) -> Option<Vec<PaneLayout>> {
+    let (_checksum, cell) = layout.split_once(',')?;
+    let mut panes = Vec::new();
+    let rest = parse_cell(cell, &mut panes)?;
+    rest.is_empty().then_some(panes)
+}
+
+/// Parse one cell off the front of `s`, returning what follows it.
+fn parse_cell<'a>(s: &'a str, panes: &mut Vec<PaneLayout>) -> Option<&'a str> {
+    let (cols, s) = s.split_once('x')?;
+    let mut numbers = [0usize; 3];
+    let mut s = s;
+    for (i, n) in numbers.iter_mut().enumerate() {
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        *n = s[..end].parse().ok()?;
+        s = &s[end..];
+        if i < 2 {
+            s = s.strip_prefix(',')?;
+        }
+    }
+    let [rows, x, y] = numbers;
+    let cols = cols.parse().ok()?;
+
+    let close = match s.chars().next() {
+        Some('{') => '}',
+        Some('[') => ']',
+        _ => {
+            // A pane: its id follows
+            let s = s.strip_prefix(',')?;
+            let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            panes.push(PaneLayout {
+                pane: s[..end].parse().ok()?,
+                cols,
+                rows,
+                x,
+                y,
+            });
+            return Some(&s[end..]);
+        }
+    };
+    let mut s = &s[1..];
+    loop {
+        s = parse_cell(s, panes)?;
+        match s.strip_prefix(',') {
+            Some(rest) => s = rest,
+            None => return s.strip_prefix(close),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen_text(term: &VirtualTerminal) -> String {
+        term.grid().viewport[0]
+            .columns
+            .iter()
+            .map(|c| c.character)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    #[test]
+    fn layouts_nest_rows_and_columns() {
+        assert_eq!(
+            parse_layout("b25f,80x24,0,0,0"),
+            Some(vec![PaneLayout {
+                pane: 0,
+                cols: 80,
+                rows: 24,
+                x: 0,
+                y: 0
+            }])
+        );
+        let panes =
+            parse_layout("c3a6,159x48,0,0{79x48,0,0,1,79x48,80,0[79x24,80,0,2,79x23,80,25,3]}")
+                .unwrap();
+        let summary: Vec<_> = panes
+            .iter()
+            .map(|p| (p.pane, p.cols, p.rows, p.x, p.y))
+            .collect();
+        assert_eq!(
+            summary,
+            [(1, 79, 48, 0, 0), (2, 79, 24, 80, 0), (3, 79, 23, 80, 25)]
+        );
+        assert_eq!(parse_layout("c3a6,80x24,0,0{80x24,0,0,1"), None);
+        assert_eq!(parse_layout("nonsense"), None);
+    }
+
+    #[test]
+    fn output_is_unescaped_into_the_pane() {
+        assert_eq!(unescape(b"a\\033[1mb\\134c\\9"), b"a\x1b[1mb\\c\\9");
+
+        let mut tmux = TmuxControl::new();
+        let events = tmux.feed(b"\x1bP1000p%begin 1 0 1\r\n%end 1 0 1\r\n%window-add @0\n");
+        assert_eq!(
+            events,
+            [
+                ControlEvent::CommandDone {
+                    number: 0,
+                    output: vec![],
+                    error: false
+                },
+                ControlEvent::WindowAdded { window: 0 },
+            ]
+        );
+        tmux.feed(b"%layout-change @0 b25f,40x5,0,0,3 b25f,40x5,0,0,3 *\n");
+        assert_eq!(tmux.feed(b"%output %3 he\\033[1ml"), []);
+        assert_eq!(
+            tmux.feed(b"lo\\015\\012\n%extended-output %3 10 : !\n"),
+            [
+                ControlEvent::Output { pane: 3 },
+                ControlEvent::Output { pane: 3 }
+            ]
+        );
+        let pane = tmux.pane(3).unwrap();
+        assert_eq!((pane.grid().rows, pane.grid().cols), (5, 40));
+        assert_eq!(screen_text(pane), "hello");
+        assert_eq!(pane.grid().viewport[1].columns[0].character, '!');
+    }
+
+    #[test]
+    fn windows_and_command_replies_are_tracked() {
+        let mut tmux = TmuxControl::new();
+        let events = tmux.feed(
+            b"%begin 1700000000 7 1\nsession: 1 windows\n%end 1700000000 7 1\n\
+              %begin 1700000000 8 1\nunknown command: frob\n%error 1700000000 8 1\n\
+              %window-add @1\n%layout-change @1 aaaa,80x24,0,0{40x24,0,0,1,39x24,41,0,2}\n\
+              %window-renamed @1 build logs\n%session-changed $2 work\n",
+        );
+        assert_eq!(
+            events,
+            [
+                ControlEvent::CommandDone {
+                    number: 7,
+                    output: vec!["session: 1 windows".into()],
+                    error: false
+                },
+                ControlEvent::CommandDone {
+                    number: 8,
+                    output: vec!["unknown command: frob".into()],
+                    error: true
+                },
+                ControlEvent::WindowAdded { window: 1 },
+                ControlEvent::LayoutChanged { window: 1 },
+                ControlEvent::WindowRenamed {
+                    window: 1,
+                    name: "build logs".into()
+                },
+                ControlEvent::SessionChanged {
+                    session: 2,
+                    name: "work".into()
+                },
+            ]
+        );
+        assert_eq!(tmux.panes().count(), 2);
+
+        // Closing a pane resizes the survivor and drops the closed one
+        tmux.feed(b"%layout-change @1 bbbb,80x24,0,0,2\n");
+        assert!(tmux.pane(1).is_none());
+        assert_eq!(tmux.pane(2).unwrap().grid().cols, 80);
+        assert_eq!(tmux.layout(1).unwrap().len(), 1);
+
+        let events = tmux.feed(b"%window-close @1\n%exit detached\n\x1b\\\n");
+        assert_eq!(
+            events,
+            [
+                ControlEvent::WindowClosed { window: 1 },
+                ControlEvent::Exit {
+                    reason: Some("detached".into())
+                },
+            ]
+        );
+        assert_eq!(tmux.panes().count(), 0);
+        assert_eq!(tmux.windows().count(), 0);
+    }
+}
@@ -6,14 +6,25 @@ mod demo_content;
 mod events;
 mod logging;
 mod markdown;
+mod permissions;
+mod prompt_queue;
 mod provider;
+mod reasoning;
 mod runner;
 mod state;
+mod stop_reason;
+mod tool_output;
+mod transcript;
+mod turn_pipeline;
 mod ui;
+mod warm_pool;
 mod workspace_sync;
 
 pub use config::load_last_provider;
 pub use demo::run_demo_tui;
+pub use permissions::{PermissionFallback, PermissionMode, PermissionPolicy};
 pub use provider::AcpProvider;
+pub use reasoning::ReasoningVisibility;
 pub use runner::{run_chat_tui, run_chat_tui_with_workspace_status};
+pub use warm_pool::WarmPoolSizes;
 pub use workspace_sync::WorkspaceSyncStatus;
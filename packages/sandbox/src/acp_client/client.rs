@@ -7,7 +7,7 @@ use agent_client_protocol::{
     WriteTextFileResponse,
 };
 use anyhow::Result;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
 use crate::acp_client::events::AppEvent;
 use crate::acp_client::logging::log_debug;
@@ -23,14 +23,16 @@ impl Client for AppClient {
         request: RequestPermissionRequest,
     ) -> Result<RequestPermissionResponse, Error> {
         log_debug(&format!("RequestPermission: {:?}", request));
-        let option_id = request
-            .options
-            .first()
-            .map(|o| o.id.clone())
-            .unwrap_or(agent_client_protocol::PermissionOptionId("allow".into()));
-
+        let (respond, answer) = oneshot::channel();
+        let _ = self.tx.send(AppEvent::PermissionRequest {
+            request: Box::new(request),
+            respond,
+        });
+        // The app answers per its permission policy, including timeouts;
+        // if it goes away without answering, the request is cancelled.
+        let outcome = answer.await.unwrap_or(RequestPermissionOutcome::Cancelled);
         Ok(RequestPermissionResponse {
-            outcome: RequestPermissionOutcome::Selected { option_id },
+            outcome,
             meta: None,
         })
     }
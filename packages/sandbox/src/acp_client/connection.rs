@@ -13,6 +13,7 @@ use crate::acp_client::client::AppClient;
 use crate::acp_client::events::AppEvent;
 use crate::acp_client::logging::log_debug;
 use crate::acp_client::provider::AcpProvider;
+use crate::acp_client::stop_reason::ProviderExit;
 
 /// WebSocket reader wrapper for ACP protocol
 struct WsRead {
@@ -102,7 +103,18 @@ impl tokio::io::AsyncRead for WsRead {
                     buf.put_slice(data.as_bytes());
                     return std::task::Poll::Ready(Ok(()));
                 }
-                Some(Ok(tokio_tungstenite::tungstenite::Message::Close(_))) | None => {
+                Some(Ok(tokio_tungstenite::tungstenite::Message::Close(frame))) => {
+                    log_debug(&format!("RECV CLOSE: {:?}", frame));
+                    // Attach reports how the provider process ended
+                    if let Some(exit) = frame
+                        .as_ref()
+                        .and_then(|f| ProviderExit::from_close_reason(&f.reason))
+                    {
+                        let _ = self.tx.send(AppEvent::ProviderExited(exit));
+                    }
+                    return std::task::Poll::Ready(Ok(()));
+                }
+                None => {
                     log_debug("RECV EOF");
                     return std::task::Poll::Ready(Ok(()));
                 }
@@ -165,6 +177,35 @@ impl tokio::io::AsyncWrite for WsWrite {
 
 /// Connect to an ACP provider and return the connection, session ID, and model state.
 /// This function can be called from background tasks for provider switching.
+/// Best-effort preflight; servers without the endpoint just skip the check.
+async fn fetch_preflight(
+    base_url: &str,
+    sandbox_id: &str,
+    provider: AcpProvider,
+) -> Option<crate::models::AcpPreflightReport> {
+    let url = format!(
+        "{}/sandboxes/{}/acp/{}/preflight",
+        base_url.trim_end_matches('/'),
+        sandbox_id,
+        provider.short_name()
+    );
+    let response = match reqwest::get(&url).await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            log_debug(&format!("Preflight skipped: HTTP {}", response.status()));
+            return None;
+        }
+        Err(err) => {
+            log_debug(&format!("Preflight skipped: {err}"));
+            return None;
+        }
+    };
+    response.json().await.ok()
+}
+
+/// Working directory every new ACP session starts in.
+const SESSION_CWD: &str = "/workspace";
+
 pub(crate) async fn connect_to_provider(
     base_url: &str,
     sandbox_id: &str,
@@ -175,11 +216,34 @@ pub(crate) async fn connect_to_provider(
     SessionId,
     Option<SessionModelState>,
 )> {
+    let client_conn = open_provider(base_url, sandbox_id, provider, tx).await?;
+    let (session_id, models) = start_session(&client_conn).await?;
+    Ok((client_conn, session_id, models))
+}
+
+/// Spawn the provider CLI and complete the ACP `initialize` handshake,
+/// without starting a session.
+pub(crate) async fn open_provider(
+    base_url: &str,
+    sandbox_id: &str,
+    provider: AcpProvider,
+    tx: mpsc::UnboundedSender<AppEvent>,
+) -> Result<Arc<ClientSideConnection>> {
     log_debug(&format!(
         "Connecting to provider: {}",
         provider.display_name()
     ));
 
+    if let Some(report) = fetch_preflight(base_url, sandbox_id, provider).await {
+        if !report.ok {
+            anyhow::bail!(
+                "{} can't start in this sandbox:\n{}",
+                provider.display_name(),
+                report.summary()
+            );
+        }
+    }
+
     let ws_url = base_url
         .replace("http://", "ws://")
         .replace("https://", "wss://")
@@ -244,10 +308,17 @@ pub(crate) async fn connect_to_provider(
         .await?;
     log_debug("Initialize complete");
 
+    Ok(client_conn)
+}
+
+/// Start a session on an initialized connection.
+pub(crate) async fn start_session(
+    client_conn: &ClientSideConnection,
+) -> Result<(SessionId, Option<SessionModelState>)> {
     log_debug("Starting New Session...");
     let new_session_res = client_conn
         .new_session(NewSessionRequest {
-            cwd: std::path::PathBuf::from("/workspace"),
+            cwd: std::path::PathBuf::from(SESSION_CWD),
             mcp_servers: vec![],
             meta: None,
         })
@@ -257,11 +328,7 @@ pub(crate) async fn connect_to_provider(
         new_session_res.models
     ));
 
-    Ok((
-        client_conn,
-        new_session_res.session_id,
-        new_session_res.models,
-    ))
+    Ok((new_session_res.session_id, new_session_res.models))
 }
 
 /// Fetch models from a provider without keeping the connection.
@@ -190,60 +190,70 @@ fn create_demo_chat_entries() -> Vec<ChatEntry> {
             title: "Read src/auth/mod.rs".to_string(),
             kind: agent_client_protocol::ToolKind::Read,
             status: agent_client_protocol::ToolCallStatus::Completed,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-2".to_string(),
             title: "Edit src/auth/jwt.rs - add token validation".to_string(),
             kind: agent_client_protocol::ToolKind::Edit,
             status: agent_client_protocol::ToolCallStatus::InProgress,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-3".to_string(),
             title: "Delete src/auth/deprecated.rs".to_string(),
             kind: agent_client_protocol::ToolKind::Delete,
             status: agent_client_protocol::ToolCallStatus::Completed,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-4".to_string(),
             title: "Move src/utils/hash.rs → src/auth/hash.rs".to_string(),
             kind: agent_client_protocol::ToolKind::Move,
             status: agent_client_protocol::ToolCallStatus::Completed,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-5".to_string(),
             title: "Search for \"password\" in src/".to_string(),
             kind: agent_client_protocol::ToolKind::Search,
             status: agent_client_protocol::ToolCallStatus::Completed,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-6".to_string(),
             title: "Execute: cargo test auth::tests".to_string(),
             kind: agent_client_protocol::ToolKind::Execute,
             status: agent_client_protocol::ToolCallStatus::Failed,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-7".to_string(),
             title: "Analyzing authentication flow".to_string(),
             kind: agent_client_protocol::ToolKind::Think,
             status: agent_client_protocol::ToolCallStatus::Completed,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-8".to_string(),
             title: "Fetch https://docs.rs/jsonwebtoken".to_string(),
             kind: agent_client_protocol::ToolKind::Fetch,
             status: agent_client_protocol::ToolCallStatus::Pending,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-9".to_string(),
             title: "Switch to code-review mode".to_string(),
             kind: agent_client_protocol::ToolKind::SwitchMode,
             status: agent_client_protocol::ToolCallStatus::Completed,
+            output: String::new(),
         },
         ChatEntry::ToolCall {
             id: "tool-10".to_string(),
             title: "Custom: generate-schema".to_string(),
             kind: agent_client_protocol::ToolKind::Other,
             status: agent_client_protocol::ToolCallStatus::InProgress,
+            output: String::new(),
         },
         ChatEntry::Message {
             role: "User".to_string(),
@@ -1,12 +1,22 @@
 use std::sync::Arc;
 
-use agent_client_protocol::{ModelId, SessionId, SessionModelState, SessionNotification};
+use agent_client_protocol::{
+    ModelId, RequestPermissionOutcome, RequestPermissionRequest, SessionId, SessionModelState,
+    SessionNotification,
+};
+use tokio::sync::oneshot;
 
 use crate::acp_client::provider::AcpProvider;
+use crate::acp_client::stop_reason::{ProviderExit, TurnEnd};
 use crate::acp_client::workspace_sync::WorkspaceSyncStatus;
 
 pub(crate) enum AppEvent {
     SessionUpdate(Box<SessionNotification>),
+    /// The agent asks before running a tool call; answer on `respond`
+    PermissionRequest {
+        request: Box<RequestPermissionRequest>,
+        respond: oneshot::Sender<RequestPermissionOutcome>,
+    },
     DebugMessage {
         direction: String,
         message: String,
@@ -31,10 +41,10 @@ pub(crate) enum AppEvent {
     ModelSwitchFailed {
         error: String,
     },
-    /// ACP request error (prompt, tool calls, etc.)
-    RequestError {
-        error: String,
-    },
+    /// The turn started by a queued prompt ended, normally or not
+    PromptFinished(TurnEnd),
+    /// The provider process behind the connection exited
+    ProviderExited(ProviderExit),
     /// Models loaded for a provider (for the model picker)
     ProviderModelsLoaded {
         provider: AcpProvider,
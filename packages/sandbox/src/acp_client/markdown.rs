@@ -93,15 +93,10 @@ pub(crate) fn markdown_to_lines(source: &str) -> Vec<Line<'static>> {
                 current_spans.push(Span::styled(format!("`{}`", code), code_style));
             }
             MdEvent::Start(Tag::Paragraph) => {}
-            MdEvent::End(TagEnd::Paragraph) => {
-                if !current_spans.is_empty() {
-                    lines.push(Line::from(std::mem::take(&mut current_spans)));
-                }
-            }
-            MdEvent::SoftBreak | MdEvent::HardBreak => {
-                if !current_spans.is_empty() {
-                    lines.push(Line::from(std::mem::take(&mut current_spans)));
-                }
+            MdEvent::End(TagEnd::Paragraph) | MdEvent::SoftBreak | MdEvent::HardBreak
+                if !current_spans.is_empty() =>
+            {
+                lines.push(Line::from(std::mem::take(&mut current_spans)));
             }
             MdEvent::Start(Tag::Heading { level, .. }) => {
                 let prefix = "#".repeat(level as usize);
@@ -110,18 +105,14 @@ pub(crate) fn markdown_to_lines(source: &str) -> Vec<Line<'static>> {
                     .add_modifier(ratatui::style::Modifier::BOLD);
                 current_spans.push(Span::styled(format!("{} ", prefix), header_style));
             }
-            MdEvent::End(TagEnd::Heading(_)) => {
-                if !current_spans.is_empty() {
-                    lines.push(Line::from(std::mem::take(&mut current_spans)));
-                }
+            MdEvent::End(TagEnd::Heading(_)) if !current_spans.is_empty() => {
+                lines.push(Line::from(std::mem::take(&mut current_spans)));
             }
             MdEvent::Start(Tag::Item) => {
                 current_spans.push(Span::raw("• ".to_owned()));
             }
-            MdEvent::End(TagEnd::Item) => {
-                if !current_spans.is_empty() {
-                    lines.push(Line::from(std::mem::take(&mut current_spans)));
-                }
+            MdEvent::End(TagEnd::Item) if !current_spans.is_empty() => {
+                lines.push(Line::from(std::mem::take(&mut current_spans)));
             }
             _ => {}
         }
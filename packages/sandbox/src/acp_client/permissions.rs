@@ -0,0 +1,338 @@
+//! How a chat session answers the agent's `session/request_permission` calls.
+//!
+//! In `auto` mode the agent's first option is picked straight away, as the
+//! client always did. In `ask` mode the request is shown to the user, who
+//! picks an option by number; if nobody answers within the timeout, the
+//! configured fallback (allow or deny) is applied so the agent never hangs.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use agent_client_protocol::{
+    PermissionOption, PermissionOptionId, PermissionOptionKind, RequestPermissionOutcome,
+    RequestPermissionRequest,
+};
+use clap::ValueEnum;
+use tokio::sync::oneshot;
+
+pub const DEFAULT_PERMISSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum PermissionMode {
+    /// Approve every request with the agent's first option
+    #[default]
+    Auto,
+    /// Ask in the chat and wait for an answer
+    Ask,
+}
+
+/// Answer used when an `ask` prompt times out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum PermissionFallback {
+    Allow,
+    #[default]
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermissionPolicy {
+    pub mode: PermissionMode,
+    pub timeout: Duration,
+    pub fallback: PermissionFallback,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self {
+            mode: PermissionMode::default(),
+            timeout: DEFAULT_PERMISSION_TIMEOUT,
+            fallback: PermissionFallback::default(),
+        }
+    }
+}
+
+/// A request waiting for the user; dropping it answers `Cancelled`.
+pub(crate) struct PendingPermission {
+    pub(crate) title: String,
+    pub(crate) options: Vec<PermissionOption>,
+    pub(crate) deadline: Instant,
+    respond: oneshot::Sender<RequestPermissionOutcome>,
+}
+
+impl PendingPermission {
+    fn answer(self, outcome: RequestPermissionOutcome) {
+        // The agent may have given up on the request already
+        let _ = self.respond.send(outcome);
+    }
+}
+
+/// What the user was told after a request was settled.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PermissionNotice {
+    Answered { title: String, choice: String },
+    TimedOut { title: String, choice: String },
+}
+
+impl PermissionNotice {
+    pub(crate) fn text(&self) -> String {
+        match self {
+            PermissionNotice::Answered { title, choice } => format!("{choice}: {title}"),
+            PermissionNotice::TimedOut { title, choice } => {
+                format!("No answer in time, {choice}: {title}")
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct PermissionPrompts {
+    pub(crate) policy: PermissionPolicy,
+    pending: VecDeque<PendingPermission>,
+}
+
+impl PermissionPrompts {
+    pub(crate) fn new(policy: PermissionPolicy) -> Self {
+        Self {
+            policy,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The request currently shown to the user, oldest first.
+    pub(crate) fn current(&self) -> Option<&PendingPermission> {
+        self.pending.front()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Answer right away in `auto` mode, otherwise queue for the user.
+    pub(crate) fn request(
+        &mut self,
+        request: RequestPermissionRequest,
+        respond: oneshot::Sender<RequestPermissionOutcome>,
+        now: Instant,
+    ) {
+        if self.policy.mode == PermissionMode::Auto {
+            let _ = respond.send(auto_outcome(&request.options));
+            return;
+        }
+        let title = request
+            .tool_call
+            .fields
+            .title
+            .unwrap_or_else(|| request.tool_call.id.to_string());
+        // Each prompt gets the full timeout once it reaches the front
+        let deadline = self
+            .pending
+            .back()
+            .map_or(now, |last| last.deadline.max(now))
+            + self.policy.timeout;
+        self.pending.push_back(PendingPermission {
+            title,
+            options: request.options,
+            deadline,
+            respond,
+        });
+    }
+
+    /// Pick option `index` (0-based) of the current request.
+    pub(crate) fn choose(&mut self, index: usize, now: Instant) -> Option<PermissionNotice> {
+        let option = self.current()?.options.get(index)?.clone();
+        let pending = self.pop_front(now)?;
+        let title = pending.title.clone();
+        pending.answer(RequestPermissionOutcome::Selected {
+            option_id: option.id,
+        });
+        Some(PermissionNotice::Answered {
+            title,
+            choice: option.name,
+        })
+    }
+
+    /// Reject the current request with its reject-once option.
+    pub(crate) fn reject(&mut self, now: Instant) -> Option<PermissionNotice> {
+        let pending = self.pop_front(now)?;
+        let (outcome, choice) = fallback_outcome(&pending.options, PermissionFallback::Deny);
+        let title = pending.title.clone();
+        pending.answer(outcome);
+        Some(PermissionNotice::Answered { title, choice })
+    }
+
+    /// Apply the fallback to every request whose deadline has passed.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<PermissionNotice> {
+        let mut notices = Vec::new();
+        while self.current().is_some_and(|p| p.deadline <= now) {
+            let Some(pending) = self.pending.pop_front() else {
+                break;
+            };
+            let (outcome, choice) = fallback_outcome(&pending.options, self.policy.fallback);
+            let title = pending.title.clone();
+            pending.answer(outcome);
+            notices.push(PermissionNotice::TimedOut { title, choice });
+        }
+        notices
+    }
+
+    /// Remove the current request and restart the clock for the next one.
+    fn pop_front(&mut self, now: Instant) -> Option<PendingPermission> {
+        let pending = self.pending.pop_front()?;
+        let mut deadline = now;
+        for next in self.pending.iter_mut() {
+            deadline += self.policy.timeout;
+            next.deadline = deadline;
+        }
+        Some(pending)
+    }
+}
+
+/// The old always-approve behaviour: the agent's first option.
+pub(crate) fn auto_outcome(options: &[PermissionOption]) -> RequestPermissionOutcome {
+    let option_id = options
+        .first()
+        .map(|o| o.id.clone())
+        .unwrap_or(PermissionOptionId("allow".into()));
+    RequestPermissionOutcome::Selected { option_id }
+}
+
+/// The once-only option matching `fallback` (falling back to the always
+/// variant), or `Cancelled` when the agent offered neither.
+pub(crate) fn fallback_outcome(
+    options: &[PermissionOption],
+    fallback: PermissionFallback,
+) -> (RequestPermissionOutcome, String) {
+    let kinds = match fallback {
+        PermissionFallback::Allow => [
+            PermissionOptionKind::AllowOnce,
+            PermissionOptionKind::AllowAlways,
+        ],
+        PermissionFallback::Deny => [
+            PermissionOptionKind::RejectOnce,
+            PermissionOptionKind::RejectAlways,
+        ],
+    };
+    kinds
+        .iter()
+        .find_map(|kind| options.iter().find(|o| o.kind == *kind))
+        .map(|o| {
+            (
+                RequestPermissionOutcome::Selected {
+                    option_id: o.id.clone(),
+                },
+                o.name.clone(),
+            )
+        })
+        .unwrap_or_else(|| (RequestPermissionOutcome::Cancelled, "Cancelled".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(title: &str) -> RequestPermissionRequest {
+        serde_json::from_value(json!({
+            "sessionId": "s1",
+            "toolCall": { "toolCallId": "call-1", "title": title },
+            "options": [
+                { "optionId": "yes", "name": "Allow", "kind": "allow_once" },
+                { "optionId": "always", "name": "Always allow", "kind": "allow_always" },
+                { "optionId": "no", "name": "Reject", "kind": "reject_once" },
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn selected(id: &str) -> RequestPermissionOutcome {
+        RequestPermissionOutcome::Selected {
+            option_id: PermissionOptionId(id.into()),
+        }
+    }
+
+    fn ask(fallback: PermissionFallback) -> PermissionPrompts {
+        PermissionPrompts::new(PermissionPolicy {
+            mode: PermissionMode::Ask,
+            timeout: Duration::from_secs(10),
+            fallback,
+        })
+    }
+
+    #[test]
+    fn auto_mode_answers_immediately_with_first_option() {
+        let mut prompts = PermissionPrompts::default();
+        let (tx, mut rx) = oneshot::channel();
+        prompts.request(request("rm -rf build"), tx, Instant::now());
+        assert_eq!(rx.try_recv().unwrap(), selected("yes"));
+        assert!(prompts.current().is_none());
+    }
+
+    #[test]
+    fn ask_mode_waits_for_the_users_choice() {
+        let mut prompts = ask(PermissionFallback::Deny);
+        let now = Instant::now();
+        let (tx, mut rx) = oneshot::channel();
+        prompts.request(request("cargo test"), tx, now);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(prompts.current().unwrap().title, "cargo test");
+
+        assert!(prompts.choose(7, now).is_none(), "out of range is ignored");
+        assert_eq!(
+            prompts.choose(1, now),
+            Some(PermissionNotice::Answered {
+                title: "cargo test".into(),
+                choice: "Always allow".into()
+            })
+        );
+        assert_eq!(rx.try_recv().unwrap(), selected("always"));
+        assert_eq!(prompts.len(), 0);
+    }
+
+    #[test]
+    fn timed_out_prompts_use_the_fallback_one_at_a_time() {
+        let mut prompts = ask(PermissionFallback::Allow);
+        let now = Instant::now();
+        let (first_tx, mut first) = oneshot::channel();
+        let (second_tx, mut second) = oneshot::channel();
+        prompts.request(request("first"), first_tx, now);
+        prompts.request(request("second"), second_tx, now);
+
+        assert!(prompts.expire(now + Duration::from_secs(9)).is_empty());
+        let notices = prompts.expire(now + Duration::from_secs(10));
+        assert_eq!(
+            notices,
+            [PermissionNotice::TimedOut {
+                title: "first".into(),
+                choice: "Allow".into()
+            }]
+        );
+        assert_eq!(first.try_recv().unwrap(), selected("yes"));
+        assert!(second.try_recv().is_err(), "second gets its own timeout");
+
+        // Answering restarts the clock for whatever is next
+        let (third_tx, mut third) = oneshot::channel();
+        prompts.request(request("third"), third_tx, now + Duration::from_secs(10));
+        assert!(prompts.reject(now + Duration::from_secs(15)).is_some());
+        assert_eq!(second.try_recv().unwrap(), selected("no"));
+        assert!(prompts.expire(now + Duration::from_secs(24)).is_empty());
+        assert_eq!(prompts.expire(now + Duration::from_secs(25)).len(), 1);
+        assert_eq!(third.try_recv().unwrap(), selected("yes"));
+    }
+
+    #[test]
+    fn fallback_without_matching_option_cancels() {
+        let options: Vec<PermissionOption> = serde_json::from_value(json!([
+            { "optionId": "go", "name": "Go", "kind": "allow_always" },
+        ]))
+        .unwrap();
+        assert_eq!(
+            fallback_outcome(&options, PermissionFallback::Allow).0,
+            selected("go")
+        );
+        assert_eq!(
+            fallback_outcome(&options, PermissionFallback::Deny).0,
+            RequestPermissionOutcome::Cancelled
+        );
+        assert_eq!(auto_outcome(&[]), selected("allow"));
+    }
+}
@@ -0,0 +1,155 @@
+//! Ordered queue of prompts waiting to be sent to the agent.
+//!
+//! Only one turn runs at a time: a prompt is sent once the previous turn has
+//! finished and its `not_before` time (if any) has passed. Prompts never
+//! overtake each other, so a delayed prompt also holds back the ones after it.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Local, NaiveTime, TimeZone, Utc};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct QueuedPrompt {
+    pub(crate) text: String,
+    pub(crate) not_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PromptQueue {
+    pending: VecDeque<QueuedPrompt>,
+    turn_in_progress: bool,
+}
+
+impl PromptQueue {
+    pub(crate) fn enqueue(&mut self, prompt: QueuedPrompt) {
+        self.pending.push_back(prompt);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub(crate) fn is_busy(&self) -> bool {
+        self.turn_in_progress
+    }
+
+    /// Pop the next prompt if no turn is running and it is due, marking a
+    /// turn as started.
+    pub(crate) fn next_ready(&mut self, now: DateTime<Utc>) -> Option<QueuedPrompt> {
+        if self.turn_in_progress {
+            return None;
+        }
+        let head = self.pending.front()?;
+        if head.not_before.is_some_and(|t| t > now) {
+            return None;
+        }
+        self.turn_in_progress = true;
+        self.pending.pop_front()
+    }
+
+    pub(crate) fn finish_turn(&mut self) {
+        self.turn_in_progress = false;
+    }
+}
+
+/// Parse chat input into a prompt. `/in <n>[smh] <text>` delays by a
+/// duration and `/at <HH:MM> <text>` waits for the next local wall-clock time;
+/// anything else is sent as soon as the previous turn finishes.
+pub(crate) fn parse_prompt_input(input: &str, now: DateTime<Utc>) -> Result<QueuedPrompt, String> {
+    let Some((command @ ("/in" | "/at"), rest)) = input.split_once(char::is_whitespace) else {
+        return Ok(QueuedPrompt {
+            text: input.to_string(),
+            not_before: None,
+        });
+    };
+    let (when, text) = rest
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("Usage: {} <time> <prompt>", command))?;
+    let not_before = if command == "/in" {
+        now + parse_delay(when)?
+    } else {
+        next_local_time(when, now)?
+    };
+    Ok(QueuedPrompt {
+        text: text.trim_start().to_string(),
+        not_before: Some(not_before),
+    })
+}
+
+fn parse_delay(spec: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid delay '{}', expected e.g. 30s, 10m or 2h", spec);
+    let (unit_at, _) = spec.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = spec.split_at(unit_at);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    match unit {
+        "s" => Ok(Duration::seconds(amount)),
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        _ => Err(invalid()),
+    }
+}
+
+fn next_local_time(spec: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let time = NaiveTime::parse_from_str(spec, "%H:%M")
+        .map_err(|_| format!("Invalid time '{}', expected HH:MM", spec))?;
+    let local_now = now.with_timezone(&Local);
+    let mut date = local_now.date_naive();
+    if local_now.time() >= time {
+        date = date.succ_opt().unwrap_or(date);
+    }
+    Local
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+        .ok_or_else(|| format!("Time '{}' does not exist today", spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(text: &str, not_before: Option<DateTime<Utc>>) -> QueuedPrompt {
+        QueuedPrompt {
+            text: text.to_string(),
+            not_before,
+        }
+    }
+
+    #[test]
+    fn prompts_wait_for_previous_turn_and_their_time() {
+        let now = Utc::now();
+        let mut queue = PromptQueue::default();
+        queue.enqueue(prompt("first", None));
+        queue.enqueue(prompt("later", Some(now + Duration::minutes(5))));
+        queue.enqueue(prompt("after", None));
+
+        assert_eq!(queue.next_ready(now).unwrap().text, "first");
+        assert!(queue.next_ready(now).is_none(), "turn still running");
+        queue.finish_turn();
+        assert!(queue.next_ready(now).is_none(), "head not due yet");
+        let due = now + Duration::minutes(5);
+        assert_eq!(queue.next_ready(due).unwrap().text, "later");
+        queue.finish_turn();
+        assert_eq!(queue.next_ready(due).unwrap().text, "after");
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn parses_delay_commands() {
+        let now = Utc::now();
+        assert_eq!(
+            parse_prompt_input("/in 10m run the tests", now).unwrap(),
+            prompt("run the tests", Some(now + Duration::minutes(10)))
+        );
+        assert_eq!(
+            parse_prompt_input("fix /in the parser", now).unwrap(),
+            prompt("fix /in the parser", None)
+        );
+        assert!(parse_prompt_input("/in soon do it", now).is_err());
+        assert!(parse_prompt_input("/at 25:00 do it", now).is_err());
+        let at = parse_prompt_input("/at 09:30 standup notes", now).unwrap();
+        let wait = at.not_before.unwrap() - now;
+        assert!(wait > Duration::zero() && wait <= Duration::days(1));
+    }
+}
@@ -1,5 +1,79 @@
 use clap::ValueEnum;
 
+use crate::models::AcpProviderCapabilities;
+
+/// Wrapper every provider runs under, for unbuffered I/O
+const STDBUF: &str = "/usr/bin/stdbuf -i0 -o0 -e0";
+
+/// How a provider's CLI is spawned and what it accepts once running. The
+/// spawn command and the advertised capabilities are both built from this,
+/// so they can't disagree.
+#[derive(Debug, Clone, Copy)]
+pub struct ProviderSpec {
+    /// Executable to run
+    pub binary: &'static str,
+    /// Arguments that put the CLI in ACP mode
+    pub args: &'static [&'static str],
+    /// Codex `-c key="value"` config overrides
+    pub config: &'static [(&'static str, &'static str)],
+    /// Model the CLI is pinned to, passed as the `model` config override
+    pub model: Option<&'static str>,
+    /// Session modes accepted by `session/set_mode`
+    pub permission_modes: &'static [&'static str],
+    pub supports_set_model: bool,
+    /// Whether the CLI implements `session/load`
+    pub supports_resume: bool,
+    /// Variables the CLI reads its API key from
+    pub required_env: &'static [&'static str],
+}
+
+const CODEX: ProviderSpec = ProviderSpec {
+    binary: "/usr/local/bin/codex-acp",
+    args: &[],
+    config: &[
+        ("approval_policy", "never"),
+        ("sandbox_mode", "danger-full-access"),
+    ],
+    model: Some("gpt-5.1-codex-max"),
+    permission_modes: &["read-only", "auto", "full-access"],
+    supports_set_model: true,
+    supports_resume: true,
+    required_env: &["OPENAI_API_KEY"],
+};
+
+const OPENCODE: ProviderSpec = ProviderSpec {
+    binary: "opencode",
+    args: &["acp"],
+    config: &[],
+    model: None,
+    permission_modes: &[],
+    supports_set_model: true,
+    supports_resume: true,
+    required_env: &[],
+};
+
+const CLAUDE: ProviderSpec = ProviderSpec {
+    binary: "claude-code-acp",
+    args: &[],
+    config: &[],
+    model: None,
+    permission_modes: &["default", "acceptEdits", "plan", "bypassPermissions"],
+    supports_set_model: true,
+    supports_resume: true,
+    required_env: &["ANTHROPIC_API_KEY"],
+};
+
+const GEMINI: ProviderSpec = ProviderSpec {
+    binary: "gemini",
+    args: &["--experimental-acp"],
+    config: &[],
+    model: None,
+    permission_modes: &[],
+    supports_set_model: false,
+    supports_resume: false,
+    required_env: &["GEMINI_API_KEY"],
+};
+
 /// Available ACP (Agent Client Protocol) providers
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum)]
 pub enum AcpProvider {
@@ -35,16 +109,63 @@ impl AcpProvider {
         }
     }
 
+    /// How this provider is spawned
+    pub fn spec(&self) -> &'static ProviderSpec {
+        match self {
+            AcpProvider::Codex => &CODEX,
+            AcpProvider::Opencode => &OPENCODE,
+            AcpProvider::Claude => &CLAUDE,
+            AcpProvider::Gemini => &GEMINI,
+        }
+    }
+
     /// Get the command to execute for this provider
     /// Commands are wrapped with stdbuf for unbuffered I/O
-    pub fn command(&self) -> &'static str {
+    pub fn command(&self) -> String {
+        let spec = self.spec();
+        let mut command = format!("{} {}", STDBUF, spec.binary);
+        for arg in spec.args {
+            command.push(' ');
+            command.push_str(arg);
+        }
+        let model = spec.model.map(|model| ("model", model));
+        for (key, value) in spec.config.iter().copied().chain(model) {
+            command.push_str(&format!(" -c {}=\"{}\"", key, value));
+        }
+        command
+    }
+
+    /// Executable that [`Self::command`] runs, after the `stdbuf` wrapper
+    pub fn binary(&self) -> &'static str {
+        self.spec().binary
+    }
+
+    /// Oldest CLI version that speaks the ACP features this client uses
+    pub fn min_version(&self) -> &'static str {
         match self {
-            AcpProvider::Codex => {
-                "/usr/bin/stdbuf -i0 -o0 -e0 /usr/local/bin/codex-acp -c approval_policy=\"never\" -c sandbox_mode=\"danger-full-access\" -c model=\"gpt-5.1-codex-max\""
-            }
-            AcpProvider::Opencode => "/usr/bin/stdbuf -i0 -o0 -e0 opencode acp",
-            AcpProvider::Claude => "/usr/bin/stdbuf -i0 -o0 -e0 claude-code-acp",
-            AcpProvider::Gemini => "/usr/bin/stdbuf -i0 -o0 -e0 gemini --experimental-acp",
+            AcpProvider::Codex => "0.3.0",
+            AcpProvider::Opencode => "0.9.0",
+            AcpProvider::Claude => "0.4.0",
+            AcpProvider::Gemini => "0.4.0",
+        }
+    }
+
+    /// Variables that also authenticate the provider besides `required_env`,
+    /// such as the Claude OAuth token `cmux setup-claude` stores
+    pub fn alternate_auth_env(&self) -> &'static [&'static str] {
+        match self {
+            AcpProvider::Claude => &["CLAUDE_CODE_OAUTH_TOKEN"],
+            _ => &[],
+        }
+    }
+
+    /// Sandbox files that authenticate the provider when no auth variable is set
+    pub fn auth_files(&self) -> &'static [&'static str] {
+        match self {
+            AcpProvider::Codex => &["/root/.codex/auth.json"],
+            AcpProvider::Opencode => &["/root/.local/share/opencode/auth.json"],
+            AcpProvider::Claude => &["/root/.claude/.credentials.json"],
+            AcpProvider::Gemini => &["/root/.gemini/oauth_creds.json"],
         }
     }
 
@@ -58,6 +179,24 @@ impl AcpProvider {
         }
     }
 
+    /// Describe what this provider supports when spawned via [`Self::command`]
+    pub fn capabilities(&self) -> AcpProviderCapabilities {
+        let spec = self.spec();
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        AcpProviderCapabilities {
+            id: self.short_name().to_string(),
+            display_name: self.display_name().to_string(),
+            default_model: spec.model.map(str::to_string),
+            models: strings(spec.model.as_slice()),
+            permission_modes: strings(spec.permission_modes),
+            supports_set_model: spec.supports_set_model,
+            // session/cancel is a required part of the protocol
+            supports_cancel: true,
+            supports_resume: spec.supports_resume,
+            required_env: strings(spec.required_env),
+        }
+    }
+
     /// Parse a short name back to AcpProvider
     pub fn from_short_name(name: &str) -> Option<AcpProvider> {
         match name {
@@ -69,3 +208,48 @@ impl AcpProvider {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_follow_spawn_command() {
+        let codex = AcpProvider::Codex.capabilities();
+        assert_eq!(codex.id, "codex");
+        assert_eq!(codex.default_model.as_deref(), Some("gpt-5.1-codex-max"));
+        assert_eq!(codex.models, vec!["gpt-5.1-codex-max".to_string()]);
+
+        let claude = AcpProvider::Claude.capabilities();
+        assert_eq!(claude.default_model, None);
+        assert!(claude.models.is_empty());
+        assert!(claude.permission_modes.contains(&"plan".to_string()));
+        assert_eq!(claude.required_env, vec!["ANTHROPIC_API_KEY".to_string()]);
+    }
+
+    #[test]
+    fn command_is_built_from_spec() {
+        assert_eq!(
+            AcpProvider::Codex.command(),
+            "/usr/bin/stdbuf -i0 -o0 -e0 /usr/local/bin/codex-acp -c approval_policy=\"never\" -c sandbox_mode=\"danger-full-access\" -c model=\"gpt-5.1-codex-max\""
+        );
+        assert_eq!(
+            AcpProvider::Gemini.command(),
+            "/usr/bin/stdbuf -i0 -o0 -e0 gemini --experimental-acp"
+        );
+    }
+
+    #[test]
+    fn binary_matches_spawn_command() {
+        for provider in AcpProvider::all() {
+            let command = provider.command();
+            assert!(command.starts_with("/usr/bin/stdbuf "));
+            assert!(
+                command
+                    .split_whitespace()
+                    .any(|arg| arg == provider.binary()),
+                "{command}"
+            );
+        }
+    }
+}
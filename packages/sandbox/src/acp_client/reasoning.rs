@@ -0,0 +1,93 @@
+use clap::ValueEnum;
+
+/// What a chat session does with agent reasoning (`AgentThoughtChunk`) updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ReasoningVisibility {
+    /// Show reasoning live and include it in exported transcripts
+    #[default]
+    Persist,
+    /// Show reasoning live but never write it to disk
+    Stream,
+    /// Discard reasoning as soon as it arrives
+    Drop,
+}
+
+impl ReasoningVisibility {
+    pub fn shows(self) -> bool {
+        !matches!(self, ReasoningVisibility::Drop)
+    }
+
+    pub fn persists(self) -> bool {
+        matches!(self, ReasoningVisibility::Persist)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ReasoningVisibility::Persist => "shown and saved in transcripts",
+            ReasoningVisibility::Stream => "shown live only, never saved",
+            ReasoningVisibility::Drop => "dropped",
+        }
+    }
+
+    /// Next setting when cycling from the command palette.
+    pub(crate) fn next(self) -> Self {
+        match self {
+            ReasoningVisibility::Persist => ReasoningVisibility::Stream,
+            ReasoningVisibility::Stream => ReasoningVisibility::Drop,
+            ReasoningVisibility::Drop => ReasoningVisibility::Persist,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acp_client::provider::AcpProvider;
+    use crate::acp_client::state::{App, ChatEntry};
+
+    fn message(role: &str, text: &str) -> ChatEntry {
+        ChatEntry::Message {
+            role: role.into(),
+            text: text.into(),
+            normalized_markdown: None,
+        }
+    }
+
+    #[test]
+    fn dropping_reasoning_purges_what_was_shown() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut app = App::new(AcpProvider::Codex, tx, String::new(), "sb".into());
+        app.history = vec![message("User", "hi"), message("Thought", "secret plan")];
+
+        app.set_reasoning_visibility(ReasoningVisibility::Stream);
+        assert_eq!(app.reasoning_visibility, ReasoningVisibility::Stream);
+        assert!(app
+            .history
+            .iter()
+            .any(|e| matches!(e, ChatEntry::Message { role, .. } if role == "Thought")));
+
+        app.set_reasoning_visibility(app.reasoning_visibility.next());
+        assert_eq!(app.reasoning_visibility, ReasoningVisibility::Drop);
+        assert!(!app
+            .history
+            .iter()
+            .any(|e| matches!(e, ChatEntry::Message { role, .. } if role == "Thought")));
+        assert!(matches!(
+            app.history.last(),
+            Some(ChatEntry::Message { role, text, .. })
+                if role == "System" && text == "Agent reasoning: dropped"
+        ));
+    }
+
+    #[test]
+    fn only_persist_writes_reasoning() {
+        assert!(ReasoningVisibility::Persist.persists());
+        assert!(!ReasoningVisibility::Stream.persists());
+        assert!(ReasoningVisibility::Stream.shows());
+        assert!(!ReasoningVisibility::Drop.shows());
+        assert_eq!(
+            ReasoningVisibility::from_str("stream", true),
+            Ok(ReasoningVisibility::Stream)
+        );
+    }
+}
@@ -10,16 +10,24 @@ use crossterm::{
 };
 use futures::StreamExt;
 use ratatui::{backend::CrosstermBackend, Terminal};
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
 use crate::acp_client::config::{load_last_model, save_last_model, save_last_provider};
-use crate::acp_client::connection::{connect_to_provider, fetch_provider_models};
+use crate::acp_client::connection::fetch_provider_models;
 use crate::acp_client::events::AppEvent;
 use crate::acp_client::logging::log_debug;
+use crate::acp_client::permissions::{PermissionPolicy, PermissionPrompts};
 use crate::acp_client::provider::AcpProvider;
+use crate::acp_client::reasoning::ReasoningVisibility;
 use crate::acp_client::state::{App, ConnectionState, PaletteCommand, UiMode};
 use crate::acp_client::ui::ui;
+use crate::acp_client::warm_pool::{
+    connect_with_pool, refill, SharedWarmPool, WarmPool, WarmPoolSizes,
+};
 use crate::acp_client::workspace_sync::WorkspaceSyncStatus;
 use crate::terminal_guard;
 
@@ -28,6 +36,7 @@ fn spawn_provider_tasks(
     base_url: String,
     sandbox_id: String,
     initial_provider: AcpProvider,
+    pool: SharedWarmPool,
 ) {
     for provider in AcpProvider::all() {
         let tx_clone = tx.clone();
@@ -36,8 +45,10 @@ fn spawn_provider_tasks(
         let provider = *provider;
 
         if provider == initial_provider {
+            let pool = pool.clone();
             tokio::task::spawn_local(async move {
-                match connect_to_provider(
+                match connect_with_pool(
+                    &pool,
                     &base_url_clone,
                     &sandbox_id_clone,
                     provider,
@@ -63,6 +74,7 @@ fn spawn_provider_tasks(
                 }
             });
         } else {
+            refill(&pool, &base_url, &sandbox_id, provider, tx.clone());
             tokio::task::spawn_local(async move {
                 fetch_provider_models(&base_url_clone, &sandbox_id_clone, provider, tx_clone).await;
             });
@@ -75,13 +87,28 @@ pub async fn run_chat_tui(
     sandbox_id: String,
     provider: AcpProvider,
 ) -> Result<()> {
-    run_chat_tui_with_workspace_status(base_url, sandbox_id, provider, None).await
+    run_chat_tui_with_workspace_status(
+        base_url,
+        sandbox_id,
+        provider,
+        ReasoningVisibility::default(),
+        PermissionPolicy::default(),
+        None,
+        WarmPoolSizes::default(),
+        None,
+    )
+    .await
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run_chat_tui_with_workspace_status(
     base_url: String,
     sandbox_id: String,
     provider: AcpProvider,
+    reasoning: ReasoningVisibility,
+    permissions: PermissionPolicy,
+    turn_timeout: Option<Duration>,
+    warm_pool: WarmPoolSizes,
     workspace_status_rx: Option<mpsc::UnboundedReceiver<WorkspaceSyncStatus>>,
 ) -> Result<()> {
     let mut stdout = std::io::stdout();
@@ -106,6 +133,10 @@ pub async fn run_chat_tui_with_workspace_status(
             base_url,
             sandbox_id,
             provider,
+            reasoning,
+            permissions,
+            turn_timeout,
+            warm_pool,
             workspace_status_rx,
         ))
         .await;
@@ -147,11 +178,16 @@ pub async fn run_chat_tui_with_workspace_status(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run_main_loop<B: ratatui::backend::Backend>(
     terminal: &mut Terminal<B>,
     base_url: String,
     sandbox_id: String,
     initial_provider: AcpProvider,
+    reasoning: ReasoningVisibility,
+    permissions: PermissionPolicy,
+    turn_timeout: Option<Duration>,
+    warm_pool: WarmPoolSizes,
     workspace_status_rx: Option<mpsc::UnboundedReceiver<WorkspaceSyncStatus>>,
 ) -> Result<()> {
     log_debug(&format!(
@@ -161,6 +197,7 @@ async fn run_main_loop<B: ratatui::backend::Backend>(
     let (tx, rx) = mpsc::unbounded_channel();
 
     let provider_tasks_started = workspace_status_rx.is_none();
+    let pool: SharedWarmPool = Rc::new(RefCell::new(WarmPool::new(warm_pool)));
 
     if let Some(mut workspace_rx) = workspace_status_rx {
         let tx_clone = tx.clone();
@@ -168,6 +205,7 @@ async fn run_main_loop<B: ratatui::backend::Backend>(
         let sandbox_id_clone = sandbox_id.clone();
         let initial_provider_clone = initial_provider;
         let mut tasks_started = provider_tasks_started;
+        let pool = pool.clone();
         tokio::task::spawn_local(async move {
             while let Some(status) = workspace_rx.recv().await {
                 let is_done = matches!(
@@ -182,6 +220,7 @@ async fn run_main_loop<B: ratatui::backend::Backend>(
                         base_url_clone.clone(),
                         sandbox_id_clone.clone(),
                         initial_provider_clone,
+                        pool.clone(),
                     );
                 }
             }
@@ -191,6 +230,7 @@ async fn run_main_loop<B: ratatui::backend::Backend>(
                     base_url_clone,
                     sandbox_id_clone,
                     initial_provider_clone,
+                    pool,
                 );
             }
         });
@@ -203,6 +243,10 @@ async fn run_main_loop<B: ratatui::backend::Backend>(
         sandbox_id.clone(),
     );
     app.connection_state = ConnectionState::Connecting;
+    app.reasoning_visibility = reasoning;
+    app.permissions = PermissionPrompts::new(permissions);
+    app.turn_timeout = turn_timeout;
+    app.warm_pool = pool.clone();
 
     for provider in AcpProvider::all() {
         app.providers_loading.push(*provider);
@@ -214,6 +258,7 @@ async fn run_main_loop<B: ratatui::backend::Backend>(
             base_url.clone(),
             sandbox_id.clone(),
             initial_provider,
+            pool,
         );
     }
 
@@ -229,14 +274,23 @@ async fn run_app<B: ratatui::backend::Backend>(
     mut rx: mpsc::UnboundedReceiver<AppEvent>,
 ) -> std::io::Result<()> {
     let mut reader = EventStream::new();
+    // Wakes the loop so prompts scheduled with /in or /at go out on time
+    let mut schedule_tick = tokio::time::interval(std::time::Duration::from_secs(1));
 
     loop {
         terminal.draw(|f| ui(f, &mut app))?;
 
         tokio::select! {
+            _ = schedule_tick.tick() => {
+                app.dispatch_queued_prompt();
+                app.expire_permissions();
+            }
             Some(event) = rx.recv() => {
                 match event {
                     AppEvent::SessionUpdate(notification) => app.on_session_update(*notification),
+                    AppEvent::PermissionRequest { request, respond } => {
+                        app.on_permission_request(*request, respond);
+                    }
                     AppEvent::DebugMessage { direction, message } => {
                         app.add_debug_message(&direction, &message);
                     }
@@ -248,6 +302,7 @@ async fn run_app<B: ratatui::backend::Backend>(
                         let was_initial_connection = app.connection_state == ConnectionState::Connecting;
                         app.current_provider = provider;
                         app.client_connection = Some(connection);
+                        app.end_session();
                         app.session_id = Some(session_id);
                         app.model_state = model_state.clone();
                         app.connection_state = ConnectionState::Connected;
@@ -319,13 +374,10 @@ async fn run_app<B: ratatui::backend::Backend>(
                             normalized_markdown: None,
                         });
                     }
-                    AppEvent::RequestError { error } => {
-                        log_debug(&format!("Request error: {}", error));
-                        app.history.push(crate::acp_client::state::ChatEntry::Message {
-                            role: "Error".to_string(),
-                            text: error,
-                            normalized_markdown: None,
-                        });
+                    AppEvent::PromptFinished(end) => app.finish_turn(end),
+                    AppEvent::ProviderExited(exit) => {
+                        log_debug(&format!("Provider exited: {:?}", exit));
+                        app.on_provider_exited(exit);
                     }
                     AppEvent::ProviderModelsLoaded { provider, models } => {
                         log_debug(&format!("Loaded {} models for {}", models.len(), provider.display_name()));
@@ -370,6 +422,13 @@ async fn run_app<B: ratatui::backend::Backend>(
                                                 PaletteCommand::SwitchProviderModel => {
                                                     app.open_switch_palette();
                                                 }
+                                                PaletteCommand::ExportTranscript => {
+                                                    app.export_transcript();
+                                                }
+                                                PaletteCommand::CycleReasoningVisibility => {
+                                                    let next = app.reasoning_visibility.next();
+                                                    app.set_reasoning_visibility(next);
+                                                }
                                             }
                                         }
                                     }
@@ -420,10 +479,16 @@ async fn run_app<B: ratatui::backend::Backend>(
                                         _ => { app.textarea.input(key); }
                                     }
                                 } else {
+                                    let asking = app.permissions.current().is_some();
                                     match key.code {
+                                        // While a permission prompt is shown, digits pick an option
+                                        KeyCode::Char(c @ '1'..='9') if asking => {
+                                            app.answer_permission(c as usize - '1' as usize);
+                                        }
+                                        KeyCode::Esc if asking => app.reject_permission(),
                                         KeyCode::Enter => {
                                             if app.connection_state == ConnectionState::Connected {
-                                                app.send_message().await;
+                                                app.send_message();
                                             }
                                         }
                                         KeyCode::PageUp => {
@@ -1,19 +1,26 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use agent_client_protocol::{
-    Agent, ClientSideConnection, ContentBlock, ModelId, Plan, PromptRequest, SessionId,
-    SessionModelState, SessionNotification, SessionUpdate, SetSessionModelRequest, TextContent,
-    ToolCall, ToolCallStatus, ToolCallUpdate, ToolKind,
+    Agent, CancelNotification, ClientSideConnection, ContentBlock, ModelId, Plan, PromptRequest,
+    RequestPermissionOutcome, RequestPermissionRequest, SessionId, SessionModelState,
+    SessionNotification, SetSessionModelRequest, TextContent, ToolCallStatus, ToolKind,
 };
 use ratatui::widgets::{Block, Borders};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tui_textarea::TextArea;
 
-use crate::acp_client::connection::connect_to_provider;
 use crate::acp_client::events::AppEvent;
-use crate::acp_client::markdown::normalize_code_fences;
+use crate::acp_client::permissions::{PermissionNotice, PermissionPrompts};
+use crate::acp_client::prompt_queue::{parse_prompt_input, PromptQueue};
 use crate::acp_client::provider::AcpProvider;
+use crate::acp_client::reasoning::ReasoningVisibility;
+use crate::acp_client::stop_reason::{ProviderExit, TurnEnd};
+use crate::acp_client::tool_output::WorkspaceSpill;
+use crate::acp_client::transcript::export_transcript;
+use crate::acp_client::turn_pipeline::TurnPipeline;
+use crate::acp_client::warm_pool::{connect_with_pool, SharedWarmPool, WarmPool, WarmPoolSizes};
 use crate::acp_client::workspace_sync::WorkspaceSyncStatus;
 use crate::palette::{fuzzy_match_str, PaletteCommand as PaletteCommandTrait};
 
@@ -29,6 +36,8 @@ pub(crate) enum ChatEntry {
         title: String,
         kind: ToolKind,
         status: ToolCallStatus,
+        /// Text output reported so far, capped by `WorkspaceSpill::cap`
+        output: String,
     },
     Plan(Plan),
 }
@@ -51,6 +60,8 @@ pub(crate) enum UiMode {
 pub(crate) enum PaletteCommand {
     ToggleDebugMode,
     SwitchProviderModel,
+    ExportTranscript,
+    CycleReasoningVisibility,
 }
 
 impl PaletteCommand {
@@ -58,6 +69,8 @@ impl PaletteCommand {
         &[
             PaletteCommand::ToggleDebugMode,
             PaletteCommand::SwitchProviderModel,
+            PaletteCommand::ExportTranscript,
+            PaletteCommand::CycleReasoningVisibility,
         ]
     }
 
@@ -65,6 +78,8 @@ impl PaletteCommand {
         match self {
             PaletteCommand::ToggleDebugMode => "Toggle Debug Mode",
             PaletteCommand::SwitchProviderModel => "Switch Provider / Model",
+            PaletteCommand::ExportTranscript => "Export Transcript",
+            PaletteCommand::CycleReasoningVisibility => "Cycle Reasoning Visibility",
         }
     }
 
@@ -72,6 +87,10 @@ impl PaletteCommand {
         match self {
             PaletteCommand::ToggleDebugMode => "Show/hide raw ACP protocol messages",
             PaletteCommand::SwitchProviderModel => "Change AI provider or model",
+            PaletteCommand::ExportTranscript => "Save this session as Markdown and JSON",
+            PaletteCommand::CycleReasoningVisibility => {
+                "Save, stream-only, or drop agent reasoning for this session"
+            }
         }
     }
 
@@ -142,6 +161,19 @@ pub(crate) struct App<'a> {
     pub(crate) providers_loading: Vec<AcpProvider>,
     pub(crate) pending_model_switch: Option<ModelId>,
     pub(crate) workspace_sync_state: WorkspaceSyncState,
+    pub(crate) prompt_queue: PromptQueue,
+    pub(crate) reasoning_visibility: ReasoningVisibility,
+    pub(crate) permissions: PermissionPrompts,
+    /// Cancel a turn the agent hasn't finished after this long
+    pub(crate) turn_timeout: Option<Duration>,
+    /// How the provider process last exited, to explain a lost connection
+    pub(crate) provider_exit: Option<ProviderExit>,
+    /// History index and end of the last turn, while it is a lost connection
+    /// the exit hasn't explained yet; the exit can be reported after the turn
+    lost_turn: Option<(usize, TurnEnd)>,
+    /// Oversized tool output held until its call finishes
+    tool_output: WorkspaceSpill,
+    pub(crate) warm_pool: SharedWarmPool,
 }
 
 impl<'a> App<'a> {
@@ -178,6 +210,7 @@ impl<'a> App<'a> {
             debug_mode: false,
             debug_messages: vec![],
             event_tx,
+            tool_output: WorkspaceSpill::new(&base_url, &sandbox_id),
             base_url,
             sandbox_id,
             model_state: None,
@@ -186,6 +219,15 @@ impl<'a> App<'a> {
             providers_loading: vec![],
             pending_model_switch: None,
             workspace_sync_state: WorkspaceSyncState::Idle,
+            prompt_queue: PromptQueue::default(),
+            reasoning_visibility: ReasoningVisibility::default(),
+            permissions: PermissionPrompts::default(),
+            turn_timeout: None,
+            provider_exit: None,
+            lost_turn: None,
+            warm_pool: std::rc::Rc::new(std::cell::RefCell::new(WarmPool::new(
+                WarmPoolSizes::disabled(),
+            ))),
         }
     }
 
@@ -439,9 +481,10 @@ impl<'a> App<'a> {
         let tx = self.event_tx.clone();
         let base_url = self.base_url.clone();
         let sandbox_id = self.sandbox_id.clone();
+        let pool = self.warm_pool.clone();
 
         tokio::task::spawn_local(async move {
-            match connect_to_provider(&base_url, &sandbox_id, provider, tx.clone()).await {
+            match connect_with_pool(&pool, &base_url, &sandbox_id, provider, tx.clone()).await {
                 Ok((connection, session_id, model_state)) => {
                     let _ = tx.send(AppEvent::ProviderSwitchComplete {
                         provider,
@@ -498,6 +541,38 @@ impl<'a> App<'a> {
         }
     }
 
+    pub(crate) fn export_transcript(&mut self) {
+        let text = match export_transcript(
+            &self.history,
+            self.current_provider.short_name(),
+            &self.sandbox_id,
+            self.reasoning_visibility.persists(),
+        ) {
+            Ok(path) => format!("Transcript saved to {}", path.display()),
+            Err(e) => format!("Failed to export transcript: {}", e),
+        };
+        self.history.push(ChatEntry::Message {
+            role: "System".to_string(),
+            text,
+            normalized_markdown: None,
+        });
+    }
+
+    pub(crate) fn set_reasoning_visibility(&mut self, visibility: ReasoningVisibility) {
+        self.reasoning_visibility = visibility;
+        if !visibility.shows() {
+            // Dropping means nothing stays around, including what was already shown
+            self.history.retain(
+                |entry| !matches!(entry, ChatEntry::Message { role, .. } if role == "Thought"),
+            );
+        }
+        self.history.push(ChatEntry::Message {
+            role: "System".to_string(),
+            text: format!("Agent reasoning: {}", visibility.label()),
+            normalized_markdown: None,
+        });
+    }
+
     pub(crate) fn toggle_debug_mode(&mut self) {
         self.debug_mode = !self.debug_mode;
         if !self.debug_mode {
@@ -536,133 +611,126 @@ impl<'a> App<'a> {
     }
 
     pub(crate) fn on_session_update(&mut self, notification: SessionNotification) {
-        match notification.update {
-            SessionUpdate::UserMessageChunk(chunk) => {
-                if let ContentBlock::Text(text_content) = chunk.content {
-                    self.append_message("User", &text_content.text);
-                }
-            }
-            SessionUpdate::AgentMessageChunk(chunk) => {
-                if let ContentBlock::Text(text_content) = chunk.content {
-                    self.append_message("Agent", &text_content.text);
-                }
-            }
-            SessionUpdate::AgentThoughtChunk(chunk) => {
-                if let ContentBlock::Text(text_content) = chunk.content {
-                    self.append_message("Thought", &text_content.text);
-                }
-            }
-            SessionUpdate::ToolCall(tool_call) => {
-                self.add_tool_call(tool_call);
-            }
-            SessionUpdate::ToolCallUpdate(update) => {
-                self.update_tool_call(update);
-            }
-            SessionUpdate::Plan(plan) => {
-                self.update_plan(plan);
-            }
-            SessionUpdate::AvailableCommandsUpdate(_) | SessionUpdate::CurrentModeUpdate(_) => {}
+        self.turn_pipeline().apply(notification.update);
+    }
+
+    /// The running prompt ended: report how, settle its tool calls and send
+    /// the next one.
+    pub(crate) fn finish_turn(&mut self, end: TurnEnd) {
+        let exit = self.provider_exit.take();
+        let explained = exit.is_some();
+        let end = end.with_exit(exit);
+        crate::acp_client::logging::log_debug(&format!("Turn ended: {}", end.code()));
+        self.lost_turn =
+            (end.is_connection_lost() && !explained).then(|| (self.history.len(), end.clone()));
+        if let Some(text) = end.message() {
+            let role = if end.is_error() { "Error" } else { "System" };
+            self.history.push(ChatEntry::Message {
+                role: role.to_string(),
+                text,
+                normalized_markdown: None,
+            });
         }
+        self.turn_pipeline().finish_turn();
+        self.prompt_queue.finish_turn();
+        self.dispatch_queued_prompt();
     }
 
-    fn append_message(&mut self, role: &str, text: &str) {
-        if role == "Thought" && text.trim().is_empty() {
+    /// The provider process exited. Explain the turn it cut short if that
+    /// turn already ended, else keep the exit for when it does.
+    pub(crate) fn on_provider_exited(&mut self, exit: ProviderExit) {
+        let Some((index, end)) = self.lost_turn.take() else {
+            self.provider_exit = Some(exit);
             return;
-        }
-        if let Some(ChatEntry::Message {
-            role: last_role,
-            text: last_text,
-            normalized_markdown,
-        }) = self.history.last_mut()
+        };
+        let end = end.with_exit(Some(exit));
+        crate::acp_client::logging::log_debug(&format!("Turn ended: {}", end.code()));
+        if let (
+            Some(ChatEntry::Message {
+                text,
+                normalized_markdown,
+                ..
+            }),
+            Some(message),
+        ) = (self.history.get_mut(index), end.message())
         {
-            if last_role == role {
-                last_text.push_str(text);
-                if matches!(role, "Agent" | "Thought") {
-                    *normalized_markdown = Some(normalize_code_fences(last_text));
-                }
-                return;
-            }
+            *text = message;
+            *normalized_markdown = None;
         }
-        let normalized_markdown = if matches!(role, "Agent" | "Thought") {
-            Some(normalize_code_fences(text))
-        } else {
-            None
-        };
-        self.history.push(ChatEntry::Message {
-            role: role.to_string(),
-            text: text.to_string(),
-            normalized_markdown,
-        });
     }
 
-    fn add_tool_call(&mut self, tool_call: ToolCall) {
-        self.history.push(ChatEntry::ToolCall {
-            id: tool_call.id.to_string(),
-            title: tool_call.title,
-            kind: tool_call.kind,
-            status: tool_call.status,
-        });
+    /// The session was replaced, so its tool calls will never finish; drop
+    /// the output still held for them.
+    pub(crate) fn end_session(&mut self) {
+        self.tool_output.clear();
     }
 
-    fn update_tool_call(&mut self, update: ToolCallUpdate) {
-        let id_str = update.id.to_string();
-        for entry in self.history.iter_mut().rev() {
-            if let ChatEntry::ToolCall {
-                id,
-                title,
-                kind,
-                status,
-            } = entry
-            {
-                if id == &id_str {
-                    if let Some(new_title) = update.fields.title {
-                        *title = new_title;
-                    }
-                    if let Some(new_kind) = update.fields.kind {
-                        *kind = new_kind;
-                    }
-                    if let Some(new_status) = update.fields.status {
-                        *status = new_status;
-                    }
-                    return;
-                }
-            }
-        }
-        if let Some(title) = update.fields.title {
-            self.history.push(ChatEntry::ToolCall {
-                id: id_str,
-                title,
-                kind: update.fields.kind.unwrap_or_default(),
-                status: update.fields.status.unwrap_or_default(),
-            });
+    fn turn_pipeline(&mut self) -> TurnPipeline<'_, &WorkspaceSpill> {
+        TurnPipeline::new(
+            &mut self.history,
+            &self.tool_output,
+            self.reasoning_visibility.shows(),
+        )
+    }
+
+    pub(crate) fn on_permission_request(
+        &mut self,
+        request: RequestPermissionRequest,
+        respond: oneshot::Sender<RequestPermissionOutcome>,
+    ) {
+        self.permissions.request(request, respond, Instant::now());
+        if self.permissions.current().is_some() {
+            self.scroll_to_bottom();
         }
     }
 
-    fn update_plan(&mut self, plan: Plan) {
-        for entry in self.history.iter_mut().rev() {
-            if matches!(entry, ChatEntry::Plan(_)) {
-                *entry = ChatEntry::Plan(plan);
-                return;
-            }
+    /// Answer the shown permission prompt with option `index` (0-based).
+    pub(crate) fn answer_permission(&mut self, index: usize) {
+        let notice = self.permissions.choose(index, Instant::now());
+        self.push_permission_notices(notice);
+    }
+
+    pub(crate) fn reject_permission(&mut self) {
+        let notice = self.permissions.reject(Instant::now());
+        self.push_permission_notices(notice);
+    }
+
+    /// Apply the timeout fallback to prompts nobody answered.
+    pub(crate) fn expire_permissions(&mut self) {
+        let notices = self.permissions.expire(Instant::now());
+        self.push_permission_notices(notices);
+    }
+
+    fn push_permission_notices(&mut self, notices: impl IntoIterator<Item = PermissionNotice>) {
+        for notice in notices {
+            self.push_system_message(notice.text());
         }
-        self.history.push(ChatEntry::Plan(plan));
     }
 
-    pub(crate) async fn send_message(&mut self) {
-        let (conn, session_id, tx) =
-            if let (Some(conn), Some(session_id)) = (&self.client_connection, &self.session_id) {
-                (conn.clone(), session_id.clone(), self.event_tx.clone())
-            } else {
-                return;
-            };
+    fn push_system_message(&mut self, text: String) {
+        self.history.push(ChatEntry::Message {
+            role: "System".to_string(),
+            text,
+            normalized_markdown: None,
+        });
+    }
 
+    /// Queue the composed message; it is sent once earlier turns finish and
+    /// any `/in` or `/at` delay has passed.
+    pub(crate) fn send_message(&mut self) {
         let lines = self.textarea.lines();
         let text = lines.join("\n");
         if text.trim().is_empty() {
             return;
         }
 
-        self.append_message("User", &text);
+        let prompt = match parse_prompt_input(&text, chrono::Utc::now()) {
+            Ok(prompt) => prompt,
+            Err(error) => {
+                self.push_system_message(error);
+                return;
+            }
+        };
 
         self.textarea = TextArea::default();
         self.textarea.set_block(
@@ -673,10 +741,50 @@ impl<'a> App<'a> {
         self.textarea
             .set_placeholder_text("Type a message and press Enter to send. Ctrl+J for new line.");
 
+        if let Some(not_before) = prompt.not_before {
+            self.push_system_message(format!(
+                "Scheduled for {}: {}",
+                not_before
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S"),
+                prompt.text
+            ));
+        } else if self.prompt_queue.is_busy() || self.prompt_queue.len() > 0 {
+            self.push_system_message(format!(
+                "Queued behind {} prompt(s): {}",
+                self.prompt_queue.len() + usize::from(self.prompt_queue.is_busy()),
+                prompt.text
+            ));
+        }
+        self.prompt_queue.enqueue(prompt);
+        self.dispatch_queued_prompt();
+    }
+
+    /// Send the next queued prompt if the agent is idle and it is due.
+    pub(crate) fn dispatch_queued_prompt(&mut self) {
+        let (conn, session_id, tx) =
+            if let (Some(conn), Some(session_id)) = (&self.client_connection, &self.session_id) {
+                (conn.clone(), session_id.clone(), self.event_tx.clone())
+            } else {
+                return;
+            };
+        if self.connection_state != ConnectionState::Connected {
+            return;
+        }
+        let Some(prompt) = self.prompt_queue.next_ready(chrono::Utc::now()) else {
+            return;
+        };
+
+        self.turn_pipeline().append_message("User", &prompt.text);
+        // An exit seen before this turn belongs to some earlier connection
+        self.provider_exit = None;
+        self.lost_turn = None;
+        let turn_timeout = self.turn_timeout;
+
         let request = PromptRequest {
-            session_id,
+            session_id: session_id.clone(),
             prompt: vec![ContentBlock::Text(TextContent {
-                text,
+                text: prompt.text,
                 annotations: None,
                 meta: None,
             })],
@@ -684,12 +792,25 @@ impl<'a> App<'a> {
         };
 
         tokio::task::spawn_local(async move {
-            if let Err(error) = Agent::prompt(&*conn, request).await {
-                crate::acp_client::logging::log_debug(&format!("Prompt failed: {}", error));
-                let _ = tx.send(AppEvent::RequestError {
-                    error: error.to_string(),
-                });
+            let prompt = Agent::prompt(&*conn, request);
+            let end = match turn_timeout {
+                Some(limit) => match tokio::time::timeout(limit, prompt).await {
+                    Ok(result) => TurnEnd::from_result(result),
+                    Err(_) => {
+                        let cancel = CancelNotification {
+                            session_id,
+                            meta: None,
+                        };
+                        let _ = Agent::cancel(&*conn, cancel).await;
+                        TurnEnd::TimedOut(limit)
+                    }
+                },
+                None => TurnEnd::from_result(prompt.await),
+            };
+            if end.is_error() {
+                crate::acp_client::logging::log_debug(&format!("Prompt failed: {:?}", end));
             }
+            let _ = tx.send(AppEvent::PromptFinished(end));
         });
     }
 }
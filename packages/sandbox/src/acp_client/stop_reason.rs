@@ -0,0 +1,404 @@
+//! Why a prompt turn ended.
+//!
+//! ACP agents report a [`StopReason`] when a turn completes normally, but a
+//! turn can also end because the provider API refused (rate limits, outages),
+//! the agent returned an error, its process died or was killed, or the client
+//! gave up waiting. [`TurnEnd`] keeps those apart so the chat can say what
+//! happened instead of showing a bare error string.
+
+use std::time::Duration;
+
+use agent_client_protocol::{PromptResponse, StopReason};
+
+/// How the provider process ended, from the attach socket's close reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProviderExit {
+    Code(i32),
+    Signal(i32),
+}
+
+impl ProviderExit {
+    /// Parse the `exit <code>` / `signal <n>` close reasons sent by attach.
+    pub(crate) fn from_close_reason(reason: &str) -> Option<Self> {
+        let (kind, value) = reason.trim().split_once(' ')?;
+        let value = value.trim().parse().ok()?;
+        match kind {
+            "exit" => Some(ProviderExit::Code(value)),
+            "signal" => Some(ProviderExit::Signal(value)),
+            _ => None,
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            ProviderExit::Code(code) => format!("exited with code {code}"),
+            ProviderExit::Signal(signal) => format!("killed by signal {signal}"),
+        }
+    }
+
+    /// SIGKILL (usually the OOM killer), SIGXCPU and SIGXFSZ, or the shell's
+    /// `128 + signal` encoding of the same.
+    fn is_resource_limit(self) -> bool {
+        const LIMIT_SIGNALS: [i32; 3] = [9, 24, 25];
+        match self {
+            ProviderExit::Signal(signal) => LIMIT_SIGNALS.contains(&signal),
+            ProviderExit::Code(code) => LIMIT_SIGNALS.iter().any(|s| code == 128 + s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TurnEnd {
+    /// The agent answered the prompt with this stop reason
+    Agent(StopReason),
+    /// The provider API rejected the request for exceeding a rate limit
+    RateLimited(String),
+    /// The provider API failed or was overloaded
+    ServerError(String),
+    /// The agent returned any other error
+    ProviderError(String),
+    /// The connection to the agent closed mid-turn
+    ConnectionLost(String),
+    /// The agent's process was killed for exceeding a resource limit
+    ResourceLimit(String),
+    /// No answer within the turn timeout; the turn was cancelled
+    TimedOut(Duration),
+}
+
+impl TurnEnd {
+    pub(crate) fn from_result(
+        result: Result<PromptResponse, agent_client_protocol::Error>,
+    ) -> Self {
+        match result {
+            Ok(response) => TurnEnd::Agent(response.stop_reason),
+            Err(error) => Self::from_error(&error),
+        }
+    }
+
+    pub(crate) fn from_error(error: &agent_client_protocol::Error) -> Self {
+        let detail = match &error.data {
+            Some(serde_json::Value::String(data)) => format!("{}: {}", error.message, data),
+            Some(data) => format!("{}: {}", error.message, data),
+            None => error.message.clone(),
+        };
+        let text = detail.to_ascii_lowercase();
+        let has = |needles: &[&str]| needles.iter().any(|n| text.contains(n));
+        if has(&["shut down unexpectedly", "connection closed", "broken pipe"]) {
+            return TurnEnd::ConnectionLost(detail);
+        }
+        // Structured fields first; the text is only a fallback
+        let status = u16::try_from(error.code)
+            .ok()
+            .filter(is_http_error)
+            .or_else(|| error.data.as_ref().and_then(status_in_data))
+            .or_else(|| status_in_text(&text));
+        let kind = error.data.as_ref().and_then(type_in_data);
+        match (status, kind) {
+            (Some(429), _) | (_, Some("rate_limit_error")) => TurnEnd::RateLimited(detail),
+            (Some(500..=599), _) | (_, Some("overloaded_error" | "api_error")) => {
+                TurnEnd::ServerError(detail)
+            }
+            (Some(_), _) => TurnEnd::ProviderError(detail),
+            _ if has(&["rate limit", "rate_limit", "too many requests"]) => {
+                TurnEnd::RateLimited(detail)
+            }
+            _ if has(&[
+                "overloaded",
+                "server_error",
+                "internal server error",
+                "bad gateway",
+                "service unavailable",
+            ]) =>
+            {
+                TurnEnd::ServerError(detail)
+            }
+            _ => TurnEnd::ProviderError(detail),
+        }
+    }
+
+    pub(crate) fn is_connection_lost(&self) -> bool {
+        matches!(self, TurnEnd::ConnectionLost(_))
+    }
+
+    /// Explain a lost connection with how the provider process ended.
+    pub(crate) fn with_exit(self, exit: Option<ProviderExit>) -> Self {
+        match (self, exit) {
+            (TurnEnd::ConnectionLost(_), Some(exit)) if exit.is_resource_limit() => {
+                TurnEnd::ResourceLimit(exit.describe())
+            }
+            (TurnEnd::ConnectionLost(detail), Some(exit)) => {
+                TurnEnd::ConnectionLost(format!("{detail} (agent {})", exit.describe()))
+            }
+            (end, _) => end,
+        }
+    }
+
+    /// Stable identifier, for logs and exported transcripts.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            TurnEnd::Agent(StopReason::EndTurn) => "end_turn",
+            TurnEnd::Agent(StopReason::MaxTokens) => "max_tokens",
+            TurnEnd::Agent(StopReason::MaxTurnRequests) => "max_turn_requests",
+            TurnEnd::Agent(StopReason::Refusal) => "refusal",
+            TurnEnd::Agent(StopReason::Cancelled) => "cancelled",
+            TurnEnd::RateLimited(_) => "rate_limit",
+            TurnEnd::ServerError(_) => "server_error",
+            TurnEnd::ProviderError(_) => "provider_error",
+            TurnEnd::ConnectionLost(_) => "connection_lost",
+            TurnEnd::ResourceLimit(_) => "resource_limit",
+            TurnEnd::TimedOut(_) => "timeout",
+        }
+    }
+
+    pub(crate) fn is_error(&self) -> bool {
+        !matches!(self, TurnEnd::Agent(_))
+    }
+
+    /// What to tell the user, or `None` for an ordinary end of turn.
+    pub(crate) fn message(&self) -> Option<String> {
+        let text = match self {
+            TurnEnd::Agent(StopReason::EndTurn) => return None,
+            TurnEnd::Agent(StopReason::MaxTokens) => {
+                "Turn stopped: the model hit its output token limit".to_string()
+            }
+            TurnEnd::Agent(StopReason::MaxTurnRequests) => {
+                "Turn stopped: the agent hit its per-turn request limit".to_string()
+            }
+            TurnEnd::Agent(StopReason::Refusal) => "The agent refused to continue".to_string(),
+            TurnEnd::Agent(StopReason::Cancelled) => "Turn cancelled".to_string(),
+            TurnEnd::RateLimited(detail) => format!("Rate limited by the provider: {detail}"),
+            TurnEnd::ServerError(detail) => format!("Provider server error: {detail}"),
+            TurnEnd::ProviderError(detail) => detail.clone(),
+            TurnEnd::ConnectionLost(detail) => format!("Lost connection to the agent: {detail}"),
+            TurnEnd::ResourceLimit(detail) => {
+                format!("The agent was stopped for exceeding a resource limit ({detail})")
+            }
+            TurnEnd::TimedOut(after) => {
+                format!(
+                    "Turn timed out after {}s and was cancelled",
+                    after.as_secs()
+                )
+            }
+        };
+        Some(text)
+    }
+}
+
+fn is_http_error(status: &u16) -> bool {
+    (400..600).contains(status)
+}
+
+/// An HTTP status in an error's `data`, e.g. `{"status": 429}` or
+/// `{"error": {"statusCode": 503}}`.
+fn status_in_data(data: &serde_json::Value) -> Option<u16> {
+    const KEYS: [&str; 4] = ["status", "statusCode", "status_code", "httpStatus"];
+    let status = KEYS
+        .iter()
+        .filter_map(|key| data.get(key)?.as_u64())
+        .find_map(|status| u16::try_from(status).ok().filter(is_http_error));
+    status.or_else(|| status_in_data(data.get("error")?))
+}
+
+/// The provider API's error type in an error's `data`, e.g.
+/// `{"error": {"type": "overloaded_error"}}`.
+fn type_in_data(data: &serde_json::Value) -> Option<&str> {
+    data.get("type")
+        .and_then(serde_json::Value::as_str)
+        .or_else(|| type_in_data(data.get("error")?))
+}
+
+/// An HTTP status written out in lowercased error text: a message that starts
+/// with one (`429 too many requests`) or one after `status`, `code` or `http`
+/// (`status 503`, `status code: 529`, `http/1.1 502`). Numbers anywhere else
+/// are ignored, so `wrote 500 lines` is not a server error.
+fn status_in_text(text: &str) -> Option<u16> {
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let status = |word: &str| {
+        (word.len() == 3)
+            .then(|| word.parse().ok())
+            .flatten()
+            .filter(is_http_error)
+    };
+    if let Some(found) = words.first().and_then(|w| status(w)) {
+        return Some(found);
+    }
+    words.iter().enumerate().find_map(|(i, word)| {
+        if !matches!(*word, "status" | "code" | "http") {
+            return None;
+        }
+        // Step over `code` in `status code` and the version in `http/1.1`
+        words[i + 1..]
+            .iter()
+            .find(|w| **w != "code" && !(w.len() == 1 && w.parse::<u8>().is_ok()))
+            .and_then(|w| status(w))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(message: &str, data: Option<&str>) -> agent_client_protocol::Error {
+        let error = agent_client_protocol::Error::internal_error();
+        let error = agent_client_protocol::Error {
+            message: message.to_string(),
+            ..error
+        };
+        match data {
+            Some(data) => error.with_data(data.to_string()),
+            None => error,
+        }
+    }
+
+    #[test]
+    fn classifies_prompt_errors() {
+        let cases = [
+            (
+                error("Internal error", Some("server shut down unexpectedly")),
+                "connection_lost",
+            ),
+            (error("429 Too Many Requests", None), "rate_limit"),
+            (
+                error("Internal error", Some("rate_limit_error: slow down")),
+                "rate_limit",
+            ),
+            (error("Overloaded", None), "server_error"),
+            (error("upstream returned status 503", None), "server_error"),
+            (error("API Error: HTTP/1.1 529", None), "server_error"),
+            (
+                error("Request failed, status code: 404", None),
+                "provider_error",
+            ),
+            (
+                error("Invalid params", Some("unknown model")),
+                "provider_error",
+            ),
+            // Bare numbers aren't statuses
+            (
+                error("Tool wrote 500 lines to out-429.log", None),
+                "provider_error",
+            ),
+            (
+                error("Internal error", Some("port 5030 in use")),
+                "provider_error",
+            ),
+        ];
+        for (err, code) in cases {
+            let end = TurnEnd::from_error(&err);
+            assert_eq!(end.code(), code, "{err:?}");
+            assert!(end.is_error());
+            assert!(end.message().unwrap().contains(&err.message));
+        }
+    }
+
+    #[test]
+    fn structured_errors_win_over_text() {
+        use serde_json::json;
+        let with_code = |code| agent_client_protocol::Error {
+            code,
+            ..error("mentions rate limit", None)
+        };
+        assert_eq!(TurnEnd::from_error(&with_code(503)).code(), "server_error");
+        // JSON-RPC codes aren't statuses
+        assert_eq!(TurnEnd::from_error(&with_code(-32603)).code(), "rate_limit");
+
+        let with_data = |data: serde_json::Value| {
+            TurnEnd::from_error(&error("Internal error: 500 lines", None).with_data(data))
+        };
+        assert_eq!(with_data(json!({ "status": 429 })).code(), "rate_limit");
+        assert_eq!(
+            with_data(json!({ "error": { "statusCode": 400 } })).code(),
+            "provider_error"
+        );
+        assert_eq!(
+            with_data(json!({ "error": { "type": "overloaded_error" } })).code(),
+            "server_error"
+        );
+        assert_eq!(
+            with_data(json!({ "type": "rate_limit_error" })).code(),
+            "rate_limit"
+        );
+    }
+
+    #[test]
+    fn agent_stop_reasons_map_to_codes() {
+        let end = TurnEnd::from_result(Ok(PromptResponse {
+            stop_reason: StopReason::MaxTokens,
+            meta: None,
+        }));
+        assert_eq!(end.code(), "max_tokens");
+        assert!(!end.is_error());
+        assert!(end.message().is_some());
+        assert_eq!(TurnEnd::Agent(StopReason::EndTurn).message(), None);
+        assert_eq!(TurnEnd::TimedOut(Duration::from_secs(90)).code(), "timeout");
+    }
+
+    #[test]
+    fn provider_exit_explains_lost_connections() {
+        assert_eq!(
+            ProviderExit::from_close_reason("signal 9"),
+            Some(ProviderExit::Signal(9))
+        );
+        assert_eq!(
+            ProviderExit::from_close_reason("exit 137"),
+            Some(ProviderExit::Code(137))
+        );
+        assert_eq!(ProviderExit::from_close_reason("bye"), None);
+
+        let lost = || TurnEnd::ConnectionLost("server shut down unexpectedly".into());
+        assert_eq!(
+            lost().with_exit(Some(ProviderExit::Signal(9))).code(),
+            "resource_limit"
+        );
+        assert_eq!(
+            lost().with_exit(Some(ProviderExit::Code(137))).code(),
+            "resource_limit"
+        );
+        assert_eq!(
+            lost().with_exit(Some(ProviderExit::Code(1))),
+            TurnEnd::ConnectionLost(
+                "server shut down unexpectedly (agent exited with code 1)".into()
+            )
+        );
+        assert_eq!(lost().with_exit(None), lost());
+        // Only lost connections are reinterpreted
+        let refused = TurnEnd::Agent(StopReason::Refusal);
+        assert_eq!(
+            refused.clone().with_exit(Some(ProviderExit::Signal(9))),
+            refused
+        );
+    }
+
+    #[test]
+    fn provider_exit_after_the_turn_still_explains_it() {
+        use crate::acp_client::provider::AcpProvider;
+        use crate::acp_client::state::{App, ChatEntry};
+
+        let last_text = |app: &App| match app.history.last() {
+            Some(ChatEntry::Message { text, .. }) => text.clone(),
+            _ => panic!("expected a message"),
+        };
+        let lost = || TurnEnd::ConnectionLost("server shut down unexpectedly".into());
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut app = App::new(AcpProvider::Codex, tx, String::new(), "sb".into());
+
+        // Exit reported before the turn ends
+        app.on_provider_exited(ProviderExit::Signal(9));
+        app.finish_turn(lost());
+        assert!(last_text(&app).contains("resource limit"));
+
+        // Exit reported after the turn ended rewrites its message
+        app.finish_turn(lost());
+        assert!(last_text(&app).starts_with("Lost connection"));
+        app.on_provider_exited(ProviderExit::Code(137));
+        assert!(last_text(&app).contains("resource limit"));
+        assert_eq!(app.provider_exit, None);
+
+        // Only once; a later exit waits for the next turn
+        app.on_provider_exited(ProviderExit::Code(1));
+        assert_eq!(app.provider_exit, Some(ProviderExit::Code(1)));
+    }
+}
@@ -0,0 +1,200 @@
+//! Size limits for tool call output kept in the chat history.
+//!
+//! Commands like `npm install` can report megabytes of log. Past
+//! [`MAX_TOOL_OUTPUT_BYTES`] the entry keeps a short summary with the tail of
+//! the output, and once the call finishes its full text is written to
+//! [`SPILL_DIR`] in the sandbox workspace, next to the files it concerns.
+//! Agents resend a call's whole output on every update, so only the latest
+//! copy is held until then and each call is written once. Output of calls
+//! that never finish is dropped when the turn or session ends.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::acp_client::logging::log_debug;
+
+pub(crate) const MAX_TOOL_OUTPUT_BYTES: usize = 64 * 1024;
+const TAIL_EXCERPT_BYTES: usize = 4 * 1024;
+
+/// Workspace directory spilled output is written to.
+pub(crate) const SPILL_DIR: &str = ".cmux/tool-output";
+
+/// Workspace-relative path of a tool call's spilled output. The name is the
+/// id's bytes in hex, so distinct ids never share a file.
+pub(crate) fn spill_path(tool_id: &str) -> String {
+    let name: String = tool_id.bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("{}/{}.log", SPILL_DIR, name)
+}
+
+/// The last `max_bytes` of `text`, cut on a char boundary.
+fn tail(text: &str, max_bytes: usize) -> &str {
+    let mut start = text.len().saturating_sub(max_bytes);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+/// Summary kept in place of `output`, pointing at where it is spilled.
+fn summarize(output: &str, tool_id: &str) -> String {
+    format!(
+        "[output truncated: {} bytes, full output in {} once the call finishes]\n...\n{}",
+        output.len(),
+        spill_path(tool_id),
+        tail(output, TAIL_EXCERPT_BYTES)
+    )
+}
+
+/// Tar archive holding `output` at the call's spill path, in the form
+/// `POST /sandboxes/{id}/files` unpacks into the workspace. It also holds a
+/// `.gitignore` that keeps the spill directory out of the user's commits.
+fn spill_archive(tool_id: &str, output: &str) -> std::io::Result<Vec<u8>> {
+    let mut archive = tar::Builder::new(Vec::new());
+    let files = [
+        (format!("{}/.gitignore", SPILL_DIR), "*\n"),
+        (spill_path(tool_id), output),
+    ];
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        archive.append_data(&mut header, path, contents.as_bytes())?;
+    }
+    archive.into_inner()
+}
+
+/// Oversized output waiting for its call to finish, and where to write it.
+pub(crate) struct WorkspaceSpill {
+    base_url: String,
+    sandbox_id: String,
+    pending: RefCell<HashMap<String, String>>,
+}
+
+impl WorkspaceSpill {
+    pub(crate) fn new(base_url: &str, sandbox_id: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            sandbox_id: sandbox_id.to_string(),
+            pending: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Return `output` unchanged if it fits, otherwise hold it for
+    /// [`finish`](Self::finish) and return a summary.
+    pub(crate) fn cap(&self, tool_id: &str, output: String) -> String {
+        if output.len() <= MAX_TOOL_OUTPUT_BYTES {
+            self.pending.borrow_mut().remove(tool_id);
+            return output;
+        }
+        let summary = summarize(&output, tool_id);
+        self.pending
+            .borrow_mut()
+            .insert(tool_id.to_string(), output);
+        summary
+    }
+
+    /// Drop the output held for calls that never finished.
+    pub(crate) fn clear(&self) {
+        let mut pending = self.pending.borrow_mut();
+        if !pending.is_empty() {
+            log_debug(&format!(
+                "Dropping output of {} unfinished tool calls",
+                pending.len()
+            ));
+            pending.clear();
+        }
+    }
+
+    /// Write the held output of a finished call to the workspace.
+    pub(crate) fn finish(&self, tool_id: &str) {
+        let Some(output) = self.pending.borrow_mut().remove(tool_id) else {
+            return;
+        };
+        let archive = match spill_archive(tool_id, &output) {
+            Ok(archive) => archive,
+            Err(e) => {
+                log_debug(&format!("Failed to pack output of {}: {}", tool_id, e));
+                return;
+            }
+        };
+        let url = format!("{}/sandboxes/{}/files", self.base_url, self.sandbox_id);
+        let path = spill_path(tool_id);
+        tokio::spawn(async move {
+            let result = reqwest::Client::new().post(&url).body(archive).send().await;
+            match result.and_then(|response| response.error_for_status()) {
+                Ok(_) => log_debug(&format!("Saved tool output to {}", path)),
+                Err(e) => log_debug(&format!("Failed to save tool output to {}: {}", path, e)),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_output_is_kept_inline() {
+        let spill = WorkspaceSpill::new("http://sandboxd", "sb");
+        assert_eq!(spill.cap("t1", "ok".to_string()), "ok");
+        assert!(spill.pending.borrow().is_empty());
+    }
+
+    #[test]
+    fn large_output_is_summarized_and_held_once_per_call() {
+        let spill = WorkspaceSpill::new("http://sandboxd", "sb");
+        let full = format!(
+            "{}npm ERR! missing peer dep",
+            "é".repeat(MAX_TOOL_OUTPUT_BYTES)
+        );
+        let out = spill.cap("call/1", full[..MAX_TOOL_OUTPUT_BYTES + 2].to_string());
+        let out_again = spill.cap("call/1", full.clone());
+
+        assert!(out.contains(".cmux/tool-output/63616c6c2f31.log"));
+        assert!(out_again.ends_with("npm ERR! missing peer dep"));
+        assert!(out_again.len() < TAIL_EXCERPT_BYTES + 512);
+        // Only the latest copy is held
+        assert_eq!(spill.pending.borrow().len(), 1);
+        assert_eq!(spill.pending.borrow()["call/1"], full);
+
+        // Calls that never finish don't hold their output forever
+        spill.clear();
+        assert!(spill.pending.borrow().is_empty());
+    }
+
+    #[test]
+    fn spill_paths_are_distinct_per_call() {
+        assert_ne!(spill_path("call/1"), spill_path("call_1"));
+        assert_eq!(spill_path("../x"), ".cmux/tool-output/2e2e2f78.log");
+    }
+
+    #[test]
+    fn spill_archive_unpacks_to_the_spill_path() {
+        let archive = spill_archive("call/1", "full output").unwrap();
+        let mut archive = tar::Archive::new(archive.as_slice());
+        let files: Vec<(String, String)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_str().unwrap().to_string();
+                let mut contents = String::new();
+                std::io::Read::read_to_string(&mut entry, &mut contents).unwrap();
+                (path, contents)
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (
+                    ".cmux/tool-output/.gitignore".to_string(),
+                    "*\n".to_string()
+                ),
+                (
+                    ".cmux/tool-output/63616c6c2f31.log".to_string(),
+                    "full output".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! Export of a chat session as Markdown or structured JSON.
+
+use std::path::PathBuf;
+
+use agent_client_protocol::{ContentBlock, ToolCallContent};
+use serde_json::{json, Value};
+
+use crate::acp_client::config::get_config_dir;
+use crate::acp_client::state::ChatEntry;
+
+/// Tool output beyond this many characters is cut down to its tail.
+const MAX_TOOL_OUTPUT_CHARS: usize = 2000;
+
+/// Collect the text a tool call produced, falling back to its raw output.
+pub(crate) fn tool_output_text(content: &[ToolCallContent], raw_output: Option<&Value>) -> String {
+    let mut parts = Vec::new();
+    for item in content {
+        match item {
+            ToolCallContent::Content {
+                content: ContentBlock::Text(text),
+            } => parts.push(text.text.clone()),
+            ToolCallContent::Diff { diff } => {
+                parts.push(format!("(diff of {})", diff.path.display()))
+            }
+            _ => {}
+        }
+    }
+    if parts.is_empty() {
+        return match raw_output {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(other) => other.to_string(),
+        };
+    }
+    parts.join("\n")
+}
+
+/// Keep the tail of long output, which is where errors and summaries end up.
+fn truncate_output(output: &str) -> (String, bool) {
+    let total = output.chars().count();
+    if total <= MAX_TOOL_OUTPUT_CHARS {
+        return (output.to_string(), false);
+    }
+    let tail: String = output.chars().skip(total - MAX_TOOL_OUTPUT_CHARS).collect();
+    (tail, true)
+}
+
+fn enum_label<T: serde::Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in text.chars() {
+        if c == '`' {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    longest
+}
+
+pub(crate) fn render_markdown(
+    history: &[ChatEntry],
+    provider: &str,
+    include_reasoning: bool,
+) -> String {
+    let mut out = format!("# Agent session ({})\n", provider);
+    for entry in history {
+        match entry {
+            ChatEntry::Message { role, text, .. } => {
+                if role == "Thought" && !include_reasoning {
+                    continue;
+                }
+                out.push_str(&format!("\n## {}\n\n{}\n", role, text.trim_end()));
+            }
+            ChatEntry::ToolCall {
+                title,
+                kind,
+                status,
+                output,
+                ..
+            } => {
+                out.push_str(&format!(
+                    "\n### Tool: {} ({}, {})\n",
+                    title,
+                    enum_label(kind),
+                    enum_label(status)
+                ));
+                if !output.is_empty() {
+                    let (shown, truncated) = truncate_output(output);
+                    if truncated {
+                        out.push_str(&format!(
+                            "\n_Output truncated to the last {} characters._\n",
+                            MAX_TOOL_OUTPUT_CHARS
+                        ));
+                    }
+                    // Fence must be longer than any backtick run inside the output
+                    let fence = "`".repeat(longest_backtick_run(&shown).max(2) + 1);
+                    out.push_str(&format!("\n{fence}\n{}\n{fence}\n", shown.trim_end()));
+                }
+            }
+            ChatEntry::Plan(plan) => {
+                out.push_str("\n### Plan\n\n");
+                for item in &plan.entries {
+                    let mark = if enum_label(&item.status) == "completed" {
+                        "x"
+                    } else {
+                        " "
+                    };
+                    out.push_str(&format!("- [{}] {}\n", mark, item.content));
+                }
+            }
+        }
+    }
+    out
+}
+
+pub(crate) fn render_json(history: &[ChatEntry], provider: &str, include_reasoning: bool) -> Value {
+    let entries: Vec<Value> = history
+        .iter()
+        .filter_map(|entry| match entry {
+            ChatEntry::Message { role, text, .. } => {
+                if role == "Thought" && !include_reasoning {
+                    return None;
+                }
+                Some(json!({ "type": "message", "role": role, "text": text }))
+            }
+            ChatEntry::ToolCall {
+                id,
+                title,
+                kind,
+                status,
+                output,
+            } => {
+                let (shown, truncated) = truncate_output(output);
+                Some(json!({
+                    "type": "tool_call",
+                    "id": id,
+                    "title": title,
+                    "kind": kind,
+                    "status": status,
+                    "output": shown,
+                    "output_truncated": truncated,
+                }))
+            }
+            ChatEntry::Plan(plan) => Some(json!({ "type": "plan", "entries": plan.entries })),
+        })
+        .collect();
+    json!({ "provider": provider, "entries": entries })
+}
+
+/// Write the session to `~/.cmux/transcripts` as both `.md` and `.json`,
+/// returning the Markdown path.
+pub(crate) fn export_transcript(
+    history: &[ChatEntry],
+    provider: &str,
+    sandbox_id: &str,
+    include_reasoning: bool,
+) -> std::io::Result<PathBuf> {
+    let dir = get_config_dir().join("transcripts");
+    std::fs::create_dir_all(&dir)?;
+    let stem = format!(
+        "{}-{}",
+        sandbox_id,
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+    let md_path = dir.join(format!("{stem}.md"));
+    std::fs::write(
+        &md_path,
+        render_markdown(history, provider, include_reasoning),
+    )?;
+    let json = render_json(history, provider, include_reasoning);
+    std::fs::write(
+        dir.join(format!("{stem}.json")),
+        serde_json::to_vec_pretty(&json)?,
+    )?;
+    Ok(md_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agent_client_protocol::{ToolCallStatus, ToolKind};
+
+    fn history() -> Vec<ChatEntry> {
+        vec![
+            ChatEntry::Message {
+                role: "User".into(),
+                text: "install deps".into(),
+                normalized_markdown: None,
+            },
+            ChatEntry::Message {
+                role: "Thought".into(),
+                text: "run npm".into(),
+                normalized_markdown: None,
+            },
+            ChatEntry::ToolCall {
+                id: "t1".into(),
+                title: "npm install".into(),
+                kind: ToolKind::Execute,
+                status: ToolCallStatus::Completed,
+                output: format!("{}done", "x".repeat(MAX_TOOL_OUTPUT_CHARS)),
+            },
+        ]
+    }
+
+    #[test]
+    fn markdown_skips_reasoning_and_truncates_output() {
+        let md = render_markdown(&history(), "codex", false);
+        assert!(md.contains("## User\n\ninstall deps"));
+        assert!(!md.contains("run npm"));
+        assert!(md.contains("### Tool: npm install (execute, completed)"));
+        assert!(md.contains("_Output truncated"));
+        assert!(md.contains("done\n```"));
+
+        assert!(render_markdown(&history(), "codex", true).contains("## Thought"));
+    }
+
+    #[test]
+    fn json_reports_truncation() {
+        let value = render_json(&history(), "codex", true);
+        let entries = value["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2]["type"], "tool_call");
+        assert_eq!(entries[2]["output_truncated"], true);
+        assert_eq!(
+            entries[2]["output"].as_str().unwrap().chars().count(),
+            MAX_TOOL_OUTPUT_CHARS
+        );
+    }
+}
@@ -0,0 +1,511 @@
+//! Folds an agent's session updates into chat history.
+//!
+//! Within a turn the agent streams message text in arbitrary chunks,
+//! announces tool calls and then updates them by id, and replaces its plan
+//! wholesale. [`TurnPipeline`] merges those updates into [`ChatEntry`] values
+//! with no UI or connection state, so ordering edge cases can be tested
+//! directly: updates for calls it has not seen yet, interleaved calls, and a
+//! turn that ends while calls are still running.
+
+use agent_client_protocol::{
+    ContentBlock, Plan, SessionUpdate, ToolCall, ToolCallStatus, ToolCallUpdate,
+};
+
+use crate::acp_client::markdown::normalize_code_fences;
+use crate::acp_client::state::ChatEntry;
+use crate::acp_client::tool_output::WorkspaceSpill;
+use crate::acp_client::transcript::tool_output_text;
+
+/// Decides how much tool output stays in the history.
+pub(crate) trait ToolOutputSink {
+    /// The text to keep for `tool_id`; the full output may be stored elsewhere.
+    fn keep(&self, tool_id: &str, output: String) -> String;
+
+    /// `tool_id` finished and will not report more output. Called when the
+    /// call completes or fails and again when its turn ends.
+    fn finish(&self, _tool_id: &str) {}
+
+    /// The turn ended after its calls were finished; calls of earlier turns
+    /// that are still open will not report again either.
+    fn end_turn(&self) {}
+}
+
+impl ToolOutputSink for &WorkspaceSpill {
+    fn keep(&self, tool_id: &str, output: String) -> String {
+        self.cap(tool_id, output)
+    }
+
+    fn finish(&self, tool_id: &str) {
+        WorkspaceSpill::finish(self, tool_id)
+    }
+
+    fn end_turn(&self) {
+        self.clear()
+    }
+}
+
+fn is_finished(status: ToolCallStatus) -> bool {
+    matches!(status, ToolCallStatus::Completed | ToolCallStatus::Failed)
+}
+
+pub(crate) struct TurnPipeline<'a, S> {
+    history: &'a mut Vec<ChatEntry>,
+    tool_output: S,
+    show_reasoning: bool,
+}
+
+impl<'a, S: ToolOutputSink> TurnPipeline<'a, S> {
+    pub(crate) fn new(
+        history: &'a mut Vec<ChatEntry>,
+        tool_output: S,
+        show_reasoning: bool,
+    ) -> Self {
+        Self {
+            history,
+            tool_output,
+            show_reasoning,
+        }
+    }
+
+    pub(crate) fn apply(&mut self, update: SessionUpdate) {
+        match update {
+            SessionUpdate::UserMessageChunk(chunk) => {
+                if let ContentBlock::Text(text_content) = chunk.content {
+                    self.append_message("User", &text_content.text);
+                }
+            }
+            SessionUpdate::AgentMessageChunk(chunk) => {
+                if let ContentBlock::Text(text_content) = chunk.content {
+                    self.append_message("Agent", &text_content.text);
+                }
+            }
+            SessionUpdate::AgentThoughtChunk(chunk) => {
+                if !self.show_reasoning {
+                    return;
+                }
+                if let ContentBlock::Text(text_content) = chunk.content {
+                    self.append_message("Thought", &text_content.text);
+                }
+            }
+            SessionUpdate::ToolCall(tool_call) => self.add_tool_call(tool_call),
+            SessionUpdate::ToolCallUpdate(update) => self.update_tool_call(update),
+            SessionUpdate::Plan(plan) => self.update_plan(plan),
+            SessionUpdate::AvailableCommandsUpdate(_) | SessionUpdate::CurrentModeUpdate(_) => {}
+        }
+    }
+
+    /// Extend the last message if it has the same role, otherwise start one.
+    pub(crate) fn append_message(&mut self, role: &str, text: &str) {
+        if role == "Thought" && text.trim().is_empty() {
+            return;
+        }
+        if let Some(ChatEntry::Message {
+            role: last_role,
+            text: last_text,
+            normalized_markdown,
+        }) = self.history.last_mut()
+        {
+            if last_role == role {
+                last_text.push_str(text);
+                if matches!(role, "Agent" | "Thought") {
+                    *normalized_markdown = Some(normalize_code_fences(last_text));
+                }
+                return;
+            }
+        }
+        let normalized_markdown = if matches!(role, "Agent" | "Thought") {
+            Some(normalize_code_fences(text))
+        } else {
+            None
+        };
+        self.history.push(ChatEntry::Message {
+            role: role.to_string(),
+            text: text.to_string(),
+            normalized_markdown,
+        });
+    }
+
+    /// The prompt returned, so the agent will not report on this turn's tool
+    /// calls again; any still pending or running are marked failed, and all
+    /// of them are finished with the sink before the turn ends there.
+    pub(crate) fn finish_turn(&mut self) {
+        for entry in self.history.iter_mut().rev() {
+            match entry {
+                ChatEntry::Message { role, .. } if role == "User" => break,
+                ChatEntry::ToolCall { id, status, .. } => {
+                    if !is_finished(*status) {
+                        *status = ToolCallStatus::Failed;
+                    }
+                    self.tool_output.finish(id);
+                }
+                _ => {}
+            }
+        }
+        self.tool_output.end_turn();
+    }
+
+    fn find_tool_call(&mut self, tool_id: &str) -> Option<&mut ChatEntry> {
+        self.history
+            .iter_mut()
+            .rev()
+            .find(|entry| matches!(entry, ChatEntry::ToolCall { id, .. } if id == tool_id))
+    }
+
+    fn add_tool_call(&mut self, tool_call: ToolCall) {
+        let tool_id = tool_call.id.to_string();
+        let text = tool_output_text(&tool_call.content, tool_call.raw_output.as_ref());
+        let has_output = !text.is_empty();
+        let new_output = self.tool_output.keep(&tool_id, text);
+        if is_finished(tool_call.status) {
+            self.tool_output.finish(&tool_id);
+        }
+        // An update that raced ahead of the announcement already made an entry
+        if let Some(ChatEntry::ToolCall {
+            title,
+            kind,
+            status,
+            output,
+            ..
+        }) = self.find_tool_call(&tool_id)
+        {
+            *title = tool_call.title;
+            *kind = tool_call.kind;
+            *status = tool_call.status;
+            if has_output {
+                *output = new_output;
+            }
+            return;
+        }
+        self.history.push(ChatEntry::ToolCall {
+            id: tool_id,
+            output: new_output,
+            title: tool_call.title,
+            kind: tool_call.kind,
+            status: tool_call.status,
+        });
+    }
+
+    fn update_tool_call(&mut self, update: ToolCallUpdate) {
+        let tool_id = update.id.to_string();
+        let fields = update.fields;
+        let new_output = (fields.content.is_some() || fields.raw_output.is_some()).then(|| {
+            let text = tool_output_text(
+                fields.content.as_deref().unwrap_or_default(),
+                fields.raw_output.as_ref(),
+            );
+            self.tool_output.keep(&tool_id, text)
+        });
+        if fields.status.is_some_and(is_finished) {
+            self.tool_output.finish(&tool_id);
+        }
+        if let Some(ChatEntry::ToolCall {
+            title,
+            kind,
+            status,
+            output,
+            ..
+        }) = self.find_tool_call(&tool_id)
+        {
+            if let Some(new_output) = new_output {
+                *output = new_output;
+            }
+            if let Some(new_title) = fields.title {
+                *title = new_title;
+            }
+            if let Some(new_kind) = fields.kind {
+                *kind = new_kind;
+            }
+            if let Some(new_status) = fields.status {
+                *status = new_status;
+            }
+            return;
+        }
+        // Keep what the update carried; the announcement fills in the rest.
+        self.history.push(ChatEntry::ToolCall {
+            output: new_output.unwrap_or_default(),
+            title: fields.title.unwrap_or_else(|| tool_id.clone()),
+            id: tool_id,
+            kind: fields.kind.unwrap_or_default(),
+            status: fields.status.unwrap_or_default(),
+        });
+    }
+
+    fn update_plan(&mut self, plan: Plan) {
+        for entry in self.history.iter_mut().rev() {
+            if matches!(entry, ChatEntry::Plan(_)) {
+                *entry = ChatEntry::Plan(plan);
+                return;
+            }
+        }
+        self.history.push(ChatEntry::Plan(plan));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    use serde_json::json;
+
+    /// Keeps at most `limit` bytes and records what it was handed.
+    #[derive(Default)]
+    struct Recording {
+        limit: Option<usize>,
+        seen: RefCell<Vec<(String, usize)>>,
+        finished: RefCell<Vec<String>>,
+        turns_ended: std::cell::Cell<usize>,
+    }
+
+    impl ToolOutputSink for &Recording {
+        fn keep(&self, tool_id: &str, mut output: String) -> String {
+            self.seen
+                .borrow_mut()
+                .push((tool_id.to_string(), output.len()));
+            if let Some(limit) = self.limit {
+                output.truncate(limit);
+            }
+            output
+        }
+
+        fn finish(&self, tool_id: &str) {
+            self.finished.borrow_mut().push(tool_id.to_string());
+        }
+
+        fn end_turn(&self) {
+            self.turns_ended.set(self.turns_ended.get() + 1);
+        }
+    }
+
+    fn update(value: serde_json::Value) -> SessionUpdate {
+        serde_json::from_value(value).expect("session update")
+    }
+
+    fn agent(text: &str) -> SessionUpdate {
+        update(json!({
+            "sessionUpdate": "agent_message_chunk",
+            "content": { "type": "text", "text": text },
+        }))
+    }
+
+    fn tool_call(id: &str, title: &str, status: &str) -> SessionUpdate {
+        update(json!({
+            "sessionUpdate": "tool_call",
+            "toolCallId": id,
+            "title": title,
+            "kind": "execute",
+            "status": status,
+        }))
+    }
+
+    fn tool_update(id: &str, fields: serde_json::Value) -> SessionUpdate {
+        let mut value = json!({ "sessionUpdate": "tool_call_update", "toolCallId": id });
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        update(value)
+    }
+
+    fn run(history: &mut Vec<ChatEntry>, sink: &Recording, updates: Vec<SessionUpdate>) {
+        let mut pipeline = TurnPipeline::new(history, sink, true);
+        for update in updates {
+            pipeline.apply(update);
+        }
+    }
+
+    fn summary(history: &[ChatEntry]) -> Vec<String> {
+        history
+            .iter()
+            .map(|entry| match entry {
+                ChatEntry::Message { role, text, .. } => format!("{role}: {text}"),
+                ChatEntry::ToolCall {
+                    id,
+                    title,
+                    status,
+                    output,
+                    ..
+                } => format!("tool {id} [{status:?}] {title} => {output}"),
+                ChatEntry::Plan(plan) => format!("plan ({} entries)", plan.entries.len()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_chunks_and_splits_around_tool_calls() {
+        let sink = Recording::default();
+        let mut history = Vec::new();
+        run(
+            &mut history,
+            &sink,
+            vec![
+                agent("Let me "),
+                agent("check."),
+                tool_call("a", "ls", "in_progress"),
+                agent("Done."),
+            ],
+        );
+        assert_eq!(
+            summary(&history),
+            [
+                "Agent: Let me check.",
+                "tool a [InProgress] ls => ",
+                "Agent: Done.",
+            ]
+        );
+    }
+
+    #[test]
+    fn interleaved_tool_calls_update_their_own_entries() {
+        let sink = Recording::default();
+        let mut history = Vec::new();
+        run(
+            &mut history,
+            &sink,
+            vec![
+                tool_call("a", "build", "pending"),
+                tool_call("b", "test", "pending"),
+                tool_update("b", json!({ "status": "in_progress" })),
+                tool_update("a", json!({ "status": "completed", "rawOutput": "built" })),
+                tool_update(
+                    "b",
+                    json!({ "status": "failed", "title": "test (1 failed)" }),
+                ),
+            ],
+        );
+        assert_eq!(
+            summary(&history),
+            [
+                "tool a [Completed] build => built",
+                "tool b [Failed] test (1 failed) => ",
+            ]
+        );
+    }
+
+    #[test]
+    fn update_before_announcement_is_kept_and_merged() {
+        let sink = Recording::default();
+        let mut history = Vec::new();
+        run(
+            &mut history,
+            &sink,
+            vec![
+                tool_update(
+                    "a",
+                    json!({ "status": "in_progress", "rawOutput": "partial" }),
+                ),
+                tool_call("a", "npm install", "in_progress"),
+                tool_update("a", json!({ "status": "completed" })),
+            ],
+        );
+        assert_eq!(
+            summary(&history),
+            ["tool a [Completed] npm install => partial"]
+        );
+    }
+
+    #[test]
+    fn finishing_a_turn_fails_calls_left_running() {
+        let sink = Recording::default();
+        let mut history = Vec::new();
+        {
+            let mut pipeline = TurnPipeline::new(&mut history, &sink, true);
+            pipeline.append_message("User", "first");
+            pipeline.apply(tool_call("old", "earlier turn", "in_progress"));
+            pipeline.append_message("User", "second");
+            pipeline.apply(tool_call("done", "cat", "completed"));
+            pipeline.apply(tool_call("stuck", "sleep", "in_progress"));
+            pipeline.apply(tool_call("queued", "echo", "pending"));
+            pipeline.apply(agent("Interrupted mid-"));
+            pipeline.finish_turn();
+        }
+        assert_eq!(
+            summary(&history),
+            [
+                "User: first",
+                "tool old [InProgress] earlier turn => ",
+                "User: second",
+                "tool done [Completed] cat => ",
+                "tool stuck [Failed] sleep => ",
+                "tool queued [Failed] echo => ",
+                "Agent: Interrupted mid-",
+            ]
+        );
+        assert_eq!(*sink.finished.borrow(), ["done", "queued", "stuck", "done"]);
+        // The earlier turn's call is left to the sink's end of turn
+        assert_eq!(sink.turns_ended.get(), 1);
+    }
+
+    #[test]
+    fn tool_output_goes_through_the_sink() {
+        let sink = Recording {
+            limit: Some(4),
+            ..Default::default()
+        };
+        let mut history = Vec::new();
+        run(
+            &mut history,
+            &sink,
+            vec![
+                tool_call("a", "cat", "in_progress"),
+                tool_update("a", json!({ "rawOutput": "0123456789" })),
+                // Status-only updates leave the output alone
+                tool_update("a", json!({ "status": "completed" })),
+            ],
+        );
+        assert_eq!(summary(&history), ["tool a [Completed] cat => 0123"]);
+        assert_eq!(
+            *sink.seen.borrow(),
+            [("a".to_string(), 0), ("a".to_string(), 10)]
+        );
+        assert_eq!(*sink.finished.borrow(), ["a"]);
+    }
+
+    #[test]
+    fn hidden_reasoning_and_blank_thoughts_are_dropped() {
+        let thought = |text: &str| {
+            update(json!({
+                "sessionUpdate": "agent_thought_chunk",
+                "content": { "type": "text", "text": text },
+            }))
+        };
+        let sink = Recording::default();
+        let mut history = Vec::new();
+        TurnPipeline::new(&mut history, &sink, false).apply(thought("secret"));
+        assert!(history.is_empty());
+
+        let mut pipeline = TurnPipeline::new(&mut history, &sink, true);
+        pipeline.apply(thought("  "));
+        pipeline.apply(thought("hmm"));
+        assert_eq!(summary(&history), ["Thought: hmm"]);
+    }
+
+    #[test]
+    fn plan_updates_replace_the_previous_plan() {
+        let plan = |n: usize| {
+            update(json!({
+                "sessionUpdate": "plan",
+                "entries": (0..n).map(|i| json!({
+                    "content": format!("step {i}"),
+                    "priority": "medium",
+                    "status": "pending",
+                })).collect::<Vec<_>>(),
+            }))
+        };
+        let sink = Recording::default();
+        let mut history = Vec::new();
+        run(
+            &mut history,
+            &sink,
+            vec![plan(1), agent("working"), plan(3)],
+        );
+        assert_eq!(summary(&history), ["plan (3 entries)", "Agent: working"]);
+        assert!(matches!(
+            history.last(),
+            Some(ChatEntry::Message {
+                normalized_markdown: Some(_),
+                ..
+            })
+        ));
+    }
+}
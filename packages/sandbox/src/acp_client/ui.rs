@@ -34,6 +34,11 @@ pub(crate) fn ui(f: &mut ratatui::Frame, app: &mut App) {
     let input_height = (line_count + 2).clamp(3, 12);
     let status_height = 1u16;
     let debug_height = if app.debug_mode { 8u16 } else { 0u16 };
+    let permission_height = if app.permissions.current().is_some() {
+        5u16
+    } else {
+        0u16
+    };
 
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -41,6 +46,7 @@ pub(crate) fn ui(f: &mut ratatui::Frame, app: &mut App) {
             [
                 Constraint::Min(1),
                 Constraint::Length(debug_height),
+                Constraint::Length(permission_height),
                 Constraint::Length(input_height),
                 Constraint::Length(status_height),
             ]
@@ -50,8 +56,9 @@ pub(crate) fn ui(f: &mut ratatui::Frame, app: &mut App) {
 
     let history_area = chunks[0];
     let debug_area = chunks[1];
-    let input_area = chunks[2];
-    let status_area = chunks[3];
+    let permission_area = chunks[2];
+    let input_area = chunks[3];
+    let status_area = chunks[4];
 
     let area_width = history_area.width as usize;
     let mut lines: Vec<Line<'_>> = Vec::new();
@@ -129,6 +136,8 @@ pub(crate) fn ui(f: &mut ratatui::Frame, app: &mut App) {
         f.render_widget(debug_paragraph, debug_area);
     }
 
+    render_permission_prompt(f, app, permission_area);
+
     f.render_widget(&app.textarea, input_area);
 
     let provider_style = ratatui::style::Style::default()
@@ -245,6 +254,52 @@ pub(crate) fn ui(f: &mut ratatui::Frame, app: &mut App) {
     }
 }
 
+/// The oldest unanswered permission request, with its numbered options.
+fn render_permission_prompt(f: &mut ratatui::Frame, app: &App, area: ratatui::layout::Rect) {
+    let Some(pending) = app.permissions.current() else {
+        return;
+    };
+    let key_style = ratatui::style::Style::default()
+        .fg(ratatui::style::Color::Yellow)
+        .add_modifier(ratatui::style::Modifier::BOLD);
+    let hint_style = ratatui::style::Style::default().fg(ratatui::style::Color::DarkGray);
+
+    let mut options = Vec::new();
+    for (i, option) in pending.options.iter().take(9).enumerate() {
+        if i > 0 {
+            options.push(Span::raw("  "));
+        }
+        options.push(Span::styled(format!("[{}] ", i + 1), key_style));
+        options.push(Span::raw(option.name.clone()));
+    }
+
+    let remaining = pending
+        .deadline
+        .saturating_duration_since(std::time::Instant::now())
+        .as_secs();
+    let fallback = match app.permissions.policy.fallback {
+        crate::acp_client::permissions::PermissionFallback::Allow => "allow",
+        crate::acp_client::permissions::PermissionFallback::Deny => "deny",
+    };
+    let mut hint = format!("Esc: reject │ {} in {}s", fallback, remaining);
+    let waiting = app.permissions.len() - 1;
+    if waiting > 0 {
+        hint.push_str(&format!(" │ {} more waiting", waiting));
+    }
+
+    let lines = vec![
+        Line::from(pending.title.clone()),
+        Line::from(options),
+        Line::styled(hint, hint_style),
+    ];
+    let block = Block::default()
+        .title(" Permission requested ")
+        .title_style(key_style)
+        .borders(Borders::ALL)
+        .border_style(ratatui::style::Style::default().fg(ratatui::style::Color::Yellow));
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
 /// Item types for palette rendering
 enum PaletteItem {
     Header(String),
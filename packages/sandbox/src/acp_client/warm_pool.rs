@@ -0,0 +1,245 @@
+//! Idle provider processes kept ready for the next conversation.
+//!
+//! Spawning a provider CLI and completing the ACP `initialize` handshake takes
+//! seconds, most of it Node start-up. The pool keeps a few initialized
+//! connections per provider so switching only has to run `session/new`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use agent_client_protocol::{ClientSideConnection, SessionId, SessionModelState};
+use anyhow::Result;
+use tokio::sync::mpsc;
+
+use crate::acp_client::connection::{connect_to_provider, open_provider, start_session};
+use crate::acp_client::events::AppEvent;
+use crate::acp_client::logging::log_debug;
+use crate::acp_client::provider::AcpProvider;
+
+/// How many idle processes to keep per provider, e.g. `claude=2,codex=1`.
+///
+/// `off` (or an empty string) disables the pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmPoolSizes(HashMap<AcpProvider, usize>);
+
+impl WarmPoolSizes {
+    pub fn disabled() -> Self {
+        Self(HashMap::new())
+    }
+
+    pub fn get(&self, provider: AcpProvider) -> usize {
+        self.0.get(&provider).copied().unwrap_or(0)
+    }
+}
+
+impl Default for WarmPoolSizes {
+    /// Claude is both the most common choice and the slowest to start.
+    fn default() -> Self {
+        Self(HashMap::from([(AcpProvider::Claude, 1)]))
+    }
+}
+
+impl FromStr for WarmPoolSizes {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.is_empty() || value.eq_ignore_ascii_case("off") {
+            return Ok(Self::disabled());
+        }
+        let mut sizes = HashMap::new();
+        for item in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, count) = item
+                .split_once('=')
+                .ok_or_else(|| format!("expected provider=count, got '{item}'"))?;
+            let provider = AcpProvider::from_short_name(name.trim())
+                .ok_or_else(|| format!("unknown provider '{}'", name.trim()))?;
+            let count = count
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid count '{}' for {}", count.trim(), name.trim()))?;
+            sizes.insert(provider, count);
+        }
+        Ok(Self(sizes))
+    }
+}
+
+impl fmt::Display for WarmPoolSizes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = AcpProvider::all()
+            .iter()
+            .filter(|p| self.get(**p) > 0)
+            .map(|p| format!("{}={}", p.short_name(), self.get(*p)))
+            .collect();
+        if entries.is_empty() {
+            f.write_str("off")
+        } else {
+            f.write_str(&entries.join(","))
+        }
+    }
+}
+
+/// Idle connections plus the spawns still in flight, so refills started
+/// back-to-back don't overshoot the target.
+pub(crate) struct WarmPool<C = Arc<ClientSideConnection>> {
+    sizes: WarmPoolSizes,
+    idle: HashMap<AcpProvider, Vec<C>>,
+    pending: HashMap<AcpProvider, usize>,
+}
+
+pub(crate) type SharedWarmPool = Rc<RefCell<WarmPool>>;
+
+impl<C> WarmPool<C> {
+    pub(crate) fn new(sizes: WarmPoolSizes) -> Self {
+        Self {
+            sizes,
+            idle: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Spawns needed to bring `provider` back to its target size. Counts
+    /// them as in flight; each must end in [`Self::put`] or [`Self::abandon`].
+    pub(crate) fn reserve(&mut self, provider: AcpProvider) -> usize {
+        let have = self.idle.get(&provider).map_or(0, Vec::len)
+            + self.pending.get(&provider).copied().unwrap_or(0);
+        let needed = self.sizes.get(provider).saturating_sub(have);
+        *self.pending.entry(provider).or_default() += needed;
+        needed
+    }
+
+    pub(crate) fn put(&mut self, provider: AcpProvider, connection: C) {
+        self.settle(provider);
+        self.idle.entry(provider).or_default().push(connection);
+    }
+
+    pub(crate) fn abandon(&mut self, provider: AcpProvider) {
+        self.settle(provider);
+    }
+
+    /// Oldest idle connection first, since it has waited longest.
+    pub(crate) fn take(&mut self, provider: AcpProvider) -> Option<C> {
+        let idle = self.idle.get_mut(&provider)?;
+        (!idle.is_empty()).then(|| idle.remove(0))
+    }
+
+    fn settle(&mut self, provider: AcpProvider) {
+        if let Some(pending) = self.pending.get_mut(&provider) {
+            *pending = pending.saturating_sub(1);
+        }
+    }
+}
+
+/// Top the pool for `provider` back up in the background.
+pub(crate) fn refill(
+    pool: &SharedWarmPool,
+    base_url: &str,
+    sandbox_id: &str,
+    provider: AcpProvider,
+    tx: mpsc::UnboundedSender<AppEvent>,
+) {
+    let needed = pool.borrow_mut().reserve(provider);
+    for _ in 0..needed {
+        let pool = pool.clone();
+        let base_url = base_url.to_string();
+        let sandbox_id = sandbox_id.to_string();
+        let tx = tx.clone();
+        tokio::task::spawn_local(async move {
+            match open_provider(&base_url, &sandbox_id, provider, tx).await {
+                Ok(connection) => {
+                    log_debug(&format!("Warmed {}", provider.display_name()));
+                    pool.borrow_mut().put(provider, connection);
+                }
+                Err(e) => {
+                    // Not retried here; the next take refills again
+                    log_debug(&format!(
+                        "Warming {} failed: {}",
+                        provider.display_name(),
+                        e
+                    ));
+                    pool.borrow_mut().abandon(provider);
+                }
+            }
+        });
+    }
+}
+
+/// Start a session on a warm connection if one is idle, falling back to a
+/// fresh spawn, then refill the pool.
+pub(crate) async fn connect_with_pool(
+    pool: &SharedWarmPool,
+    base_url: &str,
+    sandbox_id: &str,
+    provider: AcpProvider,
+    tx: mpsc::UnboundedSender<AppEvent>,
+) -> Result<(
+    Arc<ClientSideConnection>,
+    SessionId,
+    Option<SessionModelState>,
+)> {
+    let warm = pool.borrow_mut().take(provider);
+    let result = match warm {
+        Some(connection) => match start_session(&connection).await {
+            Ok((session_id, models)) => {
+                log_debug(&format!("Reused warm {} process", provider.display_name()));
+                Ok((connection, session_id, models))
+            }
+            Err(e) => {
+                // The idle process may have exited while waiting
+                log_debug(&format!(
+                    "Warm {} process unusable, spawning: {}",
+                    provider.display_name(),
+                    e
+                ));
+                connect_to_provider(base_url, sandbox_id, provider, tx.clone()).await
+            }
+        },
+        None => connect_to_provider(base_url, sandbox_id, provider, tx.clone()).await,
+    };
+    refill(pool, base_url, sandbox_id, provider, tx);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_displays_sizes() {
+        let sizes: WarmPoolSizes = "claude=2, codex=1".parse().unwrap();
+        assert_eq!(sizes.get(AcpProvider::Claude), 2);
+        assert_eq!(sizes.get(AcpProvider::Codex), 1);
+        assert_eq!(sizes.get(AcpProvider::Gemini), 0);
+        assert_eq!(sizes.to_string(), "codex=1,claude=2");
+
+        assert_eq!("off".parse::<WarmPoolSizes>().unwrap().to_string(), "off");
+        assert_eq!(WarmPoolSizes::default().to_string(), "claude=1");
+        assert!("claude".parse::<WarmPoolSizes>().is_err());
+        assert!("cursor=1".parse::<WarmPoolSizes>().is_err());
+        assert!("claude=many".parse::<WarmPoolSizes>().is_err());
+    }
+
+    #[test]
+    fn reserve_counts_idle_and_in_flight_spawns() {
+        let mut pool: WarmPool<u32> = WarmPool::new("claude=2".parse().unwrap());
+        assert_eq!(pool.reserve(AcpProvider::Claude), 2);
+        // Both spawns are still running, so nothing more is needed
+        assert_eq!(pool.reserve(AcpProvider::Claude), 0);
+        assert_eq!(pool.reserve(AcpProvider::Codex), 0);
+
+        pool.put(AcpProvider::Claude, 1);
+        pool.abandon(AcpProvider::Claude);
+        assert_eq!(pool.reserve(AcpProvider::Claude), 1);
+        pool.put(AcpProvider::Claude, 2);
+
+        assert_eq!(pool.take(AcpProvider::Claude), Some(1));
+        assert_eq!(pool.take(AcpProvider::Claude), Some(2));
+        assert_eq!(pool.take(AcpProvider::Claude), None);
+        assert_eq!(pool.take(AcpProvider::Gemini), None);
+        assert_eq!(pool.reserve(AcpProvider::Claude), 2);
+    }
+}
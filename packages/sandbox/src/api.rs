@@ -1,20 +1,23 @@
 use crate::errors::{ErrorBody, SandboxError, SandboxResult};
+use crate::logs::LogStore;
 use crate::models::{
-    AwaitReadyRequest, AwaitReadyResponse, CreateSandboxRequest, ExecRequest, ExecResponse,
-    HealthResponse, HostEvent, NotificationLevel, NotificationLogEntry, NotificationRequest,
-    OpenUrlRequest, PruneRequest, PruneResponse, PrunedItem, SandboxSummary, ServiceReadiness,
+    AwaitReadyRequest, AwaitReadyResponse, BootManifest, BootReport, ComponentLogs,
+    CreateSandboxRequest, ExecRequest, ExecResponse, HealthResponse, HostEvent, NotificationLevel,
+    NotificationLogEntry, NotificationRequest, OpenUrlRequest, PruneRequest, PruneResponse,
+    PrunedItem, ReplayRequest, SandboxSummary, ServiceDefinition, ServiceLogs, ServiceReadiness,
+    ServiceStatus, VscodeConfigRequest, VscodeConfigResponse,
 };
 use crate::notifications::NotificationStore;
 use crate::service::{AppState, GhResponseRegistry, HostEventSender, SandboxService};
 use crate::vnc_proxy::proxy_vnc_websocket;
 use axum::body::Body;
 use axum::extract::ws::WebSocketUpgrade;
-use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::extract::{DefaultBodyLimit, Path, Query, RawQuery, State};
 use axum::http::header::HOST;
 use axum::http::HeaderMap;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use axum::routing::{any, get, post};
+use axum::routing::{any, delete, get, post, put};
 use axum::{Json, Router};
 use serde::Deserialize;
 use std::net::SocketAddr;
@@ -56,9 +59,27 @@ fn default_tty() -> bool {
         send_notification,
         prune_orphaned,
         await_ready,
+        configure_vscode,
+        list_services,
+        define_service,
+        get_service,
+        remove_service,
+        start_service,
+        stop_service,
+        restart_service,
+        service_logs,
+        apply_manifest,
+        list_log_components,
+        component_logs,
+        list_acp_providers,
+        acp_preflight,
+        self_test,
+        replay_events,
     ),
     components(schemas(
         CreateSandboxRequest,
+        crate::models::HostOverride,
+        crate::models::NetworkPolicy,
         ExecRequest,
         ExecResponse,
         SandboxSummary,
@@ -75,7 +96,31 @@ fn default_tty() -> bool {
         PrunedItem,
         AwaitReadyRequest,
         AwaitReadyResponse,
-        ServiceReadiness
+        ServiceReadiness,
+        VscodeConfigRequest,
+        VscodeConfigResponse,
+        crate::models::VscodeExtensionFailure,
+        ServiceDefinition,
+        crate::models::EnvVar,
+        crate::models::RestartPolicy,
+        crate::models::HealthProbe,
+        ServiceStatus,
+        crate::models::ServiceState,
+        ServiceLogs,
+        crate::models::LogLine,
+        BootManifest,
+        crate::models::ExposedPort,
+        crate::models::ProviderDefaults,
+        BootReport,
+        ComponentLogs,
+        crate::models::AcpProviderCapabilities,
+        crate::models::AcpPreflightReport,
+        crate::models::PreflightCheck,
+        crate::models::PreflightStatus,
+        crate::models::SelfTestRequest,
+        crate::models::SelfTestReport,
+        ReplayRequest,
+        crate::models::ReplayEvent
     )),
     tags((name = "sandboxes", description = "Manage bubblewrap-based sandboxes"))
 )]
@@ -101,6 +146,14 @@ pub fn build_router(
 
     Router::new()
         .route("/healthz", get(health))
+        .route("/api/metrics", get(metrics))
+        .route("/api/acp/providers", get(list_acp_providers))
+        .route("/api/logs", get(list_log_components))
+        .route("/api/logs/{component}", get(component_logs))
+        .route(
+            "/api/replay",
+            post(replay_events).layer(DefaultBodyLimit::max(REPLAY_BODY_LIMIT)),
+        )
         .route("/sandboxes", get(list_sandboxes).post(create_sandbox))
         .route("/sandboxes/{id}", get(get_sandbox).delete(delete_sandbox))
         .route("/sandboxes/{id}/exec", post(exec_sandbox))
@@ -111,6 +164,28 @@ pub fn build_router(
         .route("/sandboxes/{id}/attach", any(attach_sandbox))
         .route("/sandboxes/{id}/proxy", any(proxy_sandbox))
         .route("/sandboxes/{id}/await-ready", post(await_ready))
+        .route("/sandboxes/{id}/vscode/config", post(configure_vscode))
+        .route(
+            "/sandboxes/{id}/services",
+            get(list_services).post(define_service),
+        )
+        .route(
+            "/sandboxes/{id}/services/{name}",
+            get(get_service).delete(remove_service),
+        )
+        .route("/sandboxes/{id}/services/{name}/start", post(start_service))
+        .route("/sandboxes/{id}/services/{name}/stop", post(stop_service))
+        .route(
+            "/sandboxes/{id}/services/{name}/restart",
+            post(restart_service),
+        )
+        .route("/sandboxes/{id}/services/{name}/logs", get(service_logs))
+        .route("/sandboxes/{id}/manifest", post(apply_manifest))
+        .route(
+            "/sandboxes/{id}/acp/{provider}/preflight",
+            get(acp_preflight),
+        )
+        .route("/sandboxes/{id}/self-test", post(self_test))
         // PTY proxy endpoints - direct access to sandbox's cmux-pty
         .route(
             "/sandboxes/{id}/pty/sessions",
@@ -128,11 +203,27 @@ pub fn build_router(
             "/sandboxes/{id}/pty/sessions/{session_id}/capture",
             get(pty_capture_session),
         )
+        .route(
+            "/sandboxes/{id}/pty/sessions/{session_id}/share",
+            post(pty_create_share).delete(pty_revoke_shares),
+        )
         .route(
             "/sandboxes/{id}/pty/sessions/{session_id}/attach",
             any(pty_attach_session),
         )
+        .route(
+            "/sandboxes/{id}/pty/shares/{token}/attach",
+            any(pty_attach_share),
+        )
         .route("/sandboxes/{id}/pty/signal", post(pty_signal))
+        .route(
+            "/sandboxes/{id}/pty/conversations/{conversation_id}/env",
+            put(pty_set_conversation_env).delete(pty_delete_conversation_env),
+        )
+        .route(
+            "/sandboxes/{id}/pty/conversations/{conversation_id}",
+            delete(pty_delete_conversation),
+        )
         // Multiplexed WebSocket endpoint - single connection for all PTY sessions
         .route("/mux/attach", any(mux_attach))
         // Open URL on host - used by sandboxed processes to open links
@@ -161,6 +252,33 @@ async fn health() -> Json<HealthResponse> {
     })
 }
 
+/// Prometheus scrape endpoint for fleet placement and reclamation decisions.
+async fn metrics(state: axum::extract::State<AppState>) -> SandboxResult<Response> {
+    let snapshot = state.service.metrics().await?;
+    Ok((
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        crate::metrics::render_prometheus(&snapshot),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/acp/providers",
+    responses((status = 200, description = "Capabilities of each ACP provider", body = [crate::models::AcpProviderCapabilities]))
+)]
+async fn list_acp_providers() -> Json<Vec<crate::models::AcpProviderCapabilities>> {
+    Json(
+        crate::acp_client::AcpProvider::all()
+            .iter()
+            .map(|provider| provider.capabilities())
+            .collect(),
+    )
+}
+
 #[utoipa::path(
     post,
     path = "/sandboxes",
@@ -469,6 +587,8 @@ async fn subdomain_proxy(
     };
 
     let target_url = format!("http://{}:{}{}", sandbox_ip, port, path_and_query);
+    crate::metrics::record_proxy_request();
+    crate::metrics::record_proxy_upstream(body_bytes.len());
 
     tracing::info!(
         sandbox_index = index,
@@ -558,6 +678,7 @@ async fn subdomain_proxy(
             match resp.bytes().await {
                 Ok(body) => {
                     tracing::debug!("proxy response body size: {} bytes", body.len());
+                    crate::metrics::record_proxy_downstream(body.len());
                     response.body(Body::from(body)).unwrap_or_else(|e| {
                         tracing::error!("Failed to build response: {e}");
                         StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -598,6 +719,7 @@ async fn proxy_websocket(
 
     // Upgrade to WebSocket
     let (upstream_ws, _) = tokio_tungstenite::client_async(&url, stream).await?;
+    crate::metrics::record_proxy_request();
     let (mut upstream_sink, mut upstream_stream) = upstream_ws.split();
 
     let (mut client_sink, mut client_stream) = client_socket.split();
@@ -607,6 +729,7 @@ async fn proxy_websocket(
         while let Some(msg_result) = client_stream.next().await {
             match msg_result {
                 Ok(axum::extract::ws::Message::Binary(data)) => {
+                    crate::metrics::record_proxy_upstream(data.len());
                     if upstream_sink
                         .send(TungsteniteMessage::Binary(data.to_vec()))
                         .await
@@ -616,6 +739,7 @@ async fn proxy_websocket(
                     }
                 }
                 Ok(axum::extract::ws::Message::Text(text)) => {
+                    crate::metrics::record_proxy_upstream(text.len());
                     if upstream_sink
                         .send(TungsteniteMessage::Text(text.to_string()))
                         .await
@@ -652,6 +776,7 @@ async fn proxy_websocket(
     while let Some(msg_result) = upstream_stream.next().await {
         match msg_result {
             Ok(TungsteniteMessage::Binary(data)) => {
+                crate::metrics::record_proxy_downstream(data.len());
                 if client_sink
                     .send(axum::extract::ws::Message::Binary(data.into()))
                     .await
@@ -661,6 +786,7 @@ async fn proxy_websocket(
                 }
             }
             Ok(TungsteniteMessage::Text(text)) => {
+                crate::metrics::record_proxy_downstream(text.len());
                 if client_sink
                     .send(axum::extract::ws::Message::Text(text.into()))
                     .await
@@ -874,11 +1000,326 @@ async fn await_ready(
     Ok(Json(response))
 }
 
+#[utoipa::path(
+    post,
+    path = "/sandboxes/{id}/vscode/config",
+    request_body = VscodeConfigRequest,
+    params(
+        ("id" = String, Path, description = "Sandbox ID")
+    ),
+    responses(
+        (status = 200, description = "Editor configuration applied", body = VscodeConfigResponse),
+        (status = 400, description = "Invalid extension or settings", body = ErrorBody),
+        (status = 404, description = "Sandbox not found", body = ErrorBody)
+    )
+)]
+async fn configure_vscode(
+    state: axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<VscodeConfigRequest>,
+) -> SandboxResult<Json<VscodeConfigResponse>> {
+    let response = state.service.configure_vscode(id, request).await?;
+    Ok(Json(response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sandboxes/{id}/manifest",
+    request_body = BootManifest,
+    params(
+        ("id" = String, Path, description = "Sandbox ID")
+    ),
+    responses(
+        (status = 200, description = "Manifest applied; failed steps are listed in errors", body = BootReport),
+        (status = 400, description = "Invalid manifest", body = ErrorBody),
+        (status = 404, description = "Sandbox not found", body = ErrorBody)
+    )
+)]
+async fn apply_manifest(
+    state: axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    Json(manifest): Json<BootManifest>,
+) -> SandboxResult<Json<BootReport>> {
+    let report = state.service.apply_manifest(id, manifest).await?;
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+struct LogsParams {
+    tail: Option<usize>,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/logs",
+    responses((status = 200, description = "Components with recorded logs", body = [String]))
+)]
+async fn list_log_components() -> Json<Vec<String>> {
+    Json(LogStore::global().components())
+}
+
+/// Recent log lines for one component: `server` for this process, or
+/// `<sandbox-id>.<service>` for a supervised service.
+#[utoipa::path(
+    get,
+    path = "/api/logs/{component}",
+    params(
+        ("component" = String, Path, description = "Log component, e.g. server or <sandbox-id>.cmux-pty"),
+        ("tail" = Option<usize>, Query, description = "Only return the last N lines"),
+        ("since" = Option<String>, Query, description = "Only return lines at or after this RFC 3339 time")
+    ),
+    responses(
+        (status = 200, description = "Recent log lines", body = ComponentLogs),
+        (status = 404, description = "Component has no logs", body = ErrorBody)
+    )
+)]
+async fn component_logs(
+    Path(component): Path<String>,
+    Query(params): Query<LogsParams>,
+) -> SandboxResult<Json<ComponentLogs>> {
+    let lines = LogStore::global()
+        .query(&component, params.tail, params.since)
+        .ok_or_else(|| SandboxError::LogComponentNotFound(component.clone()))?;
+    Ok(Json(ComponentLogs { component, lines }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/sandboxes/{id}/acp/{provider}/preflight",
+    params(
+        ("id" = String, Path, description = "Sandbox ID"),
+        ("provider" = String, Path, description = "ACP provider short name, e.g. `claude`")
+    ),
+    responses(
+        (status = 200, description = "Binary, version and credential checks; `ok` is false when the provider can't be spawned", body = crate::models::AcpPreflightReport),
+        (status = 400, description = "Unknown provider", body = ErrorBody),
+        (status = 404, description = "Sandbox not found", body = ErrorBody)
+    )
+)]
+async fn acp_preflight(
+    state: axum::extract::State<AppState>,
+    Path((id, provider)): Path<(String, String)>,
+) -> SandboxResult<Json<crate::models::AcpPreflightReport>> {
+    let provider = crate::acp_client::AcpProvider::from_short_name(&provider).ok_or_else(|| {
+        SandboxError::InvalidRequest(format!("unknown ACP provider '{provider}'"))
+    })?;
+    let report = crate::preflight::run_preflight(state.service.as_ref(), id, provider).await?;
+    Ok(Json(report))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sandboxes/{id}/self-test",
+    params(("id" = String, Path, description = "Sandbox ID")),
+    request_body = crate::models::SelfTestRequest,
+    responses(
+        (status = 200, description = "One check per provider, the PTY server, cmux-proxy, VNC and the callback URL; `ok` is false when any check failed", body = crate::models::SelfTestReport),
+        (status = 404, description = "Sandbox not found", body = ErrorBody)
+    )
+)]
+async fn self_test(
+    state: axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<crate::models::SelfTestRequest>,
+) -> SandboxResult<Json<crate::models::SelfTestReport>> {
+    let report = crate::self_test::run_self_test(state.service.as_ref(), id, request).await?;
+    Ok(Json(report))
+}
+
+/// Recordings of long sessions easily exceed axum's 2 MB default, but the
+/// whole body is buffered, so keep the cap modest.
+const REPLAY_BODY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Test utility for clients: paces events the caller uploads. Nothing is read
+/// from server-side history.
+#[utoipa::path(
+    post,
+    path = "/api/replay",
+    request_body = ReplayRequest,
+    responses(
+        (status = 200, description = "The uploaded events streamed back with their recorded timing, ending with a `replay-end` event", content_type = "text/event-stream"),
+        (status = 400, description = "Invalid speed", body = ErrorBody)
+    )
+)]
+async fn replay_events(Json(request): Json<ReplayRequest>) -> SandboxResult<Response> {
+    let stream = crate::replay::replay(request)?;
+    Ok(axum::response::sse::Sse::new(stream).into_response())
+}
+
+#[derive(Deserialize)]
+struct ServiceLogsParams {
+    tail: Option<usize>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/sandboxes/{id}/services",
+    params(
+        ("id" = String, Path, description = "Sandbox ID")
+    ),
+    responses(
+        (status = 200, description = "Supervised services", body = [ServiceStatus]),
+        (status = 404, description = "Sandbox not found", body = ErrorBody)
+    )
+)]
+async fn list_services(
+    state: axum::extract::State<AppState>,
+    Path(id): Path<String>,
+) -> SandboxResult<Json<Vec<ServiceStatus>>> {
+    let supervisor = state.service.services(id).await?;
+    Ok(Json(supervisor.list().await))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sandboxes/{id}/services",
+    request_body = ServiceDefinition,
+    params(
+        ("id" = String, Path, description = "Sandbox ID")
+    ),
+    responses(
+        (status = 200, description = "Service defined and started", body = ServiceStatus),
+        (status = 400, description = "Invalid service definition", body = ErrorBody),
+        (status = 404, description = "Sandbox not found", body = ErrorBody)
+    )
+)]
+async fn define_service(
+    state: axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    Json(definition): Json<ServiceDefinition>,
+) -> SandboxResult<Json<ServiceStatus>> {
+    let supervisor = state.service.services(id).await?;
+    Ok(Json(supervisor.define(definition).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/sandboxes/{id}/services/{name}",
+    params(
+        ("id" = String, Path, description = "Sandbox ID"),
+        ("name" = String, Path, description = "Service name")
+    ),
+    responses(
+        (status = 200, description = "Service status", body = ServiceStatus),
+        (status = 404, description = "Sandbox or service not found", body = ErrorBody)
+    )
+)]
+async fn get_service(
+    state: axum::extract::State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+) -> SandboxResult<Json<ServiceStatus>> {
+    let supervisor = state.service.services(id).await?;
+    Ok(Json(supervisor.status(&name).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/sandboxes/{id}/services/{name}",
+    params(
+        ("id" = String, Path, description = "Sandbox ID"),
+        ("name" = String, Path, description = "Service name")
+    ),
+    responses(
+        (status = 200, description = "Service stopped and removed", body = ServiceStatus),
+        (status = 404, description = "Sandbox or service not found", body = ErrorBody)
+    )
+)]
+async fn remove_service(
+    state: axum::extract::State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+) -> SandboxResult<Json<ServiceStatus>> {
+    let supervisor = state.service.services(id).await?;
+    Ok(Json(supervisor.remove(&name).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sandboxes/{id}/services/{name}/start",
+    params(
+        ("id" = String, Path, description = "Sandbox ID"),
+        ("name" = String, Path, description = "Service name")
+    ),
+    responses(
+        (status = 200, description = "Service started", body = ServiceStatus),
+        (status = 404, description = "Sandbox or service not found", body = ErrorBody)
+    )
+)]
+async fn start_service(
+    state: axum::extract::State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+) -> SandboxResult<Json<ServiceStatus>> {
+    let supervisor = state.service.services(id).await?;
+    Ok(Json(supervisor.start(&name).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sandboxes/{id}/services/{name}/stop",
+    params(
+        ("id" = String, Path, description = "Sandbox ID"),
+        ("name" = String, Path, description = "Service name")
+    ),
+    responses(
+        (status = 200, description = "Service stopped", body = ServiceStatus),
+        (status = 404, description = "Sandbox or service not found", body = ErrorBody)
+    )
+)]
+async fn stop_service(
+    state: axum::extract::State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+) -> SandboxResult<Json<ServiceStatus>> {
+    let supervisor = state.service.services(id).await?;
+    Ok(Json(supervisor.stop(&name).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sandboxes/{id}/services/{name}/restart",
+    params(
+        ("id" = String, Path, description = "Sandbox ID"),
+        ("name" = String, Path, description = "Service name")
+    ),
+    responses(
+        (status = 200, description = "Service restarted", body = ServiceStatus),
+        (status = 404, description = "Sandbox or service not found", body = ErrorBody)
+    )
+)]
+async fn restart_service(
+    state: axum::extract::State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+) -> SandboxResult<Json<ServiceStatus>> {
+    let supervisor = state.service.services(id).await?;
+    Ok(Json(supervisor.restart(&name).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/sandboxes/{id}/services/{name}/logs",
+    params(
+        ("id" = String, Path, description = "Sandbox ID"),
+        ("name" = String, Path, description = "Service name"),
+        ("tail" = Option<usize>, Query, description = "Only return the last N lines")
+    ),
+    responses(
+        (status = 200, description = "Recent service output", body = ServiceLogs),
+        (status = 404, description = "Sandbox or service not found", body = ErrorBody)
+    )
+)]
+async fn service_logs(
+    state: axum::extract::State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+    Query(params): Query<ServiceLogsParams>,
+) -> SandboxResult<Json<ServiceLogs>> {
+    let supervisor = state.service.services(id).await?;
+    Ok(Json(supervisor.logs(&name, params.tail).await?))
+}
+
 // =============================================================================
 // PTY Proxy Endpoints - Direct access to sandbox's cmux-pty service
 // =============================================================================
 
-const PTY_PORT: u16 = 39383;
+pub(crate) const PTY_PORT: u16 = 39383;
 
 /// Get the sandbox IP address from the service.
 async fn get_sandbox_ip(state: &AppState, id: &str) -> SandboxResult<String> {
@@ -1061,18 +1502,80 @@ async fn pty_capture_session(
     proxy_pty_request(&sandbox_ip, reqwest::Method::GET, &path, None, None).await
 }
 
-/// WebSocket attach to a PTY session.
-async fn pty_attach_session(
+/// Mint an expiring, read-only share link for a PTY session.
+async fn pty_create_share(
     state: axum::extract::State<AppState>,
     Path((id, session_id)): Path<(String, String)>,
-    ws: WebSocketUpgrade,
+    body: axum::body::Bytes,
 ) -> Response {
     let sandbox_ip = match get_sandbox_ip(&state, &id).await {
         Ok(ip) => ip,
         Err(e) => return e.into_response(),
     };
 
-    let path = format!("/sessions/{}/attach", session_id);
+    let path = format!("/sessions/{}/share", session_id);
+    proxy_pty_request(
+        &sandbox_ip,
+        reqwest::Method::POST,
+        &path,
+        Some(body.to_vec()),
+        Some("application/json"),
+    )
+    .await
+}
+
+/// Revoke every share link for a PTY session.
+async fn pty_revoke_shares(
+    state: axum::extract::State<AppState>,
+    Path((id, session_id)): Path<(String, String)>,
+) -> Response {
+    let sandbox_ip = match get_sandbox_ip(&state, &id).await {
+        Ok(ip) => ip,
+        Err(e) => return e.into_response(),
+    };
+
+    let path = format!("/sessions/{}/share", session_id);
+    proxy_pty_request(&sandbox_ip, reqwest::Method::DELETE, &path, None, None).await
+}
+
+/// WebSocket attach to a PTY session. The query (`replay`, `links`) is
+/// passed through.
+async fn pty_attach_session(
+    state: axum::extract::State<AppState>,
+    Path((id, session_id)): Path<(String, String)>,
+    RawQuery(query): RawQuery,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let path = format!("/sessions/{}/ws", session_id);
+    pty_attach(state, &id, path, query, ws).await
+}
+
+/// Read-only WebSocket attach through a share link's token.
+async fn pty_attach_share(
+    state: axum::extract::State<AppState>,
+    Path((id, token)): Path<(String, String)>,
+    RawQuery(query): RawQuery,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let path = format!("/shares/{}/ws", token);
+    pty_attach(state, &id, path, query, ws).await
+}
+
+async fn pty_attach(
+    state: axum::extract::State<AppState>,
+    id: &str,
+    mut path: String,
+    query: Option<String>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let sandbox_ip = match get_sandbox_ip(&state, id).await {
+        Ok(ip) => ip,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(query) = query {
+        path.push('?');
+        path.push_str(&query);
+    }
 
     ws.on_upgrade(move |socket| async move {
         if let Err(e) = proxy_websocket(socket, &sandbox_ip, PTY_PORT, &path).await {
@@ -1102,6 +1605,58 @@ async fn pty_signal(
     .await
 }
 
+/// Register environment that new PTY sessions for an ACP conversation inherit,
+/// so commands typed by hand hit the same proxies as the agent.
+async fn pty_set_conversation_env(
+    state: axum::extract::State<AppState>,
+    Path((id, conversation_id)): Path<(String, String)>,
+    body: axum::body::Bytes,
+) -> Response {
+    let sandbox_ip = match get_sandbox_ip(&state, &id).await {
+        Ok(ip) => ip,
+        Err(e) => return e.into_response(),
+    };
+
+    let path = format!("/conversations/{}/env", conversation_id);
+    proxy_pty_request(
+        &sandbox_ip,
+        reqwest::Method::PUT,
+        &path,
+        Some(body.to_vec()),
+        Some("application/json"),
+    )
+    .await
+}
+
+/// Forget the environment registered for an ACP conversation.
+async fn pty_delete_conversation_env(
+    state: axum::extract::State<AppState>,
+    Path((id, conversation_id)): Path<(String, String)>,
+) -> Response {
+    let sandbox_ip = match get_sandbox_ip(&state, &id).await {
+        Ok(ip) => ip,
+        Err(e) => return e.into_response(),
+    };
+
+    let path = format!("/conversations/{}/env", conversation_id);
+    proxy_pty_request(&sandbox_ip, reqwest::Method::DELETE, &path, None, None).await
+}
+
+/// Clean up after a deleted ACP conversation: kill its terminals, drop its
+/// environment and remove its temp files.
+async fn pty_delete_conversation(
+    state: axum::extract::State<AppState>,
+    Path((id, conversation_id)): Path<(String, String)>,
+) -> Response {
+    let sandbox_ip = match get_sandbox_ip(&state, &id).await {
+        Ok(ip) => ip,
+        Err(e) => return e.into_response(),
+    };
+
+    let path = format!("/conversations/{}", conversation_id);
+    proxy_pty_request(&sandbox_ip, reqwest::Method::DELETE, &path, None, None).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1116,9 +1671,22 @@ mod tests {
     use tower::ServiceExt;
     use uuid::Uuid;
 
-    #[derive(Clone, Default)]
+    #[derive(Clone)]
     struct MockService {
         calls: Arc<Mutex<usize>>,
+        services: Arc<crate::supervisor::Supervisor>,
+    }
+
+    impl Default for MockService {
+        fn default() -> Self {
+            Self {
+                calls: Arc::default(),
+                services: Arc::new(crate::supervisor::Supervisor::new(
+                    Vec::new(),
+                    std::net::Ipv4Addr::LOCALHOST.into(),
+                )),
+            }
+        }
     }
 
     #[async_trait]
@@ -1203,6 +1771,42 @@ mod tests {
                 timed_out: vec![],
             })
         }
+
+        async fn configure_vscode(
+            &self,
+            _id: String,
+            request: VscodeConfigRequest,
+        ) -> SandboxResult<VscodeConfigResponse> {
+            Ok(VscodeConfigResponse {
+                installed: request.extensions,
+                settings_updated: request.settings.is_some(),
+                keybindings_updated: request.keybindings.is_some(),
+                ..Default::default()
+            })
+        }
+
+        async fn metrics(&self) -> SandboxResult<crate::metrics::MetricsSnapshot> {
+            Ok(crate::metrics::MetricsSnapshot {
+                sandboxes_running: 1,
+                sandboxes_total: 1,
+                ..Default::default()
+            })
+        }
+
+        async fn services(&self, _id: String) -> SandboxResult<Arc<crate::supervisor::Supervisor>> {
+            Ok(self.services.clone())
+        }
+
+        async fn apply_manifest(
+            &self,
+            _id: String,
+            manifest: crate::models::BootManifest,
+        ) -> SandboxResult<crate::models::BootReport> {
+            Ok(crate::models::BootReport {
+                services: manifest.services.into_iter().map(|s| s.name).collect(),
+                ..Default::default()
+            })
+        }
     }
 
     fn fake_summary(name: String) -> SandboxSummary {
@@ -1256,6 +1860,209 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[tokio::test]
+    async fn lists_acp_provider_capabilities() {
+        let app = make_test_router();
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/acp/providers")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let providers: Vec<crate::models::AcpProviderCapabilities> =
+            serde_json::from_slice(&body).unwrap();
+        let ids: Vec<&str> = providers.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["codex", "opencode", "claude", "gemini"]);
+        assert!(providers.iter().all(|p| p.supports_cancel));
+    }
+
+    #[tokio::test]
+    async fn acp_preflight_reports_missing_cli() {
+        let app = make_test_router();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/sandboxes/abc/acp/claude/preflight")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let report: crate::models::AcpPreflightReport = serde_json::from_slice(&body).unwrap();
+        // The mock exec prints nothing the script would, so nothing is found
+        assert_eq!(report.provider, "claude");
+        assert!(!report.ok);
+        assert_eq!(report.checks[0].name, "binary");
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/sandboxes/abc/acp/nope/preflight")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn replay_streams_recorded_events_as_sse() {
+        let app = make_test_router();
+        let body = serde_json::json!({
+            "events": [
+                { "at_ms": 1000, "event": "chunk", "data": { "text": "hel" } },
+                { "at_ms": 1040, "data": { "text": "lo" } },
+            ],
+            "speed": 4.0,
+        });
+        let started = std::time::Instant::now();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/replay")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= std::time::Duration::from_millis(10));
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(
+            text,
+            "event: chunk\nid: 0\ndata: {\"text\":\"hel\"}\n\n\
+             id: 1\ndata: {\"text\":\"lo\"}\n\n\
+             event: replay-end\ndata: {\"events\":2}\n\n"
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/replay")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"events":[],"speed":0}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn logs_endpoint_returns_component_tail() {
+        let component = format!("{}.api-test", Uuid::new_v4());
+        for idx in 0..5 {
+            LogStore::global().push(&component, format!("line-{idx}"));
+        }
+        let app = make_test_router();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/api/logs/{component}?tail=2"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let logs: ComponentLogs = serde_json::from_slice(&body).unwrap();
+        let lines: Vec<&str> = logs.lines.iter().map(|l| l.line.as_str()).collect();
+        assert_eq!(lines, vec!["line-3", "line-4"]);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs/no-such-component")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn services_can_be_defined_and_stopped() {
+        let app = make_test_router();
+        let definition = serde_json::json!({
+            "name": "worker",
+            "command": "echo ready; exec sleep 30",
+            "restart": "always"
+        });
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/sandboxes/any/services")
+                    .header("content-type", "application/json")
+                    .body(Body::from(definition.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/sandboxes/any/services/worker/stop")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let status: ServiceStatus = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status.state, crate::models::ServiceState::Stopped);
+        assert_eq!(
+            status.definition.restart,
+            crate::models::RestartPolicy::Always
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/sandboxes/any/services/missing/logs?tail=10")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
     #[tokio::test]
     async fn create_endpoint_returns_summary() {
         let app = make_test_router();
@@ -1266,6 +2073,8 @@ mod tests {
             read_only_paths: Vec::new(),
             tmpfs: Vec::new(),
             env: Vec::new(),
+            hosts: Vec::new(),
+            network_policy: None,
         };
 
         let response = app
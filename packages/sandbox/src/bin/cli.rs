@@ -1,7 +1,8 @@
 use chrono::SecondsFormat;
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use cmux_sandbox::models::{
-    CreateSandboxRequest, EnvVar, ExecRequest, ExecResponse, NotificationLogEntry, SandboxSummary,
+    CreateSandboxRequest, EnvVar, ExecRequest, ExecResponse, HostOverride, NetworkPolicy,
+    NotificationLogEntry, SandboxSummary,
 };
 use cmux_sandbox::{
     build_default_env_vars, cache_access_token, clear_cached_access_token, clear_default_team,
@@ -11,8 +12,9 @@ use cmux_sandbox::{
     sync_files::{
         prebuild_sync_files_tar, upload_prebuilt_sync_files, upload_sync_files, SYNC_FILES,
     },
-    AcpProvider, DEFAULT_HTTP_PORT, DEFAULT_IMAGE, DMUX_DEFAULT_CONTAINER, DMUX_DEFAULT_HTTP_PORT,
-    DMUX_DEFAULT_IMAGE,
+    AcpProvider, PermissionFallback, PermissionMode, PermissionPolicy, ReasoningVisibility,
+    WarmPoolSizes, DEFAULT_HTTP_PORT, DEFAULT_IMAGE, DMUX_DEFAULT_CONTAINER,
+    DMUX_DEFAULT_HTTP_PORT, DMUX_DEFAULT_IMAGE,
 };
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use futures::{SinkExt, StreamExt};
@@ -343,6 +345,38 @@ struct ChatArgs {
     /// ACP provider to use (codex, opencode, claude, gemini). Defaults to last used provider.
     #[arg(long, short = 'a', value_enum)]
     acp: Option<AcpProvider>,
+
+    /// What to do with agent reasoning: keep it in transcripts (persist), only
+    /// show it live (stream), or drop it. Can be changed from the palette.
+    #[arg(long, value_enum, env = "CMUX_CHAT_REASONING", default_value_t)]
+    reasoning: ReasoningVisibility,
+
+    /// Idle provider processes to keep ready per provider, e.g. `claude=2,codex=1`
+    /// or `off`. Switching to a warm provider skips CLI start-up.
+    #[arg(long, env = "CMUX_CHAT_WARM_POOL", default_value_t)]
+    warm_pool: WarmPoolSizes,
+
+    /// How to answer the agent's permission requests: approve automatically
+    /// (auto) or ask in the chat (ask).
+    #[arg(long, value_enum, env = "CMUX_CHAT_PERMISSIONS", default_value_t)]
+    permissions: PermissionMode,
+
+    /// Seconds to wait for an answer in `ask` mode before applying the fallback.
+    #[arg(long, env = "CMUX_CHAT_PERMISSION_TIMEOUT", default_value_t = 60)]
+    permission_timeout: u64,
+
+    /// Answer applied when a permission prompt times out.
+    #[arg(
+        long,
+        value_enum,
+        env = "CMUX_CHAT_PERMISSION_FALLBACK",
+        default_value_t
+    )]
+    permission_fallback: PermissionFallback,
+
+    /// Cancel a turn the agent hasn't finished after this many seconds.
+    #[arg(long, env = "CMUX_CHAT_TURN_TIMEOUT")]
+    turn_timeout: Option<u64>,
 }
 
 #[derive(Args, Debug)]
@@ -558,6 +592,12 @@ struct CreateArgs {
     read_only_paths: Vec<PathBuf>,
     #[arg(long, value_name = "PATH")]
     tmpfs: Vec<String>,
+    /// Extra /etc/hosts entry inside the sandbox, e.g. `--add-host api.anthropic.com:10.201.0.1`
+    #[arg(long = "add-host", value_name = "HOST:IP", value_parser = parse_host_override)]
+    hosts: Vec<HostOverride>,
+    /// Restrict egress to this domain or IPv4 CIDR (repeatable); all other outbound traffic is rejected
+    #[arg(long = "allow-egress", value_name = "DOMAIN|CIDR")]
+    allow_egress: Vec<String>,
 }
 
 #[derive(Args, Debug)]
@@ -630,6 +670,17 @@ fn parse_env(raw: &str) -> Result<EnvVar, String> {
     })
 }
 
+fn parse_host_override(raw: &str) -> Result<HostOverride, String> {
+    let (hostname, address) = raw
+        .split_once(':')
+        .ok_or_else(|| "host override should look like HOST:IP".to_string())?;
+
+    Ok(HostOverride {
+        hostname: hostname.to_string(),
+        address: address.to_string(),
+    })
+}
+
 /// Parse a duration string like "24h", "7d", "1w" into a Duration
 fn parse_duration(raw: &str) -> Result<Duration, String> {
     let raw = raw.trim();
@@ -794,6 +845,8 @@ async fn run() -> anyhow::Result<()> {
                     read_only_paths: vec![],
                     tmpfs: vec![],
                     env: build_default_env_vars(),
+                    hosts: vec![],
+                    network_policy: None,
                 };
                 let url = format!("{}/sandboxes", cli.base_url.trim_end_matches('/'));
                 let response = client.post(url).json(&body).send().await?;
@@ -1114,6 +1167,8 @@ async fn run() -> anyhow::Result<()> {
                     read_only_paths: vec![],
                     tmpfs: vec![],
                     env: build_default_env_vars(),
+                    hosts: vec![],
+                    network_policy: None,
                 };
                 let url = format!("{}/sandboxes", cli.base_url.trim_end_matches('/'));
                 let response = client.post(url).json(&body).send().await?;
@@ -1167,6 +1222,14 @@ async fn run() -> anyhow::Result<()> {
                     cli.base_url,
                     sandbox_id,
                     provider,
+                    args.reasoning,
+                    PermissionPolicy {
+                        mode: args.permissions,
+                        timeout: std::time::Duration::from_secs(args.permission_timeout),
+                        fallback: args.permission_fallback,
+                    },
+                    args.turn_timeout.map(std::time::Duration::from_secs),
+                    args.warm_pool,
                     Some(workspace_status_rx),
                 )
                 .await
@@ -1266,6 +1329,10 @@ async fn run() -> anyhow::Result<()> {
                             .collect(),
                         tmpfs: args.tmpfs,
                         env: args.env,
+                        hosts: args.hosts,
+                        network_policy: (!args.allow_egress.is_empty()).then_some(NetworkPolicy {
+                            allow: args.allow_egress,
+                        }),
                     };
 
                     let url = format!("{}/sandboxes", cli.base_url.trim_end_matches('/'));
@@ -1294,6 +1361,8 @@ async fn run() -> anyhow::Result<()> {
                         read_only_paths: vec![],
                         tmpfs: vec![],
                         env: build_default_env_vars(),
+                        hosts: vec![],
+                        network_policy: None,
                     };
                     let url = format!("{}/sandboxes", cli.base_url.trim_end_matches('/'));
                     let response = client.post(url).json(&body).send().await?;
@@ -1563,7 +1632,7 @@ async fn handle_prune(client: &Client, base_url: &str, args: PruneArgs) -> anyho
         .collect();
 
     // Sort by age (oldest first)
-    to_prune.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    to_prune.sort_by_key(|a| a.created_at);
 
     if to_prune.is_empty() {
         let filter_desc = if args.all {
@@ -5230,6 +5299,8 @@ async fn handle_esctest(client: &Client, base_url: &str, args: EsctestArgs) -> a
         read_only_paths: vec![],
         tmpfs: vec![],
         env: build_default_env_vars(),
+        hosts: vec![],
+        network_policy: None,
     };
     let url = format!("{}/sandboxes", base_url.trim_end_matches('/'));
     let response = client.post(url).json(&body).send().await?;
@@ -7,6 +7,7 @@ use clap::{Parser, Subcommand};
 use cmux_sandbox::models::{
     BridgeRequest, BridgeResponse, NotificationLevel, NotificationRequest, OpenUrlRequest,
 };
+use cmux_sandbox::pty_agent::{self, PtyAgentConfig};
 use cmux_sandbox::DEFAULT_HTTP_PORT;
 use reqwest::Client;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -56,6 +57,19 @@ enum Command {
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
+    /// Serve ACP on stdio by driving a CLI in a PTY.
+    /// Lets ACP clients use coding CLIs that have no ACP mode of their own.
+    PtyAgent {
+        /// Input typed for each prompt; `{prompt}` is replaced by the prompt text
+        #[arg(long, default_value = "{prompt}\r")]
+        prompt_template: String,
+        /// End a turn after this much quiet output when the CLI emits no OSC 133 marks
+        #[arg(long, default_value_t = 3000)]
+        idle_timeout_ms: u64,
+        /// Command to run, e.g. `cmux-bridge pty-agent -- aider --no-pretty`
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
 }
 
 #[tokio::main]
@@ -81,6 +95,19 @@ async fn main() {
             handle_notify(&cli, message.clone(), *level, sandbox_id, tab_id, pane_id).await
         }
         Command::Gh { args } => handle_gh(&cli, args.clone(), sandbox_id, tab_id).await,
+        Command::PtyAgent {
+            prompt_template,
+            idle_timeout_ms,
+            command,
+        } => {
+            pty_agent::serve_stdio(PtyAgentConfig {
+                command: command.clone(),
+                prompt_template: prompt_template.clone(),
+                idle_timeout: Duration::from_millis(*idle_timeout_ms),
+                ..PtyAgentConfig::default()
+            })
+            .await
+        }
     };
 
     if let Err(error) = result {
@@ -4,6 +4,7 @@ use clap::Parser;
 use cmux_sandbox::bubblewrap::BubblewrapService;
 use cmux_sandbox::build_router;
 use cmux_sandbox::errors::{SandboxError, SandboxResult};
+use cmux_sandbox::logs::{LogStore, SERVER_COMPONENT};
 use cmux_sandbox::models::{
     BridgeRequest, BridgeResponse, CreateSandboxRequest, ExecRequest, ExecResponse, GhRequest,
     GhResponse, HostEvent, NotificationLevel, NotificationRequest, OpenUrlRequest, SandboxSummary,
@@ -66,6 +67,17 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Keeps recent server logs in memory for `GET /api/logs/server`.
+fn memory_layer<S>() -> impl tracing_subscriber::Layer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    tracing_subscriber::fmt::layer()
+        .with_writer(LogStore::global().writer(SERVER_COMPONENT))
+        .with_target(false)
+        .with_ansi(false)
+}
+
 fn init_tracing(log_dir: &PathBuf) -> Option<tracing_appender::non_blocking::WorkerGuard> {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
@@ -80,6 +92,7 @@ fn init_tracing(log_dir: &PathBuf) -> Option<tracing_appender::non_blocking::Wor
         tracing_subscriber::registry()
             .with(filter)
             .with(stdout_layer)
+            .with(memory_layer())
             .init();
         return None;
     }
@@ -96,6 +109,7 @@ fn init_tracing(log_dir: &PathBuf) -> Option<tracing_appender::non_blocking::Wor
         .with(filter)
         .with(stdout_layer)
         .with(file_layer)
+        .with(memory_layer())
         .init();
 
     Some(guard)
@@ -123,7 +137,7 @@ async fn run_server(options: Options) {
     let gh_auth_cache: GhAuthCache = Arc::new(Mutex::new(None));
     let notifications = NotificationStore::new();
 
-    let service = build_service(&options).await;
+    let service = build_service(&options, host_event_tx.clone()).await;
     let app = build_router(
         service,
         host_event_tx.clone(),
@@ -232,9 +246,9 @@ fn parse_bind_ip(bind: &str) -> IpAddr {
     }
 }
 
-async fn build_service(options: &Options) -> Arc<dyn SandboxService> {
+async fn build_service(options: &Options, host_events: HostEventSender) -> Arc<dyn SandboxService> {
     match BubblewrapService::new(options.data_dir.clone(), options.port).await {
-        Ok(service) => Arc::new(service),
+        Ok(service) => Arc::new(service.with_host_events(host_events)),
         Err(error) => {
             tracing::error!(
                 ?error,
@@ -676,4 +690,31 @@ impl SandboxService for UnavailableSandboxService {
     ) -> SandboxResult<cmux_sandbox::models::AwaitReadyResponse> {
         Err(self.error("await services ready"))
     }
+
+    async fn configure_vscode(
+        &self,
+        _id: String,
+        _request: cmux_sandbox::models::VscodeConfigRequest,
+    ) -> SandboxResult<cmux_sandbox::models::VscodeConfigResponse> {
+        Err(self.error("configure vscode"))
+    }
+
+    async fn metrics(&self) -> SandboxResult<cmux_sandbox::metrics::MetricsSnapshot> {
+        Err(self.error("metrics"))
+    }
+
+    async fn services(
+        &self,
+        _id: String,
+    ) -> SandboxResult<Arc<cmux_sandbox::supervisor::Supervisor>> {
+        Err(self.error("services"))
+    }
+
+    async fn apply_manifest(
+        &self,
+        _id: String,
+        _manifest: cmux_sandbox::models::BootManifest,
+    ) -> SandboxResult<cmux_sandbox::models::BootReport> {
+        Err(self.error("apply manifest"))
+    }
 }
@@ -0,0 +1,323 @@
+//! Boot profile applied from a `sandbox.toml` manifest.
+//!
+//! A manifest declares the env to load into cmux-env, ports to route through
+//! cmux-proxy, services for the [`Supervisor`] and the default ACP provider.
+//! Steps are applied independently: a failure is recorded in the
+//! [`BootReport`] and the remaining steps still run.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Stdio;
+
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use crate::acp_client::AcpProvider;
+use crate::errors::{SandboxError, SandboxResult};
+use crate::models::{
+    BootManifest, BootReport, EnvVar, ExposedPort, HealthProbe, ProviderDefaults, RestartPolicy,
+    ServiceDefinition,
+};
+use crate::supervisor::Supervisor;
+
+/// Manifest file name looked up at the workspace root.
+pub const MANIFEST_FILE: &str = "sandbox.toml";
+
+const ENVCTL_PATH: &str = "/usr/local/bin/envctl";
+const PROXY_PATH: &str = "/usr/local/bin/cmux-proxy";
+pub(crate) const PROXY_PORT: u16 = 39379;
+/// Service name reserved for the manifest's cmux-proxy instance.
+pub const PROXY_SERVICE: &str = "cmux-proxy";
+
+/// Where a manifest is applied.
+pub struct BootTarget<'a> {
+    pub services: &'a Supervisor,
+    /// Workspace path as seen inside the sandbox; the default service workdir
+    pub workspace: &'a str,
+    /// Host path of the sandbox user's home directory
+    pub home: &'a Path,
+}
+
+pub fn parse_manifest(text: &str) -> SandboxResult<BootManifest> {
+    let manifest: BootManifest = toml::from_str(text).map_err(|error| {
+        SandboxError::InvalidRequest(format!("invalid {MANIFEST_FILE}: {error}"))
+    })?;
+    validate(&manifest)?;
+    Ok(manifest)
+}
+
+/// Read `sandbox.toml` from a workspace directory on the host, if present.
+pub async fn load_manifest(workspace: &Path) -> SandboxResult<Option<BootManifest>> {
+    match tokio::fs::read_to_string(workspace.join(MANIFEST_FILE)).await {
+        Ok(text) => parse_manifest(&text).map(Some),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub fn validate(manifest: &BootManifest) -> SandboxResult<()> {
+    let mut names = HashSet::new();
+    for service in &manifest.services {
+        if service.name == PROXY_SERVICE && !manifest.ports.is_empty() {
+            return Err(SandboxError::InvalidRequest(format!(
+                "service name '{PROXY_SERVICE}' is reserved when ports are declared"
+            )));
+        }
+        if !names.insert(service.name.as_str()) {
+            return Err(SandboxError::InvalidRequest(format!(
+                "service '{}' is declared more than once",
+                service.name
+            )));
+        }
+    }
+    if let Some(port) = manifest
+        .ports
+        .iter()
+        .find(|p| p.port == 0 || p.replicas.contains(&0))
+    {
+        return Err(SandboxError::InvalidRequest(format!(
+            "invalid port entry for {}: ports must be non-zero",
+            port.port
+        )));
+    }
+    if let Some(provider) = &manifest.provider {
+        if AcpProvider::from_short_name(&provider.id).is_none() {
+            return Err(SandboxError::InvalidRequest(format!(
+                "unknown provider '{}'",
+                provider.id
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// `--replicas` value for cmux-proxy, e.g. `3000=3000,3001;8080=8081`.
+fn replicas_arg(ports: &[ExposedPort]) -> String {
+    ports
+        .iter()
+        .filter(|p| !p.replicas.is_empty())
+        .map(|p| {
+            let upstreams: Vec<String> = p.replicas.iter().map(u16::to_string).collect();
+            format!("{}={}", p.port, upstreams.join(","))
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// `--rewrite` value for cmux-proxy: opted-in ports rewrite the origins of
+/// every declared port, e.g. `3000=3000,8080`.
+fn rewrite_arg(ports: &[ExposedPort]) -> String {
+    let all: Vec<String> = ports.iter().map(|p| p.port.to_string()).collect();
+    ports
+        .iter()
+        .filter(|p| p.rewrite_urls)
+        .map(|p| format!("{}={}", p.port, all.join(",")))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Files in the sandbox home that make the ACP client default to `defaults`.
+fn provider_home_files(defaults: &ProviderDefaults) -> Vec<(String, String)> {
+    let mut files = vec![(".cmux/last_acp_provider".to_string(), defaults.id.clone())];
+    if let Some(model) = &defaults.model {
+        files.push((format!(".cmux/last_model_{}", defaults.id), model.clone()));
+    }
+    files
+}
+
+async fn run_in_sandbox(launcher: &[String], command: &[String]) -> Result<(), String> {
+    let mut argv = launcher.to_vec();
+    argv.extend_from_slice(command);
+    let output = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|error| format!("{}: {error}", command.join(" ")))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{}: {}",
+            command.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+pub async fn apply(manifest: &BootManifest, target: &BootTarget<'_>) -> BootReport {
+    let mut report = BootReport::default();
+    let launcher = target.services.launcher();
+
+    for (key, value) in &manifest.env {
+        let command = vec![
+            ENVCTL_PATH.to_string(),
+            "set".to_string(),
+            format!("{key}={value}"),
+        ];
+        match run_in_sandbox(launcher, &command).await {
+            Ok(()) => report.env_vars += 1,
+            Err(error) => report.errors.push(format!("env {key}: {error}")),
+        }
+    }
+    for file in &manifest.env_files {
+        let path = if file.starts_with('/') {
+            file.clone()
+        } else {
+            format!("{}/{}", target.workspace, file)
+        };
+        let command = vec![ENVCTL_PATH.to_string(), "load".to_string(), path];
+        match run_in_sandbox(launcher, &command).await {
+            Ok(()) => report.env_files.push(file.clone()),
+            Err(error) => report.errors.push(format!("env file {file}: {error}")),
+        }
+    }
+
+    if !manifest.ports.is_empty() {
+        let proxy = ServiceDefinition {
+            name: PROXY_SERVICE.to_string(),
+            command: format!(
+                "exec {PROXY_PATH} --replicas '{}' --rewrite '{}'",
+                replicas_arg(&manifest.ports),
+                rewrite_arg(&manifest.ports)
+            ),
+            env: Vec::new(),
+            workdir: None,
+            restart: RestartPolicy::Always,
+            health: Some(HealthProbe::Tcp { port: PROXY_PORT }),
+        };
+        match target.services.define(proxy).await {
+            Ok(_) => report.exposed_ports = manifest.ports.iter().map(|p| p.port).collect(),
+            Err(error) => report.errors.push(format!("ports: {error}")),
+        }
+    }
+
+    let shared_env: Vec<EnvVar> = manifest
+        .env
+        .iter()
+        .map(|(key, value)| EnvVar {
+            key: key.clone(),
+            value: value.clone(),
+        })
+        .collect();
+    for service in &manifest.services {
+        let mut definition = service.clone();
+        // Service-specific values come last so they win
+        definition.env = shared_env.iter().chain(&service.env).cloned().collect();
+        if definition.workdir.is_none() {
+            definition.workdir = Some(target.workspace.to_string());
+        }
+        match target.services.define(definition).await {
+            Ok(_) => report.services.push(service.name.clone()),
+            Err(error) => report
+                .errors
+                .push(format!("service {}: {error}", service.name)),
+        }
+    }
+
+    if let Some(defaults) = &manifest.provider {
+        let mut written = true;
+        for (relative, content) in provider_home_files(defaults) {
+            let path = target.home.join(relative);
+            let result = match path.parent() {
+                Some(parent) => tokio::fs::create_dir_all(parent).await,
+                None => Ok(()),
+            };
+            if let Err(error) = result.and(tokio::fs::write(&path, content).await) {
+                report
+                    .errors
+                    .push(format!("provider defaults {}: {error}", path.display()));
+                written = false;
+            }
+        }
+        if written {
+            report.provider = Some(defaults.clone());
+        }
+    }
+
+    if report.errors.is_empty() {
+        info!(services = report.services.len(), "boot manifest applied");
+    } else {
+        warn!(errors = ?report.errors, "boot manifest applied with errors");
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ServiceState;
+    use std::net::Ipv4Addr;
+
+    const MANIFEST: &str = r#"
+env_files = [".env.local"]
+
+[env]
+NODE_ENV = "development"
+
+[[services]]
+name = "web"
+command = "echo $NODE_ENV $PORT from $(pwd)"
+restart = "never"
+env = [{ key = "PORT", value = "3000" }]
+
+[[ports]]
+port = 3000
+replicas = [3000, 3001]
+
+[[ports]]
+port = 8080
+rewrite_urls = true
+
+[provider]
+id = "claude"
+model = "opus"
+"#;
+
+    #[test]
+    fn parses_and_validates_manifest() {
+        let manifest = parse_manifest(MANIFEST).unwrap();
+        assert_eq!(manifest.env["NODE_ENV"], "development");
+        assert_eq!(manifest.services[0].restart, RestartPolicy::Never);
+        assert_eq!(replicas_arg(&manifest.ports), "3000=3000,3001");
+        assert_eq!(rewrite_arg(&manifest.ports), "8080=3000,8080");
+
+        let mut bad = manifest.clone();
+        bad.services.push(bad.services[0].clone());
+        assert!(validate(&bad).is_err());
+        assert!(parse_manifest("[provider]\nid = \"nope\"").is_err());
+        assert!(parse_manifest("ports = [{ port = 0 }]").is_err());
+    }
+
+    #[tokio::test]
+    async fn applies_services_and_provider_defaults() {
+        let mut manifest = parse_manifest(MANIFEST).unwrap();
+        // No cmux-proxy binary on the test host
+        manifest.ports.clear();
+        let home = tempfile::tempdir().unwrap();
+        let supervisor = Supervisor::new(Vec::new(), Ipv4Addr::LOCALHOST.into());
+        let target = BootTarget {
+            services: &supervisor,
+            workspace: "/",
+            home: home.path(),
+        };
+
+        let report = apply(&manifest, &target).await;
+        assert_eq!(report.services, vec!["web"]);
+        assert_eq!(report.provider, manifest.provider);
+        assert_eq!(
+            std::fs::read_to_string(home.path().join(".cmux/last_model_claude")).unwrap(),
+            "opus"
+        );
+
+        for _ in 0..100 {
+            if supervisor.status("web").await.unwrap().state == ServiceState::Exited {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let logs = supervisor.logs("web", None).await.unwrap();
+        assert_eq!(logs.lines[0].line, "development 3000 from /");
+    }
+}
@@ -1,23 +1,31 @@
+use crate::bootstrap::{self, BootTarget};
+use crate::egress;
 use crate::errors::{SandboxError, SandboxResult};
 use crate::ip_pool::{IpLease, IpPool};
+use crate::logs::LogStore;
+use crate::metrics;
 use crate::models::{
-    AwaitReadyRequest, AwaitReadyResponse, CreateSandboxRequest, EnvVar, ExecRequest, ExecResponse,
-    HostEvent, MuxClientMessage, MuxServerMessage, PruneRequest, PruneResponse, PrunedItem,
-    PtySessionId, SandboxDisplay, SandboxNetwork, SandboxStatus, SandboxSummary, ServiceReadiness,
+    AwaitReadyRequest, AwaitReadyResponse, BootManifest, BootReport, CreateSandboxRequest, EnvVar,
+    ExecRequest, ExecResponse, HealthProbe, HostEvent, HostOverride, MuxClientMessage,
+    MuxServerMessage, PruneRequest, PruneResponse, PrunedItem, PtySessionId, RestartPolicy,
+    SandboxDisplay, SandboxNetwork, SandboxStatus, SandboxSummary, ServiceDefinition,
+    ServiceReadiness, VscodeConfigRequest, VscodeConfigResponse, VscodeExtensionFailure,
 };
 use crate::mux::terminal::{DaFilter, VirtualTerminal};
-use crate::service::SandboxService;
+use crate::service::{HostEventSender, SandboxService};
+use crate::supervisor::Supervisor;
 use crate::timing::TimingReport;
+use crate::vscode;
 use async_trait::async_trait;
 use axum::body::Body;
-use axum::extract::ws::{Message, WebSocket};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
 use chrono::{DateTime, Utc};
 use futures::{SinkExt, StreamExt};
 use portable_pty::{CommandBuilder, MasterPty, NativePtySystem, PtySize, PtySystem};
 use serde::Deserialize;
 use std::collections::{BTreeMap, HashMap};
 use std::io::{Read, Write};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -48,6 +56,8 @@ struct PtySessionHandle {
     child: Box<dyn portable_pty::Child + Send + Sync>,
     /// Child process ID for signal forwarding
     child_pid: Option<u32>,
+    /// Keeps the PTY session gauge accurate until the handle is dropped
+    _metrics: metrics::PtySessionGuard,
 }
 
 #[derive(Deserialize)]
@@ -77,6 +87,7 @@ struct SandboxEntry {
     child: Arc<Mutex<Child>>,
     inner_pid: u32,
     env: Vec<EnvVar>,
+    services: Arc<Supervisor>,
 }
 
 #[derive(Clone)]
@@ -117,6 +128,8 @@ pub struct BubblewrapService {
     bubblewrap_path: String,
     ip_path: String,
     iptables_path: String,
+    /// Needed only to enforce network policies, which cover IPv6 too.
+    ip6tables_path: Option<String>,
     nsenter_path: String,
     port: u16,
     next_index: AtomicUsize,
@@ -124,6 +137,8 @@ pub struct BubblewrapService {
     /// Service readiness tracking per sandbox.
     /// Uses watch channels so multiple waiters can subscribe efficiently.
     readiness: Mutex<HashMap<Uuid, ReadinessWatch>>,
+    /// Host event channel used to surface egress policy violations.
+    host_events: Option<HostEventSender>,
 }
 
 fn nsenter_args(pid: u32, workdir: Option<&str>, command: &[String]) -> Vec<String> {
@@ -279,82 +294,66 @@ async fn start_x11_stack_background(
     Ok(())
 }
 
-/// Start cmux-code (VS Code server) in background.
-/// This is a non-blocking function that starts the VS Code web server inside a sandbox.
+/// Check that a supervised process matching `pattern` is running in the sandbox.
+async fn verify_process_running(nsenter_path: &str, inner_pid: u32, pattern: &str) -> bool {
+    let verify_cmd = vec![
+        "/bin/sh".to_string(),
+        "-c".to_string(),
+        format!("pgrep -f '{}'", pattern),
+    ];
+    let verify_result = tokio::time::timeout(
+        Duration::from_secs(5),
+        Command::new(nsenter_path)
+            .args(nsenter_args(inner_pid, None, &verify_cmd))
+            .output(),
+    )
+    .await;
+    matches!(verify_result, Ok(Ok(ref output)) if output.status.success())
+}
+
+/// Start cmux-code (VS Code server) under the sandbox supervisor.
+/// Redefining the service replaces (and stops) any running instance.
 async fn start_vscode_background(
     nsenter_path: &str,
     inner_pid: u32,
+    services: &Supervisor,
     vscode_port: u16,
     workspace_path: &str,
 ) -> Result<(), String> {
-    use tokio::time::timeout;
-    let cmd_timeout = Duration::from_secs(10);
-
-    // Start cmux-code server
     // --host 0.0.0.0: Listen on all interfaces (needed for proxy access)
     // --port: The port to listen on
     // --without-connection-token: Disable auth (sandbox is already isolated)
     // --disable-workspace-trust: Don't prompt for trust
     // --disable-telemetry: No telemetry
-    // Use nohup to prevent SIGHUP when nsenter shell exits
-    let vscode_cmd = vec![
-        "/bin/sh".to_string(),
-        "-c".to_string(),
-        format!(
+    let definition = ServiceDefinition {
+        name: "cmux-code".to_string(),
+        command: format!(
             concat!(
-                "nohup /app/cmux-code/bin/code-server-oss ",
+                "exec /app/cmux-code/bin/code-server-oss ",
                 "--host 0.0.0.0 --port {} ",
                 "--without-connection-token ",
                 "--disable-workspace-trust ",
                 "--disable-telemetry ",
                 "--telemetry-level off ",
-                "{} ",
-                "> /tmp/cmux-code.log 2>&1 &"
+                "{}"
             ),
             vscode_port, workspace_path
         ),
-    ];
-
-    let result = timeout(
-        cmd_timeout,
-        Command::new(nsenter_path)
-            .args(nsenter_args(inner_pid, None, &vscode_cmd))
-            .output(),
-    )
-    .await;
-
-    match result {
-        Ok(Ok(output)) => {
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                debug!("cmux-code start warning: {}", stderr);
-            }
-        }
-        Ok(Err(e)) => return Err(format!("cmux-code command error: {}", e)),
-        Err(_) => {
-            debug!("cmux-code command timed out (expected for backgrounded process)");
-        }
-    }
+        env: Vec::new(),
+        workdir: None,
+        restart: RestartPolicy::OnFailure,
+        health: Some(HealthProbe::Tcp { port: vscode_port }),
+    };
+    services
+        .define(definition)
+        .await
+        .map_err(|e| format!("cmux-code service error: {}", e))?;
 
     // Wait for cmux-code to start listening
     sleep(Duration::from_millis(500)).await;
 
-    // Verify cmux-code is running by checking if it's listening on the port
-    let verify_cmd = vec![
-        "/bin/sh".to_string(),
-        "-c".to_string(),
-        format!("pgrep -f 'code-server-oss.*--port {}'", vscode_port),
-    ];
-    let verify_result = timeout(
-        Duration::from_secs(5),
-        Command::new(nsenter_path)
-            .args(nsenter_args(inner_pid, None, &verify_cmd))
-            .output(),
-    )
-    .await;
-
-    let vscode_running = matches!(verify_result, Ok(Ok(ref output)) if output.status.success());
-    if !vscode_running {
+    let pattern = format!("code-server-oss.*--port {}", vscode_port);
+    if !verify_process_running(nsenter_path, inner_pid, &pattern).await {
         return Err(format!("cmux-code failed to start on port {}", vscode_port));
     }
 
@@ -366,69 +365,41 @@ async fn start_vscode_background(
     Ok(())
 }
 
-/// Start cmux-pty server inside the sandbox (background process).
+/// Where cmux-pty saves its sessions inside the sandbox.
+const CMUX_PTY_STATE_DIR: &str = "/tmp/cmux-pty-state";
+
+/// Start cmux-pty server under the sandbox supervisor.
 /// This is the unified PTY server that handles terminal sessions.
 async fn start_cmux_pty_background(
     nsenter_path: &str,
     inner_pid: u32,
+    services: &Supervisor,
     pty_port: u16,
 ) -> Result<(), String> {
-    use tokio::time::timeout;
-    let cmd_timeout = Duration::from_secs(10);
-
-    // Start cmux-pty server
     // --host 0.0.0.0: Listen on all interfaces (needed for proxy access)
     // --port: The port to listen on
-    // Use nohup to prevent SIGHUP when nsenter shell exits
-    let pty_cmd = vec![
-        "/bin/sh".to_string(),
-        "-c".to_string(),
-        format!(
-            "nohup /usr/local/bin/cmux-pty server --host 0.0.0.0 --port {} > /tmp/cmux-pty.log 2>&1 &",
-            pty_port
+    // --state-dir: Recreate the session layout after the supervisor restarts it
+    let definition = ServiceDefinition {
+        name: "cmux-pty".to_string(),
+        command: format!(
+            "exec /usr/local/bin/cmux-pty server --host 0.0.0.0 --port {} --state-dir {}",
+            pty_port, CMUX_PTY_STATE_DIR
         ),
-    ];
-
-    let result = timeout(
-        cmd_timeout,
-        Command::new(nsenter_path)
-            .args(nsenter_args(inner_pid, None, &pty_cmd))
-            .output(),
-    )
-    .await;
-
-    match result {
-        Ok(Ok(output)) => {
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                debug!("cmux-pty start warning: {}", stderr);
-            }
-        }
-        Ok(Err(e)) => return Err(format!("cmux-pty command error: {}", e)),
-        Err(_) => {
-            debug!("cmux-pty command timed out (expected for backgrounded process)");
-        }
-    }
+        env: Vec::new(),
+        workdir: None,
+        restart: RestartPolicy::Always,
+        health: Some(HealthProbe::Tcp { port: pty_port }),
+    };
+    services
+        .define(definition)
+        .await
+        .map_err(|e| format!("cmux-pty service error: {}", e))?;
 
     // Wait for cmux-pty to start listening
     sleep(Duration::from_millis(300)).await;
 
-    // Verify cmux-pty is running by checking if it's listening on the port
-    let verify_cmd = vec![
-        "/bin/sh".to_string(),
-        "-c".to_string(),
-        format!("pgrep -f 'cmux-pty.*--port {}'", pty_port),
-    ];
-    let verify_result = timeout(
-        Duration::from_secs(5),
-        Command::new(nsenter_path)
-            .args(nsenter_args(inner_pid, None, &verify_cmd))
-            .output(),
-    )
-    .await;
-
-    let pty_running = matches!(verify_result, Ok(Ok(ref output)) if output.status.success());
-    if !pty_running {
+    let pattern = format!("cmux-pty.*--port {}", pty_port);
+    if !verify_process_running(nsenter_path, inner_pid, &pattern).await {
         return Err(format!("cmux-pty failed to start on port {}", pty_port));
     }
 
@@ -445,6 +416,7 @@ impl BubblewrapService {
         let bubblewrap_path = find_binary("bwrap")?;
         let ip_path = find_binary("ip")?;
         let iptables_path = find_binary("iptables")?;
+        let ip6tables_path = find_binary("ip6tables").ok();
         let nsenter_path = find_binary("nsenter")?;
         let docker = DockerConfig::from_env()?;
 
@@ -455,17 +427,97 @@ impl BubblewrapService {
             bubblewrap_path,
             ip_path,
             iptables_path,
+            ip6tables_path,
             nsenter_path,
             port,
             next_index: AtomicUsize::new(0),
             docker,
             readiness: Mutex::new(HashMap::new()),
+            host_events: None,
         };
 
         service.setup_host_network().await?;
         Ok(service)
     }
 
+    /// Broadcast sandbox-originated events (e.g. egress violations) to mux clients.
+    pub fn with_host_events(mut self, host_events: HostEventSender) -> Self {
+        self.host_events = Some(host_events);
+        self
+    }
+
+    /// Write a file under the sandbox's `/root` (backed by `system/root-merged/root` on the host).
+    async fn write_sandbox_home_file(
+        &self,
+        id: &Uuid,
+        relative: &str,
+        content: &str,
+    ) -> SandboxResult<()> {
+        let path = self
+            .workspace_root
+            .join(id.to_string())
+            .join("system/root-merged/root")
+            .join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, content).await?;
+        Ok(())
+    }
+
+    /// Install the egress allowlist on the host for traffic from the
+    /// sandbox's veth, for both address families, and start watching for
+    /// violations. A partial install is removed before returning an error.
+    async fn apply_network_policy(
+        &self,
+        id: &Uuid,
+        host_if: &str,
+        targets: &[egress::EgressTarget],
+    ) -> SandboxResult<()> {
+        let ip6tables_path = self
+            .ip6tables_path
+            .clone()
+            .ok_or_else(|| SandboxError::MissingBinary("ip6tables".to_owned()))?;
+        let allowed = egress::resolve_targets(targets).await;
+        let resolvers = egress::nameservers(&sandbox_resolv_conf().await);
+        let installs = [
+            (
+                &self.iptables_path,
+                egress::build_rules(host_if, &resolvers.v4, &allowed.v4),
+            ),
+            (
+                &ip6tables_path,
+                egress::build_rules(host_if, &resolvers.v6, &allowed.v6),
+            ),
+        ];
+        for (binary, rules) in &installs {
+            if let Err(error) = egress::apply_rules(binary, rules).await {
+                self.remove_network_policy(host_if).await;
+                return Err(error);
+            }
+        }
+        info!(sandbox_id = %id, allowed = allowed.len(), "egress policy applied");
+
+        if let Some(host_events) = &self.host_events {
+            egress::spawn_violation_monitor(
+                *id,
+                host_if.to_string(),
+                self.iptables_path.clone(),
+                ip6tables_path,
+                host_events.clone(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Drop a sandbox's egress chains, if it has any.
+    async fn remove_network_policy(&self, host_if: &str) {
+        egress::remove_rules(&self.iptables_path, host_if).await;
+        if let Some(ip6tables_path) = &self.ip6tables_path {
+            egress::remove_rules(ip6tables_path, host_if).await;
+        }
+    }
+
     async fn setup_host_network(&self) -> SandboxResult<()> {
         // Enable IP forwarding
         if let Err(e) = run_command("sysctl", &["-w", "net.ipv4.ip_forward=1"]).await {
@@ -587,33 +639,18 @@ impl BubblewrapService {
 
     async fn setup_dns(&self, etc_merged: &Path) -> SandboxResult<()> {
         let resolv_conf_path = etc_merged.join("resolv.conf");
-
-        // Read host's resolv.conf (following symlinks/mounts)
-        let content = match fs::read_to_string("/etc/resolv.conf").await {
-            Ok(c) => c,
-            Err(_) => "nameserver 8.8.8.8\nnameserver 1.1.1.1".to_string(),
-        };
-
-        // Filter out local resolvers that won't work in sandbox
-        let filtered_lines: Vec<&str> = content
-            .lines()
-            .filter(|l| !l.contains("127.0.0.53") && !l.contains("127.0.0.1"))
-            .collect();
-
-        let final_content = if filtered_lines.is_empty() {
-            "nameserver 8.8.8.8\nnameserver 1.1.1.1".to_string()
-        } else {
-            filtered_lines.join("\n")
-        };
-
-        fs::write(&resolv_conf_path, final_content).await?;
+        fs::write(&resolv_conf_path, sandbox_resolv_conf().await).await?;
         Ok(())
     }
 
-    async fn setup_hosts(&self, etc_merged: &Path, hostname: &str) -> SandboxResult<()> {
+    async fn setup_hosts(
+        &self,
+        etc_merged: &Path,
+        hostname: &str,
+        overrides: &[HostOverride],
+    ) -> SandboxResult<()> {
         let hosts_path = etc_merged.join("hosts");
-        let content = format!("127.0.0.1\tlocalhost\n127.0.0.1\t{}\n", hostname);
-        fs::write(&hosts_path, content).await?;
+        fs::write(&hosts_path, render_hosts_file(hostname, overrides)).await?;
         Ok(())
     }
 
@@ -859,7 +896,7 @@ fi
             self.setup_agent_configs(&root_merged),
             // etc overlay setups
             self.setup_dns(&etc_merged),
-            self.setup_hosts(&etc_merged, &hostname),
+            self.setup_hosts(&etc_merged, &hostname, &request.hosts),
             self.setup_apt(&etc_merged),
             // Docker socket can be ensured in parallel too
             self.ensure_docker_socket(),
@@ -930,11 +967,15 @@ fi
         let root_overlay = path_to_string(&root_overlay, "root overlay")?;
         command.args(["--bind", &root_overlay, "/root"]);
 
-        // Mount VS Code extensions from Docker image (read-only for efficiency)
-        // This overlays the extensions dir on top of the writable /root
-        let vscode_extensions_path = "/root/.vscode-server-oss/extensions";
+        // Mount VS Code extensions from Docker image via a per-sandbox overlay
+        // on top of the writable /root, so extensions can be installed at runtime
+        // without copying the image's extensions or touching them.
+        let vscode_extensions_path = vscode::EXTENSIONS_DIR;
         if Path::new(vscode_extensions_path).exists() {
-            command.args(["--ro-bind", vscode_extensions_path, vscode_extensions_path]);
+            let extensions_merged =
+                mount_overlay(system_dir, "vscode-extensions", vscode_extensions_path).await?;
+            let extensions_overlay = path_to_string(&extensions_merged, "extensions overlay")?;
+            command.args(["--bind", &extensions_overlay, vscode_extensions_path]);
         }
 
         // Make common paths available inside the sandbox
@@ -1085,6 +1126,7 @@ fi
     }
 
     async fn teardown_network(&self, network: &SandboxNetwork) {
+        self.remove_network_policy(&network.host_interface).await;
         let delete_result =
             run_command(&self.ip_path, &["link", "del", &network.host_interface]).await;
         if let Err(error) = delete_result {
@@ -1255,6 +1297,7 @@ fi
             master: pair.master,
             child,
             child_pid,
+            _metrics: metrics::PtySessionGuard::new(),
         })
     }
 }
@@ -1288,6 +1331,38 @@ fn path_to_string(path: &Path, context: &str) -> SandboxResult<String> {
     })
 }
 
+/// Close reason for an attached process: `exit <code>` or `signal <n>`.
+fn exit_close_reason(status: std::process::ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exit {code}"),
+        (None, Some(signal)) => format!("signal {signal}"),
+        (None, None) => "exit unknown".to_string(),
+    }
+}
+
+/// The `resolv.conf` sandboxes get: the host's, minus local resolvers that
+/// won't work from the sandbox's namespace.
+async fn sandbox_resolv_conf() -> String {
+    // Read host's resolv.conf (following symlinks/mounts)
+    let content = match fs::read_to_string("/etc/resolv.conf").await {
+        Ok(c) => c,
+        Err(_) => "nameserver 8.8.8.8\nnameserver 1.1.1.1".to_string(),
+    };
+
+    // Filter out local resolvers that won't work in sandbox
+    let filtered_lines: Vec<&str> = content
+        .lines()
+        .filter(|l| !l.contains("127.0.0.53") && !l.contains("127.0.0.1"))
+        .collect();
+
+    if filtered_lines.is_empty() {
+        "nameserver 8.8.8.8\nnameserver 1.1.1.1".to_string()
+    } else {
+        filtered_lines.join("\n")
+    }
+}
+
 fn make_interface_names(id: &Uuid) -> (String, String) {
     let mut buffer = Uuid::encode_buffer();
     let encoded = id.as_simple().encode_lower(&mut buffer);
@@ -1306,6 +1381,43 @@ fn normalize_optional_field(value: &Option<String>) -> Option<String> {
         .map(|item| item.to_string())
 }
 
+/// Reject host overrides that would produce a malformed `/etc/hosts`.
+fn validate_host_overrides(overrides: &[HostOverride]) -> SandboxResult<()> {
+    for entry in overrides {
+        let hostname = entry.hostname.trim();
+        if hostname.is_empty()
+            || hostname.starts_with('#')
+            || hostname.chars().any(char::is_whitespace)
+        {
+            return Err(SandboxError::InvalidRequest(format!(
+                "invalid host override hostname: {:?}",
+                entry.hostname
+            )));
+        }
+        if entry.address.trim().parse::<IpAddr>().is_err() {
+            return Err(SandboxError::InvalidRequest(format!(
+                "invalid host override address for {hostname}: {:?}",
+                entry.address
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Build the sandbox's `/etc/hosts`. Overrides come after the defaults so a
+/// request can also repoint `localhost` or the sandbox hostname if needed.
+fn render_hosts_file(hostname: &str, overrides: &[HostOverride]) -> String {
+    let mut content = format!("127.0.0.1\tlocalhost\n127.0.0.1\t{}\n", hostname);
+    for entry in overrides {
+        content.push_str(&format!(
+            "{}\t{}\n",
+            entry.address.trim(),
+            entry.hostname.trim()
+        ));
+    }
+    content
+}
+
 fn build_effective_env(
     request_env: &[EnvVar],
     lease: &IpLease,
@@ -1363,6 +1475,13 @@ fn session_env_with_overrides(
 #[async_trait]
 impl SandboxService for BubblewrapService {
     async fn create(&self, request: CreateSandboxRequest) -> SandboxResult<SandboxSummary> {
+        validate_host_overrides(&request.hosts)?;
+        let egress_targets = request
+            .network_policy
+            .as_ref()
+            .map(egress::parse_policy)
+            .transpose()?;
+
         let id = Uuid::new_v4();
         let mut timing = TimingReport::new("sandbox_create", &id.to_string());
 
@@ -1463,6 +1582,21 @@ impl SandboxService for BubblewrapService {
         };
         timing.record_timer("net_finish", net_finish_timer);
 
+        if let Some(targets) = &egress_targets {
+            let egress_timer = crate::timing::Timer::new("egress_policy");
+            if let Err(error) = self.apply_network_policy(&id, &host_if, targets).await {
+                let _ = child.kill().await;
+                let _ = run_command(&self.ip_path, &["link", "del", &host_if]).await;
+                cleanup_overlays(&system_dir).await;
+                {
+                    let mut pool = self.ip_pool.lock().await;
+                    pool.release(&lease);
+                }
+                return Err(error);
+            }
+            timing.record_timer("egress_policy", egress_timer);
+        }
+
         // Calculate display configuration for isolated X11/VNC desktop and VS Code
         // Display numbers start at 10 to avoid conflicts with system displays (:0, :1, etc.)
         // All sandboxes use fixed ports internally, accessed via subdomain routing:
@@ -1492,13 +1626,29 @@ impl SandboxService for BubblewrapService {
             readiness_map.insert(id, readiness_tx);
         }
 
+        // Long-running services run in the foreground under nsenter so the
+        // supervisor can restart them and collect their output
+        let mut launcher = vec![self.nsenter_path.clone()];
+        launcher.extend(nsenter_args(inner_pid, None, &[]));
+        let probe_host = network
+            .sandbox_ip
+            .parse()
+            .unwrap_or(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+        let services = Arc::new(
+            Supervisor::new(launcher, probe_host)
+                .with_log_store(LogStore::global().clone(), format!("{id}.")),
+        );
+
         // Spawn services startup in background (non-blocking)
         // This allows sandbox creation to return immediately
         {
             let nsenter_path = self.nsenter_path.clone();
+            let services = services.clone();
             let sandbox_id = id;
             let readiness = self.readiness.lock().await.get(&id).cloned();
             let workspace_path = SANDBOX_WORKSPACE_MOUNT;
+            let host_workspace = workspace.clone();
+            let home = system_dir.join("root-merged/root");
 
             tokio::spawn(async move {
                 // Start X11/VNC stack
@@ -1542,7 +1692,7 @@ impl SandboxService for BubblewrapService {
 
                 // Start cmux-pty FIRST (PTY server) - must be ready before VS Code extension activates
                 let pty_result =
-                    start_cmux_pty_background(&nsenter_path, inner_pid, pty_port).await;
+                    start_cmux_pty_background(&nsenter_path, inner_pid, &services, pty_port).await;
 
                 let pty_ready = match pty_result {
                     Ok(()) => {
@@ -1573,9 +1723,14 @@ impl SandboxService for BubblewrapService {
                 }
 
                 // Start cmux-code (VS Code server) AFTER cmux-pty is ready
-                let vscode_result =
-                    start_vscode_background(&nsenter_path, inner_pid, vscode_port, workspace_path)
-                        .await;
+                let vscode_result = start_vscode_background(
+                    &nsenter_path,
+                    inner_pid,
+                    &services,
+                    vscode_port,
+                    workspace_path,
+                )
+                .await;
 
                 let vscode_ready = match vscode_result {
                     Ok(()) => {
@@ -1604,6 +1759,34 @@ impl SandboxService for BubblewrapService {
                         pty: pty_ready,
                     });
                 }
+
+                // Apply the workspace boot profile once the built-in services are up
+                match bootstrap::load_manifest(&host_workspace).await {
+                    Ok(Some(manifest)) => {
+                        let target = BootTarget {
+                            services: &services,
+                            workspace: workspace_path,
+                            home: &home,
+                        };
+                        let report = bootstrap::apply(&manifest, &target).await;
+                        info!(
+                            sandbox_id = %sandbox_id,
+                            services = report.services.len(),
+                            errors = report.errors.len(),
+                            "applied {}",
+                            bootstrap::MANIFEST_FILE
+                        );
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!(
+                            sandbox_id = %sandbox_id,
+                            error = %e,
+                            "failed to load {}",
+                            bootstrap::MANIFEST_FILE
+                        );
+                    }
+                }
             });
         }
 
@@ -1624,6 +1807,7 @@ impl SandboxService for BubblewrapService {
             child: Arc::new(Mutex::new(child)),
             inner_pid,
             env: effective_env,
+            services,
         };
 
         // Phase: finalize
@@ -1738,6 +1922,7 @@ impl SandboxService for BubblewrapService {
         }
         .ok_or(SandboxError::NotFound(id))?;
 
+        let _pty_session = metrics::PtySessionGuard::new();
         let target_command =
             command.unwrap_or_else(|| vec!["/bin/zsh".to_string(), "-i".to_string()]);
         info!(
@@ -1886,6 +2071,7 @@ impl SandboxService for BubblewrapService {
                 }
             });
 
+            let mut output_closed = false;
             loop {
                 tokio::select! {
                     msg = socket.recv() => {
@@ -1911,12 +2097,31 @@ impl SandboxService for BubblewrapService {
                                 info!("Sending to WebSocket: {} bytes", d.len());
                                 if socket.send(Message::Binary(d.into())).await.is_err() { break; }
                             }
-                            None => break, // Channels closed (child exited)
+                            None => {
+                                // Channels closed (child exited)
+                                output_closed = true;
+                                break;
+                            }
                         }
                     }
                 }
             }
 
+            // Tell the client how the process ended so it can explain a turn
+            // that stopped mid-way (e.g. OOM kill vs. crash)
+            if output_closed {
+                if let Ok(Ok(status)) =
+                    tokio::time::timeout(Duration::from_secs(1), child.wait()).await
+                {
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::NORMAL,
+                            reason: exit_close_reason(status).into(),
+                        })))
+                        .await;
+                }
+            }
+
             let _ = child.kill().await;
             return Ok(());
         }
@@ -2031,6 +2236,8 @@ impl SandboxService for BubblewrapService {
                                 break;
                             }
                         }
+                        // `data` is moved by the send, so it can't be a guard
+                        #[allow(clippy::collapsible_match)]
                         Some(Ok(Message::Binary(data))) => {
                             if tx_in.send(data.into()).await.is_err() {
                                 break;
@@ -2252,6 +2459,8 @@ impl SandboxService for BubblewrapService {
                                     read_only_paths: vec![],
                                     tmpfs: vec![],
                                     env,
+                                    hosts: vec![],
+                                    network_policy: None,
                                 })
                                 .await
                             {
@@ -2572,6 +2781,9 @@ impl SandboxService for BubblewrapService {
         }
 
         if let Some(entry) = entry {
+            entry.services.stop_all().await;
+            LogStore::global().remove_prefix(&format!("{id}."));
+
             {
                 let mut pool = self.ip_pool.lock().await;
                 pool.release(&entry.handle.lease);
@@ -2895,6 +3107,154 @@ impl SandboxService for BubblewrapService {
             }
         }
     }
+
+    async fn configure_vscode(
+        &self,
+        id_str: String,
+        request: VscodeConfigRequest,
+    ) -> SandboxResult<VscodeConfigResponse> {
+        for extension in &request.extensions {
+            vscode::validate_extension(extension)?;
+        }
+
+        let id = self.resolve_id(&id_str).await?;
+        let (inner_pid, services) = {
+            let sandboxes = self.sandboxes.lock().await;
+            sandboxes
+                .get(&id)
+                .map(|entry| (entry.inner_pid, entry.services.clone()))
+                .ok_or(SandboxError::NotFound(id))?
+        };
+
+        let mut response = VscodeConfigResponse::default();
+
+        if let Some(settings) = &request.settings {
+            let home = self
+                .workspace_root
+                .join(id.to_string())
+                .join("system/root-merged/root");
+            for file in vscode::SETTINGS_FILES {
+                let relative = format!("{}/{file}", vscode::USER_DATA_DIR);
+                let existing = fs::read_to_string(home.join(&relative)).await.ok();
+                let merged = vscode::merge_settings(existing.as_deref(), settings)?;
+                self.write_sandbox_home_file(&id, &relative, &merged)
+                    .await?;
+            }
+            response.settings_updated = true;
+        }
+
+        if let Some(keybindings) = &request.keybindings {
+            let content = serde_json::to_string_pretty(keybindings).map_err(|error| {
+                SandboxError::InvalidRequest(format!("invalid keybindings: {error}"))
+            })?;
+            let relative = format!("{}/{}", vscode::USER_DATA_DIR, vscode::KEYBINDINGS_FILE);
+            self.write_sandbox_home_file(&id, &relative, &content)
+                .await?;
+            response.keybindings_updated = true;
+        }
+
+        for extension in &request.extensions {
+            let command = vscode::install_extension_command(extension);
+            let output = Command::new(&self.nsenter_path)
+                .args(nsenter_args(inner_pid, None, &command))
+                .output()
+                .await?;
+            if output.status.success() {
+                response.installed.push(extension.clone());
+            } else {
+                let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                warn!(sandbox_id = %id, %extension, %message, "extension install failed");
+                response.failed.push(VscodeExtensionFailure {
+                    extension: extension.clone(),
+                    message,
+                });
+            }
+        }
+
+        let restart = request.restart.unwrap_or(!response.installed.is_empty());
+        if restart {
+            let vscode_port = 39378_u16;
+            start_vscode_background(
+                &self.nsenter_path,
+                inner_pid,
+                &services,
+                vscode_port,
+                SANDBOX_WORKSPACE_MOUNT,
+            )
+            .await
+            .map_err(SandboxError::Internal)?;
+            response.restarted = true;
+        }
+
+        info!(
+            sandbox_id = %id,
+            installed = response.installed.len(),
+            failed = response.failed.len(),
+            restarted = response.restarted,
+            "applied vscode configuration"
+        );
+        Ok(response)
+    }
+
+    async fn apply_manifest(
+        &self,
+        id_str: String,
+        manifest: BootManifest,
+    ) -> SandboxResult<BootReport> {
+        bootstrap::validate(&manifest)?;
+        let id = self.resolve_id(&id_str).await?;
+        let services = self.services(id.to_string()).await?;
+        let home = self
+            .workspace_root
+            .join(id.to_string())
+            .join("system/root-merged/root");
+        let target = BootTarget {
+            services: &services,
+            workspace: SANDBOX_WORKSPACE_MOUNT,
+            home: &home,
+        };
+        Ok(bootstrap::apply(&manifest, &target).await)
+    }
+
+    async fn services(&self, id_str: String) -> SandboxResult<Arc<Supervisor>> {
+        let id = self.resolve_id(&id_str).await?;
+        let sandboxes = self.sandboxes.lock().await;
+        sandboxes
+            .get(&id)
+            .map(|entry| entry.services.clone())
+            .ok_or(SandboxError::NotFound(id))
+    }
+
+    async fn metrics(&self) -> SandboxResult<metrics::MetricsSnapshot> {
+        let mut snapshot = metrics::MetricsSnapshot::sample(&self.workspace_root);
+        let entries: Vec<SandboxEntry> = {
+            let guard = self.sandboxes.lock().await;
+            guard.values().cloned().collect()
+        };
+        snapshot.sandboxes_total = entries.len();
+        let mut running = Vec::new();
+        for entry in &entries {
+            let mut child = entry.child.lock().await;
+            running.push(matches!(child.try_wait(), Ok(None)));
+        }
+        snapshot.sandboxes_running = running.iter().filter(|&&r| r).count();
+        snapshot.sandboxes = futures::future::join_all(entries.iter().zip(running).map(
+            |(entry, running)| async move {
+                let activity = if running {
+                    metrics::sample_sandbox_activity(&entry.handle.network.sandbox_ip).await
+                } else {
+                    None
+                };
+                metrics::SandboxMetrics {
+                    id: entry.handle.id.to_string(),
+                    running,
+                    activity,
+                }
+            },
+        ))
+        .await;
+        Ok(snapshot)
+    }
 }
 
 impl SandboxHandle {
@@ -2961,6 +3321,14 @@ async fn mount_overlay(system_dir: &Path, name: &str, lower: &str) -> SandboxRes
 }
 
 async fn cleanup_overlays(system_dir: &Path) {
+    let _ = run_command(
+        "umount",
+        &[system_dir
+            .join("vscode-extensions-merged")
+            .to_string_lossy()
+            .as_ref()],
+    )
+    .await;
     let _ = run_command(
         "umount",
         &[system_dir.join("var-merged").to_string_lossy().as_ref()],
@@ -3023,6 +3391,19 @@ mod tests {
         assert!(ns_if.len() <= 15);
     }
 
+    #[test]
+    fn exit_close_reason_reports_code_or_signal() {
+        use std::os::unix::process::ExitStatusExt;
+        assert_eq!(
+            exit_close_reason(std::process::ExitStatus::from_raw(3 << 8)),
+            "exit 3"
+        );
+        assert_eq!(
+            exit_close_reason(std::process::ExitStatus::from_raw(9)),
+            "signal 9"
+        );
+    }
+
     #[test]
     fn nsenter_args_defaults() {
         let args = nsenter_args(123, None, &["ls".to_string()]);
@@ -3078,4 +3459,45 @@ mod tests {
         assert_eq!(map.get("CMUX_TAB_ID"), Some(&"new-tab".to_string()));
         assert_eq!(map.get("CMUX_PANE_ID"), Some(&"pane-1".to_string()));
     }
+
+    #[test]
+    fn hosts_file_appends_overrides() {
+        let overrides = vec![
+            HostOverride {
+                hostname: "api.anthropic.com".to_string(),
+                address: "10.201.0.1".to_string(),
+            },
+            HostOverride {
+                hostname: " convex.internal ".to_string(),
+                address: "127.0.0.1".to_string(),
+            },
+        ];
+
+        let content = render_hosts_file("sandbox-3", &overrides);
+        assert_eq!(
+            content,
+            "127.0.0.1\tlocalhost\n127.0.0.1\tsandbox-3\n10.201.0.1\tapi.anthropic.com\n127.0.0.1\tconvex.internal\n"
+        );
+    }
+
+    #[test]
+    fn host_overrides_reject_bad_entries() {
+        let bad_address = vec![HostOverride {
+            hostname: "api.anthropic.com".to_string(),
+            address: "not-an-ip".to_string(),
+        }];
+        assert!(validate_host_overrides(&bad_address).is_err());
+
+        let bad_hostname = vec![HostOverride {
+            hostname: "two names".to_string(),
+            address: "127.0.0.1".to_string(),
+        }];
+        assert!(validate_host_overrides(&bad_hostname).is_err());
+
+        let ipv6 = vec![HostOverride {
+            hostname: "api.openai.com".to_string(),
+            address: "::1".to_string(),
+        }];
+        assert!(validate_host_overrides(&ipv6).is_ok());
+    }
 }
@@ -0,0 +1,413 @@
+//! Egress network policy enforcement for sandboxes.
+//!
+//! A policy is an allowlist of domains and IPv4/IPv6 CIDRs. Domains are resolved
+//! once when the sandbox is created. The rules live on the host, in a per-sandbox
+//! chain jumped to from FORWARD for traffic arriving on the sandbox's host-side
+//! veth, so root inside the sandbox can't flush them. Each address family gets
+//! the same chain (iptables and ip6tables): established flows, DNS to the
+//! sandbox's configured resolvers and the allowlist are accepted, everything
+//! else is logged and rejected. Traffic to cmux-sandboxd on the veth's host
+//! address goes through INPUT and is unaffected. A background monitor polls
+//! the REJECT counters and reports new violations as host notifications.
+
+use crate::errors::{SandboxError, SandboxResult};
+use crate::models::{HostEvent, NetworkPolicy, NotificationLevel, NotificationRequest};
+use crate::service::HostEventSender;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Prefix used for kernel log lines emitted by the LOG rule.
+pub const EGRESS_LOG_PREFIX: &str = "cmux-egress-deny ";
+
+/// How often the violation monitor samples the REJECT counters.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A single validated allowlist entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EgressTarget {
+    /// Network in `addr/len` form (bare addresses become `/32` or `/128`).
+    Cidr(IpAddr, u8),
+    /// Hostname resolved to addresses at sandbox creation.
+    Domain(String),
+}
+
+impl EgressTarget {
+    fn parse(raw: &str) -> SandboxResult<Self> {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return Err(SandboxError::InvalidRequest(
+                "network policy entries cannot be empty".into(),
+            ));
+        }
+
+        let (addr, prefix) = match raw.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (raw, None),
+        };
+
+        match addr.parse::<IpAddr>() {
+            Ok(ip) => {
+                let max = if ip.is_ipv4() { 32 } else { 128 };
+                let len = match prefix {
+                    Some(prefix) => prefix
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|len| *len <= max)
+                        .ok_or_else(|| {
+                            SandboxError::InvalidRequest(format!(
+                                "invalid CIDR prefix in network policy: {raw}"
+                            ))
+                        })?,
+                    None => max,
+                };
+                Ok(Self::Cidr(ip, len))
+            }
+            Err(_) if prefix.is_none() && is_valid_domain(raw) => {
+                Ok(Self::Domain(raw.to_ascii_lowercase()))
+            }
+            Err(_) => Err(SandboxError::InvalidRequest(format!(
+                "network policy entry is neither a domain nor a CIDR: {raw}"
+            ))),
+        }
+    }
+}
+
+fn is_valid_domain(raw: &str) -> bool {
+    raw.len() <= 253
+        && raw.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Validate every entry of a policy without resolving domains.
+pub fn parse_policy(policy: &NetworkPolicy) -> SandboxResult<Vec<EgressTarget>> {
+    policy
+        .allow
+        .iter()
+        .map(|entry| EgressTarget::parse(entry))
+        .collect()
+}
+
+/// Allowed networks per address family, as iptables `-d` arguments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllowedNets {
+    pub v4: Vec<String>,
+    pub v6: Vec<String>,
+}
+
+impl AllowedNets {
+    fn from_cidrs(cidrs: impl IntoIterator<Item = (IpAddr, u8)>) -> Self {
+        let (mut v4, mut v6) = (BTreeSet::new(), BTreeSet::new());
+        for (ip, len) in cidrs {
+            match ip {
+                IpAddr::V4(_) => v4.insert(format!("{ip}/{len}")),
+                IpAddr::V6(_) => v6.insert(format!("{ip}/{len}")),
+            };
+        }
+        Self {
+            v4: v4.into_iter().collect(),
+            v6: v6.into_iter().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Resolve domain entries and return the deduplicated allowed networks.
+///
+/// Domains that fail to resolve are skipped with a warning rather than failing
+/// sandbox creation; the sandbox simply can't reach them.
+pub async fn resolve_targets(targets: &[EgressTarget]) -> AllowedNets {
+    let mut cidrs = Vec::new();
+    for target in targets {
+        match target {
+            EgressTarget::Cidr(ip, len) => cidrs.push((*ip, *len)),
+            EgressTarget::Domain(domain) => {
+                match tokio::net::lookup_host((domain.as_str(), 0)).await {
+                    Ok(addrs) => {
+                        for addr in addrs {
+                            let len = if addr.is_ipv4() { 32 } else { 128 };
+                            cidrs.push((addr.ip(), len));
+                        }
+                    }
+                    Err(error) => {
+                        warn!(%domain, %error, "failed to resolve network policy domain");
+                    }
+                }
+            }
+        }
+    }
+    AllowedNets::from_cidrs(cidrs)
+}
+
+/// Resolvers listed in a `resolv.conf`; the only DNS servers a sandbox under a
+/// policy may query.
+pub fn nameservers(resolv_conf: &str) -> AllowedNets {
+    AllowedNets::from_cidrs(resolv_conf.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("nameserver") {
+            return None;
+        }
+        let ip: IpAddr = words.next()?.parse().ok()?;
+        Some((ip, if ip.is_ipv4() { 32 } else { 128 }))
+    }))
+}
+
+/// Name of the host chain holding a sandbox's rules.
+pub fn chain_name(host_interface: &str) -> String {
+    format!("cmux-egress-{host_interface}")
+}
+
+/// Build the iptables (or ip6tables) invocations, without the binary, that
+/// create the sandbox's chain and hook it into FORWARD for traffic from
+/// `host_interface`. `resolvers` and `allowed` must be of the same family.
+pub fn build_rules(
+    host_interface: &str,
+    resolvers: &[String],
+    allowed: &[String],
+) -> Vec<Vec<String>> {
+    let chain = chain_name(host_interface);
+    let chain = chain.as_str();
+    let mut rules: Vec<Vec<&str>> = vec![
+        vec!["-N", chain],
+        vec![
+            "-A",
+            chain,
+            "-m",
+            "conntrack",
+            "--ctstate",
+            "ESTABLISHED,RELATED",
+            "-j",
+            "ACCEPT",
+        ],
+    ];
+    for resolver in resolvers {
+        for proto in ["udp", "tcp"] {
+            rules.push(vec![
+                "-A", chain, "-d", resolver, "-p", proto, "--dport", "53", "-j", "ACCEPT",
+            ]);
+        }
+    }
+    for cidr in allowed {
+        rules.push(vec!["-A", chain, "-d", cidr, "-j", "ACCEPT"]);
+    }
+    rules.push(vec![
+        "-A",
+        chain,
+        "-j",
+        "LOG",
+        "--log-prefix",
+        EGRESS_LOG_PREFIX,
+    ]);
+    rules.push(vec!["-A", chain, "-j", "REJECT"]);
+    // Hook the chain in only once it is complete
+    rules.push(vec!["-I", "FORWARD", "-i", host_interface, "-j", chain]);
+
+    rules
+        .into_iter()
+        .map(|rule| rule.into_iter().map(String::from).collect())
+        .collect()
+}
+
+/// Install rules from [`build_rules`] on the host.
+pub async fn apply_rules(iptables_path: &str, rules: &[Vec<String>]) -> SandboxResult<()> {
+    for rule in rules {
+        let output = Command::new(iptables_path).args(rule).output().await?;
+        if !output.status.success() {
+            return Err(SandboxError::CommandFailed {
+                command: format!("{iptables_path} {}", rule.join(" ")),
+                message: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Unhook and delete a sandbox's chain. Missing rules are ignored, so this is
+/// safe to call for sandboxes without a policy or after a partial install.
+pub async fn remove_rules(iptables_path: &str, host_interface: &str) {
+    let chain = chain_name(host_interface);
+    let removals: [&[&str]; 3] = [
+        &["-D", "FORWARD", "-i", host_interface, "-j", &chain],
+        &["-F", &chain],
+        &["-X", &chain],
+    ];
+    for args in removals {
+        let _ = Command::new(iptables_path).args(args).output().await;
+    }
+}
+
+/// Extract the packet count of the trailing REJECT rule from
+/// `iptables -L <chain> -v -x -n` output.
+pub fn parse_reject_packets(listing: &str) -> Option<u64> {
+    listing.lines().rev().find_map(|line| {
+        let mut cols = line.split_whitespace();
+        let pkts = cols.next()?.parse::<u64>().ok()?;
+        let _bytes = cols.next()?;
+        let target = cols.next()?;
+        (target == "REJECT").then_some(pkts)
+    })
+}
+
+async fn read_reject_packets(iptables_path: &str, chain: &str) -> Option<u64> {
+    let output = Command::new(iptables_path)
+        .args(["-L", chain, "-v", "-x", "-n"])
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_reject_packets(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Poll the REJECT counters of both families and broadcast a warning whenever
+/// they grow. Exits once the sandbox's chain is gone.
+pub fn spawn_violation_monitor(
+    sandbox_id: Uuid,
+    host_interface: String,
+    iptables_path: String,
+    ip6tables_path: String,
+    host_events: HostEventSender,
+) {
+    tokio::spawn(async move {
+        let chain = chain_name(&host_interface);
+        let mut last = 0_u64;
+        loop {
+            tokio::time::sleep(MONITOR_INTERVAL).await;
+            let Some(v4) = read_reject_packets(&iptables_path, &chain).await else {
+                debug!(%sandbox_id, "egress monitor stopping");
+                break;
+            };
+            let v6 = read_reject_packets(&ip6tables_path, &chain)
+                .await
+                .unwrap_or(0);
+            let current = v4 + v6;
+            if current <= last {
+                continue;
+            }
+
+            let blocked = current - last;
+            last = current;
+            warn!(%sandbox_id, blocked, total = current, "egress policy blocked packets");
+            let _ = host_events.send(HostEvent::Notification(NotificationRequest {
+                message: format!(
+                    "Egress policy blocked {blocked} outbound packet(s) ({current} total)"
+                ),
+                level: NotificationLevel::Warning,
+                sandbox_id: Some(sandbox_id.to_string()),
+                tab_id: None,
+                pane_id: None,
+            }));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn parses_cidrs_and_domains() {
+        let policy = NetworkPolicy {
+            allow: vec![
+                "10.0.0.0/8".to_string(),
+                "1.1.1.1".to_string(),
+                "2606:4700::/32".to_string(),
+                "API.Anthropic.com".to_string(),
+            ],
+        };
+        let targets = parse_policy(&policy).unwrap();
+        assert_eq!(
+            targets,
+            vec![
+                EgressTarget::Cidr(Ipv4Addr::new(10, 0, 0, 0).into(), 8),
+                EgressTarget::Cidr(Ipv4Addr::new(1, 1, 1, 1).into(), 32),
+                EgressTarget::Cidr(Ipv6Addr::new(0x2606, 0x4700, 0, 0, 0, 0, 0, 0).into(), 32),
+                EgressTarget::Domain("api.anthropic.com".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        for bad in [
+            "",
+            "10.0.0.0/33",
+            "::1/129",
+            "bad domain",
+            "-x.com",
+            "a.com/24",
+        ] {
+            let policy = NetworkPolicy {
+                allow: vec![bad.to_string()],
+            };
+            assert!(parse_policy(&policy).is_err(), "accepted {bad:?}");
+        }
+    }
+
+    #[test]
+    fn splits_nameservers_by_family() {
+        let resolvers = nameservers("search lan\nnameserver 10.0.0.2\nnameserver 2001:db8::53\n");
+        assert_eq!(resolvers.v4, ["10.0.0.2/32"]);
+        assert_eq!(resolvers.v6, ["2001:db8::53/128"]);
+    }
+
+    #[test]
+    fn rules_only_allow_dns_to_resolvers_and_end_with_log_and_reject() {
+        let rules = build_rules(
+            "vethh-1234abcd",
+            &["10.0.0.2/32".to_string()],
+            &["140.82.112.0/20".to_string()],
+        );
+        let joined: Vec<String> = rules.iter().map(|r| r.join(" ")).collect();
+        let chain = "cmux-egress-vethh-1234abcd";
+        assert_eq!(joined[0], format!("-N {chain}"));
+        let dns: Vec<&String> = joined.iter().filter(|r| r.contains("--dport 53")).collect();
+        assert_eq!(
+            dns,
+            [
+                &format!("-A {chain} -d 10.0.0.2/32 -p udp --dport 53 -j ACCEPT"),
+                &format!("-A {chain} -d 10.0.0.2/32 -p tcp --dport 53 -j ACCEPT"),
+            ]
+        );
+        assert!(joined.contains(&format!("-A {chain} -d 140.82.112.0/20 -j ACCEPT")));
+        assert_eq!(
+            joined[joined.len() - 3],
+            format!("-A {chain} -j LOG --log-prefix {EGRESS_LOG_PREFIX}")
+        );
+        assert_eq!(joined[joined.len() - 2], format!("-A {chain} -j REJECT"));
+        assert_eq!(
+            joined[joined.len() - 1],
+            format!("-I FORWARD -i vethh-1234abcd -j {chain}")
+        );
+    }
+
+    #[test]
+    fn parses_reject_counter() {
+        let listing = "\
+Chain cmux-egress-vethh-1234abcd (1 references)
+    pkts      bytes target     prot opt in     out     source               destination
+       4      240 ACCEPT     all  --  *      *       0.0.0.0/0            0.0.0.0/0            ctstate RELATED,ESTABLISHED
+       3      180 LOG        all  --  *      *       0.0.0.0/0            0.0.0.0/0            LOG flags 0 level 4 prefix \"cmux-egress-deny \"
+       3      180 REJECT     all  --  *      *       0.0.0.0/0            0.0.0.0/0            reject-with icmp-port-unreachable
+";
+        assert_eq!(parse_reject_packets(listing), Some(3));
+        assert_eq!(parse_reject_packets("Chain OUTPUT\n"), None);
+    }
+}
@@ -10,6 +10,10 @@ use uuid::Uuid;
 pub enum SandboxError {
     #[error("sandbox {0} not found")]
     NotFound(Uuid),
+    #[error("service '{0}' not found")]
+    ServiceNotFound(String),
+    #[error("no logs for component '{0}'")]
+    LogComponentNotFound(String),
     #[error("required binary '{0}' not found in PATH")]
     MissingBinary(String),
     #[error("command '{command}' failed: {message}")]
@@ -36,6 +40,8 @@ impl IntoResponse for SandboxError {
     fn into_response(self) -> Response {
         let status = match self {
             SandboxError::NotFound(_) => StatusCode::NOT_FOUND,
+            SandboxError::ServiceNotFound(_) => StatusCode::NOT_FOUND,
+            SandboxError::LogComponentNotFound(_) => StatusCode::NOT_FOUND,
             SandboxError::MissingBinary(_) => StatusCode::SERVICE_UNAVAILABLE,
             SandboxError::CommandFailed { .. } => StatusCode::BAD_GATEWAY,
             SandboxError::IpPoolExhausted => StatusCode::INSUFFICIENT_STORAGE,
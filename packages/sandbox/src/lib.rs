@@ -1,24 +1,35 @@
 pub mod acp_client;
 pub mod api;
+pub mod bootstrap;
 pub mod bubblewrap;
+pub mod egress;
 pub mod errors;
 pub mod ip_pool;
 pub mod keyring;
+pub mod logs;
+pub mod metrics;
 pub mod models;
 pub mod mux;
 pub mod notifications;
 pub mod palette;
+pub mod preflight;
+pub mod pty_agent;
+pub mod replay;
 pub mod sandbox_handle;
+pub mod self_test;
 pub mod service;
 pub mod settings;
+pub mod supervisor;
 pub mod sync_files;
 pub mod terminal_guard;
 pub mod timing;
 pub mod vnc_proxy;
+pub mod vscode;
 
 pub use acp_client::{
     load_last_provider, run_chat_tui, run_chat_tui_with_workspace_status, run_demo_tui,
-    AcpProvider, WorkspaceSyncStatus,
+    AcpProvider, PermissionFallback, PermissionMode, PermissionPolicy, ReasoningVisibility,
+    WarmPoolSizes, WorkspaceSyncStatus,
 };
 pub use api::build_router;
 pub use bubblewrap::BubblewrapService;
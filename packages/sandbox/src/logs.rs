@@ -0,0 +1,212 @@
+//! In-memory log aggregation with a bounded ring buffer per component.
+//!
+//! The server's own tracing output lands in the [`SERVER_COMPONENT`] buffer
+//! and every supervised service writes to `<sandbox-id>.<service>`, so
+//! `GET /api/logs/{component}` can answer "what just happened" without
+//! exec'ing into the sandbox and hunting for log files.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use chrono::{DateTime, Utc};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::models::LogLine;
+
+/// Lines retained per component before the oldest are dropped.
+pub const LINES_PER_COMPONENT: usize = 5000;
+
+/// Component that receives the server's own tracing output.
+pub const SERVER_COMPONENT: &str = "server";
+
+#[derive(Clone, Default)]
+pub struct LogStore {
+    inner: Arc<Mutex<HashMap<String, VecDeque<LogLine>>>>,
+}
+
+impl LogStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process-wide store shared by the tracing writer and sandbox supervisors.
+    pub fn global() -> &'static LogStore {
+        static GLOBAL: OnceLock<LogStore> = OnceLock::new();
+        GLOBAL.get_or_init(LogStore::new)
+    }
+
+    pub fn push(&self, component: &str, line: impl Into<String>) {
+        let line = LogLine {
+            timestamp: Utc::now(),
+            line: line.into(),
+        };
+        let mut guard = self.inner.lock().unwrap();
+        let buffer = guard.entry(component.to_string()).or_default();
+        if buffer.len() == LINES_PER_COMPONENT {
+            buffer.pop_front();
+        }
+        buffer.push_back(line);
+    }
+
+    /// Lines at or after `since`, limited to the last `tail`. `None` when the
+    /// component has never logged.
+    pub fn query(
+        &self,
+        component: &str,
+        tail: Option<usize>,
+        since: Option<DateTime<Utc>>,
+    ) -> Option<Vec<LogLine>> {
+        let guard = self.inner.lock().unwrap();
+        let buffer = guard.get(component)?;
+        // Timestamps come from the wall clock, which can step backwards, so
+        // the buffer isn't sorted by them and every line is checked
+        let lines: Vec<&LogLine> = buffer
+            .iter()
+            .filter(|l| since.is_none_or(|since| l.timestamp >= since))
+            .collect();
+        let skip = tail.map_or(0, |tail| lines.len().saturating_sub(tail));
+        Some(lines.into_iter().skip(skip).cloned().collect())
+    }
+
+    pub fn components(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.inner.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Drop every component starting with `prefix` (e.g. a deleted sandbox).
+    pub fn remove_prefix(&self, prefix: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .retain(|name, _| !name.starts_with(prefix));
+    }
+
+    /// A `MakeWriter` that records formatted tracing events under `component`.
+    pub fn writer(&self, component: &str) -> ComponentMakeWriter {
+        ComponentMakeWriter {
+            store: self.clone(),
+            component: component.to_string(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ComponentMakeWriter {
+    store: LogStore,
+    component: String,
+}
+
+impl<'a> MakeWriter<'a> for ComponentMakeWriter {
+    type Writer = ComponentWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ComponentWriter {
+            store: self.store.clone(),
+            component: self.component.clone(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// Buffers one formatted event and records its lines when dropped.
+pub struct ComponentWriter {
+    store: LogStore,
+    component: String,
+    buffer: Vec<u8>,
+}
+
+impl io::Write for ComponentWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ComponentWriter {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.buffer);
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            self.store.push(&self.component, line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn keeps_bounded_tail_per_component() {
+        let store = LogStore::new();
+        for idx in 0..(LINES_PER_COMPONENT + 10) {
+            store.push("svc", format!("line-{idx}"));
+        }
+        store.push("other", "hello");
+
+        let all = store.query("svc", None, None).unwrap();
+        assert_eq!(all.len(), LINES_PER_COMPONENT);
+        assert_eq!(all[0].line, "line-10");
+
+        let tail = store.query("svc", Some(2), None).unwrap();
+        let lines: Vec<&str> = tail.iter().map(|l| l.line.as_str()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                format!("line-{}", LINES_PER_COMPONENT + 8),
+                format!("line-{}", LINES_PER_COMPONENT + 9)
+            ]
+        );
+
+        let cutoff = all[LINES_PER_COMPONENT - 1].timestamp + chrono::Duration::seconds(1);
+        assert!(store.query("svc", None, Some(cutoff)).unwrap().is_empty());
+        assert!(store.query("missing", None, None).is_none());
+        assert_eq!(store.components(), vec!["other", "svc"]);
+
+        store.remove_prefix("sv");
+        assert_eq!(store.components(), vec!["other"]);
+    }
+
+    #[test]
+    fn since_does_not_assume_ordered_timestamps() {
+        let store = LogStore::new();
+        let now = Utc::now();
+        let at = |secs: i64, line: &str| LogLine {
+            timestamp: now + chrono::Duration::seconds(secs),
+            line: line.to_string(),
+        };
+        // The clock stepped back between the second and third lines
+        store.inner.lock().unwrap().insert(
+            "svc".to_string(),
+            VecDeque::from([at(0, "a"), at(10, "b"), at(5, "c"), at(20, "d")]),
+        );
+        let lines = |tail, secs| {
+            let since = now + chrono::Duration::seconds(secs);
+            let lines = store.query("svc", tail, Some(since)).unwrap();
+            lines.into_iter().map(|l| l.line).collect::<Vec<_>>()
+        };
+        assert_eq!(lines(None, 6), ["b", "d"]);
+        assert_eq!(lines(None, 5), ["b", "c", "d"]);
+        assert_eq!(lines(Some(1), 0), ["d"]);
+    }
+
+    #[test]
+    fn writer_records_each_line_on_drop() {
+        let store = LogStore::new();
+        let make = store.writer(SERVER_COMPONENT);
+        {
+            let mut writer = make.make_writer();
+            writer.write_all(b"INFO first\nINFO second\n").unwrap();
+            assert!(store.query(SERVER_COMPONENT, None, None).is_none());
+        }
+        let lines = store.query(SERVER_COMPONENT, None, None).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].line, "INFO second");
+    }
+}
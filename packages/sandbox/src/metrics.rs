@@ -0,0 +1,469 @@
+//! Host resource and activity metrics exposed in Prometheus text format.
+//!
+//! Host figures are sampled from `/proc` and `statvfs` on each scrape. Activity
+//! counters (PTY sessions, proxy traffic) are process-wide atomics updated by the
+//! code paths that own those resources. Per-sandbox gauges, labelled with the
+//! sandbox id, come from asking each running sandbox's cmux-pty server for its
+//! terminals and the conversations they belong to.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+static PTY_SESSIONS: AtomicI64 = AtomicI64::new(0);
+static PROXY_REQUESTS: AtomicU64 = AtomicU64::new(0);
+static PROXY_BYTES_UPSTREAM: AtomicU64 = AtomicU64::new(0);
+static PROXY_BYTES_DOWNSTREAM: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a live PTY session for as long as it is held.
+#[derive(Debug)]
+pub struct PtySessionGuard(());
+
+impl PtySessionGuard {
+    pub fn new() -> Self {
+        PTY_SESSIONS.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Default for PtySessionGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PtySessionGuard {
+    fn drop(&mut self) {
+        PTY_SESSIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Record one proxied HTTP request or WebSocket connection.
+pub fn record_proxy_request() {
+    PROXY_REQUESTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record bytes forwarded from the client into the sandbox.
+pub fn record_proxy_upstream(bytes: usize) {
+    PROXY_BYTES_UPSTREAM.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Record bytes forwarded from the sandbox back to the client.
+pub fn record_proxy_downstream(bytes: usize) {
+    PROXY_BYTES_DOWNSTREAM.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Aggregate CPU time from the first line of `/proc/stat`, in clock ticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub total: u64,
+    pub idle: u64,
+}
+
+pub fn parse_proc_stat(content: &str) -> Option<CpuTimes> {
+    let line = content.lines().find(|line| line.starts_with("cpu "))?;
+    let values: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    if values.len() < 4 {
+        return None;
+    }
+    // idle + iowait count as idle time
+    let idle = values[3] + values.get(4).copied().unwrap_or(0);
+    Some(CpuTimes {
+        total: values.iter().sum(),
+        idle,
+    })
+}
+
+/// Memory totals from `/proc/meminfo`, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemInfo {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+pub fn parse_meminfo(content: &str) -> Option<MemInfo> {
+    let field = |name: &str| {
+        content.lines().find_map(|line| {
+            let rest = line.strip_prefix(name)?.strip_prefix(':')?;
+            let kib: u64 = rest.split_whitespace().next()?.parse().ok()?;
+            Some(kib * 1024)
+        })
+    };
+    Some(MemInfo {
+        total_bytes: field("MemTotal")?,
+        available_bytes: field("MemAvailable")?,
+    })
+}
+
+/// 1/5/15-minute load averages from `/proc/loadavg`.
+pub fn parse_loadavg(content: &str) -> Option<[f64; 3]> {
+    let mut parts = content.split_whitespace().map(|v| v.parse::<f64>().ok());
+    Some([parts.next()??, parts.next()??, parts.next()??])
+}
+
+/// Total and available bytes of the filesystem holding `path`.
+pub fn disk_usage(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid out-pointer.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+/// Terminals open in one sandbox and the ACP conversations they belong to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SandboxActivity {
+    pub pty_sessions: usize,
+    pub conversations: usize,
+}
+
+#[derive(Deserialize)]
+struct PtySessionList {
+    sessions: Vec<PtySession>,
+}
+
+#[derive(Deserialize)]
+struct PtySession {
+    alive: bool,
+    #[serde(default)]
+    conversation_id: Option<String>,
+}
+
+/// Count live sessions and distinct conversations in a cmux-pty
+/// `GET /sessions` response.
+pub fn parse_pty_sessions(body: &[u8]) -> Option<SandboxActivity> {
+    let list: PtySessionList = serde_json::from_slice(body).ok()?;
+    let live: Vec<_> = list.sessions.iter().filter(|s| s.alive).collect();
+    let conversations: HashSet<_> = live
+        .iter()
+        .filter_map(|s| s.conversation_id.as_deref())
+        .collect();
+    Some(SandboxActivity {
+        pty_sessions: live.len(),
+        conversations: conversations.len(),
+    })
+}
+
+/// Ask the cmux-pty server at `sandbox_ip` what is open; `None` if it
+/// doesn't answer in time, so one stuck sandbox can't stall a scrape.
+pub async fn sample_sandbox_activity(sandbox_ip: &str) -> Option<SandboxActivity> {
+    let url = format!("http://{}:{}/sessions", sandbox_ip, crate::api::PTY_PORT);
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()
+        .ok()?;
+    let response = client.get(url).send().await.ok()?.error_for_status().ok()?;
+    parse_pty_sessions(&response.bytes().await.ok()?)
+}
+
+/// One sandbox's gauges.
+#[derive(Clone, Debug, Default)]
+pub struct SandboxMetrics {
+    pub id: String,
+    pub running: bool,
+    /// Missing when the sandbox is stopped or its cmux-pty didn't answer
+    pub activity: Option<SandboxActivity>,
+}
+
+/// Point-in-time values rendered by [`render_prometheus`].
+#[derive(Clone, Debug, Default)]
+pub struct MetricsSnapshot {
+    pub cpu: Option<CpuTimes>,
+    pub cpu_count: usize,
+    pub memory: Option<MemInfo>,
+    pub load: Option<[f64; 3]>,
+    pub disk: Option<(u64, u64)>,
+    pub sandboxes_running: usize,
+    pub sandboxes_total: usize,
+    pub sandboxes: Vec<SandboxMetrics>,
+    pub pty_sessions: i64,
+    pub proxy_requests: u64,
+    pub proxy_bytes_upstream: u64,
+    pub proxy_bytes_downstream: u64,
+}
+
+impl MetricsSnapshot {
+    /// Sample host metrics; sandbox counts are filled in by the caller.
+    pub fn sample(workspace_root: &Path) -> Self {
+        let read = |path: &str| std::fs::read_to_string(path).ok();
+        Self {
+            cpu: read("/proc/stat").as_deref().and_then(parse_proc_stat),
+            cpu_count: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            memory: read("/proc/meminfo").as_deref().and_then(parse_meminfo),
+            load: read("/proc/loadavg").as_deref().and_then(parse_loadavg),
+            disk: disk_usage(workspace_root),
+            pty_sessions: PTY_SESSIONS.load(Ordering::Relaxed),
+            proxy_requests: PROXY_REQUESTS.load(Ordering::Relaxed),
+            proxy_bytes_upstream: PROXY_BYTES_UPSTREAM.load(Ordering::Relaxed),
+            proxy_bytes_downstream: PROXY_BYTES_DOWNSTREAM.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+fn metric<L: AsRef<str>>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(L, String)],
+) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{} {value}", labels.as_ref());
+    }
+}
+
+/// `{sandbox="<id>"}`, escaped per the exposition format.
+fn sandbox_label(id: &str) -> String {
+    let escaped = id
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("{{sandbox=\"{escaped}\"}}")
+}
+
+/// Render a snapshot in the Prometheus text exposition format.
+pub fn render_prometheus(snapshot: &MetricsSnapshot) -> String {
+    let mut out = String::new();
+
+    if let Some(cpu) = snapshot.cpu {
+        metric(
+            &mut out,
+            "cmux_host_cpu_ticks_total",
+            "counter",
+            "Aggregate CPU time since boot in clock ticks.",
+            &[
+                ("{mode=\"busy\"}", (cpu.total - cpu.idle).to_string()),
+                ("{mode=\"idle\"}", cpu.idle.to_string()),
+            ],
+        );
+    }
+    metric(
+        &mut out,
+        "cmux_host_cpu_count",
+        "gauge",
+        "Number of CPUs available to the sandbox daemon.",
+        &[("", snapshot.cpu_count.to_string())],
+    );
+    if let Some(mem) = snapshot.memory {
+        metric(
+            &mut out,
+            "cmux_host_memory_bytes",
+            "gauge",
+            "Host memory in bytes.",
+            &[
+                ("{state=\"total\"}", mem.total_bytes.to_string()),
+                ("{state=\"available\"}", mem.available_bytes.to_string()),
+            ],
+        );
+    }
+    if let Some([one, five, fifteen]) = snapshot.load {
+        metric(
+            &mut out,
+            "cmux_host_load_average",
+            "gauge",
+            "Host load average.",
+            &[
+                ("{window=\"1m\"}", one.to_string()),
+                ("{window=\"5m\"}", five.to_string()),
+                ("{window=\"15m\"}", fifteen.to_string()),
+            ],
+        );
+    }
+    if let Some((total, available)) = snapshot.disk {
+        metric(
+            &mut out,
+            "cmux_host_disk_bytes",
+            "gauge",
+            "Filesystem space backing sandbox workspaces, in bytes.",
+            &[
+                ("{state=\"total\"}", total.to_string()),
+                ("{state=\"available\"}", available.to_string()),
+            ],
+        );
+    }
+    metric(
+        &mut out,
+        "cmux_sandboxes",
+        "gauge",
+        "Sandboxes known to the daemon.",
+        &[
+            (
+                "{status=\"running\"}",
+                snapshot.sandboxes_running.to_string(),
+            ),
+            ("{status=\"all\"}", snapshot.sandboxes_total.to_string()),
+        ],
+    );
+    let per_sandbox = |value: &dyn Fn(&SandboxMetrics) -> Option<usize>| {
+        snapshot
+            .sandboxes
+            .iter()
+            .filter_map(|sandbox| Some((sandbox_label(&sandbox.id), value(sandbox)?.to_string())))
+            .collect::<Vec<_>>()
+    };
+    metric(
+        &mut out,
+        "cmux_sandbox_running",
+        "gauge",
+        "Whether the sandbox's process is running.",
+        &per_sandbox(&|sandbox| Some(usize::from(sandbox.running))),
+    );
+    metric(
+        &mut out,
+        "cmux_sandbox_pty_sessions",
+        "gauge",
+        "Live terminals in the sandbox's cmux-pty server.",
+        &per_sandbox(&|sandbox| Some(sandbox.activity?.pty_sessions)),
+    );
+    metric(
+        &mut out,
+        "cmux_sandbox_conversations",
+        "gauge",
+        "ACP conversations with live terminals in the sandbox.",
+        &per_sandbox(&|sandbox| Some(sandbox.activity?.conversations)),
+    );
+    metric(
+        &mut out,
+        "cmux_pty_sessions",
+        "gauge",
+        "Open PTY sessions attached through the daemon.",
+        &[("", snapshot.pty_sessions.to_string())],
+    );
+    metric(
+        &mut out,
+        "cmux_proxy_requests_total",
+        "counter",
+        "Proxied HTTP requests and WebSocket connections into sandboxes.",
+        &[("", snapshot.proxy_requests.to_string())],
+    );
+    metric(
+        &mut out,
+        "cmux_proxy_bytes_total",
+        "counter",
+        "Bytes forwarded by the sandbox proxy.",
+        &[
+            (
+                "{direction=\"upstream\"}",
+                snapshot.proxy_bytes_upstream.to_string(),
+            ),
+            (
+                "{direction=\"downstream\"}",
+                snapshot.proxy_bytes_downstream.to_string(),
+            ),
+        ],
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_proc_files() {
+        let stat = "cpu  100 5 50 800 20 0 5 0 0 0\ncpu0 50 2 25 400 10 0 2 0 0 0\n";
+        assert_eq!(
+            parse_proc_stat(stat),
+            Some(CpuTimes {
+                total: 980,
+                idle: 820
+            })
+        );
+
+        let meminfo =
+            "MemTotal:       16384 kB\nMemFree:         1024 kB\nMemAvailable:    8192 kB\n";
+        assert_eq!(
+            parse_meminfo(meminfo),
+            Some(MemInfo {
+                total_bytes: 16384 * 1024,
+                available_bytes: 8192 * 1024
+            })
+        );
+
+        assert_eq!(
+            parse_loadavg("0.52 0.58 0.59 1/467 12345\n"),
+            Some([0.52, 0.58, 0.59])
+        );
+        assert_eq!(parse_loadavg("garbage"), None);
+    }
+
+    #[test]
+    fn counts_live_sessions_and_their_conversations() {
+        let body = br#"{"sessions": [
+            {"id": "a", "alive": true, "conversation_id": "c1"},
+            {"id": "b", "alive": true, "conversation_id": "c1"},
+            {"id": "c", "alive": true, "conversation_id": "c2"},
+            {"id": "d", "alive": false, "conversation_id": "c3"},
+            {"id": "e", "alive": true}
+        ]}"#;
+        assert_eq!(
+            parse_pty_sessions(body),
+            Some(SandboxActivity {
+                pty_sessions: 4,
+                conversations: 2,
+            })
+        );
+        assert_eq!(parse_pty_sessions(b"not json"), None);
+    }
+
+    #[test]
+    fn renders_prometheus_text() {
+        let snapshot = MetricsSnapshot {
+            cpu_count: 4,
+            memory: Some(MemInfo {
+                total_bytes: 2048,
+                available_bytes: 1024,
+            }),
+            sandboxes_running: 2,
+            sandboxes_total: 3,
+            sandboxes: vec![
+                SandboxMetrics {
+                    id: "sb-1".to_string(),
+                    running: true,
+                    activity: Some(SandboxActivity {
+                        pty_sessions: 3,
+                        conversations: 2,
+                    }),
+                },
+                SandboxMetrics {
+                    id: "sb-\"2\"".to_string(),
+                    running: false,
+                    activity: None,
+                },
+            ],
+            pty_sessions: 5,
+            proxy_bytes_upstream: 10,
+            proxy_bytes_downstream: 20,
+            ..Default::default()
+        };
+        let text = render_prometheus(&snapshot);
+        assert!(text.contains("# TYPE cmux_host_memory_bytes gauge\n"));
+        assert!(text.contains("cmux_host_memory_bytes{state=\"available\"} 1024\n"));
+        assert!(text.contains("cmux_sandboxes{status=\"running\"} 2\n"));
+        assert!(text.contains("cmux_pty_sessions 5\n"));
+        assert!(text.contains("cmux_sandbox_running{sandbox=\"sb-1\"} 1\n"));
+        assert!(text.contains("cmux_sandbox_running{sandbox=\"sb-\\\"2\\\"\"} 0\n"));
+        assert!(text.contains("cmux_sandbox_pty_sessions{sandbox=\"sb-1\"} 3\n"));
+        assert!(text.contains("cmux_sandbox_conversations{sandbox=\"sb-1\"} 2\n"));
+        assert!(!text.contains("cmux_sandbox_conversations{sandbox=\"sb-\\\"2"));
+        assert!(text.contains("cmux_proxy_bytes_total{direction=\"downstream\"} 20\n"));
+        assert!(!text.contains("cmux_host_load_average"));
+    }
+}
@@ -4,12 +4,32 @@ use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
 pub struct EnvVar {
     pub key: String,
     pub value: String,
 }
 
+/// Static hostname -> address mapping written into the sandbox's `/etc/hosts`.
+/// Lets CLIs that ignore `*_BASE_URL` settings still be routed through a local proxy.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct HostOverride {
+    #[schema(example = "api.anthropic.com")]
+    pub hostname: String,
+    #[schema(example = "10.201.0.1")]
+    pub address: String,
+}
+
+/// Outbound network allowlist enforced on the host for the sandbox's traffic.
+/// Traffic to anything not listed is logged and rejected.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct NetworkPolicy {
+    /// Domains or IPv4/IPv6 CIDRs the sandbox may reach. Domains are resolved once at creation.
+    #[schema(example = "[\"api.anthropic.com\", \"10.0.0.0/8\"]")]
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 pub struct CreateSandboxRequest {
     pub name: Option<String>,
@@ -24,6 +44,12 @@ pub struct CreateSandboxRequest {
     pub tmpfs: Vec<String>,
     #[serde(default)]
     pub env: Vec<EnvVar>,
+    /// Extra `/etc/hosts` entries applied to every process spawned in the sandbox
+    #[serde(default)]
+    pub hosts: Vec<HostOverride>,
+    /// Egress allowlist; when unset the sandbox has unrestricted outbound access
+    #[serde(default)]
+    pub network_policy: Option<NetworkPolicy>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
@@ -104,6 +130,47 @@ pub struct AwaitReadyResponse {
     pub timed_out: Vec<String>,
 }
 
+/// Editor configuration applied to a sandbox's cmux-code instance.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct VscodeConfigRequest {
+    /// Extension IDs (`publisher.name[@version]`) or absolute `.vsix` paths inside the sandbox
+    #[schema(example = "[\"esbenp.prettier-vscode\"]")]
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Settings merged into the user settings.json; a `null` value removes the key
+    #[schema(value_type = Option<Object>)]
+    #[serde(default)]
+    pub settings: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Replaces the user keybindings.json
+    #[schema(value_type = Option<Vec<Object>>)]
+    #[serde(default)]
+    pub keybindings: Option<Vec<serde_json::Value>>,
+    /// Restart cmux-code afterwards (default: true when extensions are installed)
+    #[serde(default)]
+    pub restart: Option<bool>,
+}
+
+/// An extension that failed to install.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct VscodeExtensionFailure {
+    pub extension: String,
+    pub message: String,
+}
+
+/// Result of applying editor configuration.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct VscodeConfigResponse {
+    /// Extensions that were installed successfully
+    pub installed: Vec<String>,
+    /// Extensions that failed to install
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<VscodeExtensionFailure>,
+    pub settings_updated: bool,
+    pub keybindings_updated: bool,
+    /// Whether cmux-code was restarted to pick up the changes
+    pub restarted: bool,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 pub struct SandboxSummary {
     pub id: Uuid,
@@ -478,6 +545,246 @@ pub enum MuxServerMessage {
     },
 }
 
+/// When a supervised service is started again after its process exits.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    Never,
+    #[default]
+    OnFailure,
+    Always,
+}
+
+/// How the supervisor decides a running service is healthy.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HealthProbe {
+    /// A TCP connect to this port on the sandbox address succeeds
+    Tcp { port: u16 },
+    /// This shell command exits 0 inside the sandbox
+    Command { command: String },
+}
+
+/// Declarative definition of a process supervised inside a sandbox.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct ServiceDefinition {
+    #[schema(example = "dev-server")]
+    pub name: String,
+    /// Run with `/bin/sh -c`; use `exec` so signals reach the process
+    #[schema(example = "exec npm run dev -- --port 3000")]
+    pub command: String,
+    #[serde(default)]
+    pub env: Vec<EnvVar>,
+    #[serde(default)]
+    pub workdir: Option<String>,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default)]
+    pub health: Option<HealthProbe>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    Starting,
+    Running,
+    /// Waiting to restart after the process exited
+    Backoff,
+    Exited,
+    Failed,
+    Stopped,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    pub last_exit_code: Option<i32>,
+    /// Result of the latest health probe; absent without a probe or before the first one
+    pub healthy: Option<bool>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub definition: ServiceDefinition,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct ServiceLogs {
+    pub name: String,
+    /// Most recent stdout/stderr lines, oldest first
+    pub lines: Vec<LogLine>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct LogLine {
+    pub timestamp: DateTime<Utc>,
+    pub line: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct ComponentLogs {
+    pub component: String,
+    /// Matching lines, oldest first
+    pub lines: Vec<LogLine>,
+}
+
+/// One recorded stream event, as captured by the client from a conversation's
+/// raw output.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq)]
+pub struct ReplayEvent {
+    /// Milliseconds since the start of the recording
+    pub at_ms: u64,
+    /// SSE event name; defaults to `message`
+    #[serde(default)]
+    pub event: Option<String>,
+    /// Event payload, sent as JSON
+    pub data: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReplayRequest {
+    /// Events in recorded order
+    pub events: Vec<ReplayEvent>,
+    /// Playback speed multiplier; 2.0 replays twice as fast
+    #[serde(default = "default_replay_speed")]
+    pub speed: f64,
+    /// Cap on any single pause, in milliseconds (after applying `speed`)
+    #[serde(default)]
+    pub max_gap_ms: Option<u64>,
+}
+
+fn default_replay_speed() -> f64 {
+    1.0
+}
+
+/// Boot profile read from `sandbox.toml` in the workspace or posted to
+/// `/sandboxes/{id}/manifest`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct BootManifest {
+    /// Variables loaded into cmux-env and passed to every service
+    #[serde(default)]
+    pub env: std::collections::BTreeMap<String, String>,
+    /// Dotenv files loaded into cmux-env, relative to the workspace
+    #[serde(default)]
+    pub env_files: Vec<String>,
+    #[serde(default)]
+    pub services: Vec<ServiceDefinition>,
+    /// Ports routed through cmux-proxy
+    #[serde(default)]
+    pub ports: Vec<ExposedPort>,
+    #[serde(default)]
+    pub provider: Option<ProviderDefaults>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct ExposedPort {
+    pub port: u16,
+    /// Upstream ports balanced behind `port`; empty forwards to `port` itself
+    #[serde(default)]
+    pub replicas: Vec<u16>,
+    /// Rewrite absolute `localhost` URLs to any declared port in HTML/JS
+    /// responses to the public workspace origin
+    #[serde(default)]
+    pub rewrite_urls: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct ProviderDefaults {
+    /// ACP provider short name, e.g. `claude`
+    #[schema(example = "claude")]
+    pub id: String,
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct BootReport {
+    /// Services defined and started
+    pub services: Vec<String>,
+    /// Number of env variables loaded into cmux-env
+    pub env_vars: usize,
+    pub env_files: Vec<String>,
+    pub exposed_ports: Vec<u16>,
+    pub provider: Option<ProviderDefaults>,
+    /// Steps that failed; the rest of the manifest is still applied
+    pub errors: Vec<String>,
+}
+
+/// What an ACP provider supports, as spawned by this sandbox.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct AcpProviderCapabilities {
+    #[schema(example = "claude")]
+    pub id: String,
+    #[schema(example = "Claude Code")]
+    pub display_name: String,
+    /// Model pinned on the spawn command line, if any
+    pub default_model: Option<String>,
+    /// Models known up front. Providers report the full list on `session/new`,
+    /// so this may be empty even when `supports_set_model` is true.
+    pub models: Vec<String>,
+    /// Session modes accepted by `session/set_mode`
+    #[schema(example = "[\"default\", \"acceptEdits\", \"plan\", \"bypassPermissions\"]")]
+    pub permission_modes: Vec<String>,
+    pub supports_set_model: bool,
+    pub supports_cancel: bool,
+    /// Whether `session/load` can resume an earlier conversation
+    pub supports_resume: bool,
+    /// Environment variables the provider needs to authenticate
+    #[schema(example = "[\"ANTHROPIC_API_KEY\"]")]
+    pub required_env: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreflightStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct PreflightCheck {
+    /// `binary`, `version` or `auth` in preflight reports; `provider:<name>`,
+    /// `pty`, `proxy`, `vnc` or `callback` in self-test reports
+    #[schema(example = "version")]
+    pub name: String,
+    pub status: PreflightStatus,
+    #[schema(example = "claude-code-acp 0.3.1 is older than the supported 0.4.0")]
+    pub message: String,
+    /// What to do about a failure or warning
+    pub hint: Option<String>,
+}
+
+/// Result of checking that a provider can be spawned in a sandbox.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct AcpPreflightReport {
+    #[schema(example = "claude")]
+    pub provider: String,
+    /// No check failed; warnings don't block spawning
+    pub ok: bool,
+    pub checks: Vec<PreflightCheck>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct SelfTestRequest {
+    /// URL the sandbox reports back to; checked for reachability from inside
+    /// the sandbox when set
+    #[serde(default)]
+    #[schema(example = "https://example.com/api/sandboxes/callback")]
+    pub callback_url: Option<String>,
+}
+
+/// Result of exercising a sandbox's critical paths, for fleet health checks
+/// and image CI.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub sandbox_id: String,
+    /// No check failed; skipped checks are reported as warnings
+    pub ok: bool,
+    pub duration_ms: u64,
+    pub checks: Vec<PreflightCheck>,
+}
+
 fn default_tty() -> bool {
     true
 }
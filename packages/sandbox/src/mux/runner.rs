@@ -897,10 +897,8 @@ fn handle_input(
                                     tab.navigate(crate::mux::layout::NavDirection::Right);
                                 }
                             }
-                            KeyCode::Tab => {
-                                if app.sidebar.visible {
-                                    app.focus = FocusArea::Sidebar;
-                                }
+                            KeyCode::Tab if app.sidebar.visible => {
+                                app.focus = FocusArea::Sidebar;
                             }
                             _ => {}
                         }
@@ -1259,6 +1257,8 @@ async fn create_sandbox_with_workspace(
         read_only_paths: vec![],
         tmpfs: vec![],
         env: crate::keyring::build_default_env_vars(),
+        hosts: vec![],
+        network_policy: None,
     };
 
     let response = client
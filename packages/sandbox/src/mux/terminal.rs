@@ -1764,11 +1764,9 @@ impl Perform for VirtualTerminal {
         if let Ok(cmd_str) = std::str::from_utf8(cmd) {
             match cmd_str {
                 // Window title (OSC 0 and OSC 2)
-                "0" | "2" => {
-                    if params.len() > 1 {
-                        if let Ok(title) = std::str::from_utf8(params[1]) {
-                            self.title = Some(title.to_string());
-                        }
+                "0" | "2" if params.len() > 1 => {
+                    if let Ok(title) = std::str::from_utf8(params[1]) {
+                        self.title = Some(title.to_string());
                     }
                 }
                 // OSC 4 - Query/Set indexed color (256-color palette)
@@ -1946,26 +1944,24 @@ impl Perform for VirtualTerminal {
                     self.default_bg_color = None;
                 }
                 // OSC 12 - Query/Set cursor color
-                "12" => {
-                    if params.len() > 1 {
-                        if let Ok(color_str) = std::str::from_utf8(params[1]) {
-                            if color_str == "?" {
-                                // Query - respond with current cursor color (default to white if not set)
-                                let (r, g, b) = self.cursor_color.unwrap_or((255, 255, 255));
-                                let response = format!(
-                                    "\x1b]12;rgb:{:04x}/{:04x}/{:04x}\x1b\\",
-                                    (r as u16) * 257,
-                                    (g as u16) * 257,
-                                    (b as u16) * 257
-                                );
-                                self.pending_responses.push(response.into_bytes());
-                            } else if color_str == "default" {
-                                // Special value "default" resets cursor color
-                                self.cursor_color = None;
-                            } else if let Some(color) = parse_osc_color(color_str) {
-                                // Set cursor color
-                                self.cursor_color = Some(color);
-                            }
+                "12" if params.len() > 1 => {
+                    if let Ok(color_str) = std::str::from_utf8(params[1]) {
+                        if color_str == "?" {
+                            // Query - respond with current cursor color (default to white if not set)
+                            let (r, g, b) = self.cursor_color.unwrap_or((255, 255, 255));
+                            let response = format!(
+                                "\x1b]12;rgb:{:04x}/{:04x}/{:04x}\x1b\\",
+                                (r as u16) * 257,
+                                (g as u16) * 257,
+                                (b as u16) * 257
+                            );
+                            self.pending_responses.push(response.into_bytes());
+                        } else if color_str == "default" {
+                            // Special value "default" resets cursor color
+                            self.cursor_color = None;
+                        } else if let Some(color) = parse_osc_color(color_str) {
+                            // Set cursor color
+                            self.cursor_color = Some(color);
                         }
                     }
                 }
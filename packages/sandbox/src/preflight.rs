@@ -0,0 +1,307 @@
+//! Checks run inside a sandbox before spawning an ACP provider.
+//!
+//! Most failed spawns come down to a missing or outdated CLI, or missing
+//! credentials. A single `exec` probes all three, and the answers become an
+//! [`AcpPreflightReport`] that says what to fix instead of a bare connection
+//! error.
+
+use crate::acp_client::AcpProvider;
+use crate::errors::SandboxResult;
+use crate::models::{AcpPreflightReport, ExecRequest, PreflightCheck, PreflightStatus};
+use crate::service::SandboxService;
+
+/// Cap on `--version`, since some CLIs start network checks on launch.
+const VERSION_TIMEOUT_SECS: u32 = 10;
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Shell script whose output [`parse_report`] understands: one `key=value`
+/// or `key` line per fact found.
+pub fn preflight_script(provider: AcpProvider) -> String {
+    let mut script = format!(
+        "bin=$(command -v {bin} 2>/dev/null) && echo \"bin=$bin\" && \
+         echo \"version=$( (command -v timeout >/dev/null && timeout {timeout} \"$bin\" --version || \"$bin\" --version) 2>&1 | head -n 1)\"\n",
+        bin = shell_quote(provider.binary()),
+        timeout = VERSION_TIMEOUT_SECS,
+    );
+    let capabilities = provider.capabilities();
+    let auth_env = capabilities
+        .required_env
+        .iter()
+        .map(String::as_str)
+        .chain(provider.alternate_auth_env().iter().copied());
+    for name in auth_env {
+        script.push_str(&format!(
+            "[ -n \"${{{name}:-}}\" ] && echo \"env={name}\"\n"
+        ));
+    }
+    for path in provider.auth_files() {
+        script.push_str(&format!(
+            "[ -s {quoted} ] && echo \"file={path}\"\n",
+            quoted = shell_quote(path)
+        ));
+    }
+    script.push_str("true\n");
+    script
+}
+
+/// First `major.minor[.patch]` in `text`, as numbers.
+fn parse_version(text: &str) -> Option<Vec<u64>> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .filter_map(|token| {
+            let parts: Vec<u64> = token
+                .trim_matches('.')
+                .split('.')
+                .map(|p| p.parse().ok())
+                .collect::<Option<_>>()?;
+            (parts.len() >= 2).then_some(parts)
+        })
+        .next()
+}
+
+fn check(
+    name: &str,
+    status: PreflightStatus,
+    message: String,
+    hint: Option<String>,
+) -> PreflightCheck {
+    PreflightCheck {
+        name: name.to_string(),
+        status,
+        message,
+        hint,
+    }
+}
+
+/// Turn the output of [`preflight_script`] into a report.
+pub fn parse_report(provider: AcpProvider, output: &str) -> AcpPreflightReport {
+    let mut bin = None;
+    let mut version_line = None;
+    let mut auth_env = Vec::new();
+    let mut auth_files = Vec::new();
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("bin", value)) => bin = Some(value.trim().to_string()),
+            Some(("version", value)) => version_line = Some(value.trim().to_string()),
+            Some(("env", value)) => auth_env.push(value.trim().to_string()),
+            Some(("file", value)) => auth_files.push(value.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    let binary = provider.binary();
+    let mut checks = Vec::new();
+    match &bin {
+        Some(path) => checks.push(check(
+            "binary",
+            PreflightStatus::Pass,
+            format!("found {path}"),
+            None,
+        )),
+        None => checks.push(check(
+            "binary",
+            PreflightStatus::Fail,
+            format!("{binary} is not installed or not on PATH"),
+            Some(format!(
+                "Install {} in the sandbox image, or rebuild the image to pick it up",
+                provider.display_name()
+            )),
+        )),
+    }
+
+    if bin.is_some() {
+        let min = provider.min_version();
+        let found = version_line.as_deref().and_then(parse_version);
+        let wanted = parse_version(min).unwrap_or_default();
+        checks.push(match found {
+            Some(found) if found >= wanted => check(
+                "version",
+                PreflightStatus::Pass,
+                format!("{binary} {} (>= {min})", join_version(&found)),
+                None,
+            ),
+            Some(found) => check(
+                "version",
+                PreflightStatus::Fail,
+                format!(
+                    "{binary} {} is older than the supported {min}",
+                    join_version(&found)
+                ),
+                Some(format!("Upgrade {binary} to {min} or newer")),
+            ),
+            None => check(
+                "version",
+                PreflightStatus::Warn,
+                format!(
+                    "could not read a version from `{binary} --version`: {}",
+                    version_line.as_deref().unwrap_or("no output")
+                ),
+                None,
+            ),
+        });
+    }
+
+    let env_names: Vec<String> = provider
+        .capabilities()
+        .required_env
+        .into_iter()
+        .chain(provider.alternate_auth_env().iter().map(|s| s.to_string()))
+        .collect();
+    let auth = if let Some(name) = auth_env.first() {
+        check(
+            "auth",
+            PreflightStatus::Pass,
+            format!("{name} is set"),
+            None,
+        )
+    } else if let Some(path) = auth_files.first() {
+        check("auth", PreflightStatus::Pass, format!("found {path}"), None)
+    } else {
+        let mut options: Vec<String> = env_names.clone();
+        options.extend(provider.auth_files().iter().map(|p| p.to_string()));
+        // Providers without a required variable may authenticate through
+        // their own config, so only warn for them.
+        let status = if env_names.is_empty() {
+            PreflightStatus::Warn
+        } else {
+            PreflightStatus::Fail
+        };
+        check(
+            "auth",
+            status,
+            format!("no credentials found for {}", provider.display_name()),
+            Some(format!(
+                "Provide one of: {} (`cmux auth` syncs host credential files)",
+                options.join(", ")
+            )),
+        )
+    };
+    checks.push(auth);
+
+    AcpPreflightReport {
+        provider: provider.short_name().to_string(),
+        ok: checks.iter().all(|c| c.status != PreflightStatus::Fail),
+        checks,
+    }
+}
+
+fn join_version(parts: &[u64]) -> String {
+    parts
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Run the checks for `provider` inside sandbox `id`.
+pub async fn run_preflight(
+    service: &dyn SandboxService,
+    id: String,
+    provider: AcpProvider,
+) -> SandboxResult<AcpPreflightReport> {
+    let response = service
+        .exec(
+            id,
+            ExecRequest {
+                command: vec!["/bin/sh".into(), "-c".into(), preflight_script(provider)],
+                workdir: None,
+                env: vec![],
+            },
+        )
+        .await?;
+    Ok(parse_report(provider, &response.stdout))
+}
+
+impl AcpPreflightReport {
+    /// One line per failed or warning check, for showing in a terminal.
+    pub fn summary(&self) -> String {
+        self.checks
+            .iter()
+            .filter(|c| c.status != PreflightStatus::Pass)
+            .map(|c| match &c.hint {
+                Some(hint) => format!("{}: {} ({})", c.name, c.message, hint),
+                None => format!("{}: {}", c.name, c.message),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_versions_out_of_cli_banners() {
+        assert_eq!(parse_version("codex-acp 0.3.7"), Some(vec![0, 3, 7]));
+        assert_eq!(parse_version("v1.2"), Some(vec![1, 2]));
+        assert_eq!(parse_version("0.9.12 (build 42)"), Some(vec![0, 9, 12]));
+        assert_eq!(parse_version("no version here 7"), None);
+    }
+
+    #[test]
+    fn missing_binary_and_credentials_fail() {
+        let report = parse_report(AcpProvider::Claude, "");
+        assert!(!report.ok);
+        let names: Vec<(&str, PreflightStatus)> = report
+            .checks
+            .iter()
+            .map(|c| (c.name.as_str(), c.status))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("binary", PreflightStatus::Fail),
+                ("auth", PreflightStatus::Fail)
+            ]
+        );
+        let summary = report.summary();
+        assert!(summary.contains("claude-code-acp is not installed"));
+        assert!(summary.contains("ANTHROPIC_API_KEY, CLAUDE_CODE_OAUTH_TOKEN"));
+    }
+
+    #[test]
+    fn outdated_cli_fails_and_unreadable_version_warns() {
+        let report = parse_report(
+            AcpProvider::Claude,
+            "bin=/usr/bin/claude-code-acp\nversion=0.3.1\nenv=CLAUDE_CODE_OAUTH_TOKEN\n",
+        );
+        assert!(!report.ok);
+        assert_eq!(report.checks[1].status, PreflightStatus::Fail);
+        assert_eq!(
+            report.checks[1].hint.as_deref(),
+            Some("Upgrade claude-code-acp to 0.4.0 or newer")
+        );
+        assert_eq!(report.checks[2].status, PreflightStatus::Pass);
+
+        let report = parse_report(
+            AcpProvider::Codex,
+            "bin=/usr/local/bin/codex-acp\nversion=error: unknown flag\nfile=/root/.codex/auth.json\n",
+        );
+        assert!(report.ok);
+        assert_eq!(report.checks[1].status, PreflightStatus::Warn);
+    }
+
+    #[test]
+    fn opencode_without_credentials_only_warns() {
+        let report = parse_report(
+            AcpProvider::Opencode,
+            "bin=/usr/bin/opencode\nversion=0.15.2\n",
+        );
+        assert!(report.ok);
+        assert_eq!(report.checks[2].status, PreflightStatus::Warn);
+    }
+
+    #[test]
+    fn script_probes_binary_env_and_files() {
+        let script = preflight_script(AcpProvider::Claude);
+        assert!(script.starts_with("bin=$(command -v 'claude-code-acp' 2>/dev/null)"));
+        assert!(
+            script.contains("[ -n \"${ANTHROPIC_API_KEY:-}\" ] && echo \"env=ANTHROPIC_API_KEY\"")
+        );
+        assert!(script.contains("[ -s '/root/.claude/.credentials.json' ]"));
+        assert!(script.ends_with("true\n"));
+    }
+}
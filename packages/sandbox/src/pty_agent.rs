@@ -0,0 +1,470 @@
+//! ACP agent for coding CLIs that don't speak ACP.
+//!
+//! `cmux-bridge pty-agent -- <command>` serves ACP on stdio and runs the
+//! command in a PTY for each session, so the CLI can be driven through the
+//! same conversation API as the native providers. A prompt is typed into the
+//! PTY through a template. The turn ends when the CLI draws its next prompt,
+//! as reported by OSC 133 shell-integration marks, or, for CLIs that emit no
+//! marks, once its output has been quiet for `idle_timeout`. The text the
+//! turn produced is sent back as the agent's message.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use agent_client_protocol::{
+    Agent, AgentCapabilities, AgentSideConnection, AuthenticateRequest, AuthenticateResponse,
+    CancelNotification, Client, ContentBlock, ContentChunk, Error, InitializeRequest,
+    InitializeResponse, NewSessionRequest, NewSessionResponse, PromptRequest, PromptResponse,
+    SessionId, SessionNotification, SessionUpdate, StopReason, TextContent, V1,
+};
+use cmux_terminal::{SemanticMarkKind, VirtualTerminal};
+use portable_pty::{Child, CommandBuilder, MasterPty, NativePtySystem, PtySize, PtySystem};
+use tokio::sync::{mpsc, oneshot, Mutex, Notify};
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+/// Replaced by the prompt text in [`PtyAgentConfig::prompt_template`].
+pub const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+/// How often a running turn checks whether output has gone quiet.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct PtyAgentConfig {
+    /// Program and arguments started for each session.
+    pub command: Vec<String>,
+    /// Input typed for each prompt; `{prompt}` is replaced by the prompt text.
+    pub prompt_template: String,
+    /// Quiet period that ends a turn when the CLI emits no OSC 133 marks.
+    pub idle_timeout: Duration,
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl Default for PtyAgentConfig {
+    fn default() -> Self {
+        Self {
+            command: Vec::new(),
+            prompt_template: format!("{PROMPT_PLACEHOLDER}\r"),
+            idle_timeout: Duration::from_secs(3),
+            rows: 24,
+            cols: 120,
+        }
+    }
+}
+
+/// Input for one prompt. Multi-line prompts are sent as a bracketed paste
+/// when the CLI has asked for it, so their newlines don't submit early.
+pub fn render_prompt(template: &str, prompt: &str, bracketed_paste: bool) -> String {
+    let prompt = if bracketed_paste && prompt.contains('\n') {
+        format!("\x1b[200~{prompt}\x1b[201~")
+    } else {
+        prompt.replace('\n', "\r")
+    };
+    template.replace(PROMPT_PLACEHOLDER, &prompt)
+}
+
+/// Line the cursor is on, numbered like [`VirtualTerminal::get_lines`].
+fn cursor_line(term: &VirtualTerminal) -> usize {
+    term.scrollback_len() + term.cursor_row()
+}
+
+/// One prompt's worth of PTY output, and the heuristics for when it is over.
+struct Turn {
+    /// Line the prompt was typed on
+    input_line: usize,
+    /// Line after the OSC 133 `C` mark, when the CLI sent one
+    output_line: Option<usize>,
+    saw_output: bool,
+    last_output: Instant,
+}
+
+impl Turn {
+    /// Starts a turn; marks left over from before the prompt are dropped.
+    fn start(term: &mut VirtualTerminal, now: Instant) -> Self {
+        term.drain_semantic_marks();
+        Self {
+            input_line: cursor_line(term),
+            output_line: None,
+            saw_output: false,
+            last_output: now,
+        }
+    }
+
+    /// Records output `term` has just processed. Returns the line the turn's
+    /// output ends at once the CLI marked its next prompt.
+    fn observe(&mut self, term: &mut VirtualTerminal, now: Instant) -> Option<usize> {
+        self.saw_output = true;
+        self.last_output = now;
+        for mark in term.drain_semantic_marks() {
+            match mark.kind {
+                SemanticMarkKind::OutputStart => self.output_line = Some(mark.line),
+                SemanticMarkKind::CommandFinished { .. } if self.output_line.is_some() => {
+                    return Some(mark.line);
+                }
+                SemanticMarkKind::PromptStart => return Some(mark.line),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// End line once output has been quiet for `timeout`; the cursor's line
+    /// is left out as it usually holds the CLI's next prompt.
+    fn idle(&self, term: &VirtualTerminal, now: Instant, timeout: Duration) -> Option<usize> {
+        (self.saw_output && now.duration_since(self.last_output) >= timeout)
+            .then(|| cursor_line(term))
+    }
+
+    /// Text between the typed prompt (or the `C` mark) and `end`.
+    fn output(&self, term: &VirtualTerminal, end: usize) -> String {
+        let lines = term.get_lines();
+        let start = self.output_line.unwrap_or(self.input_line + 1);
+        let end = end.min(lines.len());
+        let lines = lines.get(start..end).unwrap_or_default();
+        let first = lines.iter().position(|line| !line.is_empty());
+        let last = lines.iter().rposition(|line| !line.is_empty());
+        match (first, last) {
+            (Some(first), Some(last)) => lines[first..=last].join("\n"),
+            _ => String::new(),
+        }
+    }
+}
+
+/// A CLI running in a PTY for one ACP session.
+struct PtySession {
+    /// Kept so the PTY stays open for the session's lifetime
+    _master: Box<dyn MasterPty + Send>,
+    child: RefCell<Box<dyn Child + Send + Sync>>,
+    input: std::sync::mpsc::Sender<Vec<u8>>,
+    /// PTY output; held for the length of a turn so prompts run one at a time
+    output: Mutex<mpsc::Receiver<Vec<u8>>>,
+    terminal: RefCell<VirtualTerminal>,
+    cancelled: Notify,
+}
+
+impl PtySession {
+    fn spawn(config: &PtyAgentConfig, cwd: &Path) -> anyhow::Result<Self> {
+        let (program, args) = config
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("no command configured for the pty agent"))?;
+        let pair = NativePtySystem::default().openpty(PtySize {
+            rows: config.rows,
+            cols: config.cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+        let mut cmd = CommandBuilder::new(program);
+        cmd.args(args);
+        cmd.cwd(cwd);
+        cmd.env("TERM", "xterm-256color");
+        let child = pair.slave.spawn_command(cmd)?;
+        // Release slave so reads end when the child exits
+        drop(pair.slave);
+
+        let mut reader = pair.master.try_clone_reader()?;
+        let mut writer = pair.master.take_writer()?;
+        let (output_tx, output_rx) = mpsc::channel::<Vec<u8>>(32);
+        let (input_tx, input_rx) = std::sync::mpsc::channel::<Vec<u8>>();
+
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if output_tx.blocking_send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        std::thread::spawn(move || {
+            while let Ok(data) = input_rx.recv() {
+                if writer.write_all(&data).is_err() {
+                    break;
+                }
+                let _ = writer.flush();
+            }
+        });
+
+        Ok(Self {
+            _master: pair.master,
+            child: RefCell::new(child),
+            input: input_tx,
+            output: Mutex::new(output_rx),
+            terminal: RefCell::new(VirtualTerminal::new(
+                config.rows as usize,
+                config.cols as usize,
+            )),
+            cancelled: Notify::new(),
+        })
+    }
+
+    fn write(&self, data: impl Into<Vec<u8>>) -> Result<(), Error> {
+        self.input
+            .send(data.into())
+            .map_err(|_| Error::internal_error().with_data("the pty command has exited"))
+    }
+
+    /// Feed output to the terminal, answering its queries (cursor position
+    /// reports and the like) so the CLI doesn't stall waiting on them.
+    fn process(&self, data: &[u8]) -> Result<(), Error> {
+        let responses = {
+            let mut terminal = self.terminal.borrow_mut();
+            terminal.process(data);
+            terminal.drain_responses()
+        };
+        for response in responses {
+            self.write(response)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PtySession {
+    fn drop(&mut self) {
+        let _ = self.child.get_mut().kill();
+    }
+}
+
+/// Serves ACP by driving [`PtySession`]s.
+pub struct PtyAgent {
+    config: PtyAgentConfig,
+    sessions: RefCell<HashMap<SessionId, Rc<PtySession>>>,
+    next_session: AtomicU64,
+    updates: mpsc::UnboundedSender<(SessionNotification, oneshot::Sender<()>)>,
+}
+
+impl PtyAgent {
+    fn session(&self, id: &SessionId) -> Result<Rc<PtySession>, Error> {
+        self.sessions
+            .borrow()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| Error::invalid_params().with_data(format!("unknown session {id}")))
+    }
+
+    async fn send_message(&self, session_id: SessionId, text: String) -> Result<(), Error> {
+        let (done_tx, done_rx) = oneshot::channel();
+        let notification = SessionNotification {
+            session_id,
+            update: SessionUpdate::AgentMessageChunk(ContentChunk {
+                content: ContentBlock::Text(TextContent {
+                    annotations: None,
+                    text,
+                    meta: None,
+                }),
+                meta: None,
+            }),
+            meta: None,
+        };
+        self.updates
+            .send((notification, done_tx))
+            .map_err(Error::into_internal_error)?;
+        done_rx.await.map_err(Error::into_internal_error)
+    }
+}
+
+/// Prompt blocks as plain text; resources become their URI.
+fn prompt_text(prompt: &[ContentBlock]) -> String {
+    prompt
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::Text(text) => Some(text.text.clone()),
+            ContentBlock::ResourceLink(link) => Some(link.uri.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[async_trait::async_trait(?Send)]
+impl Agent for PtyAgent {
+    async fn initialize(&self, _args: InitializeRequest) -> Result<InitializeResponse, Error> {
+        Ok(InitializeResponse {
+            protocol_version: V1,
+            agent_capabilities: AgentCapabilities::default(),
+            auth_methods: Vec::new(),
+            agent_info: None,
+            meta: None,
+        })
+    }
+
+    async fn authenticate(
+        &self,
+        _args: AuthenticateRequest,
+    ) -> Result<AuthenticateResponse, Error> {
+        Ok(AuthenticateResponse::default())
+    }
+
+    async fn new_session(&self, args: NewSessionRequest) -> Result<NewSessionResponse, Error> {
+        let session = PtySession::spawn(&self.config, &args.cwd)
+            .map_err(|err| Error::internal_error().with_data(err.to_string()))?;
+        let id = SessionId::from(format!(
+            "pty-{}",
+            self.next_session.fetch_add(1, Ordering::Relaxed)
+        ));
+        self.sessions
+            .borrow_mut()
+            .insert(id.clone(), Rc::new(session));
+        Ok(NewSessionResponse {
+            session_id: id,
+            modes: None,
+            models: None,
+            meta: None,
+        })
+    }
+
+    async fn prompt(&self, args: PromptRequest) -> Result<PromptResponse, Error> {
+        let session = self.session(&args.session_id)?;
+        let mut output = session.output.lock().await;
+        let cancelled = session.cancelled.notified();
+        tokio::pin!(cancelled);
+
+        let mut turn = Turn::start(&mut session.terminal.borrow_mut(), Instant::now());
+        let input = {
+            let terminal = session.terminal.borrow();
+            render_prompt(
+                &self.config.prompt_template,
+                &prompt_text(&args.prompt),
+                terminal.modes().bracketed_paste,
+            )
+        };
+        session.write(input)?;
+
+        let mut poll = tokio::time::interval(IDLE_POLL_INTERVAL);
+        let (end, stop_reason) = loop {
+            tokio::select! {
+                chunk = output.recv() => {
+                    let Some(chunk) = chunk else {
+                        // The CLI exited; report what it printed last
+                        let end = cursor_line(&session.terminal.borrow()) + 1;
+                        break (end, StopReason::EndTurn);
+                    };
+                    session.process(&chunk)?;
+                    let end = turn.observe(&mut session.terminal.borrow_mut(), Instant::now());
+                    if let Some(end) = end {
+                        break (end, StopReason::EndTurn);
+                    }
+                }
+                _ = poll.tick() => {
+                    let terminal = session.terminal.borrow();
+                    if let Some(end) = turn.idle(&terminal, Instant::now(), self.config.idle_timeout) {
+                        break (end, StopReason::EndTurn);
+                    }
+                }
+                _ = &mut cancelled => {
+                    // Interrupt the CLI like a user pressing Ctrl-C would
+                    session.write(b"\x03".to_vec())?;
+                    break (cursor_line(&session.terminal.borrow()), StopReason::Cancelled);
+                }
+            }
+        };
+
+        let text = turn.output(&session.terminal.borrow(), end);
+        if !text.is_empty() {
+            self.send_message(args.session_id, text).await?;
+        }
+        Ok(PromptResponse {
+            stop_reason,
+            meta: None,
+        })
+    }
+
+    async fn cancel(&self, args: CancelNotification) -> Result<(), Error> {
+        if let Ok(session) = self.session(&args.session_id) {
+            session.cancelled.notify_waiters();
+        }
+        Ok(())
+    }
+}
+
+/// Serve ACP on stdin/stdout until the client disconnects.
+pub async fn serve_stdio(config: PtyAgentConfig) -> anyhow::Result<()> {
+    let local = tokio::task::LocalSet::new();
+    local
+        .run_until(async move {
+            let (updates_tx, mut updates_rx) = mpsc::unbounded_channel();
+            let agent = PtyAgent {
+                config,
+                sessions: RefCell::new(HashMap::new()),
+                next_session: AtomicU64::new(1),
+                updates: updates_tx,
+            };
+            let (conn, io_task) = AgentSideConnection::new(
+                agent,
+                tokio::io::stdout().compat_write(),
+                tokio::io::stdin().compat(),
+                |fut| {
+                    tokio::task::spawn_local(fut);
+                },
+            );
+            tokio::task::spawn_local(async move {
+                while let Some((notification, done)) = updates_rx.recv().await {
+                    if conn.session_notification(notification).await.is_err() {
+                        break;
+                    }
+                    let _ = done.send(());
+                }
+            });
+            io_task.await.map_err(|err| anyhow::anyhow!("{err}"))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multi_line_prompts_use_bracketed_paste_when_enabled() {
+        let template = "{prompt}\r";
+        assert_eq!(render_prompt(template, "fix it", true), "fix it\r");
+        assert_eq!(render_prompt(template, "a\nb", false), "a\rb\r");
+        assert_eq!(
+            render_prompt(template, "a\nb", true),
+            "\x1b[200~a\nb\x1b[201~\r"
+        );
+        assert_eq!(render_prompt("/ask {prompt}\n", "why", false), "/ask why\n");
+    }
+
+    #[test]
+    fn turn_ends_at_next_osc_133_prompt() {
+        let mut term = VirtualTerminal::new(5, 40);
+        term.process(b"\x1b]133;A\x07> \x1b]133;B\x07");
+        let now = Instant::now();
+        let mut turn = Turn::start(&mut term, now);
+
+        term.process(b"explain\r\n\x1b]133;C\x07Here is\r\nthe answer\r\n");
+        assert_eq!(turn.observe(&mut term, now), None);
+        term.process(b"\r\n\x1b]133;D;0\x07\x1b]133;A\x07> ");
+        let end = turn.observe(&mut term, now).unwrap();
+        assert_eq!(turn.output(&term, end), "Here is\nthe answer");
+    }
+
+    #[test]
+    fn turn_without_marks_ends_when_output_goes_quiet() {
+        let mut term = VirtualTerminal::new(5, 40);
+        term.process(b"> ");
+        let start = Instant::now();
+        let mut turn = Turn::start(&mut term, start);
+        let timeout = Duration::from_secs(3);
+        assert_eq!(turn.idle(&term, start + timeout, timeout), None);
+
+        term.process(b"explain\r\nworking...\r\ndone\r\n> ");
+        assert_eq!(turn.observe(&mut term, start), None);
+        assert_eq!(
+            turn.idle(&term, start + Duration::from_secs(1), timeout),
+            None
+        );
+        let end = turn.idle(&term, start + timeout, timeout).unwrap();
+        assert_eq!(turn.output(&term, end), "working...\ndone");
+    }
+}
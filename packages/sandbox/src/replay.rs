@@ -0,0 +1,144 @@
+//! Client-side test utility that paces a recorded event stream.
+//!
+//! `POST /api/replay` streams the events in the request body back as
+//! server-sent events, pausing between them as the recording did (scaled by
+//! `speed`), so a UI's stream handling can be exercised against a recording
+//! the client already has. The server stores no conversation history; this
+//! only echoes what was uploaded and replays nothing on its own.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::response::sse::Event;
+use futures::Stream;
+
+use crate::errors::{SandboxError, SandboxResult};
+use crate::models::{ReplayEvent, ReplayRequest};
+
+/// Event sent after the last recorded event.
+pub const REPLAY_END_EVENT: &str = "replay-end";
+
+/// Pause before each event, relative to the one before it.
+///
+/// Out-of-order timestamps replay immediately rather than erroring, since
+/// recordings stitched from several sources often have small clock skew.
+pub fn schedule(request: &ReplayRequest) -> SandboxResult<Vec<Duration>> {
+    if !request.speed.is_finite() || request.speed <= 0.0 {
+        return Err(SandboxError::InvalidRequest(format!(
+            "speed must be a positive number, got {}",
+            request.speed
+        )));
+    }
+    let max_gap = request.max_gap_ms.map(Duration::from_millis);
+    let mut previous = request.events.first().map_or(0, |e| e.at_ms);
+    request
+        .events
+        .iter()
+        .map(|event| {
+            let gap_ms = event.at_ms.saturating_sub(previous);
+            previous = previous.max(event.at_ms);
+            // A tiny speed stretches gaps past what a Duration can hold
+            match Duration::try_from_secs_f64(gap_ms as f64 / 1000.0 / request.speed) {
+                Ok(gap) => Ok(max_gap.map_or(gap, |max| gap.min(max))),
+                Err(_) => max_gap.ok_or_else(|| {
+                    SandboxError::InvalidRequest(format!(
+                        "speed {} makes a {gap_ms} ms gap too long to wait",
+                        request.speed
+                    ))
+                }),
+            }
+        })
+        .collect()
+}
+
+fn to_sse(index: usize, event: &ReplayEvent) -> Event {
+    let sse = match &event.event {
+        Some(name) => Event::default().event(name),
+        None => Event::default(),
+    };
+    sse.id(index.to_string()).data(event.data.to_string())
+}
+
+/// Stream the recording as SSE events, ending with [`REPLAY_END_EVENT`].
+pub fn replay(
+    request: ReplayRequest,
+) -> SandboxResult<impl Stream<Item = Result<Event, Infallible>>> {
+    let gaps = schedule(&request)?;
+    let total = request.events.len();
+    let events = request.events.into_iter().zip(gaps).enumerate();
+    let body = futures::stream::unfold(events, |mut events| async move {
+        let (index, (event, gap)) = events.next()?;
+        if !gap.is_zero() {
+            tokio::time::sleep(gap).await;
+        }
+        Some((Ok(to_sse(index, &event)), events))
+    });
+    let end = futures::stream::once(async move {
+        Ok(Event::default()
+            .event(REPLAY_END_EVENT)
+            .data(serde_json::json!({ "events": total }).to_string()))
+    });
+    Ok(futures::StreamExt::chain(body, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(times: &[u64], speed: f64, max_gap_ms: Option<u64>) -> ReplayRequest {
+        ReplayRequest {
+            events: times
+                .iter()
+                .map(|&at_ms| ReplayEvent {
+                    at_ms,
+                    event: None,
+                    data: json!({ "at": at_ms }),
+                })
+                .collect(),
+            speed,
+            max_gap_ms,
+        }
+    }
+
+    #[test]
+    fn schedule_scales_and_caps_gaps() {
+        let ms = Duration::from_millis;
+        assert_eq!(
+            schedule(&request(&[100, 300, 1300], 1.0, None)).unwrap(),
+            vec![ms(0), ms(200), ms(1000)]
+        );
+        assert_eq!(
+            schedule(&request(&[100, 300, 1300], 4.0, None)).unwrap(),
+            vec![ms(0), ms(50), ms(250)]
+        );
+        assert_eq!(
+            schedule(&request(&[0, 200, 60_000], 1.0, Some(500))).unwrap(),
+            vec![ms(0), ms(200), ms(500)]
+        );
+        // Skewed timestamps replay immediately and don't shift later gaps
+        assert_eq!(
+            schedule(&request(&[0, 500, 400, 700], 1.0, None)).unwrap(),
+            vec![ms(0), ms(500), ms(0), ms(200)]
+        );
+    }
+
+    #[test]
+    fn schedule_rejects_invalid_speed() {
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                schedule(&request(&[0], speed, None)),
+                Err(SandboxError::InvalidRequest(_))
+            ));
+        }
+        // Gaps too long for a Duration are rejected, or capped if asked
+        assert!(matches!(
+            schedule(&request(&[0, 1000], 1e-20, None)),
+            Err(SandboxError::InvalidRequest(_))
+        ));
+        assert_eq!(
+            schedule(&request(&[0, 1000], 1e-20, Some(500))).unwrap(),
+            vec![Duration::ZERO, Duration::from_millis(500)]
+        );
+    }
+}
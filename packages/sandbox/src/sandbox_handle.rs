@@ -32,7 +32,9 @@
 
 use crate::bubblewrap::BubblewrapService;
 use crate::errors::SandboxResult;
-use crate::models::{CreateSandboxRequest, EnvVar, ExecRequest, SandboxSummary};
+use crate::models::{
+    CreateSandboxRequest, EnvVar, ExecRequest, HostOverride, NetworkPolicy, SandboxSummary,
+};
 use crate::service::SandboxService;
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -225,6 +227,8 @@ pub struct SandboxBuilder {
     env: Vec<EnvVar>,
     read_only_paths: Vec<String>,
     tmpfs: Vec<String>,
+    hosts: Vec<HostOverride>,
+    network_policy: Option<NetworkPolicy>,
 }
 
 impl Default for SandboxBuilder {
@@ -244,6 +248,8 @@ impl SandboxBuilder {
             env: Vec::new(),
             read_only_paths: Vec::new(),
             tmpfs: Vec::new(),
+            hosts: Vec::new(),
+            network_policy: None,
         }
     }
 
@@ -292,6 +298,25 @@ impl SandboxBuilder {
         self
     }
 
+    /// Map a hostname to an address via the sandbox's `/etc/hosts`.
+    pub fn add_host(mut self, hostname: impl Into<String>, address: impl Into<String>) -> Self {
+        self.hosts.push(HostOverride {
+            hostname: hostname.into(),
+            address: address.into(),
+        });
+        self
+    }
+
+    /// Restrict outbound traffic to the given domain or IPv4/IPv6 CIDR.
+    /// The first call switches the sandbox from unrestricted to allowlist-only egress.
+    pub fn allow_egress(mut self, target: impl Into<String>) -> Self {
+        self.network_policy
+            .get_or_insert_with(NetworkPolicy::default)
+            .allow
+            .push(target.into());
+        self
+    }
+
     /// Build the sandbox and return a handle.
     ///
     /// This creates a new isolated sandbox using bubblewrap with its own
@@ -311,6 +336,8 @@ impl SandboxBuilder {
             read_only_paths: self.read_only_paths,
             tmpfs: self.tmpfs,
             env: self.env.clone(),
+            hosts: self.hosts,
+            network_policy: self.network_policy,
         };
 
         let summary = service.create(request).await?;
@@ -371,6 +398,8 @@ impl SandboxPool {
             read_only_paths: Vec::new(),
             tmpfs: Vec::new(),
             env: self.default_env.clone(),
+            hosts: Vec::new(),
+            network_policy: None,
         };
 
         let summary = self.service.create(request).await?;
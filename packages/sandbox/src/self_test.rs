@@ -0,0 +1,389 @@
+//! End-to-end self-test of a running sandbox.
+//!
+//! Fleet health checks and image CI need more than `/healthz`: a host can be
+//! up while the image ships a broken provider or the sandbox's desktop never
+//! started. [`run_self_test`] exercises each path a session depends on and
+//! returns one [`PreflightCheck`] per path, so failures name the broken piece.
+//! Paths that aren't configured for the sandbox are reported as warnings.
+
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use serde::Deserialize;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+use crate::acp_client::AcpProvider;
+use crate::api::PTY_PORT;
+use crate::bootstrap::{PROXY_PORT, PROXY_SERVICE};
+use crate::errors::{SandboxError, SandboxResult};
+use crate::models::{
+    AcpPreflightReport, ExecRequest, PreflightCheck, PreflightStatus, SandboxSummary,
+    SelfTestReport, SelfTestRequest, ServiceState,
+};
+use crate::preflight::run_preflight;
+use crate::service::SandboxService;
+
+/// Cap on each network probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Cap on the callback request made from inside the sandbox.
+const CALLBACK_TIMEOUT_SECS: u32 = 10;
+
+fn check(name: &str, status: PreflightStatus, message: String) -> PreflightCheck {
+    PreflightCheck {
+        name: name.to_string(),
+        status,
+        message,
+        hint: None,
+    }
+}
+
+/// Run every check against sandbox `id`.
+pub async fn run_self_test(
+    service: &dyn SandboxService,
+    id: String,
+    request: SelfTestRequest,
+) -> SandboxResult<SelfTestReport> {
+    let started = Instant::now();
+    let sandbox = service
+        .get(id.clone())
+        .await?
+        .ok_or_else(|| SandboxError::NotFound(Uuid::nil()))?;
+
+    let providers = AcpProvider::all()
+        .iter()
+        .map(|&provider| provider_check(service, id.clone(), provider));
+    let mut checks = join_all(providers).await;
+    checks.push(pty_check(&sandbox.network.sandbox_ip).await);
+    checks.push(proxy_check(service, &sandbox).await);
+    checks.push(vnc_check(&sandbox).await);
+    checks.push(callback_check(service, id, request.callback_url.as_deref()).await);
+
+    Ok(SelfTestReport {
+        sandbox_id: sandbox.id.to_string(),
+        ok: checks.iter().all(|c| c.status != PreflightStatus::Fail),
+        duration_ms: started.elapsed().as_millis() as u64,
+        checks,
+    })
+}
+
+/// Spawns the provider's CLI through its preflight; providers the image
+/// doesn't ship are skipped.
+async fn provider_check(
+    service: &dyn SandboxService,
+    id: String,
+    provider: AcpProvider,
+) -> PreflightCheck {
+    let name = format!("provider:{}", provider.short_name());
+    match run_preflight(service, id, provider).await {
+        Ok(report) => summarize_preflight(&name, &report),
+        Err(error) => check(&name, PreflightStatus::Fail, error.to_string()),
+    }
+}
+
+fn summarize_preflight(name: &str, report: &AcpPreflightReport) -> PreflightCheck {
+    let installed = report
+        .checks
+        .iter()
+        .any(|c| c.name == "binary" && c.status == PreflightStatus::Pass);
+    if !installed {
+        return check(
+            name,
+            PreflightStatus::Warn,
+            "not installed, skipped".to_string(),
+        );
+    }
+    if report.ok {
+        let version = report
+            .checks
+            .iter()
+            .find(|c| c.name == "version")
+            .map(|c| c.message.clone())
+            .unwrap_or_default();
+        return check(name, PreflightStatus::Pass, version);
+    }
+    let mut failed = check(name, PreflightStatus::Fail, report.summary());
+    failed.hint = report
+        .checks
+        .iter()
+        .find(|c| c.status == PreflightStatus::Fail)
+        .and_then(|c| c.hint.clone());
+    failed
+}
+
+#[derive(Deserialize)]
+struct PtySession {
+    id: String,
+    alive: bool,
+}
+
+/// Opens a shell through the sandbox's cmux-pty and closes it again.
+async fn pty_check(sandbox_ip: &str) -> PreflightCheck {
+    let base = format!("http://{sandbox_ip}:{PTY_PORT}");
+    let client = match reqwest::Client::builder()
+        .http1_only()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(error) => return check("pty", PreflightStatus::Fail, error.to_string()),
+    };
+    let created = client
+        .post(format!("{base}/sessions"))
+        .json(&serde_json::json!({
+            "shell": "/bin/sh",
+            "name": "self-test",
+            "metadata": { "selfTest": true },
+        }))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    let session: PtySession = match created {
+        Ok(response) => match response.json().await {
+            Ok(session) => session,
+            Err(error) => {
+                return check(
+                    "pty",
+                    PreflightStatus::Fail,
+                    format!("unexpected cmux-pty response: {error}"),
+                )
+            }
+        },
+        Err(error) => {
+            return check(
+                "pty",
+                PreflightStatus::Fail,
+                format!("could not create a session: {error}"),
+            )
+        }
+    };
+    let _ = client
+        .delete(format!("{base}/sessions/{}", session.id))
+        .send()
+        .await;
+    if session.alive {
+        check(
+            "pty",
+            PreflightStatus::Pass,
+            format!("opened and closed session {}", session.id),
+        )
+    } else {
+        check(
+            "pty",
+            PreflightStatus::Fail,
+            "the session's shell exited immediately".to_string(),
+        )
+    }
+}
+
+/// Connects to the manifest's cmux-proxy, when the sandbox declares ports.
+async fn proxy_check(service: &dyn SandboxService, sandbox: &SandboxSummary) -> PreflightCheck {
+    let status = match service.services(sandbox.id.to_string()).await {
+        Ok(services) => services.status(PROXY_SERVICE).await.ok(),
+        Err(error) => return check("proxy", PreflightStatus::Fail, error.to_string()),
+    };
+    let Some(status) = status else {
+        return check(
+            "proxy",
+            PreflightStatus::Warn,
+            "no ports declared, skipped".to_string(),
+        );
+    };
+    if status.state != ServiceState::Running {
+        return check(
+            "proxy",
+            PreflightStatus::Fail,
+            format!("{PROXY_SERVICE} is {:?}", status.state).to_lowercase(),
+        );
+    }
+    let addr = format!("{}:{PROXY_PORT}", sandbox.network.sandbox_ip);
+    match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(&addr)).await {
+        Ok(Ok(_)) => check(
+            "proxy",
+            PreflightStatus::Pass,
+            format!("connected to {addr}"),
+        ),
+        Ok(Err(error)) => check(
+            "proxy",
+            PreflightStatus::Fail,
+            format!("could not connect to {addr}: {error}"),
+        ),
+        Err(_) => check(
+            "proxy",
+            PreflightStatus::Fail,
+            format!("timed out connecting to {addr}"),
+        ),
+    }
+}
+
+/// Connects to the sandbox's VNC server and reads its RFB greeting.
+async fn vnc_check(sandbox: &SandboxSummary) -> PreflightCheck {
+    let Some(display) = &sandbox.display else {
+        return check(
+            "vnc",
+            PreflightStatus::Warn,
+            "sandbox has no display, skipped".to_string(),
+        );
+    };
+    let addr = format!("{}:{}", sandbox.network.sandbox_ip, display.vnc_port);
+    let greeting = tokio::time::timeout(PROBE_TIMEOUT, async {
+        let mut stream = TcpStream::connect(&addr).await?;
+        let mut greeting = [0u8; 12];
+        stream.read_exact(&mut greeting).await?;
+        Ok::<_, std::io::Error>(greeting)
+    })
+    .await;
+    match greeting {
+        Ok(Ok(greeting)) => vnc_greeting_check(&greeting),
+        Ok(Err(error)) => check(
+            "vnc",
+            PreflightStatus::Fail,
+            format!("could not reach VNC at {addr}: {error}"),
+        ),
+        Err(_) => check(
+            "vnc",
+            PreflightStatus::Fail,
+            format!("timed out waiting for VNC at {addr}"),
+        ),
+    }
+}
+
+fn vnc_greeting_check(greeting: &[u8]) -> PreflightCheck {
+    let greeting = String::from_utf8_lossy(greeting);
+    let greeting = greeting.trim_end();
+    if greeting.starts_with("RFB ") {
+        check(
+            "vnc",
+            PreflightStatus::Pass,
+            format!("server speaks {greeting}"),
+        )
+    } else {
+        check(
+            "vnc",
+            PreflightStatus::Fail,
+            format!("unexpected VNC greeting {greeting:?}"),
+        )
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Requests the callback URL from inside the sandbox, so its network
+/// namespace and egress policy are part of the test. Any HTTP answer below
+/// 500 counts: the endpoint may only accept authenticated POSTs.
+async fn callback_check(
+    service: &dyn SandboxService,
+    id: String,
+    url: Option<&str>,
+) -> PreflightCheck {
+    let Some(url) = url else {
+        return check(
+            "callback",
+            PreflightStatus::Warn,
+            "no callback URL given, skipped".to_string(),
+        );
+    };
+    let script = format!(
+        "curl -sS -o /dev/null -w '%{{http_code}}' --max-time {CALLBACK_TIMEOUT_SECS} {}",
+        shell_quote(url)
+    );
+    let response = service
+        .exec(
+            id,
+            ExecRequest {
+                command: vec!["/bin/sh".into(), "-c".into(), script],
+                workdir: None,
+                env: vec![],
+            },
+        )
+        .await;
+    match response {
+        Ok(response) => callback_status_check(url, &response.stdout, &response.stderr),
+        Err(error) => check("callback", PreflightStatus::Fail, error.to_string()),
+    }
+}
+
+fn callback_status_check(url: &str, stdout: &str, stderr: &str) -> PreflightCheck {
+    match stdout.trim().parse::<u16>() {
+        Ok(code @ 100..=499) => check(
+            "callback",
+            PreflightStatus::Pass,
+            format!("{url} answered {code}"),
+        ),
+        Ok(code @ 500..) => check(
+            "callback",
+            PreflightStatus::Fail,
+            format!("{url} answered {code}"),
+        ),
+        _ => check(
+            "callback",
+            PreflightStatus::Fail,
+            format!("could not reach {url}: {}", stderr.trim()),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preflight::parse_report;
+
+    #[test]
+    fn provider_checks_skip_missing_clis_and_fail_broken_ones() {
+        let missing =
+            summarize_preflight("provider:claude", &parse_report(AcpProvider::Claude, ""));
+        assert_eq!(missing.status, PreflightStatus::Warn);
+
+        let ready = summarize_preflight(
+            "provider:codex",
+            &parse_report(
+                AcpProvider::Codex,
+                "bin=/usr/local/bin/codex-acp\nversion=codex-acp 0.3.7\nenv=OPENAI_API_KEY\n",
+            ),
+        );
+        assert_eq!(ready.status, PreflightStatus::Pass);
+        assert!(ready.message.contains("0.3.7"));
+
+        let outdated = summarize_preflight(
+            "provider:claude",
+            &parse_report(
+                AcpProvider::Claude,
+                "bin=/usr/bin/claude-code-acp\nversion=0.3.1\nenv=ANTHROPIC_API_KEY\n",
+            ),
+        );
+        assert_eq!(outdated.status, PreflightStatus::Fail);
+        assert_eq!(
+            outdated.hint.as_deref(),
+            Some("Upgrade claude-code-acp to 0.4.0 or newer")
+        );
+    }
+
+    #[test]
+    fn vnc_and_callback_results_are_classified() {
+        assert_eq!(
+            vnc_greeting_check(b"RFB 003.008\n").status,
+            PreflightStatus::Pass
+        );
+        assert_eq!(
+            vnc_greeting_check(b"HTTP/1.1 400").status,
+            PreflightStatus::Fail
+        );
+
+        let url = "https://example.com/cb";
+        assert_eq!(
+            callback_status_check(url, "405", "").status,
+            PreflightStatus::Pass
+        );
+        assert_eq!(
+            callback_status_check(url, "502", "").status,
+            PreflightStatus::Fail
+        );
+        let unreachable = callback_status_check(url, "000", "curl: (6) Could not resolve host\n");
+        assert_eq!(unreachable.status, PreflightStatus::Fail);
+        assert!(unreachable.message.ends_with("Could not resolve host"));
+    }
+}
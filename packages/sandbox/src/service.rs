@@ -1,7 +1,8 @@
 use crate::errors::SandboxResult;
 use crate::models::{
     AwaitReadyRequest, AwaitReadyResponse, CreateSandboxRequest, ExecRequest, ExecResponse,
-    GhResponse, HostEvent, PruneRequest, PruneResponse, SandboxSummary,
+    GhResponse, HostEvent, PruneRequest, PruneResponse, SandboxSummary, VscodeConfigRequest,
+    VscodeConfigResponse,
 };
 use crate::notifications::NotificationStore;
 use async_trait::async_trait;
@@ -64,6 +65,22 @@ pub trait SandboxService: Send + Sync + 'static {
         id: String,
         request: AwaitReadyRequest,
     ) -> SandboxResult<AwaitReadyResponse>;
+    /// Install extensions and apply settings/keybindings to the sandbox's cmux-code.
+    async fn configure_vscode(
+        &self,
+        id: String,
+        request: VscodeConfigRequest,
+    ) -> SandboxResult<VscodeConfigResponse>;
+    /// Sample host resource usage and sandbox activity for `/api/metrics`.
+    async fn metrics(&self) -> SandboxResult<crate::metrics::MetricsSnapshot>;
+    /// Supervisor for the sandbox's long-running services.
+    async fn services(&self, id: String) -> SandboxResult<Arc<crate::supervisor::Supervisor>>;
+    /// Apply a boot manifest (env, ports, services, provider defaults).
+    async fn apply_manifest(
+        &self,
+        id: String,
+        manifest: crate::models::BootManifest,
+    ) -> SandboxResult<crate::models::BootReport>;
 }
 
 #[derive(Clone)]
@@ -0,0 +1,623 @@
+//! Supervisor for long-running processes inside a sandbox.
+//!
+//! Each sandbox owns one [`Supervisor`]. Services are declared with a
+//! [`ServiceDefinition`] and run in the foreground through a launcher prefix
+//! (`nsenter ... --` for sandboxes), so the supervisor sees every exit,
+//! applies the restart policy and records the output in a [`LogStore`].
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::errors::{SandboxError, SandboxResult};
+use crate::logs::LogStore;
+use crate::models::{
+    HealthProbe, RestartPolicy, ServiceDefinition, ServiceLogs, ServiceState, ServiceStatus,
+};
+
+const STOP_GRACE: Duration = Duration::from_secs(5);
+const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+/// A run at least this long resets the restart backoff.
+const STABLE_RUN: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+struct Runtime {
+    state: ServiceState,
+    pid: Option<u32>,
+    restarts: u32,
+    last_exit_code: Option<i32>,
+    healthy: Option<bool>,
+    started_at: Option<chrono::DateTime<Utc>>,
+    task: Option<JoinHandle<()>>,
+}
+
+struct Service {
+    definition: ServiceDefinition,
+    runtime: std::sync::Mutex<Runtime>,
+    logs: LogStore,
+    /// Log component the output is recorded under.
+    component: String,
+    stop: watch::Sender<bool>,
+}
+
+impl Service {
+    fn new(definition: ServiceDefinition, logs: LogStore, component: String) -> Self {
+        Self {
+            definition,
+            runtime: std::sync::Mutex::new(Runtime {
+                state: ServiceState::Stopped,
+                pid: None,
+                restarts: 0,
+                last_exit_code: None,
+                healthy: None,
+                started_at: None,
+                task: None,
+            }),
+            logs,
+            component,
+            stop: watch::channel(false).0,
+        }
+    }
+
+    fn status(&self) -> ServiceStatus {
+        let runtime = self.runtime.lock().unwrap();
+        ServiceStatus {
+            name: self.definition.name.clone(),
+            state: runtime.state,
+            pid: runtime.pid,
+            restarts: runtime.restarts,
+            last_exit_code: runtime.last_exit_code,
+            healthy: runtime.healthy,
+            started_at: runtime.started_at,
+            definition: self.definition.clone(),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Runtime)) {
+        f(&mut self.runtime.lock().unwrap());
+    }
+
+    fn push_log(&self, line: String) {
+        self.logs.push(&self.component, line);
+    }
+}
+
+/// Quote `value` for `/bin/sh`.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Build the full argv for running `command` through the launcher.
+fn launch_argv(launcher: &[String], definition: &ServiceDefinition) -> Vec<String> {
+    let mut argv = launcher.to_vec();
+    argv.push("env".to_string());
+    for var in &definition.env {
+        argv.push(format!("{}={}", var.key, var.value));
+    }
+    let script = match &definition.workdir {
+        Some(dir) => format!("cd {} && {}", shell_quote(dir), definition.command),
+        None => definition.command.clone(),
+    };
+    argv.extend(["/bin/sh".to_string(), "-c".to_string(), script]);
+    argv
+}
+
+/// Whether `key` is a portable environment variable name,
+/// `[A-Za-z_][A-Za-z0-9_]*`. Anything else could be read by `env` as an
+/// option or a command.
+fn is_env_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn validate(definition: &ServiceDefinition) -> SandboxResult<()> {
+    let name_ok = !definition.name.is_empty()
+        && definition
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !name_ok {
+        return Err(SandboxError::InvalidRequest(format!(
+            "invalid service name '{}': use letters, digits, '-', '_' or '.'",
+            definition.name
+        )));
+    }
+    if definition.command.trim().is_empty() {
+        return Err(SandboxError::InvalidRequest(format!(
+            "service '{}' has an empty command",
+            definition.name
+        )));
+    }
+    if let Some(var) = definition.env.iter().find(|v| !is_env_name(&v.key)) {
+        return Err(SandboxError::InvalidRequest(format!(
+            "invalid environment variable name '{}': use letters, digits and '_', not starting with a digit",
+            var.key
+        )));
+    }
+    Ok(())
+}
+
+pub struct Supervisor {
+    launcher: Vec<String>,
+    probe_host: IpAddr,
+    logs: LogStore,
+    log_prefix: String,
+    services: Mutex<HashMap<String, Arc<Service>>>,
+}
+
+impl Supervisor {
+    /// `launcher` is prepended to every service command (empty runs on the
+    /// host); TCP health probes connect to `probe_host`.
+    pub fn new(launcher: Vec<String>, probe_host: IpAddr) -> Self {
+        Self {
+            launcher,
+            probe_host,
+            logs: LogStore::new(),
+            log_prefix: String::new(),
+            services: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Command prefix that runs a process inside the sandbox.
+    pub fn launcher(&self) -> &[String] {
+        &self.launcher
+    }
+
+    /// Record service output in `logs` under `<prefix><service name>`.
+    pub fn with_log_store(mut self, logs: LogStore, prefix: impl Into<String>) -> Self {
+        self.logs = logs;
+        self.log_prefix = prefix.into();
+        self
+    }
+
+    /// Register a service (replacing one with the same name) and start it.
+    pub async fn define(&self, definition: ServiceDefinition) -> SandboxResult<ServiceStatus> {
+        validate(&definition)?;
+        let component = format!("{}{}", self.log_prefix, definition.name);
+        let service = Arc::new(Service::new(definition, self.logs.clone(), component));
+        let previous = self
+            .services
+            .lock()
+            .await
+            .insert(service.definition.name.clone(), service.clone());
+        if let Some(previous) = previous {
+            Self::shut_down(&previous).await;
+        }
+        self.launch(&service);
+        Ok(service.status())
+    }
+
+    pub async fn start(&self, name: &str) -> SandboxResult<ServiceStatus> {
+        let service = self.get(name).await?;
+        self.launch(&service);
+        Ok(service.status())
+    }
+
+    pub async fn stop(&self, name: &str) -> SandboxResult<ServiceStatus> {
+        let service = self.get(name).await?;
+        Self::shut_down(&service).await;
+        Ok(service.status())
+    }
+
+    pub async fn restart(&self, name: &str) -> SandboxResult<ServiceStatus> {
+        let service = self.get(name).await?;
+        Self::shut_down(&service).await;
+        self.launch(&service);
+        Ok(service.status())
+    }
+
+    /// Stop a service and forget its definition.
+    pub async fn remove(&self, name: &str) -> SandboxResult<ServiceStatus> {
+        let service = self
+            .services
+            .lock()
+            .await
+            .remove(name)
+            .ok_or_else(|| SandboxError::ServiceNotFound(name.to_string()))?;
+        Self::shut_down(&service).await;
+        Ok(service.status())
+    }
+
+    pub async fn status(&self, name: &str) -> SandboxResult<ServiceStatus> {
+        Ok(self.get(name).await?.status())
+    }
+
+    pub async fn list(&self) -> Vec<ServiceStatus> {
+        let mut statuses: Vec<ServiceStatus> = self
+            .services
+            .lock()
+            .await
+            .values()
+            .map(|service| service.status())
+            .collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    /// The last `tail` output lines (all retained lines when `None`).
+    pub async fn logs(&self, name: &str, tail: Option<usize>) -> SandboxResult<ServiceLogs> {
+        let service = self.get(name).await?;
+        Ok(ServiceLogs {
+            name: name.to_string(),
+            lines: service
+                .logs
+                .query(&service.component, tail, None)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Stop every service; used when the sandbox is torn down.
+    pub async fn stop_all(&self) {
+        let services: Vec<Arc<Service>> = self.services.lock().await.values().cloned().collect();
+        for service in services {
+            Self::shut_down(&service).await;
+        }
+    }
+
+    async fn get(&self, name: &str) -> SandboxResult<Arc<Service>> {
+        self.services
+            .lock()
+            .await
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SandboxError::ServiceNotFound(name.to_string()))
+    }
+
+    fn launch(&self, service: &Arc<Service>) {
+        let mut runtime = service.runtime.lock().unwrap();
+        if runtime
+            .task
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+        {
+            return;
+        }
+        service.stop.send_replace(false);
+        runtime.state = ServiceState::Starting;
+        runtime.task = Some(tokio::spawn(supervise(
+            service.clone(),
+            self.launcher.clone(),
+            self.probe_host,
+        )));
+    }
+
+    async fn shut_down(service: &Arc<Service>) {
+        service.stop.send_replace(true);
+        let task = service.runtime.lock().unwrap().task.take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+}
+
+fn spawn_process(launcher: &[String], service: &Arc<Service>) -> std::io::Result<Child> {
+    let argv = launch_argv(launcher, &service.definition);
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Own process group so stop reaches children forked by nsenter/sh
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(collect_output(stdout, service.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(collect_output(stderr, service.clone()));
+    }
+    Ok(child)
+}
+
+async fn collect_output(stream: impl AsyncRead + Unpin, service: Arc<Service>) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        service.push_log(line);
+    }
+}
+
+fn signal_group(child: &Child, signal: i32) {
+    if let Some(pid) = child.id() {
+        // SAFETY: kill(2) with a negative pid signals the process group we created
+        unsafe {
+            libc::kill(-(pid as i32), signal);
+        }
+    }
+}
+
+async fn terminate(child: &mut Child) {
+    signal_group(child, libc::SIGTERM);
+    if tokio::time::timeout(STOP_GRACE, child.wait())
+        .await
+        .is_err()
+    {
+        signal_group(child, libc::SIGKILL);
+        let _ = child.wait().await;
+    }
+}
+
+async fn probe_once(launcher: &[String], probe_host: IpAddr, probe: &HealthProbe) -> bool {
+    match probe {
+        HealthProbe::Tcp { port } => matches!(
+            tokio::time::timeout(
+                HEALTH_TIMEOUT,
+                tokio::net::TcpStream::connect((probe_host, *port))
+            )
+            .await,
+            Ok(Ok(_))
+        ),
+        HealthProbe::Command { command } => {
+            let mut argv = launcher.to_vec();
+            argv.extend(["/bin/sh".to_string(), "-c".to_string(), command.clone()]);
+            let run = Command::new(&argv[0])
+                .args(&argv[1..])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .kill_on_drop(true)
+                .status();
+            matches!(
+                tokio::time::timeout(HEALTH_TIMEOUT, run).await,
+                Ok(Ok(status)) if status.success()
+            )
+        }
+    }
+}
+
+async fn watch_health(
+    service: Arc<Service>,
+    launcher: Vec<String>,
+    probe_host: IpAddr,
+    probe: HealthProbe,
+) {
+    let mut interval = tokio::time::interval(HEALTH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let healthy = probe_once(&launcher, probe_host, &probe).await;
+        service.update(|rt| rt.healthy = Some(healthy));
+    }
+}
+
+/// Resolve once a stop has been requested.
+async fn stopped(stop_rx: &mut watch::Receiver<bool>) {
+    let _ = stop_rx.wait_for(|stop| *stop).await;
+}
+
+async fn supervise(service: Arc<Service>, launcher: Vec<String>, probe_host: IpAddr) {
+    let name = service.definition.name.clone();
+    let mut stop_rx = service.stop.subscribe();
+    let mut consecutive_failures: u32 = 0;
+
+    loop {
+        service.update(|rt| rt.state = ServiceState::Starting);
+        let started = Instant::now();
+        let exit = match spawn_process(&launcher, &service) {
+            Ok(mut child) => {
+                let pid = child.id();
+                service.update(|rt| {
+                    rt.state = ServiceState::Running;
+                    rt.pid = pid;
+                    rt.started_at = Some(Utc::now());
+                });
+                info!(service = %name, pid = ?pid, "service started");
+                let health = service.definition.health.clone().map(|probe| {
+                    tokio::spawn(watch_health(
+                        service.clone(),
+                        launcher.clone(),
+                        probe_host,
+                        probe,
+                    ))
+                });
+                let exit = tokio::select! {
+                    status = child.wait() => Some(status),
+                    _ = stopped(&mut stop_rx) => None,
+                };
+                if exit.is_none() {
+                    terminate(&mut child).await;
+                }
+                if let Some(health) = health {
+                    health.abort();
+                }
+                service.update(|rt| {
+                    rt.pid = None;
+                    rt.healthy = None;
+                });
+                match exit {
+                    Some(status) => status.ok(),
+                    None => {
+                        service.update(|rt| rt.state = ServiceState::Stopped);
+                        info!(service = %name, "service stopped");
+                        return;
+                    }
+                }
+            }
+            Err(error) => {
+                warn!(service = %name, %error, "service failed to spawn");
+                service.push_log(format!("[supervisor] failed to spawn: {error}"));
+                None
+            }
+        };
+
+        let success = exit.is_some_and(|status| status.success());
+        let code = exit.and_then(|status| status.code());
+        service.update(|rt| rt.last_exit_code = code);
+        service.push_log(format!("[supervisor] exited with code {code:?}"));
+
+        let restart = match service.definition.restart {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always => true,
+        };
+        if !restart {
+            let state = if success {
+                ServiceState::Exited
+            } else {
+                ServiceState::Failed
+            };
+            service.update(|rt| rt.state = state);
+            info!(service = %name, ?code, "service exited");
+            return;
+        }
+
+        if started.elapsed() >= STABLE_RUN {
+            consecutive_failures = 0;
+        }
+        let delay = Duration::from_millis(500u64 << consecutive_failures.min(6)).min(MAX_BACKOFF);
+        consecutive_failures += 1;
+        service.update(|rt| {
+            rt.state = ServiceState::Backoff;
+            rt.restarts += 1;
+        });
+        warn!(service = %name, ?code, ?delay, "service exited; restarting");
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stopped(&mut stop_rx) => {
+                service.update(|rt| rt.state = ServiceState::Stopped);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EnvVar;
+    use std::net::Ipv4Addr;
+
+    fn definition(name: &str, command: &str, restart: RestartPolicy) -> ServiceDefinition {
+        ServiceDefinition {
+            name: name.to_string(),
+            command: command.to_string(),
+            env: vec![EnvVar {
+                key: "GREETING".to_string(),
+                value: "hello world".to_string(),
+            }],
+            workdir: Some("/".to_string()),
+            restart,
+            health: None,
+        }
+    }
+
+    async fn wait_for_state(supervisor: &Supervisor, name: &str, state: ServiceState) {
+        for _ in 0..100 {
+            if supervisor.status(name).await.unwrap().state == state {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!(
+            "service {name} never reached {state:?}: {:?}",
+            supervisor.status(name).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_service_and_captures_output() {
+        let store = LogStore::new();
+        let supervisor = Supervisor::new(Vec::new(), IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .with_log_store(store.clone(), "sandbox-1.");
+        supervisor
+            .define(definition(
+                "greeter",
+                "echo \"$GREETING from $(pwd)\"; echo oops >&2",
+                RestartPolicy::Never,
+            ))
+            .await
+            .unwrap();
+        wait_for_state(&supervisor, "greeter", ServiceState::Exited).await;
+
+        let status = supervisor.status("greeter").await.unwrap();
+        assert_eq!(status.last_exit_code, Some(0));
+        assert_eq!(status.restarts, 0);
+        // Output readers may lag the exit slightly
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let logs = supervisor.logs("greeter", None).await.unwrap();
+        let lines: Vec<&str> = logs.lines.iter().map(|l| l.line.as_str()).collect();
+        assert!(lines.contains(&"hello world from /"));
+        assert!(lines.contains(&"oops"));
+        assert_eq!(store.components(), vec!["sandbox-1.greeter"]);
+        assert_eq!(
+            supervisor
+                .logs("greeter", Some(1))
+                .await
+                .unwrap()
+                .lines
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn restarts_failed_service_and_stops_on_request() {
+        let supervisor = Supervisor::new(Vec::new(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        supervisor
+            .define(definition("flaky", "exit 3", RestartPolicy::OnFailure))
+            .await
+            .unwrap();
+        for _ in 0..100 {
+            if supervisor.status("flaky").await.unwrap().restarts >= 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let status = supervisor.stop("flaky").await.unwrap();
+        assert_eq!(status.state, ServiceState::Stopped);
+        assert_eq!(status.last_exit_code, Some(3));
+        assert!(status.restarts >= 1);
+
+        supervisor
+            .define(definition(
+                "sleeper",
+                "exec sleep 30",
+                RestartPolicy::Always,
+            ))
+            .await
+            .unwrap();
+        wait_for_state(&supervisor, "sleeper", ServiceState::Running).await;
+        let stopped = supervisor.remove("sleeper").await.unwrap();
+        assert_eq!(stopped.state, ServiceState::Stopped);
+        assert!(matches!(
+            supervisor.status("sleeper").await,
+            Err(SandboxError::ServiceNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_definitions() {
+        let supervisor = Supervisor::new(Vec::new(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let mut bad = definition("has space", "true", RestartPolicy::Never);
+        assert!(supervisor.define(bad.clone()).await.is_err());
+        bad.name = "ok".to_string();
+        bad.command = "  ".to_string();
+        assert!(supervisor.define(bad.clone()).await.is_err());
+
+        bad.command = "true".to_string();
+        for key in ["", "A=B", "-i", "1ST", "MY-VAR", "PATH BAD"] {
+            bad.env[0].key = key.to_string();
+            assert!(
+                matches!(
+                    supervisor.define(bad.clone()).await,
+                    Err(SandboxError::InvalidRequest(_))
+                ),
+                "{key:?} was accepted"
+            );
+        }
+        bad.env[0].key = "_private_1".to_string();
+        assert!(supervisor.define(bad).await.is_ok());
+    }
+}
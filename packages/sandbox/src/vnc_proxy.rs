@@ -31,6 +31,7 @@ pub async fn proxy_vnc_websocket(
     stream.set_nodelay(true)?;
 
     debug!("Connected to VNC server, TCP_NODELAY enabled");
+    crate::metrics::record_proxy_request();
 
     let (mut tcp_read, mut tcp_write) = stream.into_split();
     let (mut ws_sink, mut ws_stream) = client_socket.split();
@@ -40,6 +41,7 @@ pub async fn proxy_vnc_websocket(
         while let Some(msg_result) = ws_stream.next().await {
             match msg_result {
                 Ok(Message::Binary(data)) => {
+                    crate::metrics::record_proxy_upstream(data.len());
                     if tcp_write.write_all(&data).await.is_err() {
                         break;
                     }
@@ -73,6 +75,7 @@ pub async fn proxy_vnc_websocket(
                 break;
             }
             Ok(n) => {
+                crate::metrics::record_proxy_downstream(n);
                 if ws_sink
                     .send(Message::Binary(buf[..n].to_vec().into()))
                     .await
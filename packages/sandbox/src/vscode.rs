@@ -0,0 +1,234 @@
+//! Helpers for applying editor configuration to a sandbox's cmux-code instance.
+//!
+//! Settings and keybindings are written from the host into the sandbox's `/root`
+//! directory; extensions are installed by running the cmux-code CLI inside the
+//! sandbox so they land in its (overlay-backed) extensions directory.
+
+use crate::errors::{SandboxError, SandboxResult};
+use serde_json::{Map, Value};
+
+/// cmux-code server binary inside the sandbox.
+pub const CODE_SERVER_BIN: &str = "/app/cmux-code/bin/code-server-oss";
+/// Extensions directory shared with the image (overlay-mounted per sandbox).
+pub const EXTENSIONS_DIR: &str = "/root/.vscode-server-oss/extensions";
+/// User data directory, relative to the sandbox's `/root`.
+pub const USER_DATA_DIR: &str = ".vscode-server-oss/data";
+
+/// Settings files that cmux-code reads, relative to the user data directory.
+/// The default profile keeps its own copy, so both are updated.
+pub const SETTINGS_FILES: &[&str] = &[
+    "User/settings.json",
+    "User/profiles/default-profile/settings.json",
+];
+
+/// Keybindings file, relative to the user data directory.
+pub const KEYBINDINGS_FILE: &str = "User/keybindings.json";
+
+/// Strip the JSONC extensions VS Code accepts in settings files, `//` and
+/// `/* */` comments and trailing commas, leaving plain JSON.
+fn strip_jsonc(raw: &str) -> String {
+    let mut plain = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            plain.push(c);
+            match c {
+                '\\' => plain.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                plain.push(c);
+            }
+            ('/', Some('/')) => while chars.next_if(|&c| c != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = '\0';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                plain.push(' ');
+            }
+            _ => plain.push(c),
+        }
+    }
+
+    // With comments gone, a comma is trailing if only whitespace follows it
+    // up to the closing bracket
+    let mut json = String::with_capacity(plain.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in plain.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' && plain[i + 1..].trim_start().starts_with(['}', ']']) {
+            continue;
+        }
+        json.push(c);
+    }
+    json
+}
+
+/// Merge `updates` into an existing settings document. Top-level keys in
+/// `updates` replace existing ones; a `null` value removes the key. The
+/// existing document may use comments and trailing commas, which are not
+/// kept.
+pub fn merge_settings(
+    existing: Option<&str>,
+    updates: &Map<String, Value>,
+) -> SandboxResult<String> {
+    let mut merged = match existing.map(str::trim).filter(|raw| !raw.is_empty()) {
+        Some(raw) => match serde_json::from_str::<Value>(&strip_jsonc(raw)) {
+            Ok(Value::Object(map)) => map,
+            Ok(_) => {
+                return Err(SandboxError::InvalidRequest(
+                    "existing settings.json is not a JSON object".into(),
+                ))
+            }
+            Err(error) => {
+                return Err(SandboxError::InvalidRequest(format!(
+                    "existing settings.json is not valid JSON: {error}"
+                )))
+            }
+        },
+        None => Map::new(),
+    };
+
+    for (key, value) in updates {
+        if value.is_null() {
+            merged.remove(key);
+        } else {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+
+    serde_json::to_string_pretty(&Value::Object(merged))
+        .map_err(|error| SandboxError::Internal(format!("failed to encode settings: {error}")))
+}
+
+/// Reject extension identifiers that aren't `publisher.name[@version]` or a `.vsix` path.
+pub fn validate_extension(extension: &str) -> SandboxResult<()> {
+    let extension = extension.trim();
+    if extension.ends_with(".vsix") && extension.starts_with('/') {
+        return Ok(());
+    }
+
+    let id = extension.split_once('@').map_or(extension, |(id, _)| id);
+    let valid = id.split_once('.').is_some_and(|(publisher, name)| {
+        let ok = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        };
+        ok(publisher) && ok(name)
+    });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(SandboxError::InvalidRequest(format!(
+            "invalid extension identifier: {extension}"
+        )))
+    }
+}
+
+/// Command that installs one extension into the shared extensions directory.
+pub fn install_extension_command(extension: &str) -> Vec<String> {
+    vec![
+        CODE_SERVER_BIN.to_string(),
+        "--install-extension".to_string(),
+        extension.trim().to_string(),
+        "--force".to_string(),
+        "--extensions-dir".to_string(),
+        EXTENSIONS_DIR.to_string(),
+        "--user-data-dir".to_string(),
+        format!("/root/{USER_DATA_DIR}"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_overwrites_and_removes_keys() {
+        let existing = r#"{"editor.fontSize": 12, "telemetry.telemetryLevel": "off", "old": true}"#;
+        let updates = json!({"editor.fontSize": 14, "old": null, "files.autoSave": "afterDelay"});
+        let merged = merge_settings(Some(existing), updates.as_object().unwrap()).unwrap();
+        let value: Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "editor.fontSize": 14,
+                "telemetry.telemetryLevel": "off",
+                "files.autoSave": "afterDelay"
+            })
+        );
+    }
+
+    #[test]
+    fn merge_starts_from_empty_and_rejects_non_objects() {
+        let updates = json!({"a": 1});
+        let merged = merge_settings(None, updates.as_object().unwrap()).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&merged).unwrap(), updates);
+
+        assert!(matches!(
+            merge_settings(Some("[1, 2]"), updates.as_object().unwrap()),
+            Err(SandboxError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            merge_settings(Some("{\"a\": }"), updates.as_object().unwrap()),
+            Err(SandboxError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn merge_accepts_comments_and_trailing_commas() {
+        let existing = r#"{
+            // Set by the user
+            "editor.fontSize": 12, /* inline */
+            "url": "https://example.com/*not a comment*/",
+            "quote": "a \"//\" b",
+            "list": [1, 2,],
+        }"#;
+        let updates = json!({"editor.fontSize": 14});
+        let merged = merge_settings(Some(existing), updates.as_object().unwrap()).unwrap();
+        let value: Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "editor.fontSize": 14,
+                "url": "https://example.com/*not a comment*/",
+                "quote": "a \"//\" b",
+                "list": [1, 2]
+            })
+        );
+        assert_eq!(strip_jsonc("{\"a,\": [\"]\",],}"), "{\"a,\": [\"]\"]}");
+    }
+
+    #[test]
+    fn validates_extension_identifiers() {
+        assert!(validate_extension("esbenp.prettier-vscode").is_ok());
+        assert!(validate_extension("ms-python.python@2024.2.1").is_ok());
+        assert!(validate_extension("/tmp/custom.vsix").is_ok());
+        assert!(validate_extension("prettier").is_err());
+        assert!(validate_extension("a.b; rm -rf /").is_err());
+    }
+}
@@ -47,6 +47,8 @@ async fn test_agent_config_files_exist_in_sandbox() {
         read_only_paths: vec![],
         tmpfs: vec![],
         env: vec![],
+        hosts: vec![],
+        network_policy: None,
     };
     let summary = service.create(req).await.expect("Failed to create sandbox");
 
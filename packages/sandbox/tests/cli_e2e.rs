@@ -192,6 +192,37 @@ impl SandboxService for MockService {
             timed_out: vec![],
         })
     }
+
+    async fn configure_vscode(
+        &self,
+        _id: String,
+        _request: cmux_sandbox::models::VscodeConfigRequest,
+    ) -> cmux_sandbox::errors::SandboxResult<cmux_sandbox::models::VscodeConfigResponse> {
+        Ok(cmux_sandbox::models::VscodeConfigResponse::default())
+    }
+
+    async fn metrics(
+        &self,
+    ) -> cmux_sandbox::errors::SandboxResult<cmux_sandbox::metrics::MetricsSnapshot> {
+        Ok(cmux_sandbox::metrics::MetricsSnapshot::default())
+    }
+
+    async fn services(
+        &self,
+        _id: String,
+    ) -> cmux_sandbox::errors::SandboxResult<Arc<cmux_sandbox::supervisor::Supervisor>> {
+        Err(cmux_sandbox::errors::SandboxError::InvalidRequest(
+            "services are not supported by the mock".into(),
+        ))
+    }
+
+    async fn apply_manifest(
+        &self,
+        _id: String,
+        _manifest: cmux_sandbox::models::BootManifest,
+    ) -> cmux_sandbox::errors::SandboxResult<cmux_sandbox::models::BootReport> {
+        Ok(cmux_sandbox::models::BootReport::default())
+    }
 }
 
 #[tokio::test]
@@ -248,6 +279,8 @@ async fn cli_can_list_and_create_via_http() {
         read_only_paths: Vec::new(),
         tmpfs: Vec::new(),
         env: Vec::new(),
+        hosts: Vec::new(),
+        network_policy: None,
     })
     .unwrap();
     let created = client
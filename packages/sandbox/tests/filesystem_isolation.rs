@@ -36,6 +36,8 @@ async fn test_filesystem_isolation() {
         read_only_paths: vec![],
         tmpfs: vec![],
         env: vec![],
+        hosts: vec![],
+        network_policy: None,
     };
     let summary_a = service
         .create(req_a)
@@ -66,6 +68,8 @@ async fn test_filesystem_isolation() {
         read_only_paths: vec![],
         tmpfs: vec![],
         env: vec![],
+        hosts: vec![],
+        network_policy: None,
     };
     let summary_b = service
         .create(req_b)
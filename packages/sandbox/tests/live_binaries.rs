@@ -24,6 +24,8 @@ async fn test_global_binaries_in_sandbox() {
         read_only_paths: vec![],
         tmpfs: vec![],
         env: vec![],
+        hosts: vec![],
+        network_policy: None,
     };
 
     let resp = client
@@ -33,6 +33,8 @@ export interface CreatePtySessionOptions {
   env?: Record<string, string>;
   name?: string;
   metadata?: PtyMetadata;
+  /** Inherit the environment registered for this ACP conversation */
+  conversationId?: string;
 }
 
 export interface UpdatePtySessionOptions {
@@ -111,6 +113,7 @@ export class CmuxPtyClient {
         env: options.env,
         name: options.name,
         metadata: options.metadata,
+        conversation_id: options.conversationId,
       }),
     });
 
@@ -14,5 +14,10 @@ export interface ReplaceDiffEntry {
   oldSize?: number;
   newSize?: number;
   patchSize?: number;
+  /** Git blob OIDs of each side, when the native diff was asked for hashes. */
+  oldHash?: string;
+  newHash?: string;
+  /** Set when contents were omitted; pass to gitDiffContents to load them. */
+  contentKey?: string;
 }
 